@@ -0,0 +1,122 @@
+//! Benchmarks of the parsing of the BIER header, of the bitstring operations and of the BIER
+//! processing, for the BSLs of RFC 8296 from 64 to 4096 bits and several fan-outs, i.e.,
+//! numbers of neighbors receiving a copy.
+//!
+//! ```bash
+//! $ cargo bench --bench bier_processing -- process_bier/1024
+//! ```
+
+use bier_rust::bier::{BierEntryPath, BierState, Bift, BiftEntry, Bitstring, BitstringOp};
+use bier_rust::header::BierHeader;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const BSLS: [usize; 4] = [64, 256, 1024, 4096];
+
+const FAN_OUTS: [usize; 4] = [1, 4, 16, 64];
+
+/// Bitstring of `bsl` bits with the bits of the BFR-ids for which `keep` is true.
+fn bitstring(bsl: usize, keep: impl Fn(usize) -> bool) -> Bitstring {
+    let nb_words = bsl / 64;
+    let mut words = vec![0u64; nb_words];
+    for bfr_id in (1..=bsl).filter(|bfr_id| keep(*bfr_id)) {
+        // The last word holds the BFR-ids 1 to 64.
+        words[nb_words - 1 - (bfr_id - 1) / 64] |= 1 << ((bfr_id - 1) % 64);
+    }
+    Bitstring { bitstring: words }
+}
+
+/// State with one BIFT of `bsl` bits, whose BFR-ids are spread over `fan_out` neighbors.
+fn state(bsl: usize, fan_out: usize) -> BierState {
+    let entries = (1..=bsl)
+        .map(|bit| {
+            let neighbor = (bit - 1) % fan_out;
+            let fbm = bitstring(bsl, |bfr_id| (bfr_id - 1) % fan_out == neighbor);
+            let next_hop = format!("fc00:{:x}::1", neighbor + 1).parse().unwrap();
+            BiftEntry::new(bit as u64, vec![BierEntryPath::new(fbm, next_hop)])
+        })
+        .collect();
+    let mut state = BierState::new("fc00::a".parse().unwrap());
+    state
+        .add_bift(Bift::new(1, bsl as u64 + 1, entries))
+        .unwrap();
+    state.compile().unwrap();
+    state
+}
+
+fn bench_header(c: &mut Criterion) {
+    let mut group = c.benchmark_group("header");
+    for bsl in BSLS {
+        let header = BierHeader::builder()
+            .bift_id(1)
+            .bitstring(bitstring(bsl, |bfr_id| bfr_id % 3 == 0))
+            .build()
+            .unwrap();
+        let mut slice = vec![0u8; header.header_length()];
+        header.to_slice(&mut slice).unwrap();
+        group.throughput(Throughput::Bytes(slice.len() as u64));
+        group.bench_with_input(BenchmarkId::new("from_slice", bsl), &slice, |b, slice| {
+            b.iter(|| BierHeader::from_slice(black_box(slice)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("to_slice", bsl), &header, |b, header| {
+            b.iter(|| black_box(header).to_slice(&mut slice).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_bitstring(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bitstring");
+    for bsl in BSLS {
+        let packet = bitstring(bsl, |bfr_id| bfr_id % 3 == 0);
+        let fbm = bitstring(bsl, |bfr_id| bfr_id % 2 == 0);
+        group.bench_function(BenchmarkId::new("and", bsl), |b| {
+            b.iter(|| black_box(&packet).and(black_box(&fbm)))
+        });
+        let mut copy = packet.clone();
+        group.bench_function(BenchmarkId::new("set_and", bsl), |b| {
+            b.iter(|| copy.set_and(black_box(&packet.bitstring), black_box(&fbm.bitstring)))
+        });
+        group.bench_function(BenchmarkId::new("and_not", bsl), |b| {
+            b.iter_batched_ref(
+                || packet.clone(),
+                |packet| packet.update(black_box(&fbm), BitstringOp::AndNot).unwrap(),
+                criterion::BatchSize::SmallInput,
+            )
+        });
+        group.bench_function(BenchmarkId::new("count_above", bsl), |b| {
+            b.iter(|| black_box(&packet).count_above(black_box(bsl as u64 / 2)))
+        });
+        group.bench_function(BenchmarkId::new("split", bsl), |b| {
+            b.iter(|| black_box(&packet).split(1).count())
+        });
+    }
+    group.finish();
+}
+
+fn bench_process_bier(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process_bier");
+    for bsl in BSLS {
+        let packet = bitstring(bsl, |_| true);
+        for fan_out in FAN_OUTS.into_iter().filter(|fan_out| *fan_out <= bsl) {
+            let state = state(bsl, fan_out);
+            group.throughput(Throughput::Elements(fan_out as u64));
+            let id = format!("{}/{}", bsl, fan_out);
+            group.bench_function(BenchmarkId::new("copies", &id), |b| {
+                b.iter(|| state.process_bier(black_box(&packet), 1, 0).unwrap())
+            });
+            group.bench_function(BenchmarkId::new("callback", &id), |b| {
+                b.iter(|| {
+                    let mut nb_copies = 0;
+                    state
+                        .process_bier_with(black_box(&packet), 1, 0, |_, _| nb_copies += 1)
+                        .unwrap();
+                    nb_copies
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_header, bench_bitstring, bench_process_bier);
+criterion_main!(benches);
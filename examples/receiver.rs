@@ -1,15 +1,22 @@
 #[macro_use]
 extern crate log;
 
+use bier_rust::client::BierClient;
 use clap::Parser;
-use socket2;
-use bier_rust::api::RecvInfo;
+use std::time::Duration;
 
 #[derive(Parser)]
 struct Args {
     /// Path to the BIER daemon.
+    #[clap(short = 'b', long = "bier", value_parser)]
+    bier_path: String,
+    /// Path where the BIER daemon delivers the packets to this program.
     #[clap(short = 'u', long = "unix-path", value_parser)]
     unix_path: String,
+    /// Registers the program for the packets of this proto, instead of relying on the
+    /// destinations given to the daemon.
+    #[clap(short = 'p', long = "proto", value_parser)]
+    proto: Option<u16>,
     /// Number of packets to listen.
     #[clap(short = 'n', value_parser, default_value = "1")]
     nb_to_recv: usize,
@@ -19,20 +26,22 @@ fn main() {
     env_logger::init();
     let args = Args::parse();
 
-    // Sock used to send messages.
-    // No need to bind the socket as we only send messages.
-    let sock = socket2::Socket::new(
-        socket2::Domain::UNIX,
-        socket2::Type::DGRAM,
-        None
-    ).unwrap();
-    let recv_addr = socket2::SockAddr::unix(args.unix_path).unwrap();
-    sock.bind(&recv_addr).unwrap();
+    // The socket is bound to `unix_path`, and removed at the end.
+    let mut client = BierClient::connect(&args.bier_path, &args.unix_path).unwrap();
+    if let Some(proto) = args.proto {
+        client.register(proto, Duration::from_secs(1)).unwrap();
+    }
 
-    let mut buffer = Vec::with_capacity(4096);
     for _ in 0..args.nb_to_recv {
-        let read = sock.recv(buffer.spare_capacity_mut()).unwrap();
-        let recv_info = RecvInfo::from_slice(&buffer[..read]).unwrap();
-        debug!("Received {} bytes", recv_info.payload.len());
+        let recv_info = client.recv().unwrap();
+        let metadata = recv_info.metadata.unwrap_or_default();
+        debug!(
+            "Received {} bytes with proto {} from BFR-id {:?} (entropy {:?}, DSCP {:?})",
+            recv_info.payload.len(),
+            recv_info.proto,
+            metadata.bfir_id,
+            metadata.entropy,
+            metadata.dscp
+        );
     }
-}
\ No newline at end of file
+}
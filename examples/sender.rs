@@ -19,6 +19,21 @@ struct Args {
     /// Destination multicast address.
     #[clap(short = 'a', long = "multicast-address", value_parser)]
     mc_dst: String,
+    /// BIFT-id of the packets.
+    #[clap(long = "bift-id", value_parser, default_value = "1")]
+    bift_id: u32,
+    /// Bitstring of the packets, most significant bit first.
+    #[clap(short = 's', long = "bitstring", value_parser, default_value = "11110")]
+    bitstring: String,
+    /// BFR-ids of the destinations of the packets, e.g., `3,5,17`, instead of the bitstring.
+    #[clap(long = "bfr-ids", value_parser, value_delimiter = ',', conflicts_with = "bitstring")]
+    bfr_ids: Vec<u16>,
+    /// BitString Length of the BIFT, with `--bfr-ids`.
+    #[clap(long = "bsl", value_parser, default_value = "64")]
+    bsl: usize,
+    /// Set identifier (SI) of the BIFT, with `--bfr-ids`: the BFR-ids must be in this SI.
+    #[clap(long = "si", value_parser, default_value = "0")]
+    si: u8,
 }
 
 fn main() {
@@ -36,15 +51,29 @@ fn main() {
     // Put data in the packet buffer.
     let mut buffer = [0u8; 4096];
     let packet = [0u8; 1000];
-    let bitstring = Bitstring::from_str("11110").unwrap();
+    let bitstring = match args.bfr_ids.is_empty() {
+        true => Bitstring::from_str(&args.bitstring).unwrap(),
+        false => {
+            let (si, bitstring) = Bitstring::from_bfr_ids(&args.bfr_ids, args.bsl).unwrap();
+            if si != args.si {
+                error!(
+                    "The BFR-ids are in the SI {}, not in the SI {} of the BIFT {}",
+                    si, args.si, args.bift_id
+                );
+                return;
+            }
+            bitstring
+        }
+    };
     let bitstring: Vec<u8> = (&bitstring).into();
 
     // Create the send info and the slice from it.
     let send_info = SendInfo {
-        bift_id: 1,
+        bift_id: args.bift_id,
         proto: 6, // UDP
         bitstring: &bitstring,
         payload: &packet,
+        metadata: None,
     };
 
     let bier_addr = socket2::SockAddr::unix(args.bier_path).unwrap();
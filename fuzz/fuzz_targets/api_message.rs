@@ -0,0 +1,16 @@
+//! Parses arbitrary bytes as a data message of the API. A parsed message must be serialized
+//! and parsed back to the same fields. The bytes may differ, as a message is serialized in
+//! version 1 without metadata, and the unknown TLVs are skipped.
+
+#![no_main]
+
+use bier_rust::api::CommunicationInfo;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(info) = CommunicationInfo::from_slice(data) {
+        let mut slice = vec![0u8; info.message_length()];
+        info.to_slice(&mut slice).unwrap();
+        assert_eq!(CommunicationInfo::from_slice(&slice).unwrap(), info);
+    }
+});
@@ -0,0 +1,15 @@
+//! Parses arbitrary bytes as a BIER header. A parsed header must be serialized back to the
+//! same bytes.
+
+#![no_main]
+
+use bier_rust::header::BierHeader;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(header) = BierHeader::from_slice(data) {
+        let mut slice = vec![0u8; header.header_length()];
+        header.to_slice(&mut slice).unwrap();
+        assert_eq!(slice, data[..header.header_length()]);
+    }
+});
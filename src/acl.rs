@@ -0,0 +1,300 @@
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+
+/// Credentials of the process that sent a datagram on the UNIX socket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Credentials {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Rule of the access control list of the application API.
+/// A rule matches if all its set fields match.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct AclRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    /// Protos the application is allowed to send. All protos if `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protos: Option<Vec<u16>>,
+}
+
+impl AclRule {
+    pub fn matches(&self, credentials: &Credentials, proto: u16) -> bool {
+        self.uid.is_none_or(|uid| uid == credentials.uid)
+            && self.gid.is_none_or(|gid| gid == credentials.gid)
+            && self
+                .protos
+                .as_ref()
+                .is_none_or(|protos| protos.contains(&proto))
+    }
+}
+
+/// Returns whether an application with the given credentials may send packets with `proto`.
+/// An empty access control list allows all applications.
+/// Otherwise, the credentials must be known and match at least one rule.
+pub fn is_allowed(acl: &[AclRule], credentials: Option<&Credentials>, proto: u16) -> bool {
+    acl.is_empty()
+        || credentials.is_some_and(|credentials| {
+            acl.iter().any(|rule| rule.matches(credentials, proto))
+        })
+}
+
+/// Asks the kernel to attach the credentials of the sender to each datagram received on `fd`.
+/// SO_PEERCRED is not usable on unconnected datagram sockets, hence SO_PASSCRED.
+#[cfg(target_os = "linux")]
+pub fn enable_credentials(fd: RawFd) -> io::Result<()> {
+    let enable: libc::c_int = 1;
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_PASSCRED,
+            &enable as *const _ as *const libc::c_void,
+            std::mem::size_of_val(&enable) as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// The credentials of the sender of a datagram are only attached by Linux:
+/// the access control list is not supported on the other systems.
+#[cfg(not(target_os = "linux"))]
+pub fn enable_credentials(_fd: RawFd) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Datagram received by `recv_with_credentials`.
+pub struct ReceivedDatagram {
+    /// Number of bytes written in the buffer.
+    pub read: usize,
+    pub from: socket2::SockAddr,
+    /// Credentials of the sender, if attached by the kernel.
+    pub credentials: Option<Credentials>,
+    /// The datagram was larger than the buffer, and its end was discarded.
+    pub truncated: bool,
+}
+
+/// Receives a datagram on `fd` similarly to `recv_from`, but also returns the credentials
+/// of the sender if they were attached by the kernel (see `enable_credentials`).
+pub fn recv_with_credentials(
+    fd: RawFd,
+    buf: &mut [MaybeUninit<u8>],
+) -> io::Result<ReceivedDatagram> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // Room for a single SCM_CREDENTIALS control message, correctly aligned.
+    let mut control = [0u64; 8];
+
+    let ((read, credentials, truncated), from) = unsafe {
+        socket2::SockAddr::init(|storage, len| {
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_name = storage as *mut libc::c_void;
+            msg.msg_namelen = *len;
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+            let read = libc::recvmsg(fd, &mut msg, 0);
+            if read < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            *len = msg.msg_namelen;
+
+            #[cfg(target_os = "linux")]
+            let credentials = read_credentials(&msg);
+            #[cfg(not(target_os = "linux"))]
+            let credentials = None;
+            let truncated = msg.msg_flags & libc::MSG_TRUNC != 0;
+            Ok((read as usize, credentials, truncated))
+        })?
+    };
+
+    Ok(ReceivedDatagram {
+        read,
+        from,
+        credentials,
+        truncated,
+    })
+}
+
+/// Path of the UNIX socket of the sender `from`, e.g., of a `ReceivedDatagram`. The senders
+/// without path, i.e., unbound or bound to an abstract address, have none.
+pub fn sender_path(from: &socket2::SockAddr) -> Option<PathBuf> {
+    if from.family() != libc::AF_UNIX as libc::sa_family_t {
+        return None;
+    }
+    // The path follows the family, and is not always terminated by a null byte.
+    let len = (from.len() as usize).checked_sub(std::mem::size_of::<libc::sa_family_t>())?;
+    let addr = unsafe { &*(from.as_ptr() as *const libc::sockaddr_un) };
+    let path: Vec<u8> = addr.sun_path[..len.min(addr.sun_path.len())]
+        .iter()
+        .map(|c| *c as u8)
+        .take_while(|c| *c != 0)
+        .collect();
+    match path.is_empty() {
+        true => None,
+        false => Some(PathBuf::from(OsStr::from_bytes(&path))),
+    }
+}
+
+/// Credentials of the SCM_CREDENTIALS control message of `msg`, if any.
+#[cfg(target_os = "linux")]
+unsafe fn read_credentials(msg: &libc::msghdr) -> Option<Credentials> {
+    let mut credentials = None;
+    let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+    while !cmsg.is_null() {
+        if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_CREDENTIALS {
+            let ucred = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::ucred);
+            credentials = Some(Credentials {
+                pid: ucred.pid,
+                uid: ucred.uid,
+                gid: ucred.gid,
+            });
+        }
+        cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+    }
+    credentials
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    /// Tests the matching of the access control rules.
+    fn test_is_allowed() {
+        let credentials = Credentials {
+            pid: 42,
+            uid: 1000,
+            gid: 100,
+        };
+
+        // No rule: everything is allowed, even without credentials.
+        assert!(is_allowed(&[], None, 6));
+        assert!(is_allowed(&[], Some(&credentials), 6));
+
+        let acl = [
+            AclRule {
+                uid: Some(1000),
+                gid: None,
+                protos: Some(vec![6, 17]),
+            },
+            AclRule {
+                uid: None,
+                gid: Some(200),
+                protos: None,
+            },
+        ];
+        assert!(!is_allowed(&acl, None, 6));
+        assert!(is_allowed(&acl, Some(&credentials), 6));
+        assert!(is_allowed(&acl, Some(&credentials), 17));
+        assert!(!is_allowed(&acl, Some(&credentials), 5));
+
+        let other = Credentials {
+            pid: 43,
+            uid: 1001,
+            gid: 200,
+        };
+        assert!(is_allowed(&acl, Some(&other), 5));
+
+        let other = Credentials {
+            pid: 44,
+            uid: 1001,
+            gid: 100,
+        };
+        assert!(!is_allowed(&acl, Some(&other), 6));
+    }
+
+    #[test]
+    /// Tests the parsing of the access control list from the configuration.
+    fn test_acl_deserialize() {
+        let acl: Vec<AclRule> =
+            serde_json::from_str(r#"[{"uid": 0}, {"gid": 100, "protos": [6]}]"#).unwrap();
+        assert_eq!(
+            acl,
+            vec![
+                AclRule {
+                    uid: Some(0),
+                    gid: None,
+                    protos: None
+                },
+                AclRule {
+                    uid: None,
+                    gid: Some(100),
+                    protos: Some(vec![6])
+                },
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    /// Tests that the credentials of the sender are received with the datagram.
+    fn test_recv_with_credentials() {
+        let (sender, receiver) = UnixDatagram::pair().unwrap();
+
+        // Without SO_PASSCRED, no credentials.
+        sender.send(&[1, 2, 3]).unwrap();
+        let mut buf = [MaybeUninit::new(0u8); 16];
+        let datagram = recv_with_credentials(receiver.as_raw_fd(), &mut buf).unwrap();
+        assert_eq!(datagram.read, 3);
+        assert!(datagram.credentials.is_none());
+        assert!(!datagram.truncated);
+
+        enable_credentials(receiver.as_raw_fd()).unwrap();
+        sender.send(&[4, 5]).unwrap();
+        let datagram = recv_with_credentials(receiver.as_raw_fd(), &mut buf).unwrap();
+        assert_eq!(datagram.read, 2);
+        assert_eq!(unsafe { buf[0].assume_init() }, 4);
+        let credentials = datagram.credentials.unwrap();
+        assert_eq!(credentials.pid, std::process::id() as i32);
+        assert_eq!(credentials.uid, unsafe { libc::getuid() });
+        assert_eq!(credentials.gid, unsafe { libc::getgid() });
+        assert_eq!(sender_path(&datagram.from), None);
+
+        // The path of a bound sender.
+        let dir = std::env::temp_dir();
+        let paths = ["sender", "receiver"]
+            .map(|name| dir.join(format!("bier-acl-{}-{}", name, std::process::id())));
+        for path in &paths {
+            let _ = std::fs::remove_file(path);
+        }
+        let sender = UnixDatagram::bind(&paths[0]).unwrap();
+        let receiver = UnixDatagram::bind(&paths[1]).unwrap();
+        sender.send_to(&[6], &paths[1]).unwrap();
+        let datagram = recv_with_credentials(receiver.as_raw_fd(), &mut buf).unwrap();
+        assert_eq!(sender_path(&datagram.from).as_ref(), Some(&paths[0]));
+        for path in &paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    /// Tests that a datagram larger than the buffer is reported as truncated.
+    fn test_recv_truncated() {
+        let (sender, receiver) = UnixDatagram::pair().unwrap();
+        let mut buf = [MaybeUninit::new(0u8); 16];
+        sender.send(&[0; 32]).unwrap();
+        let datagram = recv_with_credentials(receiver.as_raw_fd(), &mut buf).unwrap();
+        assert_eq!(datagram.read, 16);
+        assert!(datagram.truncated);
+    }
+}
@@ -0,0 +1,457 @@
+//! AF_PACKET socket sending and receiving the BIER packets directly in Ethernet frames
+//! (RFC 8296, ethertype 0xAB37), e.g., between BFRs on the same L2 segment. Contrary to
+//! AF_XDP (see `xdp`), it requires neither the support of the driver nor an eBPF program,
+//! at the cost of a copy of each frame. A socket receives and sends the frames of a single
+//! ethertype: the BIER one, or the MPLS one for the BIER packets in MPLS networks.
+//!
+//! The MAC address of a next hop is the one of its configuration (`NeighborConfig::mac`), or
+//! else the one of the neighbor table of the kernel, filled by ARP and NDP. A thread of the
+//! transport reads the table periodically, and on a miss triggers the resolution of the next
+//! hop by sending it an empty UDP datagram, then reads the table again. The copies toward a
+//! next hop without MAC address are dropped until its resolution, without blocking the
+//! datapath.
+
+use crate::daemon::Transport;
+use crate::ethernet::{ethernet_payload, write_ethernet_header, MacAddr};
+use crate::ethernet::{ETHERNET_HEADER_LEN, ETHERTYPE_MPLS};
+use crate::router::{Framing, Router};
+use crate::runtime::{drain, recv_datagram, report_truncated, EventSource};
+use crate::stats::DropReason;
+use log::debug;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// Period of the reads of the neighbor table of the kernel.
+const NEIGHBOR_REFRESH: Duration = Duration::from_secs(30);
+
+/// Minimum delay between two resolutions of the MAC address of a next hop.
+const RESOLUTION_DELAY: Duration = Duration::from_secs(1);
+
+/// Delay between the resolution of a next hop and the read of the neighbor table.
+const RESOLUTION_WAIT: Duration = Duration::from_millis(100);
+
+/// Discard port, destination of the datagrams triggering the resolution of a next hop.
+const DISCARD_PORT: u16 = 9;
+
+const NLMSG_HDR_LEN: usize = 16;
+const NDMSG_LEN: usize = 12;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const NLM_F_REQUEST: u16 = 1;
+const NLM_F_DUMP: u16 = 0x300;
+
+/// Index and MAC address of the interface `ifname`.
+pub fn interface(ifname: &str) -> io::Result<(u32, MacAddr)> {
+    let name = CString::new(ifname)?;
+    let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if ifindex == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No interface {}", ifname),
+        ));
+    }
+    let mac = std::fs::read_to_string(format!("/sys/class/net/{}/address", ifname))?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok((ifindex, mac))
+}
+
+/// AF_PACKET socket bound to `ethertype` on the interface `ifindex`. Requires `CAP_NET_RAW`.
+fn packet_socket(ifindex: u32, ethertype: u16) -> io::Result<Socket> {
+    let protocol = (ethertype.to_be() as i32).into();
+    let socket = Socket::new(Domain::PACKET, Type::RAW, Some(protocol))?;
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let addr = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_ll) };
+    addr.sll_family = libc::AF_PACKET as u16;
+    addr.sll_protocol = ethertype.to_be();
+    addr.sll_ifindex = ifindex as i32;
+    let len = std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+    socket.bind(&unsafe { SockAddr::new(storage, len) })?;
+    Ok(socket)
+}
+
+/// Opens an AF_PACKET socket on the interface `ifname` for the frames with `ethertype`, e.g.,
+/// `ETHERTYPE_BIER`, and splits it in the source receiving the frames and the transport
+/// sending the copies. `neighbors` are the static MAC addresses of the next hops. Larger
+/// frames than `max_packet_size` are dropped.
+pub fn open(
+    ifname: &str,
+    ethertype: u16,
+    neighbors: HashMap<IpAddr, MacAddr>,
+    max_packet_size: usize,
+) -> io::Result<(EthernetSource, EthernetTransport)> {
+    let (ifindex, mac) = interface(ifname)?;
+    let socket = packet_socket(ifindex, ethertype)?;
+    socket.set_nonblocking(true)?;
+    let source = EthernetSource {
+        socket: socket.try_clone()?,
+        ethertype,
+        buffer: Vec::with_capacity(ETHERNET_HEADER_LEN + max_packet_size),
+    };
+    let resolved = Arc::new(RwLock::new(HashMap::new()));
+    let (requests, receiver) = mpsc::channel();
+    let table = resolved.clone();
+    std::thread::Builder::new()
+        .name("bier-neighbors".to_string())
+        .spawn(move || resolver(ifindex, receiver, &table))?;
+    let transport = EthernetTransport {
+        socket,
+        mac,
+        ethertype,
+        neighbors,
+        resolved,
+        requests,
+        resolving: HashMap::new(),
+        frame: Vec::with_capacity(ETHERNET_HEADER_LEN + max_packet_size),
+    };
+    Ok((source, transport))
+}
+
+/// Receives the BIER frames of the interface, or the MPLS ones. The frames sent by this host
+/// are ignored.
+pub struct EthernetSource {
+    socket: Socket,
+    ethertype: u16,
+    buffer: Vec<u8>,
+}
+
+impl EventSource for EthernetSource {
+    fn fd(&self) -> Option<RawFd> {
+        Some(self.socket.as_raw_fd())
+    }
+
+    fn handle(&mut self, router: &mut Router) -> io::Result<()> {
+        drain(|| {
+            let (from, truncated) = recv_datagram(&self.socket, &mut self.buffer)?;
+            let pkttype = unsafe { (*(from.as_ptr() as *const libc::sockaddr_ll)).sll_pkttype };
+            if matches!(pkttype, libc::PACKET_OUTGOING | libc::PACKET_OTHERHOST) {
+                return Ok(());
+            }
+            if truncated {
+                report_truncated(router, self.buffer.capacity() - ETHERNET_HEADER_LEN);
+                return Ok(());
+            }
+            let Some(packet) = ethernet_payload(&mut self.buffer, self.ethertype) else {
+                router.daemon_mut().record_drop(DropReason::MalformedPacket);
+                return Ok(());
+            };
+            let framing = match self.ethertype {
+                ETHERTYPE_MPLS => Framing::Mpls,
+                _ => Framing::Bier,
+            };
+            if let Err(e) = router.handle_packet(framing, packet) {
+                debug!(
+                    "Error when processing the BIER packet: {:?}, continuing...",
+                    e
+                );
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Sends the copies in Ethernet frames toward the MAC address of their next hop.
+pub struct EthernetTransport {
+    socket: Socket,
+    mac: MacAddr,
+    ethertype: u16,
+    /// MAC addresses of the configuration.
+    neighbors: HashMap<IpAddr, MacAddr>,
+    /// MAC addresses of the neighbor table of the kernel, written by the resolver thread.
+    resolved: Arc<RwLock<HashMap<IpAddr, MacAddr>>>,
+    /// Next hops to resolve by the resolver thread.
+    requests: Sender<IpAddr>,
+    /// Last resolution requested for each next hop without MAC address.
+    resolving: HashMap<IpAddr, Instant>,
+    frame: Vec<u8>,
+}
+
+impl EthernetTransport {
+    /// MAC address of `next_hop`. Without MAC address, requests its resolution to the
+    /// resolver thread, at most once per `RESOLUTION_DELAY`.
+    fn resolve(&mut self, next_hop: IpAddr) -> Option<MacAddr> {
+        if let Some(&mac) = self.neighbors.get(&next_hop) {
+            return Some(mac);
+        }
+        if let Some(&mac) = self.resolved.read().unwrap().get(&next_hop) {
+            return Some(mac);
+        }
+        let now = Instant::now();
+        if self
+            .resolving
+            .get(&next_hop)
+            .is_none_or(|resolving| now - *resolving >= RESOLUTION_DELAY)
+        {
+            self.resolving.insert(next_hop, now);
+            if self.requests.send(next_hop).is_err() {
+                debug!(
+                    "Cannot resolve the MAC address of {}: no resolver",
+                    next_hop
+                );
+            }
+        }
+        None
+    }
+}
+
+/// Resolver thread: reads the neighbor table of the interface `ifindex` in `table` every
+/// `NEIGHBOR_REFRESH`, and shortly after triggering the resolution of each next hop of
+/// `requests`. Stops with the transport.
+fn resolver(ifindex: u32, requests: Receiver<IpAddr>, table: &RwLock<HashMap<IpAddr, MacAddr>>) {
+    // Sockets sending the datagrams that trigger the resolutions, by family.
+    let mut triggers: HashMap<bool, UdpSocket> = HashMap::new();
+    let mut refresh_at = Instant::now();
+    loop {
+        match requests.recv_timeout(refresh_at.saturating_duration_since(Instant::now())) {
+            Ok(next_hop) => {
+                let socket = match triggers.entry(next_hop.is_ipv4()) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        let unspecified = match next_hop {
+                            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                        };
+                        match UdpSocket::bind((unspecified, 0)) {
+                            Ok(socket) => entry.insert(socket),
+                            Err(e) => {
+                                debug!("Cannot resolve the MAC address of {}: {:?}", next_hop, e);
+                                continue;
+                            }
+                        }
+                    }
+                };
+                if let Err(e) = socket.send_to(&[], (next_hop, DISCARD_PORT)) {
+                    debug!("Cannot resolve the MAC address of {}: {:?}", next_hop, e);
+                }
+                refresh_at = refresh_at.min(Instant::now() + RESOLUTION_WAIT);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                match neighbor_table(ifindex) {
+                    Ok(neighbors) => *table.write().unwrap() = neighbors,
+                    Err(e) => debug!("Cannot read the neighbor table: {:?}", e),
+                }
+                refresh_at = Instant::now() + NEIGHBOR_REFRESH;
+            }
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+impl Transport for EthernetTransport {
+    fn send_to(&mut self, packet: &[u8], next_hop: IpAddr) -> io::Result<usize> {
+        let Some(dst) = self.resolve(next_hop) else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No MAC address for the next hop {}", next_hop),
+            ));
+        };
+        self.frame.clear();
+        self.frame.resize(ETHERNET_HEADER_LEN, 0);
+        write_ethernet_header(&mut self.frame, dst, self.mac, self.ethertype);
+        self.frame.extend_from_slice(packet);
+        self.socket.send(&self.frame)?;
+        Ok(packet.len())
+    }
+
+    /// Receives the payload of a frame with the ethertype of the transport. With MPLS, the
+    /// outer entries of the label stack are removed: the packet starts with the BIER header,
+    /// whose BIFT-id is the label of the bottom entry. The frames sent by this host are skipped.
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let (from, _) = recv_datagram(&self.socket, &mut self.frame)?;
+            let pkttype = unsafe { (*(from.as_ptr() as *const libc::sockaddr_ll)).sll_pkttype };
+            if matches!(pkttype, libc::PACKET_OUTGOING | libc::PACKET_OTHERHOST) {
+                continue;
+            }
+            let mut payload = ethernet_payload(&mut self.frame, self.ethertype)
+                .ok_or(io::ErrorKind::InvalidData)?;
+            if self.ethertype == ETHERTYPE_MPLS {
+                let offset = crate::header::bier_header_offset(payload)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                payload = &mut payload[offset..];
+            }
+            let length = payload.len().min(buf.len());
+            buf[..length].copy_from_slice(&payload[..length]);
+            return Ok(length);
+        }
+    }
+}
+
+/// Reachable neighbors of the interface `ifindex` in the neighbor table of the kernel.
+fn neighbor_table(ifindex: u32) -> io::Result<HashMap<IpAddr, MacAddr>> {
+    let socket = Socket::new(
+        Domain::from(libc::AF_NETLINK),
+        Type::RAW,
+        Some(Protocol::from(0)),
+    )?;
+    socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+    // Dump of the neighbors of all the families.
+    let mut request = [0u8; NLMSG_HDR_LEN + NDMSG_LEN];
+    request[..4].copy_from_slice(&((NLMSG_HDR_LEN + NDMSG_LEN) as u32).to_ne_bytes());
+    request[4..6].copy_from_slice(&libc::RTM_GETNEIGH.to_ne_bytes());
+    request[6..8].copy_from_slice(&(NLM_F_REQUEST | NLM_F_DUMP).to_ne_bytes());
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let kernel = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_nl) };
+    kernel.nl_family = libc::AF_NETLINK as u16;
+    let len = std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+    socket.send_to(&request, &unsafe { SockAddr::new(storage, len) })?;
+
+    let mut table = HashMap::new();
+    let mut buffer = Vec::with_capacity(32 * 1024);
+    loop {
+        recv_datagram(&socket, &mut buffer)?;
+        if parse_neighbors(&buffer, ifindex, &mut table)? {
+            return Ok(table);
+        }
+    }
+}
+
+/// Adds the reachable neighbors of `ifindex` in the netlink messages of `data`, a part of the
+/// answer to a dump of the neighbor table, to `table`. Returns whether the dump is done.
+fn parse_neighbors(
+    mut data: &[u8],
+    ifindex: u32,
+    table: &mut HashMap<IpAddr, MacAddr>,
+) -> io::Result<bool> {
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "Malformed netlink message");
+    let u16_at = |data: &[u8], at: usize| u16::from_ne_bytes([data[at], data[at + 1]]);
+    while data.len() >= NLMSG_HDR_LEN {
+        let len = u32::from_ne_bytes(data[..4].try_into().unwrap()) as usize;
+        if len < NLMSG_HDR_LEN || len > data.len() {
+            return Err(malformed());
+        }
+        let (message, rest) = data.split_at(len);
+        data = rest
+            .get(len.next_multiple_of(4) - len..)
+            .unwrap_or_default();
+        match u16_at(message, 4) {
+            NLMSG_DONE => return Ok(true),
+            NLMSG_ERROR => {
+                let code = message.get(16..20).ok_or_else(malformed)?;
+                let code = i32::from_ne_bytes(code.try_into().unwrap());
+                return Err(io::Error::from_raw_os_error(-code));
+            }
+            libc::RTM_NEWNEIGH => (),
+            _ => continue,
+        }
+        let ndmsg = message
+            .get(NLMSG_HDR_LEN..NLMSG_HDR_LEN + NDMSG_LEN)
+            .ok_or_else(malformed)?;
+        let state = u16_at(ndmsg, 8);
+        if u32::from_ne_bytes(ndmsg[4..8].try_into().unwrap()) != ifindex
+            || state & (libc::NUD_INCOMPLETE | libc::NUD_FAILED) != 0
+            || state == libc::NUD_NONE
+        {
+            continue;
+        }
+        let (mut dst, mut lladdr) = (None, None);
+        let mut attributes = &message[NLMSG_HDR_LEN + NDMSG_LEN..];
+        while attributes.len() >= 4 {
+            let attr_len = u16_at(attributes, 0) as usize;
+            if attr_len < 4 || attr_len > attributes.len() {
+                return Err(malformed());
+            }
+            let value = &attributes[4..attr_len];
+            match (u16_at(attributes, 2), value.len()) {
+                (libc::NDA_DST, 4) => dst = Some(IpAddr::from(<[u8; 4]>::try_from(value).unwrap())),
+                (libc::NDA_DST, 16) => {
+                    dst = Some(IpAddr::from(<[u8; 16]>::try_from(value).unwrap()))
+                }
+                (libc::NDA_LLADDR, 6) => lladdr = Some(MacAddr(value.try_into().unwrap())),
+                _ => (),
+            }
+            attributes = attributes
+                .get(attr_len.next_multiple_of(4)..)
+                .unwrap_or_default();
+        }
+        if let (Some(dst), Some(lladdr)) = (dst, lladdr) {
+            table.insert(dst, lladdr);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /// Netlink message of the neighbor `dst` of the interface `ifindex`.
+    fn neighbor_message(ifindex: u32, state: u16, dst: IpAddr, lladdr: &[u8]) -> Vec<u8> {
+        let mut message = vec![0u8; NLMSG_HDR_LEN + NDMSG_LEN];
+        message[4..6].copy_from_slice(&libc::RTM_NEWNEIGH.to_ne_bytes());
+        message[20..24].copy_from_slice(&ifindex.to_ne_bytes());
+        message[24..26].copy_from_slice(&state.to_ne_bytes());
+        let dst = match dst {
+            IpAddr::V4(dst) => dst.octets().to_vec(),
+            IpAddr::V6(dst) => dst.octets().to_vec(),
+        };
+        for (kind, value) in [(libc::NDA_DST, &dst[..]), (libc::NDA_LLADDR, lladdr)] {
+            message.extend(((4 + value.len()) as u16).to_ne_bytes());
+            message.extend(kind.to_ne_bytes());
+            message.extend(value);
+            message.resize(message.len().next_multiple_of(4), 0);
+        }
+        let len = message.len() as u32;
+        message[..4].copy_from_slice(&len.to_ne_bytes());
+        message
+    }
+
+    #[test]
+    /// Tests the parsing of the neighbor table, and the interfaces.
+    fn test_af_packet() {
+        let mac = [2, 0, 0, 0, 0, 0xb];
+        let (a, b, c): (IpAddr, IpAddr, IpAddr) = (
+            "fc00:b::1".parse().unwrap(),
+            "10.0.0.2".parse().unwrap(),
+            "10.0.0.3".parse().unwrap(),
+        );
+        let mut data = neighbor_message(2, libc::NUD_REACHABLE, a, &mac);
+        data.extend(neighbor_message(2, libc::NUD_STALE, b, &mac));
+        // Unresolved, and on another interface.
+        data.extend(neighbor_message(2, libc::NUD_INCOMPLETE, c, &[]));
+        data.extend(neighbor_message(3, libc::NUD_REACHABLE, c, &mac));
+        let mut table = HashMap::new();
+        assert!(!parse_neighbors(&data, 2, &mut table).unwrap());
+        assert_eq!(table.len(), 2);
+        assert_eq!((table[&a], table[&b]), (MacAddr(mac), MacAddr(mac)));
+
+        let mut done = vec![0u8; NLMSG_HDR_LEN + 4];
+        done[..4].copy_from_slice(&(NLMSG_HDR_LEN as u32 + 4).to_ne_bytes());
+        done[4..6].copy_from_slice(&NLMSG_DONE.to_ne_bytes());
+        assert!(parse_neighbors(&done, 2, &mut table).unwrap());
+        done[4..6].copy_from_slice(&NLMSG_ERROR.to_ne_bytes());
+        done[16..20].copy_from_slice(&(-libc::EPERM).to_ne_bytes());
+        let e = parse_neighbors(&done, 2, &mut table).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        assert!(parse_neighbors(&data[..30], 2, &mut table).is_err());
+
+        let (ifindex, mac) = interface("lo").unwrap();
+        assert_eq!(mac, MacAddr::default());
+        assert!(neighbor_table(ifindex).is_ok());
+        assert_eq!(
+            interface("bier-no-such-if").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        // The resolver reads the table after a resolution, and stops with the transport.
+        let table = Arc::new(RwLock::new(HashMap::from([(a, mac)])));
+        let (requests, receiver) = mpsc::channel();
+        let shared = table.clone();
+        let resolver = std::thread::spawn(move || resolver(ifindex, receiver, &shared));
+        requests.send("127.0.0.1".parse().unwrap()).unwrap();
+        std::thread::sleep(RESOLUTION_WAIT * 3);
+        assert!(!table.read().unwrap().contains_key(&a));
+        drop(requests);
+        resolver.join().unwrap();
+    }
+}
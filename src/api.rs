@@ -1,62 +1,874 @@
+use crate::bier::BierState;
+use crate::header::BierProto;
 use crate::{Error, Result};
+use log::debug;
+
+#[cfg(feature = "tokio")]
+pub use crate::tokio_client::AsyncBierSocket;
+
+/// Magic number starting every message exchanged with the BIER daemon.
+pub const API_MAGIC: u16 = 0xB1E5;
+
+/// Version of the message format written by this crate.
+pub const API_VERSION: u8 = 2;
+
+/// Oldest version of the message format that is still parsed. The version 1 data messages
+/// have no TLVs, and the data messages without TLVs are still written in this version.
+pub const API_MIN_VERSION: u8 = 1;
+
+/// Length of the magic number, version and message type prefix.
+pub const API_PREFIX_LENGTH: usize = 4;
+
+/// Type of an API message, carried after the version.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MessageType {
+    /// Payload to send in, or received from, the BIER domain.
+    Data = 0,
+
+    /// Query from an application to the daemon.
+    Query = 1,
+
+    /// Response of the daemon to a query.
+    Response = 2,
+
+    /// Several payloads to send in the BIER domain.
+    Batch = 3,
+
+    /// Notification of the daemon that a message of the application was dropped.
+    Error = 4,
+
+    /// Registration of an application receiving the packets of a proto, acknowledged by
+    /// the daemon with the same message.
+    Register = 5,
+}
+
+impl TryFrom<u8> for MessageType {
+    type Error = crate::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(MessageType::Data),
+            1 => Ok(MessageType::Query),
+            2 => Ok(MessageType::Response),
+            3 => Ok(MessageType::Batch),
+            4 => Ok(MessageType::Error),
+            5 => Ok(MessageType::Register),
+            _ => Err(Error::ApiMessageType),
+        }
+    }
+}
+
+/// Returns the type of the API message in `slice`.
+pub fn message_type(slice: &[u8]) -> Result<MessageType> {
+    check_prefix(slice)?;
+    slice[3].try_into()
+}
 
 pub type SendInfo<'a> = CommunicationInfo<'a>;
 pub type RecvInfo<'a> = CommunicationInfo<'a>;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct CommunicationInfo<'a> {
     pub bift_id: u32,
     pub proto: u16,
     pub bitstring: &'a [u8],
     pub payload: &'a [u8],
+    /// Optional fields of the BIER header, carried as TLVs: those of a packet delivered by
+    /// the daemon, or those chosen by the application for its packet.
+    pub metadata: Option<Metadata>,
 }
 
-impl CommunicationInfo<'_> {
-    pub fn from_slice(slice: &'_ [u8]) -> Result<CommunicationInfo> {
-        let bift_id = unsafe { crate::get_unchecked_be_u32(slice.as_ptr()) };
+/// Optional fields of the BIER header of a data message. The daemon delivers the packets
+/// with their BFIR-id, DSCP, entropy and S bit. The applications may set the TTL, DSCP,
+/// entropy and S bit of their packets, and the daemon sets their BFIR-id.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// BFR-id of the BFIR of the packet.
+    pub bfir_id: Option<u16>,
+    pub ttl: Option<u8>,
+    pub dscp: Option<u8>,
+    pub entropy: Option<u32>,
+    /// Bottom-of-stack bit of the first word of the header. By default, the daemon sets it
+    /// for the MPLS payloads (proto 1 and 2) only.
+    pub s: Option<bool>,
+}
+
+/// Types of the TLVs of the data messages, each followed by the length of its value (1 byte)
+/// and the value. The TLVs of an unknown type are skipped, so that the fields can be added
+/// without a new version.
+const TLV_BFIR_ID: u8 = 1;
+const TLV_TTL: u8 = 2;
+const TLV_DSCP: u8 = 3;
+const TLV_ENTROPY: u8 = 4;
+const TLV_S: u8 = 5;
+
+/// Largest DSCP (6 bits) and entropy (20 bits) of the BIER header.
+const MAX_DSCP: u8 = 0x3f;
+const MAX_ENTROPY: u32 = 0xfffff;
+
+impl Metadata {
+    /// Parses the TLVs of a data message.
+    fn from_tlvs(mut tlvs: &[u8]) -> Result<Self> {
+        let mut metadata = Metadata::default();
+        while !tlvs.is_empty() {
+            let (tlv_type, length) = match tlvs {
+                [tlv_type, length, ..] => (*tlv_type, *length as usize),
+                _ => return Err(Error::ApiTlv),
+            };
+            let value = tlvs.get(2..2 + length).ok_or(Error::ApiTlv)?;
+            match (tlv_type, value) {
+                (TLV_BFIR_ID, &[a, b]) => metadata.bfir_id = Some(u16::from_be_bytes([a, b])),
+                (TLV_TTL, &[ttl]) => metadata.ttl = Some(ttl),
+                (TLV_DSCP, &[dscp]) if dscp <= MAX_DSCP => metadata.dscp = Some(dscp),
+                (TLV_ENTROPY, &[a, b, c, d]) if u32::from_be_bytes([a, b, c, d]) <= MAX_ENTROPY => {
+                    metadata.entropy = Some(u32::from_be_bytes([a, b, c, d]))
+                }
+                (TLV_S, &[s]) if s <= 1 => metadata.s = Some(s == 1),
+                (TLV_BFIR_ID | TLV_TTL | TLV_DSCP | TLV_ENTROPY | TLV_S, _) => {
+                    return Err(Error::ApiTlv)
+                }
+                _ => debug!("Skipping the API TLV of unknown type {}", tlv_type),
+            }
+            tlvs = &tlvs[2 + length..];
+        }
+        Ok(metadata)
+    }
+
+    /// Checks that the fields fit in the BIER header, instead of truncating them.
+    fn validate(&self) -> Result<()> {
+        if self.dscp.is_some_and(|dscp| dscp > MAX_DSCP)
+            || self.entropy.is_some_and(|entropy| entropy > MAX_ENTROPY)
+        {
+            return Err(Error::ApiTlv);
+        }
+        Ok(())
+    }
+
+    /// Encoded TLVs of the fields that are set.
+    fn tlvs(&self) -> Vec<u8> {
+        let mut tlvs = Vec::new();
+        let mut push = |tlv_type: u8, value: &[u8]| {
+            tlvs.extend([tlv_type, value.len() as u8]);
+            tlvs.extend(value);
+        };
+        if let Some(bfir_id) = self.bfir_id {
+            push(TLV_BFIR_ID, &bfir_id.to_be_bytes());
+        }
+        if let Some(ttl) = self.ttl {
+            push(TLV_TTL, &[ttl]);
+        }
+        if let Some(dscp) = self.dscp {
+            push(TLV_DSCP, &[dscp]);
+        }
+        if let Some(entropy) = self.entropy {
+            push(TLV_ENTROPY, &entropy.to_be_bytes());
+        }
+        if let Some(s) = self.s {
+            push(TLV_S, &[s as u8]);
+        }
+        tlvs
+    }
+
+    /// Length of the encoded TLVs.
+    fn tlvs_length(&self) -> usize {
+        self.bfir_id.map_or(0, |_| 4)
+            + self.ttl.map_or(0, |_| 3)
+            + self.dscp.map_or(0, |_| 3)
+            + self.entropy.map_or(0, |_| 6)
+            + self.s.map_or(0, |_| 3)
+    }
+}
 
-        let proto = unsafe { crate::get_unchecked_be_u16(slice.as_ptr().add(4)) };
+impl CommunicationInfo<'_> {
+    /// Parses a data message. Since version 2, the fixed fields are followed by the length
+    /// of the TLVs (2 bytes) and the TLVs, before the bitstring. The metadata without any
+    /// known field is `None`, as it is encoded without TLVs.
+    pub fn from_slice(slice: &'_ [u8]) -> Result<CommunicationInfo<'_>> {
+        if message_type(slice)? != MessageType::Data {
+            return Err(Error::ApiMessageType);
+        }
+        let version = slice[2];
+        // The fixed fields must be present before reading them.
+        let fixed_length = API_PREFIX_LENGTH + if version == 1 { 8 } else { 10 };
+        crate::check_length(slice, fixed_length)?;
 
-        let bitstring_length =
-            unsafe { crate::get_unchecked_be_u16(slice.as_ptr().add(6)) as usize };
+        let bift_id = crate::be_u32(slice, API_PREFIX_LENGTH);
+        let proto = crate::be_u16(slice, API_PREFIX_LENGTH + 4);
+        let bitstring_length = crate::be_u16(slice, API_PREFIX_LENGTH + 6) as usize;
+        let tlvs_length = match version {
+            1 => 0,
+            _ => crate::be_u16(slice, API_PREFIX_LENGTH + 8) as usize,
+        };
 
-        if slice.len() < 4 + 2 + 2 + bitstring_length {
-            return Err(crate::Error::SliceWrongLength);
+        crate::check_length(slice, fixed_length + tlvs_length + bitstring_length)?;
+        let slice = &slice[fixed_length..];
+        let metadata = match tlvs_length {
+            0 => None,
+            _ => Some(Metadata::from_tlvs(&slice[..tlvs_length])?)
+                .filter(|metadata| *metadata != Metadata::default()),
+        };
+        let slice = &slice[tlvs_length..];
+        if !crate::bier::Bitstring::is_valid(&slice[..bitstring_length]) {
+            return Err(Error::BitstringLength);
         }
 
         Ok(CommunicationInfo {
             bift_id,
             proto,
-            bitstring: &slice[8..8 + bitstring_length],
-            payload: &slice[8 + bitstring_length..],
+            bitstring: &slice[..bitstring_length],
+            payload: &slice[bitstring_length..],
+            metadata,
         })
     }
 
+    /// Length of the message encoded by `to_slice`.
+    pub fn message_length(&self) -> usize {
+        let tlvs_length = self.metadata.map_or(0, |metadata| metadata.tlvs_length());
+        // Without TLVs, the message is written in version 1, without the length of the TLVs.
+        let fixed_length = if tlvs_length == 0 { 8 } else { 10 };
+        API_PREFIX_LENGTH + fixed_length + tlvs_length + self.bitstring.len() + self.payload.len()
+    }
+
+    /// Writes the data message, in version 1 if there is no metadata to carry, so that the
+    /// applications that only parse version 1 keep receiving the packets without metadata.
     pub fn to_slice(&self, slice: &mut [u8]) -> Result<usize> {
-        let len = 8 + self.bitstring.len() + self.payload.len();
-        if slice.len() < len {
-            return Err(Error::SliceWrongLength);
+        let len = self.message_length();
+        crate::check_length(slice, len)?;
+        if let Some(metadata) = &self.metadata {
+            metadata.validate()?;
         }
 
+        write_prefix(slice, MessageType::Data);
+        let tlvs = self.metadata.map(|metadata| metadata.tlvs()).unwrap_or_default();
+        if tlvs.is_empty() {
+            slice[2] = API_MIN_VERSION;
+        }
+        let slice = &mut slice[API_PREFIX_LENGTH..len];
+
         let val = self.bift_id.to_be_bytes();
         slice[..4].copy_from_slice(&val);
         slice[4..6].copy_from_slice(&self.proto.to_be_bytes());
         slice[6..8].copy_from_slice(&(self.bitstring.len() as u16).to_be_bytes());
-        slice[8..8 + self.bitstring.len()].copy_from_slice(self.bitstring);
-        slice[8 + self.bitstring.len()..len].copy_from_slice(self.payload);
+        let slice = match tlvs.is_empty() {
+            true => &mut slice[8..],
+            false => {
+                slice[8..10].copy_from_slice(&(tlvs.len() as u16).to_be_bytes());
+                &mut slice[10..]
+            }
+        };
+        slice[..tlvs.len()].copy_from_slice(&tlvs);
+        let slice = &mut slice[tlvs.len()..];
+        slice[..self.bitstring.len()].copy_from_slice(self.bitstring);
+        slice[self.bitstring.len()..].copy_from_slice(self.payload);
 
         Ok(len)
     }
 }
 
+/// Vec-backed version of `CommunicationInfo`, which can be stored by applications,
+/// queued or sent to another thread.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OwnedCommunicationInfo {
+    pub bift_id: u32,
+    pub proto: u16,
+    pub bitstring: Vec<u8>,
+    pub payload: Vec<u8>,
+    pub metadata: Option<Metadata>,
+}
+
+pub type OwnedSendInfo = OwnedCommunicationInfo;
+pub type OwnedRecvInfo = OwnedCommunicationInfo;
+
+impl OwnedCommunicationInfo {
+    /// Borrows the fields as a `CommunicationInfo`, e.g., to call `to_slice`.
+    pub fn as_info(&self) -> CommunicationInfo<'_> {
+        CommunicationInfo {
+            bift_id: self.bift_id,
+            proto: self.proto,
+            bitstring: &self.bitstring,
+            payload: &self.payload,
+            metadata: self.metadata,
+        }
+    }
+
+    /// Parses a data message and copies its content.
+    pub fn from_slice(slice: &[u8]) -> Result<Self> {
+        CommunicationInfo::from_slice(slice).map(Self::from)
+    }
+
+    /// Length of the message encoded by `to_slice`.
+    pub fn message_length(&self) -> usize {
+        self.as_info().message_length()
+    }
+
+    pub fn to_slice(&self, slice: &mut [u8]) -> Result<usize> {
+        self.as_info().to_slice(slice)
+    }
+}
+
+impl CommunicationInfo<'_> {
+    /// Typed proto of the payload, or `Error::Proto` if it is not assigned.
+    pub fn bier_proto(&self) -> Result<BierProto> {
+        u8::try_from(self.proto)
+            .map_err(|_| Error::Proto)
+            .and_then(BierProto::try_from)
+    }
+
+    /// Copies the fields in an `OwnedCommunicationInfo`.
+    pub fn to_owned_info(&self) -> OwnedCommunicationInfo {
+        OwnedCommunicationInfo::from(self)
+    }
+}
+
+impl From<&CommunicationInfo<'_>> for OwnedCommunicationInfo {
+    fn from(info: &CommunicationInfo<'_>) -> Self {
+        OwnedCommunicationInfo {
+            bift_id: info.bift_id,
+            proto: info.proto,
+            bitstring: info.bitstring.to_vec(),
+            payload: info.payload.to_vec(),
+            metadata: info.metadata,
+        }
+    }
+}
+
+impl From<CommunicationInfo<'_>> for OwnedCommunicationInfo {
+    fn from(info: CommunicationInfo<'_>) -> Self {
+        OwnedCommunicationInfo::from(&info)
+    }
+}
+
+impl<'a> From<&'a OwnedCommunicationInfo> for CommunicationInfo<'a> {
+    fn from(info: &'a OwnedCommunicationInfo) -> Self {
+        info.as_info()
+    }
+}
+
+/// Several (bitstring, payload) records sent with the same BIFT-id and proto
+/// in a single message, to reduce the number of system calls of high-rate sources.
+#[derive(Debug)]
+pub struct BatchInfo<'a> {
+    pub bift_id: u32,
+    pub proto: u16,
+    pub records: Vec<(&'a [u8], &'a [u8])>,
+}
+
+impl<'a> BatchInfo<'a> {
+    /// Parses a batch. After the BIFT-id and the proto, the message contains the
+    /// number of records (2 bytes). Each record is formed by the bitstring length (2 bytes),
+    /// the payload length (2 bytes), the bitstring and the payload.
+    pub fn from_slice(slice: &'a [u8]) -> Result<BatchInfo<'a>> {
+        if message_type(slice)? != MessageType::Batch {
+            return Err(Error::ApiMessageType);
+        }
+
+        crate::check_length(slice, API_PREFIX_LENGTH + 4 + 2 + 2)?;
+        let slice = &slice[API_PREFIX_LENGTH..];
+        let bift_id = u32::from_be_bytes([slice[0], slice[1], slice[2], slice[3]]);
+        let proto = u16::from_be_bytes([slice[4], slice[5]]);
+        let nb_records = u16::from_be_bytes([slice[6], slice[7]]) as usize;
+
+        let mut remaining = &slice[8..];
+        let mut records = Vec::with_capacity(nb_records);
+        for _ in 0..nb_records {
+            crate::check_length(remaining, 4)?;
+            let bitstring_length = u16::from_be_bytes([remaining[0], remaining[1]]) as usize;
+            let payload_length = u16::from_be_bytes([remaining[2], remaining[3]]) as usize;
+            crate::check_length(remaining, 4 + bitstring_length + payload_length)?;
+            records.push((
+                &remaining[4..4 + bitstring_length],
+                &remaining[4 + bitstring_length..4 + bitstring_length + payload_length],
+            ));
+            remaining = &remaining[4 + bitstring_length + payload_length..];
+        }
+
+        Ok(BatchInfo {
+            bift_id,
+            proto,
+            records,
+        })
+    }
+
+    pub fn to_slice(&self, slice: &mut [u8]) -> Result<usize> {
+        let len = API_PREFIX_LENGTH
+            + 8
+            + self
+                .records
+                .iter()
+                .map(|(bitstring, payload)| 4 + bitstring.len() + payload.len())
+                .sum::<usize>();
+        crate::check_length(slice, len)?;
+        if self.records.len() > u16::MAX as usize {
+            return Err(Error::PacketTooLarge);
+        }
+
+        write_prefix(slice, MessageType::Batch);
+        let slice = &mut slice[API_PREFIX_LENGTH..];
+
+        slice[..4].copy_from_slice(&self.bift_id.to_be_bytes());
+        slice[4..6].copy_from_slice(&self.proto.to_be_bytes());
+        slice[6..8].copy_from_slice(&(self.records.len() as u16).to_be_bytes());
+        let mut offset = 8;
+        for (bitstring, payload) in self.records.iter() {
+            slice[offset..offset + 2].copy_from_slice(&(bitstring.len() as u16).to_be_bytes());
+            slice[offset + 2..offset + 4].copy_from_slice(&(payload.len() as u16).to_be_bytes());
+            offset += 4;
+            slice[offset..offset + bitstring.len()].copy_from_slice(bitstring);
+            offset += bitstring.len();
+            slice[offset..offset + payload.len()].copy_from_slice(payload);
+            offset += payload.len();
+        }
+
+        Ok(len)
+    }
+
+    /// Iterates over the records as individual `SendInfo`s.
+    pub fn iter(&self) -> impl Iterator<Item = SendInfo<'a>> + '_ {
+        self.records.iter().map(|&(bitstring, payload)| SendInfo {
+            bift_id: self.bift_id,
+            proto: self.proto,
+            bitstring,
+            payload,
+            metadata: None,
+        })
+    }
+}
+
+/// Checks that the message starts with the magic number and a supported version,
+/// to reject datagrams that do not come from a BIER application.
+fn check_prefix(slice: &[u8]) -> Result<()> {
+    if slice.len() < API_PREFIX_LENGTH || u16::from_be_bytes([slice[0], slice[1]]) != API_MAGIC {
+        return Err(Error::ApiMagic);
+    }
+
+    if !(API_MIN_VERSION..=API_VERSION).contains(&slice[2]) {
+        return Err(Error::ApiVersion(slice[2]));
+    }
+
+    Ok(())
+}
+
+/// Writes the magic number, the version and the message type at the start of the message.
+fn write_prefix(slice: &mut [u8], message_type: MessageType) {
+    slice[..2].copy_from_slice(&API_MAGIC.to_be_bytes());
+    slice[2] = API_VERSION;
+    slice[3] = message_type as u8;
+}
+
+/// Information an application can query from the daemon.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Query {
+    /// BFR-id, sub-domain and BSL of each BIFT of the daemon.
+    Bifts = 1,
+
+    /// Mapping between the BFR names and their BFR-id.
+    BfrNames = 2,
+}
+
+impl Query {
+    pub fn from_slice(slice: &[u8]) -> Result<Self> {
+        if message_type(slice)? != MessageType::Query {
+            return Err(Error::ApiMessageType);
+        }
+
+        crate::check_length(slice, API_PREFIX_LENGTH + 1)?;
+        match slice[API_PREFIX_LENGTH] {
+            1 => Ok(Query::Bifts),
+            2 => Ok(Query::BfrNames),
+            _ => Err(Error::ApiMessageType),
+        }
+    }
+
+    pub fn to_slice(&self, slice: &mut [u8]) -> Result<usize> {
+        let len = API_PREFIX_LENGTH + 1;
+        crate::check_length(slice, len)?;
+
+        write_prefix(slice, MessageType::Query);
+        slice[API_PREFIX_LENGTH] = *self as u8;
+
+        Ok(len)
+    }
+
+    /// Answers the query using the state of the daemon.
+    pub fn answer(&self, bier_state: &BierState) -> Response {
+        match self {
+            Query::Bifts => Response::Bifts(
+                bier_state
+                    .bifts
+                    .iter()
+                    .map(|bift| BiftInfo {
+                        bift_id: bift.bift_id as u32,
+                        sub_domain: bift.sub_domain,
+                        bfr_id: bift.bfr_id as u16,
+                        bsl: bift.get_bsl() as u16,
+                    })
+                    .collect(),
+            ),
+            Query::BfrNames => Response::BfrNames(
+                bier_state
+                    .bfr_names
+                    .iter()
+                    .map(|(name, &bfr_id)| (name.clone(), bfr_id as u16))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Description of a BIFT of the daemon, used to build `SendInfo`s.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BiftInfo {
+    pub bift_id: u32,
+    pub sub_domain: u8,
+    /// BFR-id of the daemon in this BIFT.
+    pub bfr_id: u16,
+    /// BitString Length, in bits.
+    pub bsl: u16,
+}
+
+/// Length of an encoded `BiftInfo`.
+const BIFT_INFO_LENGTH: usize = 4 + 1 + 2 + 2;
+
+/// Response of the daemon to a `Query`, with the same discriminant.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Response {
+    Bifts(Vec<BiftInfo>),
+    BfrNames(Vec<(String, u16)>),
+}
+
+impl Response {
+    /// Parses a response. The message is formed by the query type,
+    /// the number of records (2 bytes), and the records.
+    pub fn from_slice(slice: &[u8]) -> Result<Self> {
+        if message_type(slice)? != MessageType::Response {
+            return Err(Error::ApiMessageType);
+        }
+
+        crate::check_length(slice, API_PREFIX_LENGTH + 3)?;
+        let slice = &slice[API_PREFIX_LENGTH..];
+        let nb_records = u16::from_be_bytes([slice[1], slice[2]]) as usize;
+        let mut records = &slice[3..];
+
+        match slice[0] {
+            1 => {
+                crate::check_length(records, nb_records * BIFT_INFO_LENGTH)?;
+                let infos = records
+                    .chunks_exact(BIFT_INFO_LENGTH)
+                    .take(nb_records)
+                    .map(|record| BiftInfo {
+                        bift_id: u32::from_be_bytes([record[0], record[1], record[2], record[3]]),
+                        sub_domain: record[4],
+                        bfr_id: u16::from_be_bytes([record[5], record[6]]),
+                        bsl: u16::from_be_bytes([record[7], record[8]]),
+                    })
+                    .collect();
+                Ok(Response::Bifts(infos))
+            }
+            2 => {
+                // Each record is the name length (1 byte), the name and the BFR-id (2 bytes).
+                let mut names = Vec::with_capacity(nb_records);
+                for _ in 0..nb_records {
+                    crate::check_length(records, 1)?;
+                    let name_length = records[0] as usize;
+                    crate::check_length(records, 1 + name_length + 2)?;
+                    let name = String::from_utf8_lossy(&records[1..1 + name_length]).to_string();
+                    let bfr_id =
+                        u16::from_be_bytes([records[1 + name_length], records[2 + name_length]]);
+                    names.push((name, bfr_id));
+                    records = &records[3 + name_length..];
+                }
+                Ok(Response::BfrNames(names))
+            }
+            _ => Err(Error::ApiMessageType),
+        }
+    }
+
+    pub fn to_slice(&self, slice: &mut [u8]) -> Result<usize> {
+        let (query, nb_records, len) = match self {
+            Response::Bifts(infos) => (Query::Bifts, infos.len(), infos.len() * BIFT_INFO_LENGTH),
+            Response::BfrNames(names) => (
+                Query::BfrNames,
+                names.len(),
+                names.iter().map(|(name, _)| 1 + name.len().min(255) + 2).sum(),
+            ),
+        };
+        let len = API_PREFIX_LENGTH + 3 + len;
+        crate::check_length(slice, len)?;
+
+        write_prefix(slice, MessageType::Response);
+        slice[API_PREFIX_LENGTH] = query as u8;
+        slice[API_PREFIX_LENGTH + 1..API_PREFIX_LENGTH + 3]
+            .copy_from_slice(&(nb_records as u16).to_be_bytes());
+        let mut records = &mut slice[API_PREFIX_LENGTH + 3..len];
+
+        match self {
+            Response::Bifts(infos) => {
+                for (info, record) in infos.iter().zip(records.chunks_exact_mut(BIFT_INFO_LENGTH)) {
+                    record[..4].copy_from_slice(&info.bift_id.to_be_bytes());
+                    record[4] = info.sub_domain;
+                    record[5..7].copy_from_slice(&info.bfr_id.to_be_bytes());
+                    record[7..9].copy_from_slice(&info.bsl.to_be_bytes());
+                }
+            }
+            Response::BfrNames(names) => {
+                for (name, bfr_id) in names {
+                    // Names are truncated to 255 bytes.
+                    let name = &name.as_bytes()[..name.len().min(255)];
+                    records[0] = name.len() as u8;
+                    records[1..1 + name.len()].copy_from_slice(name);
+                    records[1 + name.len()..3 + name.len()].copy_from_slice(&bfr_id.to_be_bytes());
+                    records = &mut records[3 + name.len()..];
+                }
+            }
+        }
+
+        Ok(len)
+    }
+}
+
+/// Registration of the application bound at `path` to receive the packets with `proto`
+/// delivered to this BFER, instead of the default destination of the daemon. The path must
+/// be the one of the sender of the registration. An empty path removes the registration of
+/// the proto by the sender.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Registration {
+    pub proto: u16,
+    pub path: String,
+    /// Version of the registration message, i.e., the newest version of the data messages
+    /// parsed by the application. The daemon delivers the metadata of the packets only to
+    /// the applications of version 2 or newer.
+    pub version: u8,
+}
+
+impl Registration {
+    /// Parses a registration, formed by the proto (2 bytes) and the path, until the end.
+    pub fn from_slice(slice: &[u8]) -> Result<Self> {
+        if message_type(slice)? != MessageType::Register {
+            return Err(Error::ApiMessageType);
+        }
+        crate::check_length(slice, API_PREFIX_LENGTH + 2)?;
+        let version = slice[2];
+        let slice = &slice[API_PREFIX_LENGTH..];
+        let path = std::str::from_utf8(&slice[2..]).map_err(|_| Error::Registration)?;
+
+        Ok(Registration {
+            proto: crate::be_u16(slice, 0),
+            path: path.to_string(),
+            version,
+        })
+    }
+
+    pub fn to_slice(&self, slice: &mut [u8]) -> Result<usize> {
+        let len = API_PREFIX_LENGTH + 2 + self.path.len();
+        crate::check_length(slice, len)?;
+
+        write_prefix(slice, MessageType::Register);
+        slice[2] = self.version;
+        slice[API_PREFIX_LENGTH..API_PREFIX_LENGTH + 2].copy_from_slice(&self.proto.to_be_bytes());
+        slice[API_PREFIX_LENGTH + 2..len].copy_from_slice(self.path.as_bytes());
+
+        Ok(len)
+    }
+}
+
+impl std::fmt::Display for Registration {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "REGISTER proto={} path={}", self.proto, self.path)
+    }
+}
+
+/// Reason of the drop of the message of an application, see `ErrorNotification`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ErrorCode {
+    /// The message cannot be parsed.
+    MalformedMessage = 1,
+
+    /// The application is not allowed to send packets with this proto.
+    Unauthorized = 2,
+
+    /// No BIFT with the BIFT-id of the message.
+    UnknownBift = 3,
+
+    /// A bit of the bitstring has no entry in the BIFT.
+    NoEntry = 4,
+
+    /// The length of the bitstring is not a supported BSL.
+    BitstringLength = 5,
+
+    /// The packet is larger than the buffers of the daemon.
+    PacketTooLarge = 6,
+
+    /// The registration of the application is refused.
+    Registration = 7,
+
+    /// The version of the message is not supported by the daemon.
+    UnsupportedVersion = 8,
+
+    /// The type of the message is unknown, or not expected from an application.
+    UnknownMessageType = 9,
+
+    /// The MPLS payload does not start with a label stack ending with the bottom-of-stack bit.
+    MplsLabelStack = 10,
+}
+
+impl From<Error> for ErrorCode {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Unauthorized => ErrorCode::Unauthorized,
+            Error::BiftId(_) => ErrorCode::UnknownBift,
+            Error::NoEntry => ErrorCode::NoEntry,
+            Error::BitstringLength | Error::BitstringLengthMismatch { .. } => {
+                ErrorCode::BitstringLength
+            }
+            Error::PacketTooLarge => ErrorCode::PacketTooLarge,
+            Error::Registration => ErrorCode::Registration,
+            Error::ApiVersion(_) => ErrorCode::UnsupportedVersion,
+            Error::ApiMessageType => ErrorCode::UnknownMessageType,
+            Error::MplsLabelStack => ErrorCode::MplsLabelStack,
+            Error::Remote(code) => code,
+            _ => ErrorCode::MalformedMessage,
+        }
+    }
+}
+
+/// Error of the daemon behind the code, e.g., to handle the notifications like the errors
+/// of an embedded daemon. The codes of the errors with a context that the notification
+/// does not carry, e.g., the lengths of a malformed message, are reported as `Error::Remote`.
+impl From<ErrorCode> for Error {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::Unauthorized => Error::Unauthorized,
+            ErrorCode::NoEntry => Error::NoEntry,
+            ErrorCode::BitstringLength => Error::BitstringLength,
+            ErrorCode::PacketTooLarge => Error::PacketTooLarge,
+            ErrorCode::Registration => Error::Registration,
+            ErrorCode::UnknownMessageType => Error::ApiMessageType,
+            ErrorCode::MplsLabelStack => Error::MplsLabelStack,
+            ErrorCode::MalformedMessage
+            | ErrorCode::UnknownBift
+            | ErrorCode::UnsupportedVersion => Error::Remote(code),
+        }
+    }
+}
+
+impl TryFrom<u8> for ErrorCode {
+    type Error = crate::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            1 => Ok(ErrorCode::MalformedMessage),
+            2 => Ok(ErrorCode::Unauthorized),
+            3 => Ok(ErrorCode::UnknownBift),
+            4 => Ok(ErrorCode::NoEntry),
+            5 => Ok(ErrorCode::BitstringLength),
+            6 => Ok(ErrorCode::PacketTooLarge),
+            7 => Ok(ErrorCode::Registration),
+            8 => Ok(ErrorCode::UnsupportedVersion),
+            9 => Ok(ErrorCode::UnknownMessageType),
+            10 => Ok(ErrorCode::MplsLabelStack),
+            _ => Err(Error::ApiMessageType),
+        }
+    }
+}
+
+/// Notification sent back by the daemon to an application whose message was dropped,
+/// so that the application can react, e.g., to a wrong BIFT-id. The BIFT-id and the proto
+/// are those of the dropped message, 0 if they cannot be read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ErrorNotification {
+    pub code: ErrorCode,
+    pub bift_id: u32,
+    pub proto: u16,
+}
+
+impl std::fmt::Display for ErrorNotification {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "message dropped by the daemon: {:?} (BIFT-id {}, proto {})",
+            self.code, self.bift_id, self.proto
+        )
+    }
+}
+
+impl std::error::Error for ErrorNotification {}
+
+/// Length of an encoded `ErrorNotification`.
+const ERROR_NOTIFICATION_LENGTH: usize = API_PREFIX_LENGTH + 1 + 4 + 2;
+
+impl ErrorNotification {
+    /// Error of the daemon that dropped the message, with the BIFT-id of the notification
+    /// for an unknown BIFT, see `From<ErrorCode> for Error`.
+    pub fn error(&self) -> Error {
+        match self.code {
+            ErrorCode::UnknownBift => Error::BiftId(self.bift_id),
+            code => code.into(),
+        }
+    }
+
+    /// Notification of the drop of `message` because of `error`.
+    pub fn new(message: &[u8], error: Error) -> Self {
+        // The data and batch messages start with the BIFT-id and the proto, and the
+        // registrations with the proto.
+        let fields = match message_type(message) {
+            Ok(MessageType::Register) => message
+                .get(API_PREFIX_LENGTH..API_PREFIX_LENGTH + 2)
+                .map(|fields| (0, crate::be_u16(fields, 0))),
+            _ => message
+                .get(API_PREFIX_LENGTH..API_PREFIX_LENGTH + 6)
+                .map(|fields| (crate::be_u32(fields, 0), crate::be_u16(fields, 4))),
+        };
+        let (bift_id, proto) = fields.unwrap_or((0, 0));
+        ErrorNotification {
+            code: error.into(),
+            bift_id,
+            proto,
+        }
+    }
+
+    /// Parses a notification, formed by the error code (1 byte), the BIFT-id (4 bytes)
+    /// and the proto (2 bytes).
+    pub fn from_slice(slice: &[u8]) -> Result<Self> {
+        if message_type(slice)? != MessageType::Error {
+            return Err(Error::ApiMessageType);
+        }
+        crate::check_length(slice, ERROR_NOTIFICATION_LENGTH)?;
+
+        let slice = &slice[API_PREFIX_LENGTH..];
+        Ok(ErrorNotification {
+            code: slice[0].try_into()?,
+            bift_id: u32::from_be_bytes([slice[1], slice[2], slice[3], slice[4]]),
+            proto: u16::from_be_bytes([slice[5], slice[6]]),
+        })
+    }
+
+    pub fn to_slice(&self, slice: &mut [u8]) -> Result<usize> {
+        crate::check_length(slice, ERROR_NOTIFICATION_LENGTH)?;
+
+        write_prefix(slice, MessageType::Error);
+        slice[API_PREFIX_LENGTH] = self.code as u8;
+        slice[API_PREFIX_LENGTH + 1..API_PREFIX_LENGTH + 5]
+            .copy_from_slice(&self.bift_id.to_be_bytes());
+        slice[API_PREFIX_LENGTH + 5..ERROR_NOTIFICATION_LENGTH]
+            .copy_from_slice(&self.proto.to_be_bytes());
+
+        Ok(ERROR_NOTIFICATION_LENGTH)
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_recv_info_from_slice() {
         let buffer = [
+            0xb1, 0xe5, 1, 0, // Magic + Version + Reserved
             0, 0, 0, 1, // BIFT-ID
             0, 36, // Proto
             0, 8, // Bitstring length
@@ -70,6 +882,7 @@ mod tests {
         let recv_info = recv_info.unwrap();
         assert_eq!(recv_info.bift_id, 1);
         assert_eq!(recv_info.proto, 36);
+        assert_eq!(recv_info.bier_proto(), Err(Error::Proto));
         assert_eq!(recv_info.bitstring.len(), 8);
         assert_eq!(recv_info.bitstring, &[0, 0, 0, 0, 0, 0, 0, 0xff]);
         assert_eq!(recv_info.payload.len(), 5);
@@ -83,6 +896,7 @@ mod tests {
             proto: 0x37,
             bitstring: &[0xff, 0xee, 0xdd, 0xcc, 0xbb, 0xaa, 0x43, 0x78],
             payload: &[0x11, 0x44, 0xdf, 0x21, 0x44, 0x33, 0x3, 0x21],
+            metadata: None,
         };
 
         let mut buffer = [0u8; 1000];
@@ -90,11 +904,493 @@ mod tests {
         let res = send_info.to_slice(&mut buffer[..]);
         assert!(res.is_ok());
         let res = res.unwrap();
-        assert_eq!(res, 4 + 2 + 2 + send_info.bitstring.len() + send_info.payload.len());
-        assert_eq!(&buffer[..4], &[0xff, 0xdd, 0xee, 0x11]);
-        assert_eq!(&buffer[4..6], &[0x00, 0x37]);
-        assert_eq!(&buffer[6..8], &[0, 8]);
-        assert_eq!(&buffer[8..16], &[0xff, 0xee, 0xdd, 0xcc, 0xbb, 0xaa, 0x43, 0x78]);
-        assert_eq!(&buffer[16..res], &[0x11, 0x44, 0xdf, 0x21, 0x44, 0x33, 0x3, 0x21]);
+        assert_eq!(res, 4 + 4 + 2 + 2 + send_info.bitstring.len() + send_info.payload.len());
+        // No TLV: version 1.
+        assert_eq!(&buffer[..4], &[0xb1, 0xe5, 1, 0]);
+        assert_eq!(&buffer[4..8], &[0xff, 0xdd, 0xee, 0x11]);
+        assert_eq!(&buffer[8..10], &[0x00, 0x37]);
+        assert_eq!(&buffer[10..12], &[0, 8]);
+        assert_eq!(&buffer[12..20], &[0xff, 0xee, 0xdd, 0xcc, 0xbb, 0xaa, 0x43, 0x78]);
+        assert_eq!(&buffer[20..res], &[0x11, 0x44, 0xdf, 0x21, 0x44, 0x33, 0x3, 0x21]);
+    }
+
+    #[test]
+    /// Tests that messages without the magic number or with another version are rejected.
+    fn test_recv_info_from_slice_wrong_prefix() {
+        let mut buffer = [
+            0xb1, 0xe5, 1, 0, // Magic + Version + Reserved
+            0, 0, 0, 1, // BIFT-ID
+            0, 36, // Proto
+            0, 8, // Bitstring length
+            0, 0, 0, 0, 0, 0, 0, 0xff, // Bitstring
+        ];
+        assert!(RecvInfo::from_slice(&buffer).is_ok());
+
+        // Too short to even contain the magic number.
+        assert_eq!(RecvInfo::from_slice(&buffer[..1]).unwrap_err(), Error::ApiMagic);
+
+        buffer[2] = API_VERSION + 1;
+        assert_eq!(
+            RecvInfo::from_slice(&buffer).unwrap_err(),
+            Error::ApiVersion(API_VERSION + 1)
+        );
+        buffer[2] = 0;
+        assert_eq!(RecvInfo::from_slice(&buffer).unwrap_err(), Error::ApiVersion(0));
+
+        buffer[0] = 0;
+        assert_eq!(RecvInfo::from_slice(&buffer).unwrap_err(), Error::ApiMagic);
+    }
+
+    #[test]
+    /// Tests that truncated messages and invalid bitstring lengths are rejected.
+    fn test_recv_info_from_slice_wrong_length() {
+        let mut buffer = [
+            0xb1, 0xe5, 1, 0, // Magic + Version + Reserved
+            0, 0, 0, 1, // BIFT-ID
+            0, 36, // Proto
+            0, 8, // Bitstring length
+            0, 0, 0, 0, 0, 0, 0, 0xff, // Bitstring
+        ];
+        for len in 4..20 {
+            // The fixed fields, then the bitstring.
+            let expected = if len < 12 { 12 } else { 20 };
+            assert_eq!(
+                RecvInfo::from_slice(&buffer[..len]).unwrap_err(),
+                Error::SliceWrongLength {
+                    expected,
+                    actual: len
+                }
+            );
+        }
+
+        // Bitstring of 3 bytes.
+        buffer[11] = 3;
+        assert_eq!(RecvInfo::from_slice(&buffer).unwrap_err(), Error::BitstringLength);
+        buffer[11] = 0;
+        assert_eq!(RecvInfo::from_slice(&buffer).unwrap_err(), Error::BitstringLength);
+    }
+
+    #[test]
+    /// Tests that a data message is not parsed as a query, and conversely.
+    fn test_message_type() {
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 1],
+            payload: &[1, 2, 3],
+            metadata: None,
+        };
+        let mut buffer = [0u8; 100];
+        let len = send_info.to_slice(&mut buffer).unwrap();
+        assert_eq!(message_type(&buffer[..len]).unwrap(), MessageType::Data);
+        assert_eq!(Query::from_slice(&buffer[..len]).unwrap_err(), Error::ApiMessageType);
+
+        let len = Query::Bifts.to_slice(&mut buffer).unwrap();
+        assert_eq!(len, 5);
+        assert_eq!(message_type(&buffer[..len]).unwrap(), MessageType::Query);
+        assert_eq!(RecvInfo::from_slice(&buffer[..len]).unwrap_err(), Error::ApiMessageType);
+        assert_eq!(Query::from_slice(&buffer[..len]).unwrap(), Query::Bifts);
+
+        buffer[3] = 42;
+        assert_eq!(message_type(&buffer[..len]).unwrap_err(), Error::ApiMessageType);
+    }
+
+    #[test]
+    /// Tests the answer to the queries and the encoding of the responses.
+    fn test_query_response() {
+        let bier_state: BierState = serde_json::from_str(
+            r#"{"loopback": "fc00::a", "bifts": [
+                {"bift_id": 1, "bift_type": 1, "sub_domain": 3, "bfr_id": 2, "entries": [
+                    {"bit": 1, "paths": [{"bitstring": "1", "next_hop": "fc00:a::1"}]}
+                ]},
+                {"bift_id": 2, "bift_type": 1, "bfr_id": 1, "entries": []}
+            ], "bfr_names": {"a": 2, "bb": 1}}"#,
+        )
+        .unwrap();
+        let mut buffer = [0u8; 100];
+
+        let response = Query::Bifts.answer(&bier_state);
+        assert_eq!(
+            response,
+            Response::Bifts(vec![
+                BiftInfo { bift_id: 1, sub_domain: 3, bfr_id: 2, bsl: 64 },
+                BiftInfo { bift_id: 2, sub_domain: 0, bfr_id: 1, bsl: 0 },
+            ])
+        );
+        let len = response.to_slice(&mut buffer).unwrap();
+        assert_eq!(len, 4 + 3 + 2 * 9);
+        assert_eq!(&buffer[..7], &[0xb1, 0xe5, 2, 2, 1, 0, 2]);
+        assert_eq!(&buffer[7..16], &[0, 0, 0, 1, 3, 0, 2, 0, 64]);
+        assert_eq!(Response::from_slice(&buffer[..len]).unwrap(), response);
+        assert!(Response::from_slice(&buffer[..len - 1]).is_err());
+
+        let response = Query::BfrNames.answer(&bier_state);
+        assert_eq!(
+            response,
+            Response::BfrNames(vec![("a".to_string(), 2), ("bb".to_string(), 1)])
+        );
+        let len = response.to_slice(&mut buffer).unwrap();
+        assert_eq!(len, 4 + 3 + 4 + 5);
+        assert_eq!(&buffer[7..16], &[1, b'a', 0, 2, 2, b'b', b'b', 0, 1]);
+        assert_eq!(Response::from_slice(&buffer[..len]).unwrap(), response);
+        assert!(Response::from_slice(&buffer[..len - 1]).is_err());
+
+        // Too small buffer.
+        assert!(response.to_slice(&mut buffer[..len - 1]).is_err());
+    }
+
+    #[test]
+    /// Tests the encoding of a batch and its parsing back.
+    fn test_batch_info() {
+        let batch_info = BatchInfo {
+            bift_id: 0x11223344,
+            proto: 6,
+            records: vec![
+                (&[0, 0, 0, 0, 0, 0, 0, 0b11], &[1, 2, 3]),
+                (&[0, 0, 0, 0, 0, 0, 0, 0b100], &[]),
+                (&[0xff; 16], &[4, 5]),
+            ],
+        };
+
+        let mut buffer = [0u8; 1000];
+        let len = batch_info.to_slice(&mut buffer).unwrap();
+        assert_eq!(len, 4 + 8 + (4 + 8 + 3) + (4 + 8) + (4 + 16 + 2));
+        assert_eq!(&buffer[..4], &[0xb1, 0xe5, 2, 3]);
+        assert_eq!(&buffer[4..12], &[0x11, 0x22, 0x33, 0x44, 0, 6, 0, 3]);
+        assert_eq!(&buffer[12..16], &[0, 8, 0, 3]);
+        assert_eq!(message_type(&buffer[..len]).unwrap(), MessageType::Batch);
+
+        let res = BatchInfo::from_slice(&buffer[..len]).unwrap();
+        assert_eq!(res.bift_id, 0x11223344);
+        assert_eq!(res.proto, 6);
+        assert_eq!(res.records, batch_info.records);
+
+        let send_infos: Vec<_> = res.iter().collect();
+        assert_eq!(send_infos.len(), 3);
+        assert_eq!(send_infos[2].bift_id, 0x11223344);
+        assert_eq!(send_infos[2].bitstring, &[0xff; 16]);
+        assert_eq!(send_infos[2].payload, &[4, 5]);
+
+        // Truncated message.
+        assert!(BatchInfo::from_slice(&buffer[..len - 1]).is_err());
+        assert!(batch_info.to_slice(&mut buffer[..len - 1]).is_err());
+
+        // A batch is not a single data message.
+        assert_eq!(RecvInfo::from_slice(&buffer[..len]).unwrap_err(), Error::ApiMessageType);
+    }
+
+    #[test]
+    /// Tests the conversions between the borrowed and owned messages.
+    fn test_owned_info() {
+        let bitstring = [0, 0, 0, 0, 0, 0, 0, 0b101];
+        let payload = [1, 2, 3];
+        let send_info = SendInfo {
+            bift_id: 2,
+            proto: 17,
+            bitstring: &bitstring,
+            payload: &payload,
+            metadata: None,
+        };
+        let owned = send_info.to_owned_info();
+        assert_eq!(owned.bitstring, bitstring.to_vec());
+        assert_eq!(owned.message_length(), send_info.message_length());
+
+        // The owned message can be moved to another thread.
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || tx.send(owned).unwrap())
+            .join()
+            .unwrap();
+        let owned: OwnedSendInfo = rx.recv().unwrap();
+
+        let mut buffer = [0u8; 100];
+        let mut expected = [0u8; 100];
+        let len = owned.to_slice(&mut buffer).unwrap();
+        assert_eq!(send_info.to_slice(&mut expected).unwrap(), len);
+        assert_eq!(&buffer[..len], &expected[..len]);
+
+        let recv_info = OwnedRecvInfo::from_slice(&buffer[..len]).unwrap();
+        assert_eq!(recv_info, owned);
+        let borrowed = CommunicationInfo::from(&recv_info);
+        assert_eq!(borrowed.bift_id, 2);
+        assert_eq!(borrowed.proto, 17);
+        assert_eq!(borrowed.payload, &payload);
+        assert_eq!(OwnedRecvInfo::from(borrowed), owned);
+
+        assert!(OwnedRecvInfo::from_slice(&buffer[1..len]).is_err());
+        assert!(owned.to_slice(&mut buffer[..len - 1]).is_err());
+    }
+
+    #[test]
+    /// Tests the TLVs of the optional fields of the data messages.
+    fn test_metadata_tlvs() {
+        let metadata = Metadata {
+            bfir_id: Some(0x102),
+            ttl: None,
+            dscp: Some(46),
+            entropy: Some(0xabcde),
+            s: None,
+        };
+        let recv_info = RecvInfo {
+            bift_id: 1,
+            proto: 17,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 1],
+            payload: &[1, 2, 3],
+            metadata: Some(metadata),
+        };
+        let mut buffer = [0u8; 100];
+        let len = recv_info.to_slice(&mut buffer).unwrap();
+        assert_eq!(len, 4 + 10 + 13 + 8 + 3);
+        assert_eq!(&buffer[12..14], &[0, 13]);
+        assert_eq!(
+            &buffer[14..27],
+            &[1, 2, 1, 2, 3, 1, 46, 4, 4, 0, 0x0a, 0xbc, 0xde]
+        );
+
+        let parsed = RecvInfo::from_slice(&buffer[..len]).unwrap();
+        assert_eq!(parsed.metadata, Some(metadata));
+        assert_eq!((parsed.bitstring, parsed.payload), (recv_info.bitstring, recv_info.payload));
+        assert_eq!(
+            RecvInfo::from_slice(&buffer[..4 + 10 + 12]).unwrap_err(),
+            Error::SliceWrongLength {
+                expected: 4 + 10 + 13 + 8,
+                actual: 4 + 10 + 12
+            }
+        );
+
+        // The TLVs of an unknown type are skipped, the known ones must have their length.
+        let mut message = buffer[..len].to_vec();
+        message[13] += 3;
+        message.splice(14..14, [42, 1, 0]);
+        assert_eq!(RecvInfo::from_slice(&message).unwrap().metadata, Some(metadata));
+        message[15] = 2;
+        message[18] = 1;
+        assert_eq!(RecvInfo::from_slice(&message).unwrap_err(), Error::ApiTlv);
+
+        // Only unknown TLVs: no metadata.
+        let mut message = buffer[..len].to_vec();
+        message[12..14].copy_from_slice(&[0, 3]);
+        message.splice(14..27, [42, 1, 0]);
+        assert_eq!(RecvInfo::from_slice(&message).unwrap().metadata, None);
+
+        // The DSCP and entropy too large for the BIER header are refused.
+        for (index, value) in [(20, 0x40), (23, 0x10)] {
+            let mut message = buffer[..len].to_vec();
+            message[index] = value;
+            assert_eq!(RecvInfo::from_slice(&message).unwrap_err(), Error::ApiTlv);
+        }
+        let info = RecvInfo {
+            metadata: Some(Metadata {
+                entropy: Some(0x100000),
+                ..metadata
+            }),
+            ..recv_info
+        };
+        assert_eq!(info.to_slice(&mut [0u8; 100]).unwrap_err(), Error::ApiTlv);
+
+        // The S bit is a single byte, 0 or 1.
+        let info = RecvInfo {
+            metadata: Some(Metadata {
+                s: Some(true),
+                ..metadata
+            }),
+            ..recv_info
+        };
+        let mut message = [0u8; 100];
+        let len = info.to_slice(&mut message).unwrap();
+        assert_eq!(&message[27..30], &[5, 1, 1]);
+        let with_s = RecvInfo::from_slice(&message[..len]).unwrap();
+        assert_eq!(with_s.metadata, info.metadata);
+        message[29] = 2;
+        let invalid_s = RecvInfo::from_slice(&message[..len]);
+        assert_eq!(invalid_s.unwrap_err(), Error::ApiTlv);
+
+        // Without TLV, the messages are written in version 1.
+        let owned = OwnedRecvInfo {
+            metadata: None,
+            ..parsed.to_owned_info()
+        };
+        let len = owned.to_slice(&mut buffer).unwrap();
+        assert_eq!(len, 4 + 8 + 8 + 3);
+        assert_eq!(&buffer[2..4], &[API_MIN_VERSION, MessageType::Data as u8]);
+        assert_eq!(OwnedRecvInfo::from_slice(&buffer[..len]), Ok(owned.clone()));
+        let empty = OwnedRecvInfo {
+            metadata: Some(Metadata::default()),
+            ..owned.clone()
+        };
+        assert_eq!(empty.to_slice(&mut buffer), Ok(len));
+        assert_eq!(OwnedRecvInfo::from_slice(&buffer[..len]), Ok(owned));
+    }
+
+    #[test]
+    /// Tests the notifications of the messages dropped by the daemon.
+    fn test_error_notification() {
+        let send_info = SendInfo {
+            bift_id: 42,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 1],
+            payload: &[1, 2, 3],
+            metadata: None,
+        };
+        let mut message = [0u8; 100];
+        let len = send_info.to_slice(&mut message).unwrap();
+        let notification = ErrorNotification::new(&message[..len], Error::BiftId(42));
+        assert_eq!(
+            notification,
+            ErrorNotification {
+                code: ErrorCode::UnknownBift,
+                bift_id: 42,
+                proto: 6,
+            }
+        );
+
+        let mut buffer = [0u8; 16];
+        let len = notification.to_slice(&mut buffer).unwrap();
+        assert_eq!(message_type(&buffer[..len]), Ok(MessageType::Error));
+        assert_eq!(
+            ErrorNotification::from_slice(&buffer[..len]),
+            Ok(notification)
+        );
+        assert!(ErrorNotification::from_slice(&buffer[..len - 1]).is_err());
+        assert!(notification.to_slice(&mut buffer[..len - 1]).is_err());
+
+        // Too short to read the BIFT-id and the proto.
+        let error = Error::SliceWrongLength {
+            expected: 10,
+            actual: 2,
+        };
+        let notification = ErrorNotification::new(&message[..6], error);
+        assert_eq!(notification.code, ErrorCode::MalformedMessage);
+        assert_eq!((notification.bift_id, notification.proto), (0, 0));
+
+        // The application gets back the error of the daemon.
+        for code in 1..=10 {
+            let code = ErrorCode::try_from(code).unwrap();
+            let notification = ErrorNotification {
+                code,
+                bift_id: 42,
+                proto: 6,
+            };
+            assert_eq!(ErrorCode::from(notification.error()), code);
+        }
+        assert!(ErrorCode::try_from(11).is_err());
+        let notification = ErrorNotification::new(&message[..len], Error::BiftId(42));
+        assert_eq!(notification.error(), Error::BiftId(42));
+        let notification = ErrorNotification::new(&message[..len], Error::ApiTlv);
+        assert_eq!(
+            notification.error(),
+            Error::Remote(ErrorCode::MalformedMessage)
+        );
+        assert_eq!(
+            Error::from(ErrorCode::UnsupportedVersion),
+            Error::Remote(ErrorCode::UnsupportedVersion)
+        );
+        assert_eq!(Error::from(ErrorCode::NoEntry), Error::NoEntry);
+    }
+
+    #[test]
+    /// Tests the registrations of the applications and their notifications.
+    fn test_registration() {
+        let registration = Registration {
+            proto: 0x37,
+            path: "/tmp/app.sock".to_string(),
+            version: API_VERSION,
+        };
+        let mut buffer = [0u8; 64];
+        let len = registration.to_slice(&mut buffer).unwrap();
+        assert_eq!(&buffer[..6], &[0xb1, 0xe5, 2, 5, 0, 0x37]);
+        assert_eq!(message_type(&buffer[..len]), Ok(MessageType::Register));
+        assert_eq!(Registration::from_slice(&buffer[..len]), Ok(registration.clone()));
+        assert_eq!(
+            registration.to_string(),
+            "REGISTER proto=55 path=/tmp/app.sock"
+        );
+        assert!(registration.to_slice(&mut buffer[..len - 1]).is_err());
+        assert_eq!(
+            Registration::from_slice(&buffer[..5]),
+            Err(Error::SliceWrongLength {
+                expected: 6,
+                actual: 5
+            })
+        );
+
+        let notification = ErrorNotification::new(&buffer[..len], Error::Registration);
+        assert_eq!(notification.code, ErrorCode::Registration);
+        assert_eq!((notification.bift_id, notification.proto), (0, 0x37));
+
+        // The path is not valid UTF-8.
+        buffer[len - 1] = 0xff;
+        assert_eq!(
+            Registration::from_slice(&buffer[..len]),
+            Err(Error::Registration)
+        );
+    }
+
+    /// Strategy of the optional fields of the data messages, in the ranges of the BIER header.
+    fn arbitrary_metadata() -> impl Strategy<Value = Metadata> {
+        (
+            prop::option::of(any::<u16>()),
+            prop::option::of(any::<u8>()),
+            prop::option::of(0..=MAX_DSCP),
+            prop::option::of(0..=MAX_ENTROPY),
+            prop::option::of(any::<bool>()),
+        )
+            .prop_map(|(bfir_id, ttl, dscp, entropy, s)| Metadata {
+                bfir_id,
+                ttl,
+                dscp,
+                entropy,
+                s,
+            })
+    }
+
+    /// Strategy of the encoded TLVs of unknown types, with any value.
+    fn arbitrary_unknown_tlvs() -> impl Strategy<Value = Vec<u8>> {
+        let tlv = (TLV_S + 1.., prop::collection::vec(any::<u8>(), 0..8));
+        prop::collection::vec(tlv, 0..4).prop_map(|tlvs| {
+            tlvs.into_iter()
+                .flat_map(|(tlv_type, value)| [vec![tlv_type, value.len() as u8], value].concat())
+                .collect()
+        })
+    }
+
+    proptest! {
+        #[test]
+        /// Tests that random data messages are parsed back from their serialization, also with
+        /// TLVs of unknown types, which are skipped. The metadata without any field set is not
+        /// serialized, and is parsed back as `None`: it is not generated.
+        fn test_communication_info_round_trip(
+            (bift_id, proto) in (any::<u32>(), any::<u16>()),
+            bitstring in crate::bitstring::arbitrary_bitstring(),
+            payload in prop::collection::vec(any::<u8>(), 0..1024),
+            metadata in prop::option::of(
+                arbitrary_metadata().prop_filter("no field set", |m| *m != Metadata::default())
+            ),
+            unknown_tlvs in arbitrary_unknown_tlvs(),
+        ) {
+            let send_info = OwnedSendInfo {
+                bift_id,
+                proto,
+                bitstring: (&bitstring).into(),
+                payload,
+                metadata,
+            };
+            let length = send_info.message_length();
+            let mut buffer = vec![0u8; length];
+            prop_assert_eq!(send_info.to_slice(&mut buffer).unwrap(), length);
+            prop_assert!(send_info.to_slice(&mut buffer[..length - 1]).is_err());
+            prop_assert_eq!(&OwnedRecvInfo::from_slice(&buffer).unwrap(), &send_info);
+
+            // The unknown TLVs are added before the known ones, in a message of version 2.
+            if buffer[2] == API_MIN_VERSION {
+                buffer[2] = API_VERSION;
+                buffer.splice(API_PREFIX_LENGTH + 8..API_PREFIX_LENGTH + 8, [0, 0]);
+            }
+            let tlvs_length = crate::be_u16(&buffer, API_PREFIX_LENGTH + 8) as usize;
+            let tlvs_length = (tlvs_length + unknown_tlvs.len()) as u16;
+            buffer[API_PREFIX_LENGTH + 8..API_PREFIX_LENGTH + 10]
+                .copy_from_slice(&tlvs_length.to_be_bytes());
+            buffer.splice(API_PREFIX_LENGTH + 10..API_PREFIX_LENGTH + 10, unknown_tlvs);
+            prop_assert_eq!(&OwnedRecvInfo::from_slice(&buffer).unwrap(), &send_info);
+        }
     }
 }
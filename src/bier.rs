@@ -1,273 +1,1727 @@
+pub use crate::bitstring::{Bitstring, BitstringFormat, BitstringOp, MAX_BITSTRING_WORDS};
+use crate::ethernet::MacAddr;
+use crate::overlay::{Overlay, OverlayEntry};
 use crate::{Error, Result};
-use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
-use std::{net::IpAddr, str::FromStr};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::time::{Duration, Instant};
+use std::net::IpAddr;
 
 pub type BierSendInfo = (Bitstring, Option<IpAddr>);
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct BierState {
     pub loopback: IpAddr,
     pub bifts: Vec<Bift>,
+    /// Human-readable names of the BFRs, mapped to their BFR-id.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub bfr_names: BTreeMap<String, u64>,
+    /// Applications allowed to send packets through the daemon. Everyone if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub api_acl: Vec<crate::acl::AclRule>,
+    /// Drops the received packets whose nibble or version do not follow RFC 8296,
+    /// and sets the nibble of the packets of the applications. Permissive by default.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub strict_rfc8296: bool,
+    /// Processing of the packets with bits above the highest BFR-id of their BIFT.
+    #[serde(default, skip_serializing_if = "UnknownBitsPolicy::is_default")]
+    pub unknown_bits: UnknownBitsPolicy,
+    /// Delivery policy of the packets of each proto. Delivered and forwarded if absent.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub delivery: BTreeMap<u8, DeliveryPolicy>,
+    /// Capabilities of the neighbors, if they differ from the BIFTs of this BFR.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub neighbors: BTreeMap<IpAddr, NeighborConfig>,
+    /// UDP port on which the daemon receives the BIER packets carried in UDP, and to which
+    /// it sends the copies toward the neighbors with the UDP encapsulation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub udp_port: Option<u16>,
+    /// MPLS label of each BIFT-id of this BFR, for the packets received in MPLS networks.
+    /// The label of a BIFT-id without entry is the BIFT-id itself.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub mpls_labels: BTreeMap<u32, u32>,
+    /// Type of the IPv6 option carrying the BIER header of the BIERin6 packets. The
+    /// experimental type `bierin6::DEFAULT_OPTION_TYPE` if absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bierin6_option: Option<u8>,
+    /// Options of the raw IP socket of the daemon, read at startup.
+    #[serde(default, skip_serializing_if = "SocketConfig::is_default")]
+    pub socket: SocketConfig,
+    /// Choice of the path of the entries with several paths administratively up.
+    #[serde(default, skip_serializing_if = "PathSelection::is_default")]
+    pub path_selection: PathSelection,
+    /// BFERs of the multicast groups encapsulated by this BFIR, e.g., from a TUN device.
+    #[serde(default, skip_serializing_if = "Overlay::is_empty")]
+    pub overlay: Overlay,
+    /// Structures derived from the BIFTs by `compile`, required by `process_bier`.
+    #[serde(skip)]
+    pub compiled: Option<CompiledState>,
+    /// Next hops declared dead, e.g., by the liveness prober, whose paths are skipped by
+    /// `process_bier`. Not part of the configuration.
+    #[serde(skip)]
+    pub dead_next_hops: BTreeSet<IpAddr>,
+}
+
+/// Structures derived from the BIFTs by `BierState::compile`. They must be rebuilt
+/// when the BIFTs change.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CompiledState {
+    /// Index in `bifts` of each BIFT-id.
+    bifts: HashMap<u32, usize>,
+    /// Index in the entries of each BIFT of each bit of its bitstring, in the order of `bifts`.
+    /// The bits are the BFR-ids minus the offset of the SI of the BIFT.
+    entries: Vec<HashMap<u64, usize>>,
+    /// BSL of each BIFT, in the order of `bifts`.
+    bsls: Vec<usize>,
+    /// Entries and F-BMs of each BIFT packed for `process_bier`, in the order of `bifts`.
+    packed: Vec<PackedBift>,
+    /// BIFT-id of each (sub-domain, BSL, SI). The first BIFT wins if several share them.
+    sets: HashMap<(u8, usize, u8), u32>,
+    /// Next hops of the paths of all the BIFTs, except the local entries, with the number
+    /// of entries whose preferred path is toward them.
+    next_hops: BTreeMap<IpAddr, usize>,
+    /// Live paths of the entries of each BIFT, in the order of `bifts`. Updated without
+    /// compiling again when the liveness of a next hop or the administrative state of a path
+    /// changes.
+    reroutes: Vec<Reroute>,
+}
+
+/// BIFT packed by `BierState::compile` for the BIER processing: the entry of each bit in a
+/// dense table, and the F-BMs of all the paths in a single array of words, so that the
+/// bitstring of a packet is masked without following the paths of the entries.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct PackedBift {
+    /// Words of each F-BM, `bsl / 64` per path.
+    nb_words: usize,
+    /// Index of the entry of each bit of the bitstring, the bit 1 first.
+    slots: Vec<Option<u32>>,
+    /// F-BMs of the paths of the entries, in their order, the first word of each F-BM
+    /// holding the most significant bits.
+    fbms: Vec<u64>,
+    /// Index of the F-BM of the first path of each entry, in the order of the entries.
+    first_path: Vec<usize>,
+}
+
+impl PackedBift {
+    /// Packs the `bift` of `bsl` bits. Its entries must be in its SI and have F-BMs of `bsl`
+    /// bits, as checked by `compile`.
+    fn new(bift: &Bift, bsl: usize) -> Self {
+        let offset = bift.bfr_id_offset();
+        let mut packed = PackedBift {
+            nb_words: bsl / 64,
+            slots: vec![None; bsl],
+            fbms: Vec::with_capacity(bift.entries.len() * bsl / 64),
+            first_path: Vec::with_capacity(bift.entries.len()),
+        };
+        for (entry_idx, entry) in bift.entries.iter().enumerate() {
+            if let Some(slot) = packed.slots.get_mut((entry.bit - offset - 1) as usize) {
+                *slot = Some(entry_idx as u32);
+            }
+            packed.first_path.push(packed.fbms.len() / packed.nb_words.max(1));
+            for path in entry.paths.iter() {
+                packed.fbms.extend_from_slice(&path.bitstring.bitstring);
+            }
+        }
+        packed
+    }
+
+    /// Index of the entry of `bit`, from 1 to the BSL.
+    fn entry(&self, bit: u64) -> Option<usize> {
+        let slot = self.slots.get(usize::try_from(bit.checked_sub(1)?).ok()?)?;
+        slot.map(|entry_idx| entry_idx as usize)
+    }
+
+    /// F-BM of the path `path_idx` of the entry `entry_idx`.
+    fn fbm(&self, entry_idx: usize, path_idx: usize) -> &[u64] {
+        let start = (self.first_path[entry_idx] + path_idx) * self.nb_words;
+        &self.fbms[start..start + self.nb_words]
+    }
+
+    /// Memory used by the packed BIFT, in bytes.
+    fn memory_footprint(&self) -> usize {
+        self.slots.len() * std::mem::size_of::<Option<u32>>()
+            + self.fbms.len() * 8
+            + self.first_path.len() * std::mem::size_of::<usize>()
+    }
+}
+
+/// Bits of a BIFT grouped by the next hops of their live paths, i.e., administratively up
+/// and toward a live next hop, so that `merge_backup_fbm` masks the F-BMs word by word. The
+/// words are in the order of the bitstrings.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Reroute {
+    /// Bits whose entry has a live path.
+    live: Vec<u64>,
+    /// Bits whose entry has several live paths: with `PathSelection::Ecmp`, the selected one
+    /// depends on the entropy of the packet.
+    balanced: Vec<u64>,
+    /// Bits whose first live path is toward each next hop.
+    first: HashMap<IpAddr, Vec<u64>>,
+}
+
+impl Reroute {
+    /// Live paths of the entries of the `bift` of `bsl` bits, without the `dead` next hops.
+    fn new(bift: &Bift, bsl: usize, dead: &BTreeSet<IpAddr>) -> Self {
+        let nb_words = bsl / 64;
+        let offset = bift.bfr_id_offset();
+        let mut reroute = Reroute {
+            live: vec![0; nb_words],
+            balanced: vec![0; nb_words],
+            first: HashMap::new(),
+        };
+        for entry in bift.entries.iter() {
+            let mut live_paths = entry
+                .up_paths()
+                .filter(|(_, path)| !dead.contains(&path.next_hop));
+            let Some((_, first)) = live_paths.next() else {
+                continue;
+            };
+            // The last word holds the bits 1 to 64, as checked by `compile`.
+            let bit = entry.bit - offset - 1;
+            let idx_word = nb_words - 1 - (bit / 64) as usize;
+            let mask = 1 << (bit % 64);
+            reroute.live[idx_word] |= mask;
+            if live_paths.next().is_some() {
+                reroute.balanced[idx_word] |= mask;
+            }
+            reroute
+                .first
+                .entry(first.next_hop)
+                .or_insert_with(|| vec![0; nb_words])[idx_word] |= mask;
+        }
+        reroute
+    }
+
+    /// Memory used by the groups of bits, in bytes.
+    fn memory_footprint(&self) -> usize {
+        (self.live.len() + self.balanced.len()) * 8
+            + self
+                .first
+                .values()
+                .map(|bits| std::mem::size_of::<IpAddr>() + bits.len() * 8)
+                .sum::<usize>()
+    }
+}
+
+impl CompiledState {
+    /// Next hops of the paths of all the BIFTs, i.e., the neighbors of this BFR.
+    /// The paths of the entries of this BFR are not toward a neighbor.
+    pub fn next_hops(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        self.next_hops.keys().copied()
+    }
+
+    /// Next hops by decreasing number of entries whose preferred path is toward them.
+    /// The next hops of the backup paths only are last.
+    pub fn busiest_next_hops(&self) -> Vec<IpAddr> {
+        let mut next_hops: Vec<_> = self.next_hops.iter().collect();
+        next_hops.sort_by(|a, b| b.1.cmp(a.1));
+        next_hops.into_iter().map(|(next_hop, _)| *next_hop).collect()
+    }
+}
+
+/// Summary of `BierState::compile`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompileReport {
+    pub duration: Duration,
+    /// Approximate memory used by the BIFTs and the compiled structures, in bytes.
+    pub memory: usize,
+    pub nb_bifts: usize,
+    pub nb_entries: usize,
+    pub nb_next_hops: usize,
+}
+
+impl fmt::Display for CompileReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} BIFTs, {} entries and {} next hops compiled in {:?}, {} KiB",
+            self.nb_bifts,
+            self.nb_entries,
+            self.nb_next_hops,
+            self.duration,
+            self.memory.div_ceil(1024)
+        )
+    }
+}
+
+/// Problem of a configuration found by `BierState::validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    /// Path of the faulty field in the JSON configuration, e.g.,
+    /// `bifts[0].entries[2].paths[0].next_hop`.
+    pub field: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Capabilities of a neighbor. The copies toward a neighbor supporting a BSL smaller than
+/// the BIFTs of this BFR are re-encapsulated with its BSL, one packet per set identifier (SI).
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct NeighborConfig {
+    /// Largest BSL supported by the neighbor, in bits: a power of two from 64 to 4096. Any
+    /// BSL if absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bsl: Option<usize>,
+    /// BIFT-id of the neighbor for each SI, i.e., for the bits `SI * bsl + 1` to `(SI + 1) * bsl`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bift_ids: Vec<u32>,
+    /// Encapsulation of the copies toward the neighbor.
+    #[serde(default, skip_serializing_if = "Encapsulation::is_default")]
+    pub encapsulation: Encapsulation,
+    /// MAC address of the neighbor with the Ethernet encapsulation. Resolved by ARP or NDP if
+    /// absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac: Option<MacAddr>,
+    /// Label of the neighbor for each BIFT-id of the copies toward it, with the MPLS
+    /// encapsulation. The label of a BIFT-id without entry is the BIFT-id itself.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub mpls_labels: BTreeMap<u32, u32>,
+    /// Labels pushed above the BIER label of the copies toward the neighbor with the MPLS
+    /// encapsulation, the outermost first, e.g., of an LSP toward a neighbor that is not
+    /// directly connected.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub label_stack: Vec<u32>,
+    /// Interface through which the copies toward the neighbor leave, instead of the one of the
+    /// route chosen by the kernel, e.g., in a multi-homed BFR. Linux only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    /// Source address of the copies toward the neighbor, instead of `SocketConfig::source`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<IpAddr>,
+}
+
+impl NeighborConfig {
+    /// Whether the copies toward the neighbor are sent with their own socket, bound to its
+    /// interface or source address.
+    pub fn is_bound(&self) -> bool {
+        self.interface.is_some() || self.source.is_some()
+    }
+
+    /// Options of the socket toward the neighbor: `socket` with the interface and the source
+    /// address of the neighbor.
+    pub fn socket_config(&self, socket: &SocketConfig) -> SocketConfig {
+        SocketConfig {
+            interface: self.interface.clone().or_else(|| socket.interface.clone()),
+            source: self.source.or(socket.source),
+            ..socket.clone()
+        }
+    }
+}
+
+/// Encapsulation of the BIER packets sent to a neighbor.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Encapsulation {
+    /// The transport of the daemon, by default IP packets with proto 253.
+    #[default]
+    Ip,
+    /// UDP datagrams to the `udp_port` of the BIER state, e.g., for a neighbor in an
+    /// environment without raw sockets.
+    Udp,
+    /// Ethernet frames with the ethertype 0xAB37 on the L2 segment of the neighbor, see
+    /// `af_packet`.
+    Ethernet,
+    /// MPLS packets (RFC 8296) in Ethernet frames with the ethertype 0x8847, whose BIFT-id
+    /// is the label of the neighbor, see `NeighborConfig::mpls_labels`.
+    Mpls,
+    /// IPv6 packets with the BIER header in a Destination Options header, see `bierin6`.
+    Bierin6,
+}
+
+impl Encapsulation {
+    fn is_default(&self) -> bool {
+        *self == Encapsulation::default()
+    }
+}
+
+/// IP proto of the BIER packets without `SocketConfig::proto`, reserved for experimentation
+/// (RFC 3692).
+pub const DEFAULT_IP_PROTO: u8 = 253;
+
+/// IP protos which cannot carry the BIER packets: reserved, IPv6 extension headers, and
+/// protos handled by the kernel, e.g., the IP-in-IP and GRE tunnels, whose packets the raw
+/// socket would also receive.
+const RESERVED_IP_PROTOS: [u8; 20] = [
+    0, 1, 2, 4, 6, 17, 41, 43, 44, 47, 50, 51, 58, 59, 60, 132, 135, 139, 140, 255,
+];
+
+/// Smallest receive buffer of the raw IP socket.
+const MIN_RECV_BUFFER: usize = 1024;
+
+/// Longest interface name, without the NUL byte.
+const MAX_INTERFACE_NAME: usize = 15;
+
+/// Options of the raw IP socket carrying the BIER packets. The options without value keep
+/// the default of the kernel.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct SocketConfig {
+    /// IP proto of the BIER packets, `DEFAULT_IP_PROTO` if absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proto: Option<u8>,
+    /// Size of the receive buffer (`SO_RCVBUF`), in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recv_buffer: Option<usize>,
+    /// Hop limit of the IPv6 packets (`IPV6_UNICAST_HOPS`), or TTL of the IPv4 packets.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hop_limit: Option<u8>,
+    /// Interface to which the socket is bound (`SO_BINDTODEVICE`): the packets are only
+    /// received from and sent through this interface.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    /// Source address of the sent packets, instead of the one chosen by the kernel. The
+    /// copies are then sent with a socket of their own, since a bound raw socket only
+    /// receives the packets destined to its address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<IpAddr>,
+}
+
+impl SocketConfig {
+    fn is_default(&self) -> bool {
+        *self == SocketConfig::default()
+    }
+
+    pub fn proto(&self) -> u8 {
+        self.proto.unwrap_or(DEFAULT_IP_PROTO)
+    }
+
+    /// Checks that the options can be applied to the socket. The existence of the interface
+    /// is checked when binding the socket.
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if RESERVED_IP_PROTOS.contains(&self.proto()) {
+            return Err(format!("The IP proto {} cannot carry BIER packets", self.proto()));
+        }
+        if let Some(size) = self.recv_buffer {
+            if !(MIN_RECV_BUFFER..=i32::MAX as usize / 2).contains(&size) {
+                return Err(format!("Invalid receive buffer size: {} bytes", size));
+            }
+        }
+        if self.hop_limit == Some(0) {
+            return Err("The hop limit must be positive".into());
+        }
+        if let Some(interface) = &self.interface {
+            if interface.is_empty()
+                || interface.len() > MAX_INTERFACE_NAME
+                || interface.contains(['/', '\0'])
+            {
+                return Err(format!("Invalid interface name: {:?}", interface));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the options of a socket sending toward `address`: the source address must be
+    /// of the same family.
+    pub fn validate_for(&self, address: IpAddr) -> std::result::Result<(), String> {
+        self.validate()?;
+        match self.source {
+            Some(source) if source.is_ipv4() != address.is_ipv4() => Err(format!(
+                "The source address {} is not of the family of {}",
+                source, address
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Processing of the bits of a received bitstring without entry in the BIFT,
+/// i.e., above the highest provisioned BFR-id.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownBitsPolicy {
+    /// Drops the whole packet, including the copies toward valid destinations.
+    #[default]
+    Drop,
+    /// Clears the unknown bits and forwards the packet to the valid destinations.
+    Clear,
+}
+
+impl UnknownBitsPolicy {
+    fn is_default(&self) -> bool {
+        *self == UnknownBitsPolicy::default()
+    }
+}
+
+/// Choice of the path of an entry among its paths administratively up.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PathSelection {
+    /// The first path, in order of preference. The other paths are backups.
+    #[default]
+    First,
+    /// Equal-cost multi-path: the path is chosen by a hash of the entropy of the packet,
+    /// so that the packets of a flow follow the same path and the flows are spread.
+    Ecmp,
+}
+
+impl PathSelection {
+    fn is_default(&self) -> bool {
+        *self == PathSelection::default()
+    }
+}
+
+/// Bitstring of a copy given by `BierState::process_bier_with`: the bits of the packet not
+/// replicated yet, masked by the forwarding bitmask of the path. The words are computed
+/// when read, without allocation.
+#[derive(Debug, Clone, Copy)]
+pub struct CopyBitstring<'a> {
+    remaining: &'a [u64],
+    fbm: &'a [u64],
+}
+
+impl CopyBitstring<'_> {
+    /// Words of the bitstring, the first one holding the most significant bits.
+    pub fn words(&self) -> impl Iterator<Item = u64> + '_ {
+        self.remaining
+            .iter()
+            .zip(self.fbm.iter())
+            .map(|(word, mask)| word & mask)
+    }
+
+    /// Writes the bitstring in the BIER `header`, see `Bitstring::update_header_from_self`.
+    pub fn update_header(&self, header: &mut [u8]) -> Result<()> {
+        let len = self.remaining.len().min(self.fbm.len()) * 8;
+        let bitstring_hdr = header
+            .get_mut(
+                crate::header::BIER_HEADER_WITHOUT_BITSTRING_LENGTH
+                    ..crate::header::BIER_HEADER_WITHOUT_BITSTRING_LENGTH + len,
+            )
+            .ok_or(Error::BitstringLength)?;
+        for (hdr_word, word) in bitstring_hdr.chunks_exact_mut(8).zip(self.words()) {
+            hdr_word.copy_from_slice(&word.to_be_bytes());
+        }
+        Ok(())
+    }
+
+    pub fn to_bitstring(&self) -> Bitstring {
+        let mut bitstring = Bitstring::default();
+        self.write_to(&mut bitstring);
+        bitstring
+    }
+
+    /// Replaces the words of `bitstring` by the ones of the copy, reusing its allocation.
+    pub fn write_to(&self, bitstring: &mut Bitstring) {
+        bitstring.set_and(self.remaining, self.fbm);
+    }
+
+    /// Forwarding bitmask (F-BM) of the path applied to the bits of the packet.
+    pub fn fbm(&self) -> Bitstring {
+        Bitstring {
+            bitstring: self.fbm.iter().take(self.remaining.len()).copied().collect(),
+        }
+    }
+}
+
+/// Copy of a packet decided for an entry: the index of the path, the bitstring of
+/// the copy and its next hop, `None` for the local copy.
+type EntryCopy<'a> = (usize, CopyBitstring<'a>, Option<IpAddr>);
+
+/// Decision of the BIER processing for an entry matched by the bitstring.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "decision", rename_all = "snake_case")]
+pub enum Decision {
+    /// A copy with `bitstring` through the path `path` of the entry of the BFR-id `bit`,
+    /// i.e., the bits not replicated yet masked by the F-BM `fbm` of the path, merged with the
+    /// entries falling back to its next hop on fast reroute. No next hop for the local copy.
+    Copy {
+        bit: u64,
+        path: usize,
+        fbm: Bitstring,
+        bitstring: Bitstring,
+        next_hop: Option<IpAddr>,
+    },
+    /// All the paths of the entry are administratively down or toward dead next hops: its bit
+    /// is cleared without copy.
+    Drained { bit: u64 },
+}
+
+/// Copies of a packet allowed for its proto, beyond the bitstring.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryPolicy {
+    /// Delivered locally and forwarded, following the bitstring.
+    #[default]
+    Both,
+    /// Delivered locally if this BFER is a destination, but never forwarded,
+    /// e.g., management traffic.
+    LocalOnly,
+    /// Forwarded, but never delivered locally even if the bit of this BFER is set.
+    ForwardOnly,
+}
+
+impl DeliveryPolicy {
+    /// Whether the copy toward `next_hop` is allowed. `None` is the local copy.
+    pub fn allows(&self, next_hop: Option<IpAddr>) -> bool {
+        match self {
+            DeliveryPolicy::Both => true,
+            DeliveryPolicy::LocalOnly => next_hop.is_none(),
+            DeliveryPolicy::ForwardOnly => next_hop.is_some(),
+        }
+    }
 }
 
 impl BierState {
+    /// Builds the structures derived from the BIFTs, required by `process_bier`. Must be
+    /// called again after modifying the BIFTs. Fails if a BIFT-id or the BFR-id of an entry
+    /// of a BIFT is duplicated, if an entry is outside of the SI of its BIFT, or if the BSL
+    /// of a neighbor is invalid, leaving the state uncompiled.
+    pub fn compile(&mut self) -> Result<CompileReport> {
+        let start = Instant::now();
+        self.compiled = None;
+        let mut compiled = CompiledState::default();
+        for (idx, bift) in self.bifts.iter().enumerate() {
+            let bift_id = u32::try_from(bift.bift_id).map_err(|_| Error::BiftParsing)?;
+            if compiled.bifts.insert(bift_id, idx).is_some() {
+                return Err(Error::BiftParsing);
+            }
+            bift.check()?;
+            let bsl = bift.get_bsl();
+            let offset = bift.bfr_id_offset();
+            compiled
+                .sets
+                .entry((bift.sub_domain, bsl, bift.si))
+                .or_insert(bift_id);
+            let mut entries = HashMap::with_capacity(bift.entries.len());
+            for (entry_idx, entry) in bift.entries.iter().enumerate() {
+                entries.insert(entry.bit - offset, entry_idx);
+                // The path of the local entry is not toward a neighbor.
+                if entry.bit == bift.bfr_id {
+                    continue;
+                }
+                for (path_idx, path) in entry.paths.iter().enumerate() {
+                    let nb_entries = compiled.next_hops.entry(path.next_hop).or_default();
+                    if path_idx == 0 {
+                        *nb_entries += 1;
+                    }
+                }
+            }
+            compiled.entries.push(entries);
+            compiled.bsls.push(bsl);
+            compiled.packed.push(PackedBift::new(bift, bsl));
+            compiled
+                .reroutes
+                .push(Reroute::new(bift, bsl, &self.dead_next_hops));
+        }
+        if self
+            .neighbors
+            .values()
+            .filter_map(|neighbor| neighbor.bsl)
+            .any(|bsl| !Bitstring::is_valid_bsl(bsl))
+        {
+            return Err(Error::BitstringLength);
+        }
+
+        let report = CompileReport {
+            duration: start.elapsed(),
+            memory: self.memory_footprint(&compiled),
+            nb_bifts: self.bifts.len(),
+            nb_entries: compiled.entries.iter().map(HashMap::len).sum(),
+            nb_next_hops: compiled.next_hops.len(),
+        };
+        self.compiled = Some(compiled);
+        Ok(report)
+    }
+
+    /// Approximate memory used by the BIFTs and by `compiled`, in bytes.
+    fn memory_footprint(&self, compiled: &CompiledState) -> usize {
+        use std::mem::size_of;
+        let bifts: usize = self
+            .bifts
+            .iter()
+            .flat_map(|bift| bift.entries.iter())
+            .flat_map(|entry| entry.paths.iter())
+            .map(|path| size_of::<BierEntryPath>() + path.bitstring.bitstring.len() * 8)
+            .sum::<usize>()
+            + self
+                .bifts
+                .iter()
+                .map(|bift| size_of::<Bift>() + bift.entries.len() * size_of::<BiftEntry>())
+                .sum::<usize>();
+        let indexes = compiled.bifts.len() * size_of::<(u32, usize)>()
+            + compiled
+                .entries
+                .iter()
+                .map(|entries| entries.len() * size_of::<(u64, usize)>())
+                .sum::<usize>()
+            + compiled.next_hops.len() * size_of::<(IpAddr, usize)>()
+            + compiled
+                .packed
+                .iter()
+                .map(PackedBift::memory_footprint)
+                .sum::<usize>()
+            + compiled
+                .reroutes
+                .iter()
+                .map(Reroute::memory_footprint)
+                .sum::<usize>();
+        bifts + indexes
+    }
+
+    /// JSON configuration of the state, with the F-BMs of the paths and the bitstrings of the
+    /// overlay written in `format`, e.g., the compact hex form for large BSLs.
+    pub fn to_json(&self, format: BitstringFormat) -> serde_json::Result<serde_json::Value> {
+        let mut value = serde_json::to_value(self)?;
+        if format == BitstringFormat::Binary {
+            return Ok(value);
+        }
+        for (idx_bift, bift) in self.bifts.iter().enumerate() {
+            for (idx_entry, entry) in bift.entries.iter().enumerate() {
+                for (idx_path, path) in entry.paths.iter().enumerate() {
+                    value["bifts"][idx_bift]["entries"][idx_entry]["paths"][idx_path]
+                        ["bitstring"] = serde_json::to_value(path.bitstring.formatted(format))?;
+                }
+            }
+        }
+        // The overlay is serialized as the list of its entries, in the same order.
+        for (idx, entry) in self.overlay.entries().enumerate() {
+            value["overlay"][idx]["bitstring"] =
+                serde_json::to_value(entry.bitstring.formatted(format))?;
+        }
+        Ok(value)
+    }
+
+    /// Delivery policy of the packets of `proto`.
+    pub fn delivery_policy(&self, proto: u8) -> DeliveryPolicy {
+        self.delivery.get(&proto).copied().unwrap_or_default()
+    }
+
+    /// Copies of a packet with `original_bitstring` and the BIER header `entropy`.
+    /// The entropy only matters with `PathSelection::Ecmp`.
     pub fn process_bier(
         &self,
         original_bitstring: &Bitstring,
         bift_id: u32,
+        entropy: u32,
     ) -> Result<Vec<BierSendInfo>> {
-        let bift_id = bift_id as usize;
+        let mut out = Vec::new();
+        self.process_bier_with(original_bitstring, bift_id, entropy, |bitstring, next_hop| {
+            out.push((bitstring.to_bitstring(), next_hop))
+        })?;
+        Ok(out)
+    }
 
-        // Make a copy that will be edited during the processing.
-        let mut bitstring = original_bitstring.clone();
+    /// Calls `copy` with the bitstring and the next hop of each copy of `process_bier`,
+    /// without allocation: the bitstring of the copy is written directly in the header of
+    /// the outgoing packet with `CopyBitstring::update_header`. On error, e.g., a bit
+    /// without entry, the previous copies were already given to `copy`.
+    pub fn process_bier_with(
+        &self,
+        bitstring: &Bitstring,
+        bift_id: u32,
+        entropy: u32,
+        mut copy: impl FnMut(CopyBitstring, Option<IpAddr>),
+    ) -> Result<()> {
+        self.replicate(bitstring, bift_id, entropy, |_, decision| {
+            if let Some((_, bitstring, next_hop)) = decision {
+                copy(bitstring, next_hop)
+            }
+        })
+    }
 
-        let mut out = Vec::new();
-        let bift = self.bifts.get(bift_id - 1).ok_or(Error::BiftId)?;
-        // TODO: is the vector correctly indexed?
-        assert_eq!(bift.bift_id, bift_id);
+    /// Decisions of `process_bier` for each entry matched by the bitstring, i.e., the
+    /// chosen path of each copy and the drained destinations. Nothing is sent.
+    pub fn explain(
+        &self,
+        bitstring: &Bitstring,
+        bift_id: u32,
+        entropy: u32,
+    ) -> Result<Vec<Decision>> {
+        let mut decisions = Vec::new();
+        self.replicate(bitstring, bift_id, entropy, |bit, decision| {
+            decisions.push(match decision {
+                Some((path, bitstring, next_hop)) => Decision::Copy {
+                    bit,
+                    path,
+                    fbm: bitstring.fbm(),
+                    bitstring: bitstring.to_bitstring(),
+                    next_hop,
+                },
+                None => Decision::Drained { bit },
+            })
+        })?;
+        Ok(decisions)
+    }
 
-        // TODO: currently only supports BIER (RFC8279).
-        assert_eq!(bift.bift_type, BiftType::Bier);
+    /// Calls `decide` with the BFR-id of each entry matched by the bitstring, in the order
+    /// of the BFR-ids, and its copy. No copy if all the paths of the entry are down.
+    fn replicate(
+        &self,
+        original_bitstring: &Bitstring,
+        bift_id: u32,
+        entropy: u32,
+        mut decide: impl FnMut(u64, Option<EntryCopy>),
+    ) -> Result<()> {
+        let compiled = self.compiled.as_ref().ok_or(Error::NotCompiled)?;
+
+        // Make a copy on the stack that will be edited during the processing.
+        let mut words = [0u64; MAX_BITSTRING_WORDS];
+        let bitstring = words
+            .get_mut(..original_bitstring.bitstring.len())
+            .ok_or(Error::BitstringLength)?;
+        bitstring.copy_from_slice(&original_bitstring.bitstring);
+        // F-BM of the copies over a backup path, see `merge_backup_fbm`.
+        let mut merged = [0u64; MAX_BITSTRING_WORDS];
+
+        let bift_idx = *compiled.bifts.get(&bift_id).ok_or(Error::BiftId(bift_id))?;
+        let bift = self.bifts.get(bift_idx).ok_or(Error::BiftId(bift_id))?;
+        let packed = &compiled.packed[bift_idx];
+        let reroute = &compiled.reroutes[bift_idx];
+        let bsl = compiled.bsls[bift_idx];
+        if bsl != 0 && bitstring.len() * 64 != bsl {
+            return Err(Error::BitstringLengthMismatch {
+                expected: bsl,
+                actual: bitstring.len() * 64,
+            });
+        }
+
+        // `compile` refuses the BIER-TE BIFTs, this only guards a BIFT whose type changed since.
+        if bift.bift_type != BiftType::Bier {
+            return Err(Error::BiftType);
+        }
+
+        let nb_words = bitstring.len();
+
+        // Iterate over the words from the last one, holding the BFR-ids 1 to 64.
+        for idx_word in (0..nb_words).rev() {
+            let first_bfr_id = (nb_words - 1 - idx_word) as u64 * 64 + 1;
+            // Bits of the word not visited yet. The bitstring is read again after each copy,
+            // as the bits of its F-BM are cleared.
+            let mut from_bit = 0;
+            loop {
+                let word = bitstring[idx_word] & u64::MAX.checked_shl(from_bit).unwrap_or(0);
+                if word == 0 {
+                    break;
+                }
+                let bit = word.trailing_zeros();
+                from_bit = bit + 1;
+
+                let entry_idx = packed
+                    .entry(first_bfr_id + bit as u64)
+                    .ok_or(Error::NoEntry)?;
+                let bift_entry = bift.entries.get(entry_idx).ok_or(Error::NoEntry)?;
 
-        let bitstring_number_u64 = bitstring.bitstring.len();
-        let mut bfr_idx = 0;
-
-        // Iterate over all u64 words.
-        for idx_u64_word in 0..bitstring_number_u64 {
-            let mut bitstring_word = bitstring.bitstring[bitstring_number_u64 - 1 - idx_u64_word];
-
-            // Iterate over all bits of the word.
-            while bitstring_word > 0 {
-                // The `bfr_idx` BFR has its bit set to 1. Process.
-                if ((bitstring_word >> (bfr_idx % 64)) & 1) == 1 {
-                    // Bitstring for this packet duplication.
-                    let mut dst_bitstring = bitstring.clone();
-                    let bift_entry = bift.entries.get(bfr_idx).ok_or(Error::NoEntry)?;
-                    // TODO: is the vector correctly indexed?
-                    assert_eq!(bift_entry.bit - 1, bfr_idx as u64);
-
-                    // Get the first path always.
-                    let bier_entry_path = bift_entry.paths.get(0).ok_or(Error::NoEntry)?;
-
-                    // Update the bitstring with the bitmask of the corresponding entry.
-                    dst_bitstring.update(&bier_entry_path.bitstring, BitstringOp::And);
-
-                    // Add new destination.
-                    // `None` if the packet must be sent to the local BFER.
-                    let nxt_hop_ip = if bfr_idx as u64 == bift.bfr_id - 1 {
-                        None
-                    } else {
-                        Some(bier_entry_path.next_hop)
-                    };
-                    out.push((dst_bitstring, nxt_hop_ip));
-
-                    // Update global bitstring.
-                    bitstring.update(&bier_entry_path.bitstring, BitstringOp::AndNot);
-
-                    // Update the iterated bitstring word in case we cleaned some bits.
-                    bitstring_word = bitstring.bitstring[bitstring_number_u64 - 1 - idx_u64_word];
+                if bift_entry.paths.is_empty() {
+                    return Err(Error::NoEntry);
+                }
+                // Get the selected path among the paths administratively up and toward a
+                // live next hop, i.e., the next path if the preferred one is dead. Without
+                // such path, the destination is drained: its bit is cleared without copy.
+                let Some((path_idx, bier_entry_path)) = bift_entry.select_live_path(
+                    self.path_selection,
+                    entropy,
+                    &self.dead_next_hops,
+                ) else {
+                    decide(bift_entry.bit, None);
+                    bitstring[idx_word] &= !(1 << bit);
+                    continue;
+                };
+
+                // Bitstring for this packet duplication, computed from the packed bitmask
+                // of the path when read instead of copying the bitstring. The F-BM of a
+                // backup path is merged with the other entries falling back to its next hop,
+                // as the ones of all the paths while a next hop is dead.
+                let fbm = if self.dead_next_hops.is_empty()
+                    && path_idx == bift_entry.primary_path(self.path_selection, entropy)
+                {
+                    packed.fbm(entry_idx, path_idx)
+                } else {
+                    let merged = &mut merged[..nb_words];
+                    self.merge_backup_fbm(
+                        (bift, packed, reroute),
+                        bitstring,
+                        packed.fbm(entry_idx, path_idx),
+                        bier_entry_path.next_hop,
+                        entropy,
+                        merged,
+                    );
+                    merged
+                };
+                let dst_bitstring = CopyBitstring {
+                    remaining: bitstring,
+                    fbm,
+                };
+
+                // Add new destination.
+                // `None` if the packet must be sent to the local BFER.
+                let nxt_hop_ip = if bift_entry.bit == bift.bfr_id {
+                    None
+                } else {
+                    Some(bier_entry_path.next_hop)
+                };
+                decide(bift_entry.bit, Some((path_idx, dst_bitstring, nxt_hop_ip)));
+
+                // Update global bitstring.
+                for (word, mask) in bitstring.iter_mut().zip(fbm.iter()) {
+                    *word &= !mask;
                 }
-                // Next BFR.
-                bfr_idx += 1;
             }
         }
 
-        Ok(out)
+        Ok(())
+    }
+
+    /// Writes in `merged` the F-BM of a copy toward `next_hop` over a path of F-BM `fbm`,
+    /// either a backup path while the primary path of its entry is down (fast reroute) or any
+    /// path while a next hop is dead. The bits of `remaining` whose entries are forwarded
+    /// toward another next hop are removed from `fbm`, so that their destinations are not
+    /// reached twice, and the bits of the entries falling back to `next_hop` are added, so that
+    /// a single copy reaches them. The bits without entry or without live path follow `fbm`.
+    /// Only the entries balanced by ECMP over several live paths are checked one by one.
+    fn merge_backup_fbm(
+        &self,
+        (bift, packed, reroute): (&Bift, &PackedBift, &Reroute),
+        remaining: &[u64],
+        fbm: &[u64],
+        next_hop: IpAddr,
+        entropy: u32,
+        merged: &mut [u64],
+    ) {
+        let nb_words = remaining.len();
+        let first = reroute.first.get(&next_hop);
+        for (idx_word, (word, mask)) in remaining.iter().zip(fbm.iter()).enumerate() {
+            let mut live = reroute.live[idx_word];
+            let mut toward = first.map_or(0, |first| first[idx_word]);
+            let balanced = match self.path_selection {
+                PathSelection::First => 0,
+                PathSelection::Ecmp => word & reroute.balanced[idx_word],
+            };
+            live &= !balanced;
+            toward &= !balanced;
+            merged[idx_word] = (*mask & !(word & live)) | (word & toward);
+
+            let first_bfr_id = (nb_words - 1 - idx_word) as u64 * 64 + 1;
+            let mut bits = balanced;
+            while bits != 0 {
+                let bit = bits.trailing_zeros();
+                bits &= bits - 1;
+                let live_path = packed
+                    .entry(first_bfr_id + bit as u64)
+                    .and_then(|entry_idx| bift.entries.get(entry_idx))
+                    .and_then(|entry| {
+                        entry.select_live_path(self.path_selection, entropy, &self.dead_next_hops)
+                    });
+                match live_path {
+                    Some((_, path)) if path.next_hop == next_hop => merged[idx_word] |= 1 << bit,
+                    Some(_) => merged[idx_word] &= !(1 << bit),
+                    None => (),
+                }
+            }
+        }
+    }
+
+    /// Groups again the bits of the BIFTs by the next hops of their live paths, after a
+    /// change of the liveness of the next hops or of the administrative state of the paths.
+    pub(crate) fn update_reroutes(&mut self) {
+        if let Some(compiled) = self.compiled.as_mut() {
+            compiled.reroutes = self
+                .bifts
+                .iter()
+                .zip(compiled.bsls.iter())
+                .map(|(bift, bsl)| Reroute::new(bift, *bsl, &self.dead_next_hops))
+                .collect();
+        }
     }
 
     pub fn get_loopback(&self) -> IpAddr {
         self.loopback
     }
-}
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
-pub struct Bift {
-    pub bift_id: usize,
-    pub bift_type: BiftType,
-    pub bfr_id: u64,
-    pub entries: Vec<BiftEntry>,
-}
+    /// Checks the consistency of the BIFTs, beyond what `compile` requires, and returns all the
+    /// problems found:
+    /// * the BIFT-ids are unique, and the BIFTs of the SIs of a sub-domain and BSL have
+    ///   consecutive BIFT-ids, e.g., 4, 5 and 7 for the SIs 0, 1 and 3;
+    /// * the BSL of each BIFT is valid, and all its F-BMs have this length;
+    /// * the BFR-ids of the entries are unique and in the SI of their BIFT;
+    /// * the next hops have the address family of the loopback;
+    /// * the BIFT of the SI of this BFR has an entry for its BFR-id, toward the loopback and
+    ///   whose F-BM has the bit of this BFR;
+    /// * the BSL of each neighbor is valid.
+    pub fn validate(&self) -> std::result::Result<(), Vec<ConfigIssue>> {
+        let mut issues = Vec::new();
+        let mut issue =
+            |field: String, message: String| issues.push(ConfigIssue { field, message });
+
+        let mut bift_ids = HashMap::new();
+        // BIFTs of each sub-domain and BSL, with their SI.
+        let mut sets: BTreeMap<(u8, usize), Vec<(u8, usize)>> = BTreeMap::new();
+        for (idx_bift, bift) in self.bifts.iter().enumerate() {
+            let field = format!("bifts[{}]", idx_bift);
+            if let Some(other) = bift_ids.insert(bift.bift_id, idx_bift) {
+                issue(
+                    format!("{}.bift_id", field),
+                    format!("BIFT-id {} already used by bifts[{}]", bift.bift_id, other),
+                );
+            }
+            let bsl = bift.get_bsl();
+            sets.entry((bift.sub_domain, bsl)).or_default().push((bift.si, idx_bift));
+            if bsl != 0 && !Bitstring::is_valid_bsl(bsl) {
+                issue(field.clone(), format!("invalid BSL of {} bits", bsl));
+            }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
-pub struct BiftEntry {
-    /// Bit representing the router of the entry.
-    pub bit: u64,
-    /// All (Bitstring, next-hop) pairsfor this bit.
-    pub paths: Vec<BierEntryPath>,
-}
+            let offset = bift.bfr_id_offset();
+            let last = offset + bsl as u64;
+            let mut bits = HashMap::new();
+            for (idx_entry, entry) in bift.entries.iter().enumerate() {
+                let field = format!("{}.entries[{}]", field, idx_entry);
+                if entry.bit <= offset || (bsl != 0 && entry.bit > last) {
+                    issue(
+                        format!("{}.bit", field),
+                        format!(
+                            "BFR-id {} outside of the SI {} (BFR-ids {} to {})",
+                            entry.bit,
+                            bift.si,
+                            offset + 1,
+                            last
+                        ),
+                    );
+                }
+                if let Some(other) = bits.insert(entry.bit, idx_entry) {
+                    issue(
+                        format!("{}.bit", field),
+                        format!("BFR-id {} already in entries[{}]", entry.bit, other),
+                    );
+                }
+                for (idx_path, path) in entry.paths.iter().enumerate() {
+                    let field = format!("{}.paths[{}]", field, idx_path);
+                    if path.bitstring.bsl() != bsl {
+                        issue(
+                            format!("{}.bitstring", field),
+                            format!("{} bits, expected the BSL {}", path.bitstring.bsl(), bsl),
+                        );
+                    }
+                    if path.next_hop.is_ipv4() != self.loopback.is_ipv4() {
+                        issue(
+                            format!("{}.next_hop", field),
+                            format!(
+                                "next hop {} not in the family of the loopback {}",
+                                path.next_hop, self.loopback
+                            ),
+                        );
+                    }
+                }
+            }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
-pub struct BierEntryPath {
-    pub bitstring: Bitstring,
-    pub next_hop: IpAddr,
+            // The local entry, if this BFR is in the SI of the BIFT.
+            if bift.bfr_id <= offset || (bsl != 0 && bift.bfr_id > last) {
+                continue;
+            }
+            let Some(&idx_entry) = bits.get(&bift.bfr_id) else {
+                issue(
+                    format!("{}.bfr_id", field),
+                    format!("no entry for the BFR-id {} of this BFR", bift.bfr_id),
+                );
+                continue;
+            };
+            let field = format!("{}.entries[{}]", field, idx_entry);
+            let entry = &bift.entries[idx_entry];
+            if entry.paths.is_empty() {
+                issue(field.clone(), "no path for the BFR-id of this BFR".to_string());
+            }
+            for (idx_path, path) in entry.paths.iter().enumerate() {
+                let field = format!("{}.paths[{}]", field, idx_path);
+                if path.next_hop != self.loopback {
+                    issue(
+                        format!("{}.next_hop", field),
+                        format!(
+                            "local entry toward {} instead of the loopback {}",
+                            path.next_hop, self.loopback
+                        ),
+                    );
+                }
+                if !path.bitstring.is_set(bift.bfr_id - offset) {
+                    issue(
+                        format!("{}.bitstring", field),
+                        format!("F-BM of the local entry without the bit {}", bift.bfr_id),
+                    );
+                }
+            }
+        }
+
+        for (next_hop, neighbor) in &self.neighbors {
+            if let Some(bsl) = neighbor.bsl.filter(|bsl| !Bitstring::is_valid_bsl(*bsl)) {
+                issue(
+                    format!("neighbors[{}].bsl", next_hop),
+                    format!("invalid BSL of {} bits", bsl),
+                );
+            }
+        }
+
+        for ((sub_domain, bsl), mut set) in sets {
+            set.sort();
+            for pair in set.windows(2) {
+                let ((si, idx), (next_si, next_idx)) = (pair[0], pair[1]);
+                let bift_id = self.bifts[idx].bift_id;
+                let next_bift_id = self.bifts[next_idx].bift_id;
+                if si == next_si {
+                    issue(
+                        format!("bifts[{}].si", next_idx),
+                        format!(
+                            "SI {} of the sub-domain {} and BSL {} already in bifts[{}]",
+                            si, sub_domain, bsl, idx
+                        ),
+                    );
+                } else if next_bift_id.checked_sub(bift_id) != Some((next_si - si) as usize) {
+                    issue(
+                        format!("bifts[{}].bift_id", next_idx),
+                        format!(
+                            "BIFT-id {} of the SI {} does not follow the BIFT-id {} of the SI {}",
+                            next_bift_id, next_si, bift_id, si
+                        ),
+                    );
+                }
+            }
+        }
+
+        match issues.is_empty() {
+            true => Ok(()),
+            false => Err(issues),
+        }
+    }
+
+    /// Checks the options of the socket of the daemon and of the sockets bound toward the
+    /// neighbors, see `SocketConfig::validate_for`.
+    pub fn validate_sockets(&self) -> std::result::Result<(), String> {
+        self.socket.validate_for(self.loopback)?;
+        for (next_hop, neighbor) in self.neighbors.iter().filter(|(_, n)| n.is_bound()) {
+            let config = neighbor.socket_config(&self.socket);
+            config
+                .validate_for(*next_hop)
+                .map_err(|e| format!("Neighbor {}: {}", next_hop, e))?;
+        }
+        Ok(())
+    }
+
+    /// BIFT-id of the MPLS `label` of this BFR, see `mpls_labels`.
+    pub fn mpls_bift_id(&self, label: u32) -> u32 {
+        self.mpls_labels
+            .iter()
+            .find(|(_, bift_label)| **bift_label == label)
+            .map_or(label, |(bift_id, _)| *bift_id)
+    }
+
+    /// Encapsulation of the copies toward `next_hop`.
+    pub fn encapsulation(&self, next_hop: IpAddr) -> Encapsulation {
+        self.neighbors
+            .get(&next_hop)
+            .map(|neighbor| neighbor.encapsulation)
+            .unwrap_or_default()
+    }
+
+    /// Sets the administrative state of the paths toward `next_hop`, in the BIFT `bift_id`
+    /// and the entry of the BFR-id `bit` if given. Returns the number of updated paths.
+    pub fn set_path_admin_state(
+        &mut self,
+        bift_id: Option<usize>,
+        bit: Option<u64>,
+        next_hop: IpAddr,
+        admin_state: AdminState,
+    ) -> usize {
+        let count = self
+            .entries_mut(bift_id, bit)
+            .flat_map(|entry| entry.paths.iter_mut())
+            .filter(|path| path.next_hop == next_hop)
+            .map(|path| path.admin_state = admin_state)
+            .count();
+        self.update_reroutes();
+        count
+    }
+
+    /// Sets the administrative state of the entries of the BFR-id `bit`, in the BIFT `bift_id`
+    /// if given. Returns the number of updated entries.
+    pub fn set_entry_admin_state(
+        &mut self,
+        bift_id: Option<usize>,
+        bit: u64,
+        admin_state: AdminState,
+    ) -> usize {
+        let count = self
+            .entries_mut(bift_id, Some(bit))
+            .map(|entry| entry.admin_state = admin_state)
+            .count();
+        self.update_reroutes();
+        count
+    }
+
+    /// Empty state of the BFR with the `loopback` and the default options, e.g., for a
+    /// controller building the BIFTs in code with `add_bift` and `upsert_entry`. As after the
+    /// deserialization, the state must be compiled before `process_bier`.
+    pub fn new(loopback: IpAddr) -> Self {
+        BierState {
+            loopback,
+            bifts: Vec::new(),
+            bfr_names: BTreeMap::new(),
+            api_acl: Vec::new(),
+            strict_rfc8296: false,
+            unknown_bits: Default::default(),
+            delivery: BTreeMap::new(),
+            neighbors: BTreeMap::new(),
+            udp_port: None,
+            mpls_labels: BTreeMap::new(),
+            bierin6_option: None,
+            socket: Default::default(),
+            path_selection: Default::default(),
+            overlay: Default::default(),
+            compiled: None,
+            dead_next_hops: BTreeSet::new(),
+        }
+    }
+
+    /// Adds `bift`, whose entries are checked with `Bift::check`. Fails if its BIFT-id is
+    /// already used.
+    pub fn add_bift(&mut self, bift: Bift) -> Result<()> {
+        if u32::try_from(bift.bift_id).is_err()
+            || self.bifts.iter().any(|other| other.bift_id == bift.bift_id)
+        {
+            return Err(Error::BiftParsing);
+        }
+        bift.check()?;
+        self.bifts.push(bift);
+        self.compiled = None;
+        Ok(())
+    }
+
+    /// Removes the BIFT `bift_id`, which is returned.
+    pub fn remove_bift(&mut self, bift_id: usize) -> Option<Bift> {
+        let idx = self.bifts.iter().position(|bift| bift.bift_id == bift_id)?;
+        self.compiled = None;
+        Some(self.bifts.remove(idx))
+    }
+
+    /// BIFT with the BIFT-id `bift_id`, to be modified. The state must be compiled again if
+    /// the BIFT exists.
+    pub fn bift_mut(&mut self, bift_id: u32) -> Option<&mut Bift> {
+        let idx = self
+            .bifts
+            .iter()
+            .position(|bift| bift.bift_id as u64 == bift_id as u64)?;
+        self.compiled = None;
+        Some(&mut self.bifts[idx])
+    }
+
+    /// Replaces the paths of the entry of the BFR-id `bit` in the BIFT `bift_id`, e.g., after
+    /// a change of the topology. The F-BMs of the paths must have the BSL of the BIFT.
+    pub fn set_paths(
+        &mut self,
+        bift_id: usize,
+        bit: u64,
+        paths: Vec<BierEntryPath>,
+    ) -> Result<()> {
+        let bift = self
+            .bifts
+            .iter_mut()
+            .find(|bift| bift.bift_id == bift_id)
+            .ok_or(Error::BiftId(bift_id as u32))?;
+        let entry_idx = bift
+            .entries
+            .iter()
+            .position(|entry| entry.bit == bit)
+            .ok_or(Error::NoEntry)?;
+        let previous = std::mem::replace(&mut bift.entries[entry_idx].paths, paths);
+        if let Err(e) = bift.check() {
+            bift.entries[entry_idx].paths = previous;
+            return Err(e);
+        }
+        self.compiled = None;
+        Ok(())
+    }
+
+    /// Adds `entry` to the BIFT `bift_id`, or replaces its entry with the same BFR-id.
+    /// The forwarding bitmasks of the paths must have the BSL of the BIFT.
+    pub fn upsert_entry(&mut self, bift_id: usize, entry: BiftEntry) -> Result<()> {
+        let bift = self
+            .bifts
+            .iter_mut()
+            .find(|bift| bift.bift_id == bift_id)
+            .ok_or(Error::BiftId(bift_id as u32))?;
+        let bsl = bift.get_bsl();
+        if bsl > 0
+            && entry
+                .paths
+                .iter()
+                .any(|path| path.bitstring.bitstring.len() * 64 != bsl)
+        {
+            return Err(Error::BitstringLength);
+        }
+        let previous = match bift.entries.iter().position(|other| other.bit == entry.bit) {
+            Some(idx) => Some((idx, std::mem::replace(&mut bift.entries[idx], entry))),
+            None => {
+                bift.entries.push(entry);
+                None
+            }
+        };
+        // E.g., an entry outside of the SI of the BIFT.
+        if let Err(e) = bift.check() {
+            match previous {
+                Some((idx, previous)) => bift.entries[idx] = previous,
+                None => {
+                    bift.entries.pop();
+                }
+            }
+            return Err(e);
+        }
+        self.compiled = None;
+        Ok(())
+    }
+
+    /// Removes the entry of the BFR-id `bit` from the BIFT `bift_id`.
+    /// Returns the number of removed entries.
+    pub fn remove_entry(&mut self, bift_id: usize, bit: u64) -> Result<usize> {
+        let bift = self
+            .bifts
+            .iter_mut()
+            .find(|bift| bift.bift_id == bift_id)
+            .ok_or(Error::BiftId(bift_id as u32))?;
+        let nb_entries = bift.entries.len();
+        bift.entries.retain(|entry| entry.bit != bit);
+        self.compiled = None;
+        Ok(nb_entries - bift.entries.len())
+    }
+
+    /// Replaces the next hop `old` of the paths by `new`, in the BIFT `bift_id` if given.
+    /// Returns the number of updated paths. The BIFTs must be compiled again if a path is
+    /// updated, as the compiled F-BMs are grouped by next hop.
+    pub fn replace_next_hop(&mut self, bift_id: Option<usize>, old: IpAddr, new: IpAddr) -> usize {
+        let count = self
+            .entries_mut(bift_id, None)
+            .flat_map(|entry| entry.paths.iter_mut())
+            .filter(|path| path.next_hop == old)
+            .map(|path| path.next_hop = new)
+            .count();
+        if count > 0 {
+            self.compiled = None;
+        }
+        count
+    }
+
+    /// Next hops whose paths are all administratively down, e.g., drained before maintenance.
+    pub fn drained_next_hops(&self) -> BTreeSet<IpAddr> {
+        let mut up = BTreeSet::new();
+        let mut down = BTreeSet::new();
+        for entry in self.bifts.iter().flat_map(|bift| bift.entries.iter()) {
+            for path in entry.paths.iter() {
+                if entry.admin_state.is_up() && path.admin_state.is_up() {
+                    up.insert(path.next_hop);
+                } else {
+                    down.insert(path.next_hop);
+                }
+            }
+        }
+        &down - &up
+    }
+
+    /// Declares `next_hop` alive or dead, e.g., by the liveness prober. While it is dead,
+    /// the entries use their next path, as if their paths toward it were down. Returns
+    /// whether its liveness changed. The compiled state is updated, not rebuilt.
+    pub fn set_next_hop_alive(&mut self, next_hop: IpAddr, alive: bool) -> bool {
+        let changed = match alive {
+            true => self.dead_next_hops.remove(&next_hop),
+            false => self.dead_next_hops.insert(next_hop),
+        };
+        if changed {
+            self.update_reroutes();
+        }
+        changed
+    }
+
+    /// Entries of the BIFT `bift_id` and of the BFR-id `bit`, or all of them.
+    fn entries_mut(
+        &mut self,
+        bift_id: Option<usize>,
+        bit: Option<u64>,
+    ) -> impl Iterator<Item = &mut BiftEntry> {
+        self.bifts
+            .iter_mut()
+            .filter(move |bift| bift_id.is_none_or(|id| bift.bift_id == id))
+            .flat_map(|bift| bift.entries.iter_mut())
+            .filter(move |entry| bit.is_none_or(|bit| entry.bit == bit))
+    }
+
+    /// BIFT with the BIFT-id `bift_id`, using the index of `compile` if compiled.
+    pub fn bift(&self, bift_id: u32) -> Option<&Bift> {
+        match self.compiled.as_ref() {
+            Some(compiled) => compiled.bifts.get(&bift_id).map(|&idx| &self.bifts[idx]),
+            None => self
+                .bifts
+                .iter()
+                .find(|bift| bift.bift_id as u64 == bift_id as u64),
+        }
+    }
+
+    /// Entry of the BFR-id `bfr_id` in the BIFT `bift_id`, using the indexes of `compile` if
+    /// compiled.
+    pub fn entry(&self, bift_id: u32, bfr_id: u64) -> Option<&BiftEntry> {
+        match self.compiled.as_ref() {
+            Some(compiled) => {
+                let bift_idx = *compiled.bifts.get(&bift_id)?;
+                let bift = &self.bifts[bift_idx];
+                let bit = bfr_id.checked_sub(bift.bfr_id_offset())?;
+                compiled.entries[bift_idx]
+                    .get(&bit)
+                    .map(|&idx| &bift.entries[idx])
+            }
+            None => self
+                .bift(bift_id)?
+                .entries
+                .iter()
+                .find(|entry| entry.bit == bfr_id),
+        }
+    }
+
+    /// BIFT-id and bitstring of a packet from `source` to the multicast `group`, from the
+    /// overlay. The BIFT-id is the one of the first SI of the sub-domain of the group, so that
+    /// a bitstring longer than the BSL is split by `split_sets`.
+    pub fn overlay_lookup(&self, source: IpAddr, group: IpAddr) -> Option<(u32, &Bitstring)> {
+        let entry = self.overlay.lookup(source, group)?;
+        let bift = self
+            .bifts
+            .iter()
+            .find(|bift| bift.sub_domain == entry.sub_domain && bift.si == 0)?;
+        Some((bift.bift_id as u32, &entry.bitstring))
+    }
+
+    /// Adds the BFER `bfr_id` of `sub_domain` to the receivers of `group` and `source` in the
+    /// overlay. A new entry has the BSL of the sub-domain. Returns whether it was not a receiver.
+    pub fn join_group(
+        &mut self,
+        group: IpAddr,
+        source: Option<IpAddr>,
+        sub_domain: u8,
+        bfr_id: u64,
+    ) -> Result<bool> {
+        let bsl = self.sub_domain_bsl(sub_domain)?;
+        self.overlay
+            .join(group, source, sub_domain, bfr_id, bsl / 64)
+    }
+
+    /// Adds `entry` to the overlay, or replaces the entry of its group and source. The
+    /// bitstring must be a multiple of the BSL of the sub-domain, so that it is split by
+    /// `split_sets` when sent.
+    pub fn add_group(&mut self, entry: OverlayEntry) -> Result<()> {
+        let bsl = self.sub_domain_bsl(entry.sub_domain)?;
+        if !entry.bitstring.bsl().is_multiple_of(bsl) {
+            return Err(Error::BitstringLengthMismatch {
+                expected: bsl,
+                actual: entry.bitstring.bsl(),
+            });
+        }
+        self.overlay.insert(entry);
+        Ok(())
+    }
+
+    /// BSL of the BIFT of the first SI of `sub_domain`, i.e., of its overlay entries.
+    fn sub_domain_bsl(&self, sub_domain: u8) -> Result<usize> {
+        self.bifts
+            .iter()
+            .find(|bift| bift.sub_domain == sub_domain && bift.si == 0)
+            .map(|bift| bift.get_bsl())
+            .filter(|bsl| *bsl > 0)
+            .ok_or(Error::SubDomain(sub_domain))
+    }
+
+    /// Splits `bitstring`, a multiple of the BSL of the BIFT `bift_id`, in one bitstring per
+    /// set identifier (SI) from the least significant bits, with the BIFT-id of its SI in the
+    /// same sub-domain and BSL. The first SI is the one of `bift_id`, and the SIs without bit
+    /// set are skipped. The application can thus send a packet to all the BFRs of a sub-domain
+    /// with a single bitstring, replicated into one packet per SI as required by RFC 8279.
+    pub fn split_sets<'a>(
+        &self,
+        bift_id: u32,
+        bitstring: &'a [u8],
+    ) -> Result<Vec<(u32, &'a [u8])>> {
+        let compiled = self.compiled.as_ref().ok_or(Error::NotCompiled)?;
+        let bift_idx = *compiled.bifts.get(&bift_id).ok_or(Error::BiftId(bift_id))?;
+        let bift = &self.bifts[bift_idx];
+        let bsl = compiled.bsls[bift_idx];
+        let nb_bytes = bsl / 8;
+        if nb_bytes == 0 || bitstring.is_empty() || !bitstring.len().is_multiple_of(nb_bytes) {
+            return Err(Error::BitstringLength);
+        }
+        bitstring
+            .rchunks(nb_bytes)
+            .enumerate()
+            .filter(|(_, bitstring)| bitstring.iter().any(|byte| *byte != 0))
+            .map(|(idx, bitstring)| {
+                let si = u8::try_from(bift.si as usize + idx).map_err(|_| Error::BiftId(bift_id))?;
+                let bift_id = compiled
+                    .sets
+                    .get(&(bift.sub_domain, bsl, si))
+                    .ok_or(Error::BiftId(bift_id))?;
+                Ok((*bift_id, bitstring))
+            })
+            .collect()
+    }
+
+    /// Bitstring of the BIFT `bift_id` with the single bit of `bfr_id`, e.g., to send a reply
+    /// to the BFIR of a packet. The BFR-id must have an entry with a path in the BIFT.
+    pub fn bitstring_for_bfr_id(&self, bift_id: u32, bfr_id: u64) -> Result<Bitstring> {
+        let bift = self.bift(bift_id).ok_or(Error::BiftId(bift_id))?;
+        let has_path = self
+            .entry(bift_id, bfr_id)
+            .is_some_and(|entry| !entry.paths.is_empty());
+        if bfr_id <= bift.bfr_id_offset() || !has_path {
+            return Err(Error::NoEntry);
+        }
+        let mut bitstring = Bitstring {
+            bitstring: vec![0; bift.get_bsl() / 64],
+        };
+        bitstring.set(bfr_id - bift.bfr_id_offset())?;
+        Ok(bitstring)
+    }
+
+    /// Highest BFR-id with an entry in the BIFT `bift_id`, if the BIFT exists.
+    pub fn highest_bfr_id(&self, bift_id: u32) -> Option<u64> {
+        let bift = self.bift(bift_id)?;
+        Some(bift.highest_bfr_id())
+    }
+
+    /// Highest bit of the bitstring of the BIFT `bift_id` with an entry, i.e., its highest
+    /// BFR-id minus the offset of its SI. The bits above it have no entry.
+    pub fn highest_bit(&self, bift_id: u32) -> Option<u64> {
+        let bift = self.bift(bift_id)?;
+        Some(bift.highest_bfr_id().saturating_sub(bift.bfr_id_offset()))
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Bitstring {
-    pub bitstring: Vec<u64>,
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Bift {
+    pub bift_id: usize,
+    pub bift_type: BiftType,
+    /// BIER sub-domain of the BIFT.
+    #[serde(default)]
+    pub sub_domain: u8,
+    /// Set identifier (SI) of the BIFT: its bitstring represents the BFR-ids `si * BSL + 1`
+    /// to `(si + 1) * BSL`, so that a sub-domain may have more BFRs than the BSL.
+    #[serde(default)]
+    pub si: u8,
+    /// BFR-id of this BFR in the sub-domain, possibly in another SI.
+    pub bfr_id: u64,
+    pub entries: Vec<BiftEntry>,
 }
 
-impl Bitstring {
-    pub fn update(&mut self, other: &Bitstring, bitop: BitstringOp) {
-        self.bitstring = self
-            .bitstring
-            .iter()
-            .zip(other.bitstring.iter())
-            .map(|(bw_self, bw_other)| match bitop {
-                BitstringOp::And => bw_self & bw_other,
-                BitstringOp::AndNot => bw_self & !bw_other,
-            })
-            .collect();
+impl Bift {
+    /// BIER BIFT `bift_id` of the BFR `bfr_id` in the sub-domain 0 and the SI 0, e.g., to
+    /// build the BIFTs in code before `BierState::add_bift`.
+    pub fn new(bift_id: usize, bfr_id: u64, entries: Vec<BiftEntry>) -> Self {
+        Bift {
+            bift_id,
+            bift_type: BiftType::Bier,
+            sub_domain: 0,
+            si: 0,
+            bfr_id,
+            entries,
+        }
     }
 
-    pub fn update_header_from_self(&self, header: &mut [u8]) -> Result<()> {
-        if header.len() < crate::header::BIER_HEADER_WITHOUT_BITSTRING_LENGTH + self.bitstring.len()
-        {
-            return Err(Error::BitstringLength);
+    /// Checks that the BIFT is a BIER one, that the entries are in its SI with distinct
+    /// BFR-ids, and that all the F-BMs have the BSL of the BIFT, as required by
+    /// `BierState::compile`: the F-BMs are applied to the bitstrings of the packets, of the BSL.
+    pub fn check(&self) -> Result<()> {
+        if self.bift_type != BiftType::Bier {
+            return Err(Error::BiftType);
         }
-
-        // Get the bitstring.
-        let bitstring_hdr = &mut header[crate::header::BIER_HEADER_WITHOUT_BITSTRING_LENGTH
-            ..crate::header::BIER_HEADER_WITHOUT_BITSTRING_LENGTH + self.bitstring.len() * 8];
-
-        unsafe {
-            let bitstring: Vec<u64> = self.bitstring.iter().map(|item| item.to_be()).collect();
-            let p = bitstring.as_ptr() as *const u8;
-            let slice = std::slice::from_raw_parts(p, self.bitstring.len() * 8);
-            bitstring_hdr.copy_from_slice(slice);
+        let bsl = self.get_bsl();
+        let offset = self.bfr_id_offset();
+        let mut bits = HashSet::with_capacity(self.entries.len());
+        for entry in self.entries.iter() {
+            let in_set = entry.bit > offset && (bsl == 0 || entry.bit <= offset + bsl as u64);
+            if !in_set || !bits.insert(entry.bit) {
+                return Err(Error::BiftParsing);
+            }
+            if let Some(path) = entry.paths.iter().find(|p| p.bitstring.bsl() != bsl) {
+                return Err(Error::BitstringLengthMismatch {
+                    expected: bsl,
+                    actual: path.bitstring.bsl(),
+                });
+            }
         }
-
         Ok(())
     }
 
-    pub fn is_valid(slice: &[u8]) -> bool {
-        matches!(slice.len(), 8 | 16 | 32 | 64 | 128 | 256 | 512)
+    /// BitString Length of the BIFT in bits, derived from the forwarding bitmasks.
+    /// 0 if the BIFT has no path.
+    pub fn get_bsl(&self) -> usize {
+        self.entries
+            .iter()
+            .flat_map(|entry| entry.paths.iter())
+            .map(|path| path.bitstring.bitstring.len() * 64)
+            .next()
+            .unwrap_or(0)
     }
 
-    pub fn is_valid_from_u64(slice: &[u64]) -> bool {
-        matches!(slice.len(), 1 | 2 | 4 | 8 | 16 | 32 | 64)
+    /// BFR-id represented by the bit before the first bit of the bitstring, i.e., `si * BSL`.
+    pub fn bfr_id_offset(&self) -> u64 {
+        self.si as u64 * self.get_bsl() as u64
     }
-}
 
-impl<'de> Deserialize<'de> for Bitstring {
-    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let s = String::deserialize(deserializer)?;
-        FromStr::from_str(&s).map_err(de::Error::custom)
+    /// Highest BFR-id with an entry. 0 if the BIFT has no entry.
+    pub fn highest_bfr_id(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.bit).max().unwrap_or(0)
     }
 }
 
-impl Serialize for Bitstring {
-    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let a: String = self
-            .bitstring
-            .iter()
-            .rev()
-            .fold(String::new(), |s, v| s + &format!("{:064b}", v));
-        serializer.serialize_str(&a)
-    }
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BiftEntry {
+    /// BFR-id of the router of the entry. Its bit in the bitstring of the BIFT is the
+    /// BFR-id minus the offset of the SI of the BIFT.
+    pub bit: u64,
+    /// All (Bitstring, next-hop) pairsfor this bit.
+    pub paths: Vec<BierEntryPath>,
+    /// Disabled by the operator, like all its paths. The copies of the other entries
+    /// may still carry the bit, following their forwarding bitmask.
+    #[serde(default, skip_serializing_if = "AdminState::is_default")]
+    pub admin_state: AdminState,
 }
 
-impl TryFrom<Vec<u64>> for Bitstring {
-    type Error = crate::Error;
-
-    fn try_from(slice: Vec<u64>) -> crate::Result<Self> {
-        if !Bitstring::is_valid_from_u64(&slice[..]) {
-            return Err(crate::Error::BitstringLength);
+impl BiftEntry {
+    /// Entry of the BFR-id `bit` with the `paths`, in order of preference.
+    pub fn new(bit: u64, paths: Vec<BierEntryPath>) -> Self {
+        BiftEntry {
+            bit,
+            paths,
+            admin_state: AdminState::default(),
         }
+    }
 
-        Ok(Bitstring { bitstring: slice })
+    /// Paths administratively up with their index, in order of preference.
+    /// None if the entry is down.
+    pub fn up_paths(&self) -> impl Iterator<Item = (usize, &BierEntryPath)> {
+        self.paths
+            .iter()
+            .enumerate()
+            .filter(move |(_, path)| self.admin_state.is_up() && path.admin_state.is_up())
     }
-}
 
-impl TryFrom<&[u8]> for Bitstring {
-    type Error = crate::Error;
+    /// Path administratively up of a packet with `entropy`, following `selection`.
+    pub fn select_path(
+        &self,
+        selection: PathSelection,
+        entropy: u32,
+    ) -> Option<(usize, &BierEntryPath)> {
+        self.select_live_path(selection, entropy, &BTreeSet::new())
+    }
 
-    fn try_from(value: &[u8]) -> crate::Result<Self> {
-        if !Bitstring::is_valid(value) {
-            return Err(crate::Error::BitstringLength);
+    /// Index of the path of a packet with `entropy` following `selection` if all the paths
+    /// were up and alive, i.e., its primary path. The other paths are backup paths.
+    pub fn primary_path(&self, selection: PathSelection, entropy: u32) -> usize {
+        match selection {
+            PathSelection::First => 0,
+            PathSelection::Ecmp => ecmp_index(entropy, self.paths.len() as u64),
         }
+    }
 
-        Ok(Bitstring {
-            bitstring: {
-                unsafe {
-                    let p = value.as_ptr() as *mut u64;
-                    let slice = std::slice::from_raw_parts(p, value.len() / 8);
-                    slice.iter().map(|item| item.to_be()).collect()
+    /// Path administratively up and not toward the `dead` next hops of a packet with
+    /// `entropy`, following `selection`.
+    pub fn select_live_path(
+        &self,
+        selection: PathSelection,
+        entropy: u32,
+        dead: &BTreeSet<IpAddr>,
+    ) -> Option<(usize, &BierEntryPath)> {
+        let live = |(_, path): &(usize, &BierEntryPath)| !dead.contains(&path.next_hop);
+        match selection {
+            PathSelection::First => self.up_paths().find(live),
+            PathSelection::Ecmp => {
+                let nb_paths = self.up_paths().filter(live).count() as u64;
+                if nb_paths == 0 {
+                    return None;
                 }
-            },
-        })
+                self.up_paths()
+                    .filter(live)
+                    .nth(ecmp_index(entropy, nb_paths))
+            }
+        }
     }
 }
 
-impl FromStr for Bitstring {
-    type Err = String;
-
-    fn from_str(str_bitstring: &str) -> std::result::Result<Self, Self::Err> {
-        let len_of_64_bits = (str_bitstring.len() as f64 / 64.0).ceil() as usize;
-        if !matches!(len_of_64_bits, 1 | 2 | 4 | 8 | 16 | 32 | 64) {
-            return Err("String to bitstring not correct length".to_string());
-        }
-        let r = str_bitstring.chars().collect::<Vec<char>>().chunks(64).map(|window| {
-            u64::from_str_radix(&String::from_iter(window), 2)
-        }).collect();
+/// Index among `nb_paths` ECMP paths of a packet with `entropy`, by Fibonacci hashing so that
+/// close entropies are spread over the paths.
+fn ecmp_index(entropy: u32, nb_paths: u64) -> usize {
+    let hash = (entropy as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32;
+    hash.checked_rem(nb_paths).unwrap_or(0) as usize
+}
 
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct BierEntryPath {
+    pub bitstring: Bitstring,
+    pub next_hop: IpAddr,
+    /// Disabled by the operator, e.g., to drain the neighbor before maintenance.
+    #[serde(default, skip_serializing_if = "AdminState::is_default")]
+    pub admin_state: AdminState,
+}
 
-        match r {
-            Ok(v) => Ok(Bitstring { bitstring: v }),
-            Err(e) => Err(format!("Impossible to parse the bitstring: {:?}", e).to_string()),
+impl BierEntryPath {
+    /// Path toward `next_hop` whose copies carry the bits of the F-BM `bitstring`.
+    pub fn new(bitstring: Bitstring, next_hop: IpAddr) -> Self {
+        BierEntryPath {
+            bitstring,
+            next_hop,
+            admin_state: AdminState::default(),
         }
-
-        // match (0..len_of_64_bits)
-        //     .map(|i| {
-        //         let lower_bound = str_bitstring.len().saturating_sub(64 * (i + 1));
-        //         let upper_bound = usize::min(lower_bound + 64, str_bitstring.len());
-        //         let substr = &str_bitstring[lower_bound..upper_bound];
-        //         u64::from_str_radix(substr, 2)
-        //     })
-        //     .collect()
-        // {
-        //     Ok(v) => Ok(Bitstring { bitstring: v }),
-        //     Err(e) => Err(format!("Impossible to parse: {:?}", e)),
-        // }
-
     }
 }
 
-impl From<&Bitstring> for Vec<u8> {
-    fn from(bitstring: &Bitstring) -> Self {
-        bitstring
-            .bitstring
-            .iter()
-            .flat_map(|elem| elem.to_be_bytes())
-            .collect()
-    }
+/// Administrative state of an entry or a path. The disabled paths are skipped
+/// by the BIER processing, which uses the next path of the entry.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminState {
+    #[default]
+    Up,
+    Down,
 }
 
-impl Default for Bitstring {
-    fn default() -> Self {
-        Self {
-            bitstring: vec![0; 1],
-        }
+impl AdminState {
+    pub fn is_up(&self) -> bool {
+        *self == AdminState::Up
+    }
+
+    fn is_default(&self) -> bool {
+        *self == AdminState::default()
     }
 }
 
-#[derive(Deserialize_repr, Serialize_repr, PartialEq, Eq, Debug)]
+#[derive(Deserialize_repr, Serialize_repr, PartialEq, Eq, Debug, Clone, Copy)]
 #[repr(u32)]
 pub enum BiftType {
     Bier = 1,
     BierTe = 2,
 }
 
-pub enum BitstringOp {
-    And = 1,
-    AndNot = 2,
+/// State of the tests at `loopback`, with the BIFT 1 of the BFR `bfr_id` and the entries
+/// `(bit, [(F-BM, next hop)])`. It is not compiled.
+#[cfg(test)]
+pub(crate) fn test_state(
+    loopback: &str,
+    bfr_id: u64,
+    entries: &[(u64, &[(&str, &str)])],
+) -> BierState {
+    let entries = entries
+        .iter()
+        .map(|(bit, paths)| {
+            let paths = paths
+                .iter()
+                .map(|(bitstring, next_hop)| {
+                    BierEntryPath::new(bitstring.parse().unwrap(), next_hop.parse().unwrap())
+                })
+                .collect();
+            BiftEntry::new(*bit, paths)
+        })
+        .collect();
+    let mut state = BierState::new(loopback.parse().unwrap());
+    state.add_bift(Bift::new(1, bfr_id, entries)).unwrap();
+    state
+}
+
+/// State of `configs/example.json`, the BFR A of the example topology. It is not compiled.
+#[cfg(test)]
+pub(crate) fn example_state() -> BierState {
+    serde_json::from_str(include_str!("../configs/example.json")).unwrap()
 }
 
 #[cfg(test)]
@@ -275,6 +1729,7 @@ mod tests {
 
     use super::*;
     use std::net::IpAddr;
+    use std::str::FromStr;
 
     fn get_dummy_config_json() -> &'static str {
         r#"{"loopback": "fc00::a","bifts": [
@@ -421,32 +1876,19 @@ mod tests {
         );
     }
 
-    #[test]
-    /// Tests the update of a bitstring.
-    fn test_update_bitstring() {
-        let bitstring = Bitstring::from_str("1101");
-        assert!(bitstring.is_ok());
-        let mut bitstring = bitstring.unwrap();
-
-        bitstring.update(&Bitstring::from_str("1011").unwrap(), BitstringOp::And);
-        assert_eq!(bitstring.bitstring[0], 0b1001);
-
-        bitstring.update(&Bitstring::from_str("0011").unwrap(), BitstringOp::AndNot);
-        assert_eq!(bitstring.bitstring[0], 0b1000);
-    }
-
     #[test]
     /// Tests the BIER processing of a bitstring using the dummy BIFT.
     fn test_bier_processing() {
         let txt = get_dummy_config_json();
-        let bier_state: BierState = serde_json::from_str(txt).unwrap();
+        let mut bier_state: BierState = serde_json::from_str(txt).unwrap();
+        bier_state.compile().unwrap();
 
         let bitstring = Bitstring::from_str("11111");
         assert!(bitstring.is_ok());
         let bitstring = bitstring.unwrap();
         // TODO: test also with invalid bitstring length (e.g., longer).
 
-        let outputs = bier_state.process_bier(&bitstring, 1);
+        let outputs = bier_state.process_bier(&bitstring, 1, 0);
         assert!(outputs.is_ok());
         let outputs = outputs.unwrap();
 
@@ -469,18 +1911,657 @@ mod tests {
         assert!(res);
     }
 
+    #[test]
+    /// Tests the copies written directly in the headers of the outgoing packets.
+    fn test_process_bier_with() {
+        let mut bier_state: BierState = serde_json::from_str(get_dummy_config_json()).unwrap();
+        bier_state.compile().unwrap();
+        let bitstring = Bitstring::from_str("11111").unwrap();
+        let header_length = crate::header::BIER_HEADER_WITHOUT_BITSTRING_LENGTH + 8;
+
+        let mut copies = Vec::new();
+        let mut packet = vec![0xff; header_length];
+        bier_state
+            .process_bier_with(&bitstring, 1, 0, |bitstring, next_hop| {
+                bitstring.update_header(&mut packet).unwrap();
+                let written = Bitstring::try_from(&packet[header_length - 8..]).unwrap();
+                assert_eq!(written, bitstring.to_bitstring());
+                copies.push((written, next_hop));
+            })
+            .unwrap();
+        assert_eq!(copies, bier_state.process_bier(&bitstring, 1, 0).unwrap());
+        // The header is too short for the bitstring.
+        bier_state
+            .process_bier_with(&bitstring, 1, 0, |bitstring, _| {
+                assert_eq!(
+                    bitstring.update_header(&mut packet[..header_length - 1]),
+                    Err(Error::BitstringLength)
+                );
+            })
+            .unwrap();
+
+        // Longer than the maximum BSL.
+        let too_long = Bitstring {
+            bitstring: vec![1; MAX_BITSTRING_WORDS + 1],
+        };
+        assert_eq!(
+            bier_state.process_bier_with(&too_long, 1, 0, |_, _| ()),
+            Err(Error::BitstringLength)
+        );
+    }
+
+    #[test]
+    /// Tests the BIER processing after the replacement of a next hop of the compiled BIFTs.
+    fn test_bier_processing_replace_next_hop() {
+        let mut bier_state: BierState = serde_json::from_str(get_dummy_config_json()).unwrap();
+        bier_state.compile().unwrap();
+        let b = IpAddr::V6("fc00:b::1".parse().unwrap());
+        let e = IpAddr::V6("fc00:e::1".parse().unwrap());
+        let bitstring = Bitstring::from_str("11010").unwrap();
+
+        assert_eq!(bier_state.replace_next_hop(Some(1), e, b), 0);
+        assert!(bier_state.process_bier(&bitstring, 1, 0).is_ok());
+        assert_eq!(bier_state.replace_next_hop(Some(1), b, e), 3);
+        // The copies toward the old next hop are not sent from the stale compiled state.
+        assert_eq!(
+            bier_state.process_bier(&bitstring, 1, 0),
+            Err(Error::NotCompiled)
+        );
+        bier_state.compile().unwrap();
+        assert_eq!(
+            bier_state.process_bier(&bitstring, 1, 0).unwrap(),
+            vec![(Bitstring::from_str("11010").unwrap(), Some(e))]
+        );
+    }
+
+    #[test]
+    /// Tests the BIER processing with paths and entries administratively down.
+    fn test_bier_processing_admin_state() {
+        let mut bier_state: BierState = serde_json::from_str(get_dummy_config_json()).unwrap();
+        bier_state.compile().unwrap();
+        let b = IpAddr::V6("fc00:b::1".parse().unwrap());
+        let c = IpAddr::V6("fc00:c::1".parse().unwrap());
+        let bitstring = Bitstring::from_str("11010").unwrap();
+        assert_eq!(
+            bier_state.process_bier(&bitstring, 1, 0).unwrap(),
+            vec![(Bitstring::from_str("11010").unwrap(), Some(b))]
+        );
+
+        // BFR 2 has no other path, BFRs 4 and 5 fall back to C.
+        assert_eq!(bier_state.set_path_admin_state(None, None, b, AdminState::Down), 3);
+        assert_eq!(bier_state.drained_next_hops(), BTreeSet::from([b]));
+        assert_eq!(
+            bier_state.process_bier(&bitstring, 1, 0).unwrap(),
+            vec![(Bitstring::from_str("11000").unwrap(), Some(c))]
+        );
+        // The bit of a disabled entry is still carried by the copies of the other entries.
+        assert_eq!(bier_state.set_entry_admin_state(Some(1), 5, AdminState::Down), 1);
+        assert_eq!(
+            bier_state.explain(&bitstring, 1, 0).unwrap(),
+            vec![
+                Decision::Drained { bit: 2 },
+                Decision::Copy {
+                    bit: 4,
+                    path: 1,
+                    fbm: Bitstring::from_str("11100").unwrap(),
+                    bitstring: Bitstring::from_str("11000").unwrap(),
+                    next_hop: Some(c),
+                },
+            ]
+        );
+        assert_eq!(
+            bier_state.process_bier(&bitstring, 1, 0).unwrap(),
+            vec![(Bitstring::from_str("11000").unwrap(), Some(c))]
+        );
+        let bfr_5 = Bitstring::from_str("10000").unwrap();
+        assert!(bier_state.process_bier(&bfr_5, 1, 0).unwrap().is_empty());
+
+        bier_state.set_path_admin_state(Some(1), None, b, AdminState::Up);
+        bier_state.set_entry_admin_state(None, 5, AdminState::Up);
+        assert_eq!(
+            bier_state.process_bier(&bitstring, 1, 0).unwrap(),
+            vec![(Bitstring::from_str("11010").unwrap(), Some(b))]
+        );
+        assert!(bier_state.drained_next_hops().is_empty());
+        let json = serde_json::to_string(&bier_state).unwrap();
+        assert!(!json.contains("admin_state"));
+    }
+
+    #[test]
+    /// Tests the fallback of the entries to their next path while a next hop is dead.
+    fn test_bier_processing_dead_next_hop() {
+        let mut bier_state: BierState = serde_json::from_str(get_dummy_config_json()).unwrap();
+        bier_state.compile().unwrap();
+        let b = IpAddr::V6("fc00:b::1".parse().unwrap());
+        let c = IpAddr::V6("fc00:c::1".parse().unwrap());
+        let bitstring = Bitstring::from_str("11010").unwrap();
+
+        // As with the paths toward B administratively down, without modifying the BIFTs.
+        assert!(bier_state.set_next_hop_alive(b, false));
+        assert!(!bier_state.set_next_hop_alive(b, false));
+        assert!(bier_state.compiled.is_some());
+        assert_eq!(
+            bier_state.process_bier(&bitstring, 1, 0).unwrap(),
+            vec![(Bitstring::from_str("11000").unwrap(), Some(c))]
+        );
+        assert_eq!(
+            bier_state.explain(&bitstring, 1, 0).unwrap()[0],
+            Decision::Drained { bit: 2 }
+        );
+        assert!(bier_state.drained_next_hops().is_empty());
+        bier_state.path_selection = PathSelection::Ecmp;
+        for entropy in 0..16 {
+            let copies = bier_state.process_bier(&bitstring, 1, entropy).unwrap();
+            assert!(copies.iter().all(|(_, next_hop)| *next_hop == Some(c)));
+        }
+
+        // Without live path, the destinations are drained.
+        bier_state.set_next_hop_alive(c, false);
+        assert!(bier_state.process_bier(&bitstring, 1, 0).unwrap().is_empty());
+        assert!(bier_state.set_next_hop_alive(b, true));
+        bier_state.path_selection = PathSelection::First;
+        assert_eq!(
+            bier_state.process_bier(&bitstring, 1, 0).unwrap(),
+            vec![(Bitstring::from_str("11010").unwrap(), Some(b))]
+        );
+        assert!(!serde_json::to_string(&bier_state).unwrap().contains("dead"));
+    }
+
+    #[test]
+    /// Tests the fast reroute of the single link failures of the diamond topology of
+    /// `bier-config`, A (1) to E (5): a single copy per neighbor, and each destination reached
+    /// once unless it has no live path.
+    /// ```text
+    ///     a
+    ///   /   \
+    ///  b     c
+    ///   \   /
+    ///     d
+    ///     |
+    ///     e
+    /// ```
+    fn test_bier_processing_fast_reroute() {
+        let node = |node: char| Some(format!("fc00:{}::1", node).parse::<IpAddr>().unwrap());
+        let mut bfr_a = test_state(
+            "fc00::a",
+            1,
+            &[
+                (1, &[("1", "fc00:a::1")]),
+                (2, &[("11010", "fc00:b::1"), ("10", "fc00:c::1")]),
+                (3, &[("100", "fc00:c::1"), ("100", "fc00:b::1")]),
+                (4, &[("11010", "fc00:b::1"), ("1000", "fc00:c::1")]),
+                (5, &[("11010", "fc00:b::1"), ("10000", "fc00:c::1")]),
+            ],
+        );
+        let mut bfr_d = test_state(
+            "fc00::a",
+            4,
+            &[
+                (1, &[("11", "fc00:b::1"), ("1", "fc00:c::1")]),
+                (2, &[("11", "fc00:b::1"), ("10", "fc00:c::1")]),
+                (3, &[("100", "fc00:c::1"), ("100", "fc00:b::1")]),
+                (4, &[("1000", "fc00:d::1")]),
+                (5, &[("10000", "fc00:e::1")]),
+            ],
+        );
+        bfr_a.compile().unwrap();
+        bfr_d.compile().unwrap();
+        let packet = Bitstring::from_str("11111").unwrap();
+        let copies = |bier_state: &BierState, entropy| {
+            bier_state.process_bier(&packet, 1, entropy).unwrap()
+        };
+        let bitstring = |bits: &str| Bitstring::from_str(bits).unwrap();
+
+        // Without failure, the backup paths are not used.
+        assert_eq!(
+            copies(&bfr_a, 0),
+            vec![
+                (bitstring("1"), None),
+                (bitstring("11010"), node('b')),
+                (bitstring("100"), node('c')),
+            ]
+        );
+
+        // Link A-B: BFRs 2, 4 and 5 fall back to C, merged with BFR 3 in a single copy.
+        let mut failed = bfr_a.clone();
+        failed.set_next_hop_alive(node('b').unwrap(), false);
+        assert_eq!(
+            copies(&failed, 0),
+            vec![(bitstring("1"), None), (bitstring("11110"), node('c'))]
+        );
+        // Link A-C: BFR 3 falls back to B, merged with the copy of the BFRs 2, 4 and 5.
+        let mut failed = bfr_a.clone();
+        failed.set_next_hop_alive(node('c').unwrap(), false);
+        assert_eq!(
+            copies(&failed, 0),
+            vec![(bitstring("1"), None), (bitstring("11110"), node('b'))]
+        );
+        // Link D-B: BFRs 1 and 2 fall back to C, without the local and the E copies.
+        let mut failed = bfr_d.clone();
+        failed.set_next_hop_alive(node('b').unwrap(), false);
+        assert_eq!(
+            copies(&failed, 0),
+            vec![
+                (bitstring("111"), node('c')),
+                (bitstring("1000"), None),
+                (bitstring("10000"), node('e')),
+            ]
+        );
+        // Link D-E: BFR 5 has no backup path and is drained.
+        let mut failed = bfr_d.clone();
+        failed.set_next_hop_alive(node('e').unwrap(), false);
+        assert_eq!(
+            failed.explain(&packet, 1, 0).unwrap().last(),
+            Some(&Decision::Drained { bit: 5 })
+        );
+
+        // Whatever the selection of the paths, each neighbor of a single link failure
+        // receives at most one copy, and each destination with a live path exactly one.
+        for (bier_state, neighbors) in [(&bfr_a, "bc"), (&bfr_d, "bce")] {
+            for (failed_node, selection, entropy) in neighbors.chars().flat_map(|failed_node| {
+                [PathSelection::First, PathSelection::Ecmp]
+                    .into_iter()
+                    .flat_map(move |selection| (0..8).map(move |e| (failed_node, selection, e)))
+            }) {
+                let mut failed = bier_state.clone();
+                failed.path_selection = selection;
+                failed.set_next_hop_alive(node(failed_node).unwrap(), false);
+                let copies = failed.process_bier(&packet, 1, entropy).unwrap();
+                let mut next_hops: Vec<_> = copies.iter().map(|(_, next_hop)| *next_hop).collect();
+                next_hops.sort();
+                next_hops.dedup();
+                assert_eq!(next_hops.len(), copies.len());
+                assert!(!next_hops.contains(&node(failed_node)));
+
+                let drained = failed
+                    .explain(&packet, 1, entropy)
+                    .unwrap()
+                    .into_iter()
+                    .filter(|decision| matches!(decision, Decision::Drained { .. }))
+                    .count();
+                let mut reached = 0;
+                for (bitstring, _) in copies.iter() {
+                    assert_eq!(reached & bitstring.bitstring[0], 0);
+                    reached |= bitstring.bitstring[0];
+                }
+                assert_eq!(reached.count_ones() as usize + drained, 5);
+            }
+        }
+    }
+
+    #[test]
+    /// Tests the choice of the ECMP paths by the entropy of the packets.
+    fn test_bier_processing_ecmp() {
+        let mut bier_state: BierState = serde_json::from_str(get_dummy_config_json()).unwrap();
+        bier_state.compile().unwrap();
+        let b = IpAddr::V6("fc00:b::1".parse().unwrap());
+        let c = IpAddr::V6("fc00:c::1".parse().unwrap());
+        let bitstring = Bitstring::from_str("11000").unwrap();
+        let next_hops = |bier_state: &BierState, entropy| {
+            bier_state
+                .process_bier(&bitstring, 1, entropy)
+                .unwrap()
+                .into_iter()
+                .map(|(_, next_hop)| next_hop.unwrap())
+                .collect::<Vec<_>>()
+        };
+        // Without ECMP, the first path is always chosen.
+        assert!((0..16).all(|entropy| next_hops(&bier_state, entropy) == vec![b]));
+
+        bier_state.path_selection = PathSelection::Ecmp;
+        let chosen: Vec<_> = (0..16).map(|entropy| next_hops(&bier_state, entropy)).collect();
+        // A single copy covers both BFRs, and the same entropy always takes the same path.
+        assert!(chosen.iter().all(|next_hops| next_hops.len() == 1));
+        assert!(chosen.contains(&vec![b]) && chosen.contains(&vec![c]));
+        assert!((0..16).all(|entropy| next_hops(&bier_state, entropy) == chosen[entropy as usize]));
+
+        // Only the paths administratively up are chosen.
+        bier_state.set_path_admin_state(None, None, c, AdminState::Down);
+        assert!((0..16).all(|entropy| next_hops(&bier_state, entropy) == vec![b]));
+
+        let json = serde_json::to_string(&bier_state).unwrap();
+        assert!(json.contains(r#""path_selection":"ecmp""#));
+    }
+
+    #[test]
+    /// Tests that the BIER processing requires compiled BIFTs, indexed by BIFT-id and BFR-id.
+    fn test_compile() {
+        let mut bier_state: BierState = serde_json::from_str(get_dummy_config_json()).unwrap();
+        let bitstring = Bitstring::from_str("11111").unwrap();
+        assert_eq!(
+            bier_state.process_bier(&bitstring, 1, 0),
+            Err(Error::NotCompiled)
+        );
+
+        let report = bier_state.compile().unwrap();
+        assert_eq!(report.nb_bifts, 1);
+        assert_eq!(report.nb_entries, 5);
+        assert_eq!(report.nb_next_hops, 2);
+        assert!(report.memory > 0);
+        let compiled = bier_state.compiled.as_ref().unwrap();
+        assert_eq!(compiled.next_hops().count(), 2);
+        assert_eq!(
+            compiled.busiest_next_hops()[0],
+            IpAddr::V6("fc00:b::1".parse().unwrap())
+        );
+        let copies = bier_state.process_bier(&bitstring, 1, 0).unwrap();
+
+        // The BIFT-ids and the entries do not depend on their position.
+        bier_state.bifts[0].bift_id = 7;
+        bier_state.bifts[0].entries.reverse();
+        bier_state.compile().unwrap();
+        assert_eq!(bier_state.process_bier(&bitstring, 1, 0), Err(Error::BiftId(1)));
+        assert_eq!(bier_state.process_bier(&bitstring, 7, 0).unwrap(), copies);
+
+        // The bitstrings of the packets have the BSL of their BIFT: their words would not be
+        // aligned with the ones of the F-BMs otherwise.
+        let longer = Bitstring::from_str(&format!("{}11111", "0".repeat(123))).unwrap();
+        assert_eq!(
+            bier_state.process_bier(&longer, 7, 0),
+            Err(Error::BitstringLengthMismatch {
+                expected: 64,
+                actual: 128
+            })
+        );
+        let mut mixed = bier_state.clone();
+        mixed.bifts[0].entries[2].paths[0].bitstring = longer;
+        assert_eq!(
+            mixed.compile(),
+            Err(Error::BitstringLengthMismatch {
+                expected: 64,
+                actual: 128
+            })
+        );
+
+        // The BIER-TE BIFTs are refused, and never processed.
+        let mut bier_te = bier_state.clone();
+        bier_te.bifts[0].bift_type = BiftType::BierTe;
+        assert_eq!(bier_te.compile(), Err(Error::BiftType));
+        bier_te.compiled = bier_state.compiled.clone();
+        assert_eq!(bier_te.process_bier(&bitstring, 7, 0), Err(Error::BiftType));
+
+        bier_state.bifts.push(bier_state.bifts[0].clone());
+        assert_eq!(bier_state.compile(), Err(Error::BiftParsing));
+        assert!(bier_state.compiled.is_none());
+    }
+
+    #[test]
+    /// Tests the construction and the modification of a state in code, as by a controller.
+    fn test_build_state() {
+        let loopback: IpAddr = "fc00::1".parse().unwrap();
+        let neighbor: IpAddr = "fc00::2".parse().unwrap();
+        let backup: IpAddr = "fc00::3".parse().unwrap();
+        let path = |fbm: &str, next_hop: IpAddr| BierEntryPath {
+            bitstring: Bitstring::from_str(fbm).unwrap(),
+            next_hop,
+            admin_state: AdminState::Up,
+        };
+        let entry = |bit: u64, paths: Vec<BierEntryPath>| BiftEntry {
+            bit,
+            paths,
+            admin_state: AdminState::Up,
+        };
+        let mut bier_state = BierState::new(loopback);
+        let bift = Bift {
+            bift_id: 1,
+            bift_type: BiftType::Bier,
+            sub_domain: 0,
+            si: 0,
+            bfr_id: 1,
+            entries: vec![entry(1, vec![path("1", loopback)])],
+        };
+        bier_state.add_bift(bift.clone()).unwrap();
+        assert_eq!(bier_state.add_bift(bift.clone()), Err(Error::BiftParsing));
+        let mut outside = Bift {
+            bift_id: 2,
+            si: 1,
+            ..bift.clone()
+        };
+        assert_eq!(bier_state.add_bift(outside.clone()), Err(Error::BiftParsing));
+        outside.entries[0].bit = 65;
+        bier_state.add_bift(outside).unwrap();
+        assert_eq!(bier_state.remove_bift(2).unwrap().bift_id, 2);
+        assert!(bier_state.remove_bift(2).is_none());
+
+        bier_state
+            .upsert_entry(1, entry(2, vec![path("10", neighbor)]))
+            .unwrap();
+        bier_state.compile().unwrap();
+        let bitstring = Bitstring::from_str("11").unwrap();
+        assert_eq!(bier_state.process_bier(&bitstring, 1, 0).unwrap().len(), 2);
+
+        // The modifications require a new compilation.
+        bier_state
+            .set_paths(1, 2, vec![path("10", backup), path("10", neighbor)])
+            .unwrap();
+        assert_eq!(bier_state.process_bier(&bitstring, 1, 0), Err(Error::NotCompiled));
+        bier_state.compile().unwrap();
+        assert_eq!(
+            bier_state.process_bier(&bitstring, 1, 0).unwrap(),
+            vec![
+                (Bitstring::from_str("1").unwrap(), None),
+                (Bitstring::from_str("10").unwrap(), Some(backup))
+            ]
+        );
+
+        // The invalid paths are refused, keeping the previous ones.
+        let longer = path(&format!("1{}", "0".repeat(64)), neighbor);
+        assert_eq!(
+            bier_state.set_paths(1, 2, vec![longer]),
+            Err(Error::BitstringLengthMismatch {
+                expected: 64,
+                actual: 128
+            })
+        );
+        assert_eq!(bier_state.set_paths(1, 3, Vec::new()), Err(Error::NoEntry));
+        assert_eq!(
+            bier_state.upsert_entry(1, entry(65, Vec::new())),
+            Err(Error::BiftParsing)
+        );
+        assert_eq!(bier_state.set_paths(3, 2, Vec::new()), Err(Error::BiftId(3)));
+        assert_eq!(bier_state.bift(1).unwrap().entries.len(), 2);
+        assert_eq!(bier_state.bift(1).unwrap().entries[1].paths.len(), 2);
+
+        // An unknown BIFT-id keeps the compiled state.
+        bier_state.compile().unwrap();
+        assert!(bier_state.bift_mut(3).is_none());
+        assert!(bier_state.compiled.is_some());
+        bier_state.bift_mut(1).unwrap().bfr_id = 2;
+        assert!(bier_state.compiled.is_none());
+        assert_eq!(bier_state.bift(1).unwrap().bfr_id, 2);
+    }
+
+    #[test]
+    /// Tests the entries and the F-BMs of the packed BIFTs, used by `process_bier`.
+    fn test_packed_bift() {
+        let mut bier_state: BierState = serde_json::from_str(get_dummy_config_json()).unwrap();
+        bier_state.bifts[0].entries.swap(0, 3);
+        bier_state.bifts[0].entries[2].paths.clear();
+        bier_state.compile().unwrap();
+        let packed = &bier_state.compiled.as_ref().unwrap().packed[0];
+        assert_eq!(packed.slots.len(), 64);
+        assert_eq!(packed.fbms.len(), 6);
+        assert_eq!(packed.first_path, vec![0, 2, 3, 3, 4]);
+        assert_eq!(
+            (packed.entry(4), packed.entry(1), packed.entry(3)),
+            (Some(0), Some(3), Some(2))
+        );
+        assert_eq!((packed.entry(0), packed.entry(6), packed.entry(65)), (None, None, None));
+        let entries = &bier_state.bifts[0].entries;
+        for (entry_idx, entry) in entries.iter().enumerate() {
+            for (path_idx, path) in entry.paths.iter().enumerate() {
+                assert_eq!(packed.fbm(entry_idx, path_idx), &path.bitstring.bitstring[..]);
+            }
+        }
+    }
+
+    #[test]
+    /// Tests the validation of the BIFTs, reporting all the problems with their field.
+    fn test_validate() {
+        let mut bier_state: BierState = serde_json::from_str(get_dummy_config_json()).unwrap();
+        // The local entry is toward another address than the loopback.
+        let issues = bier_state.validate().unwrap_err();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(
+            issues[0].to_string(),
+            "bifts[0].entries[0].paths[0].next_hop: local entry toward fc00:a::1 instead of \
+             the loopback fc00::a"
+        );
+        bier_state.bifts[0].entries[0].paths[0].next_hop = bier_state.loopback;
+        assert_eq!(bier_state.validate(), Ok(()));
+
+        // A neighbor with an invalid BSL, refused by the compilation too.
+        let neighbor: IpAddr = "fc00:a::1".parse().unwrap();
+        for bsl in [96, 8192] {
+            bier_state.neighbors.insert(
+                neighbor,
+                NeighborConfig {
+                    bsl: Some(bsl),
+                    ..Default::default()
+                },
+            );
+            let issues = bier_state.validate().unwrap_err();
+            assert_eq!(
+                issues[0].to_string(),
+                format!("neighbors[fc00:a::1].bsl: invalid BSL of {} bits", bsl)
+            );
+            assert_eq!(bier_state.compile(), Err(Error::BitstringLength));
+        }
+        bier_state.neighbors.clear();
+
+        // The SI 1 with a non-consecutive BIFT-id, and again with a duplicate BIFT-id.
+        let mut si = bier_state.bifts[0].clone();
+        si.entries.iter_mut().for_each(|entry| entry.bit += 64);
+        si.si = 1;
+        for bift_id in [3, 1] {
+            bier_state.bifts.push(Bift {
+                bift_id,
+                ..si.clone()
+            });
+        }
+        // Outside of the SI 0, duplicate, of another length and of another family.
+        let entries = &mut bier_state.bifts[0].entries;
+        entries[1].bit = 65;
+        entries[2].bit = 4;
+        entries[3].paths[1].bitstring = Bitstring::from_str(&"1".repeat(128)).unwrap();
+        entries[4].paths[0].next_hop = "10.0.0.1".parse().unwrap();
+        let issues: Vec<_> = bier_state
+            .validate()
+            .unwrap_err()
+            .into_iter()
+            .map(|issue| issue.field)
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                "bifts[0].entries[1].bit",
+                "bifts[0].entries[3].bit",
+                "bifts[0].entries[3].paths[1].bitstring",
+                "bifts[0].entries[4].paths[0].next_hop",
+                "bifts[2].bift_id",
+                "bifts[1].bift_id",
+                "bifts[2].si",
+            ]
+        );
+    }
+
+    #[test]
+    /// Tests the BIFTs of several set identifiers (SIs) in the same sub-domain and BSL.
+    fn test_set_identifiers() {
+        let mut bier_state: BierState = serde_json::from_str(get_dummy_config_json()).unwrap();
+        // The same BIFT for the BFR-ids 65 to 69, in the SI 1.
+        let mut bift = bier_state.bifts[0].clone();
+        bift.bift_id = 2;
+        bift.si = 1;
+        bift.entries.iter_mut().for_each(|entry| entry.bit += 64);
+        bier_state.bifts.push(bift);
+        bier_state.compile().unwrap();
+        assert_eq!(bier_state.bifts[1].bfr_id_offset(), 64);
+        assert_eq!(bier_state.highest_bfr_id(2), Some(69));
+        assert_eq!(bier_state.highest_bit(2), Some(5));
+
+        // The local BFR-id is in the SI 0: the first bit of the SI 1 is another BFR.
+        let bitstring = Bitstring::from_str("1").unwrap();
+        assert_eq!(bier_state.process_bier(&bitstring, 1, 0).unwrap()[0].1, None);
+        assert_eq!(
+            bier_state.process_bier(&bitstring, 2, 0).unwrap()[0].1,
+            Some(IpAddr::V6("fc00:a::1".parse().unwrap()))
+        );
+        assert_eq!(
+            bier_state.bitstring_for_bfr_id(2, 66),
+            Ok(Bitstring::from_str("10").unwrap())
+        );
+        assert_eq!(bier_state.bitstring_for_bfr_id(2, 2), Err(Error::NoEntry));
+
+        // One bitstring per SI with a bit set, from the least significant bits.
+        let both = Bitstring::from_str(&format!("11{}110", "0".repeat(61))).unwrap();
+        let both: Vec<u8> = (&both).into();
+        let sets = bier_state.split_sets(1, &both).unwrap();
+        assert_eq!(sets.len(), 2);
+        assert_eq!(sets[0], (1, &both[8..]));
+        assert_eq!(sets[1], (2, &both[..8]));
+        assert_eq!(bier_state.split_sets(1, &[0; 8]).unwrap(), vec![]);
+        let second = Bitstring::from_str(&format!("1{}", "0".repeat(64))).unwrap();
+        let second: Vec<u8> = (&second).into();
+        assert_eq!(bier_state.split_sets(1, &second), Ok(vec![(2, &second[..8])]));
+        // The SI 1 of the BIFT 2 is the last one.
+        assert_eq!(bier_state.split_sets(2, &second), Err(Error::BiftId(2)));
+        assert_eq!(bier_state.split_sets(1, &[0; 12]), Err(Error::BitstringLength));
+
+        // The entries of a BIFT must be in its SI.
+        bier_state.bifts[1].entries[0].bit = 3;
+        assert_eq!(bier_state.compile(), Err(Error::BiftParsing));
+    }
+
+    #[test]
+    /// Tests the single-bit bitstrings toward a BFR-id, e.g., for the replies to the BFIR.
+    fn test_bitstring_for_bfr_id() {
+        let mut bier_state: BierState = serde_json::from_str(get_dummy_config_json()).unwrap();
+        bier_state.compile().unwrap();
+        let bitstring = bier_state.bitstring_for_bfr_id(1, 4).unwrap();
+        assert_eq!(bitstring, Bitstring::from_str("1000").unwrap());
+        // The copy follows the BIFT toward this BFR only.
+        let copies = bier_state.process_bier(&bitstring, 1, 0).unwrap();
+        assert_eq!(copies.len(), 1);
+        assert_eq!(copies[0].0, bitstring);
+
+        assert_eq!(bier_state.bitstring_for_bfr_id(2, 4), Err(Error::BiftId(2)));
+        assert_eq!(bier_state.bitstring_for_bfr_id(1, 0), Err(Error::NoEntry));
+        assert_eq!(bier_state.bitstring_for_bfr_id(1, 42), Err(Error::NoEntry));
+
+        // The BIFTs and their entries are found by id, wherever they are, compiled or not.
+        bier_state.bifts.insert(0, bier_state.bifts[0].clone());
+        bier_state.bifts[0].bift_id = 9;
+        bier_state.bifts[1].bift_id = 3;
+        bier_state.bifts[1].entries.reverse();
+        for compiled in [false, true] {
+            if compiled {
+                bier_state.compile().unwrap();
+            } else {
+                bier_state.compiled = None;
+            }
+            assert_eq!(bier_state.bitstring_for_bfr_id(3, 4), Ok(bitstring.clone()));
+            assert_eq!(bier_state.bitstring_for_bfr_id(1, 4), Err(Error::BiftId(1)));
+            assert_eq!(bier_state.highest_bfr_id(3), Some(5));
+            assert_eq!(bier_state.highest_bfr_id(1), None);
+            assert_eq!(bier_state.entry(3, 2).unwrap().bit, 2);
+            assert!(bier_state.entry(3, 6).is_none());
+        }
+    }
+
     #[test]
     /// Tests the BIER processing of a bitstring using the dummy BIFT.
     fn test_bier_processing_2() {
         let txt = get_dummy_config_json();
-        let bier_state: BierState = serde_json::from_str(txt).unwrap();
+        let mut bier_state: BierState = serde_json::from_str(txt).unwrap();
+        bier_state.compile().unwrap();
 
         let bitstring = Bitstring::from_str("11000");
         assert!(bitstring.is_ok());
         let bitstring = bitstring.unwrap();
         // TODO: test also with invalid bitstring length (e.g., longer).
 
-        let outputs = bier_state.process_bier(&bitstring, 1);
+        let outputs = bier_state.process_bier(&bitstring, 1, 0);
         assert!(outputs.is_ok());
         let outputs = outputs.unwrap();
 
@@ -498,6 +2579,46 @@ mod tests {
         assert!(res);
     }
 
+    #[test]
+    /// Tests the BFR-ids of the bits of the words after the last one, once the bits of the
+    /// last word are all replicated, and of the bits left in the bitstring by an F-BM
+    /// without the bit of its entry.
+    fn test_bier_processing_multi_word() {
+        let bit = |bfr_id: u64| {
+            let mut bitstring = Bitstring {
+                bitstring: vec![0; 2],
+            };
+            bitstring.set(bfr_id).unwrap();
+            bitstring
+        };
+        let entries = [(1, bit(1), "fc00::a"), (2, bit(2), "fc00:2::1")]
+            .into_iter()
+            .chain([(66, bit(66), "fc00:66::1"), (70, bit(66), "fc00:70::1")])
+            .map(|(bfr_id, fbm, next_hop)| {
+                let path = BierEntryPath::new(fbm, next_hop.parse().unwrap());
+                BiftEntry::new(bfr_id, vec![path])
+            })
+            .collect();
+        let mut bier_state = BierState::new("fc00::a".parse().unwrap());
+        bier_state.add_bift(Bift::new(1, 1, entries)).unwrap();
+        bier_state.compile().unwrap();
+
+        let mut bitstring = bit(1);
+        bitstring.set(66).unwrap();
+        bitstring.set(70).unwrap();
+        let copies = bier_state.process_bier(&bitstring, 1, 0).unwrap();
+        let next_hop = |next_hop: &str| Some(next_hop.parse().unwrap());
+        assert_eq!(
+            copies,
+            vec![
+                (bit(1), None),
+                (bit(66), next_hop("fc00:66::1")),
+                // The F-BM of 70 does not hold its bit: the copy is empty.
+                (Bitstring { bitstring: vec![0; 2] }, next_hop("fc00:70::1")),
+            ]
+        );
+    }
+
     #[test]
     /// Tests that the update_header_from_self() method of the Bitstring struct
     /// correctly encodes a new bitstring in a packet slice.
@@ -519,64 +2640,12 @@ mod tests {
         // The remaining of the header is the same.
         let expected = crate::header::tests::get_dummy_bier_header_slice();
         assert_eq!(expected[..12], header[..12]);
-    }
-
-    #[test]
-    /// Tests the function returning if a bitstring given as input is valid
-    /// following RFC 8279.
-    fn test_bitstring_is_valid() {
-        for i in 0..6 {
-            let bitstring = vec![0u8; 8 << i];
-            assert!(Bitstring::is_valid(&bitstring[..]));
-            assert!(!Bitstring::is_valid(&bitstring[1..]));
-        }
-    }
-
-    #[test]
-    /// Tests the parsing of bitstring from &[u8].
-    fn test_bitstring_from_slice_u8_simple() {
-        let raw = [0u8, 0, 0, 0, 0, 0, 0, 1];
-        let res: Result<Bitstring> = raw.as_ref().try_into();
-        assert!(res.is_ok());
-        let res = res.unwrap();
-        assert_eq!(res.bitstring.len(), 1);
-        assert_eq!(res.bitstring[0], 1);
-
-        let raw = [0u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff];
-        let res: Result<Bitstring> = raw.as_ref().try_into();
-        assert!(res.is_ok());
-        let res = res.unwrap();
-        assert_eq!(res.bitstring.len(), 2);
-        assert_eq!(res.bitstring[0], 0);
-        assert_eq!(res.bitstring[1], 0xffff);
-
-        // Wrong bitstring.
-        let raw = [0u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff];
-        let res: Result<Bitstring> = raw.as_ref().try_into();
-        assert!(res.is_err());
-
-        let raw = [0u8, 0, 0, 0, 0xff, 0xff];
-        let res: Result<Bitstring> = raw.as_ref().try_into();
-        assert!(res.is_err());
 
-        let raw: [u8; 0] = [];
-        let res: Result<Bitstring> = raw.as_ref().try_into();
-        assert!(res.is_err());
-    }
-
-    #[test]
-    /// Tests the conversion of a Bitstring to a Vec<u8> method.
-    fn test_vec_u8_from_bitstring() {
-        let raw = [0u8, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
-        let res: Result<Bitstring> = raw.as_ref().try_into();
-        assert!(res.is_ok());
-        let res = res.unwrap();
-        assert_eq!(res.bitstring.len(), 2);
-
-        // Convert to an array of u8.
-        let res_u8: Vec<u8> = (&res).into();
-        assert_eq!(res_u8.len(), 16);
-        assert_eq!(res_u8, raw);
+        // The header is too short for the bitstring.
+        assert_eq!(
+            bitstring.update_header_from_self(&mut header[..16]),
+            Err(Error::BitstringLength)
+        );
     }
 
     #[test]
@@ -601,107 +2670,57 @@ mod tests {
     }
 
     #[test]
-    /// Tests the bitstring from Vec<u64>.
-    fn test_bitstring_from_vec_u64() {
-        let valid_bitstring_length_u64 = [1, 2, 4, 8, 16, 32, 64];
-
-        for i in 0..100 {
-            let v: Vec<_> = (0..i).collect();
-            let bitstring = v.clone().try_into();
-            if valid_bitstring_length_u64.contains(&i) {
-                assert!(bitstring.is_ok());
-                let bitstring: Bitstring = bitstring.unwrap();
-                assert_eq!(bitstring.bitstring, v);
-            } else {
-                assert!(bitstring.is_err());
-                assert_eq!(bitstring.unwrap_err(), crate::Error::BitstringLength);
-            }
-        }
+    /// Tests the bits above a BFR-id, in a multi-word bitstring.
+    fn test_bitstring_clear_above() {
+        // BFR-ids 1, 3, 64, 65 and 70.
+        let mut bitstring = Bitstring::from_str(&format!("1000011{}101", "0".repeat(60))).unwrap();
+        assert_eq!(bitstring.bitstring, vec![0b100001, (1 << 63) | 0b101]);
+        assert_eq!(bitstring.count_above(70), 0);
+        assert_eq!(bitstring.count_above(64), 2);
+        assert_eq!(bitstring.count_above(2), 4);
+        assert_eq!(bitstring.count_above(0), 5);
+
+        bitstring.clear_above(64);
+        assert_eq!(bitstring.bitstring, vec![0, (1 << 63) | 0b101]);
+        bitstring.clear_above(2);
+        assert_eq!(bitstring.bitstring, vec![0, 0b1]);
+
+        let bier_state: BierState = serde_json::from_str(get_dummy_config_json()).unwrap();
+        assert_eq!(bier_state.unknown_bits, UnknownBitsPolicy::Drop);
+        let nb_entries = bier_state.bifts[0].entries.len() as u64;
+        assert_eq!(bier_state.highest_bfr_id(1), Some(nb_entries));
+        assert_eq!(bier_state.highest_bfr_id(0), None);
+        assert_eq!(bier_state.highest_bfr_id(42), None);
     }
 
     #[test]
-    /// Tests the bitstring from &[u8].
-    fn test_bitstring_from_slice_u8() {
-        let valid_bitstring_length_u64: Vec<_> = [1, 2, 4, 8, 16, 32, 64].iter().map(|i| i * 8).collect();
-
-        for i in 0..1000 {
-            let v: Vec<u8> = (0..i).map(|i| (i % 255) as u8).collect();
-            let bitstring: Result<Bitstring> = (&v[..]).try_into();
-            if valid_bitstring_length_u64.contains(&i) {
-                assert!(bitstring.is_ok());
-            } else {
-                println!("This is i={}", i);
-                assert!(bitstring.is_err());
-                assert_eq!(bitstring.unwrap_err(), crate::Error::BitstringLength);
-            }
+    /// Tests the validation of the socket options of the configuration.
+    fn test_socket_config() {
+        let bier_state: BierState = serde_json::from_str(get_dummy_config_json()).unwrap();
+        assert_eq!(bier_state.socket, SocketConfig::default());
+        assert_eq!(bier_state.socket.proto(), DEFAULT_IP_PROTO);
+        assert!(bier_state.socket.validate().is_ok());
+
+        let txt = r#"{"proto": 254, "recv_buffer": 4194304, "hop_limit": 1, "interface": "eth0"}"#;
+        let mut config: SocketConfig = serde_json::from_str(txt).unwrap();
+        assert_eq!(config.proto(), 254);
+        assert!(config.validate().is_ok());
+
+        // UDP and the IP-in-IP, IPv6-in-IP and GRE tunnels of the kernel.
+        for proto in [17, 4, 41, 47] {
+            config.proto = Some(proto);
+            assert!(config.validate().is_err());
+        }
+        config.proto = None;
+        config.recv_buffer = Some(16);
+        assert!(config.validate().is_err());
+        config.recv_buffer = None;
+        config.hop_limit = Some(0);
+        assert!(config.validate().is_err());
+        config.hop_limit = None;
+        for interface in ["", "a-very-long-name0", "../eth0"] {
+            config.interface = Some(interface.to_string());
+            assert!(config.validate().is_err());
         }
-    }
-
-    #[test]
-    /// Tests the bitstring from str (FromStr trait).
-    /// For this test, every bitstring (up to 4098 characters) should be accepted
-    /// if they contain only 0's and 1's.
-    fn test_bitstring_from_str() {
-        let s = "1";
-        let bitstring = Bitstring::from_str(s);
-        assert!(bitstring.is_ok());
-        let bitstring = bitstring.unwrap();
-        assert_eq!(bitstring.bitstring, vec![1]);
-
-        let s = "1".repeat(64);
-        let bitstring = Bitstring::from_str(&s);
-        assert!(bitstring.is_ok());
-        let bitstring = bitstring.unwrap();
-        assert_eq!(bitstring.bitstring, vec![0xffffffffffffffff]);
-
-        let s = "1".repeat(65);
-        let bitstring = Bitstring::from_str(&s);
-        assert!(bitstring.is_ok());
-        let bitstring = bitstring.unwrap();
-        assert_eq!(bitstring.bitstring, vec![0xffffffffffffffff, 1]);
-        assert_eq!(bitstring.bitstring[0], 0xffffffffffffffff);
-        assert_eq!(bitstring.bitstring[1], 1);
-
-        let s = "1".repeat(128);
-        let bitstring = Bitstring::from_str(&s);
-        assert!(bitstring.is_ok());
-        let bitstring = bitstring.unwrap();
-        assert_eq!(bitstring.bitstring, vec![0xffffffffffffffff, 0xffffffffffffffff]);
-        assert_eq!(bitstring.bitstring[0], 0xffffffffffffffff);
-        assert_eq!(bitstring.bitstring[1], 0xffffffffffffffff);
-
-        let s = "1".repeat(4096);
-        let bitstring = Bitstring::from_str(&s);
-        assert!(bitstring.is_ok());
-        let bitstring = bitstring.unwrap();
-        assert_eq!(bitstring.bitstring, vec![0xffffffffffffffff; 64]);
-
-        let s = "1".repeat(4097);
-        let bitstring = Bitstring::from_str(&s);
-        assert!(bitstring.is_err());
-
-        let s = "1".repeat(6000);
-        let bitstring = Bitstring::from_str(&s);
-        assert!(bitstring.is_err());
-    }
-
-    #[test]
-    /// Tests the bitstring from str (FromStr trait).
-    /// For this test, every bitstring (up to 4098 characters) should be accepted
-    /// if they contain only 0's and 1's.
-    fn test_bitstring_from_str_value() {
-        let s = "1010001011";
-        let bitstring = Bitstring::from_str(s);
-        assert!(bitstring.is_ok());
-        let bitstring = bitstring.unwrap();
-        assert_eq!(bitstring.bitstring, vec![0b1010001011]);
-
-        let s = "1010001012";
-        let bitstring = Bitstring::from_str(s);
-        assert!(bitstring.is_err());
-
-        let s = "1010f01011";
-        let bitstring = Bitstring::from_str(s);
-        assert!(bitstring.is_err());
     }
 }
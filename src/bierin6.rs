@@ -0,0 +1,124 @@
+//! BIERin6 encapsulation: the BIER header is carried in an option of an IPv6 Destination
+//! Options extension header, instead of a bare IP payload with proto 253, so that the
+//! BIER packets cross IPv6 networks as regular IPv6 packets, as in the BIERin6 and BIERv6
+//! drafts of the IETF.
+//!
+//! The Destination Options header holds the BIER option only, whose data is the BIER
+//! header of RFC 8296, and is followed by the BIER payload. Its next header is 59 (No Next
+//! Header): the payload is passed unchanged by the IPv6 routers, and its type is given by
+//! the proto of the BIER header. The type of the option is not assigned by the IANA yet: it
+//! is the experimental type 0x1e (RFC 4727) by default, and can be changed to interoperate
+//! with other implementations (`BierState::bierin6_option`).
+
+use crate::header::BierHeader;
+use crate::{Error, Result};
+use std::io;
+
+/// Next header of the IPv6 Destination Options extension header.
+pub const IPPROTO_DSTOPTS: u8 = 60;
+
+/// Next header value without upper-layer header.
+const NO_NEXT_HEADER: u8 = 59;
+
+/// Default type of the BIER option: experimental, skipped by the nodes not recognizing it.
+pub const DEFAULT_OPTION_TYPE: u8 = 0x1e;
+
+const PAD1: u8 = 0;
+
+/// Length of the next header and header length fields, and of the type and length fields of
+/// an option.
+const FIELDS_LENGTH: usize = 2;
+
+/// Raw IPv6 socket receiving and sending the Destination Options headers, i.e., the BIERin6
+/// packets. The IPv6 header is built by the kernel.
+pub fn bierin6_socket() -> io::Result<socket2::Socket> {
+    socket2::Socket::new(
+        socket2::Domain::IPV6,
+        socket2::Type::RAW,
+        Some(socket2::Protocol::from(IPPROTO_DSTOPTS as i32)),
+    )
+}
+
+/// BIERin6 payload of the BIER `packet`: the Destination Options header holding its BIER
+/// header, followed by its payload. The BIER header must fit in an option, i.e., in 255 bytes,
+/// thus the BSL is at most 1024 bits.
+pub fn encapsulate(packet: &[u8], option_type: u8) -> Result<Vec<u8>> {
+    let header_length = BierHeader::from_slice(packet)?.header_length();
+    let option_length = u8::try_from(header_length).map_err(|_| Error::BitstringLength)?;
+    // The BIER headers have a length of 4 modulo 8: the extension header needs no padding.
+    let extension_length = 2 * FIELDS_LENGTH + header_length;
+    let mut copy = Vec::with_capacity(packet.len() + 2 * FIELDS_LENGTH);
+    copy.extend([NO_NEXT_HEADER, (extension_length / 8 - 1) as u8]);
+    copy.extend([option_type, option_length]);
+    copy.extend_from_slice(packet);
+    Ok(copy)
+}
+
+/// BIER packet of the BIERin6 payload `packet`, starting with the Destination Options header,
+/// i.e., the BIER header of the BIER option followed by the payload. The BIER header is moved
+/// in place before the payload.
+pub fn decapsulate(packet: &mut [u8], option_type: u8) -> Result<&mut [u8]> {
+    let extension_length = match packet.get(1) {
+        Some(length) => (*length as usize + 1) * 8,
+        None => return Err(Error::Header),
+    };
+    if packet.len() < extension_length {
+        return Err(Error::Header);
+    }
+    let mut offset = FIELDS_LENGTH;
+    while offset < extension_length {
+        if packet[offset] == PAD1 {
+            offset += 1;
+            continue;
+        }
+        let length = *packet.get(offset + 1).ok_or(Error::Header)? as usize;
+        let data = offset + FIELDS_LENGTH;
+        if data + length > extension_length {
+            return Err(Error::Header);
+        }
+        if packet[offset] == option_type {
+            let start = extension_length - length;
+            packet.copy_within(data..data + length, start);
+            return Ok(&mut packet[start..]);
+        }
+        offset = data + length;
+    }
+    Err(Error::Header)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::header::tests::get_dummy_bier_header_slice;
+
+    #[test]
+    /// Tests the BIER option of the BIERin6 packets.
+    fn test_bierin6() {
+        let mut packet = get_dummy_bier_header_slice().to_vec();
+        packet.extend([1, 2, 3]);
+        let mut bierin6 = encapsulate(&packet, DEFAULT_OPTION_TYPE).unwrap();
+        assert_eq!(bierin6.len(), packet.len() + 4);
+        assert_eq!(&bierin6[..4], &[NO_NEXT_HEADER, 2, DEFAULT_OPTION_TYPE, 20]);
+        assert_eq!(
+            decapsulate(&mut bierin6.clone(), DEFAULT_OPTION_TYPE).unwrap(),
+            packet
+        );
+        assert_eq!(decapsulate(&mut bierin6, 0x3e), Err(Error::Header));
+
+        // Options of another implementation before the BIER option, with padding.
+        let mut other = vec![NO_NEXT_HEADER, 3, 0x3e, 1, 0, PAD1, PAD1, 1, 0];
+        other.extend([DEFAULT_OPTION_TYPE, 20]);
+        other.extend(&packet);
+        other.insert(31, PAD1);
+        assert_eq!(other.len(), 32 + 3);
+        assert_eq!(
+            decapsulate(&mut other, DEFAULT_OPTION_TYPE).unwrap(),
+            packet
+        );
+        assert_eq!(
+            decapsulate(&mut other[..20], DEFAULT_OPTION_TYPE),
+            Err(Error::Header)
+        );
+    }
+}
@@ -1,42 +1,239 @@
-use bier_rust::bier::{BierEntryPath, BierState, Bift, BiftEntry, Bitstring};
+use bier_rust::bier::{BierEntryPath, BierState, Bift, BiftEntry, Bitstring, BitstringFormat};
+use bier_rust::bier::ConfigIssue;
 use bier_rust::dijkstra::dijkstra;
-use clap::Parser;
-use serde_json::to_writer;
+use bier_rust::snapshot;
+use clap::{Parser, Subcommand};
+use serde_json::{to_writer, Value};
 use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::io::{BufRead, BufReader};
-use std::net::IpAddr;
-use std::str::FromStr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 #[derive(Debug)]
 enum Error {
     /// Impossible to parse the file to crate a topo.
     FileParse,
+
+    /// Impossible to parse an LLDP neighbors dump.
+    LldpParse,
+
+    /// No address family has a loopback for every node of the topology.
+    MissingLoopback,
+
+    /// The BitString Length is invalid.
+    Bsl,
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
+/// Valid BitString Lengths, in bits (RFC 8296).
+const VALID_BSLS: [usize; 7] = [64, 128, 256, 512, 1024, 2048, 4096];
+
 #[derive(Parser)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
     /// Topology NTF-like file.
-    #[clap(short = 'f', long = "topo-file", value_parser)]
-    topo_file: String,
+    #[clap(short = 'f', long = "topo-file", value_parser, required_unless_present = "lldp")]
+    topo_file: Option<String>,
+    /// Build the topology from LLDP neighbors instead of a topology file.
+    /// Each file is the output of `lldpcli -f json show neighbors` on one node,
+    /// and is named after this node (e.g., `r1.json`).
+    #[clap(short = 'l', long = "lldp", value_parser, num_args = 1.., conflicts_with = "topo_file")]
+    lldp: Vec<String>,
     /// Path containing the output files.
-    #[clap(short = 'd', long = "directory", value_parser)]
-    directory: String,
-    /// Mapping between node and IPv6 address.
-    #[clap(short = 'i', long = "node2ipv6", value_parser)]
-    node_to_ipv6: String,
+    #[clap(short = 'd', long = "directory", value_parser, required = true)]
+    directory: Option<String>,
+    /// Mapping between node and loopback address(es).
+    /// Each line contains the node ID followed by an IPv6 and/or an IPv4 address.
+    /// Optional with `--lldp`, where the management addresses are used by default.
+    #[clap(
+        short = 'i',
+        long = "node2ipv6",
+        alias = "node2ip",
+        value_parser,
+        required_unless_present = "lldp"
+    )]
+    node_to_ipv6: Option<String>,
+    /// BitString Length of the generated BIFTs, in bits (64, 128, ..., 4096).
+    /// By default, the smallest BSL covering all nodes of the topology.
+    #[clap(short = 'b', long = "bsl", value_parser)]
+    bsl: Option<usize>,
+    /// Form of the bitstrings in the generated files: `binary` (e.g., "1010"), `hex`
+    /// (e.g., "0x000000000000000a") or `bits` (e.g., {"bits": [2, 4], "bsl": 64}).
+    #[clap(long = "bitstring-format", default_value = "binary")]
+    bitstring_format: BitstringFormat,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Checks existing configurations instead of generating them, and prints all their
+    /// problems. Exits with an error if any configuration is invalid.
+    Validate {
+        #[clap(value_parser, required = true)]
+        files: Vec<String>,
+        /// Expected BitString Length of the BIFTs, in bits.
+        #[clap(short = 'b', long = "bsl", value_parser)]
+        bsl: Option<usize>,
+    },
+    /// Converts a JSON configuration to a binary snapshot, loaded faster by the daemon, or a
+    /// binary snapshot back to JSON.
+    Convert {
+        #[clap(value_parser)]
+        input: String,
+        #[clap(value_parser)]
+        output: String,
+        /// Form of the bitstrings of the JSON configuration converted from a snapshot.
+        #[clap(long = "bitstring-format", default_value = "binary")]
+        bitstring_format: BitstringFormat,
+    },
 }
 
 fn main() {
     env_logger::init();
     let args = Args::parse();
 
-    let graph = Graph::from_file(&args.topo_file, &args.node_to_ipv6).unwrap();
-    let path = std::path::Path::new(&args.topo_file);
-    let filename = path.file_stem().unwrap().to_str().unwrap();
-    graph.get_bier_config(&args.directory, filename).unwrap();
+    if let Some(Command::Validate { files, bsl }) = &args.command {
+        let nb_invalid = files
+            .iter()
+            .filter(|file| !validate(file, *bsl))
+            .count();
+        if nb_invalid > 0 {
+            eprintln!("{} invalid configuration(s)", nb_invalid);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Convert {
+        input,
+        output,
+        bitstring_format,
+    }) = &args.command
+    {
+        if let Err(e) = convert(input, output, *bitstring_format) {
+            eprintln!("Cannot convert {}: {}", input, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let (graph, filename) = match &args.topo_file {
+        Some(topo_file) => {
+            let graph =
+                Graph::from_file(topo_file, args.node_to_ipv6.as_deref().unwrap()).unwrap();
+            let path = std::path::Path::new(topo_file);
+            (graph, path.file_stem().unwrap().to_str().unwrap())
+        }
+        None => (
+            Graph::from_lldp(&args.lldp, args.node_to_ipv6.as_deref()).unwrap(),
+            "lldp",
+        ),
+    };
+    graph
+        .get_bier_config(
+            args.directory.as_deref().unwrap(),
+            filename,
+            args.bsl,
+            args.bitstring_format,
+        )
+        .unwrap();
+}
+
+/// Prints the problems of the configuration `file`, see `BierState::validate`, and the
+/// BIFTs whose BSL is not `bsl`. The parsing errors are given with their line and column.
+/// Returns whether the configuration is valid.
+fn validate(file: &str, bsl: Option<usize>) -> bool {
+    let content = match std::fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("{}: {}", file, e);
+            return false;
+        }
+    };
+    let bier_state: BierState = match serde_json::from_str(&content) {
+        Ok(bier_state) => bier_state,
+        Err(e) => {
+            // The error of serde_json ends with its line and column.
+            eprintln!("{}: {}", file, e);
+            return false;
+        }
+    };
+    let mut issues = bier_state.validate().err().unwrap_or_default();
+    if let Some(bsl) = bsl {
+        for (idx, bift) in bier_state.bifts.iter().enumerate() {
+            if bift.get_bsl() != 0 && bift.get_bsl() != bsl {
+                issues.push(ConfigIssue {
+                    field: format!("bifts[{}]", idx),
+                    message: format!("BSL of {} bits instead of {}", bift.get_bsl(), bsl),
+                });
+            }
+        }
+    }
+    for issue in issues.iter() {
+        eprintln!("{}: {}", file, issue);
+    }
+    if issues.is_empty() {
+        println!("{}: valid", file);
+    }
+    issues.is_empty()
+}
+
+/// Writes the configuration `input` to `output` as a binary snapshot if it is JSON, or as
+/// JSON with the bitstrings in `format` if it is a snapshot.
+fn convert(input: &str, output: &str, format: BitstringFormat) -> std::result::Result<(), String> {
+    let content = std::fs::read(input).map_err(|e| e.to_string())?;
+    let converted = if snapshot::is_snapshot(&content) {
+        let bier_state = BierState::from_snapshot(&content).map_err(|e| e.to_string())?;
+        let json = bier_state.to_json(format).map_err(|e| e.to_string())?;
+        serde_json::to_vec_pretty(&json).map_err(|e| e.to_string())?
+    } else {
+        let bier_state: BierState = serde_json::from_slice(&content).map_err(|e| e.to_string())?;
+        bier_state.to_snapshot().map_err(|e| e.to_string())?
+    };
+    std::fs::write(output, converted).map_err(|e| e.to_string())
+}
+
+/// Address family of a generated configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Family {
+    Ipv6,
+    Ipv4,
+}
+
+/// Loopback addresses of a node. At least one of them is set.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Loopbacks {
+    v6: Option<Ipv6Addr>,
+    v4: Option<Ipv4Addr>,
+}
+
+impl Loopbacks {
+    /// Parses a line of the node to IP mapping file, e.g.,
+    /// `0 babe:cafe::1/64 10.0.0.1/24`. The address order does not matter.
+    fn from_line(line: &str) -> Option<Self> {
+        let mut loopbacks = Loopbacks::default();
+        for addr in line.split_whitespace().skip(1) {
+            loopbacks.add(addr.split('/').next()?.parse().ok()?);
+        }
+
+        (loopbacks.v6.is_some() || loopbacks.v4.is_some()).then_some(loopbacks)
+    }
+
+    /// Sets the loopback of the family of `addr`, keeping the first one.
+    fn add(&mut self, addr: IpAddr) {
+        match addr {
+            IpAddr::V6(v6) => self.v6 = self.v6.or(Some(v6)),
+            IpAddr::V4(v4) => self.v4 = self.v4.or(Some(v4)),
+        }
+    }
+
+    fn get(&self, family: Family) -> Option<IpAddr> {
+        match family {
+            Family::Ipv6 => self.v6.map(IpAddr::V6),
+            Family::Ipv4 => self.v4.map(IpAddr::V4),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -44,7 +241,7 @@ struct Node {
     id: usize, // Used as bitstring ID.
     name: String,
     neighbours: Vec<(usize, i32)>, // (id, cost)
-    loopback: IpAddr,
+    loopbacks: Loopbacks,
 }
 
 struct Graph {
@@ -54,22 +251,7 @@ struct Graph {
 impl Graph {
     fn from_file(file_path: &str, node_to_ipv6_file: &str) -> Result<Self> {
         let file = std::fs::File::open(file_path).map_err(|_| Error::FileParse)?;
-        let node_to_ipv6 = std::fs::File::open(node_to_ipv6_file).map_err(|_| Error::FileParse)?;
-
-        // Form a vector of the mapping, assuming that we have 0 first, then 1, then ...
-        let reader = BufReader::new(node_to_ipv6);
-        let node_to_ipv6: Vec<_> = reader
-            .lines()
-            .map(|line| {
-                let line = line.unwrap();
-                let line = line.trim().trim_end();
-                let ip_str = line.split(' ').nth(1)?.split('/').next()?;
-                Some(IpAddr::V6(ip_str.parse().ok()?))
-            })
-            .into_iter()
-            .filter(|value| value.is_some())
-            .collect::<Option<Vec<_>>>()
-            .unwrap();
+        let node_to_ipv6 = read_node_to_ip(node_to_ipv6_file)?;
 
         let mut nodes = Vec::new(); // We do not know the size at first.
         let reader = BufReader::new(file);
@@ -90,7 +272,7 @@ impl Graph {
                     name: split[0].to_string(),
                     neighbours: Vec::new(),
                     id: a_id,
-                    loopback: node_to_ipv6[a_id],
+                    loopbacks: node_to_ipv6[a_id],
                 };
                 nodes.push(node);
             }
@@ -102,7 +284,7 @@ impl Graph {
                     name: split[1].to_string(),
                     neighbours: Vec::new(),
                     id: b_id,
-                    loopback: node_to_ipv6[b_id],
+                    loopbacks: node_to_ipv6[b_id],
                 };
                 nodes.push(node);
             }
@@ -118,6 +300,59 @@ impl Graph {
         Ok(Graph { nodes })
     }
 
+    /// Builds the graph from the LLDP neighbors of each node. All links have a cost of 1.
+    /// The loopbacks come from `node_to_ip_file` if any, otherwise from the management
+    /// addresses advertised in LLDP.
+    fn from_lldp(lldp_files: &[String], node_to_ip_file: Option<&str>) -> Result<Self> {
+        let node_to_ip = node_to_ip_file.map(read_node_to_ip).transpose()?;
+
+        let mut nodes: Vec<Node> = Vec::new();
+        let mut node2id = HashMap::new();
+        let mut get_id = |name: &str, nodes: &mut Vec<Node>| -> usize {
+            *node2id.entry(name.to_string()).or_insert_with(|| {
+                nodes.push(Node {
+                    id: nodes.len(),
+                    name: name.to_string(),
+                    neighbours: Vec::new(),
+                    loopbacks: Loopbacks::default(),
+                });
+                nodes.len() - 1
+            })
+        };
+
+        for lldp_file in lldp_files {
+            let path = std::path::Path::new(lldp_file);
+            let local_name = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .ok_or(Error::FileParse)?;
+            let file = std::fs::File::open(path).map_err(|_| Error::FileParse)?;
+            let json: Value = serde_json::from_reader(file).map_err(|_| Error::LldpParse)?;
+
+            let a_id = get_id(local_name, &mut nodes);
+            for (name, mgmt_ips) in get_lldp_neighbours(&json)? {
+                let b_id = get_id(&name, &mut nodes);
+                for addr in mgmt_ips {
+                    nodes[b_id].loopbacks.add(addr);
+                }
+
+                // Both ends of a link advertise it.
+                if a_id != b_id && !nodes[a_id].neighbours.iter().any(|&(id, _)| id == b_id) {
+                    nodes[a_id].neighbours.push((b_id, 1));
+                    nodes[b_id].neighbours.push((a_id, 1));
+                }
+            }
+        }
+
+        if let Some(node_to_ip) = node_to_ip {
+            for node in nodes.iter_mut() {
+                node.loopbacks = *node_to_ip.get(node.id).ok_or(Error::MissingLoopback)?;
+            }
+        }
+
+        Ok(Graph { nodes })
+    }
+
     fn graph_node_to_usize(&self) -> Vec<Vec<(usize, i32)>> {
         self.nodes
             .iter()
@@ -125,10 +360,50 @@ impl Graph {
             .collect()
     }
 
-    fn get_bier_config(&self, directory: &str, filename_root: &str) -> Result<()> {
+    /// Address families for which every node has a loopback, IPv6 first.
+    fn get_families(&self) -> Vec<Family> {
+        [Family::Ipv6, Family::Ipv4]
+            .into_iter()
+            .filter(|&family| {
+                self.nodes
+                    .iter()
+                    .all(|node| node.loopbacks.get(family).is_some())
+            })
+            .collect()
+    }
+
+    /// Returns the BSL to use for this topology, in bits.
+    /// The requested `bsl` must be valid. By default, the smallest BSL with one bit per node,
+    /// or the largest BSL if the nodes are spread over several set identifiers (SIs).
+    fn get_bsl(&self, bsl: Option<usize>) -> Result<usize> {
+        let nb_nodes = self.nodes.len();
+        match bsl {
+            Some(bsl) if VALID_BSLS.contains(&bsl) => Ok(bsl),
+            Some(_) => Err(Error::Bsl),
+            None => Ok(VALID_BSLS
+                .into_iter()
+                .find(|&bsl| bsl >= nb_nodes)
+                .unwrap_or(VALID_BSLS[VALID_BSLS.len() - 1])),
+        }
+    }
+
+    /// Writes one configuration per node and per address family in `directory`.
+    /// If the topology is dual-stack, the IPv4 configurations get an `-ipv4` suffix.
+    fn get_bier_config(
+        &self,
+        directory: &str,
+        filename_root: &str,
+        bsl: Option<usize>,
+        format: BitstringFormat,
+    ) -> Result<()> {
         let nodes = &self.nodes;
         let nb_nodes = nodes.len();
         let graph_id = self.graph_node_to_usize();
+        let families = self.get_families();
+        if families.is_empty() {
+            return Err(Error::MissingLoopback);
+        }
+        let bsl = self.get_bsl(bsl)?;
 
         for node in 0..nb_nodes {
             // Predecessor(s) for each node, alongside the shortest path(s) from `node`
@@ -139,54 +414,146 @@ impl Graph {
                 .map(|i| get_all_out_interfaces_to_destination(&predecessors, node, i))
                 .collect();
 
-            let mut bift = Bift {
-                bift_id: 1,
-                bift_type: bier_rust::bier::BiftType::Bier,
-                bfr_id: node as u64 + 1,
-                entries: Vec::new(),
-            };
-
-            for bfr_id in 0..nb_nodes {
-                let mut entry = BiftEntry {
-                    bit: bfr_id as u64 + 1,
-                    paths: Vec::new(),
-                };
-                for &the_next_hop in &next_hop[bfr_id] {
-                    let s = next_hop.iter().rev().fold(String::new(), |mut fbm, nh| {
-                        if nh.contains(&the_next_hop) {
-                            fbm.push('1');
-                            fbm
-                        } else {
-                            if !fbm.is_empty() {
-                                fbm.push('0');
+            for &family in &families {
+                // One BIFT per set identifier if the nodes do not fit in the BSL.
+                let bifts = (0..nb_nodes.div_ceil(bsl))
+                    .map(|si| {
+                        let mut bift = Bift {
+                            bift_id: si + 1,
+                            bift_type: bier_rust::bier::BiftType::Bier,
+                            sub_domain: 0,
+                            si: si as u8,
+                            bfr_id: node as u64 + 1,
+                            entries: Vec::new(),
+                        };
+                        for bfr_id in si * bsl..nb_nodes.min((si + 1) * bsl) {
+                            let mut entry = BiftEntry {
+                                bit: bfr_id as u64 + 1,
+                                paths: Vec::new(),
+                                admin_state: Default::default(),
+                            };
+                            for &the_next_hop in &next_hop[bfr_id] {
+                                entry.paths.push(BierEntryPath {
+                                    bitstring: get_forwarding_bitmask(
+                                        &next_hop,
+                                        the_next_hop,
+                                        bsl,
+                                        si,
+                                    ),
+                                    next_hop: nodes[the_next_hop].loopbacks.get(family).unwrap(),
+                                    admin_state: Default::default(),
+                                });
                             }
-                            fbm
+                            bift.entries.push(entry);
                         }
-                    });
-                    let bitstring: Bitstring = FromStr::from_str(&s).unwrap();
-                    entry.paths.push(BierEntryPath {
-                        bitstring,
-                        next_hop: nodes[the_next_hop].loopback,
-                    });
-                }
-                bift.entries.push(entry);
-            }
+                        bift
+                    })
+                    .collect();
 
-            let bier_state = BierState {
-                loopback: nodes[node].loopback,
-                bifts: vec![bift],
-            };
+                let bier_state = BierState {
+                    bifts,
+                    bfr_names: nodes
+                        .iter()
+                        .map(|node| (node.name.clone(), node.id as u64 + 1))
+                        .collect(),
+                    ..BierState::new(nodes[node].loopbacks.get(family).unwrap())
+                };
 
-            let pathname = format!("{}-{}.json", filename_root, node);
-            let path = std::path::Path::new(directory).join(&pathname);
-            let file = std::fs::File::create(&path).unwrap();
-            to_writer(file, &bier_state).unwrap();
+                let pathname = match family {
+                    Family::Ipv4 if families.len() > 1 => {
+                        format!("{}-{}-ipv4.json", filename_root, node)
+                    }
+                    _ => format!("{}-{}.json", filename_root, node),
+                };
+                let path = std::path::Path::new(directory).join(&pathname);
+                let file = std::fs::File::create(&path).unwrap();
+                to_writer(file, &bier_state.to_json(format).unwrap()).unwrap();
+            }
         }
 
         Ok(())
     }
 }
 
+/// Reads the mapping between node IDs and loopbacks, assuming that we have 0 first, then 1, then ...
+fn read_node_to_ip(node_to_ip_file: &str) -> Result<Vec<Loopbacks>> {
+    let file = std::fs::File::open(node_to_ip_file).map_err(|_| Error::FileParse)?;
+    Ok(BufReader::new(file)
+        .lines()
+        .map_while(|line| line.ok())
+        .filter_map(|line| Loopbacks::from_line(&line))
+        .collect())
+}
+
+/// Returns the (name, management addresses) of each neighbour of an
+/// `lldpcli -f json show neighbors` dump.
+/// lldpd outputs an object if there is a single interface or chassis, and an array otherwise.
+fn get_lldp_neighbours(json: &Value) -> Result<Vec<(String, Vec<IpAddr>)>> {
+    // Flattens `{"a": x, "b": y}` and `[{"a": x}, {"b": y}]` into `[("a", x), ("b", y)]`.
+    fn entries(value: &Value) -> Vec<(&String, &Value)> {
+        match value {
+            Value::Object(map) => map.iter().collect(),
+            Value::Array(array) => array.iter().flat_map(entries).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    let interfaces = match json.pointer("/lldp/interface") {
+        Some(interfaces) => interfaces,
+        // No neighbour at all.
+        None if json.pointer("/lldp").is_some() => return Ok(Vec::new()),
+        None => return Err(Error::LldpParse),
+    };
+
+    let mut neighbours = Vec::new();
+    for (_, interface) in entries(interfaces) {
+        let chassis = interface.get("chassis").ok_or(Error::LldpParse)?;
+        // Without system name, the chassis is directly the content and we use its ID.
+        let chassis = if chassis.get("id").is_some() {
+            let id = chassis.pointer("/id/value").and_then(Value::as_str);
+            vec![(id.ok_or(Error::LldpParse)?.to_string(), chassis)]
+        } else {
+            entries(chassis)
+                .into_iter()
+                .map(|(name, chassis)| (name.to_string(), chassis))
+                .collect()
+        };
+        for (name, chassis) in chassis {
+            let mgmt_ips = match chassis.get("mgmt-ip") {
+                Some(Value::String(ip)) => vec![ip.as_str()],
+                Some(Value::Array(ips)) => ips.iter().filter_map(Value::as_str).collect(),
+                _ => Vec::new(),
+            };
+            let mgmt_ips = mgmt_ips
+                .iter()
+                .filter_map(|ip| ip.parse().ok())
+                .collect();
+            neighbours.push((name, mgmt_ips));
+        }
+    }
+    Ok(neighbours)
+}
+
+/// Returns the forwarding bitmask of `the_next_hop` on `bsl` bits for the SI `si`, i.e.,
+/// the bitstring of all BFRs of this SI reached through this next hop.
+fn get_forwarding_bitmask(
+    next_hop: &[Vec<usize>],
+    the_next_hop: usize,
+    bsl: usize,
+    si: usize,
+) -> Bitstring {
+    let nb_words = bsl / 64;
+    let mut words = vec![0u64; nb_words];
+    let set = next_hop.iter().enumerate().skip(si * bsl).take(bsl);
+    for (bfr_idx, nhs) in set.map(|(bfr_idx, nhs)| (bfr_idx - si * bsl, nhs)) {
+        if nhs.contains(&the_next_hop) {
+            // The first word holds the most significant bits.
+            words[nb_words - 1 - bfr_idx / 64] |= 1 << (bfr_idx % 64);
+        }
+    }
+    words.try_into().unwrap()
+}
+
 fn get_all_out_interfaces_to_destination(
     predecessors: &HashMap<&usize, Vec<&usize>>,
     source: usize,
@@ -231,12 +598,15 @@ mod tests {
     use std::path::Path;
 
     const TEST_DIRECTORY: &str = "test_configs";
+    const TEST_DIRECTORY_DUAL_STACK: &str = "test_configs_dual_stack";
+    const TEST_DIRECTORY_LARGE: &str = "test_configs_large";
+    const TEST_DIRECTORY_LLDP: &str = "test_configs_lldp";
     const EXPECTED_CONFIGURATIONS: [&str; 5] = [
-        r#"{"loopback":"babe:cafe::1","bifts":[{"bift_id":1,"bift_type":1,"bfr_id":1,"entries":[{"bit":1,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000001","next_hop":"babe:cafe::1"}]},{"bit":2,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000011010","next_hop":"babe:cafe:1::1"}]},{"bit":3,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000011100","next_hop":"babe:cafe:2::1"}]},{"bit":4,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000011010","next_hop":"babe:cafe:1::1"},{"bitstring":"0000000000000000000000000000000000000000000000000000000000011100","next_hop":"babe:cafe:2::1"}]},{"bit":5,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000011010","next_hop":"babe:cafe:1::1"},{"bitstring":"0000000000000000000000000000000000000000000000000000000000011100","next_hop":"babe:cafe:2::1"}]}]}]}"#,
-        r#"{"loopback":"babe:cafe:1::1","bifts":[{"bift_id":1,"bift_type":1,"bfr_id":2,"entries":[{"bit":1,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000101","next_hop":"babe:cafe::1"}]},{"bit":2,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000010","next_hop":"babe:cafe:1::1"}]},{"bit":3,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000101","next_hop":"babe:cafe::1"},{"bitstring":"0000000000000000000000000000000000000000000000000000000000011100","next_hop":"babe:cafe:3::1"}]},{"bit":4,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000011100","next_hop":"babe:cafe:3::1"}]},{"bit":5,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000011100","next_hop":"babe:cafe:3::1"}]}]}]}"#,
-        r#"{"loopback":"babe:cafe:2::1","bifts":[{"bift_id":1,"bift_type":1,"bfr_id":3,"entries":[{"bit":1,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000011","next_hop":"babe:cafe::1"}]},{"bit":2,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000011","next_hop":"babe:cafe::1"},{"bitstring":"0000000000000000000000000000000000000000000000000000000000011010","next_hop":"babe:cafe:3::1"}]},{"bit":3,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000100","next_hop":"babe:cafe:2::1"}]},{"bit":4,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000011010","next_hop":"babe:cafe:3::1"}]},{"bit":5,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000011010","next_hop":"babe:cafe:3::1"}]}]}]}"#,
-        r#"{"loopback":"babe:cafe:3::1","bifts":[{"bift_id":1,"bift_type":1,"bfr_id":4,"entries":[{"bit":1,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000011","next_hop":"babe:cafe:1::1"},{"bitstring":"0000000000000000000000000000000000000000000000000000000000000101","next_hop":"babe:cafe:2::1"}]},{"bit":2,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000011","next_hop":"babe:cafe:1::1"}]},{"bit":3,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000101","next_hop":"babe:cafe:2::1"}]},{"bit":4,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000001000","next_hop":"babe:cafe:3::1"}]},{"bit":5,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000010000","next_hop":"babe:cafe:4::1"}]}]}]}"#,
-        r#"{"loopback":"babe:cafe:4::1","bifts":[{"bift_id":1,"bift_type":1,"bfr_id":5,"entries":[{"bit":1,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000001111","next_hop":"babe:cafe:3::1"}]},{"bit":2,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000001111","next_hop":"babe:cafe:3::1"}]},{"bit":3,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000001111","next_hop":"babe:cafe:3::1"}]},{"bit":4,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000001111","next_hop":"babe:cafe:3::1"}]},{"bit":5,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000010000","next_hop":"babe:cafe:4::1"}]}]}]}"#,
+        r#"{"loopback":"babe:cafe::1","bifts":[{"bift_id":1,"bift_type":1,"sub_domain":0,"bfr_id":1,"entries":[{"bit":1,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000001","next_hop":"babe:cafe::1"}]},{"bit":2,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000011010","next_hop":"babe:cafe:1::1"}]},{"bit":3,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000011100","next_hop":"babe:cafe:2::1"}]},{"bit":4,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000011010","next_hop":"babe:cafe:1::1"},{"bitstring":"0000000000000000000000000000000000000000000000000000000000011100","next_hop":"babe:cafe:2::1"}]},{"bit":5,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000011010","next_hop":"babe:cafe:1::1"},{"bitstring":"0000000000000000000000000000000000000000000000000000000000011100","next_hop":"babe:cafe:2::1"}]}]}],"bfr_names":{"a":1,"b":2,"c":3,"d":4,"e":5}}"#,
+        r#"{"loopback":"babe:cafe:1::1","bifts":[{"bift_id":1,"bift_type":1,"sub_domain":0,"bfr_id":2,"entries":[{"bit":1,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000101","next_hop":"babe:cafe::1"}]},{"bit":2,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000010","next_hop":"babe:cafe:1::1"}]},{"bit":3,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000101","next_hop":"babe:cafe::1"},{"bitstring":"0000000000000000000000000000000000000000000000000000000000011100","next_hop":"babe:cafe:3::1"}]},{"bit":4,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000011100","next_hop":"babe:cafe:3::1"}]},{"bit":5,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000011100","next_hop":"babe:cafe:3::1"}]}]}],"bfr_names":{"a":1,"b":2,"c":3,"d":4,"e":5}}"#,
+        r#"{"loopback":"babe:cafe:2::1","bifts":[{"bift_id":1,"bift_type":1,"sub_domain":0,"bfr_id":3,"entries":[{"bit":1,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000011","next_hop":"babe:cafe::1"}]},{"bit":2,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000011","next_hop":"babe:cafe::1"},{"bitstring":"0000000000000000000000000000000000000000000000000000000000011010","next_hop":"babe:cafe:3::1"}]},{"bit":3,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000100","next_hop":"babe:cafe:2::1"}]},{"bit":4,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000011010","next_hop":"babe:cafe:3::1"}]},{"bit":5,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000011010","next_hop":"babe:cafe:3::1"}]}]}],"bfr_names":{"a":1,"b":2,"c":3,"d":4,"e":5}}"#,
+        r#"{"loopback":"babe:cafe:3::1","bifts":[{"bift_id":1,"bift_type":1,"sub_domain":0,"bfr_id":4,"entries":[{"bit":1,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000011","next_hop":"babe:cafe:1::1"},{"bitstring":"0000000000000000000000000000000000000000000000000000000000000101","next_hop":"babe:cafe:2::1"}]},{"bit":2,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000011","next_hop":"babe:cafe:1::1"}]},{"bit":3,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000000101","next_hop":"babe:cafe:2::1"}]},{"bit":4,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000001000","next_hop":"babe:cafe:3::1"}]},{"bit":5,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000010000","next_hop":"babe:cafe:4::1"}]}]}],"bfr_names":{"a":1,"b":2,"c":3,"d":4,"e":5}}"#,
+        r#"{"loopback":"babe:cafe:4::1","bifts":[{"bift_id":1,"bift_type":1,"sub_domain":0,"bfr_id":5,"entries":[{"bit":1,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000001111","next_hop":"babe:cafe:3::1"}]},{"bit":2,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000001111","next_hop":"babe:cafe:3::1"}]},{"bit":3,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000001111","next_hop":"babe:cafe:3::1"}]},{"bit":4,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000001111","next_hop":"babe:cafe:3::1"}]},{"bit":5,"paths":[{"bitstring":"0000000000000000000000000000000000000000000000000000000000010000","next_hop":"babe:cafe:4::1"}]}]}],"bfr_names":{"a":1,"b":2,"c":3,"d":4,"e":5}}"#,
     ];
 
     /// This is an "extended" diamond topology.
@@ -270,6 +640,52 @@ mod tests {
         write!(file, "{}", content)
     }
 
+    fn write_dummy_node_to_ip_dual_stack(path: &Path) -> std::io::Result<()> {
+        let mut file = File::create(path)?;
+        let content = r#"0 babe:cafe:0::1/64 10.0.0.1/24
+        1 10.0.1.1/24 babe:cafe:1::1/64
+        2 babe:cafe:2::1/64 10.0.2.1/24
+        3 babe:cafe:3::1/64 10.0.3.1/24
+        4 babe:cafe:4::1/64 10.0.4.1/24
+        "#;
+
+        write!(file, "{}", content)
+    }
+
+    /// Writes a line topology of `nb_nodes` nodes: n0 - n1 - ... - n{nb_nodes - 1}.
+    fn write_line_topo(topo_path: &Path, node_to_ip_path: &Path, nb_nodes: usize) -> std::io::Result<()> {
+        let mut file = File::create(topo_path)?;
+        for i in 0..nb_nodes - 1 {
+            writeln!(file, "n{} n{} 1 1", i, i + 1)?;
+        }
+
+        let mut file = File::create(node_to_ip_path)?;
+        for i in 0..nb_nodes {
+            writeln!(file, "{} babe:cafe:{:x}::1/64", i, i)?;
+        }
+        Ok(())
+    }
+
+    /// Returns an `lldpcli -f json show neighbors` dump of a node with the given
+    /// (interface, neighbour name, management address) adjacencies.
+    fn get_dummy_lldp_json(adjacencies: &[(&str, &str, &str)]) -> String {
+        let interfaces: Vec<_> = adjacencies
+            .iter()
+            .map(|(itf, name, mgmt_ip)| {
+                format!(
+                    r#"{{"{}": {{"via": "LLDP", "rid": "1", "chassis": {{"{}": {{"id": {{"type": "mac", "value": "00:00:00:00:00:01"}}, "mgmt-ip": ["{}", "fe80::1"]}}}}, "port": {{"id": {{"type": "ifname", "value": "eth0"}}}}}}}}"#,
+                    itf, name, mgmt_ip
+                )
+            })
+            .collect();
+        // lldpd only uses an array if there are several interfaces.
+        if interfaces.len() == 1 {
+            format!(r#"{{"lldp": {{"interface": {}}}}}"#, interfaces[0])
+        } else {
+            format!(r#"{{"lldp": {{"interface": [{}]}}}}"#, interfaces.join(","))
+        }
+    }
+
     fn get_bier_state_from_path(path: &Path) -> Result<BierState> {
         let content = std::fs::read_to_string(path).map_err(|_| Error::FileParse)?;
         serde_json::from_str(&content).map_err(|_| Error::FileParse)
@@ -301,6 +717,8 @@ mod tests {
         let res = graph.get_bier_config(
             TEST_DIRECTORY,
             topo_path.file_stem().unwrap().to_str().unwrap(),
+            None,
+            BitstringFormat::Binary,
         );
         assert!(res.is_ok());
 
@@ -313,6 +731,337 @@ mod tests {
             let expected: BierState =
                 serde_json::from_str(EXPECTED_CONFIGURATIONS[node_id]).unwrap();
             assert_eq!(bier_state, expected);
+            assert_eq!(bier_state.validate(), Ok(()));
+        }
+
+        // Clean test.
+        std::fs::remove_dir_all(dir_path).unwrap();
+    }
+
+    #[test]
+    /// Tests the parsing of the loopback addresses of a node.
+    fn test_loopbacks_from_line() {
+        let loopbacks = Loopbacks::from_line("0 babe:cafe::1/64").unwrap();
+        assert_eq!(loopbacks.v6, Some("babe:cafe::1".parse().unwrap()));
+        assert_eq!(loopbacks.v4, None);
+
+        let loopbacks = Loopbacks::from_line("  1 10.0.0.1/24 ").unwrap();
+        assert_eq!(loopbacks.v6, None);
+        assert_eq!(loopbacks.v4, Some("10.0.0.1".parse().unwrap()));
+
+        let loopbacks = Loopbacks::from_line("2 10.0.0.2 babe:cafe::2/64").unwrap();
+        assert_eq!(loopbacks.v6, Some("babe:cafe::2".parse().unwrap()));
+        assert_eq!(loopbacks.v4, Some("10.0.0.2".parse().unwrap()));
+
+        assert!(Loopbacks::from_line("3").is_none());
+        assert!(Loopbacks::from_line("4 not-an-address").is_none());
+    }
+
+    #[test]
+    /// Tests the BIER configuration build with both IPv6 and IPv4 loopbacks.
+    /// The IPv6 configurations are unchanged, and the IPv4 configurations
+    /// contain the same BIFTs with the IPv4 next hops.
+    fn test_bier_configuration_dual_stack() {
+        // Test setup.
+        let dir_path = Path::new(TEST_DIRECTORY_DUAL_STACK);
+        if dir_path.exists() {
+            std::fs::remove_dir_all(dir_path).unwrap();
+        }
+        std::fs::create_dir(dir_path).unwrap();
+
+        let topo_path = dir_path.join("topo.ntf");
+        write_dummy_topo(&topo_path).unwrap();
+
+        let node_to_ip_path = dir_path.join("node_to_ip.ntf");
+        write_dummy_node_to_ip_dual_stack(&node_to_ip_path).unwrap();
+
+        // Actual test.
+        let graph = Graph::from_file(
+            topo_path.to_str().unwrap(),
+            node_to_ip_path.to_str().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(graph.get_families(), vec![Family::Ipv6, Family::Ipv4]);
+        let res = graph.get_bier_config(
+            TEST_DIRECTORY_DUAL_STACK,
+            topo_path.file_stem().unwrap().to_str().unwrap(),
+            None,
+            BitstringFormat::Binary,
+        );
+        assert!(res.is_ok());
+
+        for (node_id, expected) in EXPECTED_CONFIGURATIONS.iter().enumerate() {
+            let expected: BierState = serde_json::from_str(expected).unwrap();
+
+            let bier_state =
+                get_bier_state_from_path(&dir_path.join(format!("topo-{}.json", node_id)))
+                    .unwrap();
+            assert_eq!(bier_state, expected);
+
+            let bier_state_v4 =
+                get_bier_state_from_path(&dir_path.join(format!("topo-{}-ipv4.json", node_id)))
+                    .unwrap();
+            assert_eq!(
+                bier_state_v4.loopback,
+                IpAddr::V4(Ipv4Addr::new(10, 0, node_id as u8, 1))
+            );
+            let bift = &bier_state_v4.bifts[0];
+            let expected_bift = &expected.bifts[0];
+            assert_eq!(bift.bfr_id, expected_bift.bfr_id);
+            for (entry, expected_entry) in bift.entries.iter().zip(expected_bift.entries.iter()) {
+                assert_eq!(entry.paths.len(), expected_entry.paths.len());
+                for (path, expected_path) in entry.paths.iter().zip(expected_entry.paths.iter()) {
+                    assert_eq!(path.bitstring, expected_path.bitstring);
+                    // babe:cafe:X::1 becomes 10.0.X.1.
+                    let IpAddr::V6(v6) = expected_path.next_hop else {
+                        panic!("Expected an IPv6 next hop");
+                    };
+                    let expected_v4 = Ipv4Addr::new(10, 0, v6.segments()[2] as u8, 1);
+                    assert_eq!(path.next_hop, IpAddr::V4(expected_v4));
+                }
+            }
+        }
+
+        // Clean test.
+        std::fs::remove_dir_all(dir_path).unwrap();
+    }
+
+    #[test]
+    /// Tests the BIER configuration of a topology with more than 64 nodes.
+    /// The bitstrings span several words and are padded to the BSL.
+    fn test_bier_configuration_large_topology() {
+        // Test setup.
+        let nb_nodes = 130;
+        let dir_path = Path::new(TEST_DIRECTORY_LARGE);
+        if dir_path.exists() {
+            std::fs::remove_dir_all(dir_path).unwrap();
+        }
+        std::fs::create_dir(dir_path).unwrap();
+
+        let topo_path = dir_path.join("line.ntf");
+        let node_to_ip_path = dir_path.join("node_to_ip.ntf");
+        write_line_topo(&topo_path, &node_to_ip_path, nb_nodes).unwrap();
+
+        let graph = Graph::from_file(
+            topo_path.to_str().unwrap(),
+            node_to_ip_path.to_str().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(graph.nodes.len(), nb_nodes);
+
+        // The BSL must be valid. By default, it covers all nodes.
+        assert_eq!(graph.get_bsl(None).unwrap(), 256);
+        assert_eq!(graph.get_bsl(Some(1024)).unwrap(), 1024);
+        assert_eq!(graph.get_bsl(Some(128)).unwrap(), 128);
+        assert!(graph.get_bsl(Some(200)).is_err());
+
+        // Default BSL: 256 bits.
+        assert!(graph
+            .get_bier_config(TEST_DIRECTORY_LARGE, "line", None, BitstringFormat::Binary)
+            .is_ok());
+        let bier_state = get_bier_state_from_path(&dir_path.join("line-0.json")).unwrap();
+        let bift = &bier_state.bifts[0];
+        assert_eq!(bift.entries.len(), nb_nodes);
+        for entry in bift.entries.iter() {
+            assert_eq!(entry.paths.len(), 1);
+            assert_eq!(entry.paths[0].bitstring.bitstring.len(), 4);
+        }
+
+        // From n0, the local bit is alone and all others are reached through n1.
+        let local = &bift.entries[0].paths[0];
+        assert_eq!(local.bitstring.bitstring, vec![0, 0, 0, 1]);
+        assert_eq!(local.next_hop, IpAddr::V6("babe:cafe::1".parse().unwrap()));
+        let last = &bift.entries[nb_nodes - 1].paths[0];
+        assert_eq!(last.bitstring.bitstring, vec![0, 0b11, u64::MAX, u64::MAX - 1]);
+        assert_eq!(last.next_hop, IpAddr::V6("babe:cafe:1::1".parse().unwrap()));
+
+        // From n100, the bits of n101..n129 cross the word boundary at bit 128.
+        let bier_state = get_bier_state_from_path(&dir_path.join("line-100.json")).unwrap();
+        let bift = &bier_state.bifts[0];
+        assert_eq!(bift.bfr_id, 101);
+        let downstream = &bift.entries[nb_nodes - 1].paths[0];
+        assert_eq!(
+            downstream.bitstring.bitstring,
+            vec![0, 0b11, u64::MAX << 37, 0]
+        );
+        assert_eq!(downstream.next_hop, IpAddr::V6("babe:cafe:65::1".parse().unwrap()));
+        let upstream = &bift.entries[0].paths[0];
+        assert_eq!(upstream.bitstring.bitstring, vec![0, 0, (1 << 36) - 1, u64::MAX]);
+        assert_eq!(upstream.next_hop, IpAddr::V6("babe:cafe:63::1".parse().unwrap()));
+
+        // Explicit BSL: 1024 bits.
+        assert!(graph
+            .get_bier_config(TEST_DIRECTORY_LARGE, "line", Some(1024), BitstringFormat::Binary)
+            .is_ok());
+        let bier_state = get_bier_state_from_path(&dir_path.join("line-0.json")).unwrap();
+        let last = &bier_state.bifts[0].entries[nb_nodes - 1].paths[0];
+        let mut expected = vec![0; 16];
+        expected[13] = 0b11;
+        expected[14] = u64::MAX;
+        expected[15] = u64::MAX - 1;
+        assert_eq!(last.bitstring.bitstring, expected);
+
+        // The same configuration with compact bitstrings.
+        for format in [BitstringFormat::Hex, BitstringFormat::Bits] {
+            assert!(graph
+                .get_bier_config(TEST_DIRECTORY_LARGE, "line", Some(1024), format)
+                .is_ok());
+            let path = dir_path.join("line-0.json");
+            let txt = std::fs::read_to_string(&path).unwrap();
+            assert!(!txt.contains(&"0".repeat(1024)));
+            assert_eq!(get_bier_state_from_path(&path).unwrap(), bier_state);
+        }
+
+        // Converted to a binary snapshot and back.
+        let json_path = dir_path.join("line-0.json");
+        let snapshot_path = dir_path.join("line-0.bin");
+        let converted_path = dir_path.join("line-0-converted.json");
+        let (json, snapshot, converted) = (
+            json_path.to_str().unwrap(),
+            snapshot_path.to_str().unwrap(),
+            converted_path.to_str().unwrap(),
+        );
+        convert(json, snapshot, BitstringFormat::Binary).unwrap();
+        let content = std::fs::read(&snapshot_path).unwrap();
+        assert!(content.len() < std::fs::metadata(&json_path).unwrap().len() as usize);
+        assert_eq!(BierState::from_snapshot(&content).unwrap(), bier_state);
+        convert(snapshot, converted, BitstringFormat::Hex).unwrap();
+        assert_eq!(get_bier_state_from_path(&converted_path).unwrap(), bier_state);
+        assert!(convert(converted, snapshot, BitstringFormat::Binary).is_ok());
+        assert!(convert(snapshot, dir_path.to_str().unwrap(), BitstringFormat::Binary).is_err());
+
+        // BSL of 64 bits: the nodes are spread over three SIs, each with its BIFT.
+        assert!(graph
+            .get_bier_config(TEST_DIRECTORY_LARGE, "line", Some(64), BitstringFormat::Binary)
+            .is_ok());
+        let path = dir_path.join("line-0.json");
+        let mut bier_state = get_bier_state_from_path(&path).unwrap();
+        assert_eq!(bier_state.bifts.len(), 3);
+        assert_eq!(bier_state.validate(), Ok(()));
+        assert!(validate(path.to_str().unwrap(), Some(64)));
+        assert!(!validate(path.to_str().unwrap(), Some(128)));
+        let bift = &bier_state.bifts[2];
+        assert_eq!((bift.bift_id, bift.si, bift.bfr_id), (3, 2, 1));
+        assert_eq!(
+            bift.entries.iter().map(|entry| entry.bit).collect::<Vec<_>>(),
+            vec![129, 130]
+        );
+        assert_eq!(bift.entries[1].paths[0].bitstring.bitstring, vec![0b11]);
+        assert_eq!(bier_state.bifts[1].entries.len(), 64);
+        bier_state.compile().unwrap();
+        let copies = bier_state
+            .process_bier(&Bitstring { bitstring: vec![0b10] }, 3, 0)
+            .unwrap();
+        assert_eq!(
+            copies,
+            vec![(
+                Bitstring { bitstring: vec![0b10] },
+                Some(IpAddr::V6("babe:cafe:1::1".parse().unwrap()))
+            )]
+        );
+
+        // Clean test.
+        std::fs::remove_dir_all(dir_path).unwrap();
+    }
+
+    #[test]
+    /// Tests the parsing of the neighbours in LLDP dumps.
+    fn test_get_lldp_neighbours() {
+        let json: Value = serde_json::from_str(&get_dummy_lldp_json(&[
+            ("eth0", "b", "babe:cafe:1::1"),
+            ("eth1", "c", "10.0.2.1"),
+        ]))
+        .unwrap();
+        let neighbours = get_lldp_neighbours(&json).unwrap();
+        assert_eq!(
+            neighbours,
+            vec![
+                (
+                    "b".to_string(),
+                    vec![
+                        "babe:cafe:1::1".parse().unwrap(),
+                        "fe80::1".parse().unwrap()
+                    ]
+                ),
+                (
+                    "c".to_string(),
+                    vec!["10.0.2.1".parse().unwrap(), "fe80::1".parse().unwrap()]
+                ),
+            ]
+        );
+
+        // Chassis without system name and a single management address.
+        let json: Value = serde_json::from_str(
+            r#"{"lldp": {"interface": {"eth0": {"chassis": {"id": {"type": "mac", "value": "00:11:22:33:44:55"}, "mgmt-ip": "10.0.0.2"}}}}}"#,
+        )
+        .unwrap();
+        let neighbours = get_lldp_neighbours(&json).unwrap();
+        assert_eq!(
+            neighbours,
+            vec![(
+                "00:11:22:33:44:55".to_string(),
+                vec!["10.0.0.2".parse().unwrap()]
+            )]
+        );
+
+        // No neighbour.
+        let json: Value = serde_json::from_str(r#"{"lldp": {}}"#).unwrap();
+        assert!(get_lldp_neighbours(&json).unwrap().is_empty());
+
+        // Not an LLDP dump.
+        let json: Value = serde_json::from_str(r#"{"bifts": []}"#).unwrap();
+        assert!(get_lldp_neighbours(&json).is_err());
+    }
+
+    #[test]
+    /// Tests the BIER configuration build from LLDP dumps of the diamond topology.
+    /// The result must be the same as with the topology file.
+    fn test_bier_configuration_lldp() {
+        // Test setup.
+        let dir_path = Path::new(TEST_DIRECTORY_LLDP);
+        if dir_path.exists() {
+            std::fs::remove_dir_all(dir_path).unwrap();
+        }
+        std::fs::create_dir(dir_path).unwrap();
+
+        let dumps = [
+            ("a", vec![("eth0", "b", "babe:cafe:1::1"), ("eth1", "c", "babe:cafe:2::1")]),
+            ("b", vec![("eth0", "a", "babe:cafe::1"), ("eth1", "d", "babe:cafe:3::1")]),
+            ("c", vec![("eth0", "a", "babe:cafe::1"), ("eth1", "d", "babe:cafe:3::1")]),
+            (
+                "d",
+                vec![
+                    ("eth0", "b", "babe:cafe:1::1"),
+                    ("eth1", "c", "babe:cafe:2::1"),
+                    ("eth2", "e", "babe:cafe:4::1"),
+                ],
+            ),
+            ("e", vec![("eth0", "d", "babe:cafe:3::1")]),
+        ];
+        let mut lldp_files = Vec::new();
+        for (name, adjacencies) in dumps.iter() {
+            let path = dir_path.join(format!("{}.json", name));
+            std::fs::write(&path, get_dummy_lldp_json(adjacencies)).unwrap();
+            lldp_files.push(path.to_str().unwrap().to_string());
+        }
+
+        // Actual test.
+        let graph = Graph::from_lldp(&lldp_files, None);
+        assert!(graph.is_ok());
+        let graph = graph.unwrap();
+        assert_eq!(graph.nodes.len(), 5);
+        // The link-local management addresses are also IPv6, but come after the loopbacks.
+        assert_eq!(graph.get_families(), vec![Family::Ipv6]);
+        assert!(graph
+            .get_bier_config(TEST_DIRECTORY_LLDP, "lldp", None, BitstringFormat::Binary)
+            .is_ok());
+
+        for (node_id, expected) in EXPECTED_CONFIGURATIONS.iter().enumerate() {
+            let bier_state =
+                get_bier_state_from_path(&dir_path.join(format!("lldp-{}.json", node_id)))
+                    .unwrap();
+            let expected: BierState = serde_json::from_str(expected).unwrap();
+            assert_eq!(bier_state, expected);
         }
 
         // Clean test.
@@ -0,0 +1,534 @@
+//! Bootstraps an experiment from the configurations generated by `bier-config`: one script
+//! per BFR to start the daemon, the senders and the receiver, and the expected-delivery
+//! matrix of the flows, computed by replicating their packets with the same BIFTs.
+
+#[macro_use]
+extern crate log;
+
+use bier_rust::bier::{BierState, Bitstring};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::net::IpAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+#[derive(Debug)]
+enum Error {
+    /// Impossible to read or parse a configuration or the flows.
+    FileParse,
+
+    /// A flow refers to a BFR without configuration.
+    UnknownBfr(String),
+
+    /// The name of a BFR is not a valid file name for its scripts.
+    InvalidName(String),
+
+    /// A copy is sent to a next hop that is not the loopback of a configured BFR.
+    UnknownNextHop(IpAddr),
+
+    /// The copies of a packet never stop being forwarded.
+    ForwardingLoop,
+
+    /// The BIER processing of a packet failed.
+    Bier(bier_rust::Error),
+
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::FileParse => write!(f, "cannot read or parse a configuration or the flows"),
+            Error::UnknownBfr(name) => write!(f, "no configuration for the BFR {}", name),
+            Error::InvalidName(name) => write!(f, "invalid name of BFR {:?}", name),
+            Error::UnknownNextHop(next_hop) => {
+                write!(f, "no configuration with the loopback {}", next_hop)
+            }
+            Error::ForwardingLoop => write!(f, "forwarding loop"),
+            Error::Bier(e) => write!(f, "BIER processing error: {}", e),
+            Error::Io(e) => write!(f, "cannot write the fixtures: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Parser)]
+struct Args {
+    /// Configurations of all the BFRs of the topology, e.g., generated by `bier-config`.
+    #[clap(value_parser, num_args = 1.., required = true)]
+    configs: Vec<String>,
+    /// Path containing the output files.
+    #[clap(short = 'd', long = "directory", value_parser)]
+    directory: String,
+    /// JSON list of flows, e.g., `[{"sender": "a", "receivers": ["d", "e"]}]`, with the
+    /// names of the BFRs. By default, each BFR sends one flow to all the other BFRs.
+    #[clap(short = 'f', long = "flows", value_parser)]
+    flows: Option<String>,
+    /// Number of packets sent per flow.
+    #[clap(short = 'n', long = "nb-packets", value_parser, default_value_t = 10)]
+    nb_packets: u64,
+    /// Directory of the UNIX sockets of the daemons and receivers.
+    #[clap(long = "socket-dir", value_parser, default_value = "/tmp")]
+    socket_dir: String,
+}
+
+fn main() {
+    env_logger::init();
+    let args = Args::parse();
+    if let Err(e) = generate(&args) {
+        error!("Cannot generate the fixtures: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn generate(args: &Args) -> Result<()> {
+    let nodes = args
+        .configs
+        .iter()
+        .map(|path| Node::from_path(path))
+        .collect::<Result<Vec<_>>>()?;
+    let flows = match &args.flows {
+        Some(path) => {
+            let content = std::fs::read_to_string(path).map_err(|_| Error::FileParse)?;
+            serde_json::from_str(&content).map_err(|_| Error::FileParse)?
+        }
+        None => default_flows(&nodes),
+    };
+    let matrix = DeliveryMatrix::new(&nodes, &flows, args.nb_packets)?;
+    matrix.write(&nodes, Path::new(&args.directory), &args.socket_dir)
+}
+
+/// Flow sent by a BFR to a set of BFRs, identified by their names.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+struct Flow {
+    sender: String,
+    receivers: Vec<String>,
+}
+
+/// Configured BFR of the topology.
+struct Node {
+    name: String,
+    /// Path of the configuration, for the daemon.
+    config: String,
+    bfr_id: u64,
+    bift_id: u32,
+    state: BierState,
+}
+
+impl Node {
+    fn from_path(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|_| Error::FileParse)?;
+        let state: BierState = serde_json::from_str(&content).map_err(|_| Error::FileParse)?;
+        Node::new(state, path)
+    }
+
+    /// The BFR is named after its BFR-id in the first BIFT, unless it has a name
+    /// in the `bfr_names` of its configuration.
+    fn new(mut state: BierState, config: &str) -> Result<Self> {
+        state.compile().map_err(Error::Bier)?;
+        let bift = state.bifts.first().ok_or(Error::FileParse)?;
+        let (bfr_id, bift_id) = (bift.bfr_id, bift.bift_id as u32);
+        let name = state
+            .bfr_names
+            .iter()
+            .find(|(_, id)| **id == bfr_id)
+            .map(|(name, _)| name.clone())
+            .unwrap_or_else(|| bfr_id.to_string());
+        // The scripts of the BFR are named after it, in the output directory.
+        if name.contains(['/', '\0']) {
+            return Err(Error::InvalidName(name));
+        }
+        Ok(Node {
+            name,
+            config: config.to_string(),
+            bfr_id,
+            bift_id,
+            state,
+        })
+    }
+
+    fn daemon_socket(&self, socket_dir: &str) -> String {
+        format!("{}/bier-{}.sock", socket_dir, self.name)
+    }
+
+    fn app_socket(&self, socket_dir: &str) -> String {
+        format!("{}/bier-{}-app.sock", socket_dir, self.name)
+    }
+}
+
+/// One flow from each BFR to all the other BFRs.
+fn default_flows(nodes: &[Node]) -> Vec<Flow> {
+    nodes
+        .iter()
+        .map(|sender| Flow {
+            sender: sender.name.clone(),
+            receivers: nodes
+                .iter()
+                .filter(|node| node.name != sender.name)
+                .map(|node| node.name.clone())
+                .collect(),
+        })
+        .collect()
+}
+
+/// Expected deliveries of a flow, with the bitstring sent by its BFIR.
+#[derive(Serialize, Debug)]
+struct ExpectedFlow {
+    #[serde(flatten)]
+    flow: Flow,
+    bitstring: Bitstring,
+    /// Copies of each packet delivered to each BFR, in the order of `DeliveryMatrix::bfrs`.
+    deliveries: Vec<u32>,
+}
+
+#[derive(Serialize, Debug)]
+struct DeliveryMatrix {
+    bfrs: Vec<String>,
+    nb_packets: u64,
+    flows: Vec<ExpectedFlow>,
+}
+
+impl DeliveryMatrix {
+    /// Replicates a packet of each flow through the BIFTs of `nodes`. A requested receiver
+    /// that is not reached, e.g., without path in the BIFTs, only raises a warning.
+    fn new(nodes: &[Node], flows: &[Flow], nb_packets: u64) -> Result<Self> {
+        let index = |name: &String| {
+            nodes
+                .iter()
+                .position(|node| node.name == *name)
+                .ok_or_else(|| Error::UnknownBfr(name.clone()))
+        };
+        let mut expected = Vec::with_capacity(flows.len());
+        for flow in flows {
+            let sender = &nodes[index(&flow.sender)?];
+            let mut bitstring = Bitstring::default();
+            for receiver in flow.receivers.iter() {
+                let bfr_id = nodes[index(receiver)?].bfr_id;
+                let bit = sender
+                    .state
+                    .bitstring_for_bfr_id(sender.bift_id, bfr_id)
+                    .map_err(Error::Bier)?;
+                bitstring.bitstring.resize(bit.bitstring.len(), 0);
+                for (word, bit) in bitstring.bitstring.iter_mut().zip(bit.bitstring) {
+                    *word |= bit;
+                }
+            }
+            let deliveries = replicate(nodes, index(&flow.sender)?, sender.bift_id, &bitstring)?;
+            for (node, nb) in nodes.iter().zip(deliveries.iter()) {
+                let requested = flow.receivers.contains(&node.name);
+                if requested != (*nb > 0) {
+                    warn!(
+                        "The flow of {} delivers {} copies to {}, requested: {}",
+                        flow.sender, nb, node.name, requested
+                    );
+                }
+            }
+            expected.push(ExpectedFlow {
+                flow: flow.clone(),
+                bitstring,
+                deliveries,
+            });
+        }
+        Ok(DeliveryMatrix {
+            bfrs: nodes.iter().map(|node| node.name.clone()).collect(),
+            nb_packets,
+            flows: expected,
+        })
+    }
+
+    /// Writes the matrix in JSON and CSV, and the scripts of each BFR in `directory`:
+    /// * `daemon-<name>.sh` starts the daemon, delivering the packets to the receiver;
+    /// * `sender-<name>.sh` sends the flows of the BFR, if any;
+    /// * `receiver-<name>.sh` waits for all the packets expected by the BFR, if any.
+    ///
+    /// The `BIER`, `SENDER` and `RECEIVER` environment variables override the path of
+    /// the binaries, e.g., `cargo run --example sender --`.
+    fn write(&self, nodes: &[Node], directory: &Path, socket_dir: &str) -> Result<()> {
+        let file = std::fs::File::create(directory.join("expected-delivery.json"))?;
+        serde_json::to_writer_pretty(file, self).map_err(std::io::Error::from)?;
+
+        let mut csv = std::fs::File::create(directory.join("expected-delivery.csv"))?;
+        writeln!(csv, "sender,{}", self.bfrs.join(","))?;
+        for flow in self.flows.iter() {
+            let deliveries: Vec<String> = flow.deliveries.iter().map(|nb| nb.to_string()).collect();
+            writeln!(csv, "{},{}", flow.flow.sender, deliveries.join(","))?;
+        }
+
+        for (idx, node) in nodes.iter().enumerate() {
+            write_script(
+                &directory.join(format!("daemon-{}.sh", node.name)),
+                &format!(
+                    "exec ${{BIER:-bier-rust}} --config {} --bier-path {} --default {}\n",
+                    quote(&node.config),
+                    quote(&node.daemon_socket(socket_dir)),
+                    quote(&node.app_socket(socket_dir))
+                ),
+            )?;
+
+            let senders: Vec<String> = self
+                .flows
+                .iter()
+                .enumerate()
+                .filter(|(_, flow)| flow.flow.sender == node.name)
+                .map(|(flow_idx, flow)| {
+                    format!(
+                        "${{SENDER:-sender}} --bier {} -n {} -a ff3e::{:x} --bift-id {} --bitstring {}\n",
+                        quote(&node.daemon_socket(socket_dir)),
+                        self.nb_packets,
+                        flow_idx + 1,
+                        node.bift_id,
+                        bitstring_to_string(&flow.bitstring)
+                    )
+                })
+                .collect();
+            if !senders.is_empty() {
+                let path = directory.join(format!("sender-{}.sh", node.name));
+                write_script(&path, &senders.concat())?;
+            }
+
+            let nb_expected: u64 = self
+                .flows
+                .iter()
+                .map(|flow| flow.deliveries[idx] as u64 * self.nb_packets)
+                .sum();
+            if nb_expected > 0 {
+                write_script(
+                    &directory.join(format!("receiver-{}.sh", node.name)),
+                    &format!(
+                        "exec ${{RECEIVER:-receiver}} --bier {} --unix-path {} -n {}\n",
+                        quote(&node.daemon_socket(socket_dir)),
+                        quote(&node.app_socket(socket_dir)),
+                        nb_expected
+                    ),
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Copies of a packet sent by `sender` with `bitstring` delivered to each node. Each copy is
+/// processed by the node whose loopback is its next hop.
+fn replicate(
+    nodes: &[Node],
+    sender: usize,
+    bift_id: u32,
+    bitstring: &Bitstring,
+) -> Result<Vec<u32>> {
+    let loopbacks: HashMap<IpAddr, usize> = nodes
+        .iter()
+        .enumerate()
+        .map(|(idx, node)| (node.state.get_loopback(), idx))
+        .collect();
+    let mut deliveries = vec![0; nodes.len()];
+    let mut queue = VecDeque::from([(sender, bitstring.clone(), 0)]);
+    while let Some((node, bitstring, nb_hops)) = queue.pop_front() {
+        // A copy crossing more links than there are nodes follows a loop.
+        if nb_hops > nodes.len() {
+            return Err(Error::ForwardingLoop);
+        }
+        let copies = nodes[node]
+            .state
+            .process_bier(&bitstring, bift_id, 0)
+            .map_err(Error::Bier)?;
+        for (bitstring, next_hop) in copies {
+            match next_hop {
+                None => deliveries[node] += 1,
+                Some(next_hop) => {
+                    let next = *loopbacks
+                        .get(&next_hop)
+                        .ok_or(Error::UnknownNextHop(next_hop))?;
+                    queue.push_back((next, bitstring, nb_hops + 1));
+                }
+            }
+        }
+    }
+    Ok(deliveries)
+}
+
+/// Bitstring written most significant bit first, as in the configurations.
+fn bitstring_to_string(bitstring: &Bitstring) -> String {
+    bitstring
+        .bitstring
+        .iter()
+        .map(|word| format!("{:064b}", word))
+        .collect()
+}
+
+/// `word` in single quotes for the scripts, so that the shell keeps the spaces and the
+/// metacharacters of the paths and of the names of the BFRs as is.
+fn quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+fn write_script(path: &Path, commands: &str) -> Result<()> {
+    let mut file = std::fs::File::create(path)?;
+    write!(
+        file,
+        "#!/bin/sh\n# Generated by bier-fixtures.\n{}",
+        commands
+    )?;
+    file.set_permissions(std::fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /// Configuration of a BFR of the line topology a - b - c, whose BFR-ids are 1, 2 and 3.
+    /// `paths` gives the forwarding bitmask and the next hop of each BFR-id.
+    fn get_node(bfr_id: u64, paths: [(&str, &str); 3]) -> Node {
+        let entries: Vec<String> = paths
+            .iter()
+            .enumerate()
+            .map(|(idx, (bitstring, next_hop))| {
+                format!(
+                    r#"{{"bit": {}, "paths": [{{"bitstring": "{}", "next_hop": "{}"}}]}}"#,
+                    idx + 1,
+                    bitstring,
+                    next_hop
+                )
+            })
+            .collect();
+        let json = format!(
+            r#"{{"loopback": "fc00::{}", "bifts": [{{"bift_id": 1, "bift_type": 1, "bfr_id": {},
+            "entries": [{}]}}], "bfr_names": {{"a": 1, "b": 2, "c": 3}}}}"#,
+            bfr_id,
+            bfr_id,
+            entries.join(",")
+        );
+        Node::new(serde_json::from_str(&json).unwrap(), "config.json").unwrap()
+    }
+
+    fn get_nodes() -> Vec<Node> {
+        vec![
+            get_node(
+                1,
+                [("1", "fc00::1"), ("110", "fc00::2"), ("110", "fc00::2")],
+            ),
+            get_node(2, [("1", "fc00::1"), ("10", "fc00::2"), ("100", "fc00::3")]),
+            get_node(
+                3,
+                [("11", "fc00::2"), ("11", "fc00::2"), ("100", "fc00::3")],
+            ),
+        ]
+    }
+
+    #[test]
+    /// Tests the expected deliveries of the flows, replicated through the BIFTs.
+    fn test_delivery_matrix() {
+        let nodes = get_nodes();
+        assert_eq!(
+            nodes
+                .iter()
+                .map(|node| node.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a", "b", "c"]
+        );
+        let flows = default_flows(&nodes);
+        assert_eq!(flows[1].receivers, vec!["a", "c"]);
+
+        let matrix = DeliveryMatrix::new(&nodes, &flows, 5).unwrap();
+        assert_eq!(matrix.flows[0].bitstring.bitstring, vec![0b110]);
+        assert_eq!(
+            matrix
+                .flows
+                .iter()
+                .map(|flow| flow.deliveries.clone())
+                .collect::<Vec<_>>(),
+            vec![vec![0, 1, 1], vec![1, 0, 1], vec![1, 1, 0]]
+        );
+
+        // The receiver has no configuration.
+        let flow = Flow {
+            sender: "c".to_string(),
+            receivers: vec!["a".to_string()],
+        };
+        assert!(matches!(
+            DeliveryMatrix::new(&nodes[1..], &[flow], 1),
+            Err(Error::UnknownBfr(_))
+        ));
+
+        // b sends the copies toward c back to a.
+        let looping = vec![
+            get_nodes().remove(0),
+            get_node(2, [("1", "fc00::1"), ("10", "fc00::2"), ("100", "fc00::1")]),
+        ];
+        assert!(matches!(
+            replicate(
+                &looping,
+                0,
+                1,
+                &Bitstring {
+                    bitstring: vec![0b100]
+                }
+            ),
+            Err(Error::ForwardingLoop)
+        ));
+        // The next hop of b toward c is not configured: the copy cannot be followed.
+        assert!(matches!(
+            replicate(
+                &nodes[..2],
+                0,
+                1,
+                &Bitstring {
+                    bitstring: vec![0b100]
+                }
+            ),
+            Err(Error::UnknownNextHop(_))
+        ));
+    }
+
+    #[test]
+    /// Tests the scripts and the CSV matrix written for each BFR.
+    fn test_write_fixtures() {
+        let nodes = get_nodes();
+        let flows = vec![Flow {
+            sender: "a".to_string(),
+            receivers: vec!["c".to_string()],
+        }];
+        let matrix = DeliveryMatrix::new(&nodes, &flows, 5).unwrap();
+        let directory = std::env::temp_dir().join(format!("bier-fixtures-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        matrix.write(&nodes, &directory, "/run").unwrap();
+
+        let read = |name: &str| std::fs::read_to_string(directory.join(name)).unwrap();
+        assert_eq!(read("expected-delivery.csv"), "sender,a,b,c\na,0,0,1\n");
+        assert!(read("sender-a.sh").contains(&format!(
+            "--bier '/run/bier-a.sock' -n 5 -a ff3e::1 --bift-id 1 --bitstring {}100",
+            "0".repeat(61)
+        )));
+        assert!(read("receiver-c.sh").contains("--unix-path '/run/bier-c-app.sock' -n 5"));
+        assert!(
+            read("daemon-b.sh").contains("--config 'config.json' --bier-path '/run/bier-b.sock'")
+        );
+        assert!(!directory.join("sender-b.sh").exists());
+        assert!(!directory.join("receiver-a.sh").exists());
+        let metadata = std::fs::metadata(directory.join("daemon-a.sh")).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o755);
+        let json: serde_json::Value =
+            serde_json::from_str(&read("expected-delivery.json")).unwrap();
+        assert_eq!(json["flows"][0]["deliveries"], serde_json::json!([0, 0, 1]));
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(quote("/tmp/a b;$(c)"), "'/tmp/a b;$(c)'");
+        let mut state = nodes[0].state.clone();
+        state.bfr_names = [("../a".to_string(), 1)].into();
+        assert!(matches!(
+            Node::new(state, "config.json"),
+            Err(Error::InvalidName(_))
+        ));
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+}
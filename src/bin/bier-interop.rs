@@ -0,0 +1,40 @@
+use bier_rust::interop::TestVectors;
+use clap::Parser;
+
+#[derive(Parser)]
+struct Args {
+    /// Test vector files (see `interop.rs` for the format).
+    #[clap(value_parser, num_args = 1.., required = true)]
+    vector_files: Vec<String>,
+}
+
+fn main() {
+    env_logger::init();
+    let args = Args::parse();
+
+    let mut nb_failed = 0;
+    for vector_file in args.vector_files.iter() {
+        let file = std::fs::File::open(vector_file).expect("Cannot find the file");
+        let vectors: TestVectors =
+            serde_json::from_reader(file).expect("Cannot parse the test vectors");
+
+        let failed = vectors.check();
+        for (name, mismatches) in failed.iter() {
+            println!("FAIL {}: {}", vector_file, name);
+            for mismatch in mismatches {
+                println!("    {}", mismatch);
+            }
+        }
+        println!(
+            "{}: {} passed, {} failed",
+            vector_file,
+            vectors.vectors.len() - failed.len(),
+            failed.len()
+        );
+        nb_failed += failed.len();
+    }
+
+    if nb_failed > 0 {
+        std::process::exit(1);
+    }
+}
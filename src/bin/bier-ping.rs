@@ -0,0 +1,226 @@
+//! Sends BIER OAM echo requests toward a list of BFERs through the daemon, and prints the
+//! round-trip time of each reply, then the losses and round-trip times of each BFER.
+//! The replies are delivered by the daemon to the UNIX socket of this program, which must
+//! be the path where the daemon delivers the packets.
+
+use bier_rust::api::{Query, Response, SendInfo};
+use bier_rust::bier::Bitstring;
+use bier_rust::client::BierClient;
+use bier_rust::header::{BierProto, PROTO_OAM};
+use bier_rust::oam::{EchoMessage, OamMessageType, ReplyMode};
+use clap::Parser;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant, SystemTime};
+
+#[derive(Parser)]
+struct Args {
+    /// Path to the BIER daemon.
+    #[clap(short = 'b', long = "bier", value_parser)]
+    bier_path: String,
+    /// Path where the BIER daemon delivers the packets to this program.
+    #[clap(short = 'u', long = "unix-path", value_parser)]
+    unix_path: String,
+    /// BFR-ids or names of the BFERs.
+    #[clap(value_parser, required = true)]
+    bfers: Vec<String>,
+    /// BIFT-id of the requests, of the first SI if the BFR-ids span several SIs.
+    #[clap(long = "bift-id", value_parser, default_value = "1")]
+    bift_id: u32,
+    /// Number of echo requests.
+    #[clap(short = 'c', long = "count", value_parser, default_value = "5")]
+    count: u32,
+    /// Interval between two echo requests, in seconds.
+    #[clap(short = 'i', long = "interval", value_parser, default_value = "1")]
+    interval: f64,
+    /// Time to wait for the replies after the last request, in seconds.
+    #[clap(short = 'W', long = "timeout", value_parser, default_value = "1")]
+    timeout: f64,
+}
+
+/// Replies of a BFER.
+#[derive(Default)]
+struct BferStats {
+    name: String,
+    rtts: Vec<Duration>,
+}
+
+impl BferStats {
+    fn summary(&self, bfr_id: u16, sent: u32) -> String {
+        let received = self.rtts.len() as u32;
+        let loss = 100.0 * (sent.saturating_sub(received)) as f64 / sent.max(1) as f64;
+        let mut summary = format!(
+            "{} (BFR-id {}): {} requests, {} replies, {:.1}% loss",
+            self.name, bfr_id, sent, received, loss
+        );
+        if let (Some(min), Some(max)) = (self.rtts.iter().min(), self.rtts.iter().max()) {
+            let avg = self.rtts.iter().sum::<Duration>() / received;
+            summary += &format!(
+                ", rtt min/avg/max = {:.3}/{:.3}/{:.3} ms",
+                ms(*min),
+                ms(avg),
+                ms(*max)
+            );
+        }
+        summary
+    }
+}
+
+fn main() {
+    env_logger::init();
+    let args = Args::parse();
+
+    let mut client =
+        BierClient::connect(&args.bier_path, &args.unix_path).expect("Cannot bind the UNIX socket");
+    let query_timeout = Duration::from_secs(1);
+
+    let bsl = match client.query(Query::Bifts, query_timeout) {
+        Ok(Response::Bifts(bifts)) => bifts
+            .iter()
+            .find(|bift| bift.bift_id == args.bift_id)
+            .map(|bift| bift.bsl as usize)
+            .unwrap_or_else(|| panic!("No BIFT with the BIFT-id {}", args.bift_id)),
+        res => panic!("Cannot query the BIFTs of the daemon: {:?}", res),
+    };
+    let names: BTreeMap<String, u16> = match client.query(Query::BfrNames, query_timeout) {
+        Ok(Response::BfrNames(names)) => names.into_iter().collect(),
+        _ => BTreeMap::new(),
+    };
+    let mut bfers: BTreeMap<u16, BferStats> = BTreeMap::new();
+    for bfer in &args.bfers {
+        let bfr_id = bfer
+            .parse()
+            .ok()
+            .or_else(|| names.get(bfer).copied())
+            .unwrap_or_else(|| panic!("Unknown BFER {}", bfer));
+        bfers.insert(
+            bfr_id,
+            BferStats {
+                name: bfer.clone(),
+                rtts: Vec::new(),
+            },
+        );
+    }
+    let bitstring = bitstring(bfers.keys().copied(), bsl);
+
+    let handle = std::process::id();
+    let interval = Duration::from_secs_f64(args.interval);
+    let mut payload = vec![0u8; 64];
+    println!("PING {} BFERs with BIFT-id {}", bfers.len(), args.bift_id);
+    for sequence in 0..args.count {
+        let request = EchoMessage::request(handle, sequence, ReplyMode::Bier, SystemTime::now());
+        let len = request.to_slice(&mut payload).unwrap();
+        let send_info = SendInfo {
+            bift_id: args.bift_id,
+            proto: PROTO_OAM as u16,
+            bitstring: &bitstring,
+            payload: &payload[..len],
+            metadata: None,
+        };
+        if let Err(e) = client.send(&send_info) {
+            eprintln!("Cannot send the echo request {}: {}", sequence, e);
+        }
+        let wait = match sequence + 1 == args.count {
+            true => Duration::from_secs_f64(args.timeout),
+            false => interval,
+        };
+        receive_replies(&mut client, handle, &mut bfers, Instant::now() + wait);
+    }
+
+    println!("--- BIER ping statistics ---");
+    for (bfr_id, stats) in &bfers {
+        println!("{}", stats.summary(*bfr_id, args.count));
+    }
+}
+
+/// Bitstring with the bits of the `bfr_ids`, a multiple of `bsl` long, in network order.
+fn bitstring(bfr_ids: impl Iterator<Item = u16> + Clone, bsl: usize) -> Vec<u8> {
+    let highest = bfr_ids.clone().max().unwrap_or(1) as usize;
+    let nb_words = highest.div_ceil(bsl.max(64)) * bsl.max(64) / 64;
+    let mut bitstring = Bitstring {
+        bitstring: vec![0; nb_words],
+    };
+    for bfr_id in bfr_ids.filter(|&bfr_id| bfr_id > 0) {
+        let bit = bfr_id as usize - 1;
+        bitstring.bitstring[nb_words - 1 - bit / 64] |= 1 << (bit % 64);
+    }
+    (&bitstring).into()
+}
+
+/// Prints the echo replies of this program received until `deadline`.
+fn receive_replies(
+    client: &mut BierClient,
+    handle: u32,
+    bfers: &mut BTreeMap<u16, BferStats>,
+    deadline: Instant,
+) {
+    while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+        let recv_info = match client.recv_timeout(timeout) {
+            Ok(Some(recv_info)) => recv_info,
+            Ok(None) => return,
+            Err(e) => {
+                eprintln!("Cannot receive from the daemon: {}", e);
+                return;
+            }
+        };
+        if recv_info.bier_proto() != Ok(BierProto::Oam) {
+            continue;
+        }
+        let Ok(reply) = EchoMessage::from_slice(recv_info.payload) else {
+            continue;
+        };
+        if reply.message_type != OamMessageType::EchoReply || reply.handle != handle {
+            continue;
+        }
+        let (Some(rtt), Some(responder)) = (reply.rtt(SystemTime::now()), reply.responder) else {
+            continue;
+        };
+        let Some(stats) = bfers.get_mut(&responder) else {
+            continue;
+        };
+        println!(
+            "Reply from {} (BFR-id {}): seq={} time={:.3} ms",
+            stats.name,
+            responder,
+            reply.sequence,
+            ms(rtt)
+        );
+        stats.rtts.push(rtt);
+    }
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    /// Tests the bitstrings of the BFERs and the summary of their replies.
+    fn test_bier_ping() {
+        assert_eq!(
+            bitstring([1, 3].into_iter(), 64),
+            vec![0, 0, 0, 0, 0, 0, 0, 0b101]
+        );
+        // The BFR-ids above the BSL are sent in the bitstring of the next SIs.
+        let bitstring = bitstring([2, 65].into_iter(), 64);
+        assert_eq!(bitstring.len(), 16);
+        assert_eq!((bitstring[7], bitstring[15]), (1, 0b10));
+
+        let stats = BferStats {
+            name: "b".to_string(),
+            rtts: vec![Duration::from_millis(1), Duration::from_millis(3)],
+        };
+        assert_eq!(
+            stats.summary(2, 4),
+            "b (BFR-id 2): 4 requests, 2 replies, 50.0% loss, \
+             rtt min/avg/max = 1.000/2.000/3.000 ms"
+        );
+        assert_eq!(
+            BferStats::default().summary(3, 2),
+            " (BFR-id 3): 2 requests, 0 replies, 100.0% loss"
+        );
+    }
+}
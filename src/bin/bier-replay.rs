@@ -0,0 +1,109 @@
+#[macro_use]
+extern crate log;
+
+use bier_rust::bier::{SocketConfig, DEFAULT_IP_PROTO};
+use bier_rust::pcap::{bier_payload, PcapReader};
+use clap::Parser;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::Instant;
+
+#[derive(Parser)]
+struct Args {
+    /// Pcap file containing the BIER packets to replay.
+    #[clap(value_parser)]
+    pcap_file: String,
+    /// Address of the BFR receiving the packets.
+    #[clap(short = 'd', long = "dst", value_parser)]
+    dst: IpAddr,
+    /// Sends the BIER packets over UDP to this port instead of the raw IP socket.
+    #[clap(short = 'u', long = "udp", value_parser)]
+    udp_port: Option<u16>,
+    /// IP protocol number of the BIER packets, i.e., the `--proto` of the BFR, both of the
+    /// packets captured over IP and of the raw IP socket. 253 by default.
+    #[clap(short = 'p', long = "proto", value_parser, default_value_t = DEFAULT_IP_PROTO)]
+    proto: u8,
+    /// Speed multiplier of the original timing. 0 replays as fast as possible.
+    #[clap(short = 's', long = "speed", value_parser, default_value = "1")]
+    speed: f64,
+}
+
+enum Sender {
+    Raw(socket2::Socket),
+    Udp(UdpSocket),
+}
+
+impl Sender {
+    fn send(&self, packet: &[u8], dst: SocketAddr) -> std::io::Result<usize> {
+        match self {
+            Sender::Raw(sock) => sock.send_to(packet, &dst.into()),
+            Sender::Udp(sock) => sock.send_to(packet, dst),
+        }
+    }
+}
+
+fn main() {
+    env_logger::init();
+    let args = Args::parse();
+
+    let file = std::fs::File::open(&args.pcap_file).expect("Cannot open the pcap file");
+    let reader = PcapReader::new(std::io::BufReader::new(file)).expect("Cannot read the pcap file");
+    let linktype = reader.linktype();
+
+    let (sender, dst) = match args.udp_port {
+        Some(port) => {
+            let local: SocketAddr = match args.dst {
+                IpAddr::V4(_) => "0.0.0.0:0".parse().unwrap(),
+                IpAddr::V6(_) => "[::]:0".parse().unwrap(),
+            };
+            let sock = UdpSocket::bind(local).expect("Cannot bind the UDP socket");
+            (Sender::Udp(sock), SocketAddr::new(args.dst, port))
+        }
+        None => {
+            let config = SocketConfig {
+                proto: Some(args.proto),
+                ..Default::default()
+            };
+            config.validate().expect("Invalid proto");
+            let sock = bier_rust::daemon::raw_socket(args.dst, &config)
+                .expect("Impossible to create the IP raw socket with proto");
+            (Sender::Raw(sock), SocketAddr::new(args.dst, 0))
+        }
+    };
+
+    let start = Instant::now();
+    let mut first_timestamp = None;
+    let (mut nb_sent, mut nb_skipped) = (0, 0);
+    for packet in reader {
+        let packet = packet.expect("Cannot read a packet of the pcap file");
+        let bier_packet = match bier_payload(linktype, &packet.data, args.proto) {
+            Some(v) => v,
+            None => {
+                nb_skipped += 1;
+                continue;
+            }
+        };
+
+        // Keep the original inter-packet times, scaled by the speed multiplier.
+        let first_timestamp = *first_timestamp.get_or_insert(packet.timestamp);
+        if args.speed > 0.0 {
+            let offset = packet.timestamp.saturating_sub(first_timestamp);
+            let deadline = start + offset.div_f64(args.speed);
+            let now = Instant::now();
+            if deadline > now {
+                std::thread::sleep(deadline - now);
+            }
+        }
+
+        match sender.send(bier_packet, dst) {
+            Ok(_) => nb_sent += 1,
+            Err(e) => error!("Error when sending a packet to {}: {:?}", dst, e),
+        }
+    }
+
+    println!(
+        "Replayed {} BIER packets in {:?}, skipped {} non-BIER packets",
+        nb_sent,
+        start.elapsed(),
+        nb_skipped
+    );
+}
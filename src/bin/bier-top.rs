@@ -0,0 +1,223 @@
+use bier_rust::control::{ControlRequest, ControlResponse};
+use bier_rust::stats::{Counter, Stats};
+use clap::Parser;
+use std::fmt::Write;
+use std::os::unix::net::UnixDatagram;
+use std::time::{Duration, Instant};
+
+#[derive(Parser)]
+struct Args {
+    /// UNIX socket address of the control socket of the daemon.
+    #[clap(short = 'c', long = "control", value_parser)]
+    control_path: String,
+    /// UNIX socket address of this program. A temporary path by default.
+    #[clap(short = 'u', long = "unix-path", value_parser)]
+    unix_path: Option<String>,
+    /// Refresh interval, in seconds.
+    #[clap(short = 'i', long = "interval", value_parser, default_value = "1")]
+    interval: f64,
+    /// Stops after this number of refreshes. Runs until interrupted by default.
+    #[clap(short = 'n', long = "iterations", value_parser)]
+    iterations: Option<usize>,
+}
+
+fn main() {
+    env_logger::init();
+    let args = Args::parse();
+
+    let unix_path = args.unix_path.unwrap_or_else(|| {
+        std::env::temp_dir()
+            .join(format!("bier-top-{}", std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    });
+    let _ = std::fs::remove_file(&unix_path);
+    let sock = UnixDatagram::bind(&unix_path).expect("Cannot bind the UNIX socket");
+    sock.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+    let interval = Duration::from_secs_f64(args.interval);
+    let mut previous: Option<(Stats, Instant)> = None;
+    let mut iteration = 0;
+    while args.iterations.is_none_or(|n| iteration < n) {
+        let output = match get_stats(&sock, &args.control_path) {
+            Ok(stats) => {
+                let now = Instant::now();
+                let output = match &previous {
+                    Some((prev, at)) => render(prev, &stats, (now - *at).as_secs_f64()),
+                    None => render(&stats, &stats, 1.0),
+                };
+                previous = Some((stats, now));
+                output
+            }
+            Err(e) => format!("Cannot reach the daemon at {}: {}\n", args.control_path, e),
+        };
+        // Clear the screen and redraw from the top-left corner.
+        print!("\x1b[2J\x1b[H{}", output);
+
+        iteration += 1;
+        std::thread::sleep(interval);
+    }
+
+    let _ = std::fs::remove_file(&unix_path);
+}
+
+fn get_stats(sock: &UnixDatagram, control_path: &str) -> std::io::Result<Stats> {
+    let request = serde_json::to_vec(&ControlRequest::Stats).unwrap();
+    sock.send_to(&request, control_path)?;
+
+    let mut buffer = vec![0u8; 1 << 20];
+    let read = sock.recv(&mut buffer)?;
+    match serde_json::from_slice(&buffer[..read])? {
+        ControlResponse::Stats(stats) => Ok(stats),
+        ControlResponse::Error(e) => Err(std::io::Error::other(e)),
+        response => Err(std::io::Error::other(format!(
+            "Unexpected response: {:?}",
+            response
+        ))),
+    }
+}
+
+/// Packet and byte rates of a counter between two snapshots taken `elapsed` seconds apart.
+fn rates(prev: &Counter, cur: &Counter, elapsed: f64) -> (f64, f64) {
+    let elapsed = elapsed.max(f64::EPSILON);
+    (
+        cur.packets.saturating_sub(prev.packets) as f64 / elapsed,
+        cur.bytes.saturating_sub(prev.bytes) as f64 / elapsed,
+    )
+}
+
+fn render(prev: &Stats, cur: &Stats, elapsed: f64) -> String {
+    let mut out = String::new();
+    let line = |out: &mut String, name: &str, prev: &Counter, cur: &Counter| {
+        let (pps, bps) = rates(prev, cur, elapsed);
+        writeln!(
+            out,
+            "{:<40} {:>12.1} {:>14.1} {:>12} {:>14}",
+            name, pps, bps, cur.packets, cur.bytes
+        )
+        .unwrap();
+    };
+    let header = |out: &mut String, title: &str| {
+        writeln!(
+            out,
+            "\n{:<40} {:>12} {:>14} {:>12} {:>14}",
+            title, "pkt/s", "B/s", "packets", "bytes"
+        )
+        .unwrap();
+    };
+
+    header(&mut out, "BFR");
+    line(&mut out, "received from network", &prev.received, &cur.received);
+    line(&mut out, "sent by applications", &prev.from_apps, &cur.from_apps);
+    line(&mut out, "delivered locally", &prev.delivered, &cur.delivered);
+    if cur.cleared_bits > 0 {
+        writeln!(
+            out,
+            "{:<40} {:>12}",
+            "unknown bits cleared", cur.cleared_bits
+        )
+        .unwrap();
+    }
+    if let Some(hit_rate) = cur.cache.hit_rate() {
+        writeln!(
+            out,
+            "{:<40} {:>11.1}%",
+            format!("result cache ({} hits)", cur.cache.hits),
+            hit_rate * 100.0
+        )
+        .unwrap();
+    }
+
+    header(&mut out, "Neighbor");
+    for (next_hop, neighbor) in cur.neighbors.iter() {
+        let prev_sent = prev
+            .neighbors
+            .get(next_hop)
+            .map(|n| n.sent)
+            .unwrap_or_default();
+        let health = match (neighbor.drained, neighbor.dead, neighbor.healthy) {
+            (true, _, _) => "drained",
+            (false, true, _) => "DEAD",
+            (false, false, true) => "up",
+            (false, false, false) => "DOWN",
+        };
+        line(
+            &mut out,
+            &format!("{} ({}, {} errors)", next_hop, health, neighbor.send_errors),
+            &prev_sent,
+            &neighbor.sent,
+        );
+    }
+
+    writeln!(
+        out,
+        "\n{:<40} {:>12} {:>14} {:>12} {:>14}",
+        "Latency (µs)", "p50", "p90", "p99", "max"
+    )
+    .unwrap();
+    for (next_hop, neighbor) in cur.neighbors.iter() {
+        let latency = &neighbor.latency;
+        let micros = |quantile: f64| latency.quantile(quantile).as_secs_f64() * 1e6;
+        writeln!(
+            out,
+            "{:<40} {:>12.1} {:>14.1} {:>12.1} {:>14.1}",
+            next_hop.to_string(),
+            micros(0.5),
+            micros(0.9),
+            micros(0.99),
+            micros(1.0)
+        )
+        .unwrap();
+    }
+
+    header(&mut out, "BFER");
+    for (bfr_id, counter) in cur.bfers.iter() {
+        let prev_counter = prev.bfers.get(bfr_id).copied().unwrap_or_default();
+        line(&mut out, &format!("{}", bfr_id), &prev_counter, counter);
+    }
+
+    writeln!(out, "\n{:<40} {:>12} {:>14}", "Drop reason", "pkt/s", "packets").unwrap();
+    for (reason, nb) in cur.drops.iter() {
+        let prev_nb = prev.drops.get(reason).copied().unwrap_or(0);
+        let rate = nb.saturating_sub(prev_nb) as f64 / elapsed.max(f64::EPSILON);
+        writeln!(out, "{:<40} {:>12.1} {:>14}", format!("{:?}", reason), rate, nb).unwrap();
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use bier_rust::stats::DropReason;
+
+    #[test]
+    /// Tests the rates computed between two snapshots.
+    fn test_render() {
+        let mut prev = Stats::default();
+        prev.record_send("fc00::b".parse().unwrap(), 100, true);
+        let mut cur = prev.clone();
+        cur.record_send("fc00::b".parse().unwrap(), 100, true);
+        cur.record_send("fc00::b".parse().unwrap(), 100, true);
+        cur.record_drop(DropReason::NoEntry);
+        cur.record_cache(true);
+        cur.record_cache(false);
+        cur.record_latency("fc00::b".parse().unwrap(), Duration::from_micros(12));
+
+        let next_hop = "fc00::b".parse().unwrap();
+        let (pps, bps) = rates(
+            &prev.neighbors[&next_hop].sent,
+            &cur.neighbors[&next_hop].sent,
+            2.0,
+        );
+        assert_eq!(pps, 1.0);
+        assert_eq!(bps, 100.0);
+
+        let out = render(&prev, &cur, 2.0);
+        assert!(out.contains("fc00::b (up, 0 errors)"));
+        assert!(out.contains("NoEntry"));
+        assert!(out.contains("12.0"));
+        assert!(out.contains("50.0%"));
+    }
+}
@@ -0,0 +1,576 @@
+//! Inspects and modifies the state of a running daemon through its control socket: prints
+//! tables of the BIFT entries, of the multicast overlay and of the counters, explains the
+//! replication of a hypothetical packet, adds and removes
+//! BIFT entries and overlay groups, drains neighbors, applies deltas of the BIFTs, and reloads
+//! the configuration. The commands modifying the state are sent to the update socket.
+
+use bier_rust::bier::{AdminState, BierEntryPath, Bift, BiftEntry, Bitstring, Decision};
+use bier_rust::control::{ControlRequest, ControlResponse};
+use bier_rust::delta::BierStateDelta;
+use bier_rust::overlay::OverlayEntry;
+use bier_rust::stats::{Counter, Stats};
+use clap::{Parser, Subcommand};
+use std::fmt::Write;
+use std::io;
+use std::net::IpAddr;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+#[derive(Parser)]
+struct Args {
+    /// UNIX socket address of the control socket of the daemon.
+    #[clap(short = 'c', long = "control", value_parser)]
+    control_path: Option<String>,
+    /// UNIX socket address of the update socket of the daemon, required to modify its state.
+    #[clap(long = "update-socket", value_parser)]
+    update_path: Option<String>,
+    /// UNIX socket address of this program. A temporary path by default.
+    #[clap(short = 'u', long = "unix-path", value_parser)]
+    unix_path: Option<String>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Prints the state of the daemon.
+    #[clap(subcommand)]
+    Show(Show),
+    /// Prints the copies of a packet with a bitstring, without sending anything: the chosen
+    /// path of each entry matched by the bitstring, or whether its destination is drained.
+    Explain {
+        #[clap(value_parser)]
+        bift_id: u32,
+        #[clap(value_parser = parse_bitstring)]
+        bitstring: Bitstring,
+        /// Entropy of the packet, selecting the ECMP paths.
+        #[clap(long = "entropy", value_parser, default_value_t = 0)]
+        entropy: u32,
+    },
+    /// Adds an entry to a BIFT, or replaces its entry with the same BFR-id.
+    AddEntry {
+        #[clap(value_parser)]
+        bift_id: usize,
+        /// BFR-id of the entry.
+        #[clap(value_parser)]
+        bit: u64,
+        /// Paths of the entry, in order of preference, as `<next hop>=<forwarding bitmask>`.
+        #[clap(value_parser = parse_path, required = true)]
+        paths: Vec<BierEntryPath>,
+    },
+    /// Removes the entry of a BFR-id from a BIFT.
+    DelEntry {
+        #[clap(value_parser)]
+        bift_id: usize,
+        /// BFR-id of the entry.
+        #[clap(value_parser)]
+        bit: u64,
+    },
+    /// Adds the BFERs of a multicast group to the overlay, or replaces them.
+    AddGroup {
+        #[clap(value_parser)]
+        group: IpAddr,
+        /// BFR-ids of the BFERs of the group, as a bitstring.
+        #[clap(value_parser = parse_bitstring)]
+        bitstring: Bitstring,
+        /// Only the traffic of this source, for an (S, G) entry.
+        #[clap(long = "source", value_parser)]
+        source: Option<IpAddr>,
+        #[clap(long = "sub-domain", value_parser, default_value_t = 0)]
+        sub_domain: u8,
+    },
+    /// Removes a multicast group from the overlay.
+    DelGroup {
+        #[clap(value_parser)]
+        group: IpAddr,
+        /// Removes the (S, G) entry of this source instead of the (*, G) entry.
+        #[clap(long = "source", value_parser)]
+        source: Option<IpAddr>,
+    },
+    /// Disables the paths toward a next hop, e.g., before a maintenance of the neighbor, or
+    /// the entries of `--bit` without next hop. The entries use their next path, if any.
+    Drain(AdminTarget),
+    /// Enables again the paths toward a next hop, or the entries of `--bit`.
+    Undrain(AdminTarget),
+    /// Applies the changes of the BIFTs of a JSON file, all of them or none.
+    Apply {
+        #[clap(value_parser)]
+        file: String,
+    },
+    /// Reads the configuration file of the daemon again, as on SIGHUP.
+    Reload,
+    /// Writes the replication decisions of each packet to a file, or to a stream socket
+    /// with `unix:<path>` or `tcp:<address>`. Stops the trace without destination.
+    Trace {
+        #[clap(value_parser)]
+        destination: Option<String>,
+    },
+}
+
+/// Paths or entries whose administrative state is set by `drain` and `undrain`.
+#[derive(clap::Args)]
+struct AdminTarget {
+    /// Next hop of the paths. Without it, the entries of `--bit` are updated.
+    #[clap(value_parser)]
+    next_hop: Option<IpAddr>,
+    /// Only updates the entries of this BIFT.
+    #[clap(long = "bift-id", value_parser)]
+    bift_id: Option<usize>,
+    /// Only updates the entries of this BFR-id.
+    #[clap(long = "bit", value_parser)]
+    bit: Option<u64>,
+}
+
+impl AdminTarget {
+    fn request(&self, admin_state: AdminState) -> ControlRequest {
+        ControlRequest::SetAdminState {
+            bift_id: self.bift_id,
+            bit: self.bit,
+            next_hop: self.next_hop,
+            admin_state,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Show {
+    /// Entries of the BIFTs, with their paths and administrative state.
+    Bift {
+        /// Only shows the BIFT with this BIFT-id.
+        #[clap(long = "bift-id", value_parser)]
+        bift_id: Option<usize>,
+    },
+    /// Cumulative counters of the daemon.
+    Stats,
+    /// Entries of the multicast overlay.
+    Groups,
+}
+
+fn main() {
+    env_logger::init();
+    let args = Args::parse();
+
+    let (request, socket_path) = match args.command {
+        Command::Show(Show::Bift { .. }) => (ControlRequest::Bifts, args.control_path),
+        Command::Show(Show::Stats) => (ControlRequest::Stats, args.control_path),
+        Command::Show(Show::Groups) => (ControlRequest::Groups, args.control_path),
+        Command::Explain {
+            bift_id,
+            ref bitstring,
+            entropy,
+        } => (
+            ControlRequest::Explain {
+                bift_id,
+                bitstring: bitstring.clone(),
+                entropy,
+            },
+            args.control_path,
+        ),
+        Command::AddEntry {
+            bift_id,
+            bit,
+            ref paths,
+        } => (
+            ControlRequest::AddEntry {
+                bift_id,
+                entry: BiftEntry {
+                    bit,
+                    paths: paths.clone(),
+                    admin_state: Default::default(),
+                },
+            },
+            args.update_path,
+        ),
+        Command::DelEntry { bift_id, bit } => (
+            ControlRequest::RemoveEntry { bift_id, bit },
+            args.update_path,
+        ),
+        Command::AddGroup {
+            group,
+            ref bitstring,
+            source,
+            sub_domain,
+        } => (
+            ControlRequest::AddGroup {
+                entry: OverlayEntry {
+                    group,
+                    source,
+                    sub_domain,
+                    bitstring: bitstring.clone(),
+                },
+            },
+            args.update_path,
+        ),
+        Command::DelGroup { group, source } => (
+            ControlRequest::RemoveGroup { group, source },
+            args.update_path,
+        ),
+        Command::Drain(ref target) => (target.request(AdminState::Down), args.update_path),
+        Command::Undrain(ref target) => (target.request(AdminState::Up), args.update_path),
+        Command::Apply { ref file } => (
+            ControlRequest::Apply {
+                delta: read_delta(file),
+            },
+            args.update_path,
+        ),
+        Command::Reload => (ControlRequest::Reload, args.update_path),
+        Command::Trace { ref destination } => (
+            ControlRequest::Trace {
+                destination: destination.clone(),
+            },
+            args.update_path,
+        ),
+    };
+    let Some(socket_path) = socket_path else {
+        match request.is_update() {
+            true => eprintln!("The update socket of the daemon is required (--update-socket)"),
+            false => eprintln!("The control socket of the daemon is required (--control)"),
+        }
+        std::process::exit(2);
+    };
+
+    let unix_path = args.unix_path.unwrap_or_else(|| {
+        std::env::temp_dir()
+            .join(format!("bierctl-{}", std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    });
+    let response = send_request(&unix_path, &socket_path, &request);
+    let _ = std::fs::remove_file(&unix_path);
+
+    match response {
+        Ok(ControlResponse::Bifts(bifts)) => {
+            let bift_id = match args.command {
+                Command::Show(Show::Bift { bift_id }) => bift_id,
+                _ => None,
+            };
+            let bifts: Vec<Bift> = bifts
+                .into_iter()
+                .filter(|bift| bift_id.is_none_or(|id| bift.bift_id == id))
+                .collect();
+            print!("{}", render_bifts(&bifts));
+        }
+        Ok(ControlResponse::Stats(stats)) => print!("{}", render_stats(&stats)),
+        Ok(ControlResponse::Groups(groups)) => print!("{}", render_groups(&groups)),
+        Ok(ControlResponse::Decisions(decisions)) => print!("{}", render_decisions(&decisions)),
+        Ok(ControlResponse::Updated(updated)) => match request {
+            ControlRequest::Reload => println!("Reloaded, {} entries", updated),
+            ControlRequest::Trace {
+                destination: Some(ref destination),
+            } => println!("Tracing the decisions to {}", destination),
+            ControlRequest::Trace { destination: None } => println!("Stopped the trace"),
+            ControlRequest::Apply { .. } => println!("Applied {} changes", updated),
+            _ => println!("Updated {} entries", updated),
+        },
+        Ok(ControlResponse::Error(e)) => {
+            eprintln!("The daemon refused the request: {}", e);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Cannot reach the daemon at {}: {}", socket_path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn send_request(
+    unix_path: &str,
+    socket_path: &str,
+    request: &ControlRequest,
+) -> io::Result<ControlResponse> {
+    let _ = std::fs::remove_file(unix_path);
+    let sock = UnixDatagram::bind(unix_path)?;
+    sock.set_read_timeout(Some(Duration::from_secs(1)))?;
+    sock.send_to(&serde_json::to_vec(request)?, socket_path)?;
+
+    let mut buffer = vec![0u8; 1 << 20];
+    let read = sock.recv(&mut buffer)?;
+    Ok(serde_json::from_slice(&buffer[..read])?)
+}
+
+/// Path given as `<next hop>=<forwarding bitmask>`, e.g., `fc00:d::1=100000`.
+fn parse_path(path: &str) -> Result<BierEntryPath, String> {
+    let (next_hop, bitstring) = path
+        .split_once('=')
+        .ok_or("Expected <next hop>=<forwarding bitmask>")?;
+    Ok(BierEntryPath {
+        bitstring: bitstring.parse()?,
+        next_hop: next_hop
+            .parse()
+            .map_err(|e| format!("Invalid next hop {}: {}", next_hop, e))?,
+        admin_state: Default::default(),
+    })
+}
+
+/// Delta of the JSON `file`, exiting if it cannot be read.
+fn read_delta(file: &str) -> BierStateDelta {
+    let delta = std::fs::read_to_string(file)
+        .map_err(|e| e.to_string())
+        .and_then(|delta| serde_json::from_str(&delta).map_err(|e| e.to_string()));
+    delta.unwrap_or_else(|e| {
+        eprintln!("Cannot read the delta {}: {}", file, e);
+        std::process::exit(2);
+    })
+}
+
+fn parse_bitstring(bitstring: &str) -> Result<Bitstring, String> {
+    bitstring.parse()
+}
+
+/// Bits of `bitstring` without the leading zeros, the least significant bit on the right.
+fn bits(bitstring: &Bitstring) -> String {
+    let bits: String = bitstring
+        .bitstring
+        .iter()
+        .map(|word| format!("{:064b}", word))
+        .collect();
+    match bits.trim_start_matches('0') {
+        "" => "0".to_string(),
+        bits => bits.to_string(),
+    }
+}
+
+fn render_bifts(bifts: &[Bift]) -> String {
+    let mut out = String::new();
+    for bift in bifts {
+        writeln!(
+            out,
+            "BIFT {} (sub-domain {}, SI {}, BSL {}, BFR-id {})",
+            bift.bift_id,
+            bift.sub_domain,
+            bift.si,
+            bift.get_bsl(),
+            bift.bfr_id
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "{:>8} {:>5} {:<40} {:<6} F-BM",
+            "BFR-id", "path", "next hop", "state"
+        )
+        .unwrap();
+        for entry in &bift.entries {
+            for (idx, path) in entry.paths.iter().enumerate() {
+                // A path is down if its entry is down.
+                let state = match entry.admin_state.is_up() && path.admin_state.is_up() {
+                    true => "up",
+                    false => "down",
+                };
+                writeln!(
+                    out,
+                    "{:>8} {:>5} {:<40} {:<6} {}",
+                    entry.bit,
+                    idx,
+                    path.next_hop.to_string(),
+                    state,
+                    bits(&path.bitstring)
+                )
+                .unwrap();
+            }
+        }
+        writeln!(out).unwrap();
+    }
+    out
+}
+
+fn render_groups(groups: &[OverlayEntry]) -> String {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{:<40} {:<40} {:>10} BFERs",
+        "group", "source", "sub-domain"
+    )
+    .unwrap();
+    for entry in groups {
+        let source = entry
+            .source
+            .map_or("*".to_string(), |source| source.to_string());
+        writeln!(
+            out,
+            "{:<40} {:<40} {:>10} {}",
+            entry.group.to_string(),
+            source,
+            entry.sub_domain,
+            bits(&entry.bitstring)
+        )
+        .unwrap();
+    }
+    out
+}
+
+fn render_decisions(decisions: &[Decision]) -> String {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{:>8} {:>5} {:<40} {:<20} bitstring",
+        "BFR-id", "path", "next hop", "F-BM"
+    )
+    .unwrap();
+    for decision in decisions {
+        match decision {
+            Decision::Copy {
+                bit,
+                path,
+                fbm,
+                bitstring,
+                next_hop,
+            } => {
+                let next_hop =
+                    next_hop.map_or("local".to_string(), |next_hop| next_hop.to_string());
+                writeln!(
+                    out,
+                    "{:>8} {:>5} {:<40} {:<20} {}",
+                    bit,
+                    path,
+                    next_hop,
+                    bits(fbm),
+                    bits(bitstring)
+                )
+                .unwrap();
+            }
+            Decision::Drained { bit } => {
+                writeln!(out, "{:>8} {:>5} {:<40}", bit, "-", "drained").unwrap()
+            }
+        }
+    }
+    out
+}
+
+fn render_stats(stats: &Stats) -> String {
+    let mut out = String::new();
+    let line = |out: &mut String, name: &str, counter: &Counter| {
+        writeln!(
+            out,
+            "{:<40} {:>12} {:>14}",
+            name, counter.packets, counter.bytes
+        )
+        .unwrap();
+    };
+
+    writeln!(out, "{:<40} {:>12} {:>14}", "BFR", "packets", "bytes").unwrap();
+    line(&mut out, "received from network", &stats.received);
+    line(&mut out, "sent by applications", &stats.from_apps);
+    line(&mut out, "delivered locally", &stats.delivered);
+
+    writeln!(
+        out,
+        "\n{:<40} {:>12} {:>14} {:>8} {:>8}",
+        "Neighbor", "packets", "bytes", "errors", "state"
+    )
+    .unwrap();
+    for (next_hop, neighbor) in &stats.neighbors {
+        let health = match (neighbor.drained, neighbor.dead, neighbor.healthy) {
+            (true, _, _) => "drained",
+            (false, true, _) => "DEAD",
+            (false, false, true) => "up",
+            (false, false, false) => "DOWN",
+        };
+        writeln!(
+            out,
+            "{:<40} {:>12} {:>14} {:>8} {:>8}",
+            next_hop.to_string(),
+            neighbor.sent.packets,
+            neighbor.sent.bytes,
+            neighbor.send_errors,
+            health
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "\n{:<40} {:>12} {:>14}", "BFER", "packets", "bytes").unwrap();
+    for (bfr_id, counter) in &stats.bfers {
+        line(&mut out, &bfr_id.to_string(), counter);
+    }
+
+    writeln!(out, "\n{:<40} {:>12}", "Drop reason", "packets").unwrap();
+    for (reason, nb) in &stats.drops {
+        writeln!(out, "{:<40} {:>12}", format!("{:?}", reason), nb).unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use bier_rust::bier::BierState;
+    use bier_rust::stats::DropReason;
+
+    #[test]
+    /// Tests the parsing of the paths and the tables of the BIFTs and counters.
+    fn test_bierctl() {
+        let path = parse_path("fc00:d::1=100000").unwrap();
+        assert_eq!(
+            path.next_hop,
+            "fc00:d::1".parse::<std::net::IpAddr>().unwrap()
+        );
+        assert_eq!(bits(&path.bitstring), "100000");
+        assert!(parse_path("fc00:d::1").is_err());
+        assert!(parse_path("node-d=1").is_err());
+
+        let args = Args::try_parse_from(["bierctl", "drain", "fc00:b::1", "--bit", "4"]).unwrap();
+        let Command::Drain(target) = args.command else {
+            panic!("Expected a drain command");
+        };
+        assert_eq!(
+            target.request(AdminState::Down),
+            ControlRequest::SetAdminState {
+                bift_id: None,
+                bit: Some(4),
+                next_hop: Some("fc00:b::1".parse().unwrap()),
+                admin_state: AdminState::Down,
+            }
+        );
+
+        let mut state: BierState =
+            serde_json::from_str(include_str!("../../configs/example.json")).unwrap();
+        state.bifts[0].entries[3].paths[0].admin_state = bier_rust::bier::AdminState::Down;
+        let table = render_bifts(&state.bifts);
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[0].starts_with("BIFT 1 (sub-domain 0, SI 0, BSL 64"));
+        let entry = &state.bifts[0].entries[3];
+        let row = lines
+            .iter()
+            .find(|line| line.contains(" down "))
+            .expect("No disabled path");
+        assert!(row.trim_start().starts_with(&entry.bit.to_string()));
+        assert!(row.ends_with(&bits(&entry.paths[0].bitstring)));
+
+        let groups: Vec<OverlayEntry> = serde_json::from_str(
+            r#"[{"group": "ff3e::1", "bitstring": "110"},
+                {"group": "ff3e::1", "source": "fc00:a::1", "sub_domain": 1, "bitstring": "1"}]"#,
+        )
+        .unwrap();
+        let table = render_groups(&groups);
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[1].starts_with("ff3e::1 ") && lines[1].ends_with(" 0 110"));
+        assert!(lines[2].contains(" fc00:a::1 ") && lines[2].ends_with(" 1 1"));
+        assert!(parse_bitstring("12").is_err());
+
+        let args =
+            Args::try_parse_from(["bierctl", "explain", "1", "11010", "--entropy", "3"]).unwrap();
+        let Command::Explain { entropy, .. } = args.command else {
+            panic!("Expected an explain command");
+        };
+        assert_eq!(entropy, 3);
+        state.compile().unwrap();
+        state.set_entry_admin_state(None, 4, AdminState::Down);
+        let bitstring = parse_bitstring("1001").unwrap();
+        let table = render_decisions(&state.explain(&bitstring, 1, 0).unwrap());
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[1].trim_start().starts_with("1 ") && lines[1].contains(" local "));
+        assert!(lines.iter().any(|line| line.trim() == "4     - drained"));
+
+        let mut stats = Stats::default();
+        stats.received.add(100);
+        stats.drops.insert(DropReason::Unauthorized, 2);
+        let table = render_stats(&stats);
+        assert!(table
+            .lines()
+            .any(|line| line.starts_with("received from network")
+                && line.ends_with(" 1            100")));
+        assert!(table
+            .lines()
+            .any(|line| line.starts_with("Unauthorized") && line.ends_with(" 2")));
+    }
+}
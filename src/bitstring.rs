@@ -0,0 +1,910 @@
+//! Bitstring of the BIER packets and of the BIFT entries. The module only depends on `core`
+//! and `alloc`, and is available without the `std` feature, e.g., for an embedded dataplane.
+
+use crate::{Error, Result};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::str::FromStr;
+use serde::ser::SerializeStruct;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Maximum number of 64-bit words of a bitstring, i.e., a BSL of 4096 bits.
+pub const MAX_BITSTRING_WORDS: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Bitstring {
+    pub bitstring: Vec<u64>,
+}
+
+impl Bitstring {
+    /// BitString Length, in bits.
+    pub fn bsl(&self) -> usize {
+        self.bitstring.len() * 64
+    }
+
+    /// Updates the bitstring in place. Both bitstrings must have the same length: their words
+    /// would not be aligned otherwise.
+    pub fn update(&mut self, other: &Bitstring, bitop: BitstringOp) -> Result<()> {
+        if self.bsl() != other.bsl() {
+            return Err(Error::BitstringLengthMismatch {
+                expected: self.bsl(),
+                actual: other.bsl(),
+            });
+        }
+        for (bw_self, bw_other) in self.bitstring.iter_mut().zip(other.bitstring.iter()) {
+            match bitop {
+                BitstringOp::And => *bw_self &= bw_other,
+                BitstringOp::AndNot => *bw_self &= !bw_other,
+            }
+        }
+        Ok(())
+    }
+
+    /// Splits the bitstring in bitstrings of `nb_words` words, one per set identifier
+    /// (SI), from the least significant bits. Only the bitstrings with a bit set are returned.
+    pub fn split(&self, nb_words: usize) -> impl Iterator<Item = (usize, Bitstring)> + '_ {
+        let nb_words = nb_words.max(1);
+        let nb_sets = self.bitstring.len().div_ceil(nb_words);
+        (0..nb_sets).filter_map(move |si| {
+            let mut bitstring = vec![0; nb_words];
+            for (idx, word) in bitstring.iter_mut().rev().enumerate() {
+                let word_idx = si * nb_words + idx;
+                if word_idx < self.bitstring.len() {
+                    *word = self.bitstring[self.bitstring.len() - 1 - word_idx];
+                }
+            }
+            bitstring
+                .iter()
+                .any(|word| *word != 0)
+                .then_some((si, Bitstring { bitstring }))
+        })
+    }
+
+    /// New bitstring with the bits set in both `self` and `other`.
+    pub fn and(&self, other: &Bitstring) -> Bitstring {
+        let mut and = Bitstring::default();
+        and.set_and(&self.bitstring, &other.bitstring);
+        and
+    }
+
+    /// Replaces the words of the bitstring by the ones of `words` masked by `mask`, as `and`
+    /// but reusing the allocation of the bitstring, e.g., for the copies of each packet.
+    pub fn set_and(&mut self, words: &[u64], mask: &[u64]) {
+        self.bitstring.clear();
+        self.bitstring.extend(
+            words
+                .iter()
+                .zip(mask.iter())
+                .map(|(word, mask)| word & mask),
+        );
+    }
+
+    /// Bitstring of `bsl` bits with the bits of `bfr_ids`, and its set identifier (SI). The
+    /// SI `si` holds the BFR-ids `si * bsl + 1` to `(si + 1) * bsl`, whose bits are 1 to
+    /// `bsl`: all the BFR-ids must be in the same SI.
+    pub fn from_bfr_ids(bfr_ids: &[u16], bsl: usize) -> Result<(u8, Bitstring)> {
+        if !Bitstring::is_valid_bsl(bsl) {
+            return Err(Error::BitstringLength);
+        }
+        let mut bitstring = Bitstring {
+            bitstring: vec![0; bsl / 64],
+        };
+        let mut si = None;
+        for bfr_id in bfr_ids {
+            let bit = (*bfr_id as usize).checked_sub(1).ok_or(Error::BitstringLength)?;
+            let bfr_si = u8::try_from(bit / bsl).map_err(|_| Error::SetIdentifier)?;
+            if *si.get_or_insert(bfr_si) != bfr_si {
+                return Err(Error::SetIdentifier);
+            }
+            bitstring.set((bit % bsl + 1) as u64)?;
+        }
+        Ok((si.unwrap_or(0), bitstring))
+    }
+
+    /// BFR-ids of the bits set, in increasing order, if the bitstring is in the SI `si`, i.e.,
+    /// the reverse of `from_bfr_ids`. The BFR-ids above 65535 are skipped.
+    pub fn to_bfr_ids(&self, si: u8) -> Vec<u16> {
+        let offset = si as u64 * self.bitstring.len() as u64 * 64;
+        self.iter_set_positions()
+            .filter_map(|bit| u16::try_from(offset + bit).ok())
+            .collect()
+    }
+
+    /// Index of the word holding the bit of `bfr_id`, and the mask of the bit in this word,
+    /// if `bfr_id` is between 1 and the BSL.
+    fn position(&self, bfr_id: u64) -> Option<(usize, u64)> {
+        let bit = bfr_id.checked_sub(1)?;
+        let from_last = usize::try_from(bit / 64).ok()?;
+        let idx_word = self.bitstring.len().checked_sub(from_last + 1)?;
+        Some((idx_word, 1 << (bit % 64)))
+    }
+
+    /// Sets the bit of `bfr_id`, relative to the SI of the bitstring, i.e., from 1 to the BSL.
+    pub fn set(&mut self, bfr_id: u64) -> Result<()> {
+        let (idx_word, mask) = self.position(bfr_id).ok_or(Error::BitstringLength)?;
+        self.bitstring[idx_word] |= mask;
+        Ok(())
+    }
+
+    /// Clears the bit of `bfr_id`. The bitstring is unchanged if it does not hold `bfr_id`.
+    pub fn clear(&mut self, bfr_id: u64) {
+        if let Some((idx_word, mask)) = self.position(bfr_id) {
+            self.bitstring[idx_word] &= !mask;
+        }
+    }
+
+    pub fn is_set(&self, bfr_id: u64) -> bool {
+        self.position(bfr_id)
+            .is_some_and(|(idx_word, mask)| self.bitstring[idx_word] & mask != 0)
+    }
+
+    /// Number of bits set.
+    pub fn count_ones(&self) -> u32 {
+        self.bitstring.iter().map(|word| word.count_ones()).sum()
+    }
+
+    /// Whether no bit is set.
+    pub fn is_empty(&self) -> bool {
+        self.bitstring.iter().all(|word| *word == 0)
+    }
+
+    /// BFR-ids of the bits set, relative to the SI of the bitstring, in increasing order.
+    pub fn iter_set_positions(&self) -> impl Iterator<Item = u64> + '_ {
+        self.bitstring
+            .iter()
+            .rev()
+            .enumerate()
+            .flat_map(|(from_last, word)| {
+                let mut word = *word;
+                core::iter::from_fn(move || {
+                    if word == 0 {
+                        return None;
+                    }
+                    let bit = word.trailing_zeros() as u64;
+                    word &= word - 1;
+                    Some(from_last as u64 * 64 + bit + 1)
+                })
+            })
+    }
+
+    /// Mask of the bits of the word `idx_word` representing BFR-ids up to `bfr_id`.
+    fn mask_up_to(&self, idx_word: usize, bfr_id: u64) -> u64 {
+        // The last word holds the BFR-ids 1 to 64.
+        let first_bfr_id = (self.bitstring.len() - 1 - idx_word) as u64 * 64;
+        match bfr_id.saturating_sub(first_bfr_id) {
+            n if n >= 64 => u64::MAX,
+            n => (1 << n) - 1,
+        }
+    }
+
+    /// Number of bits set for BFR-ids above `bfr_id`.
+    pub fn count_above(&self, bfr_id: u64) -> u32 {
+        self.bitstring
+            .iter()
+            .enumerate()
+            .map(|(idx, word)| (word & !self.mask_up_to(idx, bfr_id)).count_ones())
+            .sum()
+    }
+
+    /// Clears the bits of the BFR-ids above `bfr_id`.
+    pub fn clear_above(&mut self, bfr_id: u64) {
+        for idx in 0..self.bitstring.len() {
+            self.bitstring[idx] &= self.mask_up_to(idx, bfr_id);
+        }
+    }
+
+    pub fn update_header_from_self(&self, header: &mut [u8]) -> Result<()> {
+        if header.len()
+            < crate::header::BIER_HEADER_WITHOUT_BITSTRING_LENGTH + self.bitstring.len() * 8
+        {
+            return Err(Error::BitstringLength);
+        }
+
+        // Get the bitstring.
+        let bitstring_hdr = &mut header[crate::header::BIER_HEADER_WITHOUT_BITSTRING_LENGTH
+            ..crate::header::BIER_HEADER_WITHOUT_BITSTRING_LENGTH + self.bitstring.len() * 8];
+
+        // Write the words directly in the header, without intermediate copy.
+        for (hdr_word, word) in bitstring_hdr.chunks_exact_mut(8).zip(self.bitstring.iter()) {
+            hdr_word.copy_from_slice(&word.to_be_bytes());
+        }
+
+        Ok(())
+    }
+
+    pub fn is_valid(slice: &[u8]) -> bool {
+        matches!(slice.len(), 8 | 16 | 32 | 64 | 128 | 256 | 512)
+    }
+
+    pub fn is_valid_from_u64(slice: &[u64]) -> bool {
+        matches!(slice.len(), 1 | 2 | 4 | 8 | 16 | 32 | 64)
+    }
+
+    /// Whether `bsl` is a BitString Length of RFC 8296, in bits.
+    pub fn is_valid_bsl(bsl: usize) -> bool {
+        matches!(bsl, 64 | 128 | 256 | 512 | 1024 | 2048 | 4096)
+    }
+
+    /// Hexadecimal string of the bitstring, e.g., `0x00ff`, with 16 digits per word, most
+    /// significant digit first. `FromStr` parses it back.
+    pub fn to_hex(&self) -> String {
+        self.bitstring
+            .iter()
+            .fold("0x".to_string(), |s, v| s + &format!("{:016x}", v))
+    }
+
+    /// Bitstring of `bsl` bits with the `bits`, from 1 to `bsl`. Without `bsl`, the bitstring
+    /// has the smallest BSL holding the highest bit.
+    pub fn from_bits(bits: &[u64], bsl: Option<usize>) -> Result<Bitstring> {
+        let highest = bits.iter().max().copied().unwrap_or(1);
+        let bsl = match bsl {
+            Some(bsl) => bsl,
+            None => usize::try_from(highest)
+                .map_err(|_| Error::BitstringLength)?
+                .checked_next_power_of_two()
+                .ok_or(Error::BitstringLength)?
+                .max(64),
+        };
+        if !Bitstring::is_valid_bsl(bsl) {
+            return Err(Error::BitstringLength);
+        }
+        let mut bitstring = Bitstring {
+            bitstring: vec![0; bsl / 64],
+        };
+        for bit in bits {
+            bitstring.set(*bit)?;
+        }
+        Ok(bitstring)
+    }
+
+    /// The bitstring, serialized in `format` instead of the binary string of `Serialize`.
+    pub fn formatted(&self, format: BitstringFormat) -> FormattedBitstring<'_> {
+        FormattedBitstring {
+            bitstring: self,
+            format,
+        }
+    }
+}
+
+/// Forms of a bitstring in the JSON configurations. All of them are accepted when
+/// deserializing a bitstring.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BitstringFormat {
+    /// `"1010"`, most significant bit first.
+    #[default]
+    Binary,
+    /// `"0x000000000000000a"`, most significant digit first.
+    Hex,
+    /// `{"bits": [2, 4], "bsl": 64}`, the positions of the bits set.
+    Bits,
+}
+
+impl FromStr for BitstringFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        match s {
+            "binary" => Ok(BitstringFormat::Binary),
+            "hex" => Ok(BitstringFormat::Hex),
+            "bits" => Ok(BitstringFormat::Bits),
+            _ => Err(format!("Unknown bitstring format: {}", s)),
+        }
+    }
+}
+
+/// Bitstring serialized in a `BitstringFormat`, see `Bitstring::formatted`.
+pub struct FormattedBitstring<'a> {
+    bitstring: &'a Bitstring,
+    format: BitstringFormat,
+}
+
+impl Serialize for FormattedBitstring<'_> {
+    fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self.format {
+            BitstringFormat::Binary => self.bitstring.serialize(serializer),
+            BitstringFormat::Hex => serializer.serialize_str(&self.bitstring.to_hex()),
+            BitstringFormat::Bits => {
+                let bits: Vec<u64> = self.bitstring.iter_set_positions().collect();
+                let mut state = serializer.serialize_struct("Bits", 2)?;
+                state.serialize_field("bits", &bits)?;
+                state.serialize_field("bsl", &self.bitstring.bsl())?;
+                state.end()
+            }
+        }
+    }
+}
+
+/// `{"bits": [...], "bsl": ...}` form of a bitstring.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Bits {
+    bits: Vec<u64>,
+    #[serde(default)]
+    bsl: Option<usize>,
+}
+
+struct BitstringVisitor;
+
+impl<'de> de::Visitor<'de> for BitstringVisitor {
+    type Value = Bitstring;
+
+    fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
+        formatter.write_str("a binary or \"0x\" hex string, or a map with the \"bits\" set")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> core::result::Result<Bitstring, E> {
+        FromStr::from_str(v).map_err(de::Error::custom)
+    }
+
+    fn visit_map<A>(self, map: A) -> core::result::Result<Bitstring, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        let bits = Bits::deserialize(de::value::MapAccessDeserializer::new(map))?;
+        Bitstring::from_bits(&bits.bits, bits.bsl).map_err(de::Error::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Bitstring {
+    fn deserialize<D>(deserializer: D) -> core::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(BitstringVisitor)
+    }
+}
+
+impl Serialize for Bitstring {
+    fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        // The first word holds the most significant bits, as for `FromStr`.
+        let a: String = self
+            .bitstring
+            .iter()
+            .fold(String::new(), |s, v| s + &format!("{:064b}", v));
+        serializer.serialize_str(&a)
+    }
+}
+
+impl TryFrom<Vec<u64>> for Bitstring {
+    type Error = crate::Error;
+
+    fn try_from(slice: Vec<u64>) -> crate::Result<Self> {
+        if !Bitstring::is_valid_from_u64(&slice[..]) {
+            return Err(crate::Error::BitstringLength);
+        }
+
+        Ok(Bitstring { bitstring: slice })
+    }
+}
+
+impl TryFrom<&[u8]> for Bitstring {
+    type Error = crate::Error;
+
+    fn try_from(value: &[u8]) -> crate::Result<Self> {
+        if !Bitstring::is_valid(value) {
+            return Err(crate::Error::BitstringLength);
+        }
+
+        // The slice may not be aligned on 64 bits, e.g., in the middle of an API message.
+        Ok(Bitstring {
+            bitstring: value
+                .chunks_exact(8)
+                .map(|word| u64::from_be_bytes(word.try_into().unwrap()))
+                .collect(),
+        })
+    }
+}
+
+impl FromStr for Bitstring {
+    type Err = String;
+
+    fn from_str(str_bitstring: &str) -> core::result::Result<Self, Self::Err> {
+        if let Some(hex) = str_bitstring.strip_prefix("0x") {
+            // 16 digits per word, left-padded as the binary strings.
+            let len_of_64_bits = hex.len().div_ceil(16);
+            if !matches!(len_of_64_bits, 1 | 2 | 4 | 8 | 16 | 32 | 64) || !hex.is_ascii() {
+                return Err("String to bitstring not correct length".to_string());
+            }
+            let padded = "0".repeat(len_of_64_bits * 16 - hex.len()) + hex;
+            return padded
+                .as_bytes()
+                .chunks(16)
+                .map(|window| {
+                    u64::from_str_radix(core::str::from_utf8(window).unwrap(), 16)
+                        .map_err(|e| format!("Impossible to parse the bitstring: {:?}", e))
+                })
+                .collect::<core::result::Result<_, _>>()
+                .map(|bitstring| Bitstring { bitstring });
+        }
+        let len_of_64_bits = str_bitstring.len().div_ceil(64);
+        if !matches!(len_of_64_bits, 1 | 2 | 4 | 8 | 16 | 32 | 64) {
+            return Err("String to bitstring not correct length".to_string());
+        }
+        // Left-pad with zeros so that the last word holds the least significant bits.
+        let padded = "0".repeat(len_of_64_bits * 64 - str_bitstring.len()) + str_bitstring;
+        let r = padded.chars().collect::<Vec<char>>().chunks(64).map(|window| {
+            u64::from_str_radix(&String::from_iter(window), 2)
+        }).collect();
+
+
+        match r {
+            Ok(v) => Ok(Bitstring { bitstring: v }),
+            Err(e) => Err(format!("Impossible to parse the bitstring: {:?}", e).to_string()),
+        }
+
+        // match (0..len_of_64_bits)
+        //     .map(|i| {
+        //         let lower_bound = str_bitstring.len().saturating_sub(64 * (i + 1));
+        //         let upper_bound = usize::min(lower_bound + 64, str_bitstring.len());
+        //         let substr = &str_bitstring[lower_bound..upper_bound];
+        //         u64::from_str_radix(substr, 2)
+        //     })
+        //     .collect()
+        // {
+        //     Ok(v) => Ok(Bitstring { bitstring: v }),
+        //     Err(e) => Err(format!("Impossible to parse: {:?}", e)),
+        // }
+
+    }
+}
+
+impl From<&Bitstring> for Vec<u8> {
+    fn from(bitstring: &Bitstring) -> Self {
+        bitstring
+            .bitstring
+            .iter()
+            .flat_map(|elem| elem.to_be_bytes())
+            .collect()
+    }
+}
+
+impl Default for Bitstring {
+    fn default() -> Self {
+        Self {
+            bitstring: vec![0; 1],
+        }
+    }
+}
+
+pub enum BitstringOp {
+    And = 1,
+    AndNot = 2,
+}
+
+/// Strategy of the property tests generating the bitstrings of every valid length. Most
+/// words are zero, full or sparse, so that the bitstrings have leading zeros, empty words and
+/// a single bit set.
+#[cfg(all(test, feature = "std"))]
+pub(crate) fn arbitrary_bitstring() -> impl proptest::strategy::Strategy<Value = Bitstring> {
+    use proptest::prelude::*;
+
+    let word = prop_oneof![
+        Just(0),
+        Just(u64::MAX),
+        (0..64u32).prop_map(|bit| 1 << bit),
+        any::<u64>(),
+    ];
+    prop::sample::select(vec![1usize, 2, 4, 8, 16, 32, 64])
+        .prop_flat_map(move |nb_words| prop::collection::vec(word.clone(), nb_words))
+        .prop_map(|bitstring| Bitstring { bitstring })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[cfg(feature = "std")]
+    use proptest::prelude::*;
+
+    #[test]
+    /// Tests the manipulation of the bits of a bitstring by BFR-id.
+    fn test_bitstring_bits() {
+        let mut bitstring = Bitstring {
+            bitstring: vec![0; 2],
+        };
+        assert!(bitstring.is_empty());
+        for bfr_id in [1, 64, 65, 128, 3] {
+            bitstring.set(bfr_id).unwrap();
+        }
+        assert_eq!(bitstring.bitstring, vec![1 << 63 | 1, 1 << 63 | 0b101]);
+        assert_eq!(bitstring.count_ones(), 5);
+        assert_eq!(
+            bitstring.iter_set_positions().collect::<Vec<_>>(),
+            vec![1, 3, 64, 65, 128]
+        );
+        assert!(bitstring.is_set(65));
+        assert!(!bitstring.is_set(66) && !bitstring.is_set(0) && !bitstring.is_set(129));
+
+        // The BFR-ids outside of the bitstring are refused.
+        assert_eq!(bitstring.set(0), Err(Error::BitstringLength));
+        assert_eq!(bitstring.set(129), Err(Error::BitstringLength));
+        for bfr_id in [65, 129, 0, 3] {
+            bitstring.clear(bfr_id);
+        }
+        assert_eq!(bitstring.bitstring, vec![1 << 63, 1 << 63 | 1]);
+        assert!(!bitstring.is_empty());
+
+        // The positions are the bits of the serialization, from the right.
+        let bitstring = Bitstring::from_str("1001000").unwrap();
+        assert_eq!(bitstring.iter_set_positions().collect::<Vec<_>>(), vec![4, 7]);
+    }
+
+    #[test]
+    /// Tests the bitstrings built from BFR-ids, in the SI of the BFR-ids.
+    fn test_bitstring_bfr_ids() {
+        let (si, bitstring) = Bitstring::from_bfr_ids(&[17, 3, 5], 64).unwrap();
+        assert_eq!(si, 0);
+        assert_eq!(bitstring, Bitstring::from_str("10000000000010100").unwrap());
+        assert_eq!(bitstring.to_bfr_ids(si), vec![3, 5, 17]);
+
+        // The BFR-ids 257 to 512 are in the SI 1 of the BSL 256.
+        let (si, bitstring) = Bitstring::from_bfr_ids(&[257, 300, 512], 256).unwrap();
+        assert_eq!(si, 1);
+        assert_eq!(bitstring.bitstring.len(), 4);
+        assert_eq!(bitstring.iter_set_positions().collect::<Vec<_>>(), vec![1, 44, 256]);
+        assert_eq!(bitstring.to_bfr_ids(si), vec![257, 300, 512]);
+
+        assert_eq!(Bitstring::from_bfr_ids(&[], 128).unwrap().0, 0);
+        assert_eq!(Bitstring::from_bfr_ids(&[64, 65], 64), Err(Error::SetIdentifier));
+        assert_eq!(Bitstring::from_bfr_ids(&[65535], 64), Err(Error::SetIdentifier));
+        assert_eq!(Bitstring::from_bfr_ids(&[0], 64), Err(Error::BitstringLength));
+        assert_eq!(Bitstring::from_bfr_ids(&[1], 96), Err(Error::BitstringLength));
+        assert_eq!(Bitstring::from_bfr_ids(&[1], 8192), Err(Error::BitstringLength));
+    }
+
+    #[cfg(feature = "std")]
+    proptest! {
+        #[test]
+        /// Tests that `FromStr` and `Serialize` are inverses, and that the bytes of the
+        /// bitstrings are parsed back to the same bitstrings.
+        fn test_bitstring_round_trip(bitstring in arbitrary_bitstring()) {
+            let serialized = serde_json::to_value(&bitstring).unwrap();
+            let serialized = serialized.as_str().unwrap();
+            prop_assert_eq!(serialized.len(), bitstring.bitstring.len() * 64);
+            prop_assert_eq!(&Bitstring::from_str(serialized).unwrap(), &bitstring);
+
+            let bytes: Vec<u8> = (&bitstring).into();
+            prop_assert_eq!(&Bitstring::try_from(&bytes[..]).unwrap(), &bitstring);
+
+            for format in [BitstringFormat::Hex, BitstringFormat::Bits] {
+                let serialized = serde_json::to_value(bitstring.formatted(format)).unwrap();
+                let parsed: Bitstring = serde_json::from_value(serialized).unwrap();
+                prop_assert_eq!(&parsed, &bitstring);
+            }
+        }
+
+        #[test]
+        /// Tests the parsing of random binary strings of any length, with leading zeros: they
+        /// are valid if they fit in a number of words allowed by RFC 8296, and are serialized
+        /// left-padded with zeros.
+        fn test_bitstring_from_arbitrary_str(
+            (zeros, bits) in (1..=64 * 64 + 64usize)
+                .prop_flat_map(|length| (0..=length, prop::collection::vec(any::<bool>(), length)))
+        ) {
+            let string: String = bits
+                .iter()
+                .enumerate()
+                .map(|(idx, bit)| match idx < zeros || !bit {
+                    true => '0',
+                    false => '1',
+                })
+                .collect();
+            let nb_words = string.len().div_ceil(64);
+            match Bitstring::from_str(&string) {
+                Ok(bitstring) => {
+                    prop_assert_eq!(bitstring.bitstring.len(), nb_words);
+                    let serialized = serde_json::to_value(&bitstring).unwrap();
+                    let serialized = serialized.as_str().unwrap();
+                    prop_assert_eq!(
+                        serialized.trim_start_matches('0'),
+                        string.trim_start_matches('0')
+                    );
+                    prop_assert_eq!(serialized.len(), nb_words * 64);
+                }
+                Err(_) => prop_assert!(!matches!(nb_words, 1 | 2 | 4 | 8 | 16 | 32 | 64)),
+            }
+        }
+    }
+
+    #[test]
+    /// Tests the update of a bitstring.
+    fn test_update_bitstring() {
+        let bitstring = Bitstring::from_str("1101");
+        assert!(bitstring.is_ok());
+        let mut bitstring = bitstring.unwrap();
+
+        bitstring
+            .update(&Bitstring::from_str("1011").unwrap(), BitstringOp::And)
+            .unwrap();
+        assert_eq!(bitstring.bitstring[0], 0b1001);
+
+        bitstring
+            .update(&Bitstring::from_str("0011").unwrap(), BitstringOp::AndNot)
+            .unwrap();
+        assert_eq!(bitstring.bitstring[0], 0b1000);
+
+        // The words of bitstrings of different lengths are not aligned.
+        let longer = Bitstring::from_str(&"1".repeat(128)).unwrap();
+        assert_eq!(
+            bitstring.update(&longer, BitstringOp::AndNot),
+            Err(Error::BitstringLengthMismatch {
+                expected: 64,
+                actual: 128
+            })
+        );
+        assert_eq!(bitstring.bitstring, vec![0b1000]);
+
+        let bitstring = Bitstring::from_str("1101").unwrap();
+        let mut and = bitstring.and(&Bitstring::from_str("0111").unwrap());
+        assert_eq!(and.bitstring, vec![0b0101]);
+        and.set_and(&[0b1100, 0b1010], &[0b0110, 0b0011]);
+        assert_eq!(and.bitstring, vec![0b0100, 0b0010]);
+        assert_eq!(bitstring.bitstring, vec![0b1101]);
+    }
+
+    #[test]
+    /// Tests the split of a bitstring in the bitstrings of a smaller BSL.
+    fn test_bitstring_split() {
+        let bitstring = Bitstring {
+            bitstring: vec![0b1, 0, 0, 0b101],
+        };
+        let sets: Vec<_> = bitstring.split(1).collect();
+        assert_eq!(
+            sets,
+            vec![
+                (0, Bitstring { bitstring: vec![0b101] }),
+                (3, Bitstring { bitstring: vec![0b1] }),
+            ]
+        );
+        let sets: Vec<_> = bitstring.split(2).collect();
+        assert_eq!(
+            sets,
+            vec![
+                (0, Bitstring { bitstring: vec![0, 0b101] }),
+                (1, Bitstring { bitstring: vec![0b1, 0] }),
+            ]
+        );
+        assert_eq!(bitstring.split(4).next().unwrap().1, bitstring);
+    }
+
+    #[test]
+    /// Tests the function returning if a bitstring given as input is valid
+    /// following RFC 8279.
+    fn test_bitstring_is_valid() {
+        for i in 0..6 {
+            let bitstring = vec![0u8; 8 << i];
+            assert!(Bitstring::is_valid(&bitstring[..]));
+            assert!(!Bitstring::is_valid(&bitstring[1..]));
+        }
+    }
+
+    #[test]
+    /// Tests the parsing of bitstring from &[u8].
+    fn test_bitstring_from_slice_u8_simple() {
+        let raw = [0u8, 0, 0, 0, 0, 0, 0, 1];
+        let res: Result<Bitstring> = raw.as_ref().try_into();
+        assert!(res.is_ok());
+        let res = res.unwrap();
+        assert_eq!(res.bitstring.len(), 1);
+        assert_eq!(res.bitstring[0], 1);
+
+        let raw = [0u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff];
+        let res: Result<Bitstring> = raw.as_ref().try_into();
+        assert!(res.is_ok());
+        let res = res.unwrap();
+        assert_eq!(res.bitstring.len(), 2);
+        assert_eq!(res.bitstring[0], 0);
+        assert_eq!(res.bitstring[1], 0xffff);
+
+        // Wrong bitstring.
+        let raw = [0u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff];
+        let res: Result<Bitstring> = raw.as_ref().try_into();
+        assert!(res.is_err());
+
+        let raw = [0u8, 0, 0, 0, 0xff, 0xff];
+        let res: Result<Bitstring> = raw.as_ref().try_into();
+        assert!(res.is_err());
+
+        let raw: [u8; 0] = [];
+        let res: Result<Bitstring> = raw.as_ref().try_into();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    /// Tests the conversion of a Bitstring to a Vec<u8> method.
+    fn test_vec_u8_from_bitstring() {
+        let raw = [0u8, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let res: Result<Bitstring> = raw.as_ref().try_into();
+        assert!(res.is_ok());
+        let res = res.unwrap();
+        assert_eq!(res.bitstring.len(), 2);
+
+        // Convert to an array of u8.
+        let res_u8: Vec<u8> = (&res).into();
+        assert_eq!(res_u8.len(), 16);
+        assert_eq!(res_u8, raw);
+    }
+
+    #[test]
+    /// Tests the bitstring from Vec<u64>.
+    fn test_bitstring_from_vec_u64() {
+        let valid_bitstring_length_u64 = [1, 2, 4, 8, 16, 32, 64];
+
+        for i in 0..100 {
+            let v: Vec<_> = (0..i).collect();
+            let bitstring = v.clone().try_into();
+            if valid_bitstring_length_u64.contains(&i) {
+                assert!(bitstring.is_ok());
+                let bitstring: Bitstring = bitstring.unwrap();
+                assert_eq!(bitstring.bitstring, v);
+            } else {
+                assert!(bitstring.is_err());
+                assert_eq!(bitstring.unwrap_err(), crate::Error::BitstringLength);
+            }
+        }
+    }
+
+    #[test]
+    /// Tests the bitstring from &[u8].
+    fn test_bitstring_from_slice_u8() {
+        let valid_bitstring_length_u64: Vec<_> = [1, 2, 4, 8, 16, 32, 64].iter().map(|i| i * 8).collect();
+
+        for i in 0..1000 {
+            let v: Vec<u8> = (0..i).map(|i| (i % 255) as u8).collect();
+            let bitstring: Result<Bitstring> = (&v[..]).try_into();
+            if valid_bitstring_length_u64.contains(&i) {
+                assert!(bitstring.is_ok());
+            } else {
+                assert!(bitstring.is_err());
+                assert_eq!(bitstring.unwrap_err(), crate::Error::BitstringLength);
+            }
+        }
+    }
+
+    #[test]
+    /// Tests the bitstring from str (FromStr trait).
+    /// For this test, every bitstring (up to 4098 characters) should be accepted
+    /// if they contain only 0's and 1's.
+    fn test_bitstring_from_str() {
+        let s = "1";
+        let bitstring = Bitstring::from_str(s);
+        assert!(bitstring.is_ok());
+        let bitstring = bitstring.unwrap();
+        assert_eq!(bitstring.bitstring, vec![1]);
+
+        let s = "1".repeat(64);
+        let bitstring = Bitstring::from_str(&s);
+        assert!(bitstring.is_ok());
+        let bitstring = bitstring.unwrap();
+        assert_eq!(bitstring.bitstring, vec![0xffffffffffffffff]);
+
+        // The string is left-padded: the first word holds the most significant bits.
+        let s = "1".repeat(65);
+        let bitstring = Bitstring::from_str(&s);
+        assert!(bitstring.is_ok());
+        let bitstring = bitstring.unwrap();
+        assert_eq!(bitstring.bitstring, vec![1, 0xffffffffffffffff]);
+        assert_eq!(bitstring.bitstring[0], 1);
+        assert_eq!(bitstring.bitstring[1], 0xffffffffffffffff);
+
+        let s = "1".repeat(128);
+        let bitstring = Bitstring::from_str(&s);
+        assert!(bitstring.is_ok());
+        let bitstring = bitstring.unwrap();
+        assert_eq!(bitstring.bitstring, vec![0xffffffffffffffff, 0xffffffffffffffff]);
+        assert_eq!(bitstring.bitstring[0], 0xffffffffffffffff);
+        assert_eq!(bitstring.bitstring[1], 0xffffffffffffffff);
+
+        let s = "1".repeat(4096);
+        let bitstring = Bitstring::from_str(&s);
+        assert!(bitstring.is_ok());
+        let bitstring = bitstring.unwrap();
+        assert_eq!(bitstring.bitstring, vec![0xffffffffffffffff; 64]);
+
+        let s = "1".repeat(4097);
+        let bitstring = Bitstring::from_str(&s);
+        assert!(bitstring.is_err());
+
+        let s = "1".repeat(6000);
+        let bitstring = Bitstring::from_str(&s);
+        assert!(bitstring.is_err());
+    }
+
+    #[test]
+    /// Tests the bitstring from str (FromStr trait).
+    /// For this test, every bitstring (up to 4098 characters) should be accepted
+    /// if they contain only 0's and 1's.
+    fn test_bitstring_from_str_value() {
+        let s = "1010001011";
+        let bitstring = Bitstring::from_str(s);
+        assert!(bitstring.is_ok());
+        let bitstring = bitstring.unwrap();
+        assert_eq!(bitstring.bitstring, vec![0b1010001011]);
+
+        let s = "1010001012";
+        let bitstring = Bitstring::from_str(s);
+        assert!(bitstring.is_err());
+
+        let s = "1010f01011";
+        let bitstring = Bitstring::from_str(s);
+        assert!(bitstring.is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    /// Tests that the serialization of a multi-word bitstring is parsed back
+    /// to the same bitstring.
+    fn test_bitstring_serialize_multi_word() {
+        let bitstring = Bitstring::from_str(&format!("101{}", "0".repeat(64))).unwrap();
+        assert_eq!(bitstring.bitstring, vec![0b101, 0]);
+
+        let res = serde_json::to_string(&bitstring).unwrap();
+        assert_eq!(res, format!("\"{}101{}\"", "0".repeat(61), "0".repeat(64)));
+
+        let bitstring_after: Bitstring = serde_json::from_str(&res).unwrap();
+        assert_eq!(bitstring, bitstring_after);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    /// Tests the hex and bit-position forms of the bitstrings.
+    fn test_bitstring_formats() {
+        let bitstring = Bitstring {
+            bitstring: vec![0b101, 0xff00],
+        };
+        let hex = "0x0000000000000005000000000000ff00";
+        assert_eq!(bitstring.to_hex(), hex);
+        assert_eq!(Bitstring::from_str(hex).unwrap(), bitstring);
+        let bits = serde_json::json!({"bits": [9, 10, 11, 12, 13, 14, 15, 16, 65, 67], "bsl": 128});
+        assert_eq!(
+            serde_json::to_value(bitstring.formatted(BitstringFormat::Bits)).unwrap(),
+            bits
+        );
+        assert_eq!(serde_json::from_value::<Bitstring>(bits).unwrap(), bitstring);
+        assert_eq!(
+            serde_json::to_value(bitstring.formatted(BitstringFormat::Hex)).unwrap(),
+            hex
+        );
+
+        // The short hex strings are left-padded, and the bits without BSL have the smallest one.
+        assert_eq!(Bitstring::from_str("0x1f").unwrap().bitstring, vec![0x1f]);
+        let bitstring: Bitstring = serde_json::from_str(r#"{"bits": [1, 5, 17]}"#).unwrap();
+        assert_eq!(bitstring.bitstring, vec![0x10011]);
+        let bitstring: Bitstring = serde_json::from_str(r#"{"bits": [100]}"#).unwrap();
+        assert_eq!(bitstring.bsl(), 128);
+        let bitstring: Bitstring = serde_json::from_str(r#"{"bits": []}"#).unwrap();
+        assert_eq!(bitstring, Bitstring::default());
+
+        // Invalid lengths, digits, bits and fields.
+        assert!(Bitstring::from_str("0x").is_err());
+        assert!(Bitstring::from_str(&format!("0x{}", "1".repeat(48))).is_err());
+        assert!(Bitstring::from_str("0x1g").is_err());
+        for invalid in [
+            r#"{"bits": [0]}"#,
+            r#"{"bits": [65], "bsl": 64}"#,
+            r#"{"bits": [1], "bsl": 100}"#,
+            r#"{"bits": [4097]}"#,
+            r#"{"bits": [1], "si": 1}"#,
+            r#"{"bsl": 64}"#,
+            "42",
+        ] {
+            assert!(serde_json::from_str::<Bitstring>(invalid).is_err(), "{}", invalid);
+        }
+        // The BSL of the highest bit overflows.
+        assert_eq!(
+            Bitstring::from_bits(&[u64::MAX], None),
+            Err(Error::BitstringLength)
+        );
+
+        assert_eq!("hex".parse(), Ok(BitstringFormat::Hex));
+        assert!("octal".parse::<BitstringFormat>().is_err());
+    }
+}
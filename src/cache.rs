@@ -0,0 +1,155 @@
+//! Cache of the results of the BIER processing. The packets of a stable flow carry the
+//! same bitstring, so that their copies are found without scanning the bitstring again.
+//!
+//! The cached results are only valid for the state from which they were computed:
+//! the cache must be cleared whenever the BIFTs change.
+
+use crate::bier::{BierSendInfo, BierState, Bitstring, PathSelection};
+use crate::Result;
+use lru::LruCache;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::num::NonZeroUsize;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// Copies of a packet, computed for this packet or shared with the cache.
+pub enum NextHops {
+    Computed(Vec<BierSendInfo>),
+    Cached(Arc<[BierSendInfo]>),
+}
+
+impl Deref for NextHops {
+    type Target = [BierSendInfo];
+
+    fn deref(&self) -> &[BierSendInfo] {
+        match self {
+            NextHops::Computed(v) => v,
+            NextHops::Cached(v) => v,
+        }
+    }
+}
+
+/// Result cached for a BIFT-id, a bitstring and an entropy.
+struct CachedResult {
+    bift_id: u32,
+    bitstring: Bitstring,
+    entropy: u32,
+    next_hops: Arc<[BierSendInfo]>,
+}
+
+/// Least recently used results of `BierState::process_bier`, indexed by the hash of
+/// the BIFT-id, the bitstring and the entropy. The errors are not cached.
+pub struct ResultCache {
+    results: LruCache<u64, CachedResult>,
+    hasher: RandomState,
+}
+
+impl ResultCache {
+    /// Keeps at most `capacity` results, at least one.
+    pub fn new(capacity: usize) -> Self {
+        ResultCache {
+            results: LruCache::new(NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN)),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Returns the copies of a packet with `bitstring` and `entropy` in the BIFT `bift_id`,
+    /// and whether they were found in the cache.
+    pub fn process_bier(
+        &mut self,
+        state: &BierState,
+        bitstring: &Bitstring,
+        bift_id: u32,
+        entropy: u32,
+    ) -> Result<(NextHops, bool)> {
+        // The entropy only selects the paths with ECMP: the flows share their results otherwise.
+        let entropy = match state.path_selection {
+            PathSelection::First => 0,
+            PathSelection::Ecmp => entropy,
+        };
+        let hash = self.hasher.hash_one((bift_id, bitstring, entropy));
+        // A collision of the hashes is a miss, and replaces the cached result.
+        if let Some(cached) = self.results.get(&hash) {
+            if cached.bift_id == bift_id
+                && cached.bitstring == *bitstring
+                && cached.entropy == entropy
+            {
+                return Ok((NextHops::Cached(cached.next_hops.clone()), true));
+            }
+        }
+        let next_hops: Arc<[BierSendInfo]> =
+            state.process_bier(bitstring, bift_id, entropy)?.into();
+        self.results.put(
+            hash,
+            CachedResult {
+                bift_id,
+                bitstring: bitstring.clone(),
+                entropy,
+                next_hops: next_hops.clone(),
+            },
+        );
+        Ok((NextHops::Cached(next_hops), false))
+    }
+
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+
+    /// Removes all results, e.g., after a change of the BIFTs.
+    pub fn clear(&mut self) {
+        self.results.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::bier::example_state;
+    use crate::Error;
+    use std::str::FromStr;
+
+    #[test]
+    /// Tests the hits, the eviction and the errors of the cache.
+    fn test_result_cache() {
+        let mut state = example_state();
+        state.compile().unwrap();
+        let mut cache = ResultCache::new(2);
+        let bitstring = Bitstring::from_str("11110").unwrap();
+
+        let (next_hops, hit) = cache.process_bier(&state, &bitstring, 1, 0).unwrap();
+        assert!(!hit);
+        assert_eq!(
+            &*next_hops,
+            &state.process_bier(&bitstring, 1, 0).unwrap()[..]
+        );
+        let (cached, hit) = cache.process_bier(&state, &bitstring, 1, 0).unwrap();
+        assert!(hit);
+        assert_eq!(&*cached, &*next_hops);
+
+        // The least recently used result is evicted.
+        let other = Bitstring::from_str("10").unwrap();
+        assert!(!cache.process_bier(&state, &other, 1, 0).unwrap().1);
+        assert!(
+            !cache
+                .process_bier(&state, &Bitstring::from_str("100").unwrap(), 1, 0)
+                .unwrap()
+                .1
+        );
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.process_bier(&state, &bitstring, 1, 0).unwrap().1);
+
+        assert!(matches!(
+            cache.process_bier(&state, &bitstring, 42, 0),
+            Err(Error::BiftId(42))
+        ));
+        assert_eq!(cache.len(), 2);
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,278 @@
+//! Blocking client to communicate with the BIER daemon.
+
+use crate::api::{
+    message_type, ErrorCode, ErrorNotification, MessageType, Query, RecvInfo, Registration,
+    Response, SendInfo, API_VERSION,
+};
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Maximum length of a message received from the daemon.
+const MAX_MESSAGE_LENGTH: usize = 65535;
+
+/// Application socket connected to the BIER daemon.
+/// The socket file is removed when the client is dropped.
+pub struct BierClient {
+    socket: UnixDatagram,
+    daemon_path: PathBuf,
+    my_path: PathBuf,
+    buffer: Vec<u8>,
+}
+
+impl BierClient {
+    /// Binds the application socket to `my_path`, where the daemon delivers the packets,
+    /// and sends the packets to the daemon at `daemon_path`.
+    /// A stale socket file at `my_path` is removed first.
+    pub fn connect(daemon_path: impl AsRef<Path>, my_path: impl AsRef<Path>) -> io::Result<Self> {
+        let _ = std::fs::remove_file(my_path.as_ref());
+        let socket = UnixDatagram::bind(my_path.as_ref())?;
+
+        Ok(BierClient {
+            socket,
+            daemon_path: daemon_path.as_ref().to_path_buf(),
+            my_path: my_path.as_ref().to_path_buf(),
+            buffer: vec![0u8; MAX_MESSAGE_LENGTH],
+        })
+    }
+
+    /// Sends a payload in the BIER domain.
+    pub fn send(&mut self, send_info: &SendInfo) -> io::Result<()> {
+        let len = send_info.to_slice(&mut self.buffer).map_err(to_io_error)?;
+        self.socket.send_to(&self.buffer[..len], &self.daemon_path)?;
+        Ok(())
+    }
+
+    /// Blocks until the daemon delivers a packet. If a previous message of the application
+    /// was dropped by the daemon, returns an error wrapping its `ErrorNotification`, e.g.,
+    /// `e.get_ref().and_then(|e| e.downcast_ref::<ErrorNotification>())`, of kind
+    /// `PermissionDenied` for an unauthorized message and `InvalidInput` otherwise.
+    pub fn recv(&mut self) -> io::Result<RecvInfo<'_>> {
+        self.socket.set_read_timeout(None)?;
+        let read = self.socket.recv(&mut self.buffer)?;
+        check_notification(&self.buffer[..read])?;
+        RecvInfo::from_slice(&self.buffer[..read]).map_err(to_io_error)
+    }
+
+    /// Same as `recv`, but returns `None` if no packet is delivered within `timeout`.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> io::Result<Option<RecvInfo<'_>>> {
+        self.socket.set_read_timeout(Some(timeout))?;
+        let read = match self.socket.recv(&mut self.buffer) {
+            Ok(read) => read,
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+        check_notification(&self.buffer[..read])?;
+        RecvInfo::from_slice(&self.buffer[..read])
+            .map(Some)
+            .map_err(to_io_error)
+    }
+
+    /// Queries information from the daemon and waits for the response.
+    pub fn query(&mut self, query: Query, timeout: Duration) -> io::Result<Response> {
+        let len = query.to_slice(&mut self.buffer).map_err(to_io_error)?;
+        self.socket.send_to(&self.buffer[..len], &self.daemon_path)?;
+
+        self.socket.set_read_timeout(Some(timeout))?;
+        let read = self.socket.recv(&mut self.buffer)?;
+        check_notification(&self.buffer[..read])?;
+        Response::from_slice(&self.buffer[..read]).map_err(to_io_error)
+    }
+
+    /// Registers the application to receive the packets with `proto`, and waits for the
+    /// acknowledgment of the daemon.
+    pub fn register(&mut self, proto: u16, timeout: Duration) -> io::Result<()> {
+        let path = self.my_path.to_str().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "The path is not valid UTF-8")
+        })?;
+        let registration = Registration {
+            proto,
+            path: path.to_string(),
+            version: API_VERSION,
+        };
+        let len = registration.to_slice(&mut self.buffer).map_err(to_io_error)?;
+        self.socket.send_to(&self.buffer[..len], &self.daemon_path)?;
+
+        self.socket.set_read_timeout(Some(timeout))?;
+        let read = self.socket.recv(&mut self.buffer)?;
+        check_notification(&self.buffer[..read])?;
+        match Registration::from_slice(&self.buffer[..read]) {
+            Ok(ack) if ack == registration => Ok(()),
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Acknowledgment of another registration",
+            )),
+            Err(e) => Err(to_io_error(e)),
+        }
+    }
+}
+
+impl Drop for BierClient {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.my_path);
+    }
+}
+
+/// Returns the error notification of the daemon in `message` as an error, if any.
+pub(crate) fn check_notification(message: &[u8]) -> io::Result<()> {
+    if message_type(message) != Ok(MessageType::Error) {
+        return Ok(());
+    }
+    let notification = ErrorNotification::from_slice(message).map_err(to_io_error)?;
+    let kind = match notification.code {
+        ErrorCode::Unauthorized => io::ErrorKind::PermissionDenied,
+        _ => io::ErrorKind::InvalidInput,
+    };
+    Err(io::Error::new(kind, notification))
+}
+
+fn to_io_error(e: crate::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn get_socket_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("bier-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    /// Tests the exchange of packets between the client and a dummy daemon.
+    fn test_bier_client() {
+        let daemon_path = get_socket_path("client-daemon");
+        let app_path = get_socket_path("client-app");
+        let daemon = UnixDatagram::bind(&daemon_path).unwrap();
+
+        // A stale socket file does not prevent the connection.
+        std::fs::write(&app_path, []).unwrap();
+        let mut client = BierClient::connect(&daemon_path, &app_path).unwrap();
+
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b110],
+            payload: &[1, 2, 3, 4],
+            metadata: None,
+        };
+        client.send(&send_info).unwrap();
+
+        let mut buffer = [0u8; 100];
+        let read = daemon.recv(&mut buffer).unwrap();
+        let recv_info = RecvInfo::from_slice(&buffer[..read]).unwrap();
+        assert_eq!(recv_info.bift_id, 1);
+        assert_eq!(recv_info.payload, &[1, 2, 3, 4]);
+
+        // Nothing delivered yet.
+        let res = client.recv_timeout(Duration::from_millis(10)).unwrap();
+        assert!(res.is_none());
+
+        daemon.send_to(&buffer[..read], &app_path).unwrap();
+        let recv_info = client.recv().unwrap();
+        assert_eq!(recv_info.bitstring, &[0, 0, 0, 0, 0, 0, 0, 0b110]);
+        assert_eq!(recv_info.payload, &[1, 2, 3, 4]);
+
+        daemon.send_to(&buffer[..read], &app_path).unwrap();
+        let recv_info = client.recv_timeout(Duration::from_secs(1)).unwrap().unwrap();
+        assert_eq!(recv_info.proto, 6);
+
+        // Not a BIER message.
+        daemon.send_to(&[1, 2, 3], &app_path).unwrap();
+        assert!(client.recv().is_err());
+
+        // The payload was dropped by the daemon.
+        let notification = ErrorNotification {
+            code: crate::api::ErrorCode::NoEntry,
+            bift_id: 1,
+            proto: 6,
+        };
+        let len = notification.to_slice(&mut buffer).unwrap();
+        daemon.send_to(&buffer[..len], &app_path).unwrap();
+        let e = client.recv().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            e.get_ref()
+                .and_then(|e| e.downcast_ref::<ErrorNotification>()),
+            Some(&notification)
+        );
+
+        // The socket file is removed with the client.
+        drop(client);
+        assert!(!app_path.exists());
+        std::fs::remove_file(&daemon_path).unwrap();
+    }
+
+    #[test]
+    /// Tests that a query is answered by the daemon.
+    fn test_bier_client_query() {
+        let daemon_path = get_socket_path("client-query-daemon");
+        let app_path = get_socket_path("client-query-app");
+        let daemon = UnixDatagram::bind(&daemon_path).unwrap();
+        let mut client = BierClient::connect(&daemon_path, &app_path).unwrap();
+
+        let handle = std::thread::spawn(move || {
+            let mut buffer = [0u8; 100];
+            let (read, from) = daemon.recv_from(&mut buffer).unwrap();
+            assert_eq!(Query::from_slice(&buffer[..read]).unwrap(), Query::BfrNames);
+            let response = Response::BfrNames(vec![("a".to_string(), 1)]);
+            let len = response.to_slice(&mut buffer).unwrap();
+            daemon
+                .send_to(&buffer[..len], from.as_pathname().unwrap())
+                .unwrap();
+        });
+
+        let response = client
+            .query(Query::BfrNames, Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(response, Response::BfrNames(vec![("a".to_string(), 1)]));
+        handle.join().unwrap();
+        std::fs::remove_file(&daemon_path).unwrap();
+    }
+
+    #[test]
+    /// Tests the registration of the path of the client, acknowledged by the daemon.
+    fn test_bier_client_register() {
+        let daemon_path = get_socket_path("client-register-daemon");
+        let app_path = get_socket_path("client-register-app");
+        let daemon = UnixDatagram::bind(&daemon_path).unwrap();
+        let mut client = BierClient::connect(&daemon_path, &app_path).unwrap();
+
+        let expected_path = app_path.to_str().unwrap().to_string();
+        let handle = std::thread::spawn(move || {
+            let mut buffer = [0u8; 200];
+            for acknowledged in [true, false] {
+                let (read, from) = daemon.recv_from(&mut buffer).unwrap();
+                let registration = Registration::from_slice(&buffer[..read]).unwrap();
+                assert_eq!((registration.proto, &registration.path), (17, &expected_path));
+                let len = match acknowledged {
+                    true => read,
+                    false => ErrorNotification::new(&buffer[..read], crate::Error::Registration)
+                        .to_slice(&mut buffer)
+                        .unwrap(),
+                };
+                daemon
+                    .send_to(&buffer[..len], from.as_pathname().unwrap())
+                    .unwrap();
+            }
+        });
+
+        client.register(17, Duration::from_secs(1)).unwrap();
+        let e = client.register(17, Duration::from_secs(1)).unwrap_err();
+        let notification = e
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<ErrorNotification>());
+        assert_eq!(
+            notification.map(|notification| notification.code),
+            Some(crate::api::ErrorCode::Registration)
+        );
+        handle.join().unwrap();
+        std::fs::remove_file(&daemon_path).unwrap();
+    }
+}
@@ -0,0 +1,594 @@
+//! Control socket of the daemon, used by operators and tools (e.g., bier-top).
+//! Each datagram contains a single JSON request, answered with a JSON response
+//! sent back to the address of the requester.
+//!
+//! The updates of the BIFT entries and next hops are only accepted on a second socket,
+//! the update socket of the controller, so that the monitoring tools cannot modify the
+//! routes, nor the multicast overlay. The updated BIFTs are compiled before replacing the
+//! current ones: an update that cannot be compiled is rejected without effect.
+
+use crate::bier::{AdminState, BierState, Bift, BiftEntry, Bitstring, Decision, UnknownBitsPolicy};
+use crate::daemon::BierDaemon;
+use crate::delta::BierStateDelta;
+use crate::overlay::OverlayEntry;
+use crate::packet_log::PacketLog;
+use crate::stats::Stats;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+    /// Cumulative counters of the daemon, including the ones restored at startup.
+    Stats,
+    /// Counters since the previous `stats_delta` request, or since the start of the daemon.
+    StatsDelta,
+    /// BIFTs of the daemon, with the administrative state of the entries and paths.
+    Bifts,
+    /// Sets the administrative state of the paths toward `next_hop`, e.g., to drain a
+    /// neighbor before maintenance, or of the entries of `bit` without `next_hop`.
+    /// The optional `bift_id` and `bit` restrict the updated entries.
+    SetAdminState {
+        #[serde(default)]
+        bift_id: Option<usize>,
+        #[serde(default)]
+        bit: Option<u64>,
+        #[serde(default)]
+        next_hop: Option<IpAddr>,
+        admin_state: AdminState,
+    },
+    /// Replication decisions for a packet with `bitstring` in the BIFT `bift_id`,
+    /// without sending anything. The `entropy` of the packet selects the ECMP paths.
+    Explain {
+        bift_id: u32,
+        bitstring: Bitstring,
+        #[serde(default)]
+        entropy: u32,
+    },
+    /// Adds `entry` to the BIFT `bift_id`, or replaces its entry with the same BFR-id.
+    AddEntry { bift_id: usize, entry: BiftEntry },
+    /// Removes the entry of the BFR-id `bit` from the BIFT `bift_id`.
+    RemoveEntry { bift_id: usize, bit: u64 },
+    /// Replaces the next hop `old` of the paths by `new`, in the BIFT `bift_id` if given,
+    /// e.g., after a change of the address of a neighbor.
+    ReplaceNextHop {
+        #[serde(default)]
+        bift_id: Option<usize>,
+        old: IpAddr,
+        new: IpAddr,
+    },
+    /// Applies the changes of BIFTs, entries and paths of `delta`, all of them or none.
+    Apply { delta: BierStateDelta },
+    /// Replaces the BIER state by the one of the configuration of the daemon, as on SIGHUP.
+    Reload,
+    /// Entries of the multicast overlay.
+    Groups,
+    /// Adds `entry` to the multicast overlay, or replaces the one with the same group and
+    /// source.
+    AddGroup { entry: OverlayEntry },
+    /// Removes the entry of `group` and `source` from the multicast overlay, the (*, G)
+    /// entry without `source`.
+    RemoveGroup {
+        group: IpAddr,
+        #[serde(default)]
+        source: Option<IpAddr>,
+    },
+    /// The BFER `bfr_id` of `sub_domain` has receivers of `group`, from `source` only if
+    /// given, e.g., reported by its MLD/IGMP listener (see `membership`).
+    Join {
+        group: IpAddr,
+        #[serde(default)]
+        source: Option<IpAddr>,
+        #[serde(default)]
+        sub_domain: u8,
+        bfr_id: u64,
+    },
+    /// The BFER `bfr_id` has no more receivers of `group` and `source`.
+    Leave {
+        group: IpAddr,
+        #[serde(default)]
+        source: Option<IpAddr>,
+        bfr_id: u64,
+    },
+    /// Writes the replication decisions of each processed packet to `destination`, in the
+    /// format of the packet log, or stops the decision trace without `destination`.
+    Trace {
+        #[serde(default)]
+        destination: Option<String>,
+    },
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlResponse {
+    Stats(Stats),
+    Bifts(Vec<Bift>),
+    /// Number of entries or paths modified by the request, of changes applied from a delta,
+    /// or of entries after a reload.
+    Updated(usize),
+    Decisions(Vec<Decision>),
+    Groups(Vec<OverlayEntry>),
+    /// The request cannot be parsed or applied.
+    Error(String),
+}
+
+impl ControlRequest {
+    /// Whether the request updates the BIFTs, only accepted on the update socket.
+    /// The decision trace is started there too, as it writes to a path chosen by the
+    /// requester.
+    pub fn is_update(&self) -> bool {
+        matches!(
+            self,
+            ControlRequest::AddEntry { .. }
+                | ControlRequest::RemoveEntry { .. }
+                | ControlRequest::ReplaceNextHop { .. }
+                | ControlRequest::SetAdminState { .. }
+                | ControlRequest::Apply { .. }
+                | ControlRequest::Reload
+                | ControlRequest::AddGroup { .. }
+                | ControlRequest::RemoveGroup { .. }
+                | ControlRequest::Join { .. }
+                | ControlRequest::Leave { .. }
+                | ControlRequest::Trace { .. }
+        )
+    }
+
+    pub fn answer(&self, daemon: &mut BierDaemon) -> ControlResponse {
+        match self {
+            ControlRequest::Stats => ControlResponse::Stats(daemon.stats()),
+            ControlRequest::StatsDelta => ControlResponse::Stats(daemon.stats_delta()),
+            ControlRequest::Bifts => ControlResponse::Bifts(daemon.state().bifts.clone()),
+            ControlRequest::SetAdminState {
+                bift_id,
+                bit,
+                next_hop,
+                admin_state,
+            } => match (next_hop, bit) {
+                (Some(next_hop), _) => update(daemon, |state| {
+                    Ok(state.set_path_admin_state(*bift_id, *bit, *next_hop, *admin_state))
+                }),
+                (None, Some(bit)) => update(daemon, |state| {
+                    Ok(state.set_entry_admin_state(*bift_id, *bit, *admin_state))
+                }),
+                (None, None) => ControlResponse::Error("Missing next_hop or bit".into()),
+            },
+            ControlRequest::Explain {
+                bift_id,
+                bitstring,
+                entropy,
+            } => {
+                let state = daemon.state();
+                // The unknown bits are cleared before the processing, as by the daemon.
+                let mut bitstring = bitstring.clone();
+                if state.unknown_bits == UnknownBitsPolicy::Clear {
+                    if let Some(highest) = state.highest_bit(*bift_id) {
+                        bitstring.clear_above(highest);
+                    }
+                }
+                match state.explain(&bitstring, *bift_id, *entropy) {
+                    Ok(decisions) => ControlResponse::Decisions(decisions),
+                    Err(e) => ControlResponse::Error(e.to_string()),
+                }
+            }
+            ControlRequest::AddEntry { bift_id, entry } => update(daemon, |state| {
+                state.upsert_entry(*bift_id, entry.clone()).map(|_| 1)
+            }),
+            ControlRequest::RemoveEntry { bift_id, bit } => {
+                update(daemon, |state| state.remove_entry(*bift_id, *bit))
+            }
+            ControlRequest::ReplaceNextHop { bift_id, old, new } => update(daemon, |state| {
+                Ok(state.replace_next_hop(*bift_id, *old, *new))
+            }),
+            ControlRequest::Apply { delta } => update(daemon, |state| {
+                state.apply(delta).map(|_| delta.changes.len())
+            }),
+            ControlRequest::Reload => match daemon.reload() {
+                Ok(report) => ControlResponse::Updated(report.nb_entries),
+                Err(e) => ControlResponse::Error(e),
+            },
+            ControlRequest::Groups => {
+                ControlResponse::Groups(daemon.state().overlay.entries().cloned().collect())
+            }
+            ControlRequest::AddGroup { entry } => {
+                update(daemon, |state| state.add_group(entry.clone()).map(|_| 1))
+            }
+            ControlRequest::RemoveGroup { group, source } => update(daemon, |state| {
+                state
+                    .overlay
+                    .remove(*group, *source)
+                    .map(|_| 1)
+                    .ok_or(crate::Error::NoEntry)
+            }),
+            // The overlay is updated in place, without compiling the BIFTs again.
+            ControlRequest::Join {
+                group,
+                source,
+                sub_domain,
+                bfr_id,
+            } => match daemon.join_group(*group, *source, *sub_domain, *bfr_id) {
+                Ok(joined) => ControlResponse::Updated(usize::from(joined)),
+                Err(e) => ControlResponse::Error(e.to_string()),
+            },
+            ControlRequest::Leave {
+                group,
+                source,
+                bfr_id,
+            } => {
+                ControlResponse::Updated(usize::from(daemon.leave_group(*group, *source, *bfr_id)))
+            }
+            ControlRequest::Trace { destination } => {
+                match destination.as_deref().map(PacketLog::open).transpose() {
+                    Ok(trace) => {
+                        let enabled = trace.is_some();
+                        daemon.set_trace(trace);
+                        ControlResponse::Updated(usize::from(enabled))
+                    }
+                    Err(e) => ControlResponse::Error(e.to_string()),
+                }
+            }
+        }
+    }
+}
+
+/// Applies `update` to a copy of the BIER state of the daemon, which replaces the current
+/// state only if it can be compiled.
+fn update(
+    daemon: &mut BierDaemon,
+    update: impl FnOnce(&mut BierState) -> crate::Result<usize>,
+) -> ControlResponse {
+    let mut state = daemon.state().clone();
+    match update(&mut state).and_then(|updated| daemon.replace_state(state).map(|_| updated)) {
+        Ok(updated) => ControlResponse::Updated(updated),
+        Err(e) => ControlResponse::Error(e.to_string()),
+    }
+}
+
+/// Answers a datagram received on the control socket. The updates of the BIFTs are refused.
+pub fn handle_request(daemon: &mut BierDaemon, request: &[u8]) -> Vec<u8> {
+    handle(daemon, request, false)
+}
+
+/// Answers a datagram received on the update socket, including the updates of the BIFTs.
+pub fn handle_update_request(daemon: &mut BierDaemon, request: &[u8]) -> Vec<u8> {
+    handle(daemon, request, true)
+}
+
+fn handle(daemon: &mut BierDaemon, request: &[u8], allow_updates: bool) -> Vec<u8> {
+    let response = match serde_json::from_slice::<ControlRequest>(request) {
+        Ok(request) if request.is_update() && !allow_updates => {
+            ControlResponse::Error("Updates are only accepted on the update socket".into())
+        }
+        Ok(request) => request.answer(daemon),
+        Err(e) => ControlResponse::Error(e.to_string()),
+    };
+    serde_json::to_vec(&response).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::bier::example_state;
+    use crate::daemon::MemoryTransport;
+    use crate::Error;
+    use std::net::IpAddr;
+
+    /// Daemon of the example configuration, keeping its copies in memory.
+    fn get_daemon() -> BierDaemon {
+        BierDaemon::builder()
+            .state(example_state())
+            .transport(MemoryTransport::default())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    /// Tests the answers to the control requests.
+    fn test_handle_request() {
+        let mut daemon = get_daemon();
+        daemon.record_drop(crate::stats::DropReason::Unauthorized);
+
+        let response = handle_request(&mut daemon, br#"{"command": "stats"}"#);
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response, ControlResponse::Stats(daemon.stats()));
+
+        let response = handle_request(&mut daemon, br#"{"command": "unknown"}"#);
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert!(matches!(response, ControlResponse::Error(_)));
+    }
+
+    #[test]
+    /// Tests the replication decisions explained for a hypothetical packet.
+    fn test_explain() {
+        let mut daemon = get_daemon();
+        daemon
+            .update_state(|state| {
+                let b = "fc00:b::1".parse().unwrap();
+                state.set_path_admin_state(None, Some(4), b, AdminState::Down)
+            })
+            .unwrap();
+
+        let response = handle_request(
+            &mut daemon,
+            br#"{"command": "explain", "bift_id": 1, "bitstring": "11001"}"#,
+        );
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(
+            response,
+            ControlResponse::Decisions(vec![
+                Decision::Copy {
+                    bit: 1,
+                    path: 0,
+                    fbm: "1".parse().unwrap(),
+                    bitstring: "1".parse().unwrap(),
+                    next_hop: None,
+                },
+                // The BFR 5 is removed from the F-BM of the backup path, as its primary
+                // path is up.
+                Decision::Copy {
+                    bit: 4,
+                    path: 1,
+                    fbm: "1100".parse().unwrap(),
+                    bitstring: "1000".parse().unwrap(),
+                    next_hop: Some("fc00:c::1".parse().unwrap()),
+                },
+                Decision::Copy {
+                    bit: 5,
+                    path: 0,
+                    fbm: "11010".parse().unwrap(),
+                    bitstring: "10000".parse().unwrap(),
+                    next_hop: Some("fc00:b::1".parse().unwrap()),
+                },
+            ])
+        );
+        assert_eq!(daemon.stats().received.packets, 0);
+
+        let response = handle_request(
+            &mut daemon,
+            br#"{"command": "explain", "bift_id": 42, "bitstring": "1"}"#,
+        );
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(
+            response,
+            ControlResponse::Error(Error::BiftId(42).to_string())
+        );
+    }
+
+    #[test]
+    /// Tests the drain of a neighbor with the update socket.
+    fn test_set_admin_state() {
+        let mut daemon = get_daemon();
+        let drain =
+            br#"{"command": "set_admin_state", "next_hop": "fc00:b::1", "admin_state": "down"}"#;
+
+        // Refused on the control socket, like the other updates.
+        let response = handle_request(&mut daemon, drain);
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert!(matches!(response, ControlResponse::Error(_)));
+        assert!(daemon.state().drained_next_hops().is_empty());
+
+        let response = handle_update_request(&mut daemon, drain);
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response, ControlResponse::Updated(3));
+        let response = handle_update_request(
+            &mut daemon,
+            br#"{"command": "set_admin_state", "bift_id": 1, "bit": 3, "admin_state": "down"}"#,
+        );
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response, ControlResponse::Updated(1));
+
+        let response = handle_request(&mut daemon, br#"{"command": "bifts"}"#);
+        let ControlResponse::Bifts(bifts) = serde_json::from_slice(&response).unwrap() else {
+            panic!("Expected the BIFTs");
+        };
+        assert_eq!(bifts[0].entries[1].paths[0].admin_state, AdminState::Down);
+        assert_eq!(bifts[0].entries[2].admin_state, AdminState::Down);
+        assert_eq!(bifts[0].entries[3].paths[1].admin_state, AdminState::Up);
+        let b: IpAddr = "fc00:b::1".parse().unwrap();
+        assert!(daemon.stats().neighbors[&b].drained);
+
+        let response = handle_update_request(
+            &mut daemon,
+            br#"{"command": "set_admin_state", "admin_state": "down"}"#,
+        );
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert!(matches!(response, ControlResponse::Error(_)));
+    }
+
+    #[test]
+    /// Tests the updates of the BIFT entries and next hops on the update socket.
+    fn test_update_requests() {
+        let mut daemon = get_daemon();
+        let add_entry = br#"{"command": "add_entry", "bift_id": 1, "entry": {"bit": 6,
+            "paths": [{"bitstring": "100000", "next_hop": "fc00:d::1"}]}}"#;
+
+        // Refused on the control socket.
+        let response = handle_request(&mut daemon, add_entry);
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert!(matches!(response, ControlResponse::Error(_)));
+        assert_eq!(daemon.state().bifts[0].entries.len(), 5);
+
+        let response = handle_update_request(&mut daemon, add_entry);
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response, ControlResponse::Updated(1));
+        let bitstring = "100000".parse().unwrap();
+        let d: IpAddr = "fc00:d::1".parse().unwrap();
+        assert_eq!(
+            daemon.state().process_bier(&bitstring, 1, 0),
+            Ok(vec![(bitstring.clone(), Some(d))])
+        );
+
+        let response = handle_update_request(
+            &mut daemon,
+            br#"{"command": "replace_next_hop", "old": "fc00:d::1", "new": "fc00:e::1"}"#,
+        );
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response, ControlResponse::Updated(1));
+        let e: IpAddr = "fc00:e::1".parse().unwrap();
+        assert_eq!(
+            daemon.state().process_bier(&bitstring, 1, 0),
+            Ok(vec![(bitstring.clone(), Some(e))])
+        );
+
+        let response = handle_update_request(
+            &mut daemon,
+            br#"{"command": "remove_entry", "bift_id": 1, "bit": 6}"#,
+        );
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response, ControlResponse::Updated(1));
+        assert_eq!(daemon.state().bifts[0].entries.len(), 5);
+
+        // An entry outside of the BSL of the BIFT, and an unknown BIFT.
+        let response = handle_update_request(
+            &mut daemon,
+            br#"{"command": "add_entry", "bift_id": 1, "entry": {"bit": 65,
+                "paths": [{"bitstring": "1", "next_hop": "fc00:d::1"}]}}"#,
+        );
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(
+            response,
+            ControlResponse::Error(Error::BiftParsing.to_string())
+        );
+        let response = handle_update_request(
+            &mut daemon,
+            br#"{"command": "remove_entry", "bift_id": 42, "bit": 1}"#,
+        );
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(
+            response,
+            ControlResponse::Error(Error::BiftId(42).to_string())
+        );
+        assert_eq!(daemon.state().bifts[0].entries.len(), 5);
+
+        // A delta of a controller, applied all or none.
+        let delta = br#"{"command": "apply", "delta": {"changes": [
+            {"change": "upsert_entry", "bift_id": 1,
+             "entry": {"bit": 6, "paths": [{"bitstring": "100000", "next_hop": "fc00:d::1"}]}},
+            {"change": "add_path", "bift_id": 1, "bit": 6,
+             "path": {"bitstring": "100000", "next_hop": "fc00:e::1"}}]}}"#;
+        let response = handle_update_request(&mut daemon, delta);
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response, ControlResponse::Updated(2));
+        assert_eq!(daemon.state().bifts[0].entries[5].paths.len(), 2);
+        assert_eq!(
+            daemon.state().process_bier(&bitstring, 1, 0),
+            Ok(vec![(bitstring.clone(), Some(d))])
+        );
+        let response = handle_update_request(
+            &mut daemon,
+            br#"{"command": "apply", "delta": {"changes": [
+                {"change": "remove_entry", "bift_id": 1, "bit": 6},
+                {"change": "remove_entry", "bift_id": 1, "bit": 6}]}}"#,
+        );
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response, ControlResponse::Error(Error::NoEntry.to_string()));
+        assert_eq!(daemon.state().bifts[0].entries.len(), 6);
+
+        // The daemon has no configuration to reload.
+        let response = handle_update_request(&mut daemon, br#"{"command": "reload"}"#);
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response, ControlResponse::Error("No state loader".into()));
+    }
+
+    #[test]
+    /// Tests the start and stop of the decision trace, only on the update socket.
+    fn test_trace_request() {
+        let mut daemon = get_daemon();
+        let path = std::env::temp_dir().join(format!("bier-control-trace-{}", std::process::id()));
+        let start = format!(r#"{{"command": "trace", "destination": {:?}}}"#, path);
+        let response = handle_request(&mut daemon, start.as_bytes());
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert!(matches!(response, ControlResponse::Error(_)));
+        assert!(!path.exists());
+
+        let response = handle_update_request(&mut daemon, start.as_bytes());
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response, ControlResponse::Updated(1));
+        assert!(path.exists());
+        let response = handle_update_request(&mut daemon, br#"{"command": "trace"}"#);
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response, ControlResponse::Updated(0));
+
+        let response = handle_update_request(
+            &mut daemon,
+            br#"{"command": "trace", "destination": "unix:/nonexistent/trace.sock"}"#,
+        );
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert!(matches!(response, ControlResponse::Error(_)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    /// Tests the updates of the multicast overlay.
+    fn test_overlay_requests() {
+        let mut daemon = get_daemon();
+        let add_group = br#"{"command": "add_group",
+            "entry": {"group": "ff3e::1", "bitstring": "110"}}"#;
+        let response = handle_request(&mut daemon, add_group);
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert!(matches!(response, ControlResponse::Error(_)));
+
+        let response = handle_update_request(&mut daemon, add_group);
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response, ControlResponse::Updated(1));
+        let group: IpAddr = "ff3e::1".parse().unwrap();
+        let source: IpAddr = "fc00:a::1".parse().unwrap();
+        let (bift_id, bitstring) = daemon.state().overlay_lookup(source, group).unwrap();
+        assert_eq!((bift_id, &bitstring.bitstring[..]), (1, &[0b110][..]));
+
+        let response = handle_request(&mut daemon, br#"{"command": "groups"}"#);
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        let ControlResponse::Groups(groups) = response else {
+            panic!("Unexpected response {:?}", response);
+        };
+        assert_eq!(groups.len(), 1);
+
+        // The sub-domain must have a BIFT, and the bitstring a multiple of its BSL.
+        let add_group = br#"{"command": "add_group",
+            "entry": {"group": "ff3e::2", "sub_domain": 7, "bitstring": "110"}}"#;
+        let response = handle_update_request(&mut daemon, add_group);
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(
+            response,
+            ControlResponse::Error(Error::SubDomain(7).to_string())
+        );
+        let add_group = br#"{"command": "add_group",
+            "entry": {"group": "ff3e::2", "bitstring": {"bits": [1], "bsl": 128}}}"#;
+        let response = handle_update_request(&mut daemon, add_group);
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response, ControlResponse::Updated(1));
+
+        let remove_group = br#"{"command": "remove_group", "group": "ff3e::1"}"#;
+        let response = handle_update_request(&mut daemon, remove_group);
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response, ControlResponse::Updated(1));
+        assert!(daemon.state().overlay_lookup(source, group).is_none());
+        let response = handle_update_request(&mut daemon, remove_group);
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response, ControlResponse::Error(Error::NoEntry.to_string()));
+
+        // The BFERs 2 and 3 report receivers of the group.
+        for bfr_id in [2, 3, 3] {
+            let join = format!(
+                r#"{{"command": "join", "group": "ff3e::1", "bfr_id": {}}}"#,
+                bfr_id
+            );
+            handle_update_request(&mut daemon, join.as_bytes());
+        }
+        let (_, bitstring) = daemon.state().overlay_lookup(source, group).unwrap();
+        assert_eq!(bitstring.bitstring, [0b110]);
+        let leave = br#"{"command": "leave", "group": "ff3e::1", "bfr_id": 2}"#;
+        let response = handle_update_request(&mut daemon, leave);
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response, ControlResponse::Updated(1));
+        let (_, bitstring) = daemon.state().overlay_lookup(source, group).unwrap();
+        assert_eq!(bitstring.bitstring, [0b100]);
+        let join = br#"{"command": "join", "group": "ff3e::1", "sub_domain": 9, "bfr_id": 2}"#;
+        let response = handle_update_request(&mut daemon, join);
+        let response: ControlResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(
+            response,
+            ControlResponse::Error(Error::SubDomain(9).to_string())
+        );
+    }
+}
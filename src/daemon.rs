@@ -0,0 +1,2915 @@
+//! BIER daemon as a library type, so that other Rust programs can embed the BFR
+//! in-process instead of communicating with the binary through UNIX sockets.
+
+use crate::api::{Metadata, RecvInfo, Registration, SendInfo};
+use crate::bier::{
+    BierSendInfo, BierState, Bitstring, CompileReport, Encapsulation, NeighborConfig,
+    PathSelection, SocketConfig, UnknownBitsPolicy,
+};
+use crate::bierin6;
+use crate::cache::ResultCache;
+use crate::header::{self, BierHeader, BierProto, HeaderTemplate, LabelStackEntry};
+use crate::header::{MPLS_LABEL_ENTRY_LENGTH, RFC8296_NIBBLE};
+use crate::oam::{self, EchoMessage, OamMessageType, ReplyMode};
+use crate::packet_log::{LoggedCopy, PacketLog, PacketLogEntry, PacketSource, TraceEntry};
+use crate::pcap::BierCapture;
+use crate::stats::{AtomicStats, DropReason, Stats};
+use crate::{Error, Result};
+use log::{debug, error, info, warn};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Instant, SystemTime};
+
+pub use crate::server::{Daemon, DaemonConfig};
+
+/// Maximum length of a BIER packet built from an application payload.
+const MAX_PACKET_LENGTH: usize = 65535;
+
+/// Maximum number of header templates kept for the flows of the applications.
+/// The headers of the other flows are serialized for each packet.
+const MAX_HEADER_TEMPLATES: usize = 1024;
+
+/// Maximum number of packets with deferred copies, see `OverflowPolicy::Defer`.
+/// The copies beyond the cap of the other packets are dropped.
+const MAX_DEFERRED_PACKETS: usize = 1024;
+
+/// Flow of an application, identified by its BIFT-id, proto and bitstring length.
+type FlowKey = (u32, u16, usize);
+
+/// Sends the BIER packets to the neighbouring BFRs.
+/// A `WouldBlock` error means that the copy is dropped because of backpressure,
+/// e.g., a full queue, and not because of the next hop.
+pub trait Transport {
+    fn send_to(&mut self, packet: &[u8], next_hop: IpAddr) -> io::Result<usize>;
+
+    /// Sends the copies queued by `send_to`, called once the copies of a packet are sent.
+    /// Nothing to do for the transports sending each copy immediately.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Receives a BIER packet from the neighbours in `buf`, i.e., starting with the BIER
+    /// header whatever the encapsulation, and returns its length, truncated to the length of
+    /// `buf`. The daemon receives the packets of a transport with `BierDaemon::recv_from`.
+    /// Unsupported by default.
+    fn recv(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn send_to(&mut self, packet: &[u8], next_hop: IpAddr) -> io::Result<usize> {
+        (**self).send_to(packet, next_hop)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).recv(buf)
+    }
+}
+
+/// Raw IP socket, as created by `raw_socket`.
+impl Transport for socket2::Socket {
+    fn send_to(&mut self, packet: &[u8], next_hop: IpAddr) -> io::Result<usize> {
+        socket2::Socket::send_to(self, packet, &SocketAddr::new(next_hop, 0).into())
+    }
+
+    /// Contrary to IPv6, the packets received with IPv4 start with the IP header, which is
+    /// removed, see `Router::handle_ip_packet`.
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // SAFETY: the socket only writes initialized bytes in `buf`.
+        let uninit = unsafe { &mut *(buf as *mut [u8] as *mut [std::mem::MaybeUninit<u8>]) };
+        let (length, from) = self.recv_from(uninit)?;
+        if from.as_socket_ipv4().is_none() {
+            return Ok(length);
+        }
+        let ip_header_length = match buf[..length].first() {
+            Some(first) => ((first & 0x0f) as usize) * 4,
+            None => 1,
+        };
+        if length < ip_header_length {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Packet shorter than its IPv4 header",
+            ));
+        }
+        buf.copy_within(ip_header_length..length, 0);
+        Ok(length - ip_header_length)
+    }
+}
+
+/// Transport keeping the copies in memory instead of sending them, e.g., for deterministic
+/// tests of the forwarding or a simulated network. Its clones share the same queue of copies,
+/// and `recv` takes the oldest one.
+#[derive(Clone, Default)]
+pub struct MemoryTransport {
+    queue: Arc<Mutex<VecDeque<SentCopy>>>,
+}
+
+/// Copy of a packet with its next hop.
+pub type SentCopy = (IpAddr, Vec<u8>);
+
+impl MemoryTransport {
+    fn queue(&self) -> MutexGuard<'_, VecDeque<SentCopy>> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Takes the oldest copy, with its next hop.
+    pub fn pop(&self) -> Option<SentCopy> {
+        self.queue().pop_front()
+    }
+
+    /// Takes all the copies, the oldest first.
+    pub fn drain(&self) -> Vec<SentCopy> {
+        self.queue().drain(..).collect()
+    }
+
+    /// Clones of the copies, the oldest first, without taking them.
+    pub fn sent(&self) -> Vec<SentCopy> {
+        self.queue().iter().cloned().collect()
+    }
+
+    /// Drops all the copies.
+    pub fn clear(&self) {
+        self.queue().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue().is_empty()
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn send_to(&mut self, packet: &[u8], next_hop: IpAddr) -> io::Result<usize> {
+        self.queue().push_back((next_hop, packet.to_vec()));
+        Ok(packet.len())
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (_, packet) = self.pop().ok_or(io::ErrorKind::WouldBlock)?;
+        let length = packet.len().min(buf.len());
+        buf[..length].copy_from_slice(&packet[..length]);
+        Ok(length)
+    }
+}
+
+/// Creates the raw IP socket carrying BIER packets, with the proto and the options of
+/// `config`, and the same address family as the `loopback` of the BFR.
+pub fn raw_socket(loopback: IpAddr, config: &SocketConfig) -> io::Result<socket2::Socket> {
+    let domain = match loopback {
+        IpAddr::V4(_) => socket2::Domain::IPV4,
+        IpAddr::V6(_) => socket2::Domain::IPV6,
+    };
+    let socket = socket2::Socket::new(
+        domain,
+        socket2::Type::RAW,
+        Some(socket2::Protocol::from(config.proto() as i32)),
+    )?;
+    configure_socket(&socket, loopback, config)?;
+    Ok(socket)
+}
+
+/// Applies the receive buffer size, the hop limit and the interface of `config` to `socket`,
+/// of the family of `address`, e.g., to the UDP socket carrying the BIER packets.
+pub fn configure_socket(
+    socket: &socket2::Socket,
+    address: IpAddr,
+    config: &SocketConfig,
+) -> io::Result<()> {
+    if let Some(size) = config.recv_buffer {
+        socket.set_recv_buffer_size(size)?;
+    }
+    match (config.hop_limit, address) {
+        (Some(hop_limit), IpAddr::V4(_)) => socket.set_ttl(hop_limit as u32)?,
+        (Some(hop_limit), IpAddr::V6(_)) => socket.set_unicast_hops_v6(hop_limit as u32)?,
+        (None, _) => (),
+    }
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if let Some(interface) = &config.interface {
+        socket.bind_device(Some(interface.as_bytes()))?;
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    if config.interface.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "binding the socket to an interface requires Linux",
+        ));
+    }
+    Ok(())
+}
+
+/// Creates the UDP socket carrying BIER packets, bound to `port` on the `loopback` of the BFR.
+pub fn udp_socket(loopback: IpAddr, port: u16) -> io::Result<socket2::Socket> {
+    let address = SocketAddr::new(loopback, port);
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(address),
+        socket2::Type::DGRAM,
+        Some(socket2::Protocol::UDP),
+    )?;
+    socket.bind(&address.into())?;
+    Ok(socket)
+}
+
+/// Transport carrying the BIER packets in UDP datagrams, sent to the same `port` on
+/// every next hop. Unlike the raw IP socket, it does not require privileges.
+pub struct UdpTransport {
+    socket: socket2::Socket,
+    port: u16,
+}
+
+impl UdpTransport {
+    pub fn new(socket: socket2::Socket, port: u16) -> Self {
+        UdpTransport { socket, port }
+    }
+}
+
+impl AsRawFd for UdpTransport {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send_to(&mut self, packet: &[u8], next_hop: IpAddr) -> io::Result<usize> {
+        self.socket
+            .send_to(packet, &SocketAddr::new(next_hop, self.port).into())
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::Read::read(&mut self.socket, buf)
+    }
+}
+
+/// Creates a raw IP socket sending the copies toward the next hops of the family of
+/// `address`, from the `source` of `config` if any. The socket only sends: the received
+/// packets are read from the shared socket of `raw_socket`.
+pub fn egress_raw_socket(address: IpAddr, config: &SocketConfig) -> io::Result<socket2::Socket> {
+    let socket = raw_socket(address, config)?;
+    if let Some(source) = config.source {
+        socket.bind(&SocketAddr::new(source, 0).into())?;
+    }
+    socket.set_recv_buffer_size(0)?;
+    Ok(socket)
+}
+
+/// Creates a raw IP socket connected to `next_hop`, e.g., for `ConnectedTransport`.
+pub fn connected_raw_socket(
+    next_hop: IpAddr,
+    config: &SocketConfig,
+) -> io::Result<socket2::Socket> {
+    let socket = egress_raw_socket(next_hop, config)?;
+    socket.connect(&SocketAddr::new(next_hop, 0).into())?;
+    Ok(socket)
+}
+
+/// Creates a UDP socket sending the copies toward the next hops of the family of `address`,
+/// with the options and the source address of `config`, e.g., for `UdpTransport`.
+pub fn egress_udp_socket(address: IpAddr, config: &SocketConfig) -> io::Result<socket2::Socket> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(SocketAddr::new(address, 0)),
+        socket2::Type::DGRAM,
+        Some(socket2::Protocol::UDP),
+    )?;
+    configure_socket(&socket, address, config)?;
+    if let Some(source) = config.source {
+        socket.bind(&SocketAddr::new(source, 0).into())?;
+    }
+    Ok(socket)
+}
+
+/// Creates a UDP socket connected to `port` on `next_hop`, e.g., for `ConnectedTransport`.
+pub fn connected_udp_socket(
+    next_hop: IpAddr,
+    port: u16,
+    config: &SocketConfig,
+) -> io::Result<socket2::Socket> {
+    let socket = egress_udp_socket(next_hop, config)?;
+    socket.connect(&SocketAddr::new(next_hop, port).into())?;
+    Ok(socket)
+}
+
+/// Transport with one socket connected to each busy next hop, so that the copies are sent
+/// without building their destination address and the kernel caches the route. The copies
+/// toward the other next hops, e.g., the backup paths, are sent with the `shared` transport,
+/// except toward the next hops with a transport of their own (see `ConnectedTransport::insert`).
+pub struct ConnectedTransport {
+    shared: Box<dyn Transport + Send>,
+    connected: HashMap<IpAddr, socket2::Socket>,
+    bound: HashMap<IpAddr, Box<dyn Transport + Send>>,
+}
+
+impl ConnectedTransport {
+    /// Connects a socket with `connect` to each of the first `max` next hops, e.g., the
+    /// busiest next hops of the compiled state (see `CompiledState::busiest_next_hops`).
+    /// A next hop whose socket cannot be created uses the shared transport.
+    pub fn new(
+        shared: impl Transport + Send + 'static,
+        next_hops: impl IntoIterator<Item = IpAddr>,
+        max: usize,
+        connect: impl Fn(IpAddr) -> io::Result<socket2::Socket>,
+    ) -> Self {
+        let mut connected = HashMap::new();
+        for next_hop in next_hops.into_iter().take(max) {
+            match connect(next_hop) {
+                Ok(socket) => {
+                    connected.insert(next_hop, socket);
+                }
+                Err(e) => warn!(
+                    "Cannot connect a socket to {:?}, using the shared socket: {:?}",
+                    next_hop, e
+                ),
+            }
+        }
+        ConnectedTransport {
+            shared: Box::new(shared),
+            connected,
+            bound: HashMap::new(),
+        }
+    }
+
+    /// Sends the copies toward `next_hop` with `transport`, e.g., with a socket bound to the
+    /// interface or the source address of the neighbor (see `NeighborConfig::socket_config`).
+    /// Unlike the connected sockets, the socket needs no route toward the next hop at startup.
+    pub fn insert(&mut self, next_hop: IpAddr, transport: impl Transport + Send + 'static) {
+        self.connected.remove(&next_hop);
+        self.bound.insert(next_hop, Box::new(transport));
+    }
+
+    /// Number of next hops with a connected socket.
+    pub fn nb_connected(&self) -> usize {
+        self.connected.len()
+    }
+}
+
+impl Transport for ConnectedTransport {
+    fn send_to(&mut self, packet: &[u8], next_hop: IpAddr) -> io::Result<usize> {
+        if let Some(transport) = self.bound.get_mut(&next_hop) {
+            return transport.send_to(packet, next_hop);
+        }
+        match self.connected.get(&next_hop) {
+            Some(socket) => socket.send(packet),
+            None => self.shared.send_to(packet, next_hop),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.bound.values_mut().try_for_each(|transport| transport.flush())?;
+        self.shared.flush()
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.shared.recv(buf)
+    }
+}
+
+/// Called with the packets for which this BFR is a BFER.
+/// The bitstring only contains the bit of this BFER.
+pub type LocalDelivery = Box<dyn FnMut(&RecvInfo) + Send>;
+
+/// Called with the registrations of the applications and the path of the socket of the
+/// application, e.g., to update the destinations of the `dispatch::Dispatcher`. The path of
+/// a registration is the one of the application, or is empty to remove its registration.
+/// An error refuses the registration.
+pub type OnRegistration = Box<dyn FnMut(&Registration, &Path) -> Result<()> + Send>;
+
+/// Loads the BIER state again, e.g., from the configuration file, see `BierDaemon::reload`.
+pub type StateLoader = Box<dyn FnMut() -> std::result::Result<BierState, String> + Send>;
+
+/// Copies of a packet beyond the fan-out cap, see `BierDaemonBuilder::max_copies`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// The copies beyond the cap are dropped.
+    Drop,
+    /// The copies beyond the cap are sent by the next calls to `BierDaemon::send_deferred`.
+    Defer,
+}
+
+/// Packet whose copies beyond the fan-out cap are not sent yet.
+struct DeferredPacket {
+    source: PacketSource,
+    header: BierHeader,
+    packet: Vec<u8>,
+    copies: Vec<BierSendInfo>,
+    ingress: Instant,
+}
+
+/// Builder of a `BierDaemon`.
+#[derive(Default)]
+pub struct BierDaemonBuilder {
+    state: Option<BierState>,
+    transport: Option<Box<dyn Transport + Send>>,
+    transports: HashMap<Encapsulation, Box<dyn Transport + Send>>,
+    on_local_delivery: Option<LocalDelivery>,
+    on_registration: Option<OnRegistration>,
+    packet_log: Option<PacketLog>,
+    trace: Option<PacketLog>,
+    capture: Option<BierCapture>,
+    result_cache: Option<usize>,
+    max_copies: Option<(usize, OverflowPolicy)>,
+    restored_stats: Stats,
+    state_loader: Option<StateLoader>,
+    #[cfg(feature = "otel")]
+    tracer: Option<opentelemetry_sdk::trace::SdkTracer>,
+}
+
+impl BierDaemonBuilder {
+    /// BIFTs used to forward the packets. Mandatory.
+    pub fn state(mut self, state: BierState) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// Transport of the packets toward the next hops.
+    /// By default, a raw IP socket with the family of the loopback of the state.
+    pub fn transport(mut self, transport: impl Transport + Send + 'static) -> Self {
+        self.transport = Some(Box::new(transport));
+        self
+    }
+
+    /// Transport of the copies toward the neighbors with `encapsulation`, see
+    /// `NeighborConfig::encapsulation`, e.g., an `af_packet::EthernetTransport` for the
+    /// Ethernet encapsulation. These copies are dropped if no transport is given. The copies
+    /// with the IP encapsulation use the `transport`.
+    pub fn encapsulation_transport(
+        mut self,
+        encapsulation: Encapsulation,
+        transport: impl Transport + Send + 'static,
+    ) -> Self {
+        self.transports.insert(encapsulation, Box::new(transport));
+        self
+    }
+
+    /// Transport of the copies toward the neighbors with the UDP encapsulation, e.g., a
+    /// `UdpTransport`, see `encapsulation_transport`.
+    pub fn udp_transport(self, transport: impl Transport + Send + 'static) -> Self {
+        self.encapsulation_transport(Encapsulation::Udp, transport)
+    }
+
+    /// Callback receiving the packets destined to this BFER.
+    /// These packets are dropped if no callback is given.
+    pub fn on_local_delivery(mut self, callback: impl FnMut(&RecvInfo) + Send + 'static) -> Self {
+        self.on_local_delivery = Some(Box::new(callback));
+        self
+    }
+
+    /// Callback receiving the registrations of the applications, see `api::Registration`.
+    /// The registrations are refused if no callback is given.
+    pub fn on_registration(
+        mut self,
+        callback: impl FnMut(&Registration, &Path) -> Result<()> + Send + 'static,
+    ) -> Self {
+        self.on_registration = Some(Box::new(callback));
+        self
+    }
+
+    /// Logs each processed packet and the resulting copies. Disabled by default.
+    pub fn packet_log(mut self, packet_log: PacketLog) -> Self {
+        self.packet_log = Some(packet_log);
+        self
+    }
+
+    /// Writes the replication decisions of each processed packet in `trace`, see
+    /// `TraceEntry`. A debug facility, disabled by default.
+    pub fn trace(mut self, trace: PacketLog) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    /// Records the BIER packets received and sent in `capture`. Disabled by default.
+    pub fn capture(mut self, capture: BierCapture) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Caches the copies of the last `capacity` bitstrings, so that the packets of a
+    /// stable flow are forwarded without scanning their bitstring. Disabled by default.
+    pub fn result_cache(mut self, capacity: usize) -> Self {
+        self.result_cache = Some(capacity);
+        self
+    }
+
+    /// Sends at most `max` copies of a packet at once, at least one, so that a packet with
+    /// many next hops does not delay the other packets. The other copies are dropped or
+    /// deferred following `overflow`. Unlimited by default.
+    pub fn max_copies(mut self, max: usize, overflow: OverflowPolicy) -> Self {
+        self.max_copies = Some((max.max(1), overflow));
+        self
+    }
+
+    /// Counters of a previous run of the daemon, e.g., restored with `Stats::load`.
+    /// The counters of the daemon are added to them.
+    pub fn restored_stats(mut self, stats: Stats) -> Self {
+        self.restored_stats = stats;
+        self
+    }
+
+    /// Loads the BIER state when the daemon is asked to reload it, e.g., by reading the
+    /// configuration file again. The daemon cannot be reloaded without a loader.
+    pub fn state_loader(
+        mut self,
+        loader: impl FnMut() -> std::result::Result<BierState, String> + Send + 'static,
+    ) -> Self {
+        self.state_loader = Some(Box::new(loader));
+        self
+    }
+
+    /// Exports a span for each processed packet. Disabled by default.
+    #[cfg(feature = "otel")]
+    pub fn telemetry(mut self, telemetry: &crate::telemetry::Telemetry) -> Self {
+        self.tracer = Some(telemetry.tracer());
+        self
+    }
+
+    /// Compiles the BIER state, see `BierState::compile`.
+    pub fn build(self) -> io::Result<BierDaemon> {
+        let mut state = self
+            .state
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing BIER state"))?;
+        let report = state.compile().map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("cannot compile the BIER state: {}", e),
+            )
+        })?;
+        info!("Compiled the BIER state: {}", report);
+        let transport = match self.transport {
+            Some(transport) => transport,
+            None => Box::new(raw_socket(state.get_loopback(), &state.socket)?),
+        };
+
+        Ok(BierDaemon {
+            forwarder: Forwarder {
+                stats: AtomicStats::new(&state),
+                state,
+                transport,
+                transports: self.transports,
+                on_local_delivery: self.on_local_delivery,
+                packet_log: self.packet_log,
+                trace: self.trace,
+                capture: self.capture,
+                cache: self.result_cache.map(ResultCache::new),
+                max_copies: self.max_copies,
+                deferred: VecDeque::new(),
+                copies: Vec::new(),
+                #[cfg(feature = "otel")]
+                tracer: self.tracer,
+            },
+            buffer: vec![0u8; MAX_PACKET_LENGTH],
+            templates: HashMap::new(),
+            last_read: self.restored_stats.clone(),
+            restored_stats: self.restored_stats,
+            state_loader: self.state_loader,
+            on_registration: self.on_registration,
+        })
+    }
+}
+
+/// BFR processing of the packets sent by the local applications and received from the network.
+pub struct BierDaemon {
+    forwarder: Forwarder,
+    buffer: Vec<u8>,
+    /// Headers of the flows of the applications, serialized once.
+    templates: HashMap<FlowKey, HeaderTemplate>,
+    /// Counters of the previous runs of the daemon.
+    restored_stats: Stats,
+    /// Counters at the last call to `stats_delta`.
+    last_read: Stats,
+    state_loader: Option<StateLoader>,
+    on_registration: Option<OnRegistration>,
+}
+
+impl BierDaemon {
+    pub fn builder() -> BierDaemonBuilder {
+        BierDaemonBuilder::default()
+    }
+
+    pub fn state(&self) -> &BierState {
+        &self.forwarder.state
+    }
+
+    /// Modifies a copy of the BIFTs with `update`, and replaces the current ones by it, see
+    /// `replace_state`. The current BIFTs are kept if the copy cannot be compiled.
+    pub fn update_state<R>(&mut self, update: impl FnOnce(&mut BierState) -> R) -> Result<R> {
+        let mut state = self.forwarder.state.clone();
+        let res = update(&mut state);
+        let report = self.replace_state(state)?;
+        info!("Compiled the updated BIER state: {}", report);
+        Ok(res)
+    }
+
+    /// Declares `next_hop` alive or dead, see `BierState::set_next_hop_alive`, without
+    /// compiling the BIFTs again. The cached results are removed if its liveness changed.
+    pub fn set_next_hop_alive(&mut self, next_hop: IpAddr, alive: bool) -> bool {
+        let changed = self.forwarder.state.set_next_hop_alive(next_hop, alive);
+        if changed {
+            if let Some(cache) = self.forwarder.cache.as_mut() {
+                cache.clear();
+            }
+        }
+        changed
+    }
+
+    /// Sets the bit of the BFER `bfr_id` in the overlay entry of `group` and `source`, see
+    /// `BierState::join_group`. The overlay is updated in place: the BIFTs are not compiled
+    /// again.
+    pub fn join_group(
+        &mut self,
+        group: IpAddr,
+        source: Option<IpAddr>,
+        sub_domain: u8,
+        bfr_id: u64,
+    ) -> Result<bool> {
+        self.forwarder
+            .state
+            .join_group(group, source, sub_domain, bfr_id)
+    }
+
+    /// Clears the bit of the BFER `bfr_id` in the overlay entry of `group` and `source`, see
+    /// `Overlay::leave`, without compiling the BIFTs again.
+    pub fn leave_group(&mut self, group: IpAddr, source: Option<IpAddr>, bfr_id: u64) -> bool {
+        self.forwarder.state.overlay.leave(group, source, bfr_id)
+    }
+
+    /// Starts writing the decision trace in `trace`, or stops it with `None`.
+    pub fn set_trace(&mut self, trace: Option<PacketLog>) {
+        self.forwarder.trace = trace;
+    }
+
+    /// Replaces the BIER state, e.g., after a reload of the configuration. The new state is
+    /// compiled first, and the current one is kept if it fails. The cached results and the
+    /// headers computed from the previous state are removed.
+    pub fn replace_state(&mut self, mut state: BierState) -> Result<CompileReport> {
+        let report = state.compile()?;
+        // The liveness of the next hops does not depend on the configuration.
+        state.dead_next_hops = std::mem::take(&mut self.forwarder.state.dead_next_hops);
+        state.update_reroutes();
+        self.forwarder.state = state;
+        if let Some(cache) = self.forwarder.cache.as_mut() {
+            cache.clear();
+        }
+        self.templates.clear();
+        Ok(report)
+    }
+
+    /// Replaces the BIER state by the one of the state loader, e.g., on SIGHUP, without
+    /// closing the sockets. The current state is kept if the new one cannot be loaded or
+    /// compiled, or if it changes what the sockets and transports were built from: the
+    /// loopback, the socket options, and the encapsulation, bound socket and MAC address of
+    /// the neighbors.
+    pub fn reload(&mut self) -> std::result::Result<CompileReport, String> {
+        let loader = self.state_loader.as_mut().ok_or("No state loader")?;
+        let state = loader()?;
+        let current = self.state();
+        if state.get_loopback() != current.get_loopback() {
+            return Err("The loopback changed".into());
+        }
+        if state.socket != current.socket {
+            return Err("The socket options changed, restart to apply them".into());
+        }
+        if let Some(next_hop) = changed_transport(current, &state) {
+            return Err(format!(
+                "The transport toward {} changed, restart to apply it",
+                next_hop
+            ));
+        }
+        if !state.api_acl.is_empty() && current.api_acl.is_empty() {
+            warn!("The credentials of the applications are only enabled at startup, restart to apply the ACL");
+        }
+        self.replace_state(state)
+            .map_err(|e| format!("Cannot compile the new state: {}", e))
+    }
+
+    /// Registers the application bound at `sender` with the registration callback, see
+    /// `on_registration`. The registrations of another path are refused, so that an
+    /// application cannot redirect the packets to another socket.
+    pub fn register(&mut self, registration: &Registration, sender: &Path) -> Result<()> {
+        if !registration.path.is_empty() && Path::new(&registration.path) != sender {
+            return Err(Error::Registration);
+        }
+        let callback = self.on_registration.as_mut().ok_or(Error::Registration)?;
+        callback(registration, sender)
+    }
+
+    /// Snapshot of the cumulative counters, including the restored ones.
+    pub fn stats(&self) -> Stats {
+        let mut stats = self.restored_stats.clone();
+        stats.merge(&self.forwarder.stats.snapshot());
+        for next_hop in self.forwarder.state.drained_next_hops() {
+            stats.neighbors.entry(next_hop).or_default().drained = true;
+        }
+        for next_hop in self.forwarder.state.dead_next_hops.iter() {
+            stats.neighbors.entry(*next_hop).or_default().dead = true;
+        }
+        stats
+    }
+
+    /// Whether copies deferred by the fan-out cap wait for `send_deferred`.
+    pub fn has_deferred(&self) -> bool {
+        !self.forwarder.deferred.is_empty()
+    }
+
+    /// Sends at most the fan-out cap of the deferred copies, starting with the oldest
+    /// packet. Returns whether copies are still deferred.
+    pub fn send_deferred(&mut self) -> bool {
+        self.forwarder.send_deferred();
+        self.has_deferred()
+    }
+
+    /// Counters since the previous call, or since the start of the daemon.
+    pub fn stats_delta(&mut self) -> Stats {
+        let stats = self.stats();
+        let delta = stats.delta(&self.last_read);
+        self.last_read = stats;
+        delta
+    }
+
+    /// Receives a BIER packet with `transport` in `buf` and forwards it, e.g., the copies of
+    /// another daemon in a test or a simulated network. The errors of the transport are
+    /// returned, e.g., `WouldBlock` once there is nothing to receive. The packets which cannot
+    /// be forwarded are counted as dropped, including the ones filling `buf`, which are
+    /// possibly truncated: `buf` must be larger than the largest packet.
+    pub fn recv_from(
+        &mut self,
+        transport: &mut (impl Transport + ?Sized),
+        buf: &mut [u8],
+    ) -> io::Result<()> {
+        let length = transport.recv(buf)?;
+        if length == buf.len() {
+            self.record_drop(DropReason::Truncated);
+            warn!(
+                "Dropped a packet larger than the receive buffer of {} bytes",
+                length.saturating_sub(1)
+            );
+            return Ok(());
+        }
+        if let Err(e) = self.process_packet(&mut buf[..length]) {
+            debug!(
+                "Error when processing the BIER packet: {:?}, continuing...",
+                e
+            );
+        }
+        Ok(())
+    }
+
+    /// Records a packet dropped before reaching the daemon, e.g., an unauthorized application.
+    pub fn record_drop(&mut self, reason: DropReason) {
+        self.forwarder.stats.record_drop(reason);
+    }
+
+    /// Encapsulates the payload of a local application in a BIER packet and forwards it.
+    /// A bitstring longer than the BSL of the BIFT covers several set identifiers (SIs), and
+    /// is sent in one packet per SI (see `BierState::split_sets`). The packets of all the SIs
+    /// are sent even if one of them fails, and the first error is returned.
+    pub fn send(&mut self, send_info: &SendInfo) -> Result<()> {
+        let ingress = Instant::now();
+        self.forwarder
+            .stats
+            .record_from_app(send_info.payload.len());
+        let bsl = self
+            .forwarder
+            .state
+            .bift(send_info.bift_id)
+            .map_or(0, |bift| bift.get_bsl());
+        if bsl == 0 || send_info.bitstring.len() * 8 <= bsl {
+            return self.send_packet(send_info, ingress);
+        }
+        let sets = match self
+            .forwarder
+            .state
+            .split_sets(send_info.bift_id, send_info.bitstring)
+        {
+            Ok(v) => v,
+            Err(e) => {
+                let reason = match e {
+                    Error::BiftId(_) => DropReason::UnknownBift,
+                    _ => DropReason::MalformedApiMessage,
+                };
+                self.forwarder.drop_packet(
+                    PacketSource::Application,
+                    send_info.payload.len(),
+                    None,
+                    reason,
+                    ingress,
+                );
+                return Err(e);
+            }
+        };
+        let mut res = Ok(());
+        for (bift_id, bitstring) in sets {
+            let send_info = SendInfo {
+                bift_id,
+                bitstring,
+                ..*send_info
+            };
+            res = res.and(self.send_packet(&send_info, ingress));
+        }
+        res
+    }
+
+    /// Sends the packet of a single SI of `send`.
+    fn send_packet(&mut self, send_info: &SendInfo, ingress: Instant) -> Result<()> {
+        let forwarder = &mut self.forwarder;
+        let metadata = send_info.metadata.unwrap_or_default();
+        let key = (
+            send_info.bift_id,
+            send_info.proto,
+            send_info.bitstring.len(),
+        );
+        // The templates have the default TTL, DSCP and S bit of the proto.
+        let template = metadata.ttl.is_none() && metadata.dscp.is_none() && metadata.s.is_none();
+        if template
+            && !self.templates.contains_key(&key)
+            && self.templates.len() < MAX_HEADER_TEMPLATES
+        {
+            if let Ok(bier_header) = flow_header(&forwarder.state, send_info) {
+                self.templates
+                    .insert(key, HeaderTemplate::new(&bier_header)?);
+            }
+        }
+        // The header of a flow with a template is built once, and only its bitstring and
+        // entropy are set for each packet.
+        let mut built = None;
+        let (bier_header, template) = match self.templates.get_mut(&key).filter(|_| template) {
+            Some(template) => {
+                let bier_header = template.header();
+                let entropy =
+                    packet_entropy(&forwarder.state, &metadata, bier_header, send_info.payload);
+                template.update(send_info.bitstring, entropy)?;
+                let template = &*template;
+                (template.header(), Some(template))
+            }
+            None => match flow_header(&forwarder.state, send_info) {
+                Ok(mut bier_header) => {
+                    let entropy = packet_entropy(
+                        &forwarder.state,
+                        &metadata,
+                        &bier_header,
+                        send_info.payload,
+                    );
+                    bier_header.set_entropy(entropy);
+                    (&*built.insert(bier_header), None)
+                }
+                Err(e) => {
+                    forwarder.drop_packet(
+                        PacketSource::Application,
+                        send_info.payload.len(),
+                        None,
+                        DropReason::MalformedApiMessage,
+                        ingress,
+                    );
+                    return Err(e);
+                }
+            },
+        };
+        if bier_header.proto().is_ok_and(BierProto::is_mpls) {
+            if let Err(e) = header::mpls_label_stack_length(send_info.payload) {
+                forwarder.drop_packet(
+                    PacketSource::Application,
+                    send_info.payload.len(),
+                    Some(bier_header),
+                    DropReason::MalformedApiMessage,
+                    ingress,
+                );
+                return Err(e);
+            }
+        }
+        let packet_length = bier_header.header_length() + send_info.payload.len();
+        if self.buffer.len() < packet_length {
+            forwarder.drop_packet(
+                PacketSource::Application,
+                send_info.payload.len(),
+                None,
+                DropReason::MalformedApiMessage,
+                ingress,
+            );
+            return Err(Error::PacketTooLarge);
+        }
+        match template {
+            Some(template) => {
+                template.write(
+                    &mut self.buffer,
+                    send_info.bitstring,
+                    bier_header.get_entropy(),
+                )?;
+            }
+            None => {
+                bier_header.to_slice(&mut self.buffer)?;
+            }
+        }
+        self.buffer[bier_header.header_length()..packet_length].copy_from_slice(send_info.payload);
+
+        forwarder.forward_packet(
+            PacketSource::Application,
+            bier_header,
+            &mut self.buffer[..packet_length],
+            ingress,
+        )
+    }
+
+    /// Forwards a BIER packet received from the network.
+    /// The packet starts with the BIER header, and is modified in place.
+    /// The latency of the copies is measured from the call.
+    pub fn process_packet(&mut self, packet: &mut [u8]) -> Result<()> {
+        let ingress = Instant::now();
+        let forwarder = &mut self.forwarder;
+        forwarder.stats.record_received(packet.len());
+        if forwarder.capture.is_some() {
+            forwarder.capture(packet, None);
+        }
+        let bier_header = match BierHeader::from_slice(packet) {
+            Ok(v) => v,
+            Err(e) => {
+                forwarder.drop_packet(
+                    PacketSource::Network,
+                    packet.len(),
+                    None,
+                    DropReason::MalformedPacket,
+                    ingress,
+                );
+                return Err(e);
+            }
+        };
+        if forwarder.state.strict_rfc8296 {
+            if let Err(e) = bier_header.check_rfc8296() {
+                forwarder.drop_packet(
+                    PacketSource::Network,
+                    packet.len(),
+                    Some(&bier_header),
+                    DropReason::InvalidVersion,
+                    ingress,
+                );
+                return Err(e);
+            }
+        }
+        forwarder.forward_packet(PacketSource::Network, &bier_header, packet, ingress)
+    }
+
+    /// Forwards a BIER packet received in an MPLS network: the packet starts with the label
+    /// stack, whose bottom entry is the first word of the BIER header. The outer entries are
+    /// removed, and the label of the bottom entry is replaced by its BIFT-id, see
+    /// `BierState::mpls_labels`.
+    pub fn process_mpls_packet(&mut self, packet: &mut [u8]) -> Result<()> {
+        let offset = match header::bier_header_offset(packet) {
+            Ok(offset) => offset,
+            Err(e) => {
+                let forwarder = &mut self.forwarder;
+                forwarder.stats.record_received(packet.len());
+                forwarder.drop_packet(
+                    PacketSource::Network,
+                    packet.len(),
+                    None,
+                    DropReason::MalformedPacket,
+                    Instant::now(),
+                );
+                return Err(e);
+            }
+        };
+        let packet = &mut packet[offset..];
+        let (first, _) = packet
+            .split_first_chunk_mut::<MPLS_LABEL_ENTRY_LENGTH>()
+            .ok_or(Error::MplsLabelStack)?;
+        let mut entry = LabelStackEntry::from_bytes(*first);
+        entry.label = self.forwarder.state.mpls_bift_id(entry.label);
+        *first = entry.to_bytes();
+        self.process_packet(packet)
+    }
+
+    /// Forwards a BIERin6 packet, i.e., the payload of a Destination Options header holding
+    /// the BIER option, see `bierin6`.
+    pub fn process_bierin6_packet(&mut self, packet: &mut [u8]) -> Result<()> {
+        let length = packet.len();
+        let option_type = self.forwarder.bierin6_option();
+        match bierin6::decapsulate(packet, option_type) {
+            Ok(packet) => self.process_packet(packet),
+            Err(e) => {
+                let forwarder = &mut self.forwarder;
+                forwarder.stats.record_received(length);
+                forwarder.drop_packet(
+                    PacketSource::Network,
+                    length,
+                    None,
+                    DropReason::MalformedPacket,
+                    Instant::now(),
+                );
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Header of the packets of an application flow, without their entropy.
+fn flow_header(state: &BierState, send_info: &SendInfo) -> Result<BierHeader> {
+    let mut bier_header = BierHeader::from_recv_info(send_info)?;
+    if state.strict_rfc8296 {
+        bier_header.set_nibble(RFC8296_NIBBLE);
+    }
+    // The BFERs answer the OAM requests toward the BFIR-id of the packet.
+    if let Some(bift) = state.bift(send_info.bift_id) {
+        bier_header.set_bfr_id(bift.bfr_id as u16);
+    }
+    // In MPLS networks, the first word of the BIER header is the bottom of the label stack,
+    // unless the application chose the S bit.
+    if bier_header.proto().is_ok_and(BierProto::is_mpls) {
+        let s = send_info.metadata.and_then(|metadata| metadata.s);
+        bier_header.set_s(s.unwrap_or(true));
+    }
+    Ok(bier_header)
+}
+
+/// Entropy of a packet sent by an application. The packets of a flow follow the same ECMP
+/// paths, in this BFR and downstream, unless the application chose the entropy of its packets.
+fn packet_entropy(
+    state: &BierState,
+    metadata: &Metadata,
+    bier_header: &BierHeader,
+    payload: &[u8],
+) -> u32 {
+    match metadata.entropy {
+        Some(entropy) => entropy,
+        None if state.path_selection == PathSelection::Ecmp => {
+            header::flow_entropy(bier_header.get_proto(), payload)
+        }
+        None => 0,
+    }
+}
+
+/// First next hop whose transport differs between the `current` state and the new `state`,
+/// i.e., its encapsulation, the options of its bound socket or its MAC address.
+fn changed_transport(current: &BierState, state: &BierState) -> Option<IpAddr> {
+    let transport = |state: &BierState, next_hop: &IpAddr| {
+        let neighbor = state.neighbors.get(next_hop).cloned().unwrap_or_default();
+        let bound = neighbor
+            .is_bound()
+            .then(|| neighbor.socket_config(&state.socket));
+        (neighbor.encapsulation, bound, neighbor.mac)
+    };
+    current
+        .neighbors
+        .keys()
+        .chain(state.neighbors.keys())
+        .find(|next_hop| transport(current, next_hop) != transport(state, next_hop))
+        .copied()
+}
+
+/// Writes the copies of `BierState::process_bier` in the first entries of `copies`, reusing
+/// their bitstrings, so that the packets are forwarded without allocation. Returns the
+/// number of copies.
+fn process_bier_into(
+    state: &BierState,
+    bitstring: &Bitstring,
+    bift_id: u32,
+    entropy: u32,
+    copies: &mut Vec<BierSendInfo>,
+) -> Result<usize> {
+    let mut nb_copies = 0;
+    state.process_bier_with(bitstring, bift_id, entropy, |copy, next_hop| {
+        match copies.get_mut(nb_copies) {
+            Some((bitstring, nxt_hop)) => {
+                copy.write_to(bitstring);
+                *nxt_hop = next_hop;
+            }
+            None => copies.push((copy.to_bitstring(), next_hop)),
+        }
+        nb_copies += 1;
+    })?;
+    Ok(nb_copies)
+}
+
+/// Parts of the daemon used to process a packet, separated from the packet buffer.
+struct Forwarder {
+    state: BierState,
+    transport: Box<dyn Transport + Send>,
+    /// Transports of the copies toward the neighbors with another encapsulation than IP.
+    transports: HashMap<Encapsulation, Box<dyn Transport + Send>>,
+    on_local_delivery: Option<LocalDelivery>,
+    stats: AtomicStats,
+    packet_log: Option<PacketLog>,
+    /// Decision trace of the processed packets.
+    trace: Option<PacketLog>,
+    /// Capture of the received and sent packets, stopped at the first error.
+    capture: Option<BierCapture>,
+    cache: Option<ResultCache>,
+    max_copies: Option<(usize, OverflowPolicy)>,
+    deferred: VecDeque<DeferredPacket>,
+    /// Copies of the last packet without result cache, whose bitstrings are reused.
+    copies: Vec<BierSendInfo>,
+    #[cfg(feature = "otel")]
+    tracer: Option<opentelemetry_sdk::trace::SdkTracer>,
+}
+
+impl Forwarder {
+    /// Whether the processed packets are recorded, in the packet log or as spans.
+    fn records_packets(&self) -> bool {
+        #[cfg(feature = "otel")]
+        if self.tracer.is_some() {
+            return true;
+        }
+        self.packet_log.is_some()
+    }
+
+    /// Records a packet processed since `ingress`.
+    #[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+    fn log(&mut self, entry: &PacketLogEntry, ingress: Instant) {
+        if let Some(packet_log) = self.packet_log.as_mut() {
+            if let Err(e) = packet_log.write(entry) {
+                error!("Error when writing the packet log: {:?}", e);
+            }
+        }
+        #[cfg(feature = "otel")]
+        if let Some(tracer) = self.tracer.as_ref() {
+            crate::telemetry::export_span(tracer, entry, ingress);
+        }
+    }
+
+    fn drop_packet(
+        &mut self,
+        source: PacketSource,
+        length: usize,
+        bier_header: Option<&BierHeader>,
+        reason: DropReason,
+        ingress: Instant,
+    ) {
+        self.stats.record_drop(reason);
+        if self.records_packets() {
+            let mut entry = PacketLogEntry::now(source, length);
+            entry.header = bier_header;
+            entry.drop = Some(reason);
+            self.log(&entry, ingress);
+        }
+    }
+
+    /// Replicates the BIER `packet` toward its next hops, and delivers it
+    /// locally if this BFER is a destination. The latency of each copy sent
+    /// is measured from `ingress`.
+    fn forward_packet(
+        &mut self,
+        source: PacketSource,
+        bier_header: &BierHeader,
+        packet: &mut [u8],
+        ingress: Instant,
+    ) -> Result<()> {
+        let bift_id = bier_header.get_bift_id();
+        let entropy = bier_header.get_entropy();
+        // Without clearing, the unknown bits have no entry and the packet is dropped.
+        let mut cleared = None;
+        if self.state.unknown_bits == UnknownBitsPolicy::Clear {
+            if let Some(highest) = self.state.highest_bit(bift_id) {
+                let nb_bits = bier_header.get_bitstring().count_above(highest);
+                if nb_bits > 0 {
+                    let mut bitstring = bier_header.get_bitstring().clone();
+                    bitstring.clear_above(highest);
+                    self.stats.record_cleared_bits(nb_bits);
+                    cleared = Some(bitstring);
+                }
+            }
+        }
+        let bitstring = cleared.as_ref().unwrap_or(bier_header.get_bitstring());
+        if self.trace.is_some() {
+            self.trace(source, bitstring, bift_id, entropy);
+        }
+        let mut computed = std::mem::take(&mut self.copies);
+        let res = match self.cache.as_mut() {
+            Some(cache) => cache
+                .process_bier(&self.state, bitstring, bift_id, entropy)
+                .map(|(next_hops, hit)| {
+                    self.stats.record_cache(hit);
+                    (Some(next_hops), 0)
+                }),
+            None => process_bier_into(&self.state, bitstring, bift_id, entropy, &mut computed)
+                .map(|nb_copies| (None, nb_copies)),
+        };
+        let (cached, nb_copies) = match res {
+            Ok(v) => v,
+            Err(e) => {
+                self.copies = computed;
+                let reason = match e {
+                    Error::BiftId(_) => DropReason::UnknownBift,
+                    Error::NoEntry => DropReason::NoEntry,
+                    _ => DropReason::MalformedPacket,
+                };
+                self.drop_packet(source, packet.len(), Some(bier_header), reason, ingress);
+                return Err(e);
+            }
+        };
+        let bier_next_hops = cached.as_deref().unwrap_or(&computed[..nb_copies]);
+        let max_copies = self.max_copies.map_or(usize::MAX, |(max, _)| max);
+        let (copies, overflow) = bier_next_hops.split_at(bier_next_hops.len().min(max_copies));
+        let mut logged_copies = self.send_copies(bier_header, packet, copies, ingress);
+        if !overflow.is_empty() {
+            self.overflow(
+                source,
+                bier_header,
+                packet,
+                overflow,
+                ingress,
+                &mut logged_copies,
+            );
+        }
+        self.copies = computed;
+
+        if self.records_packets() {
+            let mut entry = PacketLogEntry::now(source, packet.len());
+            entry.header = Some(bier_header);
+            entry.copies = logged_copies;
+            self.log(&entry, ingress);
+        }
+
+        Ok(())
+    }
+
+    /// Writes the replication decisions of a packet in the decision trace. They are computed
+    /// again, so that the packets answered by the result cache are explained too.
+    fn trace(&mut self, source: PacketSource, bitstring: &Bitstring, bift_id: u32, entropy: u32) {
+        let mut entry = TraceEntry::now(source, bift_id, entropy, bitstring);
+        match self.state.explain(bitstring, bift_id, entropy) {
+            Ok(decisions) => entry.decisions = decisions,
+            Err(e) => entry.error = Some(e.to_string()),
+        }
+        if let Some(trace) = self.trace.as_mut() {
+            if let Err(e) = trace.write(&entry) {
+                error!("Error when writing the decision trace: {:?}", e);
+            }
+        }
+    }
+
+    /// Handles the copies of a packet beyond the fan-out cap, following the overflow policy.
+    fn overflow(
+        &mut self,
+        source: PacketSource,
+        bier_header: &BierHeader,
+        packet: &[u8],
+        copies: &[BierSendInfo],
+        ingress: Instant,
+        logged_copies: &mut Vec<LoggedCopy>,
+    ) {
+        let deferred = self
+            .max_copies
+            .is_some_and(|(_, overflow)| overflow == OverflowPolicy::Defer)
+            && self.deferred.len() < MAX_DEFERRED_PACKETS;
+        if deferred {
+            debug!("Deferring {} copies beyond the fan-out cap", copies.len());
+            self.deferred.push_back(DeferredPacket {
+                source,
+                header: bier_header.clone(),
+                packet: packet.to_vec(),
+                copies: copies.to_vec(),
+                ingress,
+            });
+            return;
+        }
+        debug!("Dropping {} copies beyond the fan-out cap", copies.len());
+        for (bitstring, nxt_hop) in copies {
+            self.stats.record_drop(DropReason::FanOutCap);
+            if self.records_packets() {
+                logged_copies.push(LoggedCopy {
+                    next_hop: *nxt_hop,
+                    bitstring: bitstring.clone(),
+                    sent: false,
+                });
+            }
+        }
+    }
+
+    /// Sends at most the fan-out cap of the copies of the oldest deferred packet.
+    /// Each call is logged as a packet with its copies.
+    fn send_deferred(&mut self) {
+        let Some(mut deferred) = self.deferred.pop_front() else {
+            return;
+        };
+        let max_copies = self.max_copies.map_or(usize::MAX, |(max, _)| max);
+        let remaining = deferred
+            .copies
+            .split_off(deferred.copies.len().min(max_copies));
+        let logged_copies = self.send_copies(
+            &deferred.header,
+            &mut deferred.packet,
+            &deferred.copies,
+            deferred.ingress,
+        );
+        if self.records_packets() {
+            let mut entry = PacketLogEntry::now(deferred.source, deferred.packet.len());
+            entry.header = Some(&deferred.header);
+            entry.copies = logged_copies;
+            self.log(&entry, deferred.ingress);
+        }
+        if !remaining.is_empty() {
+            deferred.copies = remaining;
+            self.deferred.push_front(deferred);
+        }
+    }
+
+    /// Sends the `copies` of the BIER `packet`, modified in place, toward their next hop or
+    /// to the local application. Returns the copies to log, if the packets are recorded.
+    fn send_copies(
+        &mut self,
+        bier_header: &BierHeader,
+        packet: &mut [u8],
+        copies: &[BierSendInfo],
+        ingress: Instant,
+    ) -> Vec<LoggedCopy> {
+        let mut logged_copies = Vec::new();
+        let policy = self.state.delivery_policy(bier_header.get_proto());
+        // The bits of the packet represent the BFR-ids of the SI of its BIFT.
+        let offset = self
+            .state
+            .bift(bier_header.get_bift_id())
+            .map_or(0, |bift| bift.bfr_id_offset());
+
+        // For each next-hop, send the modified packet with the transport.
+        for (bitstring, nxt_hop) in copies {
+            let nxt_hop = *nxt_hop;
+            if !policy.allows(nxt_hop) {
+                debug!(
+                    "Copy toward {:?} denied by the delivery policy {:?}",
+                    nxt_hop, policy
+                );
+                self.stats.record_drop(DropReason::DeliveryPolicy);
+                if self.records_packets() {
+                    logged_copies.push(LoggedCopy {
+                        next_hop: nxt_hop,
+                        bitstring: bitstring.clone(),
+                        sent: false,
+                    });
+                }
+                continue;
+            }
+            // Update the BIER bitstring with the provided bitstring.
+            match bitstring.update_header_from_self(packet) {
+                Ok(_) => debug!("Updated the header"),
+                Err(e) => {
+                    debug!("Error when updating the packet: {:?}, continuing...", e);
+                    continue;
+                }
+            }
+
+            self.stats.record_bfers(bitstring, offset, packet.len());
+
+            let sent = if let Some(dst) = nxt_hop {
+                // A neighbor with a smaller BSL cannot parse the bitstring of the packet.
+                let neighbor = self
+                    .state
+                    .neighbors
+                    .get(&dst)
+                    .filter(|neighbor| {
+                        neighbor
+                            .bsl
+                            .is_some_and(|bsl| bsl < bitstring.bitstring.len() * 64)
+                    })
+                    .cloned();
+                match neighbor {
+                    Some(neighbor) => {
+                        let payload = &packet[bier_header.header_length()..];
+                        self.send_reencapsulated(
+                            bier_header,
+                            bitstring,
+                            &neighbor,
+                            payload,
+                            dst,
+                            ingress,
+                        )
+                    }
+                    None => self.send_copy(packet, dst, ingress),
+                }
+            } else {
+                let payload = &packet[bier_header.header_length()..];
+                self.deliver_locally(bier_header, bitstring, payload, ingress)
+            };
+
+            if self.records_packets() {
+                logged_copies.push(LoggedCopy {
+                    next_hop: nxt_hop,
+                    bitstring: bitstring.clone(),
+                    sent,
+                });
+            }
+        }
+        for transport in std::iter::once(&mut self.transport).chain(self.transports.values_mut()) {
+            if let Err(e) = transport.flush() {
+                debug!("Error when flushing the transport: {:?}, continuing...", e);
+            }
+        }
+        logged_copies
+    }
+
+    /// Hands a copy destined to this BFER to the upper layer of its proto: the daemon answers
+    /// the echo requests, and the other payloads are delivered without the BIER header by
+    /// `on_local_delivery`, e.g., to the `dispatch::Dispatcher` of the applications.
+    fn deliver_locally(
+        &mut self,
+        bier_header: &BierHeader,
+        bitstring: &Bitstring,
+        payload: &[u8],
+        ingress: Instant,
+    ) -> bool {
+        match bier_header.proto() {
+            Ok(BierProto::Oam) => {
+                // The daemon answers the echo requests instead of the local application.
+                if let Some(request) = echo_request(bier_header, payload) {
+                    return self.answer_echo(bier_header, &request, ingress);
+                }
+            }
+            Ok(proto) if proto.is_mpls() && header::mpls_label_stack_length(payload).is_err() => {
+                debug!("MPLS payload without bottom of stack, dropping the local copy");
+                self.stats.record_drop(DropReason::MalformedPacket);
+                return false;
+            }
+            _ => (),
+        }
+        let Some(callback) = self.on_local_delivery.as_mut() else {
+            debug!("No local delivery, dropping the packet");
+            self.stats.record_drop(DropReason::NoLocalDelivery);
+            return false;
+        };
+        let bitstring: Vec<u8> = bitstring.into();
+        let recv_info = RecvInfo {
+            bift_id: bier_header.get_bift_id(),
+            proto: bier_header.get_proto() as u16,
+            bitstring: &bitstring,
+            payload,
+            metadata: Some(Metadata {
+                bfir_id: Some(bier_header.get_bfr_id()),
+                ttl: Some(bier_header.get_ttl()),
+                dscp: Some(bier_header.get_dscp()),
+                entropy: Some(bier_header.get_entropy()),
+                s: Some(bier_header.get_s()),
+            }),
+        };
+        self.stats.record_delivered(payload.len());
+        callback(&recv_info);
+        true
+    }
+
+    /// Answers the echo `request` of a packet with `bier_header` for which this BFR is a
+    /// BFER. The reply is forwarded toward the BFIR. Returns whether it was sent.
+    fn answer_echo(
+        &mut self,
+        bier_header: &BierHeader,
+        request: &EchoMessage,
+        ingress: Instant,
+    ) -> bool {
+        match request.reply_mode {
+            ReplyMode::DoNotReply => return false,
+            ReplyMode::Udp => {
+                debug!("Unsupported reply mode of the echo request, dropping it");
+                self.stats.record_drop(DropReason::OamNoReply);
+                return false;
+            }
+            ReplyMode::Bier => (),
+        }
+        let bift_id = bier_header.get_bift_id();
+        let bfir_id = bier_header.get_bfr_id() as u64;
+        let (Some(bift), Ok(bitstring)) = (
+            self.state.bift(bift_id),
+            self.state.bitstring_for_bfr_id(bift_id, bfir_id),
+        ) else {
+            debug!(
+                "No entry for the BFIR {} of the echo request, dropping it",
+                bfir_id
+            );
+            self.stats.record_drop(DropReason::OamNoReply);
+            return false;
+        };
+        let header = bier_header.reply(bitstring, header::PROTO_OAM, bift.bfr_id as u16);
+        let reply = request.reply(oam::RETURN_CODE_BFER, bift.bfr_id as u16, SystemTime::now());
+        let mut packet = vec![0u8; header.header_length() + reply.length()];
+        let res = header
+            .to_slice(&mut packet)
+            .and_then(|_| reply.to_slice(&mut packet[header.header_length()..]));
+        if res.is_err() {
+            self.stats.record_drop(DropReason::OamNoReply);
+            return false;
+        }
+        debug!(
+            "Answering the echo request {} of the BFIR {}",
+            request.sequence, bfir_id
+        );
+        self.forward_packet(PacketSource::Application, &header, &mut packet, ingress)
+            .is_ok()
+    }
+
+    /// Records a packet received, or sent to `next_hop`, in the capture. The capture stops
+    /// at the first error, e.g., when the reader of its FIFO exits.
+    fn capture(&mut self, packet: &[u8], next_hop: Option<IpAddr>) {
+        let Some(capture) = self.capture.as_mut() else {
+            return;
+        };
+        let loopback = self.state.get_loopback();
+        let res = match next_hop {
+            Some(next_hop) => capture.sent(packet, loopback, next_hop),
+            None => capture.received(packet, loopback),
+        };
+        if let Err(e) = res {
+            error!("Error when writing the capture, stopping it: {:?}", e);
+            self.capture = None;
+        }
+    }
+
+    /// Sends a copy to `dst` with the transport of its encapsulation. Returns whether it
+    /// was sent.
+    fn send_copy(&mut self, packet: &[u8], dst: IpAddr, ingress: Instant) -> bool {
+        let encapsulation = self.state.encapsulation(dst);
+        let copy = match encapsulation {
+            Encapsulation::Mpls => Some(self.mpls_copy(packet, dst)),
+            Encapsulation::Bierin6 => {
+                let option_type = self.bierin6_option();
+                match bierin6::encapsulate(packet, option_type) {
+                    Ok(copy) => Some(copy),
+                    Err(e) => {
+                        debug!("Cannot encapsulate the copy toward {} in IPv6: {}", dst, e);
+                        self.stats.record_drop(DropReason::UnsupportedBsl);
+                        return false;
+                    }
+                }
+            }
+            _ => None,
+        };
+        let wire = copy.as_deref().unwrap_or(packet);
+        let transport = match encapsulation {
+            Encapsulation::Ip => Some(&mut self.transport),
+            encapsulation => self.transports.get_mut(&encapsulation),
+        };
+        let res = match transport {
+            Some(transport) => transport.send_to(wire, dst),
+            None => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "no transport for the encapsulation of the next hop",
+            )),
+        };
+        // Backpressure of a queued transport, the neighbor is not unhealthy.
+        match &res {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.stats.record_drop(DropReason::QueueFull)
+            }
+            _ => self.stats.record_send(dst, wire.len(), res.is_ok()),
+        }
+        if res.is_ok() {
+            self.stats.record_latency(dst, ingress.elapsed());
+            // The capture holds the BIER packet, without its label stack or extension header.
+            if self.capture.is_some() {
+                self.capture(packet, Some(dst));
+            }
+        }
+        match res {
+            Ok(_) => debug!("Sent the packet to {:?}", dst),
+            Err(ref e) => debug!(
+                "Error when sending the packet to {:?}. Error is: {:?}, continuing...",
+                dst, e
+            ),
+        }
+        res.is_ok()
+    }
+
+    /// Type of the option of the BIERin6 packets.
+    fn bierin6_option(&self) -> u8 {
+        self.state
+            .bierin6_option
+            .unwrap_or(bierin6::DEFAULT_OPTION_TYPE)
+    }
+
+    /// Copy of `packet` toward `dst` with the MPLS encapsulation: the outer labels of the
+    /// neighbor, then the packet whose BIFT-id is replaced by the label of the neighbor.
+    fn mpls_copy(&self, packet: &[u8], dst: IpAddr) -> Vec<u8> {
+        let Some((first, rest)) = packet.split_first_chunk::<MPLS_LABEL_ENTRY_LENGTH>() else {
+            return packet.to_vec();
+        };
+        let neighbor = self.state.neighbors.get(&dst);
+        let mut bottom = LabelStackEntry::from_bytes(*first);
+        if let Some(label) = neighbor.and_then(|neighbor| neighbor.mpls_labels.get(&bottom.label)) {
+            bottom.label = *label;
+        }
+        bottom.bottom = true;
+        let outer = neighbor.map_or(&[][..], |neighbor| &neighbor.label_stack[..]);
+        let mut copy = Vec::with_capacity(outer.len() * MPLS_LABEL_ENTRY_LENGTH + packet.len());
+        for label in outer {
+            let entry = LabelStackEntry {
+                label: *label,
+                bottom: false,
+                ..bottom
+            };
+            copy.extend(entry.to_bytes());
+        }
+        copy.extend(bottom.to_bytes());
+        copy.extend_from_slice(rest);
+        copy
+    }
+
+    /// Sends the copy with `bitstring` to a neighbor supporting a smaller BSL: one packet
+    /// per SI of the neighbor, with its BIFT-id. Returns whether a packet was sent.
+    fn send_reencapsulated(
+        &mut self,
+        bier_header: &BierHeader,
+        bitstring: &Bitstring,
+        neighbor: &NeighborConfig,
+        payload: &[u8],
+        dst: IpAddr,
+        ingress: Instant,
+    ) -> bool {
+        let mut sent = false;
+        let bsl = neighbor.bsl.unwrap_or_default();
+        // The SI of the packet starts at the SI `first_si` of the neighbor.
+        let si = self
+            .state
+            .bift(bier_header.get_bift_id())
+            .map_or(0, |bift| bift.si as usize);
+        let first_si = si * bitstring.bitstring.len() * 64 / bsl.max(64);
+        for (si, bitstring) in bitstring.split(bsl / 64) {
+            let si = first_si + si;
+            let Some(&bift_id) = neighbor.bift_ids.get(si) else {
+                debug!(
+                    "No BIFT-id of {:?} for the SI {}, dropping the copy",
+                    dst, si
+                );
+                self.stats.record_drop(DropReason::UnsupportedBsl);
+                continue;
+            };
+            let header = bier_header.reencapsulate(bift_id, bitstring);
+            let mut copy = vec![0; header.header_length() + payload.len()];
+            if header.to_slice(&mut copy).is_err() {
+                self.stats.record_drop(DropReason::UnsupportedBsl);
+                continue;
+            }
+            copy[header.header_length()..].copy_from_slice(payload);
+            sent |= self.send_copy(&copy, dst, ingress);
+        }
+        sent
+    }
+}
+
+/// Echo request of a packet with `bier_header`, if it carries one.
+fn echo_request(bier_header: &BierHeader, payload: &[u8]) -> Option<EchoMessage> {
+    if bier_header.proto() != Ok(BierProto::Oam) {
+        return None;
+    }
+    EchoMessage::from_slice(payload)
+        .ok()
+        .filter(|message| message.message_type == OamMessageType::EchoRequest)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::api::OwnedRecvInfo;
+    use crate::bier::{example_state, BierEntryPath, Bift, BiftEntry, DeliveryPolicy};
+    use std::collections::BTreeSet;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    /// Tests the forwarding of an application payload by an embedded daemon.
+    fn test_daemon_send() {
+        let transport = MemoryTransport::default();
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let delivered_clone = delivered.clone();
+        let mut daemon = BierDaemon::builder()
+            .state(example_state())
+            .transport(transport.clone())
+            .on_local_delivery(move |recv_info| {
+                delivered_clone
+                    .lock()
+                    .unwrap()
+                    .push(OwnedRecvInfo::from(recv_info))
+            })
+            .build()
+            .unwrap();
+
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11111],
+            payload: &[1, 2, 3],
+            metadata: None,
+        };
+        daemon.send(&send_info).unwrap();
+
+        // One copy to node B and one copy to node C.
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 2);
+        let to_b = sent
+            .iter()
+            .find(|(nh, _)| *nh == "fc00:b::1".parse::<IpAddr>().unwrap())
+            .unwrap();
+        assert_eq!(&to_b.1[12..20], &[0, 0, 0, 0, 0, 0, 0, 0b11010]);
+        assert_eq!(&to_b.1[20..], &[1, 2, 3]);
+
+        // One local copy.
+        let delivered = delivered.lock().unwrap();
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].bift_id, 1);
+        assert_eq!(delivered[0].proto, 6);
+        assert_eq!(delivered[0].bitstring, vec![0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(delivered[0].payload, vec![1, 2, 3]);
+        // The BFIR of the packets of the applications is this BFR.
+        let metadata = delivered[0].metadata.unwrap();
+        assert_eq!((metadata.bfir_id, metadata.dscp), (Some(1), Some(0)));
+
+        let stats = daemon.stats();
+        assert_eq!(stats.from_apps.packets, 1);
+        assert_eq!(stats.delivered.bytes, 3);
+        assert_eq!(stats.neighbors.len(), 2);
+        // One latency sample per copy sent.
+        assert!(stats
+            .neighbors
+            .values()
+            .all(|neighbor| neighbor.latency.count == 1));
+        assert_eq!(stats.bfers.keys().copied().collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+        assert!(stats.drops.is_empty());
+    }
+
+    #[test]
+    /// Tests that the cached copies are the same as the computed ones.
+    fn test_daemon_result_cache() {
+        let transport = MemoryTransport::default();
+        let mut daemon = BierDaemon::builder()
+            .state(example_state())
+            .transport(transport.clone())
+            .result_cache(16)
+            .build()
+            .unwrap();
+
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11110],
+            payload: &[1, 2, 3],
+            metadata: None,
+        };
+        daemon.send(&send_info).unwrap();
+        daemon.send(&send_info).unwrap();
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 4);
+        assert_eq!(sent[..2], sent[2..]);
+
+        let stats = daemon.stats();
+        assert_eq!(stats.cache, crate::stats::CacheStats { hits: 1, misses: 1 });
+        assert_eq!(stats.cache.hit_rate(), Some(0.5));
+
+        // The copies toward a dead next hop are not taken from the cache.
+        let next_hop = sent[0].0;
+        assert!(daemon.set_next_hop_alive(next_hop, false));
+        daemon.send(&send_info).unwrap();
+        let sent = transport.sent();
+        assert!(sent.len() > 4);
+        assert!(sent[4..].iter().all(|(other, _)| *other != next_hop));
+        assert_eq!(daemon.stats().cache.misses, 2);
+        // The liveness does not depend on the configuration.
+        daemon.replace_state(example_state()).unwrap();
+        assert_eq!(daemon.state().dead_next_hops, BTreeSet::from([next_hop]));
+        transport.clear();
+        daemon.send(&send_info).unwrap();
+        assert_eq!(transport.sent(), sent[4..]);
+    }
+
+    #[test]
+    /// Tests that the packets of a flow follow the same ECMP path, from their entropy.
+    fn test_daemon_ecmp() {
+        let transport = MemoryTransport::default();
+        let mut state = example_state();
+        state.path_selection = PathSelection::Ecmp;
+        let mut daemon = BierDaemon::builder()
+            .state(state)
+            .transport(transport.clone())
+            .result_cache(16)
+            .build()
+            .unwrap();
+
+        // UDP over IPv6 toward BFRs 4 and 5, reachable through B and C, from several ports.
+        let mut payload = [0u8; 48];
+        payload[0] = 0x60;
+        payload[6] = 17;
+        for port in 0..16 {
+            payload[41] = port;
+            let send_info = SendInfo {
+                bift_id: 1,
+                proto: 6,
+                bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11000],
+                payload: &payload,
+                metadata: None,
+            };
+            daemon.send(&send_info).unwrap();
+            daemon.send(&send_info).unwrap();
+        }
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 32);
+        for packets in sent.chunks(2) {
+            assert_eq!(packets[0], packets[1]);
+            let header = BierHeader::from_slice(&packets[0].1).unwrap();
+            assert_eq!(
+                header.get_entropy(),
+                header::flow_entropy(6, &packets[0].1[header.header_length()..])
+            );
+        }
+        let next_hops: BTreeSet<_> = sent.iter().map(|(next_hop, _)| *next_hop).collect();
+        assert_eq!(next_hops.len(), 2);
+        assert_eq!(daemon.stats().cache.hits, 16);
+
+        // The entropy, TTL and DSCP chosen by the application are kept.
+        transport.clear();
+        let metadata = Metadata {
+            ttl: Some(12),
+            dscp: Some(46),
+            entropy: Some(0x12345),
+            ..Default::default()
+        };
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11000],
+            payload: &payload,
+            metadata: Some(metadata),
+        };
+        daemon.send(&send_info).unwrap();
+        let sent = transport.sent();
+        let header = BierHeader::from_slice(&sent[0].1).unwrap();
+        assert_eq!(
+            (header.get_ttl(), header.get_dscp(), header.get_entropy()),
+            (12, 46, 0x12345)
+        );
+    }
+
+    #[test]
+    /// Tests the replacement of the state, kept if the new one cannot be compiled.
+    fn test_daemon_replace_state() {
+        let transport = MemoryTransport::default();
+        let mut daemon = BierDaemon::builder()
+            .state(example_state())
+            .transport(transport.clone())
+            .result_cache(16)
+            .build()
+            .unwrap();
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b100],
+            payload: &[1, 2, 3],
+            metadata: None,
+        };
+        daemon.send(&send_info).unwrap();
+
+        // BFR 3 is now reached through B.
+        let b: IpAddr = "fc00:b::1".parse().unwrap();
+        let mut state = example_state();
+        state.bifts[0].entries[2].paths[0].next_hop = b;
+        let report = daemon.replace_state(state.clone()).unwrap();
+        assert_eq!(report.nb_bifts, 1);
+        daemon.send(&send_info).unwrap();
+        assert_eq!(transport.sent()[1].0, b);
+        assert_eq!(daemon.stats().cache.misses, 2);
+
+        // Duplicated BFR-id.
+        let mut invalid = example_state();
+        invalid.bifts[0].entries[1].bit = 1;
+        assert_eq!(daemon.replace_state(invalid), Err(Error::BiftParsing));
+        assert_eq!(daemon.state().bifts, state.bifts);
+        daemon.send(&send_info).unwrap();
+        assert_eq!(transport.sent()[2].0, b);
+    }
+
+    #[test]
+    /// Tests the reload of the BIER state with the state loader.
+    fn test_daemon_reload() {
+        let mut daemon = BierDaemon::builder()
+            .state(example_state())
+            .transport(MemoryTransport::default())
+            .build()
+            .unwrap();
+        assert!(daemon.reload().is_err());
+
+        let loaded = Arc::new(Mutex::new(example_state()));
+        let loader = loaded.clone();
+        let mut daemon = BierDaemon::builder()
+            .state(example_state())
+            .transport(MemoryTransport::default())
+            .state_loader(move || Ok(loader.lock().unwrap().clone()))
+            .build()
+            .unwrap();
+        loaded.lock().unwrap().bifts[0].entries.pop();
+        assert_eq!(daemon.reload().unwrap().nb_entries, 4);
+        assert_eq!(daemon.state().bifts[0].entries.len(), 4);
+
+        // The sockets are bound to the previous loopback, built with the previous options
+        // and toward the previous neighbors.
+        loaded.lock().unwrap().loopback = "fc00:42::1".parse().unwrap();
+        assert_eq!(daemon.reload(), Err("The loopback changed".into()));
+        let mut state = example_state();
+        state.socket.hop_limit = Some(4);
+        *loaded.lock().unwrap() = state;
+        assert!(daemon.reload().is_err());
+        let mut state = example_state();
+        let next_hop: IpAddr = "fc00:b::1".parse().unwrap();
+        state.neighbors.insert(
+            next_hop,
+            NeighborConfig {
+                interface: Some("eth1".to_string()),
+                ..Default::default()
+            },
+        );
+        *loaded.lock().unwrap() = state;
+        assert_eq!(
+            daemon.reload(),
+            Err("The transport toward fc00:b::1 changed, restart to apply it".into())
+        );
+        assert_eq!(daemon.state().bifts[0].entries.len(), 4);
+
+        // The capabilities of a neighbor do not depend on the transport.
+        let mut state = example_state();
+        state.neighbors.insert(
+            next_hop,
+            NeighborConfig {
+                bsl: Some(64),
+                ..Default::default()
+            },
+        );
+        *loaded.lock().unwrap() = state;
+        assert!(daemon.reload().is_ok());
+    }
+
+    #[test]
+    /// Tests the copies beyond the fan-out cap, dropped or deferred.
+    fn test_daemon_max_copies() {
+        // One local copy, then two copies toward the neighbors.
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11111],
+            payload: &[1, 2, 3],
+            metadata: None,
+        };
+        let transport = MemoryTransport::default();
+        let mut daemon = BierDaemon::builder()
+            .state(example_state())
+            .transport(transport.clone())
+            .on_local_delivery(|_| ())
+            .max_copies(1, OverflowPolicy::Drop)
+            .build()
+            .unwrap();
+        daemon.send(&send_info).unwrap();
+        assert!(transport.is_empty());
+        assert_eq!(daemon.stats().delivered.packets, 1);
+        assert_eq!(daemon.stats().drops[&DropReason::FanOutCap], 2);
+        assert!(!daemon.has_deferred());
+
+        let transport = MemoryTransport::default();
+        let mut daemon = BierDaemon::builder()
+            .state(example_state())
+            .transport(transport.clone())
+            .on_local_delivery(|_| ())
+            .max_copies(1, OverflowPolicy::Defer)
+            .build()
+            .unwrap();
+        daemon.send(&send_info).unwrap();
+        daemon.send(&send_info).unwrap();
+        assert!(transport.is_empty());
+        assert_eq!(daemon.stats().delivered.packets, 2);
+        assert!(daemon.has_deferred());
+        // One copy per call, the oldest packet first.
+        assert!(daemon.send_deferred());
+        assert!(daemon.send_deferred());
+        assert!(daemon.send_deferred());
+        assert!(!daemon.send_deferred());
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 4);
+        assert_ne!(sent[0].0, sent[1].0);
+        assert_eq!(sent[..2], sent[2..]);
+        assert!(daemon.stats().drops.is_empty());
+    }
+
+    #[test]
+    /// Tests the counters restored from a previous run, and their deltas.
+    fn test_daemon_restored_stats() {
+        let mut restored = Stats::default();
+        restored.from_apps.packets = 10;
+        restored.record_drop(DropReason::NoEntry);
+        let mut daemon = BierDaemon::builder()
+            .state(example_state())
+            .transport(MemoryTransport::default())
+            .restored_stats(restored)
+            .build()
+            .unwrap();
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11110],
+            payload: &[1, 2, 3],
+            metadata: None,
+        };
+        daemon.send(&send_info).unwrap();
+
+        let stats = daemon.stats();
+        assert_eq!(stats.from_apps.packets, 11);
+        assert_eq!(stats.drops[&DropReason::NoEntry], 1);
+        // The restored counters are not part of the first delta.
+        let delta = daemon.stats_delta();
+        assert_eq!(delta.from_apps.packets, 1);
+        assert!(delta.drops.is_empty());
+        assert_eq!(delta.neighbors.len(), 2);
+        assert_eq!(daemon.stats_delta(), Stats::default());
+        daemon.send(&send_info).unwrap();
+        assert_eq!(daemon.stats_delta().from_apps.packets, 1);
+        assert_eq!(daemon.stats().from_apps.packets, 12);
+    }
+
+    #[test]
+    /// Tests a bitstring of an application spanning two SIs, sent in one packet per SI.
+    fn test_daemon_set_identifiers() {
+        let mut state = example_state();
+        let mut bift = state.bifts[0].clone();
+        bift.bift_id = 2;
+        bift.si = 1;
+        bift.entries.iter_mut().for_each(|entry| entry.bit += 64);
+        state.bifts.push(bift);
+        let transport = MemoryTransport::default();
+        let mut daemon = BierDaemon::builder()
+            .state(state)
+            .transport(transport.clone())
+            .build()
+            .unwrap();
+
+        // The BFR-ids 2 to 5 and 66 to 69.
+        let bitstring = [0, 0, 0, 0, 0, 0, 0, 0b11110, 0, 0, 0, 0, 0, 0, 0, 0b11110];
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &bitstring,
+            payload: &[1, 2, 3],
+            metadata: None,
+        };
+        daemon.send(&send_info).unwrap();
+        let sent = transport.sent();
+        let bift_ids: Vec<u32> = sent
+            .iter()
+            .map(|(_, packet)| BierHeader::from_slice(packet).unwrap().get_bift_id())
+            .collect();
+        assert_eq!(bift_ids, vec![1, 1, 2, 2]);
+        assert!(sent.iter().all(|(_, packet)| packet.len() == 23));
+
+        let stats = daemon.stats();
+        assert_eq!(stats.from_apps.packets, 1);
+        assert_eq!(
+            stats.bfers.keys().copied().collect::<Vec<_>>(),
+            vec![2, 3, 4, 5, 66, 67, 68, 69]
+        );
+
+        // No BIFT for the SI 2.
+        let send_info = SendInfo {
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0],
+            bift_id: 2,
+            ..send_info
+        };
+        assert_eq!(daemon.send(&send_info), Err(Error::BiftId(2)));
+        assert_eq!(daemon.stats().drops[&DropReason::UnknownBift], 1);
+    }
+
+    #[test]
+    /// Tests the packet log of the forwarding decisions.
+    fn test_daemon_packet_log() {
+        let log_path = std::env::temp_dir().join(format!("bier-packet-log-{}", std::process::id()));
+        let _ = std::fs::remove_file(&log_path);
+        let mut daemon = BierDaemon::builder()
+            .state(example_state())
+            .transport(MemoryTransport::default())
+            .packet_log(PacketLog::open(log_path.to_str().unwrap()).unwrap())
+            .build()
+            .unwrap();
+
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11111],
+            payload: &[1, 2, 3],
+            metadata: None,
+        };
+        daemon.send(&send_info).unwrap();
+        assert!(daemon.send(&SendInfo { bift_id: 2, ..send_info }).is_err());
+        drop(daemon);
+
+        let log = std::fs::read_to_string(&log_path).unwrap();
+        let lines: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["source"], "application");
+        assert_eq!(lines[0]["length"], 23);
+        assert_eq!(lines[0]["header"]["bift_id"], 1);
+        assert_eq!(lines[0]["copies"].as_array().unwrap().len(), 3);
+        // No local delivery.
+        assert_eq!(lines[0]["copies"][0]["next_hop"], serde_json::Value::Null);
+        assert_eq!(lines[0]["copies"][0]["sent"], false);
+        assert_eq!(lines[0]["copies"][1]["sent"], true);
+        assert_eq!(lines[1]["drop"], "unknown_bift");
+        std::fs::remove_file(&log_path).unwrap();
+    }
+
+    #[test]
+    /// Tests the decision trace, including the packets answered by the result cache.
+    fn test_daemon_trace() {
+        let trace_path = std::env::temp_dir().join(format!("bier-trace-{}", std::process::id()));
+        let _ = std::fs::remove_file(&trace_path);
+        let mut daemon = BierDaemon::builder()
+            .state(example_state())
+            .transport(MemoryTransport::default())
+            .result_cache(4)
+            .trace(PacketLog::open(trace_path.to_str().unwrap()).unwrap())
+            .build()
+            .unwrap();
+
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11010],
+            payload: &[1, 2, 3],
+            metadata: None,
+        };
+        daemon.send(&send_info).unwrap();
+        daemon.send(&send_info).unwrap();
+        assert!(daemon
+            .send(&SendInfo {
+                bift_id: 2,
+                ..send_info
+            })
+            .is_err());
+        daemon.set_trace(None);
+        daemon.send(&send_info).unwrap();
+        drop(daemon);
+
+        let trace = std::fs::read_to_string(&trace_path).unwrap();
+        let lines: Vec<serde_json::Value> = trace
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        let bitstring = serde_json::to_value("11010".parse::<Bitstring>().unwrap()).unwrap();
+        assert_eq!(lines[0]["source"], "application");
+        assert_eq!(lines[0]["bitstring"], bitstring);
+        let decisions = lines[0]["decisions"].as_array().unwrap();
+        assert_eq!(decisions[0]["decision"], "copy");
+        assert_eq!(decisions[0]["bit"], 2);
+        assert_eq!(decisions[0]["bitstring"], bitstring);
+        assert_eq!(decisions[0]["next_hop"], "fc00:b::1");
+        assert!(decisions[0]["fbm"].is_string());
+        assert_eq!(lines[0]["error"], serde_json::Value::Null);
+        // The cached result is explained as well.
+        assert_eq!(lines[1]["decisions"], lines[0]["decisions"]);
+        assert_eq!(lines[2]["decisions"], serde_json::json!([]));
+        assert_eq!(lines[2]["error"], "unknown BIFT-id 2");
+        std::fs::remove_file(&trace_path).unwrap();
+    }
+
+    #[test]
+    /// Tests the forwarding of packets received from the network.
+    fn test_daemon_process_packet() {
+        let transport = MemoryTransport::default();
+        // No local delivery: local packets are dropped.
+        let mut daemon = BierDaemon::builder()
+            .state(example_state())
+            .transport(transport.clone())
+            .build()
+            .unwrap();
+
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b1],
+            payload: &[4, 5],
+            metadata: None,
+        };
+        daemon.send(&send_info).unwrap();
+        assert!(transport.is_empty());
+
+        // Packet received with the bits of the BFERs behind node B.
+        let mut packet = vec![0u8; 22];
+        BierHeader::from_recv_info(&SendInfo {
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11010],
+            ..send_info
+        })
+        .unwrap()
+        .to_slice(&mut packet)
+        .unwrap();
+        packet[20..].copy_from_slice(&[4, 5]);
+        daemon.process_packet(&mut packet).unwrap();
+        let sent = transport.sent();
+        assert_eq!(sent, vec![("fc00:b::1".parse().unwrap(), packet)]);
+
+        // Not a BIER packet.
+        assert_eq!(daemon.process_packet(&mut [1, 2, 3]), Err(Error::Header));
+
+        let stats = daemon.stats();
+        assert_eq!(stats.received.packets, 2);
+        assert_eq!(stats.drops[&DropReason::NoLocalDelivery], 1);
+        assert_eq!(stats.drops[&DropReason::MalformedPacket], 1);
+
+        // Missing state.
+        assert!(BierDaemon::builder().build().is_err());
+    }
+
+    #[test]
+    /// Tests the capture of the packets received and sent, including the malformed ones.
+    fn test_daemon_capture() {
+        use crate::bier::DEFAULT_IP_PROTO;
+        use crate::pcap::{bier_payload, synthetic_mac, PcapReader, LINKTYPE_ETHERNET};
+
+        let path = std::env::temp_dir().join(format!("bier-capture-{}", std::process::id()));
+        let mut daemon = BierDaemon::builder()
+            .state(example_state())
+            .transport(MemoryTransport::default())
+            .capture(BierCapture::open(path.to_str().unwrap()).unwrap())
+            .build()
+            .unwrap();
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11010],
+            payload: &[4, 5],
+            metadata: None,
+        };
+        daemon.send(&send_info).unwrap();
+        let mut packet = vec![0u8; 22];
+        BierHeader::from_recv_info(&send_info)
+            .unwrap()
+            .to_slice(&mut packet)
+            .unwrap();
+        packet[20..].copy_from_slice(&[4, 5]);
+        let received = packet.clone();
+        daemon.process_packet(&mut packet).unwrap();
+        assert!(daemon.process_packet(&mut [1, 2, 3]).is_err());
+        drop(daemon);
+
+        let reader = PcapReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let linktype = reader.linktype();
+        assert_eq!(linktype, LINKTYPE_ETHERNET);
+        let frames: Vec<_> = reader.map(|packet| packet.unwrap().data).collect();
+        assert_eq!(frames.len(), 4);
+        let loopback = synthetic_mac(example_state().get_loopback());
+        let b = synthetic_mac("fc00:b::1".parse().unwrap());
+        // Destination and source of the synthetic Ethernet headers.
+        let addresses = |frame: &[u8]| (frame[..6].to_vec(), frame[6..12].to_vec());
+        assert_eq!(addresses(&frames[0]), (b.0.to_vec(), loopback.0.to_vec()));
+        assert_eq!(addresses(&frames[1]).0, loopback.0.to_vec());
+        assert_eq!(
+            bier_payload(linktype, &frames[1], DEFAULT_IP_PROTO),
+            Some(&received[..])
+        );
+        assert_eq!(addresses(&frames[2]), (b.0.to_vec(), loopback.0.to_vec()));
+        assert_eq!(
+            bier_payload(linktype, &frames[3], DEFAULT_IP_PROTO),
+            Some(&[1, 2, 3][..])
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    /// Tests the replies to the OAM echo requests for this BFER.
+    fn test_daemon_oam_echo() {
+        let transport = MemoryTransport::default();
+        let delivered = Arc::new(Mutex::new(0));
+        let delivered_clone = delivered.clone();
+        let mut daemon = BierDaemon::builder()
+            .state(example_state())
+            .transport(transport.clone())
+            .on_local_delivery(move |_| *delivered_clone.lock().unwrap() += 1)
+            .build()
+            .unwrap();
+
+        // Echo request of the BFIR 2, behind node B, for this BFER and BFR 3.
+        let echo_request = |bfir_id, reply_mode| {
+            let request = EchoMessage::request(42, 7, reply_mode, SystemTime::now());
+            let mut payload = [0u8; oam::ECHO_LENGTH];
+            request.to_slice(&mut payload).unwrap();
+            let mut bier_header = BierHeader::from_recv_info(&SendInfo {
+                bift_id: 1,
+                proto: header::PROTO_OAM as u16,
+                bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b101],
+                payload: &[],
+                metadata: None,
+            })
+            .unwrap();
+            bier_header.set_bfr_id(bfir_id);
+            let mut packet = vec![0u8; bier_header.header_length() + payload.len()];
+            bier_header.to_slice(&mut packet).unwrap();
+            packet[bier_header.header_length()..].copy_from_slice(&payload);
+            packet
+        };
+        daemon
+            .process_packet(&mut echo_request(2, ReplyMode::Bier))
+            .unwrap();
+        let sent = transport.sent();
+        let b: IpAddr = "fc00:b::1".parse().unwrap();
+        let c: IpAddr = "fc00:c::1".parse().unwrap();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].0, b);
+        assert_eq!(sent[1].0, c);
+        let header = BierHeader::from_slice(&sent[0].1).unwrap();
+        assert_eq!(header.get_proto(), header::PROTO_OAM);
+        assert_eq!(header.get_bfr_id(), 1);
+        assert_eq!(header.get_bitstring(), &"10".parse::<Bitstring>().unwrap());
+        let reply = EchoMessage::from_slice(&sent[0].1[header.header_length()..]).unwrap();
+        assert_eq!(reply.message_type, OamMessageType::EchoReply);
+        assert_eq!(reply.return_code, oam::RETURN_CODE_BFER);
+        assert_eq!((reply.handle, reply.sequence), (42, 7));
+        assert_eq!(reply.responder, Some(1));
+        // The request is answered by the daemon, not delivered to the application.
+        assert_eq!(*delivered.lock().unwrap(), 0);
+
+        // Unknown BFIR, and no reply requested.
+        transport.clear();
+        daemon
+            .process_packet(&mut echo_request(0, ReplyMode::Bier))
+            .unwrap();
+        daemon
+            .process_packet(&mut echo_request(2, ReplyMode::DoNotReply))
+            .unwrap();
+        let sent = transport.sent();
+        assert!(sent.iter().all(|(next_hop, _)| *next_hop == c));
+        assert_eq!(daemon.stats().drops[&DropReason::OamNoReply], 1);
+
+        // The packets of the applications carry the BFR-id of this BFIR.
+        transport.clear();
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b10],
+            payload: &[1, 2, 3],
+            metadata: None,
+        };
+        daemon.send(&send_info).unwrap();
+        let sent = transport.sent();
+        assert_eq!(BierHeader::from_slice(&sent[0].1).unwrap().get_bfr_id(), 1);
+    }
+
+    #[test]
+    /// Tests the bits above the highest BFR-id of the BIFT.
+    fn test_daemon_unknown_bits() {
+        let transport = MemoryTransport::default();
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0b1, 0b11010],
+            payload: &[4, 5],
+            metadata: None,
+        };
+
+        // The whole packet is dropped by default.
+        let mut daemon = BierDaemon::builder()
+            .state(example_state())
+            .transport(transport.clone())
+            .build()
+            .unwrap();
+        assert_eq!(daemon.send(&send_info), Err(Error::NoEntry));
+        assert!(transport.is_empty());
+
+        let mut state = example_state();
+        state.unknown_bits = UnknownBitsPolicy::Clear;
+        let mut daemon = BierDaemon::builder()
+            .state(state)
+            .transport(transport.clone())
+            .build()
+            .unwrap();
+        daemon.send(&send_info).unwrap();
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(&sent[0].1[12..20], &[0, 0, 0, 0, 0, 0, 0, 0b11010]);
+        assert_eq!(daemon.stats().cleared_bits, 1);
+    }
+
+    #[test]
+    /// Tests the copies toward a neighbor with the UDP encapsulation, sent with the UDP
+    /// transport, and dropped without it.
+    fn test_daemon_udp_encapsulation() {
+        let b: IpAddr = "fc00:b::1".parse().unwrap();
+        let c: IpAddr = "fc00:c::1".parse().unwrap();
+        let mut state = example_state();
+        state.udp_port = Some(40000);
+        let neighbor = NeighborConfig {
+            encapsulation: Encapsulation::Udp,
+            ..Default::default()
+        };
+        state.neighbors.insert(b, neighbor);
+        assert_eq!(state.encapsulation(b), Encapsulation::Udp);
+        assert_eq!(state.encapsulation(c), Encapsulation::Ip);
+        let json = serde_json::to_value(&state).unwrap();
+        assert_eq!(json["neighbors"]["fc00:b::1"], serde_json::json!({"encapsulation": "udp"}));
+
+        let (transport, udp_transport) = (MemoryTransport::default(), MemoryTransport::default());
+        let mut daemon = BierDaemon::builder()
+            .state(state.clone())
+            .transport(transport.clone())
+            .udp_transport(udp_transport.clone())
+            .build()
+            .unwrap();
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11110],
+            payload: &[1, 2, 3],
+            metadata: None,
+        };
+        daemon.send(&send_info).unwrap();
+        let next_hops = |transport: &MemoryTransport| -> Vec<IpAddr> {
+            transport.sent().iter().map(|(nh, _)| *nh).collect()
+        };
+        assert_eq!(next_hops(&udp_transport), vec![b]);
+        assert_eq!(next_hops(&transport), vec![c]);
+
+        let transport = MemoryTransport::default();
+        let mut daemon = BierDaemon::builder()
+            .state(state)
+            .transport(transport.clone())
+            .build()
+            .unwrap();
+        daemon.send(&send_info).unwrap();
+        assert_eq!(next_hops(&transport), vec![c]);
+        assert_eq!(daemon.stats().drops[&DropReason::SendError], 1);
+    }
+
+    #[test]
+    /// Tests the copies toward a neighbor with the Ethernet encapsulation, sent with the
+    /// transport of this encapsulation.
+    fn test_daemon_ethernet_encapsulation() {
+        let b: IpAddr = "fc00:b::1".parse().unwrap();
+        let mut state: BierState = example_state();
+        state.neighbors = serde_json::from_str(
+            r#"{"fc00:b::1": {"encapsulation": "ethernet", "mac": "02:00:00:00:00:0b"}}"#,
+        )
+        .unwrap();
+        assert_eq!(state.encapsulation(b), Encapsulation::Ethernet);
+        assert_eq!(state.neighbors[&b].mac.unwrap().0, [2, 0, 0, 0, 0, 0xb]);
+
+        let (transport, ethernet) = (MemoryTransport::default(), MemoryTransport::default());
+        let mut daemon = BierDaemon::builder()
+            .state(state)
+            .transport(transport.clone())
+            .encapsulation_transport(Encapsulation::Ethernet, ethernet.clone())
+            .build()
+            .unwrap();
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11110],
+            payload: &[1, 2, 3],
+            metadata: None,
+        };
+        daemon.send(&send_info).unwrap();
+        assert_eq!(ethernet.sent()[0].0, b);
+        assert_eq!(transport.len(), 1);
+    }
+
+    #[test]
+    /// Tests the labels of the packets received and sent in an MPLS network.
+    fn test_daemon_mpls_encapsulation() {
+        let b: IpAddr = "fc00:b::1".parse().unwrap();
+        let mut state: BierState = example_state();
+        state.mpls_labels.insert(1, 1001);
+        state.neighbors = serde_json::from_str(
+            r#"{"fc00:b::1": {"encapsulation": "mpls", "mpls_labels": {"1": 2001},
+                "label_stack": [3000]}}"#,
+        )
+        .unwrap();
+        assert_eq!((state.mpls_bift_id(1001), state.mpls_bift_id(7)), (1, 7));
+        let mpls = MemoryTransport::default();
+        let mut daemon = BierDaemon::builder()
+            .state(state)
+            .transport(MemoryTransport::default())
+            .encapsulation_transport(Encapsulation::Mpls, mpls.clone())
+            .build()
+            .unwrap();
+
+        // Packet toward B received with an outer label, and the label of the BIFT-id 1.
+        let send_info = SendInfo {
+            bift_id: 1001,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11010],
+            payload: &[4, 5],
+            metadata: None,
+        };
+        let mut packet = vec![0u8; 26];
+        packet[..4].copy_from_slice(&[0, 0x1f, 0x40, 64]);
+        let mut bier_header = BierHeader::from_recv_info(&send_info).unwrap();
+        bier_header.set_s(true);
+        bier_header.to_slice(&mut packet[4..]).unwrap();
+        packet[24..].copy_from_slice(&[4, 5]);
+        daemon.process_mpls_packet(&mut packet).unwrap();
+        let sent = mpls.sent()[0].clone();
+        assert_eq!(sent.0, b);
+        let entries: Vec<_> = sent.1[..8]
+            .chunks_exact(MPLS_LABEL_ENTRY_LENGTH)
+            .map(|entry| LabelStackEntry::from_bytes(entry.try_into().unwrap()))
+            .map(|entry| (entry.label, entry.bottom))
+            .collect();
+        assert_eq!(entries, [(3000, false), (2001, true)]);
+        assert_eq!(sent.1[8..], packet[8..]);
+
+        // Label stack without bottom of stack.
+        assert_eq!(
+            daemon.process_mpls_packet(&mut packet[..3]),
+            Err(Error::MplsLabelStack)
+        );
+        assert_eq!(daemon.stats().drops[&DropReason::MalformedPacket], 1);
+    }
+
+    #[test]
+    /// Tests the BIERin6 packets received and sent with the BIER option.
+    fn test_daemon_bierin6_encapsulation() {
+        let b: IpAddr = "fc00:b::1".parse().unwrap();
+        let mut state: BierState = example_state();
+        state.bierin6_option = Some(0x3e);
+        state.neighbors =
+            serde_json::from_str(r#"{"fc00:b::1": {"encapsulation": "bierin6"}}"#).unwrap();
+        let bierin6 = MemoryTransport::default();
+        let mut daemon = BierDaemon::builder()
+            .state(state)
+            .transport(MemoryTransport::default())
+            .encapsulation_transport(Encapsulation::Bierin6, bierin6.clone())
+            .build()
+            .unwrap();
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11010],
+            payload: &[4, 5],
+            metadata: None,
+        };
+        daemon.send(&send_info).unwrap();
+        let (next_hop, mut copy) = bierin6.sent()[0].clone();
+        assert_eq!(next_hop, b);
+        assert_eq!(&copy[2..4], &[0x3e, 20]);
+        assert_eq!(&copy[24..], &[4, 5]);
+
+        // The received packet is forwarded again toward B.
+        daemon.process_bierin6_packet(&mut copy.clone()).unwrap();
+        assert_eq!(bierin6.sent()[1], (b, copy.clone()));
+        copy[2] = 0x1e;
+        assert_eq!(daemon.process_bierin6_packet(&mut copy), Err(Error::Header));
+        assert_eq!(daemon.stats().drops[&DropReason::MalformedPacket], 1);
+    }
+
+    #[test]
+    /// Tests the re-encapsulation of the copies toward a neighbor with a smaller BSL.
+    fn test_daemon_neighbor_bsl() {
+        // BSL of 128 bits: BFR-id 1 is local, the others are reached through B.
+        let local = format!("{}1", "0".repeat(127));
+        let others = format!("{}0", "1".repeat(127));
+        let entries = (1..=128)
+            .map(|bit| {
+                let (bitstring, next_hop) = match bit {
+                    1 => (&local, "fc00:a::1"),
+                    _ => (&others, "fc00:b::1"),
+                };
+                let path =
+                    BierEntryPath::new(bitstring.parse().unwrap(), next_hop.parse().unwrap());
+                BiftEntry::new(bit, vec![path])
+            })
+            .collect();
+        let mut state = BierState::new("fc00::a".parse().unwrap());
+        state.add_bift(Bift::new(1, 1, entries)).unwrap();
+        state.neighbors =
+            serde_json::from_str(r#"{"fc00:b::1": {"bsl": 64, "bift_ids": [11, 12]}}"#).unwrap();
+        let b: IpAddr = "fc00:b::1".parse().unwrap();
+
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b10, 0, 0, 0, 0, 0, 0, 0, 0b10],
+            payload: &[4, 5],
+            metadata: None,
+        };
+        let transport = MemoryTransport::default();
+        let mut daemon = BierDaemon::builder()
+            .state(state.clone())
+            .transport(transport.clone())
+            .build()
+            .unwrap();
+        daemon.send(&send_info).unwrap();
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 2);
+        for (si, (next_hop, packet)) in sent.iter().enumerate() {
+            assert_eq!(*next_hop, b);
+            let header = BierHeader::from_slice(packet).unwrap();
+            assert_eq!(header.get_bift_id(), 11 + si as u32);
+            assert_eq!(header.get_bitstring().bitstring, vec![0b10]);
+            assert_eq!(&packet[header.header_length()..], &[4, 5]);
+        }
+
+        // The second SI has no BIFT-id at the neighbor.
+        state.neighbors.get_mut(&b).unwrap().bift_ids.pop();
+        let transport = MemoryTransport::default();
+        let mut daemon = BierDaemon::builder()
+            .state(state)
+            .transport(transport.clone())
+            .build()
+            .unwrap();
+        daemon.send(&send_info).unwrap();
+        assert_eq!(transport.len(), 1);
+        assert_eq!(daemon.stats().drops[&DropReason::UnsupportedBsl], 1);
+    }
+
+    #[test]
+    /// Tests the copies sent in UDP datagrams.
+    fn test_udp_transport() {
+        let localhost = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+        let receiver = udp_socket(localhost, 0).unwrap();
+        let port = receiver.local_addr().unwrap().as_socket().unwrap().port();
+        let mut transport = UdpTransport::new(udp_socket(localhost, 0).unwrap(), port);
+        assert_eq!(transport.send_to(&[1, 2, 3], localhost).unwrap(), 3);
+
+        let mut buffer = [std::mem::MaybeUninit::new(0u8); 16];
+        assert_eq!(receiver.recv(&mut buffer).unwrap(), 3);
+        assert_eq!(unsafe { buffer[2].assume_init() }, 3);
+    }
+
+    #[test]
+    /// Tests the connected sockets toward the first next hops, and the shared transport.
+    fn test_connected_transport() {
+        let localhost = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+        let other = IpAddr::V4(std::net::Ipv4Addr::new(10, 255, 255, 1));
+        let receiver = udp_socket(localhost, 0).unwrap();
+        let port = receiver.local_addr().unwrap().as_socket().unwrap().port();
+        let shared = MemoryTransport::default();
+        let mut transport = ConnectedTransport::new(shared.clone(), [localhost, other], 1, |nh| {
+            connected_udp_socket(nh, port, &SocketConfig::default())
+        });
+        assert_eq!(transport.nb_connected(), 1);
+
+        assert_eq!(transport.send_to(&[1, 2, 3], localhost).unwrap(), 3);
+        let mut buffer = [std::mem::MaybeUninit::new(0u8); 16];
+        assert_eq!(receiver.recv(&mut buffer).unwrap(), 3);
+        assert!(shared.is_empty());
+
+        assert_eq!(transport.send_to(&[4, 5], other).unwrap(), 2);
+        assert_eq!(shared.sent(), vec![(other, vec![4, 5])]);
+    }
+
+    #[test]
+    /// Tests the forwarding of the copies received with a transport, e.g., sent by another
+    /// daemon.
+    fn test_daemon_recv_from() {
+        let (mut network, forwarded) = (MemoryTransport::default(), MemoryTransport::default());
+        let mut sender = BierDaemon::builder()
+            .state(example_state())
+            .transport(network.clone())
+            .build()
+            .unwrap();
+        let mut daemon = BierDaemon::builder()
+            .state(example_state())
+            .transport(forwarded.clone())
+            .build()
+            .unwrap();
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11110],
+            payload: &[1, 2, 3],
+            metadata: None,
+        };
+        sender.send(&send_info).unwrap();
+        assert_eq!(network.len(), 2);
+
+        let mut buf = [0u8; 64];
+        daemon.recv_from(&mut network, &mut buf).unwrap();
+        daemon.recv_from(&mut network, &mut buf).unwrap();
+        assert_eq!(
+            daemon.recv_from(&mut network, &mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        assert_eq!(daemon.stats().received.packets, 2);
+        assert_eq!(forwarded.len(), 2);
+
+        // The packets filling the buffer are possibly truncated.
+        sender.send(&send_info).unwrap();
+        daemon.recv_from(&mut network, &mut buf[..23]).unwrap();
+        assert_eq!(daemon.stats().drops[&DropReason::Truncated], 1);
+        assert_eq!(forwarded.len(), 2);
+    }
+
+    #[test]
+    /// Tests the copies received from the in-memory and UDP transports.
+    fn test_transport_recv() {
+        let mut transport = MemoryTransport::default();
+        let localhost = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+        let mut buf = [0u8; 2];
+        assert_eq!(
+            transport.recv(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        transport.clone().send_to(&[1, 2, 3], localhost).unwrap();
+        transport.send_to(&[4], localhost).unwrap();
+        assert_eq!(transport.len(), 2);
+        assert_eq!(transport.recv(&mut buf).unwrap(), 2);
+        assert_eq!(buf, [1, 2]);
+        assert_eq!(transport.pop(), Some((localhost, vec![4])));
+        assert!(transport.is_empty());
+
+        let socket = udp_socket(localhost, 0).unwrap();
+        let port = socket.local_addr().unwrap().as_socket().unwrap().port();
+        let mut transport = UdpTransport::new(socket, port);
+        assert_eq!(transport.send_to(&[1, 2, 3], localhost).unwrap(), 3);
+        let mut buf = [0u8; 16];
+        assert_eq!(transport.recv(&mut buf).unwrap(), 3);
+        assert_eq!(buf[..3], [1, 2, 3]);
+
+        // The transports only sending do not receive.
+        struct SendOnly;
+        impl Transport for SendOnly {
+            fn send_to(&mut self, packet: &[u8], _: IpAddr) -> io::Result<usize> {
+                Ok(packet.len())
+            }
+        }
+        let mut send_only = ConnectedTransport::new(SendOnly, [], 0, |_| unreachable!());
+        assert_eq!(
+            send_only.recv(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+    }
+
+    #[test]
+    /// Tests the socket bound to the source address of a neighbor.
+    fn test_bound_transport() {
+        let localhost = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+        let receiver = udp_socket(localhost, 0).unwrap();
+        let port = receiver.local_addr().unwrap().as_socket().unwrap().port();
+        let neighbor = NeighborConfig {
+            source: Some(IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 2))),
+            ..Default::default()
+        };
+        assert!(neighbor.is_bound());
+        let config = neighbor.socket_config(&SocketConfig::default());
+        assert!(config.validate_for(localhost).is_ok());
+        assert!(config.validate_for("::1".parse().unwrap()).is_err());
+
+        let shared = MemoryTransport::default();
+        let mut transport = ConnectedTransport::new(shared.clone(), [], 0, |_| unreachable!());
+        let socket = egress_udp_socket(localhost, &config).unwrap();
+        transport.insert(localhost, UdpTransport::new(socket, port));
+        assert_eq!(transport.send_to(&[1, 2, 3], localhost).unwrap(), 3);
+        let mut buffer = [std::mem::MaybeUninit::new(0u8); 16];
+        let (length, from) = receiver.recv_from(&mut buffer).unwrap();
+        assert_eq!(length, 3);
+        assert_eq!(from.as_socket().unwrap().ip(), neighbor.source.unwrap());
+        assert!(shared.is_empty());
+    }
+
+    #[test]
+    /// Tests the local-only and forward-only delivery policies of the protos.
+    fn test_daemon_delivery_policy() {
+        let transport = MemoryTransport::default();
+        let delivered = Arc::new(Mutex::new(0));
+        let delivered_clone = delivered.clone();
+        let mut state = example_state();
+        state.delivery =
+            serde_json::from_str(r#"{"6": "local_only", "17": "forward_only"}"#).unwrap();
+        assert_eq!(state.delivery_policy(6), DeliveryPolicy::LocalOnly);
+        assert_eq!(state.delivery_policy(4), DeliveryPolicy::Both);
+        let mut daemon = BierDaemon::builder()
+            .state(state)
+            .transport(transport.clone())
+            .on_local_delivery(move |_| *delivered_clone.lock().unwrap() += 1)
+            .build()
+            .unwrap();
+
+        let mut send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11011],
+            payload: &[4, 5],
+            metadata: None,
+        };
+        daemon.send(&send_info).unwrap();
+        assert!(transport.is_empty());
+        assert_eq!(*delivered.lock().unwrap(), 1);
+
+        send_info.proto = 17;
+        daemon.send(&send_info).unwrap();
+        assert_eq!(transport.len(), 1);
+        assert_eq!(*delivered.lock().unwrap(), 1);
+
+        send_info.proto = 4;
+        daemon.send(&send_info).unwrap();
+        assert_eq!(transport.len(), 2);
+        assert_eq!(*delivered.lock().unwrap(), 2);
+        assert_eq!(daemon.stats().drops[&DropReason::DeliveryPolicy], 2);
+    }
+
+    #[test]
+    /// Tests the bottom-of-stack bit of the packets with an MPLS payload.
+    fn test_daemon_mpls() {
+        let transport = MemoryTransport::default();
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let delivered_clone = delivered.clone();
+        let mut daemon = BierDaemon::builder()
+            .state(example_state())
+            .transport(transport.clone())
+            .on_local_delivery(move |recv_info| {
+                let s = recv_info.metadata.and_then(|metadata| metadata.s);
+                delivered_clone.lock().unwrap().push(s)
+            })
+            .build()
+            .unwrap();
+
+        let mut send_info = SendInfo {
+            bift_id: 1,
+            proto: header::PROTO_MPLS_DOWNSTREAM as u16,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11011],
+            payload: &[0, 1, 0x41, 64, 0x45],
+            metadata: None,
+        };
+        daemon.send(&send_info).unwrap();
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].1[2] & 1, 1);
+        assert_eq!(*delivered.lock().unwrap(), vec![Some(true)]);
+
+        // No bottom of stack in the payload.
+        send_info.payload = &[0, 1, 0x40, 64, 0x45];
+        assert_eq!(daemon.send(&send_info), Err(Error::MplsLabelStack));
+        assert_eq!(transport.len(), 1);
+
+        // Received packet for this BFER, with an invalid label stack.
+        let mut packet = sent[0].1.clone();
+        packet[19] = 0b1;
+        packet[22] = 0x40;
+        daemon.process_packet(&mut packet).unwrap();
+        assert_eq!(delivered.lock().unwrap().len(), 1);
+        let stats = daemon.stats();
+        assert_eq!(stats.drops[&DropReason::MalformedApiMessage], 1);
+        assert_eq!(stats.drops[&DropReason::MalformedPacket], 1);
+
+        // The S bit chosen by the application, for any proto.
+        for (proto, s) in [(header::PROTO_MPLS_DOWNSTREAM as u16, false), (6, true)] {
+            transport.clear();
+            let metadata = Metadata {
+                s: Some(s),
+                ..Default::default()
+            };
+            daemon
+                .send(&SendInfo {
+                    proto,
+                    payload: &[0, 1, 0x41, 64, 0x45],
+                    metadata: Some(metadata),
+                    ..send_info
+                })
+                .unwrap();
+            assert_eq!(transport.sent()[0].1[2] & 1 == 1, s);
+        }
+        assert_eq!(delivered.lock().unwrap()[1..], [Some(false), Some(true)]);
+    }
+
+    #[test]
+    /// Tests the strict enforcement of the nibble and version of RFC 8296.
+    fn test_daemon_strict_rfc8296() {
+        let transport = MemoryTransport::default();
+        let mut state = example_state();
+        state.strict_rfc8296 = true;
+        let mut daemon = BierDaemon::builder()
+            .state(state)
+            .transport(transport.clone())
+            .build()
+            .unwrap();
+
+        // The packets of the applications follow RFC 8296.
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11010],
+            payload: &[4, 5],
+            metadata: None,
+        };
+        daemon.send(&send_info).unwrap();
+        let mut packet = transport.sent()[0].1.clone();
+        assert_eq!(packet[4] >> 4, RFC8296_NIBBLE);
+        daemon.process_packet(&mut packet.clone()).unwrap();
+        assert_eq!(transport.len(), 2);
+
+        // Wrong version, then wrong nibble.
+        packet[4] = (RFC8296_NIBBLE << 4) | 1;
+        assert_eq!(
+            daemon.process_packet(&mut packet.clone()),
+            Err(Error::HeaderVersion)
+        );
+        packet[4] = 0;
+        assert_eq!(
+            daemon.process_packet(&mut packet.clone()),
+            Err(Error::HeaderVersion)
+        );
+        assert_eq!(transport.len(), 2);
+        assert_eq!(daemon.stats().drops[&DropReason::InvalidVersion], 2);
+
+        // Forwarded by a permissive daemon.
+        let mut daemon = BierDaemon::builder()
+            .state(example_state())
+            .transport(transport.clone())
+            .build()
+            .unwrap();
+        daemon.process_packet(&mut packet).unwrap();
+        assert_eq!(transport.len(), 3);
+    }
+}
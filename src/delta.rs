@@ -0,0 +1,331 @@
+//! Incremental updates of the BIFTs, e.g., streamed by a BIER controller over the update
+//! socket (see `ControlRequest::Apply`) instead of whole BIFTs. A delta is a list of changes
+//! of BIFTs, entries and paths, applied in order by `BierState::apply`: all of them, or none
+//! if one fails.
+
+use crate::bier::{BierEntryPath, BierState, Bift, BiftEntry};
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// Change of the BIFTs of a `BierStateDelta`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum Change {
+    /// Adds `bift`, whose BIFT-id must not be used yet.
+    AddBift {
+        bift: Bift,
+    },
+    RemoveBift {
+        bift_id: usize,
+    },
+    /// Adds `entry` to the BIFT `bift_id`, or replaces its entry with the same BFR-id.
+    UpsertEntry {
+        bift_id: usize,
+        entry: BiftEntry,
+    },
+    RemoveEntry {
+        bift_id: usize,
+        bit: u64,
+    },
+    /// Adds `path` after the paths of the entry of the BFR-id `bit`, or replaces its path
+    /// toward the same next hop.
+    AddPath {
+        bift_id: usize,
+        bit: u64,
+        path: BierEntryPath,
+    },
+    /// Removes the paths toward `next_hop` from the entry of the BFR-id `bit`.
+    RemovePath {
+        bift_id: usize,
+        bit: u64,
+        next_hop: IpAddr,
+    },
+}
+
+/// Changes from a BIER state to another one, applied in order.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct BierStateDelta {
+    pub changes: Vec<Change>,
+}
+
+impl BierStateDelta {
+    /// Changes of the BIFTs of `from` giving the ones of `to`, up to the order of the BIFTs
+    /// and of the entries. A BIFT whose type, sub-domain, SI or BFR-id differs is replaced,
+    /// and an entry whose paths or administrative state differ is replaced.
+    pub fn diff(from: &BierState, to: &BierState) -> Self {
+        let mut changes = Vec::new();
+        let to_bifts: HashMap<_, _> = to.bifts.iter().map(|bift| (bift.bift_id, bift)).collect();
+        let mut kept = HashMap::new();
+        for bift in from.bifts.iter() {
+            match to_bifts.get(&bift.bift_id) {
+                Some(to_bift) if same_header(bift, to_bift) => {
+                    kept.insert(bift.bift_id, bift);
+                }
+                _ => changes.push(Change::RemoveBift {
+                    bift_id: bift.bift_id,
+                }),
+            }
+        }
+        for to_bift in to.bifts.iter() {
+            let Some(bift) = kept.get(&to_bift.bift_id) else {
+                changes.push(Change::AddBift {
+                    bift: to_bift.clone(),
+                });
+                continue;
+            };
+            let to_entries: HashMap<_, _> = to_bift
+                .entries
+                .iter()
+                .map(|entry| (entry.bit, entry))
+                .collect();
+            for entry in bift.entries.iter() {
+                if !to_entries.contains_key(&entry.bit) {
+                    changes.push(Change::RemoveEntry {
+                        bift_id: bift.bift_id,
+                        bit: entry.bit,
+                    });
+                }
+            }
+            let entries: HashMap<_, _> = bift
+                .entries
+                .iter()
+                .map(|entry| (entry.bit, entry))
+                .collect();
+            for to_entry in to_bift.entries.iter() {
+                if entries.get(&to_entry.bit) != Some(&to_entry) {
+                    changes.push(Change::UpsertEntry {
+                        bift_id: bift.bift_id,
+                        entry: to_entry.clone(),
+                    });
+                }
+            }
+        }
+        BierStateDelta { changes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// Whether both BIFTs have the same type, sub-domain, SI, BFR-id and BSL. The entries of a
+/// BIFT with another BSL cannot be upserted one by one.
+fn same_header(bift: &Bift, other: &Bift) -> bool {
+    bift.bift_type == other.bift_type
+        && bift.sub_domain == other.sub_domain
+        && bift.si == other.si
+        && bift.bfr_id == other.bfr_id
+        && bift.get_bsl() == other.get_bsl()
+}
+
+impl BierState {
+    /// Applies the changes of `delta` in order, checked as by `add_bift`, `upsert_entry`
+    /// and `set_paths`. The BIFTs are unchanged if a change fails, e.g., a removed entry
+    /// that does not exist. As after any modification, the state must be compiled again.
+    pub fn apply(&mut self, delta: &BierStateDelta) -> Result<()> {
+        let previous = self.bifts.clone();
+        for change in delta.changes.iter() {
+            if let Err(e) = self.apply_change(change) {
+                self.bifts = previous;
+                self.compiled = None;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_change(&mut self, change: &Change) -> Result<()> {
+        match change {
+            Change::AddBift { bift } => self.add_bift(bift.clone()),
+            Change::RemoveBift { bift_id } => self
+                .remove_bift(*bift_id)
+                .map(|_| ())
+                .ok_or(Error::BiftId(*bift_id as u32)),
+            Change::UpsertEntry { bift_id, entry } => self.upsert_entry(*bift_id, entry.clone()),
+            Change::RemoveEntry { bift_id, bit } => match self.remove_entry(*bift_id, *bit)? {
+                0 => Err(Error::NoEntry),
+                _ => Ok(()),
+            },
+            Change::AddPath { bift_id, bit, path } => {
+                let mut paths = self.paths(*bift_id, *bit)?;
+                match paths
+                    .iter_mut()
+                    .find(|other| other.next_hop == path.next_hop)
+                {
+                    Some(other) => *other = path.clone(),
+                    None => paths.push(path.clone()),
+                }
+                self.set_paths(*bift_id, *bit, paths)
+            }
+            Change::RemovePath {
+                bift_id,
+                bit,
+                next_hop,
+            } => {
+                let mut paths = self.paths(*bift_id, *bit)?;
+                let nb_paths = paths.len();
+                paths.retain(|path| path.next_hop != *next_hop);
+                if paths.len() == nb_paths {
+                    return Err(Error::NoEntry);
+                }
+                self.set_paths(*bift_id, *bit, paths)
+            }
+        }
+    }
+
+    /// Paths of the entry of the BFR-id `bit` in the BIFT `bift_id`.
+    fn paths(&self, bift_id: usize, bit: u64) -> Result<Vec<BierEntryPath>> {
+        let bift = self
+            .bifts
+            .iter()
+            .find(|bift| bift.bift_id == bift_id)
+            .ok_or(Error::BiftId(bift_id as u32))?;
+        bift.entries
+            .iter()
+            .find(|entry| entry.bit == bit)
+            .map(|entry| entry.paths.clone())
+            .ok_or(Error::NoEntry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::bier::{test_state, Bitstring};
+    use std::str::FromStr;
+
+    fn path(bitstring: &str, next_hop: &str) -> BierEntryPath {
+        BierEntryPath::new(
+            Bitstring::from_str(bitstring).unwrap(),
+            next_hop.parse().unwrap(),
+        )
+    }
+
+    #[test]
+    /// Tests the changes of the BIFTs, applied all or none.
+    fn test_apply() {
+        let mut bier_state = test_state(
+            "fc00::1",
+            1,
+            &[(1, &[("1", "fc00::1")]), (2, &[("10", "fc00::2")])],
+        );
+        let delta: BierStateDelta = serde_json::from_value(serde_json::json!({"changes": [
+            {"change": "add_path", "bift_id": 1, "bit": 2,
+             "path": {"bitstring": "110", "next_hop": "fc00::3"}},
+            {"change": "upsert_entry", "bift_id": 1,
+             "entry": {"bit": 3, "paths": [{"bitstring": "100", "next_hop": "fc00::3"}]}},
+            {"change": "remove_path", "bift_id": 1, "bit": 2, "next_hop": "fc00::2"},
+        ]}))
+        .unwrap();
+        bier_state.apply(&delta).unwrap();
+        let expected = test_state(
+            "fc00::1",
+            1,
+            &[
+                (1, &[("1", "fc00::1")]),
+                (2, &[("110", "fc00::3")]),
+                (3, &[("100", "fc00::3")]),
+            ],
+        );
+        assert_eq!(bier_state, expected);
+
+        // The failure of the last change cancels the first ones.
+        let delta = BierStateDelta {
+            changes: vec![
+                Change::RemoveEntry { bift_id: 1, bit: 3 },
+                Change::AddPath {
+                    bift_id: 1,
+                    bit: 2,
+                    path: path("1010", "fc00::3"),
+                },
+                Change::RemoveEntry { bift_id: 1, bit: 4 },
+            ],
+        };
+        assert_eq!(bier_state.apply(&delta), Err(Error::NoEntry));
+        assert_eq!(bier_state, expected);
+        for change in [
+            Change::RemoveBift { bift_id: 2 },
+            Change::RemovePath {
+                bift_id: 1,
+                bit: 2,
+                next_hop: "fc00::2".parse().unwrap(),
+            },
+            Change::AddPath {
+                bift_id: 1,
+                bit: 2,
+                path: path(&"1".repeat(65), "fc00::3"),
+            },
+            Change::AddBift {
+                bift: expected.bifts[0].clone(),
+            },
+        ] {
+            let delta = BierStateDelta {
+                changes: vec![change],
+            };
+            assert!(bier_state.apply(&delta).is_err());
+            assert_eq!(bier_state, expected);
+        }
+
+        // The updated state is compiled before forwarding.
+        bier_state.compile().unwrap();
+        let copies = bier_state
+            .process_bier(&Bitstring::from_str("110").unwrap(), 1, 0)
+            .unwrap();
+        assert_eq!(copies.len(), 1);
+    }
+
+    #[test]
+    /// Tests that the difference between two states is applied to the first one to give the
+    /// second one.
+    fn test_diff() {
+        let from = test_state(
+            "fc00::1",
+            1,
+            &[
+                (1, &[("1", "fc00::1")]),
+                (2, &[("10", "fc00::2")]),
+                (3, &[("100", "fc00::3")]),
+            ],
+        );
+        let mut to = test_state(
+            "fc00::1",
+            1,
+            &[
+                (1, &[("1", "fc00::1")]),
+                (3, &[("1100", "fc00::3")]),
+                (4, &[("1100", "fc00::3")]),
+            ],
+        );
+        let mut other_bift = to.bifts[0].clone();
+        other_bift.bift_id = 2;
+        to.bifts.push(other_bift);
+
+        let delta = BierStateDelta::diff(&from, &to);
+        assert_eq!(delta.changes.len(), 4);
+        let mut bier_state = from.clone();
+        bier_state.apply(&delta).unwrap();
+        assert_eq!(bier_state, to);
+        assert!(BierStateDelta::diff(&to, &bier_state).is_empty());
+
+        // A BIFT with another BFR-id is replaced.
+        to.bifts[0].bfr_id = 2;
+        let delta = BierStateDelta::diff(&bier_state, &to);
+        assert!(matches!(
+            delta.changes[..],
+            [Change::RemoveBift { bift_id: 1 }, Change::AddBift { .. }]
+        ));
+        bier_state.apply(&delta).unwrap();
+        assert!(BierStateDelta::diff(&to, &bier_state).is_empty());
+
+        // As well as a BIFT with another BSL, from 64 to 128 bits.
+        let to = test_state("fc00::1", 1, &[(1, &[(&"1".repeat(65), "fc00::1")])]);
+        let delta = BierStateDelta::diff(&from, &to);
+        let mut bier_state = from.clone();
+        bier_state.apply(&delta).unwrap();
+        assert_eq!(bier_state, to);
+        bier_state.compile().unwrap();
+    }
+}
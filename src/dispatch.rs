@@ -0,0 +1,318 @@
+//! Delivery of the packets destined to this BFER to the upper layers, chosen by the proto of
+//! their payload, e.g., the IPv6 payloads to a TUN device and the payloads of each
+//! application proto to the UNIX socket of its application. The echo requests (proto OAM)
+//! are answered by the daemon before reaching the dispatcher, see `daemon`.
+//!
+//! The destinations of the operator are static: the applications cannot register their
+//! protos. The other protos are registered by the first application asking for them, until
+//! it removes its registration or closes its socket. The applications registered with a
+//! version 1 message receive the packets without their metadata, i.e., in version 1.
+
+use crate::api::{RecvInfo, API_MIN_VERSION};
+use log::{debug, info};
+use socket2::{SockAddr, Socket};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+
+/// Upper layer receiving the packets of a proto.
+#[derive(Debug)]
+pub enum Destination {
+    /// UNIX socket of an application, receiving the data messages of the API, i.e., the
+    /// payload after the API header.
+    Unix(SockAddr),
+    /// Device receiving the payloads without header, e.g., a TUN device for the IP payloads.
+    Device(File),
+}
+
+impl Destination {
+    /// UNIX socket of an application bound at `path`.
+    pub fn unix(path: impl AsRef<Path>) -> io::Result<Self> {
+        SockAddr::unix(path).map(Destination::Unix)
+    }
+}
+
+/// Delivers the packets to the destination of their proto, or to the default destination.
+pub struct Dispatcher {
+    /// Socket sending the data messages to the applications, i.e., the UNIX socket of the
+    /// daemon.
+    sock: Socket,
+    /// Static destinations of the operator.
+    destinations: HashMap<u16, Destination>,
+    /// Path, socket and API version of the application registered for each proto.
+    registrations: HashMap<u16, (PathBuf, SockAddr, u8)>,
+    default: Option<Destination>,
+}
+
+impl Dispatcher {
+    pub fn new(sock: Socket) -> Self {
+        Self {
+            sock,
+            destinations: HashMap::new(),
+            registrations: HashMap::new(),
+            default: None,
+        }
+    }
+
+    /// Destination of the protos without their own destination.
+    pub fn set_default(&mut self, destination: Option<Destination>) {
+        self.default = destination;
+    }
+
+    /// Delivers the packets with `proto` to `destination`, instead of the application that
+    /// registered it, if any. Returns the previous static destination of `proto`, if any.
+    pub fn insert(&mut self, proto: u16, destination: Destination) -> Option<Destination> {
+        self.registrations.remove(&proto);
+        self.destinations.insert(proto, destination)
+    }
+
+    pub fn remove(&mut self, proto: u16) -> Option<Destination> {
+        self.destinations.remove(&proto)
+    }
+
+    /// Delivers the packets with `proto` to the application bound at `path`, which asked
+    /// for them on the UNIX socket of the daemon with a message of `version`. The protos
+    /// with a static destination are refused, as well as the protos of another application,
+    /// unless its socket is closed.
+    pub fn register(&mut self, proto: u16, path: &Path, version: u8) -> io::Result<()> {
+        if self.destinations.contains_key(&proto) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("The proto {} has a static destination", proto),
+            ));
+        }
+        if let Some((registered, _, _)) = self.registrations.get(&proto) {
+            if registered != path && UnixDatagram::unbound()?.connect(registered).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("The proto {} is registered by {:?}", proto, registered),
+                ));
+            }
+        }
+        let addr = SockAddr::unix(path)?;
+        self.registrations
+            .insert(proto, (path.to_path_buf(), addr, version));
+        Ok(())
+    }
+
+    /// Removes the registration of `proto` by the application bound at `path`. Returns
+    /// whether it was registered by this application.
+    pub fn unregister(&mut self, proto: u16, path: &Path) -> bool {
+        match self.registrations.get(&proto) {
+            Some((registered, _, _)) if registered == path => {
+                self.registrations.remove(&proto);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Delivers `recv_info` to the destination of its proto. Returns whether it is sent.
+    /// The registration of an application whose socket is closed is removed.
+    pub fn deliver(&mut self, recv_info: &RecvInfo) -> bool {
+        let proto = recv_info.proto;
+        let res = match self.registrations.get(&proto) {
+            Some((path, addr, version)) => {
+                let res = match *version {
+                    API_MIN_VERSION => send_message(
+                        &self.sock,
+                        &RecvInfo {
+                            metadata: None,
+                            ..*recv_info
+                        },
+                        addr,
+                    ),
+                    _ => send_message(&self.sock, recv_info, addr),
+                };
+                if let Err(e) = &res {
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::ConnectionRefused | io::ErrorKind::NotFound
+                    ) {
+                        info!(
+                            "The application of the proto {} at {:?} is gone, removing its registration",
+                            proto, path
+                        );
+                        self.registrations.remove(&proto);
+                    }
+                }
+                res
+            }
+            None => match self.destinations.get_mut(&proto).or(self.default.as_mut()) {
+                Some(Destination::Unix(addr)) => send_message(&self.sock, recv_info, addr),
+                Some(Destination::Device(device)) => device.write(recv_info.payload),
+                None => {
+                    debug!(
+                        "No destination for the proto {}, dropping the packet",
+                        proto
+                    );
+                    return false;
+                }
+            },
+        };
+        match res {
+            Ok(_) => {
+                debug!("Delivered a packet with proto {}", proto);
+                true
+            }
+            Err(e) => {
+                debug!(
+                    "Error when delivering a packet with proto {}: {:?}, continuing...",
+                    proto, e
+                );
+                false
+            }
+        }
+    }
+}
+
+/// Sends `recv_info` as a data message of the API to the UNIX socket `addr`.
+fn send_message(sock: &Socket, recv_info: &RecvInfo, addr: &SockAddr) -> io::Result<usize> {
+    let mut message = vec![0u8; recv_info.message_length()];
+    match recv_info.to_slice(&mut message) {
+        Ok(_) => sock.send_to(&message, addr),
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::api::{Metadata, API_VERSION};
+    use std::io::Read;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    /// Tests the delivery to the destination of the proto, to the default one, and to a device.
+    fn test_dispatcher() {
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+        let paths: Vec<_> = ["ipv6", "app", "default"]
+            .iter()
+            .map(|name| dir.join(format!("bier-dispatch-{}-{}", name, id)))
+            .collect();
+        for path in &paths {
+            let _ = std::fs::remove_file(path);
+        }
+        let receivers: Vec<_> = paths
+            .iter()
+            .map(|path| UnixDatagram::bind(path).unwrap())
+            .collect();
+        for receiver in &receivers {
+            receiver.set_nonblocking(true).unwrap();
+        }
+        let sock = Socket::new(socket2::Domain::UNIX, socket2::Type::DGRAM, None).unwrap();
+        let mut dispatcher = Dispatcher::new(sock);
+        dispatcher.insert(6, Destination::unix(&paths[0]).unwrap());
+        dispatcher.insert(0x37, Destination::unix(&paths[1]).unwrap());
+
+        let mut recv_info = RecvInfo {
+            bift_id: 1,
+            proto: 0x37,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 1],
+            payload: &[1, 2, 3],
+            metadata: None,
+        };
+        let mut buffer = [0u8; 64];
+        assert!(dispatcher.deliver(&recv_info));
+        let read = receivers[1].recv(&mut buffer).unwrap();
+        let delivered = RecvInfo::from_slice(&buffer[..read]).unwrap();
+        assert_eq!((delivered.proto, delivered.payload), (0x37, &[1, 2, 3][..]));
+        assert!(receivers[0].recv(&mut buffer).is_err());
+
+        // Without default destination, the other protos are dropped.
+        recv_info.proto = 17;
+        assert!(!dispatcher.deliver(&recv_info));
+        dispatcher.set_default(Some(Destination::unix(&paths[2]).unwrap()));
+        assert!(dispatcher.deliver(&recv_info));
+        assert!(receivers[2].recv(&mut buffer).is_ok());
+
+        // The device receives the payload only.
+        let device_path = dir.join(format!("bier-dispatch-device-{}", id));
+        let device = File::create(&device_path).unwrap();
+        assert!(dispatcher.insert(6, Destination::Device(device)).is_some());
+        recv_info.proto = 6;
+        assert!(dispatcher.deliver(&recv_info));
+        let mut written = Vec::new();
+        File::open(&device_path)
+            .unwrap()
+            .read_to_end(&mut written)
+            .unwrap();
+        assert_eq!(written, [1, 2, 3]);
+        assert!(receivers[0].recv(&mut buffer).is_err());
+
+        for path in paths.iter().chain([&device_path]) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    /// Tests that the registrations cannot take the static destinations, nor the proto of
+    /// another application until its socket is closed.
+    fn test_dispatcher_registrations() {
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+        let paths: Vec<_> = ["static", "first", "second"]
+            .iter()
+            .map(|name| dir.join(format!("bier-dispatch-registration-{}-{}", name, id)))
+            .collect();
+        for path in &paths {
+            let _ = std::fs::remove_file(path);
+        }
+        let receivers: Vec<_> = paths
+            .iter()
+            .map(|path| UnixDatagram::bind(path).unwrap())
+            .collect();
+        let sock = Socket::new(socket2::Domain::UNIX, socket2::Type::DGRAM, None).unwrap();
+        let mut dispatcher = Dispatcher::new(sock);
+        dispatcher.insert(6, Destination::unix(&paths[0]).unwrap());
+        assert!(dispatcher.register(6, &paths[1], API_VERSION).is_err());
+        assert!(!dispatcher.unregister(6, &paths[1]));
+
+        // The first application keeps its proto. It registered in version 1, so it receives
+        // the packets without metadata.
+        dispatcher
+            .register(0x37, &paths[1], API_MIN_VERSION)
+            .unwrap();
+        assert!(dispatcher.register(0x37, &paths[2], API_VERSION).is_err());
+        assert!(!dispatcher.unregister(0x37, &paths[2]));
+        let recv_info = RecvInfo {
+            bift_id: 1,
+            proto: 0x37,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 1],
+            payload: &[1, 2, 3],
+            metadata: Some(Metadata {
+                bfir_id: Some(2),
+                ..Default::default()
+            }),
+        };
+        let mut buffer = [0u8; 64];
+        assert!(dispatcher.deliver(&recv_info));
+        let read = receivers[1].recv(&mut buffer).unwrap();
+        assert_eq!(buffer[2], API_MIN_VERSION);
+        assert_eq!(
+            RecvInfo::from_slice(&buffer[..read]).unwrap().metadata,
+            None
+        );
+
+        // Until its socket is closed.
+        let [_, first, second]: [UnixDatagram; 3] = receivers.try_into().unwrap();
+        drop(first);
+        std::fs::remove_file(&paths[1]).unwrap();
+        dispatcher.register(0x37, &paths[2], API_VERSION).unwrap();
+        assert!(dispatcher.deliver(&recv_info));
+        let read = second.recv(&mut buffer).unwrap();
+        let received = RecvInfo::from_slice(&buffer[..read]).unwrap();
+        assert_eq!(received.metadata, recv_info.metadata);
+        drop(second);
+        assert!(!dispatcher.deliver(&recv_info));
+        assert!(!dispatcher.unregister(0x37, &paths[2]));
+
+        for path in &paths {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! Ethernet framing of the BIER packets (RFC 8296): the frames have the ethertype 0xAB37
+//! and carry the BIER header right after the Ethernet header, as in the hardware
+//! deployments. In MPLS networks, the frames have the ethertype 0x8847 and carry a label
+//! stack whose bottom entry is the first word of the BIER header. The frames are sent and
+//! received with AF_XDP (see `xdp`) or with an AF_PACKET socket (see `af_packet`).
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Ethertype of the BIER packets carried in Ethernet frames.
+pub const ETHERTYPE_BIER: u16 = 0xAB37;
+
+/// Ethertype of the MPLS unicast packets.
+pub const ETHERTYPE_MPLS: u16 = 0x8847;
+
+/// Destination and source MAC addresses, and ethertype.
+pub const ETHERNET_HEADER_LEN: usize = 14;
+
+/// MAC address, written as `02:00:00:00:00:01`.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub struct MacAddr(pub [u8; 6]);
+
+impl FromStr for MacAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mac = [0u8; 6];
+        let mut bytes = s.split(':');
+        for byte in mac.iter_mut() {
+            *byte = bytes
+                .next()
+                .filter(|byte| byte.len() == 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| format!("Invalid MAC address {}", s))?;
+        }
+        match bytes.next() {
+            Some(_) => Err(format!("Invalid MAC address {}", s)),
+            None => Ok(MacAddr(mac)),
+        }
+    }
+}
+
+impl TryFrom<String> for MacAddr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<MacAddr> for String {
+    fn from(mac: MacAddr) -> Self {
+        mac.to_string()
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+/// Writes the Ethernet header of a packet with `ethertype` toward `dst` at the start of
+/// `frame`.
+pub fn write_ethernet_header(frame: &mut [u8], dst: MacAddr, src: MacAddr, ethertype: u16) {
+    frame[..6].copy_from_slice(&dst.0);
+    frame[6..12].copy_from_slice(&src.0);
+    frame[12..ETHERNET_HEADER_LEN].copy_from_slice(&ethertype.to_be_bytes());
+}
+
+/// Payload of an Ethernet frame, `None` if the frame does not have `ethertype`. VLAN tags
+/// are not supported.
+pub fn ethernet_payload(frame: &mut [u8], ethertype: u16) -> Option<&mut [u8]> {
+    if frame.len() < ETHERNET_HEADER_LEN || frame[12..14] != ethertype.to_be_bytes() {
+        return None;
+    }
+    Some(&mut frame[ETHERNET_HEADER_LEN..])
+}
+
+/// BIER packet carried by an Ethernet frame, `None` if the frame does not have the BIER
+/// ethertype.
+pub fn bier_packet(frame: &mut [u8]) -> Option<&mut [u8]> {
+    ethernet_payload(frame, ETHERTYPE_BIER)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::collections::HashMap;
+    use std::net::IpAddr;
+
+    #[test]
+    /// Tests the MAC addresses and the Ethernet framing of the BIER packets.
+    fn test_ethernet() {
+        let mac: MacAddr = "02:00:00:00:00:0a".parse().unwrap();
+        assert_eq!(mac.to_string(), "02:00:00:00:00:0a");
+        assert!("02:00:00:00:00".parse::<MacAddr>().is_err());
+        assert!("02:00:00:00:00:0a:0b".parse::<MacAddr>().is_err());
+        let neighbors: HashMap<IpAddr, MacAddr> =
+            serde_json::from_str(r#"{"fc00:b::1": "02:00:00:00:00:0b"}"#).unwrap();
+        assert_eq!(
+            neighbors[&"fc00:b::1".parse::<IpAddr>().unwrap()].0,
+            [2, 0, 0, 0, 0, 0xb]
+        );
+
+        let mut frame = vec![0u8; ETHERNET_HEADER_LEN + 2];
+        let dst = neighbors.values().next().copied().unwrap();
+        write_ethernet_header(&mut frame, dst, mac, ETHERTYPE_BIER);
+        frame[ETHERNET_HEADER_LEN..].copy_from_slice(&[0x50, 0x30]);
+        assert_eq!(&frame[..6], &[2, 0, 0, 0, 0, 0xb]);
+        assert_eq!(bier_packet(&mut frame).unwrap(), &[0x50, 0x30]);
+        frame[12] = 0x88;
+        assert!(bier_packet(&mut frame).is_none());
+        write_ethernet_header(&mut frame, dst, mac, ETHERTYPE_MPLS);
+        assert_eq!(
+            ethernet_payload(&mut frame, ETHERTYPE_MPLS).unwrap(),
+            &[0x50, 0x30]
+        );
+        assert!(bier_packet(&mut frame[..10]).is_none());
+    }
+}
@@ -0,0 +1,231 @@
+//! Import of the BIER routing information exported by FRRouting-based BFRs.
+//!
+//! The supported format is the following subset of the BIRT (RFC 8279) of each sub-domain,
+//! using the camelCase names of the FRR JSON outputs:
+//!
+//! ```json
+//! {
+//!     "bfrPrefix": "fc00::a",
+//!     "subdomains": [
+//!         {
+//!             "subdomainId": 0,
+//!             "bfrId": 1,
+//!             "bsl": 64,
+//!             "birt": [
+//!                 {"bfrId": 2, "bfrPrefix": "fc00::b", "bfrNbr": "fc00:b::1"}
+//!             ]
+//!         }
+//!     ]
+//! }
+//! ```
+//!
+//! The forwarding bitmask of each neighbor is the set of BFR-ids reached through it,
+//! as the BIFT is derived from the BIRT in RFC 8279. Each sub-domain becomes a BIFT,
+//! with the BIFT-ids given in the order of the sub-domains.
+
+use crate::bier::{BierEntryPath, BierState, Bift, BiftEntry, BiftType, Bitstring};
+use crate::{Error, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FrrExport {
+    pub bfr_prefix: IpAddr,
+    pub subdomains: Vec<FrrSubdomain>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FrrSubdomain {
+    pub subdomain_id: u8,
+    /// BFR-id of this BFR in the sub-domain.
+    pub bfr_id: u64,
+    /// BitString Length, in bits.
+    pub bsl: usize,
+    pub birt: Vec<FrrBirtEntry>,
+}
+
+/// Entry of the BIER Routing Table.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FrrBirtEntry {
+    pub bfr_id: u64,
+    pub bfr_prefix: IpAddr,
+    /// Next hop toward the BFR.
+    pub bfr_nbr: IpAddr,
+}
+
+impl TryFrom<FrrExport> for BierState {
+    type Error = Error;
+
+    fn try_from(export: FrrExport) -> Result<Self> {
+        let bifts = export
+            .subdomains
+            .iter()
+            .enumerate()
+            .map(|(idx, subdomain)| subdomain.to_bift(idx + 1, export.bfr_prefix))
+            .collect::<Result<_>>()?;
+
+        Ok(BierState {
+            bifts,
+            ..BierState::new(export.bfr_prefix)
+        })
+    }
+}
+
+impl FrrSubdomain {
+    fn to_bift(&self, bift_id: usize, loopback: IpAddr) -> Result<Bift> {
+        if !self.bsl.is_power_of_two() || !(64..=4096).contains(&self.bsl) {
+            return Err(Error::BitstringLength);
+        }
+        let in_range = |bfr_id: u64| bfr_id >= 1 && bfr_id as usize <= self.bsl;
+        if !in_range(self.bfr_id) || self.birt.iter().any(|entry| !in_range(entry.bfr_id)) {
+            return Err(Error::BiftParsing);
+        }
+
+        // Forwarding bitmask of each neighbor.
+        let mut fbms: BTreeMap<IpAddr, Vec<u64>> = BTreeMap::new();
+        for entry in self.birt.iter().filter(|entry| entry.bfr_id != self.bfr_id) {
+            let fbm = fbms
+                .entry(entry.bfr_nbr)
+                .or_insert_with(|| vec![0; self.bsl / 64]);
+            set_bit(fbm, entry.bfr_id);
+        }
+
+        // The BIFT is indexed by bit, including the BFR-ids absent from the BIRT.
+        let max_bfr_id = self
+            .birt
+            .iter()
+            .map(|entry| entry.bfr_id)
+            .chain(std::iter::once(self.bfr_id))
+            .max()
+            .unwrap_or(0);
+        let entries = (1..=max_bfr_id)
+            .map(|bit| {
+                let path = if bit == self.bfr_id {
+                    // Local delivery: only the bit of this BFR.
+                    let mut bitstring = vec![0; self.bsl / 64];
+                    set_bit(&mut bitstring, bit);
+                    Some((bitstring, loopback))
+                } else {
+                    self.birt
+                        .iter()
+                        .find(|entry| entry.bfr_id == bit)
+                        .map(|entry| (fbms[&entry.bfr_nbr].clone(), entry.bfr_nbr))
+                };
+                let paths = match path {
+                    Some((bitstring, next_hop)) => vec![BierEntryPath {
+                        bitstring: Bitstring::try_from(bitstring)?,
+                        next_hop,
+                        admin_state: Default::default(),
+                    }],
+                    None => Vec::new(),
+                };
+                Ok(BiftEntry {
+                    bit,
+                    paths,
+                    admin_state: Default::default(),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Bift {
+            bift_id,
+            bift_type: BiftType::Bier,
+            sub_domain: self.subdomain_id,
+            si: 0,
+            bfr_id: self.bfr_id,
+            entries,
+        })
+    }
+}
+
+/// Sets the bit of `bfr_id` in a bitstring whose first word holds the most significant bits.
+fn set_bit(bitstring: &mut [u64], bfr_id: u64) {
+    let bit = (bfr_id - 1) as usize;
+    let nb_words = bitstring.len();
+    bitstring[nb_words - 1 - bit / 64] |= 1 << (bit % 64);
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::str::FromStr;
+
+    fn get_frr_export_json() -> &'static str {
+        r#"{
+            "bfrPrefix": "fc00::a",
+            "subdomains": [
+                {
+                    "subdomainId": 3,
+                    "bfrId": 1,
+                    "bsl": 64,
+                    "birt": [
+                        {"bfrId": 1, "bfrPrefix": "fc00::a", "bfrNbr": "fc00::a"},
+                        {"bfrId": 2, "bfrPrefix": "fc00::b", "bfrNbr": "fc00:b::1"},
+                        {"bfrId": 4, "bfrPrefix": "fc00::d", "bfrNbr": "fc00:b::1"},
+                        {"bfrId": 5, "bfrPrefix": "fc00::e", "bfrNbr": "fc00:c::1"}
+                    ]
+                }
+            ]
+        }"#
+    }
+
+    #[test]
+    /// Tests the conversion of an FRR export to BIFTs.
+    fn test_frr_import() {
+        let export: FrrExport = serde_json::from_str(get_frr_export_json()).unwrap();
+        let mut bier_state = BierState::try_from(export).unwrap();
+        bier_state.compile().unwrap();
+
+        assert_eq!(bier_state.loopback, "fc00::a".parse::<IpAddr>().unwrap());
+        assert_eq!(bier_state.bifts.len(), 1);
+        let bift = &bier_state.bifts[0];
+        assert_eq!(bift.bift_id, 1);
+        assert_eq!(bift.sub_domain, 3);
+        assert_eq!(bift.entries.len(), 5);
+        // BFR-id 3 is not in the BIRT.
+        assert!(bift.entries[2].paths.is_empty());
+        assert_eq!(
+            bift.entries[3].paths,
+            vec![BierEntryPath {
+                bitstring: Bitstring::from_str("1010").unwrap(),
+                next_hop: "fc00:b::1".parse().unwrap(),
+                admin_state: Default::default(),
+            }]
+        );
+
+        let outputs = bier_state
+            .process_bier(&Bitstring::from_str("11011").unwrap(), 1, 0)
+            .unwrap();
+        assert_eq!(
+            outputs,
+            vec![
+                (Bitstring::from_str("1").unwrap(), None),
+                (
+                    Bitstring::from_str("1010").unwrap(),
+                    Some("fc00:b::1".parse().unwrap())
+                ),
+                (
+                    Bitstring::from_str("10000").unwrap(),
+                    Some("fc00:c::1".parse().unwrap())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    /// Tests the rejection of BFR-ids out of the BSL.
+    fn test_frr_import_invalid() {
+        let json = get_frr_export_json().replace(r#""bfrId": 5"#, r#""bfrId": 65"#);
+        let export: FrrExport = serde_json::from_str(&json).unwrap();
+        assert_eq!(BierState::try_from(export), Err(Error::BiftParsing));
+
+        let json = get_frr_export_json().replace(r#""bsl": 64"#, r#""bsl": 100"#);
+        let export: FrrExport = serde_json::from_str(&json).unwrap();
+        assert_eq!(BierState::try_from(export), Err(Error::BitstringLength));
+    }
+}
@@ -1,7 +1,14 @@
-use crate::{Error, Result, bier::Bitstring};
+//! BIER header of RFC 8296. The module only depends on `core` and `alloc`, and is available
+//! without the `std` feature, like `bitstring`.
+
+use crate::{bitstring::Bitstring, Error, Result};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use serde::Serialize;
 
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct BierHeader {
     bift_id: u32,
     tc: u8,
@@ -21,17 +28,100 @@ pub struct BierHeader {
 
 pub const BIER_MINIMUM_HEADER_LENGTH: usize = 20;
 pub const BIER_HEADER_WITHOUT_BITSTRING_LENGTH: usize = 12;
+/// First nibble after the label stack, distinguishing BIER from IP in MPLS networks (RFC 8296).
+pub const RFC8296_NIBBLE: u8 = 0b0101;
+/// Only version of the BIER header defined by RFC 8296.
+pub const RFC8296_VERSION: u8 = 0;
+/// Proto of a payload starting with an MPLS label stack, with a downstream-assigned label.
+pub const PROTO_MPLS_DOWNSTREAM: u8 = 1;
+/// Proto of a payload starting with an MPLS label stack, with an upstream-assigned label.
+pub const PROTO_MPLS_UPSTREAM: u8 = 2;
+/// Proto of an Ethernet frame.
+pub const PROTO_ETHERNET: u8 = 3;
+/// Proto of an IPv4 payload.
+pub const PROTO_IPV4: u8 = 4;
+/// Proto of a BIER OAM message, see the `oam` module.
+pub const PROTO_OAM: u8 = 5;
+/// Proto of an IPv6 payload.
+pub const PROTO_IPV6: u8 = 6;
+/// Length of an MPLS label stack entry.
+pub const MPLS_LABEL_ENTRY_LENGTH: usize = 4;
+
+/// Protocol of the payload of a BIER packet, from the proto field of its header, with the
+/// values assigned by the IANA "BIER Next Protocol Identifiers" registry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BierProto {
+    MplsDownstream = PROTO_MPLS_DOWNSTREAM,
+    MplsUpstream = PROTO_MPLS_UPSTREAM,
+    Ethernet = PROTO_ETHERNET,
+    Ipv4 = PROTO_IPV4,
+    Oam = PROTO_OAM,
+    Ipv6 = PROTO_IPV6,
+}
+
+impl TryFrom<u8> for BierProto {
+    type Error = crate::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            PROTO_MPLS_DOWNSTREAM => Ok(BierProto::MplsDownstream),
+            PROTO_MPLS_UPSTREAM => Ok(BierProto::MplsUpstream),
+            PROTO_ETHERNET => Ok(BierProto::Ethernet),
+            PROTO_IPV4 => Ok(BierProto::Ipv4),
+            PROTO_OAM => Ok(BierProto::Oam),
+            PROTO_IPV6 => Ok(BierProto::Ipv6),
+            _ => Err(Error::Proto),
+        }
+    }
+}
+
+impl From<BierProto> for u8 {
+    fn from(proto: BierProto) -> Self {
+        proto as u8
+    }
+}
+
+impl fmt::Display for BierProto {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            BierProto::MplsDownstream => "MPLS (downstream-assigned label)",
+            BierProto::MplsUpstream => "MPLS (upstream-assigned label)",
+            BierProto::Ethernet => "Ethernet",
+            BierProto::Ipv4 => "IPv4",
+            BierProto::Oam => "OAM",
+            BierProto::Ipv6 => "IPv6",
+        };
+        f.write_str(name)
+    }
+}
+
+impl BierProto {
+    /// Whether the payload starts with an MPLS label stack.
+    pub fn is_mpls(self) -> bool {
+        matches!(self, BierProto::MplsDownstream | BierProto::MplsUpstream)
+    }
+}
 
 impl BierHeader {
+    /// Builder of a header with all its fields, e.g., for the applications building their
+    /// own packets.
+    pub fn builder() -> BierHeaderBuilder {
+        BierHeaderBuilder::default()
+    }
+
     pub fn from_slice(slice: &[u8]) -> Result<BierHeader> {
         if slice.len() < BIER_MINIMUM_HEADER_LENGTH {
             return Err(Error::Header);
         }
 
-        let bsl = unsafe { (*slice.get_unchecked(5) & 0xf0) >> 4 };
+        // RFC 8296 defines the BSL values from 1 (64 bits) to 7 (4096 bits).
+        let bsl = get_bsl(slice);
+        if !(1..=7).contains(&bsl) {
+            return Err(Error::BitstringLength);
+        }
 
-        let bitstring_length = 1 << (bsl + 5);
-        let bitstring_length = bitstring_length / 8;
+        let bitstring_length = (1 << (bsl + 5)) / 8;
         if slice.len() < BIER_HEADER_WITHOUT_BITSTRING_LENGTH + bitstring_length {
             return Err(Error::Header);
         }
@@ -59,9 +149,7 @@ impl BierHeader {
     }
 
     pub fn to_slice(&self, slice: &mut [u8]) -> Result<()> {
-        if slice.len() < self.header_length() {
-            return Err(Error::SliceWrongLength);
-        }
+        crate::check_length(slice, self.header_length())?;
 
         let val: u32 = (self.bift_id << 12)
             + ((self.tc as u32) << 9)
@@ -85,11 +173,9 @@ impl BierHeader {
         let bytes: [u8; 4] = val.to_be_bytes();
         slice[8..12].copy_from_slice(&bytes);
 
-        unsafe {
-            let bitstring: Vec<u64> = self.bitstring.bitstring.iter().map(|item| item.to_be()).collect();
-            let p = bitstring.as_ptr() as *const u8;
-            let bitstring = std::slice::from_raw_parts(p, self.bitstring.bitstring.len() * 8);
-            slice[12..self.header_length()].copy_from_slice(bitstring);
+        let bitstring = &mut slice[BIER_HEADER_WITHOUT_BITSTRING_LENGTH..self.header_length()];
+        for (chunk, word) in bitstring.chunks_exact_mut(8).zip(&self.bitstring.bitstring) {
+            chunk.copy_from_slice(&word.to_be_bytes());
         }
 
         Ok(())
@@ -103,25 +189,215 @@ impl BierHeader {
         self.bift_id
     }
 
+    /// Traffic class.
+    pub fn get_tc(&self) -> u8 {
+        self.tc
+    }
+
+    pub fn get_ttl(&self) -> u8 {
+        self.ttl
+    }
+
+    pub fn get_nibble(&self) -> u8 {
+        self.nibble
+    }
+
+    pub fn get_version(&self) -> u8 {
+        self.ver
+    }
+
+    /// Value of the BSL field, from 1 (64 bits) to 7 (4096 bits).
+    pub fn get_bsl(&self) -> u8 {
+        self.bsl
+    }
+
+    pub fn get_oam(&self) -> u8 {
+        self.oam
+    }
+
+    /// Reserved field.
+    pub fn get_rsv(&self) -> u8 {
+        self.rsv
+    }
+
+    pub fn get_dscp(&self) -> u8 {
+        self.dscp
+    }
+
+    pub fn get_proto(&self) -> u8 {
+        self.proto
+    }
+
+    /// Protocol of the payload, or `Error::Proto` if the proto field has an unknown value.
+    pub fn proto(&self) -> Result<BierProto> {
+        BierProto::try_from(self.proto)
+    }
+
+    pub fn get_entropy(&self) -> u32 {
+        self.entropy
+    }
+
+    /// BFR-id of the BFIR of the packet.
+    pub fn get_bfr_id(&self) -> u16 {
+        self.bfr_id
+    }
+
+    /// Same header with another BIFT-id and bitstring, e.g., to re-encapsulate a copy
+    /// with the BSL of a neighbor.
+    pub fn reencapsulate(&self, bift_id: u32, bitstring: Bitstring) -> Self {
+        BierHeader {
+            bift_id,
+            bsl: bsl_code(bitstring.bitstring.len() * 64),
+            bitstring,
+            ..self.clone()
+        }
+    }
+
+    /// Header of a reply to this packet, e.g., an OAM reply or an upstream join, sent by
+    /// the BFR `bfr_id` with `bitstring`, usually the single bit of the BFIR (see
+    /// `BierState::bitstring_for_bfr_id`). The entropy and the class of service are kept,
+    /// so that the reply follows the same ECMP paths.
+    pub fn reply(&self, bitstring: Bitstring, proto: u8, bfr_id: u16) -> Self {
+        BierHeader {
+            bift_id: self.bift_id,
+            tc: self.tc,
+            nibble: self.nibble,
+            ver: self.ver,
+            bsl: bsl_code(bitstring.bitstring.len() * 64),
+            entropy: self.entropy,
+            dscp: self.dscp,
+            proto,
+            bfr_id,
+            bitstring,
+            ..Default::default()
+        }
+    }
+
+    /// Bottom-of-stack bit, as the first word of the header is a label stack entry in MPLS networks.
+    pub fn get_s(&self) -> bool {
+        self.s
+    }
+
+    pub fn set_s(&mut self, s: bool) {
+        self.s = s;
+    }
+
+    /// Sets the BFR-id of the BFIR, i.e., of this BFR for the packets of its applications.
+    pub fn set_bfr_id(&mut self, bfr_id: u16) {
+        self.bfr_id = bfr_id;
+    }
+
+    /// Sets the entropy, truncated to its 20 bits.
+    pub fn set_entropy(&mut self, entropy: u32) {
+        self.entropy = entropy & 0xfffff;
+    }
+
+    pub fn set_nibble(&mut self, nibble: u8) {
+        self.nibble = nibble & 0xf;
+    }
+
+    /// Sets the BIFT-id, truncated to its 20 bits.
+    pub fn set_bift_id(&mut self, bift_id: u32) {
+        self.bift_id = bift_id & 0xfffff;
+    }
+
+    /// Sets the traffic class, truncated to its 3 bits.
+    pub fn set_tc(&mut self, tc: u8) {
+        self.tc = tc & 0x7;
+    }
+
+    pub fn set_ttl(&mut self, ttl: u8) {
+        self.ttl = ttl;
+    }
+
+    pub fn set_version(&mut self, version: u8) {
+        self.ver = version & 0xf;
+    }
+
+    pub fn set_oam(&mut self, oam: u8) {
+        self.oam = oam & 0x3;
+    }
+
+    pub fn set_rsv(&mut self, rsv: u8) {
+        self.rsv = rsv & 0x3;
+    }
+
+    pub fn set_dscp(&mut self, dscp: u8) {
+        self.dscp = dscp & 0x3f;
+    }
+
+    /// Sets the proto of the payload, truncated to its 6 bits.
+    pub fn set_proto(&mut self, proto: u8) {
+        self.proto = proto & 0x3f;
+    }
+
+    /// Sets the bitstring and the matching BSL. The bitstring must have a length defined by
+    /// RFC 8296, from 64 to 4096 bits.
+    pub fn set_bitstring(&mut self, bitstring: Bitstring) -> Result<()> {
+        if !matches!(bitstring.bitstring.len(), 1 | 2 | 4 | 8 | 16 | 32 | 64) {
+            return Err(Error::BitstringLength);
+        }
+        self.bsl = bsl_code(bitstring.bitstring.len() * 64);
+        self.bitstring = bitstring;
+        Ok(())
+    }
+
+    /// Checks the nibble and the version of the header against RFC 8296.
+    pub fn check_rfc8296(&self) -> Result<()> {
+        if self.nibble != RFC8296_NIBBLE || self.ver != RFC8296_VERSION {
+            return Err(Error::HeaderVersion);
+        }
+        Ok(())
+    }
+
     pub fn header_length(&self) -> usize {
         BIER_HEADER_WITHOUT_BITSTRING_LENGTH + self.bitstring.bitstring.len() * 8
     }
 
+    /// Header of the packet sent by an application. Fails with `Error::BiftId` or
+    /// `Error::Proto` if the BIFT-id or the proto do not fit in their 20 and 6 bits.
+    #[cfg(feature = "std")]
     pub fn from_recv_info(recv_info: &crate::api::RecvInfo) -> Result<Self> {
-        let bitstring: crate::bier::Bitstring = recv_info.bitstring.try_into()?;
-        let bsl = match bitstring.bitstring.len() * 64 {
-            8 => 1,
-            16 => 2,
-            other => ((other as f64).log2() - 5f64) as usize,
-        };
+        if recv_info.bift_id > 0xfffff {
+            return Err(Error::BiftId(recv_info.bift_id));
+        }
+        if recv_info.proto > 0x3f {
+            return Err(Error::Proto);
+        }
+        let bitstring: Bitstring = recv_info.bitstring.try_into()?;
+        let bsl = bsl_code(bitstring.bitstring.len() * 64);
 
-        Ok(BierHeader {
+        let mut header = BierHeader {
             bift_id: recv_info.bift_id,
             bitstring,
             proto: recv_info.proto as u8,
-            bsl: bsl as u8,
+            bsl,
             ..Default::default()
-        })
+        };
+        // The optional fields chosen by the application.
+        let metadata = recv_info.metadata.unwrap_or_default();
+        if let Some(ttl) = metadata.ttl {
+            header.set_ttl(ttl);
+        }
+        if let Some(dscp) = metadata.dscp {
+            header.set_dscp(dscp);
+        }
+        if let Some(entropy) = metadata.entropy {
+            header.set_entropy(entropy);
+        }
+        if let Some(s) = metadata.s {
+            header.set_s(s);
+        }
+        Ok(header)
+    }
+}
+
+/// Value of the BSL field for a bitstring of `length` bits.
+fn bsl_code(length: usize) -> u8 {
+    match length {
+        8 => 1,
+        16 => 2,
+        other => other.checked_ilog2().unwrap_or(0).saturating_sub(5) as u8,
     }
 }
 
@@ -146,62 +422,309 @@ impl Default for BierHeader {
     }
 }
 
+/// Builder of a `BierHeader`, see `BierHeader::builder`. The nibble is the one of RFC 8296 by
+/// default, and the other fields are zero. The values are truncated to the size of their field.
+pub struct BierHeaderBuilder {
+    header: BierHeader,
+    bitstring: Option<Bitstring>,
+}
+
+impl Default for BierHeaderBuilder {
+    fn default() -> Self {
+        BierHeaderBuilder {
+            header: BierHeader {
+                nibble: RFC8296_NIBBLE,
+                ..Default::default()
+            },
+            bitstring: None,
+        }
+    }
+}
+
+impl BierHeaderBuilder {
+    pub fn bift_id(mut self, bift_id: u32) -> Self {
+        self.header.set_bift_id(bift_id);
+        self
+    }
+
+    pub fn tc(mut self, tc: u8) -> Self {
+        self.header.set_tc(tc);
+        self
+    }
+
+    pub fn s(mut self, s: bool) -> Self {
+        self.header.set_s(s);
+        self
+    }
+
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        self.header.set_ttl(ttl);
+        self
+    }
+
+    pub fn nibble(mut self, nibble: u8) -> Self {
+        self.header.set_nibble(nibble);
+        self
+    }
+
+    pub fn version(mut self, version: u8) -> Self {
+        self.header.set_version(version);
+        self
+    }
+
+    pub fn entropy(mut self, entropy: u32) -> Self {
+        self.header.set_entropy(entropy);
+        self
+    }
+
+    pub fn oam(mut self, oam: u8) -> Self {
+        self.header.set_oam(oam);
+        self
+    }
+
+    pub fn rsv(mut self, rsv: u8) -> Self {
+        self.header.set_rsv(rsv);
+        self
+    }
+
+    pub fn dscp(mut self, dscp: u8) -> Self {
+        self.header.set_dscp(dscp);
+        self
+    }
+
+    pub fn proto(mut self, proto: u8) -> Self {
+        self.header.set_proto(proto);
+        self
+    }
+
+    /// BFR-id of the BFIR.
+    pub fn bfr_id(mut self, bfr_id: u16) -> Self {
+        self.header.set_bfr_id(bfr_id);
+        self
+    }
+
+    /// Bitstring of the packet, which also sets the BSL. Mandatory.
+    pub fn bitstring(mut self, bitstring: Bitstring) -> Self {
+        self.bitstring = Some(bitstring);
+        self
+    }
+
+    /// Fails without bitstring, or if its length is not defined by RFC 8296.
+    pub fn build(self) -> Result<BierHeader> {
+        let mut header = self.header;
+        header.set_bitstring(self.bitstring.ok_or(Error::BitstringLength)?)?;
+        Ok(header)
+    }
+}
+
+/// BIER header serialized once for a flow, of which only the bitstring
+/// and the entropy are written for each packet.
+#[derive(Debug, Clone)]
+pub struct HeaderTemplate {
+    /// Header of the flow, with the bitstring and the entropy of its last packet.
+    header: BierHeader,
+    /// Fixed part of the header, with a zero entropy.
+    fixed: [u8; BIER_HEADER_WITHOUT_BITSTRING_LENGTH],
+    /// Length of the bitstring, in bytes.
+    bitstring_length: usize,
+}
+
+impl HeaderTemplate {
+    pub fn new(header: &BierHeader) -> Result<Self> {
+        let mut slice = vec![0u8; header.header_length()];
+        header.to_slice(&mut slice)?;
+        let mut fixed = [0u8; BIER_HEADER_WITHOUT_BITSTRING_LENGTH];
+        fixed.copy_from_slice(&slice[..BIER_HEADER_WITHOUT_BITSTRING_LENGTH]);
+        fixed[5] &= 0xf0;
+        fixed[6] = 0;
+        fixed[7] = 0;
+        Ok(HeaderTemplate {
+            header: header.clone(),
+            fixed,
+            bitstring_length: header.bitstring.bitstring.len() * 8,
+        })
+    }
+
+    pub fn header(&self) -> &BierHeader {
+        &self.header
+    }
+
+    pub fn header_length(&self) -> usize {
+        BIER_HEADER_WITHOUT_BITSTRING_LENGTH + self.bitstring_length
+    }
+
+    /// Sets the `bitstring`, in network order, and the `entropy` of the header of the flow,
+    /// in place.
+    pub fn update(&mut self, bitstring: &[u8], entropy: u32) -> Result<()> {
+        if bitstring.len() != self.bitstring_length {
+            return Err(Error::BitstringLength);
+        }
+        let words = self.header.bitstring.bitstring.iter_mut();
+        for (word, bytes) in words.zip(bitstring.chunks_exact(8)) {
+            *word = u64::from_be_bytes(bytes.try_into().unwrap());
+        }
+        self.header.set_entropy(entropy);
+        Ok(())
+    }
+
+    /// Writes the header with the `bitstring`, already in network order, and the `entropy`.
+    pub fn write(&self, slice: &mut [u8], bitstring: &[u8], entropy: u32) -> Result<usize> {
+        if bitstring.len() != self.bitstring_length {
+            return Err(Error::BitstringLength);
+        }
+        crate::check_length(slice, self.header_length())?;
+        slice[..BIER_HEADER_WITHOUT_BITSTRING_LENGTH].copy_from_slice(&self.fixed);
+        let entropy = (entropy & 0xfffff).to_be_bytes();
+        slice[5] |= entropy[1];
+        slice[6..8].copy_from_slice(&entropy[2..]);
+        slice[BIER_HEADER_WITHOUT_BITSTRING_LENGTH..self.header_length()]
+            .copy_from_slice(bitstring);
+        Ok(self.header_length())
+    }
+}
+
+/// Whether the payload of a packet with `proto` starts with an MPLS label stack.
+pub fn is_mpls_proto(proto: u8) -> bool {
+    BierProto::try_from(proto).is_ok_and(BierProto::is_mpls)
+}
+
+/// Entropy of a payload with `proto`, from its flow: a hash of the addresses, transport
+/// protocol and ports of an IP packet, so that the packets of a flow follow the same ECMP
+/// paths. 0 for the other payloads, i.e., a single path for all of them.
+pub fn flow_entropy(proto: u8, payload: &[u8]) -> u32 {
+    // Offsets of the addresses and of the transport protocol, and length of the IP header.
+    let (addresses, transport, header_length) = match proto {
+        PROTO_IPV4 if payload.len() >= 20 && payload[0] >> 4 == 4 => {
+            (12..20, 9, (payload[0] & 0x0f) as usize * 4)
+        }
+        PROTO_IPV6 if payload.len() >= 40 && payload[0] >> 4 == 6 => (8..40, 6, 40),
+        _ => return 0,
+    };
+    // FNV-1a, folded on the 20 bits of the entropy.
+    let mut hash: u32 = 0x811c_9dc5;
+    let mut add = |bytes: &[u8]| {
+        for byte in bytes {
+            hash = (hash ^ *byte as u32).wrapping_mul(0x0100_0193);
+        }
+    };
+    add(&payload[addresses]);
+    add(&payload[transport..transport + 1]);
+    // Ports of TCP and UDP.
+    if matches!(payload[transport], 6 | 17) {
+        if let Some(ports) = payload.get(header_length..header_length + 4) {
+            add(ports);
+        }
+    }
+    (hash ^ (hash >> 20)) & 0xfffff
+}
+
+/// Length of the MPLS label stack at the start of `payload`, up to the entry with
+/// the bottom-of-stack bit set.
+pub fn mpls_label_stack_length(payload: &[u8]) -> Result<usize> {
+    payload
+        .chunks_exact(MPLS_LABEL_ENTRY_LENGTH)
+        .position(|entry| entry[2] & 1 == 1)
+        .map(|idx| (idx + 1) * MPLS_LABEL_ENTRY_LENGTH)
+        .ok_or(Error::MplsLabelStack)
+}
+
+/// Entry of an MPLS label stack (RFC 3032). In MPLS networks, the first word of the BIER
+/// header is the bottom entry of the stack, whose label is the BIFT-id (RFC 8296).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LabelStackEntry {
+    pub label: u32,
+    pub tc: u8,
+    /// Bottom-of-stack bit.
+    pub bottom: bool,
+    pub ttl: u8,
+}
+
+impl LabelStackEntry {
+    pub fn from_bytes(bytes: [u8; MPLS_LABEL_ENTRY_LENGTH]) -> Self {
+        let word = u32::from_be_bytes(bytes);
+        LabelStackEntry {
+            label: word >> 12,
+            tc: ((word >> 9) & 0x7) as u8,
+            bottom: (word >> 8) & 1 == 1,
+            ttl: word as u8,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; MPLS_LABEL_ENTRY_LENGTH] {
+        let word = (self.label & 0xfffff) << 12
+            | ((self.tc as u32) & 0x7) << 9
+            | (self.bottom as u32) << 8
+            | self.ttl as u32;
+        word.to_be_bytes()
+    }
+}
+
+/// Length of the label stack entries before the BIER header of a packet received in an MPLS
+/// network, i.e., before the entry with the bottom-of-stack bit set.
+pub fn bier_header_offset(packet: &[u8]) -> Result<usize> {
+    mpls_label_stack_length(packet).map(|length| length - MPLS_LABEL_ENTRY_LENGTH)
+}
+
+// The getters are called on a slice of at least `BIER_HEADER_WITHOUT_BITSTRING_LENGTH` bytes.
+
 fn get_bift_id(slice: &[u8]) -> u32 {
-    unsafe { (crate::get_unchecked_be_u32(slice.as_ptr()) & 0xfffff000) >> 12 }
+    (crate::be_u32(slice, 0) & 0xfffff000) >> 12
 }
 
 fn get_tc(slice: &[u8]) -> u8 {
-    unsafe { (slice.get_unchecked(2) & 0x0e) >> 1 }
+    (slice[2] & 0x0e) >> 1
 }
 
 fn get_s(slice: &[u8]) -> bool {
-    unsafe { slice.get_unchecked(2) & 1 == 1 }
+    slice[2] & 1 == 1
 }
 
 fn get_ttl(slice: &[u8]) -> u8 {
-    unsafe { *slice.get_unchecked(3) }
+    slice[3]
 }
 
 fn get_nibble(slice: &[u8]) -> u8 {
-    unsafe { (*slice.get_unchecked(4) & 0xf0) >> 4 }
+    (slice[4] & 0xf0) >> 4
 }
 
 fn get_version(slice: &[u8]) -> u8 {
-    unsafe { *slice.get_unchecked(4) & 0xf }
+    slice[4] & 0xf
 }
 
 fn get_bsl(slice: &[u8]) -> u8 {
-    unsafe { (*slice.get_unchecked(5) & 0xf0) >> 4 }
+    (slice[5] & 0xf0) >> 4
 }
 
 fn get_entropy(slice: &[u8]) -> u32 {
-    unsafe { crate::get_unchecked_be_u32(slice.as_ptr().add(4)) & 0xfffff }
+    crate::be_u32(slice, 4) & 0xfffff
 }
 
 fn get_oam(slice: &[u8]) -> u8 {
-    unsafe { (*slice.get_unchecked(8) & 0xc0) >> 6 }
+    (slice[8] & 0xc0) >> 6
 }
 
 fn get_rsv(slice: &[u8]) -> u8 {
-    unsafe { (*slice.get_unchecked(8) & 0x30) >> 4 }
+    (slice[8] & 0x30) >> 4
 }
 
 fn get_dscp(slice: &[u8]) -> u8 {
-    unsafe { ((crate::get_unchecked_be_u16(slice.as_ptr().add(8)) & 0xfc0) >> 6) as u8 }
+    ((crate::be_u16(slice, 8) & 0xfc0) >> 6) as u8
 }
 
 fn get_proto(slice: &[u8]) -> u8 {
-    unsafe { *slice.get_unchecked(9) & 0x3f }
+    slice[9] & 0x3f
 }
 
 fn get_bifr_id(slice: &[u8]) -> u16 {
-    unsafe { crate::get_unchecked_be_u16(slice.as_ptr().add(10)) }
+    crate::be_u16(slice, 10)
 }
 
 fn get_bitstring(slice: &[u8]) -> Result<Bitstring> {
-    let vec = slice[12..]
-        .chunks(8)
-        .map(|chunk| u64::from_be_bytes(chunk.try_into().unwrap()))
+    let vec = slice[BIER_HEADER_WITHOUT_BITSTRING_LENGTH..]
+        .chunks_exact(8)
+        .map(|chunk| u64::from_be_bytes([
+            chunk[0], chunk[1], chunk[2], chunk[3], chunk[4], chunk[5], chunk[6], chunk[7],
+        ]))
         .collect::<Vec<u64>>();
     vec.try_into()
 }
@@ -210,6 +733,10 @@ fn get_bitstring(slice: &[u8]) -> Result<Bitstring> {
 pub mod tests {
 
     use super::*;
+    #[cfg(feature = "std")]
+    use crate::bitstring::arbitrary_bitstring;
+    #[cfg(feature = "std")]
+    use proptest::prelude::*;
 
     pub fn get_dummy_bier_header_slice() -> [u8; 20] {
         [
@@ -250,6 +777,30 @@ pub mod tests {
         assert_eq!(bier_header.bitstring.bitstring[0], 0xffff);
     }
 
+    #[test]
+    fn test_bier_header_proto() {
+        let mut header = BierHeader::from_slice(&get_dummy_bier_header_slice()).unwrap();
+        assert_eq!(header.proto(), Ok(BierProto::Ipv4));
+        header.set_proto(PROTO_MPLS_UPSTREAM);
+        assert_eq!(header.proto(), Ok(BierProto::MplsUpstream));
+        header.set_proto(63);
+        assert_eq!(header.proto(), Err(Error::Proto));
+
+        for value in 0..=u8::MAX {
+            if let Ok(proto) = BierProto::try_from(value) {
+                assert_eq!(u8::from(proto), value);
+            }
+        }
+        #[cfg(feature = "std")]
+        {
+            assert_eq!(BierProto::Ethernet.to_string(), "Ethernet");
+            assert_eq!(
+                BierProto::MplsUpstream.to_string(),
+                "MPLS (upstream-assigned label)"
+            );
+        }
+    }
+
     #[test]
     fn test_bier_header_from_bytes_wrong_bitstring_length() {
         let buf = [
@@ -261,6 +812,70 @@ pub mod tests {
         assert!(bier_header_opt.is_err());
     }
 
+    #[test]
+    /// Tests the header built field by field, and its serialization.
+    fn test_bier_header_builder() {
+        let header = BierHeader::builder()
+            .bift_id(0x12345)
+            .tc(5)
+            .s(true)
+            .ttl(64)
+            .entropy(0xfffff3)
+            .dscp(46)
+            .proto(PROTO_IPV6)
+            .bfr_id(17)
+            .bitstring(Bitstring {
+                bitstring: vec![0, 0b101],
+            })
+            .build()
+            .unwrap();
+        let mut slice = vec![0u8; header.header_length()];
+        header.to_slice(&mut slice).unwrap();
+        let parsed = BierHeader::from_slice(&slice).unwrap();
+        assert_eq!(parsed.get_bift_id(), 0x12345);
+        assert_eq!((parsed.get_tc(), parsed.get_s(), parsed.get_ttl()), (5, true, 64));
+        assert_eq!((parsed.get_nibble(), parsed.get_version()), (RFC8296_NIBBLE, 0));
+        // 128 bits, and the entropy is truncated to its 20 bits.
+        assert_eq!((parsed.get_bsl(), parsed.get_entropy()), (2, 0xffff3));
+        assert_eq!((parsed.get_oam(), parsed.get_rsv(), parsed.get_dscp()), (0, 0, 46));
+        assert_eq!((parsed.get_proto(), parsed.get_bfr_id()), (PROTO_IPV6, 17));
+        assert_eq!(parsed.proto(), Ok(BierProto::Ipv6));
+        assert_eq!(parsed.get_bitstring().bitstring, [0, 0b101]);
+        assert!(parsed.check_rfc8296().is_ok());
+
+        assert_eq!(
+            BierHeader::builder().bift_id(1).build().unwrap_err(),
+            Error::BitstringLength
+        );
+        let three_words = Bitstring {
+            bitstring: vec![0; 3],
+        };
+        assert_eq!(
+            BierHeader::builder().bitstring(three_words).build().unwrap_err(),
+            Error::BitstringLength
+        );
+    }
+
+    #[test]
+    /// The reserved BSL values and the truncated headers are errors, not panics.
+    fn test_bier_header_from_bytes_malformed() {
+        let mut buf = vec![0u8; 1024];
+        buf[..20].copy_from_slice(&get_dummy_bier_header_slice());
+        for bsl in [0u8, 8, 15] {
+            buf[5] = bsl << 4;
+            assert_eq!(
+                BierHeader::from_slice(&buf).unwrap_err(),
+                Error::BitstringLength
+            );
+        }
+        // BSL of 4096 bits.
+        buf[5] = 0x70;
+        for length in 0..BIER_HEADER_WITHOUT_BITSTRING_LENGTH + 512 {
+            assert!(BierHeader::from_slice(&buf[..length]).is_err());
+        }
+        assert!(BierHeader::from_slice(&buf[..BIER_HEADER_WITHOUT_BITSTRING_LENGTH + 512]).is_ok());
+    }
+
     #[test]
     fn test_bier_header_to_slice_dummy() {
         // Get a dummy BIER header and slice it.
@@ -289,6 +904,140 @@ pub mod tests {
     }
 
     #[test]
+    /// Tests the bottom-of-stack bit of the header and of the MPLS payloads.
+    fn test_mpls_label_stack() {
+        let mut bier_header = BierHeader::from_slice(&get_dummy_bier_header_slice()).unwrap();
+        assert!(bier_header.get_s());
+        bier_header.set_s(false);
+        let mut res = [0u8; 20];
+        bier_header.to_slice(&mut res).unwrap();
+        assert_eq!(res[2], 0x42);
+
+        assert!(is_mpls_proto(PROTO_MPLS_DOWNSTREAM));
+        assert!(!is_mpls_proto(4));
+        // Two label stack entries, the second at the bottom of the stack.
+        let payload = [0, 1, 0x40, 64, 0, 2, 0x41, 64, 0x45, 0];
+        assert_eq!(mpls_label_stack_length(&payload), Ok(8));
+        assert_eq!(
+            mpls_label_stack_length(&payload[..7]),
+            Err(Error::MplsLabelStack)
+        );
+        assert_eq!(mpls_label_stack_length(&[]), Err(Error::MplsLabelStack));
+    }
+
+    #[test]
+    /// Tests the label stack entries, and the offset of the BIER header in an MPLS packet.
+    fn test_label_stack_entry() {
+        let entry = LabelStackEntry::from_bytes([0, 0x3e, 0x9b, 64]);
+        assert_eq!(
+            entry,
+            LabelStackEntry {
+                label: 1001,
+                tc: 5,
+                bottom: true,
+                ttl: 64
+            }
+        );
+        assert_eq!(entry.to_bytes(), [0, 0x3e, 0x9b, 64]);
+        // The bottom entry of the stack is the first word of the BIER header.
+        let header = get_dummy_bier_header_slice();
+        let entry = LabelStackEntry::from_bytes([header[0], header[1], header[2], header[3]]);
+        assert_eq!((entry.label, entry.bottom, entry.ttl), (4, true, 7));
+
+        let mut packet = [0, 0x3e, 0x80, 64].to_vec();
+        packet.extend(header);
+        assert_eq!(bier_header_offset(&packet), Ok(4));
+        assert_eq!(bier_header_offset(&header), Ok(0));
+        assert_eq!(bier_header_offset(&packet[..4]), Err(Error::MplsLabelStack));
+    }
+
+    #[test]
+    /// Tests the entropy of the flows of IP payloads.
+    fn test_flow_entropy() {
+        // UDP over IPv6, from port 1000 to port 2000.
+        let mut payload = [0u8; 48];
+        payload[0] = 0x60;
+        payload[6] = 17;
+        payload[23] = 1;
+        payload[39] = 2;
+        payload[40..44].copy_from_slice(&[0x03, 0xe8, 0x07, 0xd0]);
+        let entropy = flow_entropy(PROTO_IPV6, &payload);
+        assert!(entropy > 0 && entropy <= 0xfffff);
+        // The payload itself does not change the flow, contrary to the ports.
+        payload[47] = 42;
+        assert_eq!(flow_entropy(PROTO_IPV6, &payload), entropy);
+        payload[41] = 0xe9;
+        assert_ne!(flow_entropy(PROTO_IPV6, &payload), entropy);
+
+        assert_eq!(flow_entropy(PROTO_IPV4, &payload), 0);
+        assert_eq!(flow_entropy(PROTO_IPV6, &payload[..39]), 0);
+        assert_eq!(flow_entropy(PROTO_MPLS_DOWNSTREAM, &payload), 0);
+
+        let mut bier_header = BierHeader::default();
+        bier_header.set_entropy(0x123456);
+        assert_eq!(bier_header.get_entropy(), 0x23456);
+    }
+
+    #[test]
+    /// The template gives the same header as the serialization of the whole header.
+    fn test_header_template() {
+        let buf = get_dummy_bier_header_slice();
+        let template = HeaderTemplate::new(&BierHeader::from_slice(&buf).unwrap()).unwrap();
+        assert_eq!(template.header_length(), 20);
+
+        let mut res = [0u8; 20];
+        assert_eq!(template.write(&mut res, &buf[12..], 3), Ok(20));
+        assert_eq!(buf, res);
+
+        // Only the bitstring and the entropy change.
+        let bitstring = [0x80, 0, 0, 0, 0, 0, 0, 1];
+        assert_eq!(template.write(&mut res, &bitstring, 0xabcde), Ok(20));
+        let mut header = BierHeader::from_slice(&buf).unwrap();
+        header.bitstring = bitstring.as_slice().try_into().unwrap();
+        header.entropy = 0xabcde;
+        let mut expected = [0u8; 20];
+        header.to_slice(&mut expected).unwrap();
+        assert_eq!(res, expected);
+
+        // The header of the flow follows its last packet.
+        let mut template = template;
+        assert_eq!(template.update(&bitstring, 0xabcde), Ok(()));
+        assert_eq!(template.header().get_bitstring(), header.get_bitstring());
+        assert_eq!(template.header().get_entropy(), 0xabcde);
+        assert_eq!(template.update(&[0; 16], 0), Err(Error::BitstringLength));
+
+        assert_eq!(
+            template.write(&mut res, &[0; 16], 0),
+            Err(Error::BitstringLength)
+        );
+        assert_eq!(
+            template.write(&mut res[..19], &bitstring, 0),
+            Err(Error::SliceWrongLength {
+                expected: 20,
+                actual: 19
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    /// The reply keeps the entropy of the packet, with the bitstring toward the BFIR.
+    fn test_header_reply() {
+        let buf = get_dummy_bier_header_slice();
+        let header = BierHeader::from_slice(&buf).unwrap();
+        let bitstring: crate::bier::Bitstring =
+            [0u8, 0, 0, 0, 0, 0, 0, 0b100].as_slice().try_into().unwrap();
+        let reply = header.reply(bitstring.clone(), 6, 1);
+        assert_eq!(reply.get_entropy(), header.get_entropy());
+        assert_eq!(reply.get_bift_id(), header.get_bift_id());
+        assert_eq!(reply.get_bitstring(), &bitstring);
+        assert_eq!(reply.get_proto(), 6);
+        assert_eq!(reply.get_bfr_id(), 1);
+        assert_eq!(reply.bsl, header.bsl);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
     /// The RecvInfo only specifies the BIFT-ID, the Proto, the BitString and the Payload.
     fn test_bier_header_from_recv_info() {
         let recv_info = crate::api::RecvInfo {
@@ -296,6 +1045,7 @@ pub mod tests {
             proto: 0x1f,
             bitstring: &[0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0x8],
             payload: &[0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa],
+            metadata: None,
         };
 
         let bier_header = BierHeader::from_recv_info(&recv_info);
@@ -322,9 +1072,33 @@ pub mod tests {
         assert_eq!(bier_header.rsv, 0);
         assert_eq!(bier_header.bfr_id, 0);
 
+        // The BIFT-id and the proto are not truncated to their fields.
+        let invalid = crate::api::RecvInfo {
+            bift_id: 0x100000,
+            ..recv_info
+        };
+        assert_eq!(
+            BierHeader::from_recv_info(&invalid).err(),
+            Some(Error::BiftId(0x100000))
+        );
+        let invalid = crate::api::RecvInfo {
+            proto: 0x40,
+            ..recv_info
+        };
+        assert_eq!(
+            BierHeader::from_recv_info(&invalid).err(),
+            Some(Error::Proto)
+        );
+        let invalid = crate::api::RecvInfo {
+            bift_id: 0xfffff,
+            proto: 0x3f,
+            ..recv_info
+        };
+        assert!(BierHeader::from_recv_info(&invalid).is_ok());
     }
 
     #[test]
+    #[cfg(feature = "std")]
     /// Test the RecvInfo with a longer bitstring.
     fn test_bier_header_from_recv_info_long_bitstring() {
         let recv_info = crate::api::RecvInfo {
@@ -332,6 +1106,7 @@ pub mod tests {
             proto: 0x1f,
             bitstring: &vec![0xf4u8; 512],
             payload: &[0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa],
+            metadata: None,
         };
 
         let bier_header = BierHeader::from_recv_info(&recv_info);
@@ -360,4 +1135,62 @@ pub mod tests {
         assert_eq!(bier_header.rsv, 0);
         assert_eq!(bier_header.bfr_id, 0);
     }
+
+    #[cfg(feature = "std")]
+    proptest! {
+        #[test]
+        /// Tests that random headers are parsed back from their serialization, followed by
+        /// any payload.
+        fn test_header_round_trip(
+            (bift_id, tc, s, ttl, nibble, version) in
+                (0..1u32 << 20, 0..8u8, any::<bool>(), any::<u8>(), 0..16u8, 0..16u8),
+            (entropy, oam, rsv, dscp, proto, bfr_id) in
+                (0..1u32 << 20, 0..4u8, 0..4u8, 0..64u8, 0..64u8, any::<u16>()),
+            bitstring in arbitrary_bitstring(),
+            payload_length in 0..8usize,
+        ) {
+            let header = BierHeader::builder()
+                .bift_id(bift_id)
+                .tc(tc)
+                .s(s)
+                .ttl(ttl)
+                .nibble(nibble)
+                .version(version)
+                .entropy(entropy)
+                .oam(oam)
+                .rsv(rsv)
+                .dscp(dscp)
+                .proto(proto)
+                .bfr_id(bfr_id)
+                .bitstring(bitstring)
+                .build()
+                .unwrap();
+            let mut slice = vec![0u8; header.header_length() + payload_length];
+            header.to_slice(&mut slice).unwrap();
+            let parsed = BierHeader::from_slice(&slice).unwrap();
+            prop_assert_eq!(
+                serde_json::to_value(&parsed).unwrap(),
+                serde_json::to_value(&header).unwrap()
+            );
+        }
+
+        #[test]
+        /// Tests that random bytes with a valid BSL are serialized back to the same bytes.
+        fn test_header_bytes_round_trip(
+            bytes in (1..=7u8).prop_flat_map(|bsl| {
+                let length = BIER_HEADER_WITHOUT_BITSTRING_LENGTH + (1 << (bsl + 5)) / 8;
+                prop::collection::vec(any::<u8>(), length).prop_map(move |mut bytes| {
+                    bytes[5] = (bsl << 4) | (bytes[5] & 0x0f);
+                    bytes
+                })
+            })
+        ) {
+            let mut serialized = vec![0u8; bytes.len()];
+            BierHeader::from_slice(&bytes)
+                .unwrap()
+                .to_slice(&mut serialized)
+                .unwrap();
+            prop_assert_eq!(serialized, bytes);
+        }
+    }
 }
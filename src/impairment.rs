@@ -0,0 +1,279 @@
+//! Impairment injection on the forwarded copies, to test resilience mechanisms
+//! (e.g., FRR, duplicate suppression or FEC in the applications) without netem.
+//!
+//! The impairments are applied per neighbor by wrapping the transport of the daemon.
+//! Dropped copies are reported as sent, as if they were lost in the network.
+
+use crate::daemon::Transport;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::io;
+use std::net::IpAddr;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct Impairment {
+    /// Probability to drop a copy.
+    #[serde(default)]
+    pub drop: f64,
+    /// Probability to send a copy twice.
+    #[serde(default)]
+    pub duplicate: f64,
+    /// Delay added to each copy, in milliseconds. The copies whose deadline is too far to
+    /// be represented are dropped.
+    #[serde(default)]
+    pub delay_ms: u64,
+    /// Random delay added on top of `delay_ms`, uniformly distributed up to this value.
+    #[serde(default)]
+    pub jitter_ms: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+pub struct ImpairmentConfig {
+    /// Seed of the random generator, to reproduce an experiment.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Impairment of the neighbors without a specific one.
+    #[serde(default)]
+    pub default: Impairment,
+    #[serde(default)]
+    pub neighbors: BTreeMap<IpAddr, Impairment>,
+}
+
+impl ImpairmentConfig {
+    pub fn get(&self, next_hop: IpAddr) -> &Impairment {
+        self.neighbors.get(&next_hop).unwrap_or(&self.default)
+    }
+}
+
+/// Xorshift64* generator. Impairments do not need a cryptographic generator.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // The state must not be zero.
+        Rng(seed | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    /// Uniform value in [0, 1).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+type DelayedCopy = (Instant, u64, IpAddr, Vec<u8>);
+
+/// Transport applying the impairments before sending the copies with `transport`.
+/// Delayed copies are sent by a dedicated thread.
+pub struct ImpairedTransport<T: Transport + Send + 'static> {
+    transport: Arc<Mutex<T>>,
+    config: ImpairmentConfig,
+    rng: Rng,
+    delayed: Option<mpsc::Sender<DelayedCopy>>,
+    nb_delayed: u64,
+}
+
+impl<T: Transport + Send + 'static> ImpairedTransport<T> {
+    pub fn new(transport: T, config: ImpairmentConfig) -> Self {
+        let seed = config.seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0)
+        });
+        ImpairedTransport {
+            transport: Arc::new(Mutex::new(transport)),
+            config,
+            rng: Rng::new(seed),
+            delayed: None,
+            nb_delayed: 0,
+        }
+    }
+
+    fn delay(&mut self, due: Instant, packet: &[u8], next_hop: IpAddr) {
+        let transport = &self.transport;
+        let sender = self
+            .delayed
+            .get_or_insert_with(|| spawn_delay_thread(transport.clone()));
+        // The sequence number keeps the order of the copies with the same deadline.
+        self.nb_delayed += 1;
+        let _ = sender.send((due, self.nb_delayed, next_hop, packet.to_vec()));
+    }
+}
+
+impl<T: Transport + Send + 'static> Transport for ImpairedTransport<T> {
+    fn send_to(&mut self, packet: &[u8], next_hop: IpAddr) -> io::Result<usize> {
+        let impairment = *self.config.get(next_hop);
+        if self.rng.next_f64() < impairment.drop {
+            return Ok(packet.len());
+        }
+
+        let nb_copies = if self.rng.next_f64() < impairment.duplicate {
+            2
+        } else {
+            1
+        };
+        for _ in 0..nb_copies {
+            let jitter = match impairment.jitter_ms {
+                0 => 0,
+                jitter => self.rng.next_u64() % jitter.saturating_add(1),
+            };
+            let delay = Duration::from_millis(impairment.delay_ms.saturating_add(jitter));
+            if delay.is_zero() {
+                self.transport.lock().unwrap().send_to(packet, next_hop)?;
+            } else if let Some(due) = Instant::now().checked_add(delay) {
+                self.delay(due, packet, next_hop);
+            }
+        }
+
+        Ok(packet.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.transport.lock().unwrap().flush()
+    }
+
+    /// The received packets are not impaired.
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.transport.lock().unwrap().recv(buf)
+    }
+}
+
+/// Sends the delayed copies at their deadline. The remaining copies are sent
+/// before the thread stops, once the impaired transport is dropped.
+fn spawn_delay_thread<T: Transport + Send + 'static>(
+    transport: Arc<Mutex<T>>,
+) -> mpsc::Sender<DelayedCopy> {
+    let (sender, receiver) = mpsc::channel::<DelayedCopy>();
+    std::thread::spawn(move || {
+        let mut queue: BinaryHeap<Reverse<DelayedCopy>> = BinaryHeap::new();
+        let mut disconnected = false;
+        loop {
+            let now = Instant::now();
+            while queue.peek().is_some_and(|Reverse((due, ..))| *due <= now) {
+                let Reverse((_, _, next_hop, packet)) = queue.pop().unwrap();
+                let mut transport = transport.lock().unwrap();
+                let res = transport
+                    .send_to(&packet, next_hop)
+                    .and_then(|_| transport.flush());
+                if let Err(e) = res {
+                    log::debug!(
+                        "Error when sending a delayed copy to {:?}: {:?}",
+                        next_hop,
+                        e
+                    );
+                }
+            }
+
+            let next = queue.peek().map(|Reverse((due, ..))| *due - now);
+            let res = match (next, disconnected) {
+                (None, true) => break,
+                (Some(timeout), true) => {
+                    std::thread::sleep(timeout);
+                    continue;
+                }
+                (Some(timeout), false) => receiver.recv_timeout(timeout),
+                (None, false) => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match res {
+                Ok(copy) => queue.push(Reverse(copy)),
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => disconnected = true,
+            }
+        }
+    });
+    sender
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::daemon::MemoryTransport;
+
+    fn get_config() -> ImpairmentConfig {
+        serde_json::from_str(
+            r#"{
+                "seed": 42,
+                "default": {"duplicate": 1.0},
+                "neighbors": {
+                    "fc00::b": {"drop": 1.0},
+                    "fc00::c": {"delay_ms": 50, "jitter_ms": 10},
+                    "fc00::d": {"drop": 0.5}
+                }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    /// Tests the drop, duplication and delay of the copies.
+    fn test_impaired_transport() {
+        let memory = MemoryTransport::default();
+        let mut transport = ImpairedTransport::new(memory.clone(), get_config());
+        let (a, b, c): (IpAddr, IpAddr, IpAddr) = (
+            "fc00::a".parse().unwrap(),
+            "fc00::b".parse().unwrap(),
+            "fc00::c".parse().unwrap(),
+        );
+
+        assert_eq!(transport.send_to(&[1], b).unwrap(), 1);
+        assert!(memory.is_empty());
+
+        transport.send_to(&[2], a).unwrap();
+        assert_eq!(memory.sent(), vec![(a, vec![2]), (a, vec![2])]);
+
+        let start = Instant::now();
+        transport.send_to(&[3], c).unwrap();
+        assert_eq!(memory.len(), 2);
+        // The delayed copies are flushed when the transport is dropped.
+        drop(transport);
+        while memory.len() < 3 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(memory.sent()[2], (c, vec![3]));
+
+        // The largest delays do not overflow.
+        let mut config = get_config();
+        config.default = Impairment {
+            delay_ms: u64::MAX,
+            jitter_ms: u64::MAX,
+            ..Default::default()
+        };
+        let mut transport = ImpairedTransport::new(memory.clone(), config);
+        assert_eq!(
+            transport.send_to(&[4], "fc00::e".parse().unwrap()).unwrap(),
+            1
+        );
+        assert_eq!(memory.len(), 3);
+    }
+
+    #[test]
+    /// Tests that the same seed gives the same drops.
+    fn test_impaired_transport_seed() {
+        let d: IpAddr = "fc00::d".parse().unwrap();
+        let run = || {
+            let memory = MemoryTransport::default();
+            let mut transport = ImpairedTransport::new(memory.clone(), get_config());
+            for i in 0..100 {
+                transport.send_to(&[i], d).unwrap();
+            }
+            memory.sent()
+        };
+        let sent = run();
+        assert!(sent.len() > 20 && sent.len() < 80);
+        assert_eq!(run(), sent);
+    }
+}
@@ -0,0 +1,196 @@
+//! Test vectors to check the interoperability with other BIER implementations.
+//!
+//! A vector file contains the BIFTs of the BFR under test and a list of vectors.
+//! Each vector is a packet captured from another implementation (in hex, starting with
+//! the BIER header), with optionally the expected values of the header fields, the expected
+//! replication (bitstring and next hop of each copy, `null` for the local copy), or the
+//! expected error (e.g., `"Header"` or `"BiftId"`).
+
+use crate::bier::{BierState, Bitstring};
+use crate::header::BierHeader;
+use crate::Error;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+#[derive(Deserialize, Debug)]
+pub struct TestVectors {
+    pub bier_state: BierState,
+    pub vectors: Vec<TestVector>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TestVector {
+    pub name: String,
+    /// Hex-encoded packet. Whitespaces are ignored.
+    pub packet: String,
+    /// Expected values of the header fields, named as in `BierHeader`.
+    #[serde(default)]
+    pub header: Map<String, Value>,
+    #[serde(default)]
+    pub replication: Option<Vec<ExpectedCopy>>,
+    /// Expected error when parsing or processing the packet.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub struct ExpectedCopy {
+    pub bitstring: Bitstring,
+    pub next_hop: Option<IpAddr>,
+}
+
+impl TestVectors {
+    /// Checks all vectors. Returns the name and mismatches of each failed vector.
+    pub fn check(&self) -> Vec<(&str, Vec<String>)> {
+        let mut bier_state = self.bier_state.clone();
+        if let Err(e) = bier_state.compile() {
+            return vec![("bier_state", vec![format!("cannot compile: {}", e)])];
+        }
+        self.vectors
+            .iter()
+            .map(|vector| (vector.name.as_str(), vector.check(&bier_state)))
+            .filter(|(_, mismatches)| !mismatches.is_empty())
+            .collect()
+    }
+}
+
+impl TestVector {
+    /// Runs the vector against the header parser and `process_bier` with the compiled
+    /// `bier_state`, and the checks of RFC 8296 if the state is strict.
+    /// Returns the mismatches with the expected values, empty if the vector passes.
+    pub fn check(&self, bier_state: &BierState) -> Vec<String> {
+        let packet = match from_hex(&self.packet) {
+            Some(v) => v,
+            None => return vec!["invalid hex packet".to_string()],
+        };
+
+        let res = BierHeader::from_slice(&packet).and_then(|header| {
+            if bier_state.strict_rfc8296 {
+                header.check_rfc8296()?;
+            }
+            bier_state
+                .process_bier(
+                    header.get_bitstring(),
+                    header.get_bift_id(),
+                    header.get_entropy(),
+                )
+                .map(|copies| (header, copies))
+        });
+        let (header, copies) = match (res, &self.error) {
+            (Err(e), Some(error)) if error_matches(&e, error) => return Vec::new(),
+            (Err(e), _) => return vec![format!("unexpected error: {}", e)],
+            (Ok(_), Some(error)) => return vec![format!("expected error {}", error)],
+            (Ok(v), None) => v,
+        };
+
+        let mut mismatches = Vec::new();
+        let fields = serde_json::to_value(&header).unwrap();
+        for (name, expected) in self.header.iter() {
+            let matches = match (name.as_str(), fields.get(name)) {
+                (_, None) => {
+                    mismatches.push(format!("unknown header field {}", name));
+                    continue;
+                }
+                // Bitstrings may be written without the leading zeros.
+                ("bitstring", Some(_)) => expected
+                    .as_str()
+                    .and_then(|s| Bitstring::from_str(s).ok())
+                    .is_some_and(|bitstring| &bitstring == header.get_bitstring()),
+                (_, Some(value)) => value == expected,
+            };
+            if !matches {
+                mismatches.push(format!(
+                    "header field {}: expected {}, got {}",
+                    name, expected, fields[name]
+                ));
+            }
+        }
+
+        if let Some(replication) = &self.replication {
+            // The order of the copies does not matter.
+            let mut remaining: Vec<ExpectedCopy> = copies
+                .into_iter()
+                .map(|(bitstring, next_hop)| ExpectedCopy { bitstring, next_hop })
+                .collect();
+            for expected in replication {
+                match remaining.iter().position(|copy| copy == expected) {
+                    Some(idx) => {
+                        remaining.remove(idx);
+                    }
+                    None => mismatches.push(format!("missing copy {:?}", expected)),
+                }
+            }
+            for copy in remaining {
+                mismatches.push(format!("unexpected copy {:?}", copy));
+            }
+        }
+
+        mismatches
+    }
+}
+
+/// Whether `e` is the `error` expected by a vector: the name of its variant, e.g., `BiftId`,
+/// or the variant with its context, e.g., `BiftId(42)`.
+fn error_matches(e: &Error, error: &str) -> bool {
+    let debug = format!("{:?}", e);
+    debug == error || debug.split(['(', ' ']).next() == Some(error)
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = s
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<_>>()?;
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    Some(digits.chunks(2).map(|d| (d[0] << 4) | d[1]).collect())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    /// Tests the vectors shipped with the repository.
+    fn test_example_vectors() {
+        let vectors: TestVectors =
+            serde_json::from_str(include_str!("../vectors/example.json")).unwrap();
+        assert_eq!(vectors.check(), Vec::new());
+    }
+
+    #[test]
+    /// Tests the detection of mismatches.
+    fn test_vector_mismatches() {
+        let mut vectors: TestVectors =
+            serde_json::from_str(include_str!("../vectors/example.json")).unwrap();
+        vectors.bier_state.compile().unwrap();
+        let vector: TestVector = serde_json::from_str(
+            r#"{
+                "name": "wrong",
+                "packet": "00001140 50112345 00060003 00000000 0000001f",
+                "header": {"ttl": 63, "proto": 6, "foo": 1},
+                "replication": [{"bitstring": "1", "next_hop": null}]
+            }"#,
+        )
+        .unwrap();
+        let mismatches = vector.check(&vectors.bier_state);
+        assert_eq!(mismatches.len(), 4);
+        assert!(mismatches[0].starts_with("unknown header field foo"));
+        assert!(mismatches[1].starts_with("header field ttl"));
+
+        // The expected error may pin its context.
+        assert!(error_matches(&Error::BiftId(42), "BiftId"));
+        assert!(error_matches(&Error::BiftId(42), "BiftId(42)"));
+        assert!(!error_matches(&Error::BiftId(42), "BiftId(7)"));
+        assert!(error_matches(&Error::ApiMagic, "ApiMagic"));
+
+        assert_eq!(from_hex("0a 1B"), Some(vec![0x0a, 0x1b]));
+        assert_eq!(from_hex("0a1"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+}
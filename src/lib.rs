@@ -1,18 +1,112 @@
+//! BIER forwarding (RFC 8279) and encapsulation (RFC 8296). The `bitstring` and `header`
+//! modules only depend on `core` and `alloc`: without the default `std` feature, the crate
+//! builds for `no_std` targets, e.g., an embedded dataplane. The other modules, i.e., the
+//! daemon, its sockets and its control plane, require the `std` feature.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod acl;
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub mod af_packet;
+#[cfg(feature = "std")]
 pub mod api;
+#[cfg(feature = "std")]
 pub mod bier;
+#[cfg(feature = "std")]
+pub mod bierin6;
+pub mod bitstring;
+#[cfg(feature = "std")]
+pub mod cache;
+#[cfg(feature = "std")]
+pub mod client;
+#[cfg(feature = "std")]
+pub mod control;
+#[cfg(feature = "std")]
+pub mod daemon;
+#[cfg(feature = "std")]
+pub mod delta;
+#[cfg(feature = "std")]
+pub mod dispatch;
+#[cfg(feature = "std")]
+pub mod ethernet;
+#[cfg(feature = "std")]
+pub mod frr;
 pub mod header;
+#[cfg(feature = "std")]
+pub mod impairment;
+#[cfg(feature = "std")]
+pub mod interop;
+#[cfg(feature = "std")]
+pub mod liveness;
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub mod membership;
+#[cfg(feature = "std")]
+pub mod oam;
+#[cfg(feature = "std")]
+pub mod overlay;
+#[cfg(feature = "std")]
+pub mod pcap;
+#[cfg(feature = "std")]
 pub mod dijkstra;
+#[cfg(feature = "std")]
+pub mod packet_log;
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub mod pipeline;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+#[cfg(feature = "std")]
+pub mod router;
+#[cfg(feature = "std")]
+pub mod runtime;
+#[cfg(feature = "std")]
+pub mod server;
+#[cfg(feature = "std")]
+pub mod sim;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "std")]
+pub mod stats;
+#[cfg(feature = "std")]
+pub mod sync_channel;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+#[cfg(feature = "tokio")]
+pub mod tokio_client;
+#[cfg(feature = "tokio")]
+pub mod tokio_runtime;
+#[cfg(all(feature = "tun", target_os = "linux"))]
+pub mod tun;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+pub mod uring_runtime;
+#[cfg(all(feature = "xdp", target_os = "linux"))]
+pub mod xdp;
+
+/// Big-endian `u16` at `at` in `slice`, which must be long enough.
+fn be_u16(slice: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([slice[at], slice[at + 1]])
+}
 
-unsafe fn get_unchecked_be_u16(ptr: *const u8) -> u16 {
-    u16::from_be_bytes([*ptr, *ptr.add(1)])
+/// Big-endian `u32` at `at` in `slice`, which must be long enough.
+fn be_u32(slice: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([slice[at], slice[at + 1], slice[at + 2], slice[at + 3]])
 }
 
-unsafe fn get_unchecked_be_u32(ptr: *const u8) -> u32 {
-    u32::from_be_bytes([*ptr, *ptr.add(1), *ptr.add(2), *ptr.add(3)])
+/// Checks that `slice` holds the `expected` bytes of a header or a message.
+fn check_length(slice: &[u8], expected: usize) -> Result<()> {
+    match slice.len() < expected {
+        true => Err(Error::SliceWrongLength {
+            expected,
+            actual: slice.len(),
+        }),
+        false => Ok(()),
+    }
 }
 
 /// Custom result used for Bier processing.
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
 
 /// A BIER error.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -20,8 +114,11 @@ pub enum Error {
     /// Impossible to parse the Bier header.
     Header,
 
-    /// Invalid BIFT-ID.
-    BiftId,
+    /// Unknown or invalid BIFT-id.
+    BiftId(u32),
+
+    /// No BIFT in the sub-domain.
+    SubDomain(u8),
 
     /// Impossible to parse the BIFTs.
     BiftParsing,
@@ -32,6 +129,122 @@ pub enum Error {
     /// Wrong Bitstring length.
     BitstringLength,
 
-    /// The buffer does not have the correct length for the BIER header.
-    SliceWrongLength,
+    /// The buffer is shorter than the header or the message it holds, of `expected` bytes.
+    SliceWrongLength { expected: usize, actual: usize },
+
+    /// The API message does not start with the magic number.
+    ApiMagic,
+
+    /// Unsupported version of the API message format.
+    ApiVersion(u8),
+
+    /// Unknown or unexpected type of API message.
+    ApiMessageType,
+
+    /// Truncated TLV, or TLV with an invalid length, in an API message.
+    ApiTlv,
+
+    /// The application is not allowed to send packets by the access control list.
+    Unauthorized,
+
+    /// The nibble or the version of the BIER header do not follow RFC 8296.
+    HeaderVersion,
+
+    /// The MPLS payload does not start with a label stack ending with the bottom-of-stack bit.
+    MplsLabelStack,
+
+    /// Malformed message of the control-plane sync channel.
+    SyncMessage,
+
+    /// A message of the sync channel is not acknowledged after all its retransmissions.
+    SyncTimeout,
+
+    /// The BIFTs are processed before being compiled, see `BierState::compile`.
+    NotCompiled,
+
+    /// The packet is larger than the buffers of the daemon.
+    PacketTooLarge,
+
+    /// Malformed or unsupported BIER OAM message.
+    OamMessage,
+
+    /// Unknown value of the proto field of the BIER header.
+    Proto,
+
+    /// Malformed MLDv2 or IGMPv3 report.
+    MembershipReport,
+
+    /// The daemon does not accept the registration of the application, e.g., without
+    /// dispatcher or with an invalid path.
+    Registration,
+
+    /// The BFR-ids of a bitstring are not all in the same set identifier (SI), or in an SI
+    /// above 255.
+    SetIdentifier,
+
+    /// Bitstrings of different lengths, e.g., a packet whose BSL is not the one of its BIFT.
+    /// The lengths are in bits.
+    BitstringLengthMismatch { expected: usize, actual: usize },
+
+    /// Truncated or malformed binary snapshot of a BIER state.
+    Snapshot,
+
+    /// Unsupported version of the binary snapshot format.
+    SnapshotVersion(u8),
+
+    /// Malformed hello of the liveness prober.
+    LivenessHello,
+
+    /// BIFT type not supported by the BIER processing, i.e., BIER-TE.
+    BiftType,
+
+    /// Message of the application dropped by the daemon, notified with an error code
+    /// without the context of the error, e.g., the lengths of a malformed message.
+    #[cfg(feature = "std")]
+    Remote(api::ErrorCode),
 }
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Error::Header => f.write_str("malformed BIER header"),
+            Error::BiftId(bift_id) => write!(f, "unknown BIFT-id {}", bift_id),
+            Error::SubDomain(sub_domain) => write!(f, "no BIFT in the sub-domain {}", sub_domain),
+            Error::BiftParsing => f.write_str("invalid BIFTs"),
+            Error::NoEntry => f.write_str("no BIFT entry"),
+            Error::BitstringLength => f.write_str("invalid bitstring length"),
+            Error::SliceWrongLength { expected, actual } => {
+                write!(f, "buffer too short: {} bytes, expected {}", actual, expected)
+            }
+            Error::ApiMagic => f.write_str("API message without the magic number"),
+            Error::ApiVersion(version) => write!(f, "unsupported API version {}", version),
+            Error::ApiMessageType => f.write_str("unknown or unexpected API message type"),
+            Error::ApiTlv => f.write_str("malformed TLV in an API message"),
+            Error::Unauthorized => f.write_str("denied by the access control list"),
+            Error::HeaderVersion => f.write_str("BIER header not following RFC 8296"),
+            Error::MplsLabelStack => f.write_str("missing bottom of the MPLS label stack"),
+            Error::SyncMessage => f.write_str("malformed sync channel message"),
+            Error::SyncTimeout => f.write_str("sync channel message not acknowledged"),
+            Error::NotCompiled => f.write_str("BIFTs not compiled"),
+            Error::PacketTooLarge => f.write_str("packet larger than the buffers"),
+            Error::OamMessage => f.write_str("malformed or unsupported OAM message"),
+            Error::Proto => f.write_str("unknown proto"),
+            Error::MembershipReport => f.write_str("malformed MLDv2 or IGMPv3 report"),
+            Error::Registration => f.write_str("registration refused"),
+            Error::SetIdentifier => f.write_str("BFR-ids not in a single valid SI"),
+            Error::BitstringLengthMismatch { expected, actual } => {
+                write!(f, "bitstring of {} bits, expected {}", actual, expected)
+            }
+            Error::Snapshot => f.write_str("malformed binary snapshot"),
+            Error::SnapshotVersion(version) => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+            Error::LivenessHello => f.write_str("malformed liveness hello"),
+            Error::BiftType => f.write_str("unsupported BIFT type"),
+            #[cfg(feature = "std")]
+            Error::Remote(code) => write!(f, "message dropped by the daemon: {:?}", code),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
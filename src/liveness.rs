@@ -0,0 +1,486 @@
+//! BFD-style liveness of the next hops. The prober of each BFR sends a hello request to each
+//! of its next hops every interval, to the UDP port of the probers, and the prober of the
+//! neighbor answers with a hello reply. A next hop without reply during `multiplier`
+//! intervals is declared dead: `process_bier` uses the next path of the entries toward it
+//! instead of blackholing their bits, until it answers again.
+//!
+//! Like `SyncChannel` (see `sync_channel`), `Liveness` does not perform any I/O:
+//! `LivenessSource` carries the hellos on a UDP socket of the event loop. All the neighbors
+//! must run a prober on the same port, as a next hop without prober is declared dead.
+//!
+//! Each hello holds the probed next hop, as an IPv6 or IPv4-mapped address, so that the reply
+//! is matched to the next hop whatever the source address of the neighbor, e.g., its
+//! interface address when the next hop is its loopback. As in BFD, the reply is authenticated
+//! by the discriminator of the session, drawn at random, and by the sequence number of the
+//! request, which starts at random: another host cannot forge the replies of a dead neighbor
+//! without seeing its requests.
+//! ```text
+//! magic (2) | type (1, 1 for a request and 2 for a reply) | reserved (1) | discriminator (4)
+//! | sequence (4) | next hop (16)
+//! ```
+
+use crate::router::Router;
+use crate::runtime::{drain, EventSource};
+use crate::{Error, Result};
+use log::{debug, info, warn};
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+/// First bytes of the hellos.
+pub const HELLO_MAGIC: u16 = 0xB1E6;
+
+pub const HELLO_LENGTH: usize = 28;
+
+/// Default interval between the hello requests sent to a next hop.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Default number of intervals without reply before a next hop is declared dead.
+pub const DEFAULT_MULTIPLIER: u32 = 3;
+
+const HELLO_REQUEST: u8 = 1;
+const HELLO_REPLY: u8 = 2;
+
+/// Hello request or reply of the probers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hello {
+    pub reply: bool,
+    /// Discriminator of the session of the prober, echoed by the reply.
+    pub discriminator: u32,
+    pub sequence: u32,
+    /// Next hop probed by the request.
+    pub next_hop: IpAddr,
+}
+
+impl Hello {
+    pub fn from_slice(slice: &[u8]) -> Result<Self> {
+        if slice.len() != HELLO_LENGTH || u16::from_be_bytes([slice[0], slice[1]]) != HELLO_MAGIC {
+            return Err(Error::LivenessHello);
+        }
+        let reply = match slice[2] {
+            HELLO_REQUEST => false,
+            HELLO_REPLY => true,
+            _ => return Err(Error::LivenessHello),
+        };
+        let address = Ipv6Addr::from(<[u8; 16]>::try_from(&slice[12..]).unwrap());
+        Ok(Hello {
+            reply,
+            discriminator: u32::from_be_bytes(slice[4..8].try_into().unwrap()),
+            sequence: u32::from_be_bytes(slice[8..12].try_into().unwrap()),
+            next_hop: match address.to_ipv4_mapped() {
+                Some(address) => IpAddr::V4(address),
+                None => IpAddr::V6(address),
+            },
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; HELLO_LENGTH] {
+        let mut bytes = [0u8; HELLO_LENGTH];
+        bytes[..2].copy_from_slice(&HELLO_MAGIC.to_be_bytes());
+        bytes[2] = match self.reply {
+            true => HELLO_REPLY,
+            false => HELLO_REQUEST,
+        };
+        bytes[4..8].copy_from_slice(&self.discriminator.to_be_bytes());
+        bytes[8..12].copy_from_slice(&self.sequence.to_be_bytes());
+        let address = match self.next_hop {
+            IpAddr::V4(address) => address.to_ipv6_mapped(),
+            IpAddr::V6(address) => address,
+        };
+        bytes[12..].copy_from_slice(&address.octets());
+        bytes
+    }
+}
+
+/// Unpredictable `u32`, from the random keys of the standard hasher.
+fn random_u32() -> u32 {
+    RandomState::new().build_hasher().finish() as u32
+}
+
+/// Liveness of a probed next hop.
+struct Session {
+    last_reply: Instant,
+    alive: bool,
+    /// Random discriminator of the session.
+    discriminator: u32,
+    /// Sequence number of the last request, starting at random.
+    sequence: u32,
+}
+
+impl Session {
+    fn new(now: Instant) -> Self {
+        Session {
+            last_reply: now,
+            alive: true,
+            discriminator: random_u32(),
+            sequence: random_u32(),
+        }
+    }
+}
+
+/// Liveness of the next hops of a BFR, from the replies to its hello requests.
+pub struct Liveness {
+    interval: Duration,
+    multiplier: u32,
+    sessions: BTreeMap<IpAddr, Session>,
+    next_probe: Instant,
+    /// Changes of liveness not taken yet by `take_changes`.
+    changes: Vec<(IpAddr, bool)>,
+}
+
+impl Liveness {
+    /// Prober sending its first requests at `now`. The multiplier is at least 1.
+    pub fn new(interval: Duration, multiplier: u32, now: Instant) -> Self {
+        Liveness {
+            interval,
+            multiplier: multiplier.max(1),
+            sessions: BTreeMap::new(),
+            next_probe: now,
+            changes: Vec::new(),
+        }
+    }
+
+    /// Probes `next_hops`, e.g., the next hops of the current BIFTs. The new ones are alive
+    /// until they do not answer, and the others are forgotten, and reported alive if dead.
+    pub fn set_next_hops(&mut self, next_hops: impl IntoIterator<Item = IpAddr>, now: Instant) {
+        let mut sessions = BTreeMap::new();
+        for next_hop in next_hops {
+            let session = self
+                .sessions
+                .remove(&next_hop)
+                .unwrap_or_else(|| Session::new(now));
+            sessions.insert(next_hop, session);
+        }
+        for (next_hop, session) in std::mem::replace(&mut self.sessions, sessions) {
+            if !session.alive {
+                self.changes.push((next_hop, true));
+            }
+        }
+    }
+
+    /// Liveness of `next_hop`, if probed.
+    pub fn is_alive(&self, next_hop: IpAddr) -> Option<bool> {
+        self.sessions.get(&next_hop).map(|session| session.alive)
+    }
+
+    /// Time of the next requests.
+    pub fn deadline(&self) -> Instant {
+        self.next_probe
+    }
+
+    /// Declares dead the next hops without reply during `multiplier` intervals, and returns
+    /// the requests to send to each next hop, if their time is reached.
+    pub fn poll(&mut self, now: Instant) -> Vec<(IpAddr, [u8; HELLO_LENGTH])> {
+        if now < self.next_probe {
+            return Vec::new();
+        }
+        let detection_time = self.interval * self.multiplier;
+        for (next_hop, session) in self.sessions.iter_mut() {
+            if session.alive && now.duration_since(session.last_reply) >= detection_time {
+                session.alive = false;
+                self.changes.push((*next_hop, false));
+            }
+        }
+        self.next_probe = now + self.interval;
+        self.sessions
+            .iter_mut()
+            .map(|(next_hop, session)| {
+                session.sequence = session.sequence.wrapping_add(1);
+                let request = Hello {
+                    reply: false,
+                    discriminator: session.discriminator,
+                    sequence: session.sequence,
+                    next_hop: *next_hop,
+                };
+                (*next_hop, request.to_bytes())
+            })
+            .collect()
+    }
+
+    /// Handles a hello received at `now`, and returns the reply to a request. The replies to
+    /// the requests of the last `multiplier` intervals keep their next hop alive, if they
+    /// echo the discriminator of its session.
+    pub fn receive(&mut self, datagram: &[u8], now: Instant) -> Result<Option<[u8; HELLO_LENGTH]>> {
+        let hello = Hello::from_slice(datagram)?;
+        if !hello.reply {
+            return Ok(Some(
+                Hello {
+                    reply: true,
+                    ..hello
+                }
+                .to_bytes(),
+            ));
+        }
+        let multiplier = self.multiplier;
+        match self.sessions.get_mut(&hello.next_hop) {
+            Some(session)
+                if session.discriminator == hello.discriminator
+                    && session.sequence.wrapping_sub(hello.sequence) < multiplier =>
+            {
+                session.last_reply = now;
+                if !session.alive {
+                    session.alive = true;
+                    self.changes.push((hello.next_hop, true));
+                }
+            }
+            _ => debug!("Ignoring an unexpected hello reply: {:?}", hello),
+        }
+        Ok(None)
+    }
+
+    /// Changes of liveness since the last call, in order.
+    pub fn take_changes(&mut self) -> Vec<(IpAddr, bool)> {
+        std::mem::take(&mut self.changes)
+    }
+}
+
+/// Prober of the next hops of the compiled BIFTs of the daemon, which declares them alive or
+/// dead with `BierDaemon::set_next_hop_alive`.
+pub struct LivenessSource {
+    socket: UdpSocket,
+    /// UDP port of the probers of the neighbors.
+    port: u16,
+    liveness: Liveness,
+}
+
+impl LivenessSource {
+    /// Prober receiving the hellos on `socket`, bound to its port, and sending its requests
+    /// to `port` of the next hops.
+    pub fn new(
+        socket: UdpSocket,
+        port: u16,
+        interval: Duration,
+        multiplier: u32,
+    ) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        Ok(LivenessSource {
+            socket,
+            port,
+            liveness: Liveness::new(interval, multiplier, Instant::now()),
+        })
+    }
+}
+
+impl EventSource for LivenessSource {
+    fn fd(&self) -> Option<RawFd> {
+        Some(self.socket.as_raw_fd())
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        Some(self.liveness.deadline())
+    }
+
+    fn handle(&mut self, router: &mut Router) -> io::Result<()> {
+        let now = Instant::now();
+        // Longer than a hello, to detect the longer datagrams.
+        let mut buffer = [0u8; HELLO_LENGTH + 1];
+        // The hellos are sent even if the socket fails, its error is returned afterwards.
+        let received = drain(|| {
+            let (read, from) = self.socket.recv_from(&mut buffer)?;
+            match self.liveness.receive(&buffer[..read], now) {
+                Ok(Some(reply)) => {
+                    if let Err(e) = self.socket.send_to(&reply, from) {
+                        debug!("Cannot answer the hello of {}: {}", from, e);
+                    }
+                }
+                Ok(None) => (),
+                Err(e) => debug!("Ignoring a datagram of {}: {}", from, e),
+            }
+            Ok(())
+        });
+
+        if let Some(compiled) = router.daemon().state().compiled.as_ref() {
+            self.liveness.set_next_hops(compiled.next_hops(), now);
+        }
+        for (next_hop, request) in self.liveness.poll(now) {
+            // An unreachable next hop is declared dead after the detection time.
+            if let Err(e) = self
+                .socket
+                .send_to(&request, SocketAddr::new(next_hop, self.port))
+            {
+                debug!("Cannot send a hello to {}: {}", next_hop, e);
+            }
+        }
+        for (next_hop, alive) in self.liveness.take_changes() {
+            match alive {
+                true => info!("The next hop {} is alive", next_hop),
+                false => warn!("The next hop {} is dead, using the next paths", next_hop),
+            }
+            router.daemon_mut().set_next_hop_alive(next_hop, alive);
+        }
+        received
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::bier::{BierState, Bitstring};
+    use crate::daemon::{BierDaemon, MemoryTransport};
+    use std::collections::BTreeSet;
+    use std::str::FromStr;
+
+    #[test]
+    /// Tests the detection of the dead next hops from the replies to the requests.
+    fn test_liveness() {
+        let start = Instant::now();
+        let interval = Duration::from_millis(100);
+        let mut liveness = Liveness::new(interval, 3, start);
+        let (a, b): (IpAddr, IpAddr) = ("fc00::a".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        liveness.set_next_hops([a, b], start);
+
+        // The neighbors answer the requests, whose next hop is echoed by the reply.
+        let requests = liveness.poll(start);
+        assert_eq!(requests.len(), 2);
+        assert!(liveness.poll(start + interval / 2).is_empty());
+        assert_eq!(liveness.deadline(), start + interval);
+        let request = |requests: &[(IpAddr, [u8; HELLO_LENGTH])], next_hop| {
+            let (_, request) = requests.iter().find(|(to, _)| *to == next_hop).unwrap();
+            Hello::from_slice(request).unwrap()
+        };
+        let (first_a, first_b) = (request(&requests, a), request(&requests, b));
+        assert!(!first_b.reply);
+        assert_eq!(first_b.next_hop, b);
+        let mut neighbor = Liveness::new(interval, 3, start);
+        let reply = neighbor
+            .receive(&first_b.to_bytes(), start)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            Hello::from_slice(&reply).unwrap(),
+            Hello {
+                reply: true,
+                ..first_b
+            }
+        );
+        assert_eq!(liveness.receive(&reply, start + interval * 2), Ok(None));
+
+        // Only B answered, A is dead after three intervals.
+        for i in 1..3 {
+            assert_eq!(liveness.poll(start + interval * i).len(), 2);
+        }
+        assert!(liveness.take_changes().is_empty());
+        liveness.poll(start + interval * 3);
+        assert_eq!(liveness.take_changes(), vec![(a, false)]);
+        assert_eq!(liveness.is_alive(a), Some(false));
+        assert_eq!(liveness.is_alive(b), Some(true));
+        let requests = liveness.poll(start + interval * 5);
+        assert_eq!(liveness.take_changes(), vec![(b, false)]);
+
+        // A late reply is ignored, as well as a reply with another discriminator, and a
+        // recent one makes the next hop alive again.
+        let last_a = request(&requests, a);
+        assert_eq!(last_a.discriminator, first_a.discriminator);
+        let reply = |hello: Hello| {
+            Hello {
+                reply: true,
+                ..hello
+            }
+            .to_bytes()
+        };
+        assert_eq!(
+            liveness.receive(&reply(first_a), start + interval * 5),
+            Ok(None)
+        );
+        assert!(liveness.take_changes().is_empty());
+        let forged = Hello {
+            discriminator: last_a.discriminator.wrapping_add(1),
+            ..last_a
+        };
+        liveness
+            .receive(&reply(forged), start + interval * 5)
+            .unwrap();
+        assert!(liveness.take_changes().is_empty());
+        liveness
+            .receive(&reply(last_a), start + interval * 5)
+            .unwrap();
+        assert_eq!(liveness.take_changes(), vec![(a, true)]);
+
+        // The forgotten next hops are not dead anymore.
+        liveness.set_next_hops([a], start + interval * 5);
+        assert_eq!(liveness.take_changes(), vec![(b, true)]);
+        assert_eq!(liveness.is_alive(b), None);
+
+        assert_eq!(
+            Hello::from_slice(&[0; HELLO_LENGTH]),
+            Err(Error::LivenessHello)
+        );
+        assert_eq!(
+            Hello::from_slice(&reply(last_a)[..HELLO_LENGTH - 1]),
+            Err(Error::LivenessHello)
+        );
+    }
+
+    #[test]
+    /// Tests the fallback of the daemon to the backup path while its prober does not receive
+    /// the replies of a neighbor, with the neighbors at `::1` and `127.0.0.1`.
+    fn test_liveness_source() {
+        let state: BierState = serde_json::from_value(serde_json::json!({
+            "loopback": "fc00::a",
+            "bifts": [{"bift_id": 1, "bift_type": 1, "bfr_id": 1, "entries": [
+                {"bit": 1, "paths": [{"bitstring": "1", "next_hop": "fc00::a"}]},
+                {"bit": 2, "paths": [
+                    {"bitstring": "10", "next_hop": "::1"},
+                    {"bitstring": "10", "next_hop": "127.0.0.1"},
+                ]},
+            ]}],
+        }))
+        .unwrap();
+        let get_router = |state: BierState| {
+            Router::new(
+                BierDaemon::builder()
+                    .state(state)
+                    .transport(MemoryTransport::default())
+                    .build()
+                    .unwrap(),
+            )
+        };
+        let mut router = get_router(state.clone());
+        let mut neighbor_router = get_router(state);
+
+        // Both neighbors run their prober on the same port, and the prober of the daemon is
+        // dual-stack.
+        let interval = Duration::from_millis(10);
+        let v6_socket = UdpSocket::bind("[::1]:0").unwrap();
+        let port = v6_socket.local_addr().unwrap().port();
+        let mut v6_neighbor = LivenessSource::new(v6_socket, 0, interval, 3).unwrap();
+        let v4_socket = UdpSocket::bind(("127.0.0.1", port)).unwrap();
+        let mut v4_neighbor = LivenessSource::new(v4_socket, 0, interval, 3).unwrap();
+        let socket = UdpSocket::bind("[::]:0").unwrap();
+        let mut prober = LivenessSource::new(socket, port, interval, 3).unwrap();
+        let mut run = |v6_up: bool| {
+            let start = Instant::now();
+            while start.elapsed() < interval * 10 {
+                prober.handle(&mut router).unwrap();
+                if v6_up {
+                    v6_neighbor.handle(&mut neighbor_router).unwrap();
+                }
+                v4_neighbor.handle(&mut neighbor_router).unwrap();
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            let copies = router
+                .daemon()
+                .state()
+                .process_bier(&Bitstring::from_str("10").unwrap(), 1, 0)
+                .unwrap();
+            let stats = router.daemon().stats();
+            let dead: BTreeSet<_> = stats
+                .neighbors
+                .iter()
+                .filter(|(_, neighbor)| neighbor.dead)
+                .map(|(next_hop, _)| *next_hop)
+                .collect();
+            (dead, copies[0].1)
+        };
+        let (v6, v4): (IpAddr, IpAddr) = ("::1".parse().unwrap(), "127.0.0.1".parse().unwrap());
+        assert_eq!(run(true), (BTreeSet::new(), Some(v6)));
+        // The preferred neighbor does not answer anymore.
+        assert_eq!(run(false), (BTreeSet::from([v6]), Some(v4)));
+        assert_eq!(run(true), (BTreeSet::new(), Some(v6)));
+    }
+}
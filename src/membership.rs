@@ -0,0 +1,816 @@
+//! Listener of the MLDv2 (RFC 3810) and IGMPv3 (RFC 3376) reports of the receivers on a
+//! downstream interface of a BFER. The interest of the receivers in each (*, G) or (S, G) is
+//! reported to the BFIR with the `join` and `leave` requests of the control protocol (see
+//! `control`), sent over UDP to its membership socket. The BFIR thus builds the bitstrings
+//! of its overlay from the actual receivers instead of its configuration.
+//!
+//! The listener does not query the receivers. Instead, it tracks the interest of each
+//! receiver from the source address of its reports, as MLDv2 and IGMPv3 do not suppress the
+//! reports: the link keeps its interest until its last receiver leaves. The requests are
+//! carried by a `SyncChannel` with the BFIR, which acknowledges them: a lost request is
+//! retransmitted, and the requests are applied once and in order, e.g., a retransmitted join
+//! does not override a later leave. The joins are also sent again periodically, in case the
+//! state of the BFIR is lost.
+//!
+//! The membership socket of the BFIR only accepts the requests of the allowed BFERs, and
+//! keeps their joins as soft state: a join not refreshed during the hold time expires, e.g.,
+//! after the failure of its BFER.
+
+use crate::control::{ControlRequest, ControlResponse};
+use crate::daemon::BierDaemon;
+use crate::router::Router;
+use crate::runtime::EventSource;
+use crate::sync_channel::SyncChannel;
+use crate::{Error, Result};
+use log::{debug, info, warn};
+use socket2::{Domain, InterfaceIndexOrAddress, Protocol, Socket, Type};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::CString;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+/// ICMPv6 type of the MLDv2 reports.
+const MLDV2_REPORT: u8 = 143;
+/// IGMP type of the IGMPv3 reports.
+const IGMPV3_REPORT: u8 = 0x22;
+/// Destination of the MLDv2 reports.
+const ALL_MLDV2_ROUTERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x16);
+/// Destination of the IGMPv3 reports.
+const ALL_IGMPV3_ROUTERS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 22);
+
+/// Default interval between the refreshes of the joins.
+pub const DEFAULT_REFRESH: Duration = Duration::from_secs(30);
+
+/// Default time after which the BFIR expires a join that was not refreshed, three refreshes.
+pub const DEFAULT_HOLD_TIME: Duration = Duration::from_secs(90);
+
+/// Interval between the checks of the expired joins by the BFIR.
+const EXPIRATION_CHECK: Duration = Duration::from_secs(1);
+
+/// Largest request accepted on the membership socket.
+const MAX_REQUEST_SIZE: usize = 1024;
+
+/// Types of the multicast address records of the reports.
+const MODE_IS_INCLUDE: u8 = 1;
+const MODE_IS_EXCLUDE: u8 = 2;
+const CHANGE_TO_INCLUDE: u8 = 3;
+const CHANGE_TO_EXCLUDE: u8 = 4;
+const ALLOW_NEW_SOURCES: u8 = 5;
+const BLOCK_OLD_SOURCES: u8 = 6;
+
+/// Start or end of the interest of the receivers in the traffic of `group`, from `source`
+/// only if given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Membership {
+    pub group: IpAddr,
+    pub source: Option<IpAddr>,
+    pub join: bool,
+}
+
+/// Memberships of an MLDv2 report, an ICMPv6 message.
+pub fn parse_mld_report(message: &[u8]) -> Result<Vec<Membership>> {
+    parse_report(message, MLDV2_REPORT, 16)
+}
+
+/// Memberships of an IGMPv3 report, without its IPv4 header.
+pub fn parse_igmp_report(message: &[u8]) -> Result<Vec<Membership>> {
+    parse_report(message, IGMPV3_REPORT, 4)
+}
+
+/// Parses the records of a report whose addresses have `address_len` bytes. Both reports
+/// have the type, a reserved byte, the checksum, two reserved bytes and the number of
+/// records, followed by the records.
+fn parse_report(message: &[u8], report_type: u8, address_len: usize) -> Result<Vec<Membership>> {
+    if message.len() < 8 || message[0] != report_type {
+        return Err(Error::MembershipReport);
+    }
+    let nb_records = crate::be_u16(message, 6);
+    let mut records = &message[8..];
+    let mut memberships = Vec::new();
+    for _ in 0..nb_records {
+        // Type, auxiliary data length (in words), number of sources and multicast address.
+        let header_len = 4 + address_len;
+        if records.len() < header_len {
+            return Err(Error::MembershipReport);
+        }
+        let record_type = records[0];
+        let nb_sources = crate::be_u16(records, 2) as usize;
+        let record_len = header_len + nb_sources * address_len + 4 * records[1] as usize;
+        if records.len() < record_len {
+            return Err(Error::MembershipReport);
+        }
+        let group = address(&records[4..header_len]);
+        let sources = records[header_len..header_len + nb_sources * address_len]
+            .chunks_exact(address_len)
+            .map(address);
+        let membership = |source, join| Membership {
+            group,
+            source,
+            join,
+        };
+        match record_type {
+            // Receivers of all the sources, except the excluded ones, which are ignored.
+            MODE_IS_EXCLUDE | CHANGE_TO_EXCLUDE => memberships.push(membership(None, true)),
+            MODE_IS_INCLUDE | CHANGE_TO_INCLUDE | ALLOW_NEW_SOURCES => {
+                // A change to the include mode, or an empty include mode, ends the interest
+                // in all the sources.
+                if record_type == CHANGE_TO_INCLUDE
+                    || (record_type == MODE_IS_INCLUDE && nb_sources == 0)
+                {
+                    memberships.push(membership(None, false));
+                }
+                memberships.extend(sources.map(|source| membership(Some(source), true)));
+            }
+            BLOCK_OLD_SOURCES => {
+                memberships.extend(sources.map(|source| membership(Some(source), false)))
+            }
+            _ => debug!("Unknown type of multicast address record {}", record_type),
+        }
+        records = &records[record_len..];
+    }
+    Ok(memberships)
+}
+
+/// IPv4 or IPv6 address of a slice of 4 or 16 bytes.
+/// Whether the traffic of `group` leaves its link, i.e., excluding the link-local groups of
+/// the control protocols such as the destination of the reports.
+fn is_routed(group: IpAddr) -> bool {
+    match group {
+        IpAddr::V4(group) => group.octets()[..3] != [224, 0, 0],
+        IpAddr::V6(group) => group.segments()[0] & 0xf > 2,
+    }
+}
+
+fn address(slice: &[u8]) -> IpAddr {
+    match <[u8; 16]>::try_from(slice) {
+        Ok(octets) => IpAddr::V6(Ipv6Addr::from(octets)),
+        Err(_) => IpAddr::V4(Ipv4Addr::new(slice[0], slice[1], slice[2], slice[3])),
+    }
+}
+
+/// Raw socket receiving the MLDv2 or IGMPv3 reports of a downstream interface, and reporting
+/// the receivers to the BFIR.
+pub struct MembershipSource {
+    socket: Socket,
+    /// The IPv4 raw sockets receive the IP header before the IGMP message.
+    ipv4: bool,
+    upstream: UdpSocket,
+    upstream_address: SocketAddr,
+    /// Channel carrying the requests to the BFIR.
+    channel: SyncChannel,
+    sub_domain: u8,
+    /// Receivers on the interface of each (group, source).
+    memberships: BTreeMap<(IpAddr, Option<IpAddr>), BTreeSet<IpAddr>>,
+    refresh: Duration,
+    next_refresh: Instant,
+    buffer: Vec<u8>,
+}
+
+impl MembershipSource {
+    /// Listens for the MLDv2 reports on `ifname`, and reports the receivers to the membership
+    /// socket of the BFIR at `upstream`, with the BFR-id of this BFR in `sub_domain`.
+    pub fn mld(ifname: &str, upstream: SocketAddr, sub_domain: u8) -> io::Result<Self> {
+        let socket = Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::ICMPV6))?;
+        socket.join_multicast_v6(&ALL_MLDV2_ROUTERS, if_index(ifname)?)?;
+        Self::new(socket, false, ifname, upstream, sub_domain)
+    }
+
+    /// Listens for the IGMPv3 reports on `ifname`, see `MembershipSource::mld`.
+    pub fn igmp(ifname: &str, upstream: SocketAddr, sub_domain: u8) -> io::Result<Self> {
+        let socket = Socket::new(
+            Domain::IPV4,
+            Type::RAW,
+            Some(Protocol::from(libc::IPPROTO_IGMP)),
+        )?;
+        let interface = InterfaceIndexOrAddress::Index(if_index(ifname)?);
+        socket.join_multicast_v4_n(&ALL_IGMPV3_ROUTERS, &interface)?;
+        Self::new(socket, true, ifname, upstream, sub_domain)
+    }
+
+    fn new(
+        socket: Socket,
+        ipv4: bool,
+        ifname: &str,
+        upstream: SocketAddr,
+        sub_domain: u8,
+    ) -> io::Result<Self> {
+        socket.bind_device(Some(ifname.as_bytes()))?;
+        socket.set_nonblocking(true)?;
+        Ok(MembershipSource {
+            socket,
+            ipv4,
+            upstream: connect(upstream)?,
+            upstream_address: upstream,
+            channel: SyncChannel::default(),
+            sub_domain,
+            memberships: BTreeMap::new(),
+            refresh: DEFAULT_REFRESH,
+            next_refresh: Instant::now() + DEFAULT_REFRESH,
+            buffer: Vec::with_capacity(crate::runtime::DEFAULT_MAX_PACKET_SIZE),
+        })
+    }
+
+    /// Interval between the refreshes of the joins, `DEFAULT_REFRESH` by default.
+    pub fn refresh(mut self, refresh: Duration) -> Self {
+        self.refresh = refresh;
+        self.next_refresh = Instant::now() + refresh;
+        self
+    }
+
+    /// Sends the `join` or `leave` request of `group` and `source` to the BFIR.
+    fn report(&mut self, group: IpAddr, source: Option<IpAddr>, join: bool, bfr_id: u64) {
+        let request = match join {
+            true => ControlRequest::Join {
+                group,
+                source,
+                sub_domain: self.sub_domain,
+                bfr_id,
+            },
+            false => ControlRequest::Leave {
+                group,
+                source,
+                bfr_id,
+            },
+        };
+        let request = serde_json::to_vec(&request).unwrap();
+        let datagram = self.channel.send(&request, Instant::now());
+        // A datagram not sent is retransmitted as a lost one.
+        if let Err(e) = self.upstream.send(&datagram) {
+            warn!(
+                "Cannot report the receivers of {} to the BFIR: {:?}",
+                group, e
+            );
+        }
+    }
+
+    /// Retransmits the requests not acknowledged by the BFIR. If the BFIR does not answer,
+    /// the channel is reset on a new socket, seen as a new peer by the BFIR, and all the
+    /// joins are sent again.
+    fn retransmit(&mut self, now: Instant) -> io::Result<()> {
+        match self.channel.poll(now) {
+            Ok(datagrams) => {
+                for datagram in datagrams {
+                    if let Err(e) = self.upstream.send(&datagram) {
+                        debug!("Cannot retransmit a request to the BFIR: {:?}", e);
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("The BFIR does not acknowledge the requests: {:?}", e);
+                self.upstream = connect(self.upstream_address)?;
+                self.channel = SyncChannel::default();
+                self.next_refresh = now;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles the report of `receiver`. The BFIR is only notified of the first join and of
+    /// the last leave of each (group, source).
+    fn handle_report(&mut self, message: &[u8], receiver: IpAddr, bfr_id: u64) {
+        let memberships = match self.ipv4 {
+            // The length of the IPv4 header is in its first byte.
+            true => message
+                .first()
+                .and_then(|byte| message.get(4 * (byte & 0xf) as usize..))
+                .ok_or(Error::MembershipReport)
+                .and_then(parse_igmp_report),
+            false => parse_mld_report(message),
+        };
+        let memberships = match memberships {
+            Ok(memberships) => memberships,
+            Err(e) => {
+                debug!("Ignoring a membership message: {:?}", e);
+                return;
+            }
+        };
+        for Membership {
+            group,
+            source,
+            join,
+        } in memberships.into_iter().filter(|m| is_routed(m.group))
+        {
+            let key = (group, source);
+            let changed = match join {
+                true => {
+                    let receivers = self.memberships.entry(key).or_default();
+                    receivers.insert(receiver) && receivers.len() == 1
+                }
+                false => {
+                    let last = self.memberships.get_mut(&key).is_some_and(|receivers| {
+                        receivers.remove(&receiver) && receivers.is_empty()
+                    });
+                    if last {
+                        self.memberships.remove(&key);
+                    }
+                    last
+                }
+            };
+            if changed {
+                debug!("Receivers of ({:?}, {}): {}", source, group, join);
+                self.report(group, source, join, bfr_id);
+            }
+        }
+    }
+}
+
+impl EventSource for MembershipSource {
+    fn fd(&self) -> Option<RawFd> {
+        Some(self.socket.as_raw_fd())
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        match self.channel.deadline() {
+            Some(deadline) => Some(deadline.min(self.next_refresh)),
+            None => Some(self.next_refresh),
+        }
+    }
+
+    fn handle(&mut self, router: &mut Router) -> io::Result<()> {
+        let state = router.daemon().state();
+        let Some(bfr_id) = state
+            .bifts
+            .iter()
+            .find(|bift| bift.sub_domain == self.sub_domain)
+            .map(|bift| bift.bfr_id)
+        else {
+            warn!(
+                "No BIFT in the sub-domain {} of the receivers",
+                self.sub_domain
+            );
+            return Ok(());
+        };
+        // The BFIR only answers with the acknowledgments of the channel.
+        let mut answer = [0u8; 512];
+        while let Ok(read) = self.upstream.recv(&mut answer) {
+            if let Err(e) = self.channel.recv(&answer[..read]) {
+                debug!("Ignoring an answer of the BFIR: {:?}", e);
+            }
+        }
+        let now = Instant::now();
+        self.retransmit(now)?;
+        if self.next_refresh <= now {
+            let joins: Vec<_> = self.memberships.keys().copied().collect();
+            for (group, source) in joins {
+                self.report(group, source, true, bfr_id);
+            }
+            self.next_refresh = now + self.refresh;
+        }
+        loop {
+            match crate::runtime::recv_datagram(&self.socket, &mut self.buffer) {
+                Ok((from, _)) => {
+                    let Some(receiver) = from.as_socket().map(|from| from.ip()) else {
+                        continue;
+                    };
+                    let message = std::mem::take(&mut self.buffer);
+                    self.handle_report(&message, receiver, bfr_id);
+                    self.buffer = message;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Membership socket of a BFIR, receiving the `join` and `leave` requests of the MLD/IGMP
+/// listeners of the allowed BFERs. The overlay is updated in place, without compiling the
+/// BIFTs again, so that a burst of requests does not stall the forwarding.
+pub struct MembershipServer {
+    socket: UdpSocket,
+    /// Addresses of the BFERs allowed to send requests.
+    allowed: BTreeSet<IpAddr>,
+    hold_time: Duration,
+    /// Expiration of the joins of the BFERs, by (group, source, BFR-id). The bits set by
+    /// the configuration or the update socket do not expire.
+    joins: BTreeMap<(IpAddr, Option<IpAddr>, u64), Instant>,
+    /// Channel with each BFER and the time of its last datagram. The channel of a BFER
+    /// silent during the hold time is dropped.
+    peers: BTreeMap<SocketAddr, (SyncChannel, Instant)>,
+    next_check: Instant,
+}
+
+impl MembershipServer {
+    /// Membership socket accepting the requests of `allowed` on `socket`, whose joins expire
+    /// after `hold_time` without refresh. The socket is set non-blocking.
+    pub fn new(
+        socket: UdpSocket,
+        allowed: impl IntoIterator<Item = IpAddr>,
+        hold_time: Duration,
+    ) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        Ok(MembershipServer {
+            socket,
+            allowed: allowed
+                .into_iter()
+                .map(|address| address.to_canonical())
+                .collect(),
+            hold_time,
+            joins: BTreeMap::new(),
+            peers: BTreeMap::new(),
+            next_check: Instant::now() + EXPIRATION_CHECK,
+        })
+    }
+
+    /// Answers the request of an allowed BFER received at `now`. Only the `join` and
+    /// `leave` requests are accepted.
+    pub fn handle_request(
+        &mut self,
+        daemon: &mut BierDaemon,
+        request: &[u8],
+        now: Instant,
+    ) -> ControlResponse {
+        match serde_json::from_slice::<ControlRequest>(request) {
+            Ok(ControlRequest::Join {
+                group,
+                source,
+                sub_domain,
+                bfr_id,
+            }) => match daemon.join_group(group, source, sub_domain, bfr_id) {
+                Ok(joined) => {
+                    let key = (group, source, bfr_id);
+                    if joined || self.joins.contains_key(&key) {
+                        self.joins.insert(key, now + self.hold_time);
+                    }
+                    ControlResponse::Updated(usize::from(joined))
+                }
+                Err(e) => ControlResponse::Error(e.to_string()),
+            },
+            Ok(ControlRequest::Leave {
+                group,
+                source,
+                bfr_id,
+            }) => {
+                self.joins.remove(&(group, source, bfr_id));
+                ControlResponse::Updated(usize::from(daemon.leave_group(group, source, bfr_id)))
+            }
+            Ok(_) => ControlResponse::Error(
+                "Only the join and leave requests are accepted on the membership socket".into(),
+            ),
+            Err(e) => ControlResponse::Error(e.to_string()),
+        }
+    }
+
+    /// Handles a datagram of the channel with the allowed BFER `from`, received at `now`, and
+    /// returns the acknowledgment to send back. The requests are applied once and in order,
+    /// those received ahead of a lost one wait for its retransmission.
+    pub fn handle_datagram(
+        &mut self,
+        daemon: &mut BierDaemon,
+        from: SocketAddr,
+        datagram: &[u8],
+        now: Instant,
+    ) -> Result<Option<Vec<u8>>> {
+        let (channel, last_seen) = self
+            .peers
+            .entry(from)
+            .or_insert_with(|| (SyncChannel::default(), now));
+        *last_seen = now;
+        let received = channel.recv(datagram)?;
+        for request in received.delivered {
+            if let ControlResponse::Error(e) = self.handle_request(daemon, &request, now) {
+                debug!("Cannot apply the membership request of {}: {}", from, e);
+            }
+        }
+        Ok(received.ack)
+    }
+
+    /// Clears the bits of the joins not refreshed since the hold time at `now`.
+    pub fn expire(&mut self, daemon: &mut BierDaemon, now: Instant) {
+        let hold_time = self.hold_time;
+        self.peers
+            .retain(|_, (_, last_seen)| *last_seen + hold_time > now);
+        let expired: Vec<_> = self
+            .joins
+            .iter()
+            .filter(|(_, expiration)| **expiration <= now)
+            .map(|(key, _)| *key)
+            .collect();
+        for (group, source, bfr_id) in expired {
+            self.joins.remove(&(group, source, bfr_id));
+            if daemon.leave_group(group, source, bfr_id) {
+                info!(
+                    "The join of ({:?}, {}) by the BFER {} expired",
+                    source, group, bfr_id
+                );
+            }
+        }
+    }
+}
+
+impl EventSource for MembershipServer {
+    fn fd(&self) -> Option<RawFd> {
+        Some(self.socket.as_raw_fd())
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        Some(self.next_check)
+    }
+
+    fn handle(&mut self, router: &mut Router) -> io::Result<()> {
+        let now = Instant::now();
+        // Longer than a request, to detect the longer datagrams.
+        let mut buffer = [0u8; MAX_REQUEST_SIZE + 1];
+        loop {
+            let (read, from) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    debug!("Cannot receive a membership request: {}", e);
+                    continue;
+                }
+            };
+            if !self.allowed.contains(&from.ip().to_canonical()) {
+                debug!("Ignoring a membership request of {}", from);
+                continue;
+            }
+            if read > MAX_REQUEST_SIZE {
+                debug!("Ignoring a membership request too long from {}", from);
+                continue;
+            }
+            let ack = match self.handle_datagram(router.daemon_mut(), from, &buffer[..read], now) {
+                Ok(ack) => ack,
+                Err(e) => {
+                    debug!("Ignoring a membership datagram of {}: {:?}", from, e);
+                    continue;
+                }
+            };
+            if let Some(Err(e)) = ack.map(|ack| self.socket.send_to(&ack, from)) {
+                debug!(
+                    "Cannot acknowledge the membership request of {}: {}",
+                    from, e
+                );
+            }
+        }
+        if self.next_check <= now {
+            self.expire(router.daemon_mut(), now);
+            self.next_check = now + EXPIRATION_CHECK;
+        }
+        Ok(())
+    }
+}
+
+/// Non-blocking UDP socket connected to `upstream`, on an ephemeral port.
+fn connect(upstream: SocketAddr) -> io::Result<UdpSocket> {
+    let local: SocketAddr = match upstream {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(upstream)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+fn if_index(ifname: &str) -> io::Result<u32> {
+    let name = CString::new(ifname)?;
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No interface {}", ifname),
+        )),
+        index => Ok(index),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::daemon::MemoryTransport;
+
+    /// Report of `report_type` with the records (type, group, sources).
+    fn report(report_type: u8, records: &[(u8, IpAddr, &[IpAddr])]) -> Vec<u8> {
+        let mut message = vec![report_type, 0, 0, 0, 0, 0];
+        message.extend((records.len() as u16).to_be_bytes());
+        let octets = |address: &IpAddr| match address {
+            IpAddr::V4(address) => address.octets().to_vec(),
+            IpAddr::V6(address) => address.octets().to_vec(),
+        };
+        for (record_type, group, sources) in records {
+            message.extend([*record_type, 0]);
+            message.extend((sources.len() as u16).to_be_bytes());
+            message.extend(octets(group));
+            sources
+                .iter()
+                .for_each(|source| message.extend(octets(source)));
+        }
+        message
+    }
+
+    #[test]
+    /// Tests the memberships of the records of the MLDv2 and IGMPv3 reports.
+    fn test_parse_reports() {
+        let group: IpAddr = "ff3e::1".parse().unwrap();
+        let s1: IpAddr = "fc00:a::1".parse().unwrap();
+        let s2: IpAddr = "fc00:b::1".parse().unwrap();
+        let join = |source| Membership {
+            group,
+            source,
+            join: true,
+        };
+        let leave = |source| Membership {
+            group,
+            source,
+            join: false,
+        };
+        let message = report(
+            MLDV2_REPORT,
+            &[
+                (CHANGE_TO_EXCLUDE, group, &[]),
+                (CHANGE_TO_INCLUDE, group, &[s1, s2]),
+                (BLOCK_OLD_SOURCES, group, &[s2]),
+                (MODE_IS_INCLUDE, group, &[]),
+            ],
+        );
+        assert_eq!(
+            parse_mld_report(&message),
+            Ok(vec![
+                join(None),
+                leave(None),
+                join(Some(s1)),
+                join(Some(s2)),
+                leave(Some(s2)),
+                leave(None)
+            ])
+        );
+        assert_eq!(
+            parse_mld_report(&message[..message.len() - 1]),
+            Err(Error::MembershipReport)
+        );
+        assert_eq!(parse_igmp_report(&message), Err(Error::MembershipReport));
+
+        let group = IpAddr::V4(Ipv4Addr::new(239, 1, 2, 3));
+        let source = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let message = report(IGMPV3_REPORT, &[(ALLOW_NEW_SOURCES, group, &[source])]);
+        assert_eq!(
+            parse_igmp_report(&message),
+            Ok(vec![Membership {
+                group,
+                source: Some(source),
+                join: true
+            }])
+        );
+
+        assert!(is_routed(group));
+        assert!(!is_routed(ALL_IGMPV3_ROUTERS.into()));
+        assert!(is_routed("ff3e::1".parse().unwrap()));
+        assert!(!is_routed(ALL_MLDV2_ROUTERS.into()));
+    }
+
+    #[test]
+    /// Tests the joins of the BFERs on the membership socket, only accepted from the allowed
+    /// addresses, and their expiration without refresh.
+    fn test_membership_server() {
+        let state = serde_json::from_str(include_str!("../configs/example.json")).unwrap();
+        let mut router = Router::new(
+            BierDaemon::builder()
+                .state(state)
+                .transport(MemoryTransport::default())
+                .build()
+                .unwrap(),
+        );
+        let group: IpAddr = "ff3e::1".parse().unwrap();
+        let lookup = |router: &Router| {
+            router
+                .daemon()
+                .state()
+                .overlay_lookup(group, group)
+                .map(|(_, bitstring)| bitstring.bitstring.clone())
+        };
+        let join = br#"{"command": "join", "group": "ff3e::1", "bfr_id": 2}"#;
+
+        // The requests of the other addresses are not answered.
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+        let mut server =
+            MembershipServer::new(socket, ["fc00::2".parse().unwrap()], DEFAULT_HOLD_TIME).unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut channel = SyncChannel::default();
+        let datagram = channel.send(join, Instant::now());
+        client.send_to(&datagram, address).unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        server.handle(&mut router).unwrap();
+        assert_eq!(lookup(&router), None);
+        let socket = server.socket;
+        let mut server = MembershipServer::new(socket, [localhost], DEFAULT_HOLD_TIME).unwrap();
+        server.handle(&mut router).unwrap();
+        client.send_to(&datagram, address).unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        server.handle(&mut router).unwrap();
+        let mut answer = [0u8; 512];
+        let read = client.recv(&mut answer).unwrap();
+        channel.recv(&answer[..read]).unwrap();
+        assert_eq!(channel.in_flight(), 0);
+        assert_eq!(lookup(&router), Some(vec![0b10]));
+
+        // Only the joins and leaves are accepted.
+        let start = Instant::now();
+        let daemon = router.daemon_mut();
+        let add_group = br#"{"command": "add_group",
+            "entry": {"group": "ff3e::2", "bitstring": "110"}}"#;
+        assert!(matches!(
+            server.handle_request(daemon, add_group, start),
+            ControlResponse::Error(_)
+        ));
+
+        // The refreshed join expires after the hold time, but not the bit of the BFER 3 set
+        // by the update socket.
+        daemon.join_group(group, None, 0, 3).unwrap();
+        let join_3 = br#"{"command": "join", "group": "ff3e::1", "bfr_id": 3}"#;
+        assert_eq!(
+            server.handle_request(daemon, join_3, start),
+            ControlResponse::Updated(0)
+        );
+        assert_eq!(
+            server.handle_request(daemon, join, start + DEFAULT_REFRESH),
+            ControlResponse::Updated(0)
+        );
+        server.expire(daemon, start + DEFAULT_HOLD_TIME);
+        assert_eq!(lookup(&router), Some(vec![0b110]));
+        let daemon = router.daemon_mut();
+        server.expire(daemon, start + DEFAULT_REFRESH + DEFAULT_HOLD_TIME);
+        assert_eq!(lookup(&router), Some(vec![0b100]));
+
+        let daemon = router.daemon_mut();
+        let leave = br#"{"command": "leave", "group": "ff3e::1", "bfr_id": 3}"#;
+        assert_eq!(
+            server.handle_request(daemon, leave, start),
+            ControlResponse::Updated(1)
+        );
+        assert_eq!(lookup(&router), None);
+    }
+
+    #[test]
+    /// Tests the requests of a BFER received through its sync channel: a request is applied
+    /// once, even if retransmitted, and the requests received after a lost one wait for its
+    /// retransmission.
+    fn test_membership_sync() {
+        let state = serde_json::from_str(include_str!("../configs/example.json")).unwrap();
+        let mut daemon = BierDaemon::builder()
+            .state(state)
+            .transport(MemoryTransport::default())
+            .build()
+            .unwrap();
+        let group: IpAddr = "ff3e::1".parse().unwrap();
+        let lookup = |daemon: &BierDaemon| {
+            daemon
+                .state()
+                .overlay_lookup(group, group)
+                .map(|(_, bitstring)| bitstring.bitstring.clone())
+        };
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+        let mut server = MembershipServer::new(socket, [localhost], DEFAULT_HOLD_TIME).unwrap();
+        let bfer: SocketAddr = "127.0.0.1:7000".parse().unwrap();
+        let start = Instant::now();
+        let mut channel = SyncChannel::default();
+        let join = br#"{"command": "join", "group": "ff3e::1", "bfr_id": 2}"#;
+        let leave = br#"{"command": "leave", "group": "ff3e::1", "bfr_id": 2}"#;
+
+        // The join is lost, the leave waits for its retransmission.
+        let lost = channel.send(join, start);
+        let datagram = channel.send(leave, start);
+        let ack = server.handle_datagram(&mut daemon, bfer, &datagram, start);
+        channel.recv(&ack.unwrap().unwrap()).unwrap();
+        assert_eq!(channel.in_flight(), 2);
+        assert_eq!(lookup(&daemon), None);
+        let retransmitted = channel
+            .poll(start + crate::sync_channel::DEFAULT_RTO)
+            .unwrap();
+        assert_eq!(retransmitted[0], lost);
+        let ack = server.handle_datagram(&mut daemon, bfer, &lost, start);
+        channel.recv(&ack.unwrap().unwrap()).unwrap();
+        assert_eq!(channel.in_flight(), 0);
+        assert_eq!(lookup(&daemon), None);
+
+        // The duplicate of the join is acknowledged, but does not override the leave.
+        let ack = server.handle_datagram(&mut daemon, bfer, &lost, start);
+        assert!(ack.unwrap().is_some());
+        assert_eq!(lookup(&daemon), None);
+        let datagram = channel.send(join, start);
+        server
+            .handle_datagram(&mut daemon, bfer, &datagram, start)
+            .unwrap();
+        assert_eq!(lookup(&daemon), Some(vec![0b10]));
+        assert_eq!(
+            server.handle_datagram(&mut daemon, bfer, &[0], start),
+            Err(Error::SyncMessage)
+        );
+
+        // The channel of a silent BFER is dropped with its joins, and a new channel of the
+        // BFER starts again from its first request.
+        server.expire(&mut daemon, start + DEFAULT_HOLD_TIME);
+        assert_eq!(lookup(&daemon), None);
+        let mut channel = SyncChannel::default();
+        let datagram = channel.send(join, start + DEFAULT_HOLD_TIME);
+        server
+            .handle_datagram(&mut daemon, bfer, &datagram, start + DEFAULT_HOLD_TIME)
+            .unwrap();
+        assert_eq!(lookup(&daemon), Some(vec![0b10]));
+    }
+}
@@ -0,0 +1,281 @@
+//! BIER OAM echo requests and replies, carried in BIER packets with the OAM proto
+//! (`header::PROTO_OAM`), following the BIER ping draft (draft-ietf-bier-ping).
+//!
+//! The daemon answers the echo requests for which this BFR is a BFER with an echo reply
+//! sent with the single bit of the BFIR, so that operators can check that the BFERs are
+//! reachable through the data plane. The replies are delivered to the local application
+//! of the OAM proto on the BFIR, e.g., `bier-ping`.
+//!
+//! Each message is made of the OAM header and of the echo fields, followed by TLVs:
+//! ```text
+//!  0                   1                   2                   3
+//!  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |Ver|  Message Type |   Proto   |           Reserved            |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |  QTF  |  RTF  |  Reply Mode   |  Return Code  | Return Subcode|
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |                        Sender's Handle                        |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |                        Sequence Number                        |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |                   Timestamp Sent (seconds)                    |
+//! |                 Timestamp Sent (microseconds)                 |
+//! |                 Timestamp Received (seconds)                  |
+//! |               Timestamp Received (microseconds)               |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! ```
+//! The timestamps are relative to the UNIX epoch. The only TLV is the Responder BFER TLV
+//! of the replies, with the BFR-id of the BFER (2 reserved bytes and the BFR-id).
+//! The other TLVs are skipped.
+
+use crate::{Error, Result};
+use std::time::{Duration, SystemTime};
+
+/// Version of the OAM messages.
+pub const OAM_VERSION: u8 = 1;
+
+/// Length of an echo request or reply, without TLV.
+pub const ECHO_LENGTH: usize = 32;
+
+/// Type of the TLV with the BFR-id of the BFER sending a reply.
+const TLV_RESPONDER_BFER: u16 = 4;
+
+/// Length of the type and length of a TLV.
+const TLV_HEADER_LENGTH: usize = 4;
+
+/// Length of the value of the Responder BFER TLV.
+const RESPONDER_BFER_LENGTH: usize = 4;
+
+/// Format of the timestamps: seconds and microseconds.
+const TIMESTAMP_FORMAT: u8 = 2;
+
+/// The reply is sent by a BFER of the bitstring of the request.
+pub const RETURN_CODE_BFER: u8 = 3;
+
+/// Type of an OAM message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OamMessageType {
+    EchoRequest = 1,
+    EchoReply = 2,
+}
+
+impl TryFrom<u8> for OamMessageType {
+    type Error = crate::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            1 => Ok(OamMessageType::EchoRequest),
+            2 => Ok(OamMessageType::EchoReply),
+            _ => Err(Error::OamMessage),
+        }
+    }
+}
+
+/// How the BFERs answer an echo request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ReplyMode {
+    /// The request is only processed, e.g., to measure one-way losses.
+    DoNotReply = 1,
+    /// Reply in a UDP datagram. Not supported by the daemon.
+    Udp = 2,
+    /// Reply in a BIER packet with the bit of the BFIR.
+    Bier = 3,
+}
+
+impl TryFrom<u8> for ReplyMode {
+    type Error = crate::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            1 => Ok(ReplyMode::DoNotReply),
+            2 => Ok(ReplyMode::Udp),
+            3 => Ok(ReplyMode::Bier),
+            _ => Err(Error::OamMessage),
+        }
+    }
+}
+
+/// Echo request or reply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EchoMessage {
+    pub message_type: OamMessageType,
+    pub reply_mode: ReplyMode,
+    pub return_code: u8,
+    pub return_subcode: u8,
+    /// Chosen by the sender to match its replies, e.g., its process identifier.
+    pub handle: u32,
+    pub sequence: u32,
+    /// Time since the UNIX epoch at which the request was sent.
+    pub sent: Duration,
+    /// Time since the UNIX epoch at which the request was received, zero in a request.
+    pub received: Duration,
+    /// BFR-id of the BFER sending a reply, in the Responder BFER TLV.
+    pub responder: Option<u16>,
+}
+
+impl EchoMessage {
+    /// Echo request sent at `now`.
+    pub fn request(handle: u32, sequence: u32, reply_mode: ReplyMode, now: SystemTime) -> Self {
+        EchoMessage {
+            message_type: OamMessageType::EchoRequest,
+            reply_mode,
+            return_code: 0,
+            return_subcode: 0,
+            handle,
+            sequence,
+            sent: since_epoch(now),
+            received: Duration::ZERO,
+            responder: None,
+        }
+    }
+
+    /// Reply of the BFER `responder` to this request, received at `now`.
+    pub fn reply(&self, return_code: u8, responder: u16, now: SystemTime) -> Self {
+        EchoMessage {
+            message_type: OamMessageType::EchoReply,
+            return_code,
+            received: since_epoch(now),
+            responder: Some(responder),
+            ..*self
+        }
+    }
+
+    /// Round-trip time of a reply received at `now`, if the clock did not go backward.
+    pub fn rtt(&self, now: SystemTime) -> Option<Duration> {
+        since_epoch(now).checked_sub(self.sent)
+    }
+
+    /// Length of the encoded message, with its TLVs.
+    pub fn length(&self) -> usize {
+        match self.responder {
+            Some(_) => ECHO_LENGTH + TLV_HEADER_LENGTH + RESPONDER_BFER_LENGTH,
+            None => ECHO_LENGTH,
+        }
+    }
+
+    pub fn from_slice(slice: &[u8]) -> Result<Self> {
+        if slice.len() < ECHO_LENGTH {
+            return Err(Error::OamMessage);
+        }
+        if slice[0] >> 6 != OAM_VERSION {
+            return Err(Error::OamMessage);
+        }
+        let word = |idx: usize| u32::from_be_bytes(slice[idx..idx + 4].try_into().unwrap());
+        let timestamp = |idx: usize| {
+            Duration::from_secs(word(idx) as u64) + Duration::from_micros(word(idx + 4) as u64)
+        };
+        let mut responder = None;
+        let mut tlvs = &slice[ECHO_LENGTH..];
+        while !tlvs.is_empty() {
+            if tlvs.len() < TLV_HEADER_LENGTH {
+                return Err(Error::OamMessage);
+            }
+            let tlv_type = u16::from_be_bytes([tlvs[0], tlvs[1]]);
+            let length = u16::from_be_bytes([tlvs[2], tlvs[3]]) as usize;
+            let value = tlvs
+                .get(TLV_HEADER_LENGTH..TLV_HEADER_LENGTH + length)
+                .ok_or(Error::OamMessage)?;
+            if tlv_type == TLV_RESPONDER_BFER && length == RESPONDER_BFER_LENGTH {
+                responder = Some(u16::from_be_bytes([value[2], value[3]]));
+            }
+            tlvs = &tlvs[TLV_HEADER_LENGTH + length..];
+        }
+        Ok(EchoMessage {
+            message_type: ((slice[0] << 2) | (slice[1] >> 6)).try_into()?,
+            reply_mode: slice[5].try_into()?,
+            return_code: slice[6],
+            return_subcode: slice[7],
+            handle: word(8),
+            sequence: word(12),
+            sent: timestamp(16),
+            received: timestamp(24),
+            responder,
+        })
+    }
+
+    pub fn to_slice(&self, slice: &mut [u8]) -> Result<usize> {
+        crate::check_length(slice, self.length())?;
+        let message_type = self.message_type as u8;
+        slice[0] = (OAM_VERSION << 6) | (message_type >> 2);
+        // No header after the OAM message.
+        slice[1] = message_type << 6;
+        slice[2..4].fill(0);
+        slice[4] = (TIMESTAMP_FORMAT << 4) | TIMESTAMP_FORMAT;
+        slice[5] = self.reply_mode as u8;
+        slice[6] = self.return_code;
+        slice[7] = self.return_subcode;
+        slice[8..12].copy_from_slice(&self.handle.to_be_bytes());
+        slice[12..16].copy_from_slice(&self.sequence.to_be_bytes());
+        for (idx, timestamp) in [(16, self.sent), (24, self.received)] {
+            slice[idx..idx + 4].copy_from_slice(&(timestamp.as_secs() as u32).to_be_bytes());
+            slice[idx + 4..idx + 8].copy_from_slice(&timestamp.subsec_micros().to_be_bytes());
+        }
+        if let Some(responder) = self.responder {
+            let tlv = &mut slice[ECHO_LENGTH..self.length()];
+            tlv[..2].copy_from_slice(&TLV_RESPONDER_BFER.to_be_bytes());
+            tlv[2..4].copy_from_slice(&(RESPONDER_BFER_LENGTH as u16).to_be_bytes());
+            tlv[4..6].fill(0);
+            tlv[6..8].copy_from_slice(&responder.to_be_bytes());
+        }
+        Ok(self.length())
+    }
+}
+
+fn since_epoch(time: SystemTime) -> Duration {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    /// Tests the encoding of the echo requests and of their replies.
+    fn test_echo_message() {
+        let sent = SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_000);
+        let request = EchoMessage::request(42, 7, ReplyMode::Bier, sent);
+        let mut buf = [0u8; ECHO_LENGTH];
+        assert_eq!(request.to_slice(&mut buf), Ok(ECHO_LENGTH));
+        assert_eq!(buf[0] >> 6, OAM_VERSION);
+        assert_eq!(EchoMessage::from_slice(&buf), Ok(request));
+
+        let received = sent + Duration::from_millis(3);
+        let reply = request.reply(RETURN_CODE_BFER, 3, received);
+        let mut buf = [0u8; 64];
+        let len = reply.to_slice(&mut buf).unwrap();
+        assert_eq!(len, reply.length());
+        let reply = EchoMessage::from_slice(&buf[..len]).unwrap();
+        assert_eq!(reply.message_type, OamMessageType::EchoReply);
+        assert_eq!((reply.handle, reply.sequence), (42, 7));
+        assert_eq!(reply.responder, Some(3));
+        // Truncated TLV.
+        assert_eq!(
+            EchoMessage::from_slice(&buf[..len - 1]),
+            Err(Error::OamMessage)
+        );
+        assert_eq!(reply.received - reply.sent, Duration::from_millis(3));
+        assert_eq!(
+            reply.rtt(received + Duration::from_millis(2)),
+            Some(Duration::from_millis(5))
+        );
+
+        assert_eq!(
+            EchoMessage::from_slice(&buf[..ECHO_LENGTH - 1]),
+            Err(Error::OamMessage)
+        );
+        buf[5] = 42;
+        assert_eq!(EchoMessage::from_slice(&buf), Err(Error::OamMessage));
+        assert_eq!(
+            request.to_slice(&mut [0u8; 8]),
+            Err(Error::SliceWrongLength {
+                expected: request.length(),
+                actual: 8
+            })
+        );
+    }
+}
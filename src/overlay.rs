@@ -0,0 +1,202 @@
+//! Multicast overlay of the BFIR: the BFERs of each multicast group, i.e., the sub-domain
+//! and the bitstring of the BIER packets encapsulating the native multicast traffic of the
+//! group. The mapping is part of the configuration (`BierState::overlay`), and is updated at
+//! runtime through the update socket (see `control`).
+
+use crate::bier::{Bitstring, MAX_BITSTRING_WORDS};
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+/// BFERs of the traffic of a multicast group, from any source or from a single source.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct OverlayEntry {
+    pub group: IpAddr,
+    /// Source of an (S, G) entry. Any source if absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<IpAddr>,
+    #[serde(default)]
+    pub sub_domain: u8,
+    /// BFR-ids of the BFERs with receivers of the group, as a bitstring.
+    pub bitstring: Bitstring,
+}
+
+/// Mapping from the multicast groups, and optionally their source, to their BFERs.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(from = "Vec<OverlayEntry>", into = "Vec<OverlayEntry>")]
+pub struct Overlay {
+    entries: BTreeMap<(IpAddr, Option<IpAddr>), OverlayEntry>,
+}
+
+impl Overlay {
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &OverlayEntry> {
+        self.entries.values()
+    }
+
+    /// Adds `entry`, or replaces the entry with the same group and source, which is returned.
+    pub fn insert(&mut self, entry: OverlayEntry) -> Option<OverlayEntry> {
+        self.entries.insert((entry.group, entry.source), entry)
+    }
+
+    /// Removes the entry of `group` and `source`, the (*, G) entry if `source` is `None`.
+    pub fn remove(&mut self, group: IpAddr, source: Option<IpAddr>) -> Option<OverlayEntry> {
+        self.entries.remove(&(group, source))
+    }
+
+    /// Sets the bit of `bfr_id` in the entry of `group` and `source`, e.g., for a receiver
+    /// behind this BFER. The entry is created with a bitstring of `nb_words` words if absent,
+    /// and its bitstring is extended to the next valid length if it does not cover `bfr_id`.
+    /// Returns whether the bit was not yet set.
+    pub fn join(
+        &mut self,
+        group: IpAddr,
+        source: Option<IpAddr>,
+        sub_domain: u8,
+        bfr_id: u64,
+        nb_words: usize,
+    ) -> Result<bool> {
+        let position = bfr_id.checked_sub(1).ok_or(Error::BitstringLength)?;
+        let word = (position / 64) as usize;
+        let entry = self
+            .entries
+            .entry((group, source))
+            .or_insert_with(|| OverlayEntry {
+                group,
+                source,
+                sub_domain,
+                bitstring: Bitstring {
+                    bitstring: vec![0; nb_words.max(1)],
+                },
+            });
+        let words = &mut entry.bitstring.bitstring;
+        let length = words.len().max(word + 1).next_power_of_two();
+        if length > MAX_BITSTRING_WORDS {
+            return Err(Error::BitstringLength);
+        }
+        // The first word holds the most significant bits.
+        words.splice(0..0, std::iter::repeat_n(0, length - words.len()));
+        let idx = length - 1 - word;
+        let mask = 1 << (position % 64);
+        let joined = words[idx] & mask == 0;
+        words[idx] |= mask;
+        Ok(joined)
+    }
+
+    /// Clears the bit of `bfr_id` in the entry of `group` and `source`, which is removed
+    /// without bit set. Returns whether the bit was set.
+    pub fn leave(&mut self, group: IpAddr, source: Option<IpAddr>, bfr_id: u64) -> bool {
+        let Some(position) = bfr_id.checked_sub(1) else {
+            return false;
+        };
+        let Some(entry) = self.entries.get_mut(&(group, source)) else {
+            return false;
+        };
+        let words = &mut entry.bitstring.bitstring;
+        let Some(idx) = words.len().checked_sub(1 + (position / 64) as usize) else {
+            return false;
+        };
+        let mask = 1 << (position % 64);
+        let left = words[idx] & mask != 0;
+        words[idx] &= !mask;
+        if words.iter().all(|word| *word == 0) {
+            self.entries.remove(&(group, source));
+        }
+        left
+    }
+
+    /// Entry of a packet from `source` to `group`: the (S, G) entry, or else the (*, G) one.
+    pub fn lookup(&self, source: IpAddr, group: IpAddr) -> Option<&OverlayEntry> {
+        self.entries
+            .get(&(group, Some(source)))
+            .or_else(|| self.entries.get(&(group, None)))
+    }
+}
+
+impl From<Vec<OverlayEntry>> for Overlay {
+    fn from(entries: Vec<OverlayEntry>) -> Self {
+        let mut overlay = Overlay::default();
+        for entry in entries {
+            overlay.insert(entry);
+        }
+        overlay
+    }
+}
+
+impl From<Overlay> for Vec<OverlayEntry> {
+    fn from(overlay: Overlay) -> Self {
+        overlay.entries.into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    /// Tests the (S, G) and (*, G) lookups, and the updates of the mapping.
+    fn test_overlay() {
+        let mut overlay: Overlay = serde_json::from_str(
+            r#"[
+                {"group": "ff3e::1", "bitstring": "0110"},
+                {"group": "ff3e::1", "source": "fc00:a::1", "sub_domain": 1, "bitstring": "1"}
+            ]"#,
+        )
+        .unwrap();
+        let group: IpAddr = "ff3e::1".parse().unwrap();
+        let source: IpAddr = "fc00:a::1".parse().unwrap();
+        let other: IpAddr = "fc00:b::1".parse().unwrap();
+        assert_eq!(overlay.lookup(source, group).unwrap().sub_domain, 1);
+        assert_eq!(
+            overlay.lookup(other, group).unwrap().bitstring.bitstring,
+            [0b110]
+        );
+        assert!(overlay.lookup(source, other).is_none());
+
+        let removed = overlay.remove(group, None).unwrap();
+        assert!(overlay.lookup(other, group).is_none());
+        assert!(overlay.insert(removed.clone()).is_none());
+        assert_eq!(
+            overlay.insert(removed).map(|entry| entry.source),
+            Some(None)
+        );
+        assert_eq!(overlay.entries().count(), 2);
+
+        let json = serde_json::to_string(&overlay).unwrap();
+        assert_eq!(serde_json::from_str::<Overlay>(&json).unwrap(), overlay);
+    }
+
+    #[test]
+    /// Tests the bits set and cleared by the receivers of the groups.
+    fn test_overlay_join_leave() {
+        let mut overlay = Overlay::default();
+        let group: IpAddr = "ff3e::2".parse().unwrap();
+        assert_eq!(overlay.join(group, None, 0, 2, 1), Ok(true));
+        assert_eq!(overlay.join(group, None, 0, 2, 1), Ok(false));
+        assert_eq!(overlay.join(group, None, 0, 3, 1), Ok(true));
+        let entry = overlay.lookup(group, group).unwrap();
+        assert_eq!(entry.bitstring.bitstring, [0b110]);
+
+        // BFR-id 130 is in the third word: the bitstring grows to 4 words.
+        assert_eq!(overlay.join(group, None, 0, 130, 1), Ok(true));
+        let entry = overlay.lookup(group, group).unwrap();
+        assert_eq!(entry.bitstring.bitstring, [0, 0b10, 0, 0b110]);
+        assert_eq!(overlay.join(group, None, 0, 0, 1), Err(Error::BitstringLength));
+        assert_eq!(
+            overlay.join(group, None, 0, 4097, 1),
+            Err(Error::BitstringLength)
+        );
+
+        assert!(overlay.leave(group, None, 130));
+        assert!(!overlay.leave(group, None, 130));
+        assert!(!overlay.leave(group, None, 4000));
+        assert!(overlay.leave(group, None, 2));
+        assert!(overlay.leave(group, None, 3));
+        assert!(overlay.is_empty());
+    }
+}
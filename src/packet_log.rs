@@ -0,0 +1,193 @@
+//! Log of the forwarded packets with one JSON object per line, designed to be loaded
+//! in analysis tools (e.g., pandas or ClickHouse) after the measurements.
+//!
+//! The decision trace uses the same format to debug the replication: for each packet, the
+//! decision of each entry matched by its bitstring (see `TraceEntry`).
+
+use crate::bier::{Bitstring, Decision};
+use crate::header::BierHeader;
+use crate::stats::DropReason;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PacketSource {
+    /// BIER packet received from a neighbouring BFR.
+    Network,
+    /// Payload sent by a local application.
+    Application,
+}
+
+/// Copy of the packet created by the BFR.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct LoggedCopy {
+    /// `None` for the local copy.
+    pub next_hop: Option<IpAddr>,
+    pub bitstring: Bitstring,
+    /// Whether the copy was sent to the next hop or delivered locally.
+    pub sent: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PacketLogEntry<'a> {
+    /// Seconds since the UNIX epoch.
+    pub timestamp: f64,
+    pub source: PacketSource,
+    /// Length of the BIER packet, or of the payload of the application if it is dropped.
+    pub length: usize,
+    /// Header of the packet as received, `None` if it cannot be parsed.
+    pub header: Option<&'a BierHeader>,
+    pub copies: Vec<LoggedCopy>,
+    /// Reason why the whole packet is dropped, if so.
+    pub drop: Option<DropReason>,
+}
+
+/// Seconds since the UNIX epoch.
+fn timestamp() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+impl PacketLogEntry<'_> {
+    pub fn now(source: PacketSource, length: usize) -> Self {
+        PacketLogEntry {
+            timestamp: timestamp(),
+            source,
+            length,
+            header: None,
+            copies: Vec::new(),
+            drop: None,
+        }
+    }
+}
+
+/// Decisions of the BIER processing of a packet, written in the decision trace.
+#[derive(Serialize, Debug)]
+pub struct TraceEntry<'a> {
+    /// Seconds since the UNIX epoch.
+    pub timestamp: f64,
+    pub source: PacketSource,
+    pub bift_id: u32,
+    pub entropy: u32,
+    /// Bitstring of the packet, after clearing the unknown bits if configured.
+    pub bitstring: &'a Bitstring,
+    /// Matched BIFT entries in the order of their BFR-id, with the F-BM of the chosen path
+    /// and the bitstring of the copy, see `BierState::explain`.
+    pub decisions: Vec<Decision>,
+    /// Error of the processing, e.g., a bit without entry, in which case the packet is
+    /// dropped and no decision is given.
+    pub error: Option<String>,
+}
+
+impl<'a> TraceEntry<'a> {
+    pub fn now(source: PacketSource, bift_id: u32, entropy: u32, bitstring: &'a Bitstring) -> Self {
+        TraceEntry {
+            timestamp: timestamp(),
+            source,
+            bift_id,
+            entropy,
+            bitstring,
+            decisions: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+/// Destination of the JSON lines of the packet log or of the decision trace.
+pub struct PacketLog {
+    writer: Box<dyn Write + Send>,
+}
+
+impl PacketLog {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        PacketLog {
+            writer: Box::new(writer),
+        }
+    }
+
+    /// Opens the destination of the log: `unix:<path>` or `tcp:<address>` for a stream socket,
+    /// or the path of a file, to which the lines are appended.
+    pub fn open(destination: &str) -> io::Result<Self> {
+        if let Some(path) = destination.strip_prefix("unix:") {
+            Ok(Self::new(std::os::unix::net::UnixStream::connect(path)?))
+        } else if let Some(address) = destination.strip_prefix("tcp:") {
+            Ok(Self::new(std::net::TcpStream::connect(address)?))
+        } else {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(destination)?;
+            // Flush each line, so that the log is complete even if the daemon is killed.
+            Ok(Self::new(io::LineWriter::new(file)))
+        }
+    }
+
+    pub fn write(&mut self, entry: &impl Serialize) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.writer.write_all(&line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serde_json::Value;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    /// Tests that each entry is written as a JSON line.
+    fn test_packet_log() {
+        let buffer = SharedBuffer::default();
+        let mut packet_log = PacketLog::new(buffer.clone());
+
+        let header =
+            BierHeader::from_slice(&crate::header::tests::get_dummy_bier_header_slice()).unwrap();
+        let mut entry = PacketLogEntry::now(PacketSource::Network, 20);
+        entry.header = Some(&header);
+        entry.copies.push(LoggedCopy {
+            next_hop: Some("fc00::b".parse().unwrap()),
+            bitstring: Bitstring::from_str("11").unwrap(),
+            sent: true,
+        });
+        packet_log.write(&entry).unwrap();
+
+        let mut entry = PacketLogEntry::now(PacketSource::Application, 3);
+        entry.drop = Some(DropReason::UnknownBift);
+        packet_log.write(&entry).unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["source"], "network");
+        assert_eq!(lines[0]["header"]["ttl"], 7);
+        assert_eq!(lines[0]["copies"][0]["next_hop"], "fc00::b");
+        assert_eq!(lines[0]["drop"], Value::Null);
+        assert!(lines[0]["timestamp"].as_f64().unwrap() > 0.0);
+        assert_eq!(lines[1]["header"], Value::Null);
+        assert_eq!(lines[1]["drop"], "unknown_bift");
+    }
+}
@@ -0,0 +1,548 @@
+//! Minimal reader of pcap files, to extract the BIER packets of captures, and writer of the
+//! captures of the daemon (see `BierCapture`).
+
+use crate::ethernet::{write_ethernet_header, MacAddr, ETHERNET_HEADER_LEN};
+use log::{error, warn};
+use std::io::{self, BufWriter, Read, Write};
+use std::net::IpAddr;
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_RAW: u32 = 101;
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+pub const LINKTYPE_IPV4: u32 = 228;
+pub const LINKTYPE_IPV6: u32 = 229;
+
+/// EtherType of BIER packets directly carried over Ethernet (RFC 8296).
+pub const ETHERTYPE_BIER: u16 = 0xab37;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+
+const PCAP_GLOBAL_HEADER_LENGTH: usize = 24;
+const PCAP_RECORD_HEADER_LENGTH: usize = 16;
+/// Largest captured frame.
+const PCAP_SNAPLEN: u32 = 65535;
+/// Largest snaplen accepted by the reader, the one of tcpdump, so that the length of a record
+/// does not allocate more.
+const PCAP_MAX_SNAPLEN: u32 = 262144;
+
+/// Number of frames waiting to be written by the thread of a `BierCapture`. The frames
+/// beyond are not captured, so that a slow disk or reader never blocks the forwarding.
+const CAPTURE_QUEUE_DEPTH: usize = 4096;
+
+/// Captured frame, with its timestamp relative to the UNIX epoch.
+type CapturedFrame = (Duration, Vec<u8>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PcapPacket {
+    /// Capture time, relative to the UNIX epoch.
+    pub timestamp: Duration,
+    pub data: Vec<u8>,
+}
+
+pub struct PcapReader<R: Read> {
+    reader: R,
+    big_endian: bool,
+    nanosecond: bool,
+    linktype: u32,
+    /// Largest captured length of a record.
+    snaplen: u32,
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Reads the global header of the pcap file.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0u8; PCAP_GLOBAL_HEADER_LENGTH];
+        reader.read_exact(&mut header)?;
+
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let (big_endian, nanosecond) = match magic {
+            0xa1b2c3d4 => (false, false),
+            0xa1b23c4d => (false, true),
+            0xd4c3b2a1 => (true, false),
+            0x4d3cb2a1 => (true, true),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "not a pcap file",
+                ))
+            }
+        };
+
+        let mut pcap_reader = PcapReader {
+            reader,
+            big_endian,
+            nanosecond,
+            linktype: 0,
+            snaplen: 0,
+        };
+        pcap_reader.linktype = pcap_reader.read_u32(&header[20..24]);
+        // Some writers leave the snaplen unset.
+        pcap_reader.snaplen = match pcap_reader.read_u32(&header[16..20]) {
+            0 => PCAP_SNAPLEN,
+            snaplen => snaplen.min(PCAP_MAX_SNAPLEN),
+        };
+        Ok(pcap_reader)
+    }
+
+    pub fn linktype(&self) -> u32 {
+        self.linktype
+    }
+
+    fn read_u32(&self, slice: &[u8]) -> u32 {
+        let bytes = [slice[0], slice[1], slice[2], slice[3]];
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    /// Returns the next packet of the capture, or `None` at the end of the file. A record
+    /// longer than the snaplen, or whose timestamp has more than a second of microseconds
+    /// or nanoseconds, is invalid.
+    pub fn next_packet(&mut self) -> io::Result<Option<PcapPacket>> {
+        let mut header = [0u8; PCAP_RECORD_HEADER_LENGTH];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let seconds = self.read_u32(&header[..4]) as u64;
+        let fraction = self.read_u32(&header[4..8]);
+        let captured_length = self.read_u32(&header[8..12]);
+        let nanos = match self.nanosecond {
+            true if fraction < 1_000_000_000 => fraction,
+            false if fraction < 1_000_000 => fraction * 1000,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid timestamp",
+                ))
+            }
+        };
+        if captured_length > self.snaplen {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "record longer than the snaplen",
+            ));
+        }
+
+        let mut data = vec![0u8; captured_length as usize];
+        self.reader.read_exact(&mut data)?;
+
+        Ok(Some(PcapPacket {
+            timestamp: Duration::new(seconds, nanos),
+            data,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = io::Result<PcapPacket>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_packet().transpose()
+    }
+}
+
+/// Writer of pcap files, with microsecond timestamps.
+pub struct PcapWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Writes the global header of a capture of frames with `linktype`.
+    pub fn new(mut writer: W, linktype: u32) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(PCAP_GLOBAL_HEADER_LENGTH);
+        header.extend_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+        // Version 2.4, without time zone nor accuracy.
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&PCAP_SNAPLEN.to_le_bytes());
+        header.extend_from_slice(&linktype.to_le_bytes());
+        writer.write_all(&header)?;
+        writer.flush()?;
+        Ok(PcapWriter { writer })
+    }
+
+    /// Appends the frame `data` captured at `timestamp`, relative to the UNIX epoch. The
+    /// record is written at once, and reaches a buffered writer on `flush`.
+    pub fn write_packet(&mut self, timestamp: Duration, data: &[u8]) -> io::Result<()> {
+        let captured = &data[..data.len().min(PCAP_SNAPLEN as usize)];
+        let mut record = Vec::with_capacity(PCAP_RECORD_HEADER_LENGTH + captured.len());
+        record.extend_from_slice(&(timestamp.as_secs() as u32).to_le_bytes());
+        record.extend_from_slice(&timestamp.subsec_micros().to_le_bytes());
+        record.extend_from_slice(&(captured.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        record.extend_from_slice(captured);
+        self.writer.write_all(&record)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Synthetic MAC address of the BFR with `address`: the locally administered prefix
+/// `02:00` followed by the last 4 bytes of the address.
+pub fn synthetic_mac(address: IpAddr) -> MacAddr {
+    let octets = match address {
+        IpAddr::V4(address) => address.octets(),
+        IpAddr::V6(address) => {
+            let octets = address.octets();
+            [octets[12], octets[13], octets[14], octets[15]]
+        }
+    };
+    let [a, b, c, d] = octets;
+    MacAddr([0x02, 0, a, b, c, d])
+}
+
+/// Ethernet frame of the BIER `packet` with the ethertype 0xAB37 (RFC 8296) from `src` to
+/// `dst`, dissected as BIER by Wireshark.
+pub fn ethernet_frame(src: MacAddr, dst: MacAddr, packet: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0u8; ETHERNET_HEADER_LEN + packet.len()];
+    write_ethernet_header(&mut frame, dst, src, ETHERTYPE_BIER);
+    frame[ETHERNET_HEADER_LEN..].copy_from_slice(packet);
+    frame
+}
+
+/// Capture of the BIER packets received and sent by the daemon, in a pcap file of Ethernet
+/// frames. The frames carry the synthetic MAC addresses of the BFRs (see `synthetic_mac`):
+/// the next hop is the destination of the copies sent by the BFR, whose loopback is the
+/// destination of the received packets. The frames are written by a thread, with a buffer
+/// flushed once the queue of frames is empty.
+pub struct BierCapture {
+    /// Queue of the frames of the writer thread, closed on drop.
+    frames: Option<SyncSender<CapturedFrame>>,
+    writer: Option<JoinHandle<()>>,
+    /// Frames not captured because the queue was full.
+    skipped: u64,
+}
+
+impl BierCapture {
+    pub fn new(writer: impl Write + Send + 'static) -> io::Result<Self> {
+        let mut writer = PcapWriter::new(BufWriter::new(writer), LINKTYPE_ETHERNET)?;
+        let (frames, receiver) = mpsc::sync_channel(CAPTURE_QUEUE_DEPTH);
+        let writer = std::thread::Builder::new()
+            .name("bier-capture".to_string())
+            .spawn(move || {
+                if let Err(e) = write_frames(&mut writer, receiver) {
+                    error!("Error when writing the capture, stopping it: {:?}", e);
+                }
+            })?;
+        Ok(BierCapture {
+            frames: Some(frames),
+            writer: Some(writer),
+            skipped: 0,
+        })
+    }
+
+    /// Creates the capture file at `path`, or opens the FIFO at `path`, e.g., read by
+    /// `wireshark -k -i <path>`. Opening a FIFO blocks until its reader opens it.
+    pub fn open(path: &str) -> io::Result<Self> {
+        Self::new(std::fs::File::create(path)?)
+    }
+
+    /// Records the BIER `packet` received by the BFR with this `loopback`, from an unknown
+    /// previous hop.
+    pub fn received(&mut self, packet: &[u8], loopback: IpAddr) -> io::Result<()> {
+        self.write(ethernet_frame(
+            MacAddr::default(),
+            synthetic_mac(loopback),
+            packet,
+        ))
+    }
+
+    /// Records the copy `packet` sent by the BFR with this `loopback` to `next_hop`.
+    pub fn sent(&mut self, packet: &[u8], loopback: IpAddr, next_hop: IpAddr) -> io::Result<()> {
+        self.write(ethernet_frame(
+            synthetic_mac(loopback),
+            synthetic_mac(next_hop),
+            packet,
+        ))
+    }
+
+    /// Queues the frame for the writer thread. Fails once the thread stopped on an error.
+    fn write(&mut self, frame: Vec<u8>) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let frames = self.frames.as_ref().ok_or(io::ErrorKind::BrokenPipe)?;
+        match frames.try_send((timestamp, frame)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.skipped += 1;
+                if self.skipped.is_power_of_two() {
+                    warn!(
+                        "The capture is too slow, {} frames not captured",
+                        self.skipped
+                    );
+                }
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+}
+
+impl Drop for BierCapture {
+    /// Waits for the queued frames to be written.
+    fn drop(&mut self) {
+        self.frames = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Writes the `frames` until the `BierCapture` is dropped, and flushes the buffer of `writer`
+/// whenever no frame is waiting, so that a reader of a FIFO sees the frames without delay.
+fn write_frames<W: Write>(
+    writer: &mut PcapWriter<W>,
+    frames: Receiver<CapturedFrame>,
+) -> io::Result<()> {
+    loop {
+        let (timestamp, frame) = match frames.try_recv() {
+            Ok(frame) => frame,
+            Err(TryRecvError::Empty) => {
+                writer.flush()?;
+                match frames.recv() {
+                    Ok(frame) => frame,
+                    Err(_) => return Ok(()),
+                }
+            }
+            Err(TryRecvError::Disconnected) => return writer.flush(),
+        };
+        writer.write_packet(timestamp, &frame)?;
+    }
+}
+
+/// Returns the BIER packet (starting with the BIER header) carried by a captured frame,
+/// either directly over Ethernet or over IPv4/IPv6 with the IP protocol `proto`, e.g.,
+/// `SocketConfig::proto`.
+pub fn bier_payload(linktype: u32, data: &[u8], proto: u8) -> Option<&[u8]> {
+    match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]);
+            while ethertype == ETHERTYPE_VLAN {
+                offset += 4;
+                ethertype = u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]);
+            }
+            from_ethertype(ethertype, data.get(offset + 2..)?, proto)
+        }
+        LINKTYPE_LINUX_SLL => {
+            let ethertype = u16::from_be_bytes([*data.get(14)?, *data.get(15)?]);
+            from_ethertype(ethertype, data.get(16..)?, proto)
+        }
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => from_ip(data, proto),
+        _ => None,
+    }
+}
+
+fn from_ethertype(ethertype: u16, data: &[u8], proto: u8) -> Option<&[u8]> {
+    match ethertype {
+        ETHERTYPE_BIER => Some(data),
+        ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => from_ip(data, proto),
+        _ => None,
+    }
+}
+
+fn from_ip(data: &[u8], proto: u8) -> Option<&[u8]> {
+    match data.first()? >> 4 {
+        4 => {
+            let header_length = ((data[0] & 0x0f) as usize) * 4;
+            (*data.get(9)? == proto).then(|| data.get(header_length..))?
+        }
+        // Extension headers are not supported.
+        6 => (*data.get(6)? == proto).then(|| data.get(40..))?,
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::bier::DEFAULT_IP_PROTO;
+
+    fn get_pcap(linktype: u32, packets: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        let mut pcap = Vec::new();
+        pcap.extend_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+        pcap.extend_from_slice(&2u16.to_le_bytes());
+        pcap.extend_from_slice(&4u16.to_le_bytes());
+        pcap.extend_from_slice(&[0; 8]);
+        pcap.extend_from_slice(&65535u32.to_le_bytes());
+        pcap.extend_from_slice(&linktype.to_le_bytes());
+        for (seconds, micros, data) in packets {
+            pcap.extend_from_slice(&seconds.to_le_bytes());
+            pcap.extend_from_slice(&micros.to_le_bytes());
+            pcap.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            pcap.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            pcap.extend_from_slice(data);
+        }
+        pcap
+    }
+
+    #[test]
+    /// Tests the reading of the packets of a pcap file.
+    fn test_pcap_reader() {
+        let pcap = get_pcap(LINKTYPE_RAW, &[(1, 500, &[1, 2, 3]), (2, 0, &[4])]);
+        let reader = PcapReader::new(&pcap[..]).unwrap();
+        assert_eq!(reader.linktype(), LINKTYPE_RAW);
+
+        let packets: Vec<_> = reader.map(|p| p.unwrap()).collect();
+        assert_eq!(
+            packets,
+            vec![
+                PcapPacket {
+                    timestamp: Duration::new(1, 500_000),
+                    data: vec![1, 2, 3]
+                },
+                PcapPacket {
+                    timestamp: Duration::new(2, 0),
+                    data: vec![4]
+                },
+            ]
+        );
+
+        // Truncated record.
+        let mut reader = PcapReader::new(&pcap[..pcap.len() - 1]).unwrap();
+        assert!(reader.next_packet().unwrap().is_some());
+        assert!(reader.next_packet().is_err());
+
+        assert!(PcapReader::new(&[0u8; 24][..]).is_err());
+
+        // Invalid record headers, from an untrusted file.
+        let pcap = get_pcap(LINKTYPE_RAW, &[(1, u32::MAX, &[1])]);
+        let mut reader = PcapReader::new(&pcap[..]).unwrap();
+        assert_eq!(
+            reader.next_packet().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        let mut pcap = get_pcap(LINKTYPE_RAW, &[(1, 0, &[1])]);
+        pcap[32..36].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut reader = PcapReader::new(&pcap[..]).unwrap();
+        assert_eq!(
+            reader.next_packet().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    /// Tests the extraction of the BIER packet of the captured frames.
+    fn test_bier_payload() {
+        let bier = [0xaa; 20];
+
+        let mut ipv6 = vec![0x60, 0, 0, 0, 0, 20, DEFAULT_IP_PROTO, 64];
+        ipv6.extend_from_slice(&[0; 32]);
+        ipv6.extend_from_slice(&bier);
+        assert_eq!(
+            bier_payload(LINKTYPE_RAW, &ipv6, DEFAULT_IP_PROTO),
+            Some(&bier[..])
+        );
+        assert_eq!(
+            bier_payload(LINKTYPE_IPV6, &ipv6, DEFAULT_IP_PROTO),
+            Some(&bier[..])
+        );
+
+        let mut ipv4 = vec![0x45, 0, 0, 40, 0, 0, 0, 0, 64, DEFAULT_IP_PROTO];
+        ipv4.extend_from_slice(&[0; 10]);
+        ipv4.extend_from_slice(&bier);
+        assert_eq!(
+            bier_payload(LINKTYPE_RAW, &ipv4, DEFAULT_IP_PROTO),
+            Some(&bier[..])
+        );
+
+        let mut ethernet = vec![0; 12];
+        ethernet.extend_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+        ethernet.extend_from_slice(&ipv6);
+        assert_eq!(
+            bier_payload(LINKTYPE_ETHERNET, &ethernet, DEFAULT_IP_PROTO),
+            Some(&bier[..])
+        );
+
+        let mut vlan = vec![0; 12];
+        vlan.extend_from_slice(&ETHERTYPE_VLAN.to_be_bytes());
+        vlan.extend_from_slice(&[0, 10]);
+        vlan.extend_from_slice(&ETHERTYPE_BIER.to_be_bytes());
+        vlan.extend_from_slice(&bier);
+        assert_eq!(
+            bier_payload(LINKTYPE_ETHERNET, &vlan, DEFAULT_IP_PROTO),
+            Some(&bier[..])
+        );
+
+        // Another IP protocol, e.g., the `--proto` of the BFRs.
+        ipv6[6] = 254;
+        assert_eq!(bier_payload(LINKTYPE_RAW, &ipv6, 254), Some(&bier[..]));
+        assert_eq!(bier_payload(LINKTYPE_RAW, &ipv6, DEFAULT_IP_PROTO), None);
+
+        // Not BIER.
+        ipv6[6] = 17;
+        assert_eq!(bier_payload(LINKTYPE_RAW, &ipv6, DEFAULT_IP_PROTO), None);
+        assert_eq!(
+            bier_payload(LINKTYPE_ETHERNET, &[0; 10], DEFAULT_IP_PROTO),
+            None
+        );
+    }
+
+    #[test]
+    /// Tests that the written captures are read back, with the BIER packets of the frames.
+    fn test_pcap_writer() {
+        let bier = [0xaa; 20];
+        let loopback: IpAddr = "fc00:a::1".parse().unwrap();
+        let next_hop: IpAddr = "10.0.0.2".parse().unwrap();
+        let mut pcap = Vec::new();
+        let mut writer = PcapWriter::new(&mut pcap, LINKTYPE_ETHERNET).unwrap();
+        let (src, dst) = (synthetic_mac(loopback), synthetic_mac(next_hop));
+        assert_eq!(src.to_string(), "02:00:00:00:00:01");
+        assert_eq!(dst.to_string(), "02:00:0a:00:00:02");
+        let frame = ethernet_frame(src, dst, &bier);
+        writer
+            .write_packet(Duration::new(1, 500_000), &frame)
+            .unwrap();
+        writer.write_packet(Duration::new(2, 0), &frame).unwrap();
+        writer.flush().unwrap();
+
+        let reader = PcapReader::new(&pcap[..]).unwrap();
+        assert_eq!(reader.linktype(), LINKTYPE_ETHERNET);
+        let packets: Vec<_> = reader.map(|p| p.unwrap()).collect();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].timestamp, Duration::new(1, 500_000));
+        assert_eq!(&packets[0].data[..6], &dst.0);
+        assert_eq!(&packets[0].data[6..12], &src.0);
+        assert_eq!(&packets[0].data[12..14], &ETHERTYPE_BIER.to_be_bytes());
+        assert_eq!(
+            bier_payload(LINKTYPE_ETHERNET, &packets[1].data, DEFAULT_IP_PROTO),
+            Some(&bier[..])
+        );
+    }
+
+    #[test]
+    /// Tests that the frames queued for the writer thread are written once the capture is
+    /// dropped.
+    fn test_bier_capture() {
+        let path = std::env::temp_dir().join(format!("bier-pcap-{}", std::process::id()));
+        let mut capture = BierCapture::open(path.to_str().unwrap()).unwrap();
+        let loopback: IpAddr = "fc00:a::1".parse().unwrap();
+        for i in 0..100 {
+            capture.sent(&[i; 20], loopback, loopback).unwrap();
+        }
+        drop(capture);
+
+        let reader = PcapReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let frames: Vec<_> = reader.map(|packet| packet.unwrap().data).collect();
+        assert_eq!(frames.len(), 100);
+        assert_eq!(
+            bier_payload(LINKTYPE_ETHERNET, &frames[99], DEFAULT_IP_PROTO),
+            Some(&[99; 20][..])
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,424 @@
+//! Pipelined processing: the packets are received, processed and transmitted by different
+//! threads connected by bounded queues, so that a slow neighbor does not stall the reception
+//! of the packets or the copies toward the other neighbors.
+//!
+//! * `RxStage` receives the BIER packets on a dedicated thread. It is registered with the
+//!   event loop, which processes the queued packets with the `Router`.
+//! * `TxStage` is the transport of the daemon. Each neighbor has its own queue and thread.
+//!
+//! A packet arriving on a full queue is dropped and recorded as `DropReason::QueueFull`.
+//! The buffers of the packets are recycled between the stages.
+//! A packet larger than the buffers is dropped and recorded as `DropReason::Truncated`.
+
+use crate::daemon::Transport;
+use crate::router::{Framing, Router};
+use crate::runtime::{self, EventSource};
+use crate::stats::DropReason;
+use log::debug;
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::Arc;
+
+/// Default depth of the queues.
+pub const DEFAULT_QUEUE_DEPTH: usize = 1024;
+
+/// Non-blocking eventfd signaling the packets pushed in the queue of the RX stage.
+struct EventFd(OwnedFd);
+
+impl EventFd {
+    fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(EventFd(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    fn notify(&self) -> io::Result<()> {
+        let value = 1u64.to_ne_bytes();
+        let res = unsafe { libc::write(self.0.as_raw_fd(), value.as_ptr() as *const _, 8) };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Resets the counter of the eventfd.
+    fn reset(&self) {
+        let mut value = [0u8; 8];
+        unsafe { libc::read(self.0.as_raw_fd(), value.as_mut_ptr() as *mut _, 8) };
+    }
+}
+
+/// Receives the packets of `socket` on a dedicated thread, and queues them for the event loop.
+pub struct RxStage {
+    queue: Receiver<Vec<u8>>,
+    /// Framing of the packets of the socket, e.g., `Framing::Bier` for the UDP socket.
+    framing: Framing,
+    /// Buffers returned to the thread once processed.
+    free: Sender<Vec<u8>>,
+    event: Arc<EventFd>,
+    /// Packets dropped by the thread because the queue was full.
+    dropped: Arc<AtomicU64>,
+    /// Drops already recorded in the statistics of the daemon.
+    recorded: u64,
+    /// Packets dropped by the thread because they were larger than the buffers.
+    truncated: Arc<AtomicU64>,
+    recorded_truncated: u64,
+    max_packet_size: usize,
+}
+
+impl RxStage {
+    /// Spawns the thread receiving the packets with `framing` on the blocking `socket`, with at
+    /// most `depth` queued packets. Packets larger than `max_packet_size` are dropped.
+    pub fn spawn(
+        socket: socket2::Socket,
+        framing: Framing,
+        depth: usize,
+        max_packet_size: usize,
+    ) -> io::Result<Self> {
+        let (sender, queue) = mpsc::sync_channel(depth);
+        let (free, free_receiver) = mpsc::channel::<Vec<u8>>();
+        let event = Arc::new(EventFd::new()?);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let truncated = Arc::new(AtomicU64::new(0));
+
+        let thread_event = event.clone();
+        let thread_dropped = dropped.clone();
+        let thread_truncated = truncated.clone();
+        std::thread::Builder::new()
+            .name("bier-rx".into())
+            .spawn(move || {
+                rx_thread(
+                    socket,
+                    sender,
+                    free_receiver,
+                    &thread_event,
+                    &thread_dropped,
+                    &thread_truncated,
+                    max_packet_size,
+                )
+            })?;
+
+        Ok(RxStage {
+            queue,
+            framing,
+            free,
+            event,
+            dropped,
+            recorded: 0,
+            truncated,
+            recorded_truncated: 0,
+            max_packet_size,
+        })
+    }
+}
+
+fn rx_thread(
+    socket: socket2::Socket,
+    queue: SyncSender<Vec<u8>>,
+    free: Receiver<Vec<u8>>,
+    event: &EventFd,
+    dropped: &AtomicU64,
+    truncated: &AtomicU64,
+    max_packet_size: usize,
+) {
+    let mut buffer = Vec::with_capacity(max_packet_size);
+    loop {
+        match runtime::recv_datagram(&socket, &mut buffer) {
+            Ok((_, false)) => (),
+            Ok((_, true)) => {
+                // Reported by the event loop, with the packets of the queue.
+                truncated.fetch_add(1, Ordering::Relaxed);
+                if let Err(e) = event.notify() {
+                    debug!("Error when notifying the event loop: {:?}", e);
+                }
+                continue;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            // Only the errors of the socket itself stop the stage, not the errors of a
+            // datagram, e.g., an ICMP error reported on the UDP socket.
+            Err(e) if crate::runtime::is_socket_error(&e) => {
+                log::error!(
+                    "Error when receiving a BIER packet, stopping the RX stage: {:?}",
+                    e
+                );
+                return;
+            }
+            Err(e) => {
+                debug!("Error when receiving a BIER packet: {:?}, continuing...", e);
+                continue;
+            }
+        }
+        match queue.try_send(buffer) {
+            Ok(()) => {
+                if let Err(e) = event.notify() {
+                    debug!("Error when notifying the event loop: {:?}", e);
+                }
+                buffer = free
+                    .try_recv()
+                    .unwrap_or_else(|_| Vec::with_capacity(max_packet_size));
+            }
+            Err(TrySendError::Full(full)) => {
+                dropped.fetch_add(1, Ordering::Relaxed);
+                buffer = full;
+            }
+            Err(TrySendError::Disconnected(_)) => return,
+        }
+    }
+}
+
+impl EventSource for RxStage {
+    fn fd(&self) -> Option<RawFd> {
+        Some(self.event.0.as_raw_fd())
+    }
+
+    fn handle(&mut self, router: &mut Router) -> io::Result<()> {
+        self.event.reset();
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        for _ in self.recorded..dropped {
+            router.daemon_mut().record_drop(DropReason::QueueFull);
+        }
+        self.recorded = dropped;
+        let truncated = self.truncated.load(Ordering::Relaxed);
+        for _ in self.recorded_truncated..truncated {
+            runtime::report_truncated(router, self.max_packet_size);
+        }
+        self.recorded_truncated = truncated;
+
+        while let Ok(mut packet) = self.queue.try_recv() {
+            if let Err(e) = router.handle_packet(self.framing, &mut packet) {
+                debug!(
+                    "Error when processing the BIER packet: {:?}, continuing...",
+                    e
+                );
+            }
+            let _ = self.free.send(packet);
+        }
+        Ok(())
+    }
+}
+
+/// Creates the transport of the thread of a neighbor, given its next hop.
+type TransportFactory = Box<dyn Fn(IpAddr) -> io::Result<Box<dyn Transport + Send>> + Send>;
+
+/// Transport queuing the copies toward each neighbor, sent by a thread per neighbor.
+/// The copies are reported as sent once queued: the errors of the threads are only logged.
+pub struct TxStage {
+    depth: usize,
+    queues: HashMap<IpAddr, SyncSender<Vec<u8>>>,
+    make_transport: TransportFactory,
+    free: Receiver<Vec<u8>>,
+    /// Given to the threads to return the buffers once sent.
+    free_sender: Sender<Vec<u8>>,
+}
+
+impl TxStage {
+    /// Queues at most `depth` copies per neighbor. The thread of each neighbor sends
+    /// the copies with its own transport, created by `make_transport` for its next hop, e.g.,
+    /// with a socket bound to the interface of the neighbor.
+    pub fn new<T, F>(depth: usize, make_transport: F) -> Self
+    where
+        T: Transport + Send + 'static,
+        F: Fn(IpAddr) -> io::Result<T> + Send + 'static,
+    {
+        let (free_sender, free) = mpsc::channel();
+        TxStage {
+            depth,
+            queues: HashMap::new(),
+            make_transport: Box::new(move |next_hop| {
+                make_transport(next_hop).map(|t| Box::new(t) as Box<dyn Transport + Send>)
+            }),
+            free,
+            free_sender,
+        }
+    }
+
+    fn spawn(&self, next_hop: IpAddr) -> io::Result<SyncSender<Vec<u8>>> {
+        let mut transport = (self.make_transport)(next_hop)?;
+        let (sender, queue) = mpsc::sync_channel::<Vec<u8>>(self.depth);
+        let free = self.free_sender.clone();
+        std::thread::Builder::new()
+            .name(format!("bier-tx-{}", next_hop))
+            .spawn(move || {
+                for packet in queue {
+                    if let Err(e) = transport.send_to(&packet, next_hop) {
+                        debug!(
+                            "Error when sending the packet to {:?}. Error is: {:?}, continuing...",
+                            next_hop, e
+                        );
+                    }
+                    let _ = free.send(packet);
+                }
+            })?;
+        Ok(sender)
+    }
+}
+
+impl Transport for TxStage {
+    fn send_to(&mut self, packet: &[u8], next_hop: IpAddr) -> io::Result<usize> {
+        if !self.queues.contains_key(&next_hop) {
+            let queue = self.spawn(next_hop)?;
+            self.queues.insert(next_hop, queue);
+        }
+        let queue = &self.queues[&next_hop];
+        let mut buffer = self.free.try_recv().unwrap_or_default();
+        buffer.clear();
+        buffer.extend_from_slice(packet);
+        match queue.try_send(buffer) {
+            Ok(()) => Ok(packet.len()),
+            Err(TrySendError::Full(buffer)) => {
+                let _ = self.free_sender.send(buffer);
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                self.queues.remove(&next_hop);
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::api::SendInfo;
+    use crate::bier::example_state;
+    use crate::daemon::BierDaemon;
+    use crate::header::BierHeader;
+    use crate::runtime::EventLoop;
+    use std::sync::{Condvar, Mutex};
+    use std::time::{Duration, Instant};
+
+    type SentPackets = Vec<(IpAddr, Vec<u8>)>;
+
+    /// Keeps the sent packets in memory, and blocks while `blocked` contains the next hop.
+    #[derive(Clone, Default)]
+    struct SlowTransport {
+        sent: Arc<Mutex<SentPackets>>,
+        blocked: Arc<(Mutex<Vec<IpAddr>>, Condvar)>,
+    }
+
+    impl Transport for SlowTransport {
+        fn send_to(&mut self, packet: &[u8], next_hop: IpAddr) -> io::Result<usize> {
+            let (blocked, condvar) = &*self.blocked;
+            let _guard = condvar
+                .wait_while(blocked.lock().unwrap(), |blocked| {
+                    blocked.contains(&next_hop)
+                })
+                .unwrap();
+            self.sent.lock().unwrap().push((next_hop, packet.to_vec()));
+            Ok(packet.len())
+        }
+    }
+
+    fn wait_until(condition: impl Fn() -> bool) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    /// Tests that a blocked neighbor does not stall the copies toward the other neighbors.
+    fn test_tx_stage() {
+        let transport = SlowTransport::default();
+        let b: IpAddr = "fc00:b::1".parse().unwrap();
+        let c: IpAddr = "fc00:c::1".parse().unwrap();
+        transport.blocked.0.lock().unwrap().push(b);
+        let factory = transport.clone();
+        let mut daemon = BierDaemon::builder()
+            .state(example_state())
+            .transport(TxStage::new(2, move |_| Ok(factory.clone())))
+            .build()
+            .unwrap();
+
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11110],
+            payload: &[1, 2, 3],
+            metadata: None,
+        };
+        // All copies toward C are sent, while at most 3 copies toward B are in flight:
+        // one blocked in the thread and two in the queue.
+        for i in 1..=5 {
+            daemon.send(&send_info).unwrap();
+            wait_until(|| transport.sent.lock().unwrap().len() == i);
+        }
+        let stats = daemon.stats();
+        assert!(stats.drops[&DropReason::QueueFull] >= 2);
+        assert!(!stats.neighbors.contains_key(&b) || stats.neighbors[&b].healthy);
+        assert!(transport
+            .sent
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|(nh, _)| *nh == c));
+
+        let (blocked, condvar) = &*transport.blocked;
+        blocked.lock().unwrap().clear();
+        condvar.notify_all();
+        let nb_to_b = 5 - stats.drops[&DropReason::QueueFull] as usize;
+        wait_until(|| transport.sent.lock().unwrap().len() == 5 + nb_to_b);
+    }
+
+    #[test]
+    /// Tests the packets received by the thread of the RX stage and processed by the event loop.
+    fn test_rx_stage() {
+        let (sender, receiver) = std::os::unix::net::UnixDatagram::pair().unwrap();
+        let transport = SlowTransport::default();
+        let mut router = Router::new(
+            BierDaemon::builder()
+                .state(example_state())
+                .transport(transport.clone())
+                .build()
+                .unwrap(),
+        );
+        let mut event_loop = EventLoop::new().unwrap();
+        event_loop
+            .register(
+                RxStage::spawn(socket2::Socket::from(receiver), Framing::Bier, 16, 64).unwrap(),
+            )
+            .unwrap();
+
+        let mut packet = vec![0u8; 22];
+        BierHeader::from_recv_info(&SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11010],
+            payload: &[],
+            metadata: None,
+        })
+        .unwrap()
+        .to_slice(&mut packet)
+        .unwrap();
+        for _ in 0..3 {
+            sender.send(&packet).unwrap();
+        }
+        // Larger than the buffers of the stage.
+        sender.send(&[0; 100]).unwrap();
+
+        let start = Instant::now();
+        while router.daemon().stats().received.packets < 3
+            || !router
+                .daemon()
+                .stats()
+                .drops
+                .contains_key(&DropReason::Truncated)
+        {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            event_loop
+                .run_once(&mut router, Some(Duration::from_millis(100)))
+                .unwrap();
+        }
+        assert_eq!(transport.sent.lock().unwrap().len(), 3);
+        assert_eq!(router.daemon().stats().drops[&DropReason::Truncated], 1);
+    }
+}
@@ -0,0 +1,419 @@
+//! HTTP endpoint exposing the counters of the BFR in the Prometheus text format, so that
+//! it is scraped like any other router component, e.g., at `http://[::1]:9100/metrics`.
+//!
+//! The counters are taken from the daemon every second, and the packet rates are computed
+//! between the two last snapshots, independently of the scrape interval. The requests are
+//! answered by a thread of their own, from the last snapshot, so that a slow scraper never
+//! blocks the event loop of the daemon: the endpoint is meant for a few scrapers, not as a
+//! general-purpose HTTP server.
+
+use crate::router::Router;
+use crate::runtime::EventSource;
+use crate::stats::{Counter, Stats};
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Interval between two snapshots of the counters used to compute the rates.
+const RATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum time to read a request and write its response, so that a scraper cannot hold
+/// the thread answering the others.
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Maximum length of a request. Only the request line matters, the headers are ignored.
+const MAX_REQUEST_LENGTH: usize = 8192;
+
+/// Quantiles of the latency histograms exposed in the summaries.
+const LATENCY_QUANTILES: [f64; 4] = [0.5, 0.9, 0.99, 1.0];
+
+/// Selects a counter of the statistics.
+type CounterOf = fn(&Stats) -> Counter;
+
+/// The two last snapshots of the counters, with their time.
+type Snapshots = Option<((Stats, Instant), (Stats, Instant))>;
+
+pub struct PrometheusExporter {
+    local_addr: SocketAddr,
+    /// Shared with the thread answering the scrapes.
+    snapshots: Arc<Mutex<Snapshots>>,
+    next_snapshot: Instant,
+}
+
+impl PrometheusExporter {
+    /// Listens on `addr`, and spawns the thread answering the scrapes.
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let snapshots = Arc::new(Mutex::new(None));
+        let shared = snapshots.clone();
+        std::thread::Builder::new()
+            .name("bier-prometheus".to_string())
+            .spawn(move || serve(listener, &shared))?;
+        Ok(PrometheusExporter {
+            local_addr,
+            snapshots,
+            next_snapshot: Instant::now(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    /// Counters of `stats` in the Prometheus text format, with the rates of the last interval.
+    pub fn encode(&self, stats: &Stats) -> String {
+        encode(&self.snapshots.lock().unwrap(), stats)
+    }
+}
+
+/// Answers the scrapes received on `listener`, one at a time.
+fn serve(listener: TcpListener, snapshots: &Mutex<Snapshots>) {
+    for stream in listener.incoming() {
+        let res = stream.and_then(|stream| answer(stream, snapshots));
+        if let Err(e) = res {
+            log::warn!("Error when answering a Prometheus scrape: {:?}", e);
+        }
+    }
+}
+
+/// Answers a single HTTP request on `stream` with the last snapshot, then closes it.
+fn answer(mut stream: TcpStream, snapshots: &Mutex<Snapshots>) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_LENGTH {
+        match stream.read(&mut buffer)? {
+            0 => break,
+            read => request.extend_from_slice(&buffer[..read]),
+        }
+    }
+    let request_line = String::from_utf8_lossy(&request);
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let snapshots = snapshots.lock().unwrap();
+            let stats = snapshots
+                .as_ref()
+                .map(|(_, (stats, _))| stats.clone())
+                .unwrap_or_default();
+            ("200 OK", encode(&snapshots, &stats))
+        }
+        (Some("GET"), _) => ("404 Not Found", "Not found, see /metrics\n".to_string()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// Packet and byte rates of the counters selected by `counter` over the last interval.
+fn rates(snapshots: &Snapshots, counter: impl Fn(&Stats) -> Counter) -> (f64, f64) {
+    match snapshots {
+        Some(((prev, prev_at), (cur, cur_at))) => {
+            let elapsed = (*cur_at - *prev_at).as_secs_f64().max(f64::EPSILON);
+            let delta = counter(cur).delta(counter(prev));
+            (delta.packets as f64 / elapsed, delta.bytes as f64 / elapsed)
+        }
+        None => (0.0, 0.0),
+    }
+}
+
+/// Counters of `stats` in the Prometheus text format, with the rates of the last interval.
+fn encode(snapshots: &Snapshots, stats: &Stats) -> String {
+    let mut out = String::new();
+    let header = |out: &mut String, name: &str, kind: &str, help: &str| {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+    };
+
+    let counters: [(&str, &str, CounterOf); 3] = [
+        ("received", "BIER packets received from the network", |s| {
+            s.received
+        }),
+        (
+            "application",
+            "Payloads sent by the local applications",
+            |s| s.from_apps,
+        ),
+        (
+            "delivered",
+            "Payloads delivered to the local applications",
+            |s| s.delivered,
+        ),
+    ];
+    for (name, help, counter) in counters {
+        let (pps, bps) = rates(snapshots, counter);
+        let value = counter(stats);
+        header(
+            &mut out,
+            &format!("bier_{}_packets_total", name),
+            "counter",
+            help,
+        );
+        writeln!(out, "bier_{}_packets_total {}", name, value.packets).unwrap();
+        header(
+            &mut out,
+            &format!("bier_{}_bytes_total", name),
+            "counter",
+            help,
+        );
+        writeln!(out, "bier_{}_bytes_total {}", name, value.bytes).unwrap();
+        header(
+            &mut out,
+            &format!("bier_{}_packets_per_second", name),
+            "gauge",
+            help,
+        );
+        writeln!(out, "bier_{}_packets_per_second {:.3}", name, pps).unwrap();
+        header(
+            &mut out,
+            &format!("bier_{}_bytes_per_second", name),
+            "gauge",
+            help,
+        );
+        writeln!(out, "bier_{}_bytes_per_second {:.3}", name, bps).unwrap();
+    }
+
+    header(
+        &mut out,
+        "bier_cleared_bits_total",
+        "counter",
+        "Bits above the highest BFR-id of their BIFT",
+    );
+    writeln!(out, "bier_cleared_bits_total {}", stats.cleared_bits).unwrap();
+    header(
+        &mut out,
+        "bier_cache_lookups_total",
+        "counter",
+        "Lookups in the result cache",
+    );
+    writeln!(
+        out,
+        "bier_cache_lookups_total{{result=\"hit\"}} {}",
+        stats.cache.hits
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "bier_cache_lookups_total{{result=\"miss\"}} {}",
+        stats.cache.misses
+    )
+    .unwrap();
+
+    header(
+        &mut out,
+        "bier_neighbor_sent_packets_total",
+        "counter",
+        "Copies sent to the neighbor",
+    );
+    for (next_hop, neighbor) in &stats.neighbors {
+        writeln!(
+            out,
+            "bier_neighbor_sent_packets_total{{next_hop=\"{}\"}} {}",
+            next_hop, neighbor.sent.packets
+        )
+        .unwrap();
+    }
+    header(
+        &mut out,
+        "bier_neighbor_sent_bytes_total",
+        "counter",
+        "Bytes of the copies sent to the neighbor",
+    );
+    for (next_hop, neighbor) in &stats.neighbors {
+        writeln!(
+            out,
+            "bier_neighbor_sent_bytes_total{{next_hop=\"{}\"}} {}",
+            next_hop, neighbor.sent.bytes
+        )
+        .unwrap();
+    }
+    header(
+        &mut out,
+        "bier_neighbor_sent_packets_per_second",
+        "gauge",
+        "Copies sent to the neighbor per second",
+    );
+    for next_hop in stats.neighbors.keys() {
+        let (pps, _) = rates(snapshots, |s| {
+            s.neighbors
+                .get(next_hop)
+                .map(|neighbor| neighbor.sent)
+                .unwrap_or_default()
+        });
+        writeln!(
+            out,
+            "bier_neighbor_sent_packets_per_second{{next_hop=\"{}\"}} {:.3}",
+            next_hop, pps
+        )
+        .unwrap();
+    }
+    header(
+        &mut out,
+        "bier_neighbor_send_errors_total",
+        "counter",
+        "Copies that the transport failed to send to the neighbor",
+    );
+    for (next_hop, neighbor) in &stats.neighbors {
+        writeln!(
+            out,
+            "bier_neighbor_send_errors_total{{next_hop=\"{}\"}} {}",
+            next_hop, neighbor.send_errors
+        )
+        .unwrap();
+    }
+    header(
+        &mut out,
+        "bier_neighbor_up",
+        "gauge",
+        "Whether the last copy was sent and the neighbor is neither drained nor dead",
+    );
+    for (next_hop, neighbor) in &stats.neighbors {
+        writeln!(
+            out,
+            "bier_neighbor_up{{next_hop=\"{}\"}} {}",
+            next_hop,
+            (neighbor.healthy && !neighbor.drained && !neighbor.dead) as u8
+        )
+        .unwrap();
+    }
+    header(
+        &mut out,
+        "bier_neighbor_latency_seconds",
+        "summary",
+        "Time between the reception of a packet and the sending of its copy",
+    );
+    for (next_hop, neighbor) in &stats.neighbors {
+        let latency = &neighbor.latency;
+        for quantile in LATENCY_QUANTILES {
+            writeln!(
+                out,
+                "bier_neighbor_latency_seconds{{next_hop=\"{}\",quantile=\"{}\"}} {}",
+                next_hop,
+                quantile,
+                latency.quantile(quantile).as_secs_f64()
+            )
+            .unwrap();
+        }
+        writeln!(
+            out,
+            "bier_neighbor_latency_seconds_sum{{next_hop=\"{}\"}} {}",
+            next_hop,
+            latency.sum_ns as f64 / 1e9
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "bier_neighbor_latency_seconds_count{{next_hop=\"{}\"}} {}",
+            next_hop, latency.count
+        )
+        .unwrap();
+    }
+
+    header(
+        &mut out,
+        "bier_bfer_packets_total",
+        "counter",
+        "Copies forwarded toward the BFER",
+    );
+    for (bfr_id, counter) in &stats.bfers {
+        writeln!(
+            out,
+            "bier_bfer_packets_total{{bfr_id=\"{}\"}} {}",
+            bfr_id, counter.packets
+        )
+        .unwrap();
+    }
+
+    header(
+        &mut out,
+        "bier_drops_total",
+        "counter",
+        "Packets or copies dropped, by reason",
+    );
+    for (reason, nb) in &stats.drops {
+        writeln!(out, "bier_drops_total{{reason=\"{:?}\"}} {}", reason, nb).unwrap();
+    }
+    out
+}
+
+/// Takes a snapshot of the counters every second, for the scrapes and their rates.
+impl EventSource for PrometheusExporter {
+    fn deadline(&self) -> Option<Instant> {
+        Some(self.next_snapshot)
+    }
+
+    fn handle(&mut self, router: &mut Router) -> io::Result<()> {
+        let now = Instant::now();
+        if self.next_snapshot <= now {
+            let stats = router.daemon().stats();
+            let mut snapshots = self.snapshots.lock().unwrap();
+            *snapshots = match snapshots.take() {
+                Some((_, last)) => Some((last, (stats, now))),
+                None => Some(((stats.clone(), now), (stats, now))),
+            };
+            self.next_snapshot = now + RATE_INTERVAL;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::stats::DropReason;
+
+    #[test]
+    /// Tests the encoding of the counters and the answer to a scrape.
+    fn test_prometheus_exporter() {
+        let exporter = PrometheusExporter::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut stats = Stats::default();
+        stats.record_send("fc00::b".parse().unwrap(), 100, false);
+        stats.record_drop(DropReason::NoEntry);
+        let now = Instant::now();
+        let mut later = stats.clone();
+        later.received.add(100);
+        later.received.add(100);
+        *exporter.snapshots.lock().unwrap() = Some((
+            (stats.clone(), now),
+            (later.clone(), now + Duration::from_secs(2)),
+        ));
+
+        let metrics = exporter.encode(&later);
+        let lines: Vec<&str> = metrics.lines().collect();
+        assert!(lines.contains(&"# TYPE bier_received_packets_total counter"));
+        assert!(lines.contains(&"bier_received_packets_total 2"));
+        assert!(lines.contains(&"bier_received_packets_per_second 1.000"));
+        assert!(lines.contains(&"bier_received_bytes_per_second 100.000"));
+        assert!(lines.contains(&"bier_neighbor_send_errors_total{next_hop=\"fc00::b\"} 1"));
+        assert!(lines.contains(&"bier_neighbor_up{next_hop=\"fc00::b\"} 0"));
+        assert!(lines.contains(&"bier_drops_total{reason=\"NoEntry\"} 1"));
+
+        // A scrape over HTTP, answered with the last snapshot by the thread of the exporter.
+        let addr = exporter.local_addr().unwrap();
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: bfr\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(&metrics));
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
@@ -0,0 +1,447 @@
+//! Handling of the datagrams received by the BFR, independently of the sockets.
+//! The binary only receives the datagrams and sends back the answers, so that
+//! the whole processing can be tested or embedded with injected transports.
+
+use crate::acl::{self, Credentials};
+use crate::api::{
+    BatchInfo, CommunicationInfo, ErrorNotification, MessageType, Query, Registration,
+};
+use crate::daemon::BierDaemon;
+use crate::stats::DropReason;
+use crate::{Error, Result};
+use log::{debug, error};
+use std::path::Path;
+
+/// Maximum length of an answer to an application.
+const MAX_ANSWER_LENGTH: usize = 65535;
+
+/// Start of the datagrams received from the network, before the BIER header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// The IP header with IPv4, see `Router::handle_ip_packet`.
+    Ip,
+    /// The BIER header, e.g., in UDP datagrams or Ethernet frames.
+    Bier,
+    /// The MPLS label stack, see `BierDaemon::process_mpls_packet`.
+    Mpls,
+    /// The Destination Options header holding the BIER header, see `bierin6`.
+    Bierin6,
+}
+
+pub struct Router {
+    daemon: BierDaemon,
+    /// Buffer of the answers to the applications.
+    answer: Vec<u8>,
+}
+
+impl Router {
+    pub fn new(daemon: BierDaemon) -> Self {
+        Router {
+            daemon,
+            answer: vec![0u8; MAX_ANSWER_LENGTH],
+        }
+    }
+
+    pub fn daemon(&self) -> &BierDaemon {
+        &self.daemon
+    }
+
+    pub fn daemon_mut(&mut self) -> &mut BierDaemon {
+        &mut self.daemon
+    }
+
+    /// Handles a datagram received from the network with the given `framing`, modified in
+    /// place. Whatever its content, the datagram is either processed or counted as dropped.
+    pub fn handle_packet(&mut self, framing: Framing, buf: &mut [u8]) -> Result<()> {
+        match framing {
+            Framing::Ip => self.handle_ip_packet(buf),
+            Framing::Bier => self.daemon.process_packet(buf),
+            Framing::Mpls => self.daemon.process_mpls_packet(buf),
+            Framing::Bierin6 => self.daemon.process_bierin6_packet(buf),
+        }
+    }
+
+    /// Handles a datagram received on the raw IP socket, modified in place.
+    /// Contrary to IPv6, IPv4 raw sockets also return the IP header, which is skipped.
+    pub fn handle_ip_packet(&mut self, buf: &mut [u8]) -> Result<()> {
+        let ip_header_length = match (self.daemon.state().get_loopback().is_ipv4(), buf.first()) {
+            (true, Some(first)) => ((first & 0x0f) as usize) * 4,
+            (true, None) => 1,
+            (false, _) => 0,
+        };
+        if let Err(e) = crate::check_length(buf, ip_header_length) {
+            self.daemon.record_drop(DropReason::MalformedPacket);
+            return Err(e);
+        }
+        self.daemon.process_packet(&mut buf[ip_header_length..])
+    }
+
+    /// Handles a message of a local application, sent with the given `credentials` from the
+    /// socket bound at `sender`, if any. The access control list also restricts the protos
+    /// registered by the applications. Returns the answer to send back to the application, if
+    /// any. The payloads of a batch are sent even if one of them fails, and the first error
+    /// is returned.
+    pub fn handle_app_message(
+        &mut self,
+        buf: &[u8],
+        credentials: Option<&Credentials>,
+        sender: Option<&Path>,
+    ) -> Result<Option<&[u8]>> {
+        match crate::api::message_type(buf) {
+            // The application may query information instead of sending a payload.
+            Ok(MessageType::Query) => {
+                let query = self.parse(Query::from_slice(buf))?;
+                let len = query
+                    .answer(self.daemon.state())
+                    .to_slice(&mut self.answer)?;
+                Ok(Some(&self.answer[..len]))
+            }
+            // Several payloads sharing the same BIFT-id and proto.
+            Ok(MessageType::Batch) => {
+                let batch_info = self.parse(BatchInfo::from_slice(buf))?;
+                debug!("Received a batch of {} payloads", batch_info.records.len());
+                self.check_acl(credentials, batch_info.proto)?;
+                let mut res = Ok(None);
+                for send_info in batch_info.iter() {
+                    if let Err(e) = self.daemon.send(&send_info) {
+                        error!("Impossible to send a payload from the batch: {:?}", e);
+                        res = res.and(Err(e));
+                    }
+                }
+                res
+            }
+            // The registration is acknowledged with the same message.
+            // Only the applications bound to a path can register, to receive the packets.
+            Ok(MessageType::Register) => {
+                let registration = self.parse(Registration::from_slice(buf))?;
+                self.check_acl(credentials, registration.proto)?;
+                let sender = sender.ok_or(Error::Registration)?;
+                self.daemon.register(&registration, sender)?;
+                debug!("Registered an application: {}", registration);
+                let len = registration.to_slice(&mut self.answer)?;
+                Ok(Some(&self.answer[..len]))
+            }
+            _ => {
+                let send_info = self.parse(CommunicationInfo::from_slice(buf))?;
+                self.check_acl(credentials, send_info.proto)?;
+                self.daemon.send(&send_info).map(|_| None)
+            }
+        }
+    }
+
+    /// Notification to send back to the application whose message `buf` failed with `error`.
+    pub fn error_notification(&mut self, buf: &[u8], error: Error) -> Result<&[u8]> {
+        let len = ErrorNotification::new(buf, error).to_slice(&mut self.answer)?;
+        Ok(&self.answer[..len])
+    }
+
+    /// Answers a datagram received on the control socket.
+    pub fn handle_control_request(&mut self, buf: &[u8]) -> Vec<u8> {
+        crate::control::handle_request(&mut self.daemon, buf)
+    }
+
+    /// Answers a datagram received on the update socket, including the updates of the BIFTs.
+    pub fn handle_update_request(&mut self, buf: &[u8]) -> Vec<u8> {
+        crate::control::handle_update_request(&mut self.daemon, buf)
+    }
+
+    /// Records the drop of a malformed message of an application.
+    fn parse<T>(&mut self, res: Result<T>) -> Result<T> {
+        if res.is_err() {
+            self.daemon.record_drop(DropReason::MalformedApiMessage);
+        }
+        res
+    }
+
+    fn check_acl(&mut self, credentials: Option<&Credentials>, proto: u16) -> Result<()> {
+        if acl::is_allowed(&self.daemon.state().api_acl, credentials, proto) {
+            Ok(())
+        } else {
+            self.daemon.record_drop(DropReason::Unauthorized);
+            Err(Error::Unauthorized)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::acl::AclRule;
+    use crate::api::{Response, SendInfo, API_VERSION};
+    use crate::bier::{example_state, BierState};
+    use crate::control::ControlResponse;
+    use crate::daemon::MemoryTransport;
+    use crate::header::BierHeader;
+    use std::sync::{Arc, Mutex};
+
+    fn get_router(state: BierState) -> (Router, MemoryTransport) {
+        let transport = MemoryTransport::default();
+        let daemon = BierDaemon::builder()
+            .state(state)
+            .transport(transport.clone())
+            .build()
+            .unwrap();
+        (Router::new(daemon), transport)
+    }
+
+    fn get_message() -> Vec<u8> {
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11110],
+            payload: &[1, 2, 3],
+            metadata: None,
+        };
+        let mut message = vec![0u8; send_info.message_length()];
+        send_info.to_slice(&mut message).unwrap();
+        message
+    }
+
+    #[test]
+    /// Tests the messages of the applications.
+    fn test_router_app_message() {
+        let (mut router, transport) = get_router(example_state());
+
+        assert_eq!(
+            router.handle_app_message(&get_message(), None, None),
+            Ok(None)
+        );
+        assert_eq!(transport.len(), 2);
+
+        let mut query = vec![0u8; 64];
+        let len = Query::Bifts.to_slice(&mut query).unwrap();
+        let answer = router
+            .handle_app_message(&query[..len], None, None)
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            Response::from_slice(answer),
+            Ok(Response::Bifts(_))
+        ));
+
+        assert!(router.handle_app_message(&[1, 2], None, None).is_err());
+        let stats = router.daemon().stats();
+        assert_eq!(stats.drops[&DropReason::MalformedApiMessage], 1);
+
+        // The application is notified of the unknown BIFT-id.
+        let mut message = get_message();
+        message[7] = 42;
+        let error = router.handle_app_message(&message, None, None).unwrap_err();
+        assert_eq!(error, Error::BiftId(42));
+        let notification = router.error_notification(&message, error).unwrap();
+        assert_eq!(
+            ErrorNotification::from_slice(notification),
+            Ok(ErrorNotification {
+                code: crate::api::ErrorCode::UnknownBift,
+                bift_id: 42,
+                proto: 6,
+            })
+        );
+
+        // Only the applications of the ACL may send payloads.
+        let mut state = example_state();
+        state.api_acl.push(AclRule {
+            uid: Some(1000),
+            ..Default::default()
+        });
+        let (mut router, transport) = get_router(state);
+        let credentials = Credentials {
+            pid: 1,
+            uid: 1000,
+            gid: 1000,
+        };
+        assert_eq!(
+            router.handle_app_message(&get_message(), None, None),
+            Err(Error::Unauthorized)
+        );
+        assert!(transport.is_empty());
+        assert_eq!(
+            router.handle_app_message(&get_message(), Some(&credentials), None),
+            Ok(None)
+        );
+        assert_eq!(transport.len(), 2);
+    }
+
+    #[test]
+    /// Tests the registrations of the applications.
+    fn test_router_registration() {
+        let mut message = vec![0u8; 64];
+        let registration = Registration {
+            proto: 0x37,
+            path: "/tmp/app.sock".to_string(),
+            version: API_VERSION,
+        };
+        let len = registration.to_slice(&mut message).unwrap();
+        message.truncate(len);
+
+        // Refused without registration callback.
+        let (mut router, _) = get_router(example_state());
+        assert_eq!(
+            router.handle_app_message(&message, None, None),
+            Err(Error::Registration)
+        );
+
+        let registered = Arc::new(Mutex::new(Vec::new()));
+        let registered_clone = registered.clone();
+        let daemon = BierDaemon::builder()
+            .state(example_state())
+            .transport(MemoryTransport::default())
+            .on_registration(move |registration, _| {
+                registered_clone.lock().unwrap().push(registration.clone());
+                Ok(())
+            })
+            .build()
+            .unwrap();
+        let mut router = Router::new(daemon);
+        let sender = Path::new("/tmp/app.sock");
+        let answer = router
+            .handle_app_message(&message, None, Some(sender))
+            .unwrap();
+        assert_eq!(answer, Some(&message[..]));
+        assert_eq!(*registered.lock().unwrap(), [registration]);
+
+        // An application cannot register the path of another one, nor register unbound.
+        let other = Path::new("/tmp/other.sock");
+        for sender in [Some(other), None] {
+            assert_eq!(
+                router.handle_app_message(&message, None, sender),
+                Err(Error::Registration)
+            );
+        }
+        assert_eq!(registered.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    /// Tests that the updates, including the drain of a neighbor, are refused on the control
+    /// socket and accepted on the update socket.
+    fn test_router_control_requests() {
+        let (mut router, _) = get_router(example_state());
+        let drain =
+            br#"{"command": "set_admin_state", "next_hop": "fc00:b::1", "admin_state": "down"}"#;
+        let response: ControlResponse =
+            serde_json::from_slice(&router.handle_control_request(drain)).unwrap();
+        assert!(matches!(response, ControlResponse::Error(_)));
+        assert!(router.daemon().state().drained_next_hops().is_empty());
+
+        let response: ControlResponse =
+            serde_json::from_slice(&router.handle_update_request(drain)).unwrap();
+        assert_eq!(response, ControlResponse::Updated(3));
+        assert_eq!(router.daemon().state().drained_next_hops().len(), 1);
+    }
+
+    #[test]
+    /// Tests the packets received on the raw IP socket.
+    fn test_router_ip_packet() {
+        // Packet with the bits of the BFERs behind node B.
+        let mut packet = vec![0u8; 22];
+        BierHeader::from_recv_info(&SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11010],
+            payload: &[],
+            metadata: None,
+        })
+        .unwrap()
+        .to_slice(&mut packet)
+        .unwrap();
+        packet[20..].copy_from_slice(&[4, 5]);
+
+        let (mut router, transport) = get_router(example_state());
+        assert_eq!(router.handle_ip_packet(&mut packet.clone()), Ok(()));
+        assert!(!transport.is_empty());
+
+        // The IPv4 header is skipped.
+        let mut state = example_state();
+        state.loopback = "10.0.0.1".parse().unwrap();
+        let (mut router, transport) = get_router(state);
+        let mut ipv4_packet = vec![0x45; 1];
+        ipv4_packet.extend_from_slice(&[0; 19]);
+        ipv4_packet.extend_from_slice(&packet);
+        assert_eq!(router.handle_ip_packet(&mut ipv4_packet), Ok(()));
+        assert!(!transport.is_empty());
+        assert_eq!(
+            router.handle_ip_packet(&mut [0x4f]),
+            Err(Error::SliceWrongLength {
+                expected: 60,
+                actual: 1
+            })
+        );
+    }
+
+    #[test]
+    /// Tests that the garbage received from the network never panics, and that each packet
+    /// which is not processed is counted as dropped.
+    fn test_router_garbage() {
+        let mut valid = vec![0u8; 22];
+        BierHeader::from_recv_info(&SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11011],
+            payload: &[],
+            metadata: None,
+        })
+        .unwrap()
+        .to_slice(&mut valid)
+        .unwrap();
+
+        // Xorshift64, reproducible.
+        let mut seed = 0x2545f4914f6cdd1du64;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        let mut ipv4_state = example_state();
+        ipv4_state.loopback = "10.0.0.1".parse().unwrap();
+        // A BIER-TE BIFT is refused, and its packets are dropped.
+        let mut bier_te = example_state();
+        bier_te.bifts[0].bift_type = crate::bier::BiftType::BierTe;
+        assert!(BierDaemon::builder()
+            .state(bier_te)
+            .transport(MemoryTransport::default())
+            .build()
+            .is_err());
+        let (mut bier_te_router, _) = get_router(example_state());
+        // The BIFTs that cannot be compiled are not applied.
+        assert!(bier_te_router
+            .daemon_mut()
+            .update_state(|state| {
+                state.bifts[0].bift_type = crate::bier::BiftType::BierTe;
+            })
+            .is_err());
+        assert!(bier_te_router
+            .handle_packet(Framing::Ip, &mut valid.clone())
+            .is_ok());
+
+        for mut router in [example_state(), ipv4_state]
+            .into_iter()
+            .map(|state| get_router(state).0)
+            .chain([bier_te_router])
+        {
+            let framings = [Framing::Ip, Framing::Bier, Framing::Mpls, Framing::Bierin6];
+            for round in 0..1000 {
+                let framing = framings[round % framings.len()];
+                // Random bytes, or a valid packet with random bytes overwritten.
+                let mut packet = match round % 3 {
+                    0 => (0..next() % 80).map(|_| next() as u8).collect(),
+                    _ => {
+                        let mut packet = valid.clone();
+                        for _ in 0..1 + next() % 4 {
+                            let idx = next() as usize % packet.len();
+                            packet[idx] = next() as u8;
+                        }
+                        packet.truncate(next() as usize % (packet.len() + 1));
+                        packet
+                    }
+                };
+                let drops = |router: &Router| router.daemon().stats().drops.values().sum::<u64>();
+                let before = drops(&router);
+                if router.handle_packet(framing, &mut packet).is_err() {
+                    assert!(drops(&router) > before, "{:?} {:?}", framing, packet);
+                }
+            }
+        }
+    }
+}
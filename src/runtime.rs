@@ -0,0 +1,679 @@
+//! Event loop of the binary. Each input of the BFR (socket, device, timer) is an
+//! `EventSource` registered with the loop, which polls the file descriptors of
+//! the sources with mio and calls their handler with the `Router`.
+
+use crate::acl;
+use crate::daemon::Transport;
+use crate::router::{Framing, Router};
+use crate::stats::DropReason;
+use crate::Error;
+use log::{debug, error, warn};
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{Duration, Instant};
+
+/// Default size of the receive buffers of the sockets, the largest IP packet.
+pub const DEFAULT_MAX_PACKET_SIZE: usize = 65535;
+
+/// Input of the event loop.
+pub trait EventSource {
+    /// File descriptor polled for readability, if any. The file descriptors are polled
+    /// in edge-triggered mode: `handle` must read until `WouldBlock`.
+    fn fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// Next time at which `handle` is called, even without event, e.g., for timers.
+    fn deadline(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Handles the source when its file descriptor is readable or its deadline is reached.
+    fn handle(&mut self, router: &mut Router) -> io::Result<()>;
+}
+
+impl<T: EventSource + ?Sized> EventSource for Box<T> {
+    fn fd(&self) -> Option<RawFd> {
+        (**self).fd()
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        (**self).deadline()
+    }
+
+    fn handle(&mut self, router: &mut Router) -> io::Result<()> {
+        (**self).handle(router)
+    }
+}
+
+pub struct EventLoop {
+    poll: mio::Poll,
+    events: mio::Events,
+    /// The token of each source is its index.
+    sources: Vec<Box<dyn EventSource>>,
+}
+
+impl EventLoop {
+    pub fn new() -> io::Result<Self> {
+        Ok(EventLoop {
+            poll: mio::Poll::new()?,
+            events: mio::Events::with_capacity(1024),
+            sources: Vec::new(),
+        })
+    }
+
+    pub fn register(&mut self, source: impl EventSource + 'static) -> io::Result<mio::Token> {
+        let token = mio::Token(self.sources.len());
+        if let Some(fd) = source.fd() {
+            self.poll.registry().register(
+                &mut mio::unix::SourceFd(&fd),
+                token,
+                mio::Interest::READABLE,
+            )?;
+        }
+        self.sources.push(Box::new(source));
+        Ok(token)
+    }
+
+    /// Waits for the next events, at most `timeout` or until the next deadline of a source,
+    /// and handles them. The errors of the handlers are logged. The copies deferred by the
+    /// fan-out cap are then sent, without waiting if some are left from the previous call.
+    pub fn run_once(&mut self, router: &mut Router, timeout: Option<Duration>) -> io::Result<()> {
+        let timeout = match router.daemon().has_deferred() {
+            true => Some(Duration::ZERO),
+            false => timeout,
+        };
+        let now = Instant::now();
+        let deadline_timeout = self
+            .sources
+            .iter()
+            .filter_map(|source| source.deadline())
+            .min()
+            .map(|deadline| deadline.saturating_duration_since(now));
+        let timeout = match (timeout, deadline_timeout) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+
+        match self.poll.poll(&mut self.events, timeout) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => return Ok(()),
+            Err(e) => return Err(e),
+        }
+
+        for event in self.events.iter() {
+            match self.sources.get_mut(event.token().0) {
+                Some(source) => {
+                    if let Err(e) = source.handle(router) {
+                        error!(
+                            "Error when handling the source {:?}: {:?}",
+                            event.token(),
+                            e
+                        );
+                    }
+                }
+                None => error!("Unrecognized token: {:?}", event.token()),
+            }
+        }
+
+        let now = Instant::now();
+        for (idx, source) in self.sources.iter_mut().enumerate() {
+            if source.deadline().is_some_and(|deadline| deadline <= now) {
+                if let Err(e) = source.handle(router) {
+                    error!("Error when handling the source {}: {:?}", idx, e);
+                }
+            }
+        }
+
+        if router.daemon().has_deferred() {
+            router.daemon_mut().send_deferred();
+        }
+        Ok(())
+    }
+
+    pub fn run(&mut self, router: &mut Router) -> io::Result<()> {
+        loop {
+            self.run_once(router, None)?;
+        }
+    }
+}
+
+/// Calls `recv` until there is nothing more to read. The interrupted calls are retried,
+/// and the errors of a single datagram, e.g., an ICMP error reported on a UDP socket, are
+/// logged before reading the next one. Only the errors of the socket itself are returned.
+pub(crate) fn drain(mut recv: impl FnMut() -> io::Result<()>) -> io::Result<()> {
+    loop {
+        match recv() {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) if is_socket_error(&e) => return Err(e),
+            Err(e) => debug!("Error when receiving a datagram: {:?}, continuing...", e),
+        }
+    }
+}
+
+/// Whether `e` is an error of the socket itself, which fails every following call, rather
+/// than an error of a single datagram.
+pub(crate) fn is_socket_error(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EBADF | libc::ENOTSOCK | libc::EINVAL | libc::EFAULT)
+    )
+}
+
+/// Receives a datagram in the spare capacity of the cleared `buffer`. Returns the
+/// sender and whether the datagram was larger than the buffer and thus truncated.
+pub(crate) fn recv_datagram(
+    socket: &socket2::Socket,
+    buffer: &mut Vec<u8>,
+) -> io::Result<(socket2::SockAddr, bool)> {
+    buffer.clear();
+    let mut bufs = [socket2::MaybeUninitSlice::new(buffer.spare_capacity_mut())];
+    let (read, flags, from) = socket.recv_from_vectored(&mut bufs)?;
+    unsafe {
+        buffer.set_len(read);
+    }
+    Ok((from, flags.is_truncated()))
+}
+
+/// Records a truncated datagram, dropped instead of being parsed.
+pub(crate) fn report_truncated(router: &mut Router, max_packet_size: usize) {
+    router.daemon_mut().record_drop(DropReason::Truncated);
+    warn!(
+        "Dropped a datagram larger than the receive buffer of {} bytes: increase --max-packet-size",
+        max_packet_size
+    );
+}
+
+/// Raw IP socket receiving the BIER packets from the network, or raw IPv6 socket receiving
+/// the BIERin6 packets. See `TransportSource` for the sockets of a `Transport`.
+pub struct RawIpSource {
+    socket: socket2::Socket,
+    buffer: Vec<u8>,
+    framing: Framing,
+}
+
+impl RawIpSource {
+    /// The socket is set non-blocking. Larger packets are dropped.
+    pub fn new(socket: socket2::Socket, max_packet_size: usize) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        Ok(RawIpSource {
+            socket,
+            buffer: Vec::with_capacity(max_packet_size),
+            framing: Framing::Ip,
+        })
+    }
+
+    /// Raw IPv6 socket of the Destination Options headers, see `bierin6::bierin6_socket`.
+    pub fn bierin6(socket: socket2::Socket, max_packet_size: usize) -> io::Result<Self> {
+        let mut source = Self::new(socket, max_packet_size)?;
+        source.framing = Framing::Bierin6;
+        Ok(source)
+    }
+}
+
+impl EventSource for RawIpSource {
+    fn fd(&self) -> Option<RawFd> {
+        Some(self.socket.as_raw_fd())
+    }
+
+    fn handle(&mut self, router: &mut Router) -> io::Result<()> {
+        drain(|| {
+            let (_, truncated) = recv_datagram(&self.socket, &mut self.buffer)?;
+            if truncated {
+                report_truncated(router, self.buffer.capacity());
+                return Ok(());
+            }
+            if let Err(e) = router.handle_packet(self.framing, &mut self.buffer) {
+                debug!(
+                    "Error when processing the BIER packet: {:?}, continuing...",
+                    e
+                );
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Socket of a `Transport` receiving the BIER packets from the network, e.g., the raw IP
+/// socket or a `UdpTransport`, see `BierDaemon::recv_from`.
+pub struct TransportSource<T> {
+    transport: T,
+    buffer: Vec<u8>,
+}
+
+impl<T: Transport + AsRawFd> TransportSource<T> {
+    /// The socket of the transport is set non-blocking. Larger packets are dropped.
+    pub fn new(transport: T, max_packet_size: usize) -> io::Result<Self> {
+        socket2::SockRef::from(&transport).set_nonblocking(true)?;
+        Ok(TransportSource {
+            transport,
+            buffer: vec![0u8; max_packet_size + 1],
+        })
+    }
+}
+
+impl<T: Transport + AsRawFd> EventSource for TransportSource<T> {
+    fn fd(&self) -> Option<RawFd> {
+        Some(self.transport.as_raw_fd())
+    }
+
+    fn handle(&mut self, router: &mut Router) -> io::Result<()> {
+        drain(|| {
+            router
+                .daemon_mut()
+                .recv_from(&mut self.transport, &mut self.buffer)
+        })
+    }
+}
+
+/// UNIX datagram socket receiving the messages of the local applications.
+/// The answers to the queries are sent back to the application.
+pub struct AppSource {
+    socket: socket2::Socket,
+    buffer: Vec<u8>,
+}
+
+impl AppSource {
+    /// The socket is set non-blocking. Larger messages are dropped.
+    pub fn new(socket: socket2::Socket, max_packet_size: usize) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        Ok(AppSource {
+            socket,
+            buffer: Vec::with_capacity(max_packet_size),
+        })
+    }
+
+    /// Notifies the application at `from` that its message failed with `error`.
+    /// The applications whose socket is not bound cannot be notified.
+    fn notify(&self, router: &mut Router, error: Error, from: &socket2::SockAddr) {
+        let res = router
+            .error_notification(&self.buffer, error)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            .and_then(|notification| self.socket.send_to(notification, from));
+        if let Err(e) = res {
+            debug!("Cannot notify {:?} of the error: {:?}", from, e);
+        }
+    }
+}
+
+impl EventSource for AppSource {
+    fn fd(&self) -> Option<RawFd> {
+        Some(self.socket.as_raw_fd())
+    }
+
+    fn handle(&mut self, router: &mut Router) -> io::Result<()> {
+        drain(|| {
+            self.buffer.clear();
+            let datagram = acl::recv_with_credentials(
+                self.socket.as_raw_fd(),
+                self.buffer.spare_capacity_mut(),
+            )?;
+            unsafe {
+                self.buffer.set_len(datagram.read);
+            }
+            let (from, credentials) = (datagram.from, datagram.credentials);
+            if datagram.truncated {
+                report_truncated(router, self.buffer.capacity());
+                self.notify(router, Error::PacketTooLarge, &from);
+                return Ok(());
+            }
+            let sender = acl::sender_path(&from);
+            match router.handle_app_message(&self.buffer, credentials.as_ref(), sender.as_deref()) {
+                Ok(Some(answer)) => match self.socket.send_to(answer, &from) {
+                    Ok(_) => debug!("Answered a query from {:?}", from),
+                    Err(e) => error!("Error when answering a query from {:?}: {:?}", from, e),
+                },
+                Ok(None) => (),
+                Err(e) => {
+                    error!(
+                        "Impossible to handle the message from UNIX: {:?} ({:?})",
+                        e, credentials
+                    );
+                    self.notify(router, e, &from);
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+/// UNIX datagram socket receiving the requests of the operators, e.g., bier-top,
+/// or of a controller updating the BIFTs.
+pub struct ControlSource {
+    socket: socket2::Socket,
+    buffer: Vec<u8>,
+    requests: ControlRequests,
+}
+
+/// Requests accepted by a `ControlSource`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ControlRequests {
+    /// All the requests except the updates, see `control::handle_request`.
+    Control,
+    /// Also the updates of the BIFTs, see `control::handle_update_request`.
+    Updates,
+}
+
+impl ControlSource {
+    /// The socket is set non-blocking.
+    pub fn new(socket: socket2::Socket) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        Ok(ControlSource {
+            socket,
+            buffer: Vec::with_capacity(DEFAULT_MAX_PACKET_SIZE),
+            requests: ControlRequests::Control,
+        })
+    }
+
+    /// Update socket of a controller, also accepting the updates of the BIFTs.
+    pub fn updates(socket: socket2::Socket) -> io::Result<Self> {
+        let mut source = Self::new(socket)?;
+        source.requests = ControlRequests::Updates;
+        Ok(source)
+    }
+}
+
+impl EventSource for ControlSource {
+    fn fd(&self) -> Option<RawFd> {
+        Some(self.socket.as_raw_fd())
+    }
+
+    fn handle(&mut self, router: &mut Router) -> io::Result<()> {
+        drain(|| {
+            let (from, truncated) = recv_datagram(&self.socket, &mut self.buffer)?;
+            if truncated {
+                warn!("Dropped a truncated control request from {:?}", from);
+                return Ok(());
+            }
+            let response = match self.requests {
+                ControlRequests::Control => router.handle_control_request(&self.buffer),
+                ControlRequests::Updates => router.handle_update_request(&self.buffer),
+            };
+            if let Err(e) = self.socket.send_to(&response, &from) {
+                error!(
+                    "Error when answering a control request from {:?}: {:?}",
+                    from, e
+                );
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Write end of the pipe of the `SignalSource`, written by the signal handler.
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    // Only async-signal-safe calls. The signal is lost if the pipe is full.
+    let byte = signal as u8;
+    unsafe {
+        libc::write(
+            SIGNAL_PIPE.load(Ordering::Relaxed),
+            &byte as *const u8 as *const libc::c_void,
+            1,
+        );
+    }
+}
+
+/// Handler of the signals received by a `SignalSource`.
+pub type SignalHandler = Box<dyn FnMut(&mut Router, libc::c_int) -> io::Result<()>>;
+
+/// Signals received by the process, handled in the event loop with the router instead of
+/// in the signal handler, e.g., to save the counters before exiting. The signal handler
+/// writes the signal in a pipe polled by the loop. At most one source per process.
+pub struct SignalSource {
+    read: UnixStream,
+    _write: UnixStream,
+    signals: Vec<libc::c_int>,
+    handler: SignalHandler,
+}
+
+impl SignalSource {
+    pub fn new(
+        signals: &[libc::c_int],
+        handler: impl FnMut(&mut Router, libc::c_int) -> io::Result<()> + 'static,
+    ) -> io::Result<Self> {
+        let (read, write) = UnixStream::pair()?;
+        read.set_nonblocking(true)?;
+        write.set_nonblocking(true)?;
+        if SIGNAL_PIPE
+            .compare_exchange(-1, write.as_raw_fd(), Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "a signal source already exists",
+            ));
+        }
+        let source = SignalSource {
+            read,
+            _write: write,
+            signals: signals.to_vec(),
+            handler: Box::new(handler),
+        };
+        let handler = on_signal as extern "C" fn(libc::c_int);
+        for signal in signals {
+            set_signal_handler(*signal, handler as libc::sighandler_t)?;
+        }
+        Ok(source)
+    }
+}
+
+fn set_signal_handler(signal: libc::c_int, handler: libc::sighandler_t) -> io::Result<()> {
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = handler;
+    action.sa_flags = libc::SA_RESTART;
+    if unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// The default handlers of the signals are restored.
+impl Drop for SignalSource {
+    fn drop(&mut self) {
+        for signal in self.signals.iter() {
+            let _ = set_signal_handler(*signal, libc::SIG_DFL);
+        }
+        SIGNAL_PIPE.store(-1, Ordering::SeqCst);
+    }
+}
+
+impl EventSource for SignalSource {
+    fn fd(&self) -> Option<RawFd> {
+        Some(self.read.as_raw_fd())
+    }
+
+    fn handle(&mut self, router: &mut Router) -> io::Result<()> {
+        drain(|| {
+            let mut signal = [0u8];
+            match io::Read::read(&mut self.read, &mut signal)? {
+                0 => Err(io::ErrorKind::WouldBlock.into()),
+                _ => (self.handler)(router, signal[0] as libc::c_int),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::bier::example_state;
+    use crate::control::{ControlRequest, ControlResponse};
+    use crate::daemon::{BierDaemon, MemoryTransport, UdpTransport};
+    use std::os::unix::net::UnixDatagram;
+
+    /// Counts the calls of the handler, every `interval`.
+    struct Timer {
+        next: Instant,
+        interval: Duration,
+        nb_calls: std::rc::Rc<std::cell::Cell<usize>>,
+    }
+
+    impl EventSource for Timer {
+        fn deadline(&self) -> Option<Instant> {
+            Some(self.next)
+        }
+
+        fn handle(&mut self, _router: &mut Router) -> io::Result<()> {
+            self.nb_calls.set(self.nb_calls.get() + 1);
+            self.next += self.interval;
+            Ok(())
+        }
+    }
+
+    fn get_router() -> Router {
+        Router::new(
+            BierDaemon::builder()
+                .state(example_state())
+                .transport(MemoryTransport::default())
+                .build()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    /// Tests the sockets and the timers registered with the event loop.
+    fn test_event_loop() {
+        let mut router = get_router();
+        let mut event_loop = EventLoop::new().unwrap();
+
+        let nb_calls = std::rc::Rc::new(std::cell::Cell::new(0));
+        event_loop
+            .register(Timer {
+                next: Instant::now() + Duration::from_millis(10),
+                interval: Duration::from_secs(3600),
+                nb_calls: nb_calls.clone(),
+            })
+            .unwrap();
+        // Returns at the deadline of the timer.
+        let start = Instant::now();
+        event_loop.run_once(&mut router, None).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert_eq!(nb_calls.get(), 1);
+
+        let dir = std::env::temp_dir();
+        let control_path = dir.join(format!("bier-event-loop-{}", std::process::id()));
+        let requester_path = dir.join(format!("bier-event-loop-req-{}", std::process::id()));
+        let _ = std::fs::remove_file(&control_path);
+        let _ = std::fs::remove_file(&requester_path);
+        let control = UnixDatagram::bind(&control_path).unwrap();
+        let requester = UnixDatagram::bind(&requester_path).unwrap();
+        requester.connect(&control_path).unwrap();
+        let token = event_loop
+            .register(ControlSource::new(socket2::Socket::from(control)).unwrap())
+            .unwrap();
+        assert_eq!(token, mio::Token(1));
+        // Both requests are answered after a single event.
+        let request = serde_json::to_vec(&ControlRequest::Stats).unwrap();
+        requester.send(&request).unwrap();
+        requester.send(&request).unwrap();
+        event_loop
+            .run_once(&mut router, Some(Duration::from_secs(5)))
+            .unwrap();
+        requester.set_nonblocking(true).unwrap();
+        let mut buffer = vec![0u8; 65535];
+        for _ in 0..2 {
+            let read = requester.recv(&mut buffer).unwrap();
+            let response: ControlResponse = serde_json::from_slice(&buffer[..read]).unwrap();
+            assert!(matches!(response, ControlResponse::Stats(_)));
+        }
+        assert_eq!(nb_calls.get(), 1);
+        let _ = std::fs::remove_file(&control_path);
+        let _ = std::fs::remove_file(&requester_path);
+    }
+
+    #[test]
+    /// Tests that the UDP datagrams of a `UdpTransport` are processed without IP header, also
+    /// over IPv4.
+    fn test_udp_source() {
+        let mut state = example_state();
+        state.loopback = "127.0.0.1".parse().unwrap();
+        let daemon = BierDaemon::builder()
+            .state(state)
+            .transport(MemoryTransport::default())
+            .build()
+            .unwrap();
+        let mut router = Router::new(daemon);
+        let socket = crate::daemon::udp_socket("127.0.0.1".parse().unwrap(), 0).unwrap();
+        let address = socket.local_addr().unwrap().as_socket().unwrap();
+        let port = address.port();
+        let mut source = TransportSource::new(UdpTransport::new(socket, port), 1500).unwrap();
+
+        let send_info = crate::api::SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11010],
+            payload: &[1, 2, 3],
+            metadata: None,
+        };
+        let mut packet = vec![0u8; 23];
+        let header = crate::header::BierHeader::from_recv_info(&send_info).unwrap();
+        header.to_slice(&mut packet).unwrap();
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(&packet, address).unwrap();
+        // Waits for the datagram on the loopback.
+        std::thread::sleep(Duration::from_millis(50));
+        source.handle(&mut router).unwrap();
+        let stats = router.daemon().stats();
+        assert_eq!(stats.received.packets, 1);
+        assert!(stats.drops.is_empty());
+    }
+
+    #[test]
+    /// Tests that `drain` reads until `WouldBlock` after the interrupted calls and the errors
+    /// of a datagram, and stops on the errors of the socket.
+    fn test_drain() {
+        let mut results = vec![
+            Err(io::Error::from(io::ErrorKind::WouldBlock)),
+            Ok(()),
+            Err(io::Error::from_raw_os_error(libc::ECONNREFUSED)),
+            Err(io::Error::from(io::ErrorKind::Interrupted)),
+            Ok(()),
+        ];
+        assert!(drain(|| results.pop().unwrap()).is_ok());
+        assert!(results.is_empty());
+
+        let mut results = vec![
+            Ok(()),
+            Err(io::Error::from_raw_os_error(libc::EBADF)),
+            Ok(()),
+        ];
+        let err = drain(|| results.pop().unwrap()).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EBADF));
+        assert_eq!(results.len(), 1);
+    }
+
+    #[test]
+    /// Tests that the signals are handled by the event loop.
+    fn test_signal_source() {
+        let mut router = get_router();
+        let mut event_loop = EventLoop::new().unwrap();
+        let received = std::rc::Rc::new(std::cell::Cell::new(0));
+        let received_clone = received.clone();
+        let source = SignalSource::new(&[libc::SIGUSR1], move |router, signal| {
+            assert!(router.daemon().stats().drops.is_empty());
+            received_clone.set(signal);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            SignalSource::new(&[libc::SIGUSR2], |_, _| Ok(()))
+                .err()
+                .map(|e| e.kind()),
+            Some(io::ErrorKind::AlreadyExists)
+        );
+        event_loop.register(source).unwrap();
+
+        unsafe { libc::raise(libc::SIGUSR1) };
+        event_loop
+            .run_once(&mut router, Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(received.get(), libc::SIGUSR1);
+    }
+}
@@ -0,0 +1,1125 @@
+//! Setup of the BIER daemon from its options: the sockets, the transports of the copies and
+//! the sources of the event loop, around a `BierDaemon`. The `bier-rust` binary only parses
+//! the options, so that the whole daemon can be embedded or tested with injected transports.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::os::unix::prelude::AsRawFd;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use clap::Parser;
+use log::{error, info, warn};
+use serde_json::{from_reader, from_slice, from_value, Value};
+
+use crate::acl;
+#[cfg(target_os = "linux")]
+use crate::af_packet;
+use crate::bier::{BierState, Encapsulation, SocketConfig};
+use crate::bierin6::bierin6_socket;
+use crate::daemon::{BierDaemon, ConnectedTransport, OverflowPolicy, Transport, UdpTransport};
+use crate::dispatch::{Destination, Dispatcher};
+#[cfg(target_os = "linux")]
+use crate::ethernet::{ETHERTYPE_BIER, ETHERTYPE_MPLS};
+use crate::frr::FrrExport;
+#[cfg(all(feature = "tun", target_os = "linux"))]
+use crate::header::{PROTO_IPV4, PROTO_IPV6};
+use crate::impairment::{ImpairedTransport, ImpairmentConfig};
+use crate::liveness::{LivenessSource, DEFAULT_INTERVAL, DEFAULT_MULTIPLIER};
+#[cfg(target_os = "linux")]
+use crate::membership::{MembershipServer, MembershipSource, DEFAULT_HOLD_TIME};
+use crate::packet_log::PacketLog;
+use crate::pcap::BierCapture;
+#[cfg(target_os = "linux")]
+use crate::pipeline::{RxStage, TxStage, DEFAULT_QUEUE_DEPTH};
+use crate::router::{Framing, Router};
+use crate::runtime::{
+    AppSource, ControlSource, EventLoop, EventSource, RawIpSource, SignalSource, TransportSource,
+    DEFAULT_MAX_PACKET_SIZE,
+};
+use crate::snapshot;
+use crate::stats::Stats;
+#[cfg(feature = "tokio")]
+use crate::tokio_runtime::TokioEventLoop;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+use crate::uring_runtime::{UringEventLoop, UringTransport, DEFAULT_RECV_DEPTH, DEFAULT_RING_SIZE};
+use crate::Error;
+
+/// Options of the daemon, i.e., the command line of `bier-rust`.
+#[derive(Parser, Debug, Clone)]
+#[clap(name = "bier-rust", about = None, long_about = None)]
+pub struct DaemonConfig {
+    /// Path to the configuration file of the BFR.
+    #[clap(
+        short = 'c',
+        long = "config",
+        value_parser,
+        default_value = "configs/example.json"
+    )]
+    pub config: String,
+    /// The configuration file is a BIRT exported by FRRouting (see `frr.rs`).
+    #[clap(long = "frr", value_parser)]
+    pub frr: bool,
+    /// Default UNIX socket address to forward the packets received by this BFER.
+    /// None by default.
+    #[clap(short = 'd', long = "default", value_parser)]
+    pub default_unix_path: Option<String>,
+    /// Forwards the packets with a proto to the UNIX socket of its application, as
+    /// `<proto>=<path>`. Can be repeated. The other protos go to the default path.
+    #[clap(long = "deliver", value_parser = parse_delivery)]
+    pub deliveries: Vec<(u16, String)>,
+    /// UNIX socket address of the BIER daemon.
+    #[clap(long = "bier-path", value_parser)]
+    pub bier_unix_path: String,
+    /// UNIX socket address of the control socket, e.g., for bier-top.
+    /// None by default.
+    #[clap(long = "control", value_parser)]
+    pub control_unix_path: Option<String>,
+    /// UNIX socket address of the update socket, where a controller adds, removes and
+    /// modifies the BIFT entries. None by default.
+    #[clap(long = "update-socket", value_parser)]
+    pub update_unix_path: Option<String>,
+    /// UDP port of the membership socket of this BFIR, bound on the loopback, receiving the
+    /// receivers reported by the MLD/IGMP listeners of the BFERs. None by default.
+    #[cfg(target_os = "linux")]
+    #[clap(
+        long = "membership-port",
+        value_parser,
+        requires = "membership_allowed"
+    )]
+    pub membership_port: Option<u16>,
+    /// Address of a BFER allowed to report its receivers to the membership socket.
+    /// Can be repeated.
+    #[cfg(target_os = "linux")]
+    #[clap(long = "membership-allow", value_parser, requires = "membership_port")]
+    pub membership_allowed: Vec<IpAddr>,
+    /// Seconds after which the membership socket expires a join not refreshed by its BFER.
+    #[cfg(target_os = "linux")]
+    #[clap(long = "membership-hold-time", value_parser, default_value_t = DEFAULT_HOLD_TIME.as_secs())]
+    pub membership_hold_time: u64,
+    /// Listens for the MLDv2 reports of the receivers on this downstream interface.
+    #[cfg(target_os = "linux")]
+    #[clap(long = "mld", value_parser, requires = "membership_upstream")]
+    pub mld_ifname: Option<String>,
+    /// Listens for the IGMPv3 reports of the receivers on this downstream interface.
+    #[cfg(target_os = "linux")]
+    #[clap(long = "igmp", value_parser, requires = "membership_upstream")]
+    pub igmp_ifname: Option<String>,
+    /// Address and port of the membership socket of the BFIR, to which the receivers of
+    /// `--mld` and `--igmp` are reported.
+    #[cfg(target_os = "linux")]
+    #[clap(long = "membership-upstream", value_parser)]
+    pub membership_upstream: Option<std::net::SocketAddr>,
+    /// Sub-domain of the BFR-id of this BFER reported to the BFIR.
+    #[cfg(target_os = "linux")]
+    #[clap(long = "membership-sub-domain", value_parser, default_value_t = 0)]
+    pub membership_sub_domain: u8,
+    /// Appends one JSON line per processed packet to this file,
+    /// or to a stream socket with `unix:<path>` or `tcp:<address>`.
+    #[clap(long = "packet-log", value_parser)]
+    pub packet_log: Option<String>,
+    /// Appends the replication decisions of each processed packet as JSON lines, with the
+    /// same destinations as the packet log. For debugging purposes.
+    #[clap(long = "trace", value_parser)]
+    pub trace: Option<String>,
+    /// Records the BIER packets received and sent in this pcap file or FIFO, with a synthetic
+    /// IP header toward the next hop of each copy.
+    #[clap(long = "pcap", value_parser)]
+    pub pcap: Option<String>,
+    /// JSON file with the impairments (drop, delay, duplication) applied per neighbor.
+    /// For testing purposes only.
+    #[clap(long = "impairments", value_parser)]
+    pub impairments: Option<String>,
+    /// Caches the copies of this number of recent bitstrings. Disabled by default.
+    #[clap(long = "result-cache", value_parser)]
+    pub result_cache: Option<usize>,
+    /// Sends at most this number of copies of a packet at once. Unlimited by default.
+    #[clap(long = "max-copies", value_parser)]
+    pub max_copies: Option<usize>,
+    /// The copies beyond `--max-copies` are sent by the next iterations of the event loop
+    /// instead of being dropped.
+    #[clap(long = "defer-copies", value_parser)]
+    pub defer_copies: bool,
+    /// Receives, processes and sends the packets in different threads, connected by
+    /// bounded queues. Packets arriving on a full queue are dropped. Linux only.
+    #[cfg(target_os = "linux")]
+    #[clap(long = "pipeline", value_parser)]
+    pub pipeline: bool,
+    /// Depth of the queue of the received packets, with `--pipeline`.
+    #[cfg(target_os = "linux")]
+    #[clap(long = "rx-queue-depth", value_parser, default_value_t = DEFAULT_QUEUE_DEPTH)]
+    pub rx_queue_depth: usize,
+    /// Depth of the queue of the copies toward each neighbor, with `--pipeline`.
+    #[cfg(target_os = "linux")]
+    #[clap(long = "tx-queue-depth", value_parser, default_value_t = DEFAULT_QUEUE_DEPTH)]
+    pub tx_queue_depth: usize,
+    /// Carries the BIER packets in UDP datagrams to this port of the next hops, instead of
+    /// the raw IP socket. Does not require privileges, e.g., to test applications on macOS.
+    #[clap(long = "udp", value_parser)]
+    pub udp_port: Option<u16>,
+    /// UDP port of the liveness prober, which sends hellos to the same port of the next hops.
+    /// The entries use their next path toward a next hop which stops answering, thus all the
+    /// neighbors must run the prober. Disabled by default.
+    #[clap(long = "liveness", value_parser)]
+    pub liveness_port: Option<u16>,
+    /// Interval between the hellos of the liveness prober, in milliseconds.
+    #[clap(
+        long = "liveness-interval",
+        value_parser = clap::value_parser!(u64).range(1..),
+        default_value_t = DEFAULT_INTERVAL.as_millis() as u64
+    )]
+    pub liveness_interval: u64,
+    /// Number of intervals without answer before a next hop is declared dead.
+    #[clap(
+        long = "liveness-multiplier",
+        value_parser = clap::value_parser!(u32).range(1..),
+        default_value_t = DEFAULT_MULTIPLIER
+    )]
+    pub liveness_multiplier: u32,
+    /// Sends the copies toward the busiest next hops, at most this number, with one socket
+    /// connected to each of them. The other copies use the shared socket. Without `--pipeline`.
+    #[clap(long = "connected-sockets", value_parser)]
+    pub connected_sockets: Option<usize>,
+    /// Size of the receive buffers of the raw IP and application sockets. Larger packets
+    /// are dropped with the `Truncated` drop reason.
+    #[clap(long = "max-packet-size", value_parser, default_value_t = DEFAULT_MAX_PACKET_SIZE)]
+    pub max_packet_size: usize,
+    /// IP protocol number of the raw IP socket, instead of the `socket.proto` of the
+    /// configuration, or 253 by default.
+    #[clap(long = "proto", value_parser)]
+    pub proto: Option<u8>,
+    /// Size of the kernel receive buffer of the raw IP or UDP socket, in bytes, instead of the
+    /// `socket.recv_buffer` of the configuration, or the system default.
+    #[clap(long = "recv-buffer", value_parser)]
+    pub recv_buffer: Option<usize>,
+    /// Hop limit (IPv6) or TTL (IPv4) of the sent packets, instead of the `socket.hop_limit` of
+    /// the configuration, or the system default.
+    #[clap(long = "hop-limit", value_parser)]
+    pub hop_limit: Option<u8>,
+    /// Binds the raw IP or UDP socket to this interface, instead of the `socket.interface` of
+    /// the configuration. Linux only.
+    #[clap(long = "bind-interface", value_parser)]
+    pub bind_interface: Option<String>,
+    /// Sends the copies from this address, instead of the `socket.source` of the configuration.
+    /// The `source` of a neighbor takes precedence. Without `--udp`.
+    #[clap(long = "source", value_parser)]
+    pub source: Option<std::net::IpAddr>,
+    /// Saves the counters in this file on SIGINT and SIGTERM, and restores them at startup,
+    /// so that the cumulative counters survive a graceful restart.
+    #[clap(long = "stats-file", value_parser)]
+    pub stats_file: Option<String>,
+    /// Exports the traces and metrics to this OTLP/HTTP collector, e.g., `http://localhost:4318`.
+    #[cfg(feature = "otel")]
+    #[clap(long = "otlp", value_parser)]
+    pub otlp_endpoint: Option<String>,
+    /// Exposes the counters in the Prometheus text format at `http://<address>/metrics`,
+    /// e.g., `[::1]:9100`.
+    #[cfg(feature = "prometheus")]
+    #[clap(long = "prometheus", value_parser)]
+    pub prometheus_addr: Option<std::net::SocketAddr>,
+    /// Runs the sources on a tokio runtime instead of the mio event loop.
+    #[cfg(feature = "tokio")]
+    #[clap(long = "tokio", value_parser)]
+    pub tokio: bool,
+    /// Runs the sources on an io_uring event loop instead of the mio event loop. The packets
+    /// are received with several operations in flight, and the copies of a packet are sent
+    /// with a single system call, except with `--pipeline` or `--connected-sockets`.
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    #[clap(long = "uring", value_parser)]
+    pub uring: bool,
+    /// Name of a TUN device: the IP multicast packets routed to the device are encapsulated
+    /// toward the BFERs of their group in the overlay, and the IP payloads delivered to this
+    /// BFER are written to the device.
+    #[cfg(all(feature = "tun", target_os = "linux"))]
+    #[clap(long = "tun", value_parser)]
+    pub tun_ifname: Option<String>,
+    /// Receives and sends the BIER frames (ethertype 0xAB37) of this interface with an
+    /// AF_PACKET socket, for the neighbors with the Ethernet encapsulation, and the MPLS frames
+    /// (ethertype 0x8847) with MPLS neighbors or labels in the configuration.
+    #[cfg(target_os = "linux")]
+    #[clap(long = "ethernet", value_parser)]
+    pub ethernet_ifname: Option<String>,
+    /// Receives and sends the BIER frames (ethertype 0xAB37) of this interface with an AF_XDP
+    /// socket. The copies toward the next hops without MAC address use the raw IP socket.
+    #[cfg(all(feature = "xdp", target_os = "linux"))]
+    #[clap(long = "xdp", value_parser)]
+    pub xdp_ifname: Option<String>,
+    /// Queue of the interface bound to the AF_XDP socket.
+    #[cfg(all(feature = "xdp", target_os = "linux"))]
+    #[clap(long = "xdp-queue", value_parser, default_value_t = 0)]
+    pub xdp_queue: u32,
+    /// Pinned XSKMAP of the XDP program redirecting the BIER frames, e.g.,
+    /// `/sys/fs/bpf/bier/xsks_map`. The socket is inserted at the index of its queue.
+    #[cfg(all(feature = "xdp", target_os = "linux"))]
+    #[clap(long = "xdp-map", value_parser)]
+    pub xdp_map: Option<String>,
+    /// JSON file with the MAC address of each next hop, e.g., `{"fc00:b::1": "02:00:00:00:00:0b"}`.
+    #[cfg(all(feature = "xdp", target_os = "linux"))]
+    #[clap(long = "xdp-neighbors", value_parser)]
+    pub xdp_neighbors: Option<String>,
+    /// Requires the zero-copy mode of the driver for the AF_XDP socket.
+    #[cfg(all(feature = "xdp", target_os = "linux"))]
+    #[clap(long = "xdp-zero-copy", value_parser)]
+    pub xdp_zero_copy: bool,
+}
+
+impl DaemonConfig {
+    /// Default options with the configuration file at `config` and the UNIX socket of the
+    /// daemon at `bier_unix_path`.
+    pub fn new(config: &str, bier_unix_path: &str) -> Self {
+        DaemonConfig::parse_from([
+            "bier-rust",
+            "--config",
+            config,
+            "--bier-path",
+            bier_unix_path,
+        ])
+    }
+}
+
+/// Adds the step of the startup that failed to the error of a result.
+trait Context<T> {
+    fn context(self, step: &str) -> Result<T, String>;
+}
+
+impl<T, E: std::fmt::Display> Context<T> for Result<T, E> {
+    fn context(self, step: &str) -> Result<T, String> {
+        self.map_err(|e| format!("{}: {}", step, e))
+    }
+}
+
+/// BIER daemon with its sockets, ready to run its event loop.
+pub struct Daemon {
+    router: Router,
+    sources: Vec<Box<dyn EventSource>>,
+    /// Raw IP socket received by the io_uring event loop itself.
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    uring_ip_sock: Option<socket2::Socket>,
+    options: DaemonConfig,
+}
+
+impl Daemon {
+    /// Opens the sockets of the daemon and loads its configuration, following `options`.
+    pub fn new(options: DaemonConfig) -> Result<Self, String> {
+        Self::build(options, None)
+    }
+
+    /// Same as `new`, but the copies with the IP encapsulation are sent with `transport`
+    /// instead of the raw IP socket, e.g., to test the daemon without privileges.
+    pub fn with_transport(
+        options: DaemonConfig,
+        transport: impl Transport + Send + 'static,
+    ) -> Result<Self, String> {
+        Self::build(options, Some(Box::new(transport)))
+    }
+
+    fn build(
+        options: DaemonConfig,
+        transport: Option<Box<dyn Transport + Send>>,
+    ) -> Result<Self, String> {
+        let mut bier_state = load_state(&options.config, options.frr)
+            .context(&format!("Cannot load the configuration {}", options.config))?;
+        override_socket_config(&mut bier_state.socket, &options);
+        bier_state
+            .validate_sockets()
+            .context("Invalid socket options")?;
+        if bier_state.socket.source.is_some() && options.udp_port.is_some() {
+            return Err("The source address of the socket requires the raw IP socket".into());
+        }
+
+        let _ = std::fs::remove_file(&options.bier_unix_path);
+        let bier_unix_sock = unix_socket(&options.bier_unix_path)?;
+        // The credentials of the applications are only needed to enforce the access control list.
+        if !bier_state.api_acl.is_empty() {
+            acl::enable_credentials(bier_unix_sock.as_raw_fd())
+                .context("Cannot enable the credentials on the UNIX socket")?;
+        }
+
+        // The address family of the raw socket follows the one of the loopback.
+        let loopback = bier_state.get_loopback();
+        let bier_ip_sock = match options.udp_port {
+            Some(port) => {
+                let sock = crate::daemon::udp_socket(loopback, port)
+                    .context("Impossible to bind the UDP socket on the loopback")?;
+                crate::daemon::configure_socket(&sock, loopback, &bier_state.socket)
+                    .context("Cannot set the options of the UDP socket")?;
+                sock
+            }
+            None => crate::daemon::raw_socket(loopback, &bier_state.socket).context(&format!(
+                "Impossible to create the IP raw socket with proto {}",
+                bier_state.socket.proto()
+            ))?,
+        };
+        // Without `--udp`, the neighbors with the UDP encapsulation use the port of the
+        // configuration, on which the daemon also listens.
+        let bier_udp_sock = match (options.udp_port, bier_state.udp_port) {
+            (None, Some(port)) => Some((
+                crate::daemon::udp_socket(loopback, port)
+                    .context("Impossible to bind the UDP socket of the configuration")?,
+                port,
+            )),
+            _ => None,
+        };
+        let udp_neighbors = bier_state
+            .neighbors
+            .values()
+            .any(|neighbor| neighbor.encapsulation == Encapsulation::Udp);
+        if udp_neighbors && options.udp_port.is_none() && bier_udp_sock.is_none() {
+            return Err(
+                "The neighbors with the UDP encapsulation require the udp_port of the configuration"
+                    .into(),
+            );
+        }
+        // The neighbors with the Ethernet and MPLS encapsulations are reached through the
+        // interface of `--ethernet`, at their MAC address of the configuration or of the neighbor
+        // table. The MPLS frames have their own socket.
+        let has_neighbors = |encapsulation| {
+            bier_state
+                .neighbors
+                .values()
+                .any(|neighbor| neighbor.encapsulation == encapsulation)
+        };
+        let l2_neighbors =
+            has_neighbors(Encapsulation::Ethernet) || has_neighbors(Encapsulation::Mpls);
+        #[cfg(target_os = "linux")]
+        let (ethernet, mpls) = match &options.ethernet_ifname {
+            Some(ifname) => {
+                let macs: std::collections::HashMap<_, _> = bier_state
+                    .neighbors
+                    .iter()
+                    .filter_map(|(next_hop, neighbor)| Some((*next_hop, neighbor.mac?)))
+                    .collect();
+                let max = options.max_packet_size;
+                let ethernet = af_packet::open(ifname, ETHERTYPE_BIER, macs.clone(), max)
+                    .context("Cannot open the AF_PACKET socket")?;
+                let mpls = has_neighbors(Encapsulation::Mpls) || !bier_state.mpls_labels.is_empty();
+                let mpls = match mpls {
+                    true => Some(
+                        af_packet::open(ifname, ETHERTYPE_MPLS, macs, max)
+                            .context("Cannot open the AF_PACKET socket of the MPLS frames")?,
+                    ),
+                    false => None,
+                };
+                (Some(ethernet), mpls)
+            }
+            None => (None, None),
+        };
+        #[cfg(target_os = "linux")]
+        let ethernet_interface = ethernet.is_some();
+        #[cfg(not(target_os = "linux"))]
+        let ethernet_interface = false;
+        if l2_neighbors && !ethernet_interface {
+            return Err(
+                "The neighbors with the Ethernet or MPLS encapsulation require --ethernet".into(),
+            );
+        }
+        // The neighbors with the BIERin6 encapsulation share a raw IPv6 socket, which also
+        // receives their BIERin6 packets.
+        let bierin6_sock = match has_neighbors(Encapsulation::Bierin6) {
+            true if loopback.is_ipv4() => {
+                return Err("The BIERin6 encapsulation requires an IPv6 loopback".into())
+            }
+            true => Some(bierin6_socket().context("Impossible to create the BIERin6 socket")?),
+            false => None,
+        };
+
+        // The connected sockets are created for the next hops of the compiled BIFTs.
+        let busiest_next_hops = match options.connected_sockets {
+            Some(_) => {
+                bier_state
+                    .compile()
+                    .context("Cannot compile the BIER state")?;
+                let compiled = bier_state.compiled.as_ref();
+                compiled
+                    .map(|compiled| compiled.busiest_next_hops())
+                    .unwrap_or_default()
+            }
+            None => Vec::new(),
+        };
+
+        // Packets for this BFER are delivered to the program of their proto, or to the default one.
+        let mut dispatcher = Dispatcher::new(
+            bier_unix_sock
+                .try_clone()
+                .context("Cannot clone the UNIX socket")?,
+        );
+        if let Some(path) = &options.default_unix_path {
+            dispatcher.set_default(Some(
+                Destination::unix(path).context("Invalid default UNIX path")?,
+            ));
+        }
+        for (proto, path) in &options.deliveries {
+            dispatcher.insert(
+                *proto,
+                Destination::unix(path).context("Invalid UNIX path of a proto")?,
+            );
+        }
+        #[cfg(all(feature = "tun", target_os = "linux"))]
+        let tun_source = match &options.tun_ifname {
+            Some(ifname) => {
+                let source =
+                    crate::tun::TunSource::open(ifname).context("Cannot open the TUN device")?;
+                for proto in [PROTO_IPV4, PROTO_IPV6] {
+                    let destination = source
+                        .destination()
+                        .context("Cannot clone the TUN device")?;
+                    dispatcher.insert(proto as u16, destination);
+                }
+                Some(source)
+            }
+            None => None,
+        };
+        // The copies are sent from the source address with a socket of their own, and toward the
+        // neighbors with an interface or a source address with a socket bound to them.
+        let socket_config = bier_state.socket.clone();
+        let send_sock = match socket_config.source {
+            Some(_) => crate::daemon::egress_raw_socket(loopback, &socket_config)
+                .context("Cannot bind the raw IP socket to the source address")?,
+            None => bier_ip_sock
+                .try_clone()
+                .context("Cannot clone the raw IP socket")?,
+        };
+        let bound_neighbors: HashMap<IpAddr, SocketConfig> = bier_state
+            .neighbors
+            .iter()
+            .filter(|(_, neighbor)| neighbor.is_bound())
+            .filter(|(_, neighbor)| neighbor.encapsulation == Encapsulation::Ip)
+            .map(|(next_hop, neighbor)| (*next_hop, neighbor.socket_config(&socket_config)))
+            .collect();
+        let loader_options = options.clone();
+        let mut builder = BierDaemon::builder()
+            .state(bier_state)
+            .state_loader(move || {
+                let mut state = load_state(&loader_options.config, loader_options.frr)?;
+                override_socket_config(&mut state.socket, &loader_options);
+                Ok(state)
+            });
+        let transport = match transport {
+            Some(transport) => transport,
+            None => ip_transport(
+                &options,
+                send_sock,
+                busiest_next_hops,
+                bound_neighbors,
+                socket_config,
+            )?,
+        };
+        // With AF_XDP, the raw IP socket only sends the copies toward the next hops without MAC.
+        #[cfg(all(feature = "xdp", target_os = "linux"))]
+        let (xdp_source, transport) = match &options.xdp_ifname {
+            Some(ifname) => {
+                let mut config = crate::xdp::XdpConfig::new(ifname);
+                config.queue_id = options.xdp_queue;
+                config.xsk_map = options.xdp_map.clone();
+                config.zero_copy = options.xdp_zero_copy;
+                if let Some(path) = &options.xdp_neighbors {
+                    let file =
+                        std::fs::File::open(path).context("Cannot find the XDP neighbors file")?;
+                    config.neighbors =
+                        from_reader(file).context("Cannot parse the XDP neighbors")?;
+                }
+                let (source, xdp_transport) =
+                    crate::xdp::open(&config).context("Cannot open the AF_XDP socket")?;
+                let transport: Box<dyn Transport + Send> =
+                    Box::new(xdp_transport.fallback(transport));
+                (Some(source), transport)
+            }
+            None => (None, transport),
+        };
+        builder = match &options.impairments {
+            Some(path) => {
+                let file = std::fs::File::open(path).context("Cannot find the impairment file")?;
+                let config: ImpairmentConfig =
+                    from_reader(file).context("Cannot parse the impairments")?;
+                builder.transport(ImpairedTransport::new(transport, config))
+            }
+            None => builder.transport(transport),
+        };
+        match (&bier_udp_sock, options.udp_port) {
+            (Some((sock, port)), _) => {
+                let sock = sock.try_clone().context("Cannot clone the UDP socket")?;
+                builder = builder.udp_transport(UdpTransport::new(sock, *port));
+            }
+            (None, Some(port)) => {
+                let sock = bier_ip_sock
+                    .try_clone()
+                    .context("Cannot clone the UDP socket")?;
+                builder = builder.udp_transport(UdpTransport::new(sock, port));
+            }
+            (None, None) => (),
+        }
+        #[cfg(target_os = "linux")]
+        let (ethernet_source, ethernet_transport) = ethernet.unzip();
+        #[cfg(target_os = "linux")]
+        if let Some(transport) = ethernet_transport {
+            builder = builder.encapsulation_transport(Encapsulation::Ethernet, transport);
+        }
+        if let Some(sock) = &bierin6_sock {
+            let sock = sock
+                .try_clone()
+                .context("Cannot clone the BIERin6 socket")?;
+            builder = builder.encapsulation_transport(Encapsulation::Bierin6, sock);
+        }
+        #[cfg(target_os = "linux")]
+        let (mpls_source, mpls_transport) = mpls.unzip();
+        #[cfg(target_os = "linux")]
+        if let Some(transport) = mpls_transport {
+            builder = builder.encapsulation_transport(Encapsulation::Mpls, transport);
+        }
+        // The applications register their proto on the UNIX socket of the daemon.
+        let dispatcher = Arc::new(Mutex::new(dispatcher));
+        let registrations = dispatcher.clone();
+        builder = builder
+            .on_local_delivery(move |recv_info| {
+                dispatcher.lock().unwrap().deliver(recv_info);
+            })
+            .on_registration(move |registration, sender| {
+                let mut dispatcher = registrations.lock().unwrap();
+                if registration.path.is_empty() {
+                    dispatcher.unregister(registration.proto, sender);
+                    return Ok(());
+                }
+                dispatcher
+                    .register(registration.proto, sender, registration.version)
+                    .map_err(|e| {
+                        warn!("Refused the registration of {:?}: {}", sender, e);
+                        Error::Registration
+                    })
+            });
+        if let Some(capacity) = options.result_cache {
+            builder = builder.result_cache(capacity);
+        }
+        if let Some(max) = options.max_copies {
+            let overflow = if options.defer_copies {
+                OverflowPolicy::Defer
+            } else {
+                OverflowPolicy::Drop
+            };
+            builder = builder.max_copies(max, overflow);
+        }
+        if let Some(path) = options
+            .stats_file
+            .as_deref()
+            .filter(|path| std::path::Path::new(path).exists())
+        {
+            builder =
+                builder.restored_stats(Stats::load(path).context("Cannot restore the counters")?);
+        }
+        if let Some(destination) = &options.packet_log {
+            builder = builder
+                .packet_log(PacketLog::open(destination).context("Cannot open the packet log")?);
+        }
+        if let Some(destination) = &options.trace {
+            builder = builder.trace(PacketLog::open(destination).context("Cannot open the trace")?);
+        }
+        if let Some(path) = &options.pcap {
+            builder = builder.capture(BierCapture::open(path).context("Cannot open the capture")?);
+        }
+        #[cfg(feature = "otel")]
+        let telemetry = match options.otlp_endpoint.as_deref() {
+            Some(endpoint) => Some(
+                crate::telemetry::Telemetry::otlp(Some(endpoint), loopback)
+                    .context("Cannot create the OTLP exporters")?,
+            ),
+            None => None,
+        };
+        #[cfg(feature = "otel")]
+        if let Some(telemetry) = &telemetry {
+            builder = builder.telemetry(telemetry);
+        }
+        let router = Router::new(builder.build().context("Cannot build the BIER daemon")?);
+
+        // The sockets are non-blocking once registered, including their clones used to send.
+        // With the pipeline, the raw socket stays blocking for the threads of the stages.
+        let mut sources: Vec<Box<dyn EventSource>> = Vec::new();
+        // With `--uring`, the packets of the raw IP socket are received by the event loop itself.
+        #[cfg(all(feature = "uring", target_os = "linux"))]
+        let (bier_ip_sock, uring_ip_sock) = match options.uring && !options.pipeline {
+            true => (None, Some(bier_ip_sock)),
+            false => (Some(bier_ip_sock), None),
+        };
+        #[cfg(not(all(feature = "uring", target_os = "linux")))]
+        let bier_ip_sock = Some(bier_ip_sock);
+        if let Some(bier_ip_sock) = bier_ip_sock {
+            #[cfg(target_os = "linux")]
+            if options.pipeline {
+                let stage = RxStage::spawn(
+                    bier_ip_sock,
+                    ip_framing(options.udp_port),
+                    options.rx_queue_depth,
+                    options.max_packet_size,
+                );
+                sources.push(Box::new(stage.context("Cannot start the receive stage")?));
+            } else {
+                let source = ip_source(bier_ip_sock, options.udp_port, options.max_packet_size);
+                sources.push(source.context("Cannot register the raw IP socket")?);
+            }
+            #[cfg(not(target_os = "linux"))]
+            {
+                let source = ip_source(bier_ip_sock, options.udp_port, options.max_packet_size);
+                sources.push(source.context("Cannot register the raw IP socket")?);
+            }
+        }
+        if let Some((sock, port)) = bier_udp_sock {
+            let source =
+                TransportSource::new(UdpTransport::new(sock, port), options.max_packet_size);
+            sources.push(Box::new(source.context("Cannot register the UDP socket")?));
+        }
+        if let Some(sock) = bierin6_sock {
+            let source = RawIpSource::bierin6(sock, options.max_packet_size);
+            sources.push(Box::new(
+                source.context("Cannot register the BIERin6 socket")?,
+            ));
+        }
+        #[cfg(target_os = "linux")]
+        for source in ethernet_source.into_iter().chain(mpls_source) {
+            sources.push(Box::new(source));
+        }
+        #[cfg(all(feature = "xdp", target_os = "linux"))]
+        if let Some(source) = xdp_source {
+            sources.push(Box::new(source));
+        }
+        #[cfg(all(feature = "tun", target_os = "linux"))]
+        if let Some(source) = tun_source {
+            sources.push(Box::new(source));
+        }
+        let source = AppSource::new(bier_unix_sock, options.max_packet_size);
+        sources.push(Box::new(source.context("Cannot register the UNIX socket")?));
+        if let Some(path) = &options.control_unix_path {
+            let _ = std::fs::remove_file(path);
+            let source = ControlSource::new(unix_socket(path)?);
+            sources.push(Box::new(
+                source.context("Cannot register the control socket")?,
+            ));
+        }
+        if let Some(path) = &options.update_unix_path {
+            let _ = std::fs::remove_file(path);
+            let source = ControlSource::updates(unix_socket(path)?);
+            sources.push(Box::new(
+                source.context("Cannot register the update socket")?,
+            ));
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(port) = options.membership_port {
+            let sock = std::net::UdpSocket::bind((loopback, port))
+                .context("Cannot bind the membership socket")?;
+            let hold_time = Duration::from_secs(options.membership_hold_time);
+            let source = MembershipServer::new(sock, options.membership_allowed.clone(), hold_time);
+            sources.push(Box::new(
+                source.context("Cannot register the membership socket")?,
+            ));
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(upstream) = options.membership_upstream {
+            let sub_domain = options.membership_sub_domain;
+            if let Some(ifname) = &options.mld_ifname {
+                let source = MembershipSource::mld(ifname, upstream, sub_domain)
+                    .context("Cannot listen for the MLD reports")?;
+                sources.push(Box::new(source));
+            }
+            if let Some(ifname) = &options.igmp_ifname {
+                let source = MembershipSource::igmp(ifname, upstream, sub_domain)
+                    .context("Cannot listen for the IGMP reports")?;
+                sources.push(Box::new(source));
+            }
+        }
+        if let Some(port) = options.liveness_port {
+            let unspecified = match loopback {
+                IpAddr::V4(_) => IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED),
+                IpAddr::V6(_) => IpAddr::V6(std::net::Ipv6Addr::UNSPECIFIED),
+            };
+            // Bound to all the addresses, as the next hops of the neighbors may be the
+            // addresses of the interfaces instead of the loopback.
+            let sock = std::net::UdpSocket::bind((unspecified, port))
+                .context("Cannot bind the liveness socket")?;
+            let interval = Duration::from_millis(options.liveness_interval);
+            let source = LivenessSource::new(sock, port, interval, options.liveness_multiplier);
+            sources.push(Box::new(
+                source.context("Cannot register the liveness socket")?,
+            ));
+        }
+        // SIGHUP reloads the configuration without closing the sockets. With `--stats-file`,
+        // SIGINT and SIGTERM save the counters before exiting.
+        let mut signals = vec![libc::SIGHUP];
+        if options.stats_file.is_some() {
+            signals.extend([libc::SIGINT, libc::SIGTERM]);
+        }
+        let (config, stats_file) = (options.config.clone(), options.stats_file.clone());
+        let source = SignalSource::new(&signals, move |router, signal| {
+            if signal == libc::SIGHUP {
+                match router.daemon_mut().reload() {
+                    Ok(report) => info!("Reloaded the configuration {}: {}", config, report),
+                    Err(e) => error!(
+                        "Cannot reload the configuration {}, keeping the previous one: {}",
+                        config, e
+                    ),
+                }
+                return Ok(());
+            }
+            if let Some(path) = &stats_file {
+                info!("Saving the counters in {} on signal {}", path, signal);
+                router.daemon().stats().save(path)?;
+            }
+            std::process::exit(0)
+        });
+        sources.push(Box::new(source.context("Cannot register the signals")?));
+        // The exported metrics are refreshed periodically.
+        #[cfg(feature = "otel")]
+        if let Some(telemetry) = telemetry {
+            sources.push(Box::new(telemetry));
+        }
+        #[cfg(feature = "prometheus")]
+        if let Some(addr) = options.prometheus_addr {
+            let exporter = crate::prometheus::PrometheusExporter::bind(addr)
+                .context("Cannot bind the Prometheus endpoint")?;
+            sources.push(Box::new(exporter));
+        }
+
+        Ok(Daemon {
+            router,
+            sources,
+            #[cfg(all(feature = "uring", target_os = "linux"))]
+            uring_ip_sock,
+            options,
+        })
+    }
+
+    pub fn router(&self) -> &Router {
+        &self.router
+    }
+
+    pub fn router_mut(&mut self) -> &mut Router {
+        &mut self.router
+    }
+
+    pub fn options(&self) -> &DaemonConfig {
+        &self.options
+    }
+
+    /// Runs the event loop selected by the options until an error, or a signal.
+    pub fn run(mut self) -> Result<(), String> {
+        #[cfg(feature = "tokio")]
+        if self.options.tokio {
+            let mut event_loop = TokioEventLoop::new();
+            for source in self.sources {
+                event_loop.register(source);
+            }
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .context("Cannot create the tokio runtime")?;
+            return runtime
+                .block_on(event_loop.run(&mut self.router))
+                .context("Error of the event loop");
+        }
+        #[cfg(all(feature = "uring", target_os = "linux"))]
+        if self.options.uring {
+            let mut event_loop = UringEventLoop::new(DEFAULT_RING_SIZE)
+                .context("Cannot create the io_uring instance")?;
+            if let Some(sock) = self.uring_ip_sock {
+                event_loop
+                    .register_socket(
+                        sock,
+                        ip_framing(self.options.udp_port),
+                        self.options.max_packet_size,
+                        DEFAULT_RECV_DEPTH,
+                    )
+                    .context("Cannot register the raw IP socket")?;
+            }
+            for source in self.sources {
+                event_loop
+                    .register(source)
+                    .context("Cannot register a source")?;
+            }
+            return event_loop
+                .run(&mut self.router)
+                .context("Error of the event loop");
+        }
+        let mut event_loop = EventLoop::new().context("Cannot create the event loop")?;
+        for source in self.sources {
+            event_loop
+                .register(source)
+                .context("Cannot register a source")?;
+        }
+        event_loop
+            .run(&mut self.router)
+            .context("Error of the event loop")
+    }
+}
+
+/// Transport of the copies with the IP encapsulation, sent with `send_sock` through the
+/// pipeline, the connected and bound sockets, or io_uring, following the options.
+fn ip_transport(
+    options: &DaemonConfig,
+    send_sock: socket2::Socket,
+    busiest_next_hops: Vec<IpAddr>,
+    bound_neighbors: HashMap<IpAddr, SocketConfig>,
+    socket_config: SocketConfig,
+) -> Result<Box<dyn Transport + Send>, String> {
+    let udp_port = options.udp_port;
+    #[cfg(target_os = "linux")]
+    let transport: Box<dyn Transport + Send> = if options.pipeline {
+        if options.connected_sockets.is_some() {
+            warn!("The connected sockets are not used with the pipeline");
+        }
+        let sock = send_sock
+            .try_clone()
+            .context("Cannot clone the raw IP socket")?;
+        let bound = bound_neighbors.clone();
+        Box::new(TxStage::new(options.tx_queue_depth, move |next_hop| {
+            let shared = socket_transport(sock.try_clone()?, udp_port);
+            let bound = bound
+                .get(&next_hop)
+                .map(|config| (next_hop, config.clone()));
+            let sockets = HashMap::from_iter(bound);
+            connected_transport(shared, Vec::new(), None, udp_port, &socket_config, &sockets)
+        }))
+    } else {
+        let sock = send_sock
+            .try_clone()
+            .context("Cannot clone the raw IP socket")?;
+        let transport = socket_transport(sock, udp_port);
+        connected_transport(
+            transport,
+            busiest_next_hops,
+            options.connected_sockets,
+            udp_port,
+            &socket_config,
+            &bound_neighbors,
+        )
+        .context("Cannot bind the sockets toward the neighbors")?
+    };
+    #[cfg(not(target_os = "linux"))]
+    let transport = connected_transport(
+        socket_transport(
+            send_sock
+                .try_clone()
+                .context("Cannot clone the raw IP socket")?,
+            udp_port,
+        ),
+        busiest_next_hops,
+        options.connected_sockets,
+        udp_port,
+        &socket_config,
+        &bound_neighbors,
+    )
+    .context("Cannot bind the sockets toward the neighbors")?;
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    let uring = options.uring && !options.pipeline && options.connected_sockets.is_none();
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    let transport: Box<dyn Transport + Send> = match uring && bound_neighbors.is_empty() {
+        true => {
+            let sock = send_sock
+                .try_clone()
+                .context("Cannot clone the raw IP socket")?;
+            Box::new(
+                UringTransport::new(sock, udp_port.unwrap_or(0), DEFAULT_RING_SIZE)
+                    .context("Cannot create the io_uring transport")?,
+            )
+        }
+        false => transport,
+    };
+    Ok(transport)
+}
+
+/// UNIX datagram socket bound at `path`.
+fn unix_socket(path: &str) -> Result<socket2::Socket, String> {
+    let sock = socket2::Socket::new(socket2::Domain::UNIX, socket2::Type::DGRAM, None)
+        .context("Cannot create a UNIX socket")?;
+    let addr = socket2::SockAddr::unix(path).context("Invalid UNIX path")?;
+    sock.bind(&addr)
+        .context(&format!("Cannot bind the UNIX socket {}", path))?;
+    Ok(sock)
+}
+
+/// Reads the BIER state from the configuration file at `path`, a BIRT exported by FRRouting
+/// if `frr` is set. The configuration is either JSON or a binary snapshot (see `snapshot.rs`).
+pub fn load_state(path: &str, frr: bool) -> Result<BierState, String> {
+    let content = std::fs::read(path).map_err(|e| format!("Cannot find the file: {}", e))?;
+    if snapshot::is_snapshot(&content) {
+        if frr {
+            return Err("The FRR export is not a binary snapshot".into());
+        }
+        return BierState::from_snapshot(&content)
+            .map_err(|e| format!("Cannot read the binary snapshot: {}", e));
+    }
+    let json: Value =
+        from_slice(&content).map_err(|e| format!("Cannot read the JSON content: {}", e))?;
+    if frr {
+        let export: FrrExport =
+            from_value(json).map_err(|e| format!("Cannot parse the FRR export: {}", e))?;
+        export
+            .try_into()
+            .map_err(|e| format!("Cannot convert the FRR export to BierState: {}", e))
+    } else {
+        from_value(json).map_err(|e| format!("Cannot parse the JSON to BierState: {}", e))
+    }
+}
+
+/// Applies the socket options of the command line, which take precedence over the ones of the
+/// configuration, at startup and on each reload.
+fn override_socket_config(socket_config: &mut SocketConfig, options: &DaemonConfig) {
+    socket_config.proto = options.proto.or(socket_config.proto);
+    socket_config.recv_buffer = options.recv_buffer.or(socket_config.recv_buffer);
+    socket_config.hop_limit = options.hop_limit.or(socket_config.hop_limit);
+    socket_config.source = options.source.or(socket_config.source);
+    if let Some(interface) = &options.bind_interface {
+        socket_config.interface = Some(interface.clone());
+    }
+}
+
+/// Framing of the packets of the raw IP socket, or of the UDP socket with `udp_port`.
+fn ip_framing(udp_port: Option<u16>) -> Framing {
+    match udp_port {
+        Some(_) => Framing::Bier,
+        None => Framing::Ip,
+    }
+}
+
+/// Source of the packets received on `sock`, the raw IP socket or the UDP socket with `udp_port`.
+fn ip_source(
+    sock: socket2::Socket,
+    udp_port: Option<u16>,
+    max_packet_size: usize,
+) -> std::io::Result<Box<dyn EventSource>> {
+    Ok(match udp_port {
+        Some(port) => Box::new(TransportSource::new(
+            UdpTransport::new(sock, port),
+            max_packet_size,
+        )?),
+        None => Box::new(TransportSource::new(sock, max_packet_size)?),
+    })
+}
+
+/// Transport sending the copies with `sock`, the raw IP socket or the UDP socket with `udp_port`.
+fn socket_transport(sock: socket2::Socket, udp_port: Option<u16>) -> Box<dyn Transport + Send> {
+    match udp_port {
+        Some(port) => Box::new(UdpTransport::new(sock, port)),
+        None => Box::new(sock),
+    }
+}
+
+/// Sends the copies toward the first `max` of `next_hops` with connected sockets, toward the
+/// `bound` neighbors with a socket bound to their interface or source address, and the other
+/// copies with `shared`. Only `shared` if `max` is `None` and without bound neighbors.
+fn connected_transport(
+    shared: Box<dyn Transport + Send>,
+    next_hops: Vec<IpAddr>,
+    max: Option<usize>,
+    udp_port: Option<u16>,
+    config: &SocketConfig,
+    bound: &HashMap<IpAddr, SocketConfig>,
+) -> io::Result<Box<dyn Transport + Send>> {
+    if max.is_none() && bound.is_empty() {
+        return Ok(shared);
+    }
+    let max = max.unwrap_or(0);
+    let mut transport = ConnectedTransport::new(shared, next_hops, max, |next_hop| {
+        connected_socket(next_hop, udp_port, config)
+    });
+    if max > 0 {
+        info!(
+            "Connected a socket to {} next hops",
+            transport.nb_connected()
+        );
+    }
+    for (next_hop, config) in bound {
+        let socket = match udp_port {
+            Some(_) => crate::daemon::egress_udp_socket(*next_hop, config)?,
+            None => crate::daemon::egress_raw_socket(*next_hop, config)?,
+        };
+        transport.insert(*next_hop, socket_transport(socket, udp_port));
+    }
+    Ok(Box::new(transport))
+}
+
+/// Socket connected to `next_hop`, the raw IP socket or the UDP socket with `udp_port`.
+fn connected_socket(
+    next_hop: IpAddr,
+    udp_port: Option<u16>,
+    config: &SocketConfig,
+) -> io::Result<socket2::Socket> {
+    match udp_port {
+        Some(port) => crate::daemon::connected_udp_socket(next_hop, port, config),
+        None => crate::daemon::connected_raw_socket(next_hop, config),
+    }
+}
+
+/// Parses a `<proto>=<path>` delivery of `--deliver`.
+fn parse_delivery(value: &str) -> Result<(u16, String), String> {
+    let (proto, path) = value.split_once('=').ok_or("expected <proto>=<path>")?;
+    let proto = proto
+        .parse()
+        .map_err(|e| format!("invalid proto {}: {}", proto, e))?;
+    Ok((proto, path.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::api::SendInfo;
+    use crate::daemon::MemoryTransport;
+    use crate::header::BierHeader;
+
+    /// Options of a daemon listening on UDP, without privileges, on the `::1` loopback.
+    fn get_options(name: &str) -> DaemonConfig {
+        let dir = std::env::temp_dir();
+        let prefix = format!("bier-server-{}-{}", name, std::process::id());
+        let config = dir.join(format!("{}.json", prefix));
+        let mut state = load_state("configs/example.json", false).unwrap();
+        state.loopback = "::1".parse().unwrap();
+        std::fs::write(&config, serde_json::to_vec(&state).unwrap()).unwrap();
+        let bier_unix_path = dir.join(format!("{}.sock", prefix));
+        let mut options =
+            DaemonConfig::new(config.to_str().unwrap(), bier_unix_path.to_str().unwrap());
+        options.udp_port = Some(0);
+        options
+    }
+
+    #[test]
+    /// Tests the daemon set up from its options, with an injected transport.
+    fn test_daemon_setup() {
+        let options = get_options("setup");
+        let transport = MemoryTransport::default();
+        let mut daemon = Daemon::with_transport(options.clone(), transport.clone()).unwrap();
+        assert_eq!(daemon.options().udp_port, Some(0));
+        let loopback = daemon.router().daemon().state().get_loopback();
+        assert_eq!(loopback, IpAddr::from(std::net::Ipv6Addr::LOCALHOST));
+
+        let mut packet = vec![0u8; 22];
+        BierHeader::from_recv_info(&SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11010],
+            payload: &[],
+            metadata: None,
+        })
+        .unwrap()
+        .to_slice(&mut packet)
+        .unwrap();
+        let router = daemon.router_mut();
+        assert_eq!(router.handle_packet(Framing::Bier, &mut packet), Ok(()));
+        assert!(!transport.is_empty());
+
+        // The options are validated at startup.
+        let mut invalid = options.clone();
+        invalid.hop_limit = Some(0);
+        assert!(Daemon::with_transport(invalid, transport.clone()).is_err());
+        let mut missing = options.clone();
+        missing.config = "configs/missing.json".into();
+        assert!(Daemon::new(missing).is_err());
+
+        // The configuration may be a binary snapshot, but not the FRR exports.
+        let state = load_state(&options.config, false).unwrap();
+        let snapshot_path = format!("{}.bin", options.config);
+        std::fs::write(&snapshot_path, state.to_snapshot().unwrap()).unwrap();
+        assert_eq!(load_state(&snapshot_path, false), Ok(state));
+        assert!(load_state(&snapshot_path, true).is_err());
+
+        let _ = std::fs::remove_file(&snapshot_path);
+        let _ = std::fs::remove_file(&options.config);
+        let _ = std::fs::remove_file(&options.bier_unix_path);
+    }
+}
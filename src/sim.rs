@@ -0,0 +1,309 @@
+//! In-memory network of BFRs, to test the forwarding of several `BierState`s end to end
+//! without sockets nor privileges. Each BFR is a `BierDaemon` sending its copies to a
+//! `MemoryTransport`, and the copies are given to the BFR owning their next hop until none is
+//! left, so that a test injects packets at a BFIR and checks the payloads received by each BFER.
+//!
+//! The copies are plain BIER packets: the encapsulations and the transports of the neighbors
+//! are not simulated.
+
+use crate::api::{OwnedRecvInfo, SendInfo};
+use crate::bier::BierState;
+use crate::daemon::{BierDaemon, BierDaemonBuilder, MemoryTransport, SentCopy};
+use crate::Result;
+use log::{debug, warn};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// Maximum number of copies processed by `Network::run`, bounding the forwarding loops whose
+/// copies are replicated faster than their TTL expires.
+const MAX_COPIES: usize = 1 << 16;
+
+/// Handle of a BFR of a `Network`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(usize);
+
+struct Node {
+    daemon: BierDaemon,
+    transport: MemoryTransport,
+    delivered: Arc<Mutex<Vec<OwnedRecvInfo>>>,
+}
+
+#[derive(Default)]
+pub struct Network {
+    nodes: Vec<Node>,
+    /// BFR owning each address, i.e., its loopback and the next hops added by `add_address`.
+    addresses: HashMap<IpAddr, NodeId>,
+    /// Copies sent toward an address without BFR.
+    lost: Vec<SentCopy>,
+    /// Copies not processed yet, with the BFR receiving them.
+    pending: VecDeque<(NodeId, Vec<u8>)>,
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a BFR with the `state`, reachable at its loopback.
+    pub fn add_node(&mut self, state: BierState) -> io::Result<NodeId> {
+        self.add_daemon(BierDaemon::builder().state(state))
+    }
+
+    /// Adds the BFR built by `builder`, e.g., with a result cache or a packet log. Its
+    /// transport and its local delivery callback are replaced by the ones of the network.
+    pub fn add_daemon(&mut self, builder: BierDaemonBuilder) -> io::Result<NodeId> {
+        let transport = MemoryTransport::default();
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let delivered_clone = delivered.clone();
+        let daemon = builder
+            .transport(transport.clone())
+            .on_local_delivery(move |recv_info| {
+                delivered_clone
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .push(OwnedRecvInfo::from(recv_info))
+            })
+            .build()?;
+        let id = NodeId(self.nodes.len());
+        self.addresses.insert(daemon.state().get_loopback(), id);
+        self.nodes.push(Node {
+            daemon,
+            transport,
+            delivered,
+        });
+        Ok(id)
+    }
+
+    /// Makes the BFR `node` reachable at `address`, e.g., the address of its interface used as
+    /// next hop by its neighbors.
+    pub fn add_address(&mut self, node: NodeId, address: IpAddr) {
+        self.addresses.insert(address, node);
+    }
+
+    /// BFR owning `address`.
+    pub fn node(&self, address: IpAddr) -> Option<NodeId> {
+        self.addresses.get(&address).copied()
+    }
+
+    pub fn daemon(&self, node: NodeId) -> &BierDaemon {
+        &self.nodes[node.0].daemon
+    }
+
+    pub fn daemon_mut(&mut self, node: NodeId) -> &mut BierDaemon {
+        &mut self.nodes[node.0].daemon
+    }
+
+    /// Sends the packet of an application of the BFIR `node`. Its copies are forwarded by `run`.
+    pub fn send(&mut self, node: NodeId, send_info: &SendInfo) -> Result<()> {
+        let result = self.nodes[node.0].daemon.send(send_info);
+        self.collect(node);
+        result
+    }
+
+    /// Injects a BIER packet received from the network by the BFR `node`, e.g., a packet
+    /// crafted with an invalid header.
+    pub fn inject(&mut self, node: NodeId, packet: Vec<u8>) {
+        self.pending.push_back((node, packet));
+    }
+
+    /// Forwards the copies to the BFRs owning their next hops, in the order they are sent,
+    /// until no copy is left. Returns the number of copies processed. A forwarding loop stops
+    /// after `MAX_COPIES` copies, and its remaining copies are kept in `pending`.
+    pub fn run(&mut self) -> usize {
+        let mut processed = 0;
+        while processed < MAX_COPIES {
+            let (node, mut packet) = match self.pending.pop_front() {
+                Some(copy) => copy,
+                None => return processed,
+            };
+            processed += 1;
+            // The errors are counted by the daemon in its drops.
+            if let Err(e) = self.nodes[node.0].daemon.process_packet(&mut packet) {
+                debug!("Copy dropped by the simulated BFR {:?}: {}", node, e);
+            }
+            self.collect(node);
+        }
+        warn!(
+            "Forwarding loop in the simulated network: {} copies left",
+            self.pending.len()
+        );
+        processed
+    }
+
+    /// Number of copies not processed yet.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Copies sent toward an address without BFR.
+    pub fn lost(&self) -> &[SentCopy] {
+        &self.lost
+    }
+
+    /// Packets delivered to the applications of the BFR `node`, the oldest first.
+    pub fn delivered(&self, node: NodeId) -> Vec<OwnedRecvInfo> {
+        self.nodes[node.0]
+            .delivered
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// BFRs which delivered `payload` to their applications.
+    pub fn receivers(&self, payload: &[u8]) -> BTreeSet<NodeId> {
+        (0..self.nodes.len())
+            .map(NodeId)
+            .filter(|node| {
+                self.delivered(*node)
+                    .iter()
+                    .any(|recv_info| recv_info.payload == payload)
+            })
+            .collect()
+    }
+
+    /// Forgets the packets delivered and the copies lost, e.g., between two steps of a test.
+    pub fn clear(&mut self) {
+        for node in self.nodes.iter() {
+            node.delivered
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clear();
+        }
+        self.lost.clear();
+    }
+
+    /// Moves the copies sent by `node` toward the BFRs of their next hops.
+    fn collect(&mut self, node: NodeId) {
+        for (next_hop, packet) in self.nodes[node.0].transport.drain() {
+            match self.addresses.get(&next_hop) {
+                Some(to) => self.pending.push_back((*to, packet)),
+                None => self.lost.push((next_hop, packet)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::bier::test_state;
+
+    /// Network with the BFRs 1 to 4: 1 is connected to 2 and 3, and 2 to 4. The BFR 2 is
+    /// reached by 1 at the address of its interface `fc00:2::1`.
+    fn get_network() -> (Network, Vec<NodeId>) {
+        let states = [
+            test_state(
+                "fc00::1",
+                1,
+                &[
+                    (1, &[("1", "fc00::1")]),
+                    (2, &[("1010", "fc00:2::1")]),
+                    (3, &[("100", "fc00::3")]),
+                    (4, &[("1010", "fc00:2::1")]),
+                ],
+            ),
+            test_state(
+                "fc00::2",
+                2,
+                &[
+                    (1, &[("101", "fc00::1")]),
+                    (2, &[("10", "fc00::2")]),
+                    (3, &[("101", "fc00::1")]),
+                    (4, &[("1000", "fc00::4")]),
+                ],
+            ),
+            test_state(
+                "fc00::3",
+                3,
+                &[
+                    (1, &[("1011", "fc00::1")]),
+                    (2, &[("1011", "fc00::1")]),
+                    (3, &[("100", "fc00::3")]),
+                    (4, &[("1011", "fc00::1")]),
+                ],
+            ),
+            test_state(
+                "fc00::4",
+                4,
+                &[
+                    (1, &[("111", "fc00::2")]),
+                    (2, &[("111", "fc00::2")]),
+                    (3, &[("111", "fc00::2")]),
+                    (4, &[("1000", "fc00::4")]),
+                ],
+            ),
+        ];
+        let mut network = Network::new();
+        let nodes: Vec<_> = states
+            .into_iter()
+            .map(|state| network.add_node(state).unwrap())
+            .collect();
+        network.add_address(nodes[1], "fc00:2::1".parse().unwrap());
+        (network, nodes)
+    }
+
+    fn send_info<'a>(bitstring: &'a [u8], payload: &'a [u8]) -> SendInfo<'a> {
+        SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring,
+            payload,
+            metadata: None,
+        }
+    }
+
+    #[test]
+    /// Tests the delivery of packets injected at several BFIRs of a simulated network.
+    fn test_sim_network() {
+        let (mut network, nodes) = get_network();
+        assert_eq!(network.node("fc00:2::1".parse().unwrap()), Some(nodes[1]));
+
+        // From 1 to all the others, and to 3 and 4 from 4.
+        network
+            .send(nodes[0], &send_info(&[0, 0, 0, 0, 0, 0, 0, 0b1110], &[1]))
+            .unwrap();
+        network
+            .send(nodes[3], &send_info(&[0, 0, 0, 0, 0, 0, 0, 0b1100], &[2]))
+            .unwrap();
+        // 1 to 2 and 3, 2 to 4 for [1]; 4 to 2, 2 to 1, 1 to 3 and 4 locally for [2].
+        assert_eq!(network.run(), 6);
+        assert_eq!(network.pending(), 0);
+        assert!(network.lost().is_empty());
+
+        assert_eq!(
+            network.receivers(&[1]),
+            BTreeSet::from([nodes[1], nodes[2], nodes[3]])
+        );
+        assert_eq!(
+            network.receivers(&[2]),
+            BTreeSet::from([nodes[2], nodes[3]])
+        );
+        let delivered = network.delivered(nodes[2]);
+        assert_eq!(delivered.len(), 2);
+        assert_eq!(delivered[0].bitstring, vec![0, 0, 0, 0, 0, 0, 0, 0b100]);
+        assert_eq!(delivered[1].metadata.unwrap().bfir_id, Some(4));
+        assert!(network.delivered(nodes[0]).is_empty());
+        assert_eq!(network.daemon(nodes[1]).stats().received.packets, 2);
+
+        // A copy toward an address without BFR is lost, the malformed ones are dropped.
+        network.clear();
+        network
+            .daemon_mut(nodes[1])
+            .update_state(|state| {
+                state.bifts[0].entries[3].paths[0].next_hop = "fc00::5".parse().unwrap()
+            })
+            .unwrap();
+        network
+            .send(nodes[0], &send_info(&[0, 0, 0, 0, 0, 0, 0, 0b1000], &[3]))
+            .unwrap();
+        network.inject(nodes[2], vec![0; 4]);
+        assert_eq!(network.run(), 2);
+        assert!(network.receivers(&[3]).is_empty());
+        assert_eq!(network.lost().len(), 1);
+        assert_eq!(network.lost()[0].0, "fc00::5".parse::<IpAddr>().unwrap());
+        assert_eq!(network.daemon(nodes[2]).stats().drops.len(), 1);
+    }
+}
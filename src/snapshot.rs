@@ -0,0 +1,313 @@
+//! Binary snapshot of a `BierState`, loaded faster than its JSON configuration by the BFRs
+//! with thousands of BIFT entries: the bitstrings of the paths are packed words instead of
+//! strings of digits. `bier-config convert` converts a JSON configuration to a snapshot and
+//! back, and the daemon loads both.
+//!
+//! The snapshot starts with `SNAPSHOT_MAGIC` and `SNAPSHOT_VERSION`, followed by the length
+//! (u32) and the JSON of the state without its BIFTs, e.g., the overlay and the socket
+//! options, and by the number of BIFTs (u32) and the BIFTs. All the integers are big endian.
+//! The F-BMs of a BIFT all have the same BSL, one of RFC 8296.
+//!
+//! ```text
+//! BIFT:  bift_id (u64) | bift_type (u8) | sub_domain (u8) | si (u8) | bfr_id (u64)
+//!        | number of entries (u32) | entries
+//! Entry: bit (u64) | admin_state (u8) | number of paths (u16) | paths
+//! Path:  admin_state (u8) | next hop (4 or 6, then 4 or 16 bytes)
+//!        | number of words of the F-BM (u16) | words (u64)
+//! ```
+
+use crate::bier::{AdminState, BierEntryPath, BierState, Bift, BiftEntry, BiftType, Bitstring};
+use crate::{Error, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// First bytes of a snapshot, distinguishing it from a JSON configuration.
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"BIER";
+
+/// Version of the snapshot format.
+pub const SNAPSHOT_VERSION: u8 = 1;
+
+/// Whether `content` starts as a snapshot instead of a JSON configuration.
+pub fn is_snapshot(content: &[u8]) -> bool {
+    content.starts_with(&SNAPSHOT_MAGIC)
+}
+
+impl BierState {
+    /// Binary snapshot of the state, see the module documentation.
+    pub fn to_snapshot(&self) -> serde_json::Result<Vec<u8>> {
+        let mut others = self.clone();
+        others.bifts.clear();
+        let json = serde_json::to_vec(&others)?;
+
+        let mut snapshot = Vec::with_capacity(json.len() + 64 * self.bifts.len());
+        snapshot.extend_from_slice(&SNAPSHOT_MAGIC);
+        snapshot.push(SNAPSHOT_VERSION);
+        snapshot.extend_from_slice(&(json.len() as u32).to_be_bytes());
+        snapshot.extend_from_slice(&json);
+        snapshot.extend_from_slice(&(self.bifts.len() as u32).to_be_bytes());
+        for bift in self.bifts.iter() {
+            write_bift(&mut snapshot, bift);
+        }
+        Ok(snapshot)
+    }
+
+    /// State of the binary `snapshot`, to be compiled as after its JSON parsing.
+    pub fn from_snapshot(snapshot: &[u8]) -> Result<Self> {
+        if !is_snapshot(snapshot) {
+            return Err(Error::Snapshot);
+        }
+        let mut reader = Reader {
+            slice: snapshot,
+            offset: SNAPSHOT_MAGIC.len(),
+        };
+        let version = reader.u8()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(Error::SnapshotVersion(version));
+        }
+        let json_length = reader.u32()? as usize;
+        let mut state: BierState =
+            serde_json::from_slice(reader.bytes(json_length)?).map_err(|_| Error::Snapshot)?;
+        let nb_bifts = reader.u32()? as usize;
+        state.bifts = Vec::with_capacity(reader.capacity(nb_bifts));
+        for _ in 0..nb_bifts {
+            state.bifts.push(read_bift(&mut reader)?);
+        }
+        if reader.offset != snapshot.len() {
+            return Err(Error::Snapshot);
+        }
+        Ok(state)
+    }
+}
+
+fn write_bift(snapshot: &mut Vec<u8>, bift: &Bift) {
+    snapshot.extend_from_slice(&(bift.bift_id as u64).to_be_bytes());
+    snapshot.extend_from_slice(&[bift.bift_type as u8, bift.sub_domain, bift.si]);
+    snapshot.extend_from_slice(&bift.bfr_id.to_be_bytes());
+    snapshot.extend_from_slice(&(bift.entries.len() as u32).to_be_bytes());
+    for entry in bift.entries.iter() {
+        snapshot.extend_from_slice(&entry.bit.to_be_bytes());
+        snapshot.push(admin_state_byte(entry.admin_state));
+        snapshot.extend_from_slice(&(entry.paths.len() as u16).to_be_bytes());
+        for path in entry.paths.iter() {
+            snapshot.push(admin_state_byte(path.admin_state));
+            match path.next_hop {
+                IpAddr::V4(address) => {
+                    snapshot.push(4);
+                    snapshot.extend_from_slice(&address.octets());
+                }
+                IpAddr::V6(address) => {
+                    snapshot.push(6);
+                    snapshot.extend_from_slice(&address.octets());
+                }
+            }
+            let words = &path.bitstring.bitstring;
+            snapshot.extend_from_slice(&(words.len() as u16).to_be_bytes());
+            for word in words.iter() {
+                snapshot.extend_from_slice(&word.to_be_bytes());
+            }
+        }
+    }
+}
+
+fn read_bift(reader: &mut Reader) -> Result<Bift> {
+    let bift_id = usize::try_from(reader.u64()?).map_err(|_| Error::Snapshot)?;
+    let bift_type = match reader.u8()? {
+        1 => BiftType::Bier,
+        2 => BiftType::BierTe,
+        _ => return Err(Error::Snapshot),
+    };
+    let sub_domain = reader.u8()?;
+    let si = reader.u8()?;
+    let bfr_id = reader.u64()?;
+    let nb_entries = reader.u32()? as usize;
+    let mut entries = Vec::with_capacity(reader.capacity(nb_entries));
+    // All the F-BMs of the BIFT have the BSL of its first one.
+    let mut bsl = None;
+    for _ in 0..nb_entries {
+        let bit = reader.u64()?;
+        let admin_state = reader.admin_state()?;
+        let nb_paths = reader.u16()? as usize;
+        let mut paths = Vec::with_capacity(reader.capacity(nb_paths));
+        for _ in 0..nb_paths {
+            let admin_state = reader.admin_state()?;
+            let next_hop = match reader.u8()? {
+                4 => IpAddr::V4(Ipv4Addr::from(
+                    <[u8; 4]>::try_from(reader.bytes(4)?).unwrap(),
+                )),
+                6 => IpAddr::V6(Ipv6Addr::from(
+                    <[u8; 16]>::try_from(reader.bytes(16)?).unwrap(),
+                )),
+                _ => return Err(Error::Snapshot),
+            };
+            let nb_words = reader.u16()? as usize;
+            let words = (0..nb_words)
+                .map(|_| reader.u64())
+                .collect::<Result<Vec<_>>>()?;
+            let bitstring = Bitstring::try_from(words).map_err(|_| Error::Snapshot)?;
+            if *bsl.get_or_insert(bitstring.bsl()) != bitstring.bsl() {
+                return Err(Error::Snapshot);
+            }
+            paths.push(BierEntryPath {
+                bitstring,
+                next_hop,
+                admin_state,
+            });
+        }
+        entries.push(BiftEntry {
+            bit,
+            paths,
+            admin_state,
+        });
+    }
+    Ok(Bift {
+        bift_id,
+        bift_type,
+        sub_domain,
+        si,
+        bfr_id,
+        entries,
+    })
+}
+
+fn admin_state_byte(admin_state: AdminState) -> u8 {
+    match admin_state {
+        AdminState::Up => 0,
+        AdminState::Down => 1,
+    }
+}
+
+/// Cursor over a snapshot, failing with `Error::Snapshot` when it is truncated.
+struct Reader<'a> {
+    slice: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .slice
+            .get(self.offset..self.offset.saturating_add(length))
+            .ok_or(Error::Snapshot)?;
+        self.offset += length;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn admin_state(&mut self) -> Result<AdminState> {
+        match self.u8()? {
+            0 => Ok(AdminState::Up),
+            1 => Ok(AdminState::Down),
+            _ => Err(Error::Snapshot),
+        }
+    }
+
+    /// Capacity reserved for `count` items, bounded by the remaining bytes so that a corrupted
+    /// count does not allocate more than the snapshot.
+    fn capacity(&self, count: usize) -> usize {
+        count.min(self.slice.len() - self.offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn get_state() -> BierState {
+        serde_json::from_value(serde_json::json!({
+            "loopback": "fc00::1",
+            "bifts": [
+                {"bift_id": 1, "bift_type": 1, "bfr_id": 1, "entries": [
+                    {"bit": 1, "paths": [{"bitstring": "1", "next_hop": "fc00::1"}]},
+                    {"bit": 2, "admin_state": "down", "paths": [
+                        {"bitstring": "110", "next_hop": "fc00::2"},
+                        {"bitstring": "110", "next_hop": "10.0.0.3", "admin_state": "down"},
+                    ]},
+                ]},
+                {"bift_id": 2, "bift_type": 2, "sub_domain": 3, "si": 1, "bfr_id": 1,
+                 "entries": [{"bit": 200, "paths": [
+                    {"bitstring": format!("0x{}", "0".repeat(31) + "1"), "next_hop": "fc00::4"},
+                 ]}]},
+            ],
+            "bfr_names": {"a": 1},
+            "overlay": [{"group": "ff3e::1", "bitstring": "10"}],
+            "udp_port": 5000,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    /// Tests that a state is identical after its snapshot, and that the snapshot is smaller
+    /// than its JSON.
+    fn test_snapshot() {
+        let bier_state = get_state();
+        let snapshot = bier_state.to_snapshot().unwrap();
+        assert!(is_snapshot(&snapshot));
+        assert_eq!(BierState::from_snapshot(&snapshot).unwrap(), bier_state);
+
+        let mut large = bier_state.clone();
+        let entry = large.bifts[0].entries[1].clone();
+        large.bifts[0].entries = (1..=64)
+            .map(|bit| BiftEntry {
+                bit,
+                ..entry.clone()
+            })
+            .collect();
+        // Only the BIER BIFTs are compiled.
+        large.bifts[1].bift_type = BiftType::Bier;
+        let snapshot = large.to_snapshot().unwrap();
+        assert!(snapshot.len() < serde_json::to_vec(&large).unwrap().len() / 2);
+        let mut loaded = BierState::from_snapshot(&snapshot).unwrap();
+        assert_eq!(loaded, large);
+        loaded.compile().unwrap();
+    }
+
+    #[test]
+    /// Tests the refusal of the invalid snapshots.
+    fn test_snapshot_errors() {
+        let snapshot = get_state().to_snapshot().unwrap();
+        let json = serde_json::to_vec(&get_state()).unwrap();
+        assert!(!is_snapshot(&json));
+        assert_eq!(BierState::from_snapshot(&json), Err(Error::Snapshot));
+
+        let mut other_version = snapshot.clone();
+        other_version[4] = SNAPSHOT_VERSION + 1;
+        assert_eq!(
+            BierState::from_snapshot(&other_version),
+            Err(Error::SnapshotVersion(SNAPSHOT_VERSION + 1))
+        );
+        for length in [3, 5, 20, snapshot.len() - 1] {
+            assert_eq!(
+                BierState::from_snapshot(&snapshot[..length]),
+                Err(Error::Snapshot)
+            );
+        }
+        let mut trailing = snapshot.clone();
+        trailing.push(0);
+        assert_eq!(BierState::from_snapshot(&trailing), Err(Error::Snapshot));
+
+        // F-BMs of an invalid length, or of another BSL than the BIFT.
+        let mut invalid = get_state();
+        invalid.bifts[0].entries[1].paths[0].bitstring.bitstring = vec![0b110; 3];
+        let snapshot = invalid.to_snapshot().unwrap();
+        assert_eq!(BierState::from_snapshot(&snapshot), Err(Error::Snapshot));
+        let mut mismatch = get_state();
+        mismatch.bifts[0].entries[1].paths[1].bitstring.bitstring = vec![0b110, 0];
+        let snapshot = mismatch.to_snapshot().unwrap();
+        assert_eq!(BierState::from_snapshot(&snapshot), Err(Error::Snapshot));
+    }
+}
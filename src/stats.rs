@@ -0,0 +1,892 @@
+//! Packet and byte counters of the BFR, exported through the control socket.
+//!
+//! The datapath updates `AtomicStats` without lock: each thread increments the atomic
+//! counters of its own shard, and the shards are summed in a `Stats` snapshot on read.
+//!
+//! The counters are cumulative 64-bit integers, which wrap around instead of overflowing:
+//! the deltas between two snapshots (see `Stats::delta`) stay correct across a wrap.
+
+use crate::bier::{BierState, Bitstring};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counter {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+impl Counter {
+    pub fn add(&mut self, bytes: usize) {
+        self.packets = self.packets.wrapping_add(1);
+        self.bytes = self.bytes.wrapping_add(bytes as u64);
+    }
+
+    pub fn merge(&mut self, other: Counter) {
+        self.packets = self.packets.wrapping_add(other.packets);
+        self.bytes = self.bytes.wrapping_add(other.bytes);
+    }
+
+    /// Packets and bytes counted since `previous`.
+    pub fn delta(&self, previous: Counter) -> Counter {
+        Counter {
+            packets: self.packets.wrapping_sub(previous.packets),
+            bytes: self.bytes.wrapping_sub(previous.bytes),
+        }
+    }
+}
+
+/// Lookups in the cache of the results of the BIER processing.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Ratio of the lookups found in the cache, if any lookup.
+    pub fn hit_rate(&self) -> Option<f64> {
+        match self.hits + self.misses {
+            0 => None,
+            total => Some(self.hits as f64 / total as f64),
+        }
+    }
+}
+
+/// Reason why a packet is dropped by the BFR.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum DropReason {
+    /// The BIER header cannot be parsed.
+    MalformedPacket,
+    /// No BIFT for the BIFT-id of the packet.
+    UnknownBift,
+    /// A bit of the bitstring has no entry in the BIFT.
+    NoEntry,
+    /// An application message that cannot be parsed.
+    MalformedApiMessage,
+    /// An application not allowed by the access control list.
+    Unauthorized,
+    /// The transport failed to send a copy to a next hop.
+    SendError,
+    /// A copy for this BFER, but no local application.
+    NoLocalDelivery,
+    /// A queue between two stages of the pipeline is full.
+    QueueFull,
+    /// The nibble or the version of the header do not follow RFC 8296, in strict mode.
+    InvalidVersion,
+    /// A datagram larger than the receive buffer, see `--max-packet-size`.
+    Truncated,
+    /// A copy denied by the delivery policy of the proto of the packet.
+    DeliveryPolicy,
+    /// Bits of a copy that cannot be encoded with the BSL and BIFT-ids of the neighbor.
+    UnsupportedBsl,
+    /// A copy beyond the fan-out cap of a packet, dropped by the overflow policy.
+    FanOutCap,
+    /// An OAM echo request that cannot be answered, e.g., without entry for its BFIR.
+    OamNoReply,
+}
+
+impl DropReason {
+    pub const ALL: [DropReason; 14] = [
+        DropReason::MalformedPacket,
+        DropReason::UnknownBift,
+        DropReason::NoEntry,
+        DropReason::MalformedApiMessage,
+        DropReason::Unauthorized,
+        DropReason::SendError,
+        DropReason::NoLocalDelivery,
+        DropReason::QueueFull,
+        DropReason::InvalidVersion,
+        DropReason::Truncated,
+        DropReason::DeliveryPolicy,
+        DropReason::UnsupportedBsl,
+        DropReason::FanOutCap,
+        DropReason::OamNoReply,
+    ];
+}
+
+/// Number of bits of the linear sub-buckets of each power of two of `LatencyHistogram`.
+const SUB_BUCKET_BITS: u32 = 4;
+
+/// Number of buckets of a latency histogram, i.e., the bucket of `u64::MAX` plus one.
+const NB_BUCKETS: usize = (64 - SUB_BUCKET_BITS as usize + 1) << SUB_BUCKET_BITS;
+
+/// Histogram of latencies with a bounded relative error, in the style of HDR histograms:
+/// each power of two is divided in 16 linear buckets, i.e., an error below 6.25%.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    /// Number of samples in each non-empty bucket.
+    pub buckets: BTreeMap<u32, u64>,
+    pub count: u64,
+    /// Sum of the samples, in nanoseconds.
+    pub sum_ns: u64,
+    pub max_ns: u64,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let value = latency.as_nanos().min(u64::MAX as u128) as u64;
+        *self.buckets.entry(bucket_index(value)).or_default() += 1;
+        self.count += 1;
+        self.sum_ns = self.sum_ns.saturating_add(value);
+        self.max_ns = self.max_ns.max(value);
+    }
+
+    pub fn mean(&self) -> Duration {
+        Duration::from_nanos(self.sum_ns.checked_div(self.count).unwrap_or(0))
+    }
+
+    /// Upper bound of the `quantile` (between 0 and 1) of the samples.
+    pub fn quantile(&self, quantile: f64) -> Duration {
+        let target = ((quantile * self.count as f64).ceil() as u64).max(1);
+        let mut cumulated = 0;
+        for (idx, nb) in self.buckets.iter() {
+            cumulated += nb;
+            if cumulated >= target {
+                return Duration::from_nanos(bucket_upper_bound(*idx).min(self.max_ns));
+            }
+        }
+        Duration::from_nanos(self.max_ns)
+    }
+
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (idx, nb) in other.buckets.iter() {
+            let bucket = self.buckets.entry(*idx).or_default();
+            *bucket = bucket.wrapping_add(*nb);
+        }
+        self.count = self.count.wrapping_add(other.count);
+        self.sum_ns = self.sum_ns.saturating_add(other.sum_ns);
+        self.max_ns = self.max_ns.max(other.max_ns);
+    }
+
+    /// Samples recorded since `previous`. The maximum is the one of all the samples.
+    pub fn delta(&self, previous: &LatencyHistogram) -> LatencyHistogram {
+        LatencyHistogram {
+            buckets: self
+                .buckets
+                .iter()
+                .map(|(idx, nb)| {
+                    let previous = previous.buckets.get(idx).copied().unwrap_or(0);
+                    (*idx, nb.wrapping_sub(previous))
+                })
+                .filter(|(_, nb)| *nb > 0)
+                .collect(),
+            count: self.count.wrapping_sub(previous.count),
+            sum_ns: self.sum_ns.wrapping_sub(previous.sum_ns),
+            max_ns: self.max_ns,
+        }
+    }
+
+    /// Upper bound of the values of each non-empty bucket with the number of samples,
+    /// e.g., for the buckets of a Prometheus histogram.
+    pub fn iter_buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.buckets
+            .iter()
+            .map(|(idx, nb)| (Duration::from_nanos(bucket_upper_bound(*idx)), *nb))
+    }
+}
+
+/// Small values have their own bucket. Otherwise, the bucket is given by the position
+/// of the most significant bit and the `SUB_BUCKET_BITS` following bits.
+fn bucket_index(value: u64) -> u32 {
+    if value < 1 << SUB_BUCKET_BITS {
+        return value as u32;
+    }
+    let shift = 63 - value.leading_zeros() - SUB_BUCKET_BITS;
+    let sub_bucket = (value >> shift) as u32 & ((1 << SUB_BUCKET_BITS) - 1);
+    ((shift + 1) << SUB_BUCKET_BITS) + sub_bucket
+}
+
+fn bucket_upper_bound(idx: u32) -> u64 {
+    if idx < 1 << SUB_BUCKET_BITS {
+        return idx as u64;
+    }
+    let shift = (idx >> SUB_BUCKET_BITS) - 1;
+    let sub_bucket = (idx & ((1 << SUB_BUCKET_BITS) - 1)) as u64;
+    let lower_bound = ((1 << SUB_BUCKET_BITS) + sub_bucket) << shift;
+    lower_bound + ((1 << shift) - 1)
+}
+
+/// Copies sent to a neighbouring BFR.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct NeighborStats {
+    pub sent: Counter,
+    pub send_errors: u64,
+    /// Whether the last copy was successfully sent.
+    pub healthy: bool,
+    /// Time between the reception of the packet and the sending of the copy.
+    #[serde(default)]
+    pub latency: LatencyHistogram,
+    /// All the paths toward the neighbor are administratively down.
+    #[serde(default)]
+    pub drained: bool,
+    /// The neighbor is declared dead by the liveness prober.
+    #[serde(default)]
+    pub dead: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// BIER packets received from the network.
+    pub received: Counter,
+    /// Payloads sent by the local applications.
+    pub from_apps: Counter,
+    /// Payloads delivered to the local application.
+    pub delivered: Counter,
+    pub neighbors: BTreeMap<IpAddr, NeighborStats>,
+    /// Copies forwarded for each BFER, indexed by BFR-id.
+    pub bfers: BTreeMap<u64, Counter>,
+    pub drops: BTreeMap<DropReason, u64>,
+    /// Only counted when the result cache is enabled.
+    #[serde(default)]
+    pub cache: CacheStats,
+    /// Bits above the highest BFR-id of their BIFT, cleared from the received bitstrings.
+    #[serde(default)]
+    pub cleared_bits: u64,
+}
+
+impl Stats {
+    /// Reads the counters saved by `save`, e.g., before the restart of the daemon.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Stats> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(io::BufReader::new(file))?)
+    }
+
+    /// Saves the counters in JSON. The file is replaced atomically, so that a crash
+    /// during the write does not lose the previous counters.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, serde_json::to_vec(self)?)?;
+        std::fs::rename(&tmp, path)
+    }
+
+    /// Adds the counters of `other`. The health and the administrative state of the
+    /// neighbors are the ones of `other`, considered more recent.
+    pub fn merge(&mut self, other: &Stats) {
+        self.received.merge(other.received);
+        self.from_apps.merge(other.from_apps);
+        self.delivered.merge(other.delivered);
+        for (next_hop, other) in other.neighbors.iter() {
+            let neighbor = self.neighbors.entry(*next_hop).or_default();
+            neighbor.sent.merge(other.sent);
+            neighbor.send_errors = neighbor.send_errors.wrapping_add(other.send_errors);
+            neighbor.healthy = other.healthy;
+            neighbor.latency.merge(&other.latency);
+            neighbor.drained = other.drained;
+            neighbor.dead = other.dead;
+        }
+        for (bfr_id, counter) in other.bfers.iter() {
+            self.bfers.entry(*bfr_id).or_default().merge(*counter);
+        }
+        for (reason, nb) in other.drops.iter() {
+            let drops = self.drops.entry(*reason).or_default();
+            *drops = drops.wrapping_add(*nb);
+        }
+        self.cache.hits = self.cache.hits.wrapping_add(other.cache.hits);
+        self.cache.misses = self.cache.misses.wrapping_add(other.cache.misses);
+        self.cleared_bits = self.cleared_bits.wrapping_add(other.cleared_bits);
+    }
+
+    /// Counters since the `previous` snapshot, with the current health of the neighbors.
+    /// The neighbors, BFERs and drop reasons without new record are omitted.
+    pub fn delta(&self, previous: &Stats) -> Stats {
+        let neighbors = self
+            .neighbors
+            .iter()
+            .map(|(next_hop, neighbor)| {
+                let delta = match previous.neighbors.get(next_hop) {
+                    Some(previous) => NeighborStats {
+                        sent: neighbor.sent.delta(previous.sent),
+                        send_errors: neighbor.send_errors.wrapping_sub(previous.send_errors),
+                        latency: neighbor.latency.delta(&previous.latency),
+                        ..neighbor.clone()
+                    },
+                    None => neighbor.clone(),
+                };
+                (*next_hop, delta)
+            })
+            .filter(|(_, delta)| {
+                delta.sent.packets > 0 || delta.send_errors > 0 || delta.drained || delta.dead
+            })
+            .collect();
+        let bfers = self
+            .bfers
+            .iter()
+            .map(|(bfr_id, counter)| {
+                let previous = previous.bfers.get(bfr_id).copied().unwrap_or_default();
+                (*bfr_id, counter.delta(previous))
+            })
+            .filter(|(_, counter)| counter.packets > 0)
+            .collect();
+        let drops = self
+            .drops
+            .iter()
+            .map(|(reason, nb)| {
+                let previous = previous.drops.get(reason).copied().unwrap_or(0);
+                (*reason, nb.wrapping_sub(previous))
+            })
+            .filter(|(_, nb)| *nb > 0)
+            .collect();
+        Stats {
+            received: self.received.delta(previous.received),
+            from_apps: self.from_apps.delta(previous.from_apps),
+            delivered: self.delivered.delta(previous.delivered),
+            neighbors,
+            bfers,
+            drops,
+            cache: CacheStats {
+                hits: self.cache.hits.wrapping_sub(previous.cache.hits),
+                misses: self.cache.misses.wrapping_sub(previous.cache.misses),
+            },
+            cleared_bits: self.cleared_bits.wrapping_sub(previous.cleared_bits),
+        }
+    }
+
+    pub fn record_drop(&mut self, reason: DropReason) {
+        let drops = self.drops.entry(reason).or_default();
+        *drops = drops.wrapping_add(1);
+    }
+
+    /// Records the result of sending a copy of `bytes` bytes to `next_hop`.
+    pub fn record_send(&mut self, next_hop: IpAddr, bytes: usize, success: bool) {
+        let neighbor = self.neighbors.entry(next_hop).or_default();
+        neighbor.healthy = success;
+        if success {
+            neighbor.sent.add(bytes);
+        } else {
+            neighbor.send_errors += 1;
+            self.record_drop(DropReason::SendError);
+        }
+    }
+
+    /// Records the latency of a copy successfully sent to `next_hop`.
+    pub fn record_latency(&mut self, next_hop: IpAddr, latency: Duration) {
+        self.neighbors
+            .entry(next_hop)
+            .or_default()
+            .latency
+            .record(latency);
+    }
+
+    /// Records a lookup in the result cache.
+    pub fn record_cache(&mut self, hit: bool) {
+        match hit {
+            true => self.cache.hits += 1,
+            false => self.cache.misses += 1,
+        }
+    }
+
+    /// Records a copy of `bytes` bytes for each BFER of `bitstring`, whose bits are the
+    /// BFR-ids minus `offset`, i.e., the offset of the SI of the BIFT.
+    pub fn record_bfers(&mut self, bitstring: &Bitstring, offset: u64, bytes: usize) {
+        for bfr_id in bitstring.iter_set_positions() {
+            self.bfers.entry(bfr_id + offset).or_default().add(bytes);
+        }
+    }
+}
+
+/// BFR-ids with atomic counters, i.e., the ones of the first SI of the largest BSL.
+/// The other BFR-ids are recorded behind the mutex of `AtomicStats`.
+const MAX_BFR_ID: usize = 4096;
+
+#[derive(Default)]
+struct AtomicCounter {
+    packets: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl AtomicCounter {
+    fn add(&self, bytes: usize) {
+        self.packets.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn load(&self) -> Counter {
+        Counter {
+            packets: self.packets.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct AtomicHistogram {
+    buckets: Box<[AtomicU64]>,
+    count: AtomicU64,
+    sum_ns: AtomicU64,
+    max_ns: AtomicU64,
+}
+
+impl Default for AtomicHistogram {
+    fn default() -> Self {
+        AtomicHistogram {
+            buckets: (0..NB_BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum_ns: AtomicU64::new(0),
+            max_ns: AtomicU64::new(0),
+        }
+    }
+}
+
+impl AtomicHistogram {
+    fn record(&self, latency: Duration) {
+        let value = latency.as_nanos().min(u64::MAX as u128) as u64;
+        self.buckets[bucket_index(value) as usize].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_ns.fetch_add(value, Ordering::Relaxed);
+        self.max_ns.fetch_max(value, Ordering::Relaxed);
+    }
+
+    fn add_to(&self, histogram: &mut LatencyHistogram) {
+        for (idx, bucket) in self.buckets.iter().enumerate() {
+            let nb = bucket.load(Ordering::Relaxed);
+            if nb > 0 {
+                *histogram.buckets.entry(idx as u32).or_default() += nb;
+            }
+        }
+        histogram.count += self.count.load(Ordering::Relaxed);
+        histogram.sum_ns = histogram
+            .sum_ns
+            .saturating_add(self.sum_ns.load(Ordering::Relaxed));
+        histogram.max_ns = histogram.max_ns.max(self.max_ns.load(Ordering::Relaxed));
+    }
+}
+
+#[derive(Default)]
+struct AtomicNeighbor {
+    sent: AtomicCounter,
+    send_errors: AtomicU64,
+    latency: AtomicHistogram,
+}
+
+/// Counters updated by a single thread, on their own cache lines.
+#[repr(align(64))]
+struct Shard {
+    received: AtomicCounter,
+    from_apps: AtomicCounter,
+    delivered: AtomicCounter,
+    drops: [AtomicU64; DropReason::ALL.len()],
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    cleared_bits: AtomicU64,
+    neighbors: Box<[AtomicNeighbor]>,
+    /// Indexed by BFR-id - 1.
+    bfers: Box<[AtomicCounter]>,
+}
+
+impl Shard {
+    fn new(nb_neighbors: usize) -> Self {
+        Shard {
+            received: AtomicCounter::default(),
+            from_apps: AtomicCounter::default(),
+            delivered: AtomicCounter::default(),
+            drops: Default::default(),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+            cleared_bits: AtomicU64::new(0),
+            neighbors: (0..nb_neighbors)
+                .map(|_| AtomicNeighbor::default())
+                .collect(),
+            bfers: (0..MAX_BFR_ID).map(|_| AtomicCounter::default()).collect(),
+        }
+    }
+}
+
+/// Health of a neighbor, i.e., the result of the last copy sent to it.
+const HEALTH_UNKNOWN: u8 = 0;
+const HEALTH_UP: u8 = 1;
+const HEALTH_DOWN: u8 = 2;
+
+thread_local! {
+    /// Index of the shard of the current thread.
+    static SHARD_INDEX: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Index given to the next thread recording statistics.
+static NEXT_SHARD_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+/// Statistics recorded concurrently without lock, with the same records as `Stats`.
+/// The neighbors are the next hops of the BIFTs given at creation. The copies sent to
+/// other next hops are recorded in a `Stats` behind a mutex.
+pub struct AtomicStats {
+    /// Index of each neighbor in the counters of the shards.
+    neighbor_indexes: BTreeMap<IpAddr, usize>,
+    health: Box<[AtomicU8]>,
+    shards: Box<[Shard]>,
+    others: Mutex<Stats>,
+}
+
+impl AtomicStats {
+    /// Creates a shard per available CPU.
+    pub fn new(state: &BierState) -> Self {
+        let nb_shards = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self::with_shards(state, nb_shards)
+    }
+
+    pub fn with_shards(state: &BierState, nb_shards: usize) -> Self {
+        let neighbor_indexes: BTreeMap<IpAddr, usize> = state
+            .bifts
+            .iter()
+            .flat_map(|bift| bift.entries.iter())
+            .flat_map(|entry| entry.paths.iter())
+            .map(|path| path.next_hop)
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .enumerate()
+            .map(|(idx, next_hop)| (next_hop, idx))
+            .collect();
+        let nb_neighbors = neighbor_indexes.len();
+        AtomicStats {
+            neighbor_indexes,
+            health: (0..nb_neighbors)
+                .map(|_| AtomicU8::new(HEALTH_UNKNOWN))
+                .collect(),
+            shards: (0..nb_shards.max(1))
+                .map(|_| Shard::new(nb_neighbors))
+                .collect(),
+            others: Mutex::new(Stats::default()),
+        }
+    }
+
+    fn shard(&self) -> &Shard {
+        let idx = SHARD_INDEX.with(|idx| {
+            *idx.get().get_or_insert_with(|| {
+                let new_idx = NEXT_SHARD_INDEX.fetch_add(1, Ordering::Relaxed);
+                idx.set(Some(new_idx));
+                new_idx
+            })
+        });
+        &self.shards[idx % self.shards.len()]
+    }
+
+    pub fn record_received(&self, bytes: usize) {
+        self.shard().received.add(bytes);
+    }
+
+    pub fn record_from_app(&self, bytes: usize) {
+        self.shard().from_apps.add(bytes);
+    }
+
+    pub fn record_delivered(&self, bytes: usize) {
+        self.shard().delivered.add(bytes);
+    }
+
+    pub fn record_drop(&self, reason: DropReason) {
+        self.shard().drops[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the result of sending a copy of `bytes` bytes to `next_hop`.
+    pub fn record_send(&self, next_hop: IpAddr, bytes: usize, success: bool) {
+        let idx = match self.neighbor_indexes.get(&next_hop) {
+            Some(idx) => *idx,
+            None => {
+                self.others
+                    .lock()
+                    .unwrap()
+                    .record_send(next_hop, bytes, success);
+                return;
+            }
+        };
+        let health = if success { HEALTH_UP } else { HEALTH_DOWN };
+        self.health[idx].store(health, Ordering::Relaxed);
+        let shard = self.shard();
+        if success {
+            shard.neighbors[idx].sent.add(bytes);
+        } else {
+            shard.neighbors[idx]
+                .send_errors
+                .fetch_add(1, Ordering::Relaxed);
+            shard.drops[DropReason::SendError as usize].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records the latency of a copy successfully sent to `next_hop`.
+    pub fn record_latency(&self, next_hop: IpAddr, latency: Duration) {
+        match self.neighbor_indexes.get(&next_hop) {
+            Some(idx) => self.shard().neighbors[*idx].latency.record(latency),
+            None => self
+                .others
+                .lock()
+                .unwrap()
+                .record_latency(next_hop, latency),
+        }
+    }
+
+    /// Records a lookup in the result cache.
+    pub fn record_cache(&self, hit: bool) {
+        let shard = self.shard();
+        let counter = if hit {
+            &shard.cache_hits
+        } else {
+            &shard.cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_cleared_bits(&self, nb_bits: u32) {
+        self.shard()
+            .cleared_bits
+            .fetch_add(nb_bits as u64, Ordering::Relaxed);
+    }
+
+    /// Records a copy of `bytes` bytes for each BFER of `bitstring`, whose bits are the
+    /// BFR-ids minus `offset`, i.e., the offset of the SI of the BIFT.
+    pub fn record_bfers(&self, bitstring: &Bitstring, offset: u64, bytes: usize) {
+        let shard = self.shard();
+        for bfr_id in bitstring.iter_set_positions().map(|bit| bit + offset) {
+            match shard.bfers.get(bfr_id as usize - 1) {
+                Some(counter) => counter.add(bytes),
+                None => self
+                    .others
+                    .lock()
+                    .unwrap()
+                    .bfers
+                    .entry(bfr_id)
+                    .or_default()
+                    .add(bytes),
+            }
+        }
+    }
+
+    /// Sums the counters of all shards.
+    pub fn snapshot(&self) -> Stats {
+        let mut stats = self.others.lock().unwrap().clone();
+        let add = |value: &mut u64, atomic: &AtomicU64| {
+            *value = value.wrapping_add(atomic.load(Ordering::Relaxed));
+        };
+        for shard in self.shards.iter() {
+            stats.received.merge(shard.received.load());
+            stats.from_apps.merge(shard.from_apps.load());
+            stats.delivered.merge(shard.delivered.load());
+            add(&mut stats.cache.hits, &shard.cache_hits);
+            add(&mut stats.cache.misses, &shard.cache_misses);
+            add(&mut stats.cleared_bits, &shard.cleared_bits);
+            for (reason, nb) in DropReason::ALL.iter().zip(shard.drops.iter()) {
+                if nb.load(Ordering::Relaxed) > 0 {
+                    add(stats.drops.entry(*reason).or_default(), nb);
+                }
+            }
+            for (idx, counter) in shard.bfers.iter().enumerate() {
+                let counter = counter.load();
+                if counter.packets > 0 {
+                    stats
+                        .bfers
+                        .entry(idx as u64 + 1)
+                        .or_default()
+                        .merge(counter);
+                }
+            }
+        }
+
+        // Only the neighbors to which copies were sent, as in `Stats`.
+        for (next_hop, idx) in self.neighbor_indexes.iter() {
+            let health = self.health[*idx].load(Ordering::Relaxed);
+            if health == HEALTH_UNKNOWN {
+                continue;
+            }
+            let neighbor = stats.neighbors.entry(*next_hop).or_default();
+            neighbor.healthy = health == HEALTH_UP;
+            for shard in self.shards.iter() {
+                let atomic = &shard.neighbors[*idx];
+                neighbor.sent.merge(atomic.sent.load());
+                add(&mut neighbor.send_errors, &atomic.send_errors);
+                atomic.latency.add_to(&mut neighbor.latency);
+            }
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    /// Tests the counters and their JSON representation.
+    fn test_stats() {
+        let mut stats = Stats::default();
+        let next_hop: IpAddr = "fc00::b".parse().unwrap();
+        stats.record_send(next_hop, 100, true);
+        stats.record_send(next_hop, 100, false);
+        stats.record_drop(DropReason::NoEntry);
+
+        // BFERs 65, 67 and 68, in the second word.
+        let bitstring = format!("1101{}", "0".repeat(64));
+        stats.record_bfers(&Bitstring::from_str(&bitstring).unwrap(), 0, 10);
+
+        assert_eq!(
+            stats.neighbors[&next_hop],
+            NeighborStats {
+                sent: Counter {
+                    packets: 1,
+                    bytes: 100
+                },
+                send_errors: 1,
+                healthy: false,
+                latency: LatencyHistogram::default(),
+                drained: false,
+                dead: false,
+            }
+        );
+        assert_eq!(stats.drops[&DropReason::SendError], 1);
+        assert_eq!(stats.drops[&DropReason::NoEntry], 1);
+        assert_eq!(stats.bfers.keys().copied().collect::<Vec<_>>(), vec![65, 67, 68]);
+        assert_eq!(stats.bfers[&67].bytes, 10);
+
+        let json = serde_json::to_string(&stats).unwrap();
+        assert!(json.contains(r#""drops":{"no_entry":1,"send_error":1}"#));
+        let stats_after: Stats = serde_json::from_str(&json).unwrap();
+        assert_eq!(stats_after, stats);
+    }
+
+    #[test]
+    /// Tests that the atomic counters updated by several threads give the same statistics.
+    fn test_atomic_stats() {
+        let state: BierState =
+            serde_json::from_str(include_str!("../configs/example.json")).unwrap();
+        let atomic_stats = AtomicStats::with_shards(&state, 3);
+        let neighbor: IpAddr = "fc00:b::1".parse().unwrap();
+        let unknown: IpAddr = "fc00:ff::1".parse().unwrap();
+        let bitstring = Bitstring::from_str("1011").unwrap();
+
+        let record = |stats: &mut Stats| {
+            stats.received.add(10);
+            stats.record_send(neighbor, 10, true);
+            stats.record_send(unknown, 10, true);
+            stats.record_bfers(&bitstring, 0, 10);
+            stats.record_drop(DropReason::NoEntry);
+            stats.record_cache(true);
+        };
+        let mut expected = Stats::default();
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..1000 {
+                        atomic_stats.record_received(10);
+                        atomic_stats.record_send(neighbor, 10, true);
+                        atomic_stats.record_send(unknown, 10, true);
+                        atomic_stats.record_bfers(&bitstring, 0, 10);
+                        atomic_stats.record_drop(DropReason::NoEntry);
+                        atomic_stats.record_cache(true);
+                    }
+                });
+            }
+        });
+        for _ in 0..4000 {
+            record(&mut expected);
+        }
+        assert_eq!(atomic_stats.snapshot(), expected);
+
+        // BFR-ids of another SI, above the atomic counters.
+        atomic_stats.record_bfers(&bitstring, 4096, 10);
+        let stats = atomic_stats.snapshot();
+        assert_eq!(stats.bfers[&4100].packets, 1);
+        assert_eq!(stats.bfers[&4].packets, 4000);
+
+        atomic_stats.record_send(neighbor, 10, false);
+        atomic_stats.record_latency(neighbor, Duration::from_micros(5));
+        let stats = atomic_stats.snapshot();
+        assert!(!stats.neighbors[&neighbor].healthy);
+        assert_eq!(stats.neighbors[&neighbor].send_errors, 1);
+        assert_eq!(stats.drops[&DropReason::SendError], 1);
+        assert_eq!(stats.neighbors[&neighbor].latency.count, 1);
+        assert_eq!(stats.neighbors.len(), 2);
+        atomic_stats.record_cache(false);
+        assert_eq!(atomic_stats.snapshot().cache.hit_rate(), Some(4000.0 / 4001.0));
+        assert_eq!(Stats::default().cache.hit_rate(), None);
+    }
+
+    #[test]
+    /// Tests the counters restored after a restart, and their deltas across a wrap-around.
+    fn test_stats_persistence() {
+        let next_hop: IpAddr = "fc00::b".parse().unwrap();
+        let mut saved = Stats::default();
+        saved.received.packets = u64::MAX - 1;
+        saved.record_send(next_hop, 100, true);
+        saved.record_drop(DropReason::NoEntry);
+
+        let path = std::env::temp_dir().join(format!("bier-stats-{}.json", std::process::id()));
+        saved.save(&path).unwrap();
+        let restored = Stats::load(&path).unwrap();
+        assert_eq!(restored, saved);
+        let _ = std::fs::remove_file(&path);
+        assert!(Stats::load(&path).is_err());
+
+        let mut live = Stats::default();
+        live.received.add(10);
+        live.received.add(10);
+        live.record_send(next_hop, 100, false);
+        let mut stats = restored.clone();
+        stats.merge(&live);
+        // The packet counter wrapped around.
+        assert_eq!(stats.received.packets, 0);
+        assert_eq!(stats.received.bytes, 20);
+        assert_eq!(stats.neighbors[&next_hop].sent.packets, 1);
+        assert_eq!(stats.neighbors[&next_hop].send_errors, 1);
+        assert!(!stats.neighbors[&next_hop].healthy);
+
+        let delta = stats.delta(&restored);
+        assert_eq!(
+            delta.received,
+            Counter {
+                packets: 2,
+                bytes: 20
+            }
+        );
+        assert_eq!(delta.neighbors[&next_hop].sent, Counter::default());
+        assert_eq!(delta.neighbors[&next_hop].send_errors, 1);
+        assert_eq!(
+            delta.drops.into_iter().collect::<Vec<_>>(),
+            vec![(DropReason::SendError, 1)]
+        );
+        assert_eq!(stats.delta(&stats), Stats::default());
+    }
+
+    #[test]
+    /// Tests the buckets and quantiles of the latency histogram.
+    fn test_latency_histogram() {
+        // Buckets are contiguous and cover all values.
+        let mut previous = 0;
+        for value in (1..5000).chain([u64::MAX / 2, u64::MAX]) {
+            let idx = bucket_index(value);
+            assert!(idx >= previous);
+            previous = idx;
+            let upper_bound = bucket_upper_bound(idx);
+            assert!(upper_bound >= value);
+            // Relative error below 1/16.
+            assert!((upper_bound - value) as f64 <= value as f64 / 16.0);
+        }
+        assert_eq!(bucket_index(15), 15);
+        assert_eq!(bucket_index(16), 16);
+        assert_eq!(bucket_upper_bound(bucket_index(1000)), 1023);
+
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.quantile(0.5), Duration::ZERO);
+        for micros in 1..=100 {
+            histogram.record(Duration::from_micros(micros));
+        }
+        assert_eq!(histogram.count, 100);
+        assert_eq!(histogram.mean(), Duration::from_nanos(50_500));
+        assert_eq!(histogram.quantile(1.0), Duration::from_micros(100));
+        let median = histogram.quantile(0.5).as_nanos() as f64;
+        assert!((50_000.0..=50_000.0 * 1.0625).contains(&median));
+        assert_eq!(histogram.iter_buckets().map(|(_, nb)| nb).sum::<u64>(), 100);
+
+        let json = serde_json::to_string(&histogram).unwrap();
+        assert_eq!(
+            serde_json::from_str::<LatencyHistogram>(&json).unwrap(),
+            histogram
+        );
+    }
+}
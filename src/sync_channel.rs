@@ -0,0 +1,208 @@
+//! Reliable channel exchanging control-plane updates between two BFRs, e.g., the
+//! join/leave messages and the flow tables of the overlay between a BFIR and its BFERs.
+//!
+//! Like the BIER processing, the channel does not perform any I/O: the datagrams it
+//! returns are carried by the caller, e.g., in BIER packets with the single bit of the
+//! peer, or over UDP. Each message has a sequence number and is retransmitted until
+//! acknowledged, with an exponential backoff. The messages are delivered once and in order.
+//!
+//! Each datagram starts with the kind of message (1 byte) and a sequence number (4 bytes):
+//! * a data message carries a payload after its sequence number;
+//! * an acknowledgment carries the next sequence number expected by the receiver,
+//!   i.e., it acknowledges all the previous messages.
+
+use crate::{Error, Result};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Default time before the first retransmission of a message.
+pub const DEFAULT_RTO: Duration = Duration::from_millis(200);
+
+/// Default number of retransmissions of a message before the channel fails.
+pub const DEFAULT_MAX_RETRIES: u32 = 8;
+
+/// Messages received ahead of the next expected one and kept until delivered.
+const MAX_OUT_OF_ORDER: usize = 64;
+
+const KIND_DATA: u8 = 0;
+const KIND_ACK: u8 = 1;
+const HEADER_LENGTH: usize = 5;
+
+/// Message sent and not yet acknowledged.
+struct Pending {
+    datagram: Vec<u8>,
+    retransmit_at: Instant,
+    retries: u32,
+}
+
+/// One end of the channel with a peer.
+pub struct SyncChannel {
+    rto: Duration,
+    max_retries: u32,
+    next_seq: u32,
+    unacked: BTreeMap<u32, Pending>,
+    /// Next sequence number to deliver.
+    expected: u32,
+    out_of_order: BTreeMap<u32, Vec<u8>>,
+}
+
+/// Result of the reception of a datagram.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Received {
+    /// Payloads delivered in order, possibly unblocked by this datagram.
+    pub delivered: Vec<Vec<u8>>,
+    /// Acknowledgment to send back to the peer.
+    pub ack: Option<Vec<u8>>,
+}
+
+impl Default for SyncChannel {
+    fn default() -> Self {
+        Self::new(DEFAULT_RTO, DEFAULT_MAX_RETRIES)
+    }
+}
+
+impl SyncChannel {
+    pub fn new(rto: Duration, max_retries: u32) -> Self {
+        SyncChannel {
+            rto,
+            max_retries,
+            next_seq: 0,
+            unacked: BTreeMap::new(),
+            expected: 0,
+            out_of_order: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the datagram carrying `payload` to the peer. It is retransmitted by `poll`
+    /// until acknowledged.
+    pub fn send(&mut self, payload: &[u8], now: Instant) -> Vec<u8> {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let mut datagram = Vec::with_capacity(HEADER_LENGTH + payload.len());
+        datagram.push(KIND_DATA);
+        datagram.extend_from_slice(&seq.to_be_bytes());
+        datagram.extend_from_slice(payload);
+        self.unacked.insert(
+            seq,
+            Pending {
+                datagram: datagram.clone(),
+                retransmit_at: now + self.rto,
+                retries: 0,
+            },
+        );
+        datagram
+    }
+
+    /// Handles a datagram of the peer.
+    pub fn recv(&mut self, datagram: &[u8]) -> Result<Received> {
+        if datagram.len() < HEADER_LENGTH {
+            return Err(Error::SyncMessage);
+        }
+        let seq = u32::from_be_bytes(datagram[1..HEADER_LENGTH].try_into().unwrap());
+        let mut received = Received::default();
+        match datagram[0] {
+            KIND_ACK => self.unacked.retain(|pending, _| *pending >= seq),
+            KIND_DATA => {
+                let payload = &datagram[HEADER_LENGTH..];
+                if seq == self.expected {
+                    received.delivered.push(payload.to_vec());
+                    self.expected += 1;
+                    while let Some(payload) = self.out_of_order.remove(&self.expected) {
+                        received.delivered.push(payload);
+                        self.expected += 1;
+                    }
+                } else if seq > self.expected && self.out_of_order.len() < MAX_OUT_OF_ORDER {
+                    self.out_of_order.insert(seq, payload.to_vec());
+                }
+                // Duplicates are acknowledged again, in case the previous ack was lost.
+                let mut ack = vec![KIND_ACK];
+                ack.extend_from_slice(&self.expected.to_be_bytes());
+                received.ack = Some(ack);
+            }
+            _ => return Err(Error::SyncMessage),
+        }
+        Ok(received)
+    }
+
+    /// Returns the datagrams to retransmit at `now`. Fails if a message is still not
+    /// acknowledged after the maximum number of retransmissions, e.g., the peer is down.
+    pub fn poll(&mut self, now: Instant) -> Result<Vec<Vec<u8>>> {
+        let mut datagrams = Vec::new();
+        for pending in self.unacked.values_mut() {
+            if pending.retransmit_at > now {
+                continue;
+            }
+            if pending.retries >= self.max_retries {
+                return Err(Error::SyncTimeout);
+            }
+            pending.retries += 1;
+            pending.retransmit_at = now + self.rto * 2u32.pow(pending.retries);
+            datagrams.push(pending.datagram.clone());
+        }
+        Ok(datagrams)
+    }
+
+    /// Next time at which `poll` must be called, if a message is not acknowledged.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.unacked
+            .values()
+            .map(|pending| pending.retransmit_at)
+            .min()
+    }
+
+    /// Number of messages sent and not yet acknowledged.
+    pub fn in_flight(&self) -> usize {
+        self.unacked.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    /// Tests the retransmission, acknowledgment and ordered delivery of the messages.
+    fn test_sync_channel() {
+        let start = Instant::now();
+        let rto = Duration::from_millis(100);
+        let mut bfir = SyncChannel::new(rto, 2);
+        let mut bfer = SyncChannel::new(rto, 2);
+
+        // The first message is lost, the second is received out of order.
+        let lost = bfir.send(b"join 1", start);
+        let second = bfir.send(b"join 2", start);
+        let received = bfer.recv(&second).unwrap();
+        assert!(received.delivered.is_empty());
+        assert_eq!(
+            bfir.recv(&received.ack.unwrap()).unwrap(),
+            Received::default()
+        );
+        assert_eq!(bfir.in_flight(), 2);
+
+        assert!(bfir.poll(start).unwrap().is_empty());
+        assert_eq!(bfir.deadline(), Some(start + rto));
+        let retransmitted = bfir.poll(start + rto).unwrap();
+        assert_eq!(retransmitted, vec![lost.clone(), second.clone()]);
+        let received = bfer.recv(&retransmitted[0]).unwrap();
+        assert_eq!(
+            received.delivered,
+            vec![b"join 1".to_vec(), b"join 2".to_vec()]
+        );
+        // The duplicate is acknowledged, but not delivered again.
+        let duplicate = bfer.recv(&retransmitted[1]).unwrap();
+        assert!(duplicate.delivered.is_empty());
+        bfir.recv(&duplicate.ack.unwrap()).unwrap();
+        assert_eq!(bfir.in_flight(), 0);
+        assert_eq!(bfir.deadline(), None);
+
+        // The peer never answers.
+        bfir.send(b"leave 1", start);
+        assert_eq!(bfir.poll(start + rto).unwrap().len(), 1);
+        assert_eq!(bfir.poll(start + rto * 3).unwrap().len(), 1);
+        assert_eq!(bfir.poll(start + rto * 7), Err(Error::SyncTimeout));
+
+        assert_eq!(bfer.recv(&[KIND_DATA, 0]), Err(Error::SyncMessage));
+        assert_eq!(bfer.recv(&[42, 0, 0, 0, 0]), Err(Error::SyncMessage));
+    }
+}
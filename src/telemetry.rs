@@ -0,0 +1,310 @@
+//! Export of the traces and metrics of the BFR with OpenTelemetry (OTLP over HTTP),
+//! so that the BFR shows up in the same observability stack as the applications.
+//!
+//! Each processed packet becomes a span, from its reception to the sending of the last
+//! copy, with the header fields and the number of copies as attributes. The counters of
+//! `Stats` are exported as observable metrics, from the last snapshot given to `update`.
+//!
+//! The exporters follow the standard environment variables, e.g.,
+//! `OTEL_EXPORTER_OTLP_ENDPOINT` or `OTEL_TRACES_SAMPLER` to sample the spans.
+
+use crate::packet_log::{PacketLogEntry, PacketSource};
+use crate::router::Router;
+use crate::runtime::EventSource;
+use crate::stats::Stats;
+use opentelemetry::metrics::{AsyncInstrument, Meter, MeterProvider};
+use opentelemetry::trace::{Span, Status, Tracer, TracerProvider};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+const SCOPE: &str = "bier-rust";
+
+/// Interval between two refreshes of the snapshot of the statistics,
+/// when the telemetry is registered with the event loop.
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Quantiles of the latency histograms exported as gauges.
+const LATENCY_QUANTILES: [f64; 4] = [0.5, 0.9, 0.99, 1.0];
+
+pub struct Telemetry {
+    tracer_provider: SdkTracerProvider,
+    meter_provider: SdkMeterProvider,
+    /// Snapshot of the statistics read by the metric callbacks.
+    stats: Arc<Mutex<Stats>>,
+    next_update: Instant,
+}
+
+impl Telemetry {
+    /// Exports to an OTLP/HTTP collector, e.g., `http://localhost:4318`.
+    /// Without `endpoint`, the exporters use the OTLP environment variables.
+    /// The `loopback` identifies this BFR among the instances of the service.
+    pub fn otlp(endpoint: Option<&str>, loopback: IpAddr) -> io::Result<Self> {
+        let mut span_exporter = SpanExporter::builder().with_http();
+        let mut metric_exporter = MetricExporter::builder().with_http();
+        if let Some(endpoint) = endpoint {
+            let endpoint = endpoint.trim_end_matches('/');
+            span_exporter = span_exporter.with_endpoint(format!("{}/v1/traces", endpoint));
+            metric_exporter = metric_exporter.with_endpoint(format!("{}/v1/metrics", endpoint));
+        }
+        let span_exporter = span_exporter.build().map_err(io::Error::other)?;
+        let metric_exporter = metric_exporter.build().map_err(io::Error::other)?;
+
+        let resource = Resource::builder()
+            .with_service_name(SCOPE)
+            .with_attribute(KeyValue::new("service.instance.id", loopback.to_string()))
+            .build();
+        let tracer_provider = SdkTracerProvider::builder()
+            .with_resource(resource.clone())
+            .with_batch_exporter(span_exporter)
+            .build();
+        let meter_provider = SdkMeterProvider::builder()
+            .with_resource(resource)
+            .with_periodic_exporter(metric_exporter)
+            .build();
+        Ok(Self::new(tracer_provider, meter_provider))
+    }
+
+    /// Uses the given providers, e.g., with other exporters.
+    pub fn new(tracer_provider: SdkTracerProvider, meter_provider: SdkMeterProvider) -> Self {
+        let stats = Arc::new(Mutex::new(Stats::default()));
+        register_metrics(&meter_provider.meter(SCOPE), &stats);
+        Telemetry {
+            tracer_provider,
+            meter_provider,
+            stats,
+            next_update: Instant::now(),
+        }
+    }
+
+    /// Tracer of the spans of the processed packets, given to the daemon.
+    pub fn tracer(&self) -> SdkTracer {
+        self.tracer_provider.tracer(SCOPE)
+    }
+
+    /// Updates the snapshot of the statistics exported at the next collection.
+    pub fn update(&self, stats: &Stats) {
+        self.stats.lock().unwrap().clone_from(stats);
+    }
+
+    /// Exports the remaining spans and metrics.
+    pub fn shutdown(&self) {
+        if let Err(e) = self.tracer_provider.shutdown() {
+            log::error!("Error when exporting the remaining spans: {:?}", e);
+        }
+        if let Err(e) = self.meter_provider.shutdown() {
+            log::error!("Error when exporting the remaining metrics: {:?}", e);
+        }
+    }
+}
+
+/// Refreshes the snapshot of the statistics periodically.
+impl EventSource for Telemetry {
+    fn deadline(&self) -> Option<Instant> {
+        Some(self.next_update)
+    }
+
+    fn handle(&mut self, router: &mut Router) -> io::Result<()> {
+        self.update(&router.daemon().stats());
+        self.next_update = Instant::now() + UPDATE_INTERVAL;
+        Ok(())
+    }
+}
+
+/// Exports the span of a processed packet, started at its `ingress` time.
+pub(crate) fn export_span(tracer: &SdkTracer, entry: &PacketLogEntry, ingress: Instant) {
+    let source = match entry.source {
+        PacketSource::Network => "network",
+        PacketSource::Application => "application",
+    };
+    let mut attributes = vec![
+        KeyValue::new("bier.source", source),
+        KeyValue::new("bier.length", entry.length as i64),
+        KeyValue::new("bier.copies", entry.copies.len() as i64),
+    ];
+    if let Some(header) = entry.header {
+        attributes.push(KeyValue::new("bier.bift_id", header.get_bift_id() as i64));
+        attributes.push(KeyValue::new("bier.proto", header.get_proto() as i64));
+        attributes.push(KeyValue::new(
+            "bier.bitstring",
+            format!("{:?}", header.get_bitstring()),
+        ));
+    }
+    let status = match entry.drop {
+        Some(reason) => {
+            attributes.push(KeyValue::new("bier.drop", format!("{:?}", reason)));
+            Status::error(format!("{:?}", reason))
+        }
+        None => Status::Ok,
+    };
+
+    let mut span = tracer
+        .span_builder("bier.process_packet")
+        .with_start_time(SystemTime::now() - ingress.elapsed())
+        .with_attributes(attributes)
+        .with_status(status)
+        .start(tracer);
+    span.end();
+}
+
+fn next_hop(next_hop: &IpAddr) -> Vec<KeyValue> {
+    vec![KeyValue::new("next_hop", next_hop.to_string())]
+}
+
+/// Values of a metric computed from the statistics, with their attributes.
+type Observations<T> = Vec<(T, Vec<KeyValue>)>;
+
+fn register_metrics(meter: &Meter, stats: &Arc<Mutex<Stats>>) {
+    let counter =
+        |name: &'static str, unit: &'static str, observe: fn(&Stats) -> Observations<u64>| {
+            let stats = stats.clone();
+            meter
+                .u64_observable_counter(name)
+                .with_unit(unit)
+                .with_callback(move |instrument: &dyn AsyncInstrument<u64>| {
+                    for (value, attributes) in observe(&stats.lock().unwrap()) {
+                        instrument.observe(value, &attributes);
+                    }
+                })
+                .build();
+        };
+    counter("bier.received.packets", "{packet}", |s| {
+        vec![(s.received.packets, vec![])]
+    });
+    counter("bier.received.bytes", "By", |s| {
+        vec![(s.received.bytes, vec![])]
+    });
+    counter("bier.application.packets", "{packet}", |s| {
+        vec![(s.from_apps.packets, vec![])]
+    });
+    counter("bier.application.bytes", "By", |s| {
+        vec![(s.from_apps.bytes, vec![])]
+    });
+    counter("bier.delivered.packets", "{packet}", |s| {
+        vec![(s.delivered.packets, vec![])]
+    });
+    counter("bier.delivered.bytes", "By", |s| {
+        vec![(s.delivered.bytes, vec![])]
+    });
+    counter("bier.cleared_bits", "{bit}", |s| {
+        vec![(s.cleared_bits, vec![])]
+    });
+    counter("bier.cache.hits", "{lookup}", |s| {
+        vec![(s.cache.hits, vec![])]
+    });
+    counter("bier.cache.misses", "{lookup}", |s| {
+        vec![(s.cache.misses, vec![])]
+    });
+    counter("bier.neighbor.sent.packets", "{packet}", |s| {
+        s.neighbors
+            .iter()
+            .map(|(nh, n)| (n.sent.packets, next_hop(nh)))
+            .collect()
+    });
+    counter("bier.neighbor.sent.bytes", "By", |s| {
+        s.neighbors
+            .iter()
+            .map(|(nh, n)| (n.sent.bytes, next_hop(nh)))
+            .collect()
+    });
+    counter("bier.neighbor.send_errors", "{packet}", |s| {
+        s.neighbors
+            .iter()
+            .map(|(nh, n)| (n.send_errors, next_hop(nh)))
+            .collect()
+    });
+    counter("bier.bfer.packets", "{packet}", |s| {
+        s.bfers
+            .iter()
+            .map(|(bfr_id, c)| (c.packets, vec![KeyValue::new("bfr_id", *bfr_id as i64)]))
+            .collect()
+    });
+    counter("bier.drops", "{packet}", |s| {
+        s.drops
+            .iter()
+            .map(|(reason, nb)| (*nb, vec![KeyValue::new("reason", format!("{:?}", reason))]))
+            .collect()
+    });
+
+    let cloned = stats.clone();
+    meter
+        .u64_observable_gauge("bier.neighbor.healthy")
+        .with_callback(move |instrument| {
+            for (nh, neighbor) in cloned.lock().unwrap().neighbors.iter() {
+                instrument.observe(neighbor.healthy as u64, &next_hop(nh));
+            }
+        })
+        .build();
+    let cloned = stats.clone();
+    meter
+        .f64_observable_gauge("bier.neighbor.latency")
+        .with_unit("s")
+        .with_callback(move |instrument| {
+            for (nh, neighbor) in cloned.lock().unwrap().neighbors.iter() {
+                for quantile in LATENCY_QUANTILES {
+                    let mut attributes = next_hop(nh);
+                    attributes.push(KeyValue::new("quantile", quantile));
+                    let value = neighbor.latency.quantile(quantile).as_secs_f64();
+                    instrument.observe(value, &attributes);
+                }
+            }
+        })
+        .build();
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::stats::DropReason;
+    use opentelemetry_sdk::metrics::{InMemoryMetricExporter, PeriodicReader};
+    use opentelemetry_sdk::trace::InMemorySpanExporter;
+
+    #[test]
+    /// Tests the export of the spans and of the statistics.
+    fn test_telemetry() {
+        let span_exporter = InMemorySpanExporter::default();
+        let metric_exporter = InMemoryMetricExporter::default();
+        let telemetry = Telemetry::new(
+            SdkTracerProvider::builder()
+                .with_simple_exporter(span_exporter.clone())
+                .build(),
+            SdkMeterProvider::builder()
+                .with_reader(PeriodicReader::builder(metric_exporter.clone()).build())
+                .build(),
+        );
+
+        let mut entry = PacketLogEntry::now(PacketSource::Application, 3);
+        entry.drop = Some(DropReason::UnknownBift);
+        export_span(&telemetry.tracer(), &entry, Instant::now());
+        let spans = span_exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].name, "bier.process_packet");
+        assert!(spans[0]
+            .attributes
+            .contains(&KeyValue::new("bier.source", "application")));
+        assert!(matches!(spans[0].status, Status::Error { .. }));
+
+        let mut stats = Stats::default();
+        stats.record_send("fc00::b".parse().unwrap(), 100, true);
+        stats.record_drop(DropReason::NoEntry);
+        telemetry.update(&stats);
+        telemetry.meter_provider.force_flush().unwrap();
+        let metrics = metric_exporter.get_finished_metrics().unwrap();
+        let names: Vec<&str> = metrics
+            .iter()
+            .flat_map(|resource| resource.scope_metrics())
+            .flat_map(|scope| scope.metrics())
+            .map(|metric| metric.name())
+            .collect();
+        assert!(names.contains(&"bier.neighbor.sent.packets"));
+        assert!(names.contains(&"bier.drops"));
+        assert!(names.contains(&"bier.neighbor.latency"));
+    }
+}
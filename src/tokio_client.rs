@@ -0,0 +1,353 @@
+//! Adaptors to plug the BIER daemon in tokio applications.
+//! `AsyncBierSocket` is the async version of `client::BierClient`. Alternatively, packets
+//! delivered by the daemon are received as a `Stream` of `OwnedRecvInfo`, and packets to send
+//! in the BIER domain are given to a `Sink` of `OwnedSendInfo`.
+
+use crate::api::{
+    message_type, MessageType, OwnedRecvInfo, OwnedSendInfo, Query, RecvInfo, Registration,
+    Response, SendInfo, API_VERSION,
+};
+use crate::client::check_notification;
+use futures_core::Stream;
+use futures_sink::Sink;
+use log::debug;
+use std::collections::VecDeque;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::ReadBuf;
+use tokio::net::UnixDatagram;
+
+/// Maximum length of a message received from the daemon.
+const MAX_MESSAGE_LENGTH: usize = 65535;
+
+/// Maximum number of messages received while waiting for the answer of the daemon, and
+/// queued for `AsyncBierSocket::recv`. The next ones are dropped.
+const MAX_QUEUED_MESSAGES: usize = 1024;
+
+/// Application socket connected to the BIER daemon, for tokio applications.
+/// The socket file is removed when the socket is dropped.
+pub struct AsyncBierSocket {
+    socket: UnixDatagram,
+    daemon_path: PathBuf,
+    my_path: PathBuf,
+    buffer: Vec<u8>,
+    /// Messages received while waiting for the answer of the daemon, e.g., delivered packets.
+    queued: VecDeque<Vec<u8>>,
+}
+
+impl AsyncBierSocket {
+    /// Binds the application socket to `my_path`, where the daemon delivers the packets,
+    /// and sends the packets to the daemon at `daemon_path`. A stale socket file at
+    /// `my_path` is removed first. Must be called from a tokio runtime.
+    pub fn connect(daemon_path: impl AsRef<Path>, my_path: impl AsRef<Path>) -> io::Result<Self> {
+        let _ = std::fs::remove_file(my_path.as_ref());
+        let socket = UnixDatagram::bind(my_path.as_ref())?;
+
+        Ok(AsyncBierSocket {
+            socket,
+            daemon_path: daemon_path.as_ref().to_path_buf(),
+            my_path: my_path.as_ref().to_path_buf(),
+            buffer: vec![0u8; MAX_MESSAGE_LENGTH],
+            queued: VecDeque::new(),
+        })
+    }
+
+    /// Sends a payload in the BIER domain.
+    pub async fn send(&mut self, send_info: &SendInfo<'_>) -> io::Result<()> {
+        let len = send_info.to_slice(&mut self.buffer).map_err(to_io_error)?;
+        self.socket
+            .send_to(&self.buffer[..len], &self.daemon_path)
+            .await?;
+        Ok(())
+    }
+
+    /// Waits for a packet delivered by the daemon, starting with the ones received during
+    /// a query or a registration. The error notifications of the daemon are returned as
+    /// errors, see `BierClient::recv`.
+    pub async fn recv(&mut self) -> io::Result<RecvInfo<'_>> {
+        let read = match self.queued.pop_front() {
+            Some(message) => {
+                self.buffer[..message.len()].copy_from_slice(&message);
+                message.len()
+            }
+            None => self.socket.recv(&mut self.buffer).await?,
+        };
+        check_notification(&self.buffer[..read])?;
+        RecvInfo::from_slice(&self.buffer[..read]).map_err(to_io_error)
+    }
+
+    /// Queries information from the daemon and waits for the response.
+    pub async fn query(&mut self, query: Query, timeout: Duration) -> io::Result<Response> {
+        let len = query.to_slice(&mut self.buffer).map_err(to_io_error)?;
+        let read = self.request(len, MessageType::Response, timeout).await?;
+        Response::from_slice(&self.buffer[..read]).map_err(to_io_error)
+    }
+
+    /// Registers the application to receive the packets with `proto`, and waits for the
+    /// acknowledgment of the daemon, see `BierClient::register`.
+    pub async fn register(&mut self, proto: u16, timeout: Duration) -> io::Result<()> {
+        let path = self.my_path.to_str().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "The path is not valid UTF-8")
+        })?;
+        let registration = Registration {
+            proto,
+            path: path.to_string(),
+            version: API_VERSION,
+        };
+        let len = registration
+            .to_slice(&mut self.buffer)
+            .map_err(to_io_error)?;
+        let read = self.request(len, MessageType::Register, timeout).await?;
+        match Registration::from_slice(&self.buffer[..read]).map_err(to_io_error)? {
+            ack if ack == registration => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Acknowledgment of another registration",
+            )),
+        }
+    }
+
+    /// Sends the first `len` bytes of the buffer and waits for the answer of the daemon, a
+    /// message of type `answer` or an error notification, written in the buffer. Returns its
+    /// length. The other messages, e.g., the packets delivered meanwhile, are queued for `recv`.
+    async fn request(
+        &mut self,
+        len: usize,
+        answer: MessageType,
+        timeout: Duration,
+    ) -> io::Result<usize> {
+        self.socket
+            .send_to(&self.buffer[..len], &self.daemon_path)
+            .await?;
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let read = tokio::time::timeout_at(deadline, self.socket.recv(&mut self.buffer))
+                .await
+                .map_err(|_| {
+                    io::Error::new(io::ErrorKind::TimedOut, "No answer of the daemon")
+                })??;
+            match message_type(&self.buffer[..read]) {
+                Ok(message_type) if message_type == answer => return Ok(read),
+                Ok(MessageType::Error) => {
+                    return check_notification(&self.buffer[..read]).map(|_| read)
+                }
+                _ if self.queued.len() < MAX_QUEUED_MESSAGES => {
+                    self.queued.push_back(self.buffer[..read].to_vec());
+                }
+                _ => debug!("Dropping a message received while waiting for the daemon"),
+            }
+        }
+    }
+}
+
+impl Drop for AsyncBierSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.my_path);
+    }
+}
+
+fn to_io_error(e: crate::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Splits `socket`, bound to the path where the daemon delivers the packets, into
+/// a `RecvStream` and a `SendSink` sending the packets to the daemon at `daemon_path`.
+pub fn split(socket: UnixDatagram, daemon_path: impl Into<PathBuf>) -> (RecvStream, SendSink) {
+    let socket = Arc::new(socket);
+    (
+        RecvStream {
+            socket: socket.clone(),
+            buffer: vec![0u8; MAX_MESSAGE_LENGTH],
+        },
+        SendSink {
+            socket,
+            daemon_path: daemon_path.into(),
+            pending: None,
+        },
+    )
+}
+
+/// Stream of the packets delivered by the daemon.
+/// Messages that cannot be parsed are returned as `io::ErrorKind::InvalidData` errors,
+/// and the stream can still be polled afterwards. The stream never ends.
+pub struct RecvStream {
+    socket: Arc<UnixDatagram>,
+    buffer: Vec<u8>,
+}
+
+impl Stream for RecvStream {
+    type Item = io::Result<OwnedRecvInfo>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let mut read_buf = ReadBuf::new(&mut this.buffer);
+        if let Err(e) = ready!(this.socket.poll_recv(cx, &mut read_buf)) {
+            return Poll::Ready(Some(Err(e)));
+        }
+
+        // The error notifications of the daemon are errors of the stream, see `BierClient::recv`.
+        let res = crate::client::check_notification(read_buf.filled()).and_then(|_| {
+            RecvInfo::from_slice(read_buf.filled())
+                .map(|recv_info| (&recv_info).into())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        });
+        Poll::Ready(Some(res))
+    }
+}
+
+/// Sink of the packets to send in the BIER domain through the daemon.
+/// Each packet is sent in its own datagram.
+pub struct SendSink {
+    socket: Arc<UnixDatagram>,
+    daemon_path: PathBuf,
+    /// Encoded message waiting to be sent.
+    pending: Option<Vec<u8>>,
+}
+
+impl Sink<OwnedSendInfo> for SendSink {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Only one message is buffered at a time.
+        self.poll_flush(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: OwnedSendInfo) -> io::Result<()> {
+        let send_info = item.as_info();
+        let mut message = vec![0u8; send_info.message_length()];
+        send_info
+            .to_slice(&mut message)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.get_mut().pending = Some(message);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Some(message) = &this.pending {
+            ready!(this.socket.poll_send_to(cx, message, &this.daemon_path))?;
+            this.pending = None;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use futures::{SinkExt, StreamExt};
+
+    fn get_socket_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("bier-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    /// Tests the exchanges of the async socket with a dummy daemon.
+    async fn test_async_bier_socket() {
+        let daemon_path = get_socket_path("async-socket-daemon");
+        let app_path = get_socket_path("async-socket-app");
+        let daemon = UnixDatagram::bind(&daemon_path).unwrap();
+        let mut socket = AsyncBierSocket::connect(&daemon_path, &app_path).unwrap();
+
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b110],
+            payload: &[1, 2, 3, 4],
+            metadata: None,
+        };
+        socket.send(&send_info).await.unwrap();
+        let mut buffer = [0u8; 100];
+        let read = daemon.recv(&mut buffer).await.unwrap();
+        assert_eq!(
+            RecvInfo::from_slice(&buffer[..read]).unwrap().payload,
+            &[1, 2, 3, 4]
+        );
+
+        daemon.send_to(&buffer[..read], &app_path).await.unwrap();
+        let recv_info = socket.recv().await.unwrap();
+        assert_eq!((recv_info.proto, recv_info.payload), (6, &[1, 2, 3, 4][..]));
+
+        // The daemon answers the query, after delivering a packet to the application.
+        let packet = buffer[..read].to_vec();
+        let answer = async {
+            let (read, from) = daemon.recv_from(&mut buffer).await.unwrap();
+            assert_eq!(Query::from_slice(&buffer[..read]), Ok(Query::BfrNames));
+            let from = from.as_pathname().unwrap();
+            daemon.send_to(&packet, from).await.unwrap();
+            let len = Response::BfrNames(vec![]).to_slice(&mut buffer).unwrap();
+            daemon.send_to(&buffer[..len], from).await.unwrap();
+        };
+        let (response, _) = tokio::join!(
+            socket.query(Query::BfrNames, Duration::from_secs(1)),
+            answer
+        );
+        assert_eq!(response.unwrap(), Response::BfrNames(vec![]));
+        // The packet is received after the answer.
+        let recv_info = socket.recv().await.unwrap();
+        assert_eq!(recv_info.payload, &[1, 2, 3, 4]);
+
+        // Without acknowledgment of the registration.
+        let e = socket
+            .register(17, Duration::from_millis(10))
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+
+        drop(socket);
+        assert!(!app_path.exists());
+        std::fs::remove_file(&daemon_path).unwrap();
+    }
+
+    #[tokio::test]
+    /// Tests that packets given to the sink reach the daemon, and that messages
+    /// delivered by the daemon are received from the stream.
+    async fn test_stream_sink() {
+        let daemon_path = get_socket_path("stream-sink-daemon");
+        let app_path = get_socket_path("stream-sink-app");
+        let daemon = UnixDatagram::bind(&daemon_path).unwrap();
+        let app = UnixDatagram::bind(&app_path).unwrap();
+
+        let (mut stream, mut sink) = split(app, &daemon_path);
+
+        let send_info = OwnedSendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: vec![0, 0, 0, 0, 0, 0, 0, 0b110],
+            payload: vec![1, 2, 3, 4],
+            metadata: None,
+        };
+        sink.send(send_info.clone()).await.unwrap();
+
+        let mut buffer = [0u8; 100];
+        let read = daemon.recv(&mut buffer).await.unwrap();
+        let recv_info = RecvInfo::from_slice(&buffer[..read]).unwrap();
+        assert_eq!(OwnedRecvInfo::from(&recv_info), send_info);
+
+        // The daemon delivers the packet back to the application.
+        daemon.send_to(&buffer[..read], &app_path).await.unwrap();
+        let recv_info = stream.next().await.unwrap().unwrap();
+        assert_eq!(recv_info, send_info);
+
+        // Garbage is reported, but does not end the stream.
+        daemon.send_to(&[1, 2, 3], &app_path).await.unwrap();
+        let err = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        daemon.send_to(&buffer[..read], &app_path).await.unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap(), send_info);
+
+        std::fs::remove_file(&daemon_path).unwrap();
+        std::fs::remove_file(&app_path).unwrap();
+    }
+}
@@ -0,0 +1,226 @@
+//! Event loop of the daemon on a tokio runtime, instead of the mio loop of `runtime`, e.g.,
+//! to embed the BFR in an application already using tokio.
+//!
+//! Each source with a file descriptor has its own task waiting for the descriptor to be
+//! readable, which sends the index of the source to the forwarding task through a bounded
+//! channel. The forwarding task owns the `Router`, handles the ready sources and their
+//! deadlines, and sends the deferred copies, so that the same `EventSource`s run on both
+//! loops. The task of a source waits for its source to be handled before polling again.
+
+use crate::router::Router;
+use crate::runtime::EventSource;
+use log::error;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+
+#[derive(Default)]
+pub struct TokioEventLoop {
+    sources: Vec<Box<dyn EventSource>>,
+}
+
+impl TokioEventLoop {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `source`, whose index is returned.
+    pub fn register(&mut self, source: impl EventSource + 'static) -> usize {
+        self.sources.push(Box::new(source));
+        self.sources.len() - 1
+    }
+
+    /// Handles the sources until the task of a file descriptor fails. Must be called
+    /// within a tokio runtime with the IO and time drivers enabled.
+    pub async fn run(mut self, router: &mut Router) -> io::Result<()> {
+        let (ready_tx, mut ready_rx) = mpsc::channel(self.sources.len().max(1));
+        let mut handled = Vec::with_capacity(self.sources.len());
+        let mut tasks = TaskGuard(Vec::new());
+        for (idx, source) in self.sources.iter().enumerate() {
+            let Some(fd) = source.fd() else {
+                handled.push(None);
+                continue;
+            };
+            let notify = Arc::new(Notify::new());
+            handled.push(Some(notify.clone()));
+            let fd = AsyncFd::with_interest(fd, Interest::READABLE)?;
+            tasks.0.push(tokio::spawn(wait_readable(
+                idx,
+                fd,
+                ready_tx.clone(),
+                notify,
+            )));
+        }
+
+        loop {
+            let now = Instant::now();
+            for (idx, source) in self.sources.iter_mut().enumerate() {
+                if source.deadline().is_some_and(|deadline| deadline <= now) {
+                    if let Err(e) = source.handle(router) {
+                        error!("Error when handling the source {}: {:?}", idx, e);
+                    }
+                }
+            }
+
+            let ready = if router.daemon().has_deferred() {
+                match ready_rx.try_recv() {
+                    Ok(ready) => ready,
+                    Err(TryRecvError::Empty) => {
+                        router.daemon_mut().send_deferred();
+                        continue;
+                    }
+                    Err(TryRecvError::Disconnected) => unreachable!("The loop holds a sender"),
+                }
+            } else {
+                let deadline = self
+                    .sources
+                    .iter()
+                    .filter_map(|source| source.deadline())
+                    .min();
+                let recv = ready_rx.recv();
+                let ready = match deadline {
+                    Some(deadline) => tokio::time::timeout_at(deadline.into(), recv).await,
+                    None => Ok(recv.await),
+                };
+                match ready {
+                    Ok(ready) => ready.expect("The loop holds a sender"),
+                    // The deadline of a source is reached.
+                    Err(_) => continue,
+                }
+            };
+
+            let idx = ready?;
+            if let Err(e) = self.sources[idx].handle(router) {
+                error!("Error when handling the source {}: {:?}", idx, e);
+            }
+            if let Some(notify) = &handled[idx] {
+                notify.notify_one();
+            }
+        }
+    }
+}
+
+/// Aborts the tasks of the file descriptors when the loop returns or is dropped.
+struct TaskGuard(Vec<JoinHandle<()>>);
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        for task in &self.0 {
+            task.abort();
+        }
+    }
+}
+
+/// Sends `idx` to the forwarding task each time `fd` becomes readable, then waits until
+/// the source is handled, i.e., read until `WouldBlock`.
+async fn wait_readable(
+    idx: usize,
+    fd: AsyncFd<RawFd>,
+    ready: mpsc::Sender<io::Result<usize>>,
+    handled: Arc<Notify>,
+) {
+    loop {
+        let mut guard = match fd.readable().await {
+            Ok(guard) => guard,
+            Err(e) => {
+                let _ = ready.send(Err(e)).await;
+                return;
+            }
+        };
+        if ready.send(Ok(idx)).await.is_err() {
+            return;
+        }
+        handled.notified().await;
+        // Only cleared if the descriptor did not become readable again in the meantime.
+        guard.clear_ready();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::bier::example_state;
+    use crate::control::{ControlRequest, ControlResponse};
+    use crate::daemon::{BierDaemon, MemoryTransport};
+    use crate::runtime::ControlSource;
+    use std::os::unix::net::UnixDatagram;
+    use std::time::Duration;
+
+    /// Counts the calls of the handler, once after `next`.
+    struct Timer {
+        next: Option<Instant>,
+        nb_calls: std::rc::Rc<std::cell::Cell<usize>>,
+    }
+
+    impl EventSource for Timer {
+        fn deadline(&self) -> Option<Instant> {
+            self.next
+        }
+
+        fn handle(&mut self, _router: &mut Router) -> io::Result<()> {
+            self.nb_calls.set(self.nb_calls.get() + 1);
+            self.next = None;
+            Ok(())
+        }
+    }
+
+    #[test]
+    /// Tests the sockets and the timers registered with the tokio event loop.
+    fn test_tokio_event_loop() {
+        let mut router = Router::new(
+            BierDaemon::builder()
+                .state(example_state())
+                .transport(MemoryTransport::default())
+                .build()
+                .unwrap(),
+        );
+
+        let dir = std::env::temp_dir();
+        let control_path = dir.join(format!("bier-tokio-loop-{}", std::process::id()));
+        let requester_path = dir.join(format!("bier-tokio-loop-req-{}", std::process::id()));
+        let _ = std::fs::remove_file(&control_path);
+        let _ = std::fs::remove_file(&requester_path);
+        let control = UnixDatagram::bind(&control_path).unwrap();
+        let requester = UnixDatagram::bind(&requester_path).unwrap();
+        requester.connect(&control_path).unwrap();
+        let mut event_loop = TokioEventLoop::new();
+        event_loop.register(ControlSource::new(socket2::Socket::from(control)).unwrap());
+        let nb_calls = std::rc::Rc::new(std::cell::Cell::new(0));
+        event_loop.register(Timer {
+            next: Some(Instant::now() + Duration::from_millis(10)),
+            nb_calls: nb_calls.clone(),
+        });
+        let request = serde_json::to_vec(&ControlRequest::Stats).unwrap();
+        requester.send(&request).unwrap();
+        requester.send(&request).unwrap();
+        requester
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        // The loop runs until the timeout.
+        let run = runtime.block_on(async {
+            tokio::time::timeout(Duration::from_millis(200), event_loop.run(&mut router)).await
+        });
+        assert!(run.is_err());
+        let mut buffer = vec![0u8; 65535];
+        for _ in 0..2 {
+            let read = requester.recv(&mut buffer).unwrap();
+            let response: ControlResponse = serde_json::from_slice(&buffer[..read]).unwrap();
+            assert!(matches!(response, ControlResponse::Stats(_)));
+        }
+        assert_eq!(nb_calls.get(), 1);
+        let _ = std::fs::remove_file(&control_path);
+        let _ = std::fs::remove_file(&requester_path);
+    }
+}
@@ -0,0 +1,163 @@
+//! TUN device turning the daemon into a BFIR and a BFER of IP multicast traffic.
+//!
+//! At the BFIR, the IP multicast packets routed to the TUN device are read by `TunSource`,
+//! whose source and group are mapped to a bitstring by the multicast overlay (see
+//! `overlay`), and are encapsulated in a BIER packet with the proto of their IP version
+//! (`BierDaemon::send`). At the BFER, the dispatcher writes the IPv4 and IPv6 payloads to
+//! the device (`TunSource::destination`), so that the kernel delivers them to the local
+//! receivers of the group.
+
+use crate::api::SendInfo;
+use crate::dispatch::Destination;
+use crate::header::{PROTO_IPV4, PROTO_IPV6};
+use crate::router::Router;
+use crate::runtime::EventSource;
+use log::debug;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+/// Largest IP packet read from the device.
+const TUN_MTU: usize = 65535;
+
+/// Opens the TUN device `ifname`, without the packet information header, in non-blocking
+/// mode. Requires `CAP_NET_ADMIN`.
+pub fn open_device(ifname: &str) -> io::Result<File> {
+    if ifname.len() >= libc::IFNAMSIZ {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Interface name too long: {}", ifname),
+        ));
+    }
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+        .open("/dev/net/tun")?;
+    let mut ifreq: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (dst, src) in ifreq.ifr_name.iter_mut().zip(ifname.bytes()) {
+        *dst = src as libc::c_char;
+    }
+    ifreq.ifr_ifru.ifru_flags = (libc::IFF_TUN | libc::IFF_NO_PI) as libc::c_short;
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::TUNSETIFF, &ifreq) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file)
+}
+
+/// BIER proto, source and destination addresses of an IP packet.
+fn ip_addresses(packet: &[u8]) -> Option<(u8, IpAddr, IpAddr)> {
+    match packet.first()? >> 4 {
+        4 => {
+            let src: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            let (src, dst) = (Ipv4Addr::from(src), Ipv4Addr::from(dst));
+            Some((PROTO_IPV4, IpAddr::V4(src), IpAddr::V4(dst)))
+        }
+        6 => {
+            let src: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            let (src, dst) = (Ipv6Addr::from(src), Ipv6Addr::from(dst));
+            Some((PROTO_IPV6, IpAddr::V6(src), IpAddr::V6(dst)))
+        }
+        _ => None,
+    }
+}
+
+/// Encapsulates the IP multicast packets read from the TUN device toward the BFERs of their
+/// group in the overlay. The other packets, e.g., the unicast packets of the kernel, are
+/// ignored.
+pub struct TunSource {
+    device: File,
+    buffer: Vec<u8>,
+}
+
+impl TunSource {
+    /// Opens the TUN device `ifname`, created if it does not exist.
+    pub fn open(ifname: &str) -> io::Result<Self> {
+        Ok(TunSource {
+            device: open_device(ifname)?,
+            buffer: vec![0; TUN_MTU],
+        })
+    }
+
+    /// Destination of the dispatcher writing the IP payloads to the device.
+    pub fn destination(&self) -> io::Result<Destination> {
+        self.device.try_clone().map(Destination::Device)
+    }
+}
+
+impl EventSource for TunSource {
+    fn fd(&self) -> Option<RawFd> {
+        Some(self.device.as_raw_fd())
+    }
+
+    fn handle(&mut self, router: &mut Router) -> io::Result<()> {
+        loop {
+            let read = match self.device.read(&mut self.buffer) {
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let packet = &self.buffer[..read];
+            let Some((proto, source, group)) = ip_addresses(packet) else {
+                debug!("Non-IP packet on the TUN device, ignoring it");
+                continue;
+            };
+            let state = router.daemon().state();
+            let Some((bift_id, bitstring)) = state.overlay_lookup(source, group) else {
+                debug!(
+                    "No overlay entry for ({}, {}), ignoring the packet",
+                    source, group
+                );
+                continue;
+            };
+            let bitstring: Vec<u8> = bitstring.into();
+            let send_info = SendInfo {
+                bift_id,
+                proto: proto as u16,
+                bitstring: &bitstring,
+                payload: packet,
+                metadata: None,
+            };
+            if let Err(e) = router.daemon_mut().send(&send_info) {
+                debug!("Cannot encapsulate the packet toward {}: {:?}", group, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    /// Tests the addresses of the IP packets.
+    fn test_tun() {
+        let source: IpAddr = "fc00:a::1".parse().unwrap();
+        let group: IpAddr = "ff3e::1".parse().unwrap();
+        let mut ipv6 = vec![0x60, 0, 0, 0, 0, 0, 17, 64];
+        for address in [source, group] {
+            let IpAddr::V6(address) = address else {
+                unreachable!()
+            };
+            ipv6.extend(address.octets());
+        }
+        assert_eq!(ip_addresses(&ipv6), Some((PROTO_IPV6, source, group)));
+        assert_eq!(ip_addresses(&ipv6[..39]), None);
+        let mut ipv4 = vec![0x45, 0, 0, 20, 0, 0, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1];
+        ipv4.extend([239, 1, 2, 3]);
+        let (source, group) = (Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(239, 1, 2, 3));
+        assert_eq!(
+            ip_addresses(&ipv4),
+            Some((PROTO_IPV4, IpAddr::V4(source), IpAddr::V4(group)))
+        );
+        assert_eq!(ip_addresses(&[0x20; 40]), None);
+
+        assert!(open_device(&"a".repeat(libc::IFNAMSIZ)).is_err());
+    }
+}
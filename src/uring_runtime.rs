@@ -0,0 +1,488 @@
+//! Event loop of the daemon on io_uring, instead of the mio loop of `runtime`, to reduce the
+//! number of system calls per packet on recent kernels (5.13 or later).
+//!
+//! * The raw IP (or UDP) socket registered with `UringEventLoop::register_socket` always has
+//!   several receive operations in flight: the packets received since the previous system
+//!   call are all processed before the next one.
+//! * The other sources are polled with multishot poll operations, and handled as with mio.
+//! * `UringTransport` queues the copies of a packet as send operations, submitted at once
+//!   when the daemon flushes the transport (see `Transport::flush`).
+
+use crate::daemon::Transport;
+use crate::router::{Framing, Router};
+use crate::runtime::{self, EventSource};
+use io_uring::{cqueue, opcode, squeue, types, IoUring};
+use log::{debug, error};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+/// Default number of entries of the submission queues.
+pub const DEFAULT_RING_SIZE: u32 = 256;
+
+/// Default number of receive operations in flight on the registered socket.
+pub const DEFAULT_RECV_DEPTH: usize = 32;
+
+/// Kind of the operation of a completion, in the upper half of its user data.
+const POLL: u64 = 0;
+const RECV: u64 = 1;
+const CANCEL: u64 = 2;
+
+fn user_data(kind: u64, idx: usize) -> u64 {
+    kind << 32 | idx as u64
+}
+
+/// Pushes `entry` in the submission queue, submitting the queued entries if it is full.
+///
+/// # Safety
+///
+/// The buffers of `entry` must stay valid until its completion.
+unsafe fn push(ring: &mut IoUring, entry: &squeue::Entry) -> io::Result<()> {
+    if ring.submission().push(entry).is_err() {
+        ring.submit()?;
+        ring.submission()
+            .push(entry)
+            .map_err(|_| io::Error::other("The submission queue is full"))?;
+    }
+    Ok(())
+}
+
+/// Socket receiving the BIER packets, with one buffer per receive operation.
+struct Receiver {
+    socket: socket2::Socket,
+    framing: Framing,
+    buffers: Vec<Vec<u8>>,
+}
+
+pub struct UringEventLoop {
+    ring: IoUring,
+    sources: Vec<Box<dyn EventSource>>,
+    receivers: Vec<Receiver>,
+}
+
+impl UringEventLoop {
+    /// Event loop whose submission queue has `entries` entries.
+    pub fn new(entries: u32) -> io::Result<Self> {
+        Ok(UringEventLoop {
+            ring: IoUring::new(entries)?,
+            sources: Vec::new(),
+            receivers: Vec::new(),
+        })
+    }
+
+    /// Registers `source`, whose index is returned.
+    pub fn register(&mut self, source: impl EventSource + 'static) -> io::Result<usize> {
+        let idx = self.sources.len();
+        if let Some(fd) = source.fd() {
+            self.poll(fd, idx)?;
+        }
+        self.sources.push(Box::new(source));
+        Ok(idx)
+    }
+
+    /// Receives the BIER packets of `socket`, the raw IP socket with `Framing::Ip` or the UDP
+    /// socket with `Framing::Bier`, with `depth` receive operations in flight. Larger packets
+    /// than `max_packet_size` are dropped.
+    pub fn register_socket(
+        &mut self,
+        socket: socket2::Socket,
+        framing: Framing,
+        max_packet_size: usize,
+        depth: usize,
+    ) -> io::Result<()> {
+        let idx = self.receivers.len();
+        self.receivers.push(Receiver {
+            socket,
+            framing,
+            buffers: vec![vec![0u8; max_packet_size]; depth],
+        });
+        for buffer in 0..depth {
+            self.recv(idx, buffer)?;
+        }
+        Ok(())
+    }
+
+    fn poll(&mut self, fd: RawFd, idx: usize) -> io::Result<()> {
+        let entry = opcode::PollAdd::new(types::Fd(fd), libc::POLLIN as u32)
+            .multi(true)
+            .build()
+            .user_data(user_data(POLL, idx));
+        unsafe { push(&mut self.ring, &entry) }
+    }
+
+    /// Receives in the buffer `buffer` of the receiver `idx`.
+    fn recv(&mut self, idx: usize, buffer: usize) -> io::Result<()> {
+        let receiver = &mut self.receivers[idx];
+        let buf = &mut receiver.buffers[buffer];
+        // The length of a truncated datagram is its full length.
+        let entry = opcode::Recv::new(
+            types::Fd(receiver.socket.as_raw_fd()),
+            buf.as_mut_ptr(),
+            buf.len() as u32,
+        )
+        .flags(libc::MSG_TRUNC)
+        .build()
+        .user_data(user_data(RECV, idx << 16 | buffer));
+        // The buffer is only used again once the operation is completed.
+        unsafe { push(&mut self.ring, &entry) }
+    }
+
+    /// Waits for the next completions, at most `timeout` or until the next deadline of a
+    /// source, and handles them. The errors of the handlers are logged. The copies deferred
+    /// by the fan-out cap are then sent, without waiting if some are left from the previous
+    /// call.
+    pub fn run_once(&mut self, router: &mut Router, timeout: Option<Duration>) -> io::Result<()> {
+        let timeout = match router.daemon().has_deferred() {
+            true => Some(Duration::ZERO),
+            false => timeout,
+        };
+        let now = Instant::now();
+        let deadline_timeout = self
+            .sources
+            .iter()
+            .filter_map(|source| source.deadline())
+            .min()
+            .map(|deadline| deadline.saturating_duration_since(now));
+        let timeout = match (timeout, deadline_timeout) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+
+        let res = match timeout {
+            Some(Duration::ZERO) => self.ring.submit(),
+            Some(timeout) => {
+                let timespec = types::Timespec::from(timeout);
+                let args = types::SubmitArgs::new().timespec(&timespec);
+                self.ring.submitter().submit_with_args(1, &args)
+            }
+            None => self.ring.submit_and_wait(1),
+        };
+        match res {
+            Ok(_) => (),
+            Err(e) if e.raw_os_error() == Some(libc::ETIME) => (),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => return Ok(()),
+            Err(e) => return Err(e),
+        }
+
+        let completions: Vec<(u64, i32, u32)> = self
+            .ring
+            .completion()
+            .map(|cqe| (cqe.user_data(), cqe.result(), cqe.flags()))
+            .collect();
+        for (user_data, result, flags) in completions {
+            let idx = (user_data & 0xffff_ffff) as usize;
+            match user_data >> 32 {
+                POLL => {
+                    if result < 0 {
+                        error!(
+                            "Error when polling the source {}: {:?}",
+                            idx,
+                            io::Error::from_raw_os_error(-result)
+                        );
+                    } else if let Err(e) = self.sources[idx].handle(router) {
+                        error!("Error when handling the source {}: {:?}", idx, e);
+                    }
+                    // The poll operation ends, e.g., on errors.
+                    if !cqueue::more(flags) {
+                        let fd = self.sources[idx].fd().expect("Polled source");
+                        self.poll(fd, idx)?;
+                    }
+                }
+                RECV => {
+                    let (receiver, buffer) = (idx >> 16, idx & 0xffff);
+                    self.received(router, receiver, buffer, result);
+                    self.recv(receiver, buffer)?;
+                }
+                _ => (),
+            }
+        }
+
+        let now = Instant::now();
+        for (idx, source) in self.sources.iter_mut().enumerate() {
+            if source.deadline().is_some_and(|deadline| deadline <= now) {
+                if let Err(e) = source.handle(router) {
+                    error!("Error when handling the source {}: {:?}", idx, e);
+                }
+            }
+        }
+
+        if router.daemon().has_deferred() {
+            router.daemon_mut().send_deferred();
+        }
+        Ok(())
+    }
+
+    /// Processes the packet received in `buffer` of `receiver`, of length `result`.
+    fn received(&mut self, router: &mut Router, receiver: usize, buffer: usize, result: i32) {
+        let receiver = &mut self.receivers[receiver];
+        let buffer = &mut receiver.buffers[buffer];
+        if result < 0 {
+            debug!(
+                "Error when receiving a packet: {:?}",
+                io::Error::from_raw_os_error(-result)
+            );
+            return;
+        }
+        let length = result as usize;
+        if length > buffer.len() {
+            runtime::report_truncated(router, buffer.len());
+            return;
+        }
+        if let Err(e) = router.handle_packet(receiver.framing, &mut buffer[..length]) {
+            debug!(
+                "Error when processing the BIER packet: {:?}, continuing...",
+                e
+            );
+        }
+    }
+
+    pub fn run(&mut self, router: &mut Router) -> io::Result<()> {
+        loop {
+            self.run_once(router, None)?;
+        }
+    }
+}
+
+impl Drop for UringEventLoop {
+    /// Cancels the receive operations before their buffers are freed.
+    fn drop(&mut self) {
+        let entry = opcode::AsyncCancel2::new(types::CancelBuilder::any())
+            .build()
+            .user_data(user_data(CANCEL, 0));
+        if unsafe { push(&mut self.ring, &entry) }.is_err() {
+            return;
+        }
+        loop {
+            if self.ring.submit_and_wait(1).is_err() {
+                return;
+            }
+            if self
+                .ring
+                .completion()
+                .any(|cqe| cqe.user_data() >> 32 == CANCEL)
+            {
+                return;
+            }
+        }
+    }
+}
+
+/// Copy queued in the submission queue. The slots are allocated once, so that the message
+/// headers keep their address.
+struct SendSlot {
+    buffer: Vec<u8>,
+    addr: socket2::SockAddr,
+    iov: libc::iovec,
+    msg: libc::msghdr,
+}
+
+/// Transport queuing the copies as send operations on `socket`, the raw IP socket or the UDP
+/// socket, submitted at once when flushed. The flush waits for the completion of the copies:
+/// their errors are only logged.
+pub struct UringTransport {
+    ring: IoUring,
+    socket: socket2::Socket,
+    port: u16,
+    slots: Vec<SendSlot>,
+    /// Number of slots queued since the last flush.
+    nb_queued: usize,
+}
+
+// The raw pointers of the slots only point to the slots themselves.
+unsafe impl Send for UringTransport {}
+
+impl UringTransport {
+    /// Sends the copies to `port` of the next hops, 0 for the raw IP socket. At most
+    /// `entries` copies are queued, the transport is flushed beyond.
+    pub fn new(socket: socket2::Socket, port: u16, entries: u32) -> io::Result<Self> {
+        let ring = IoUring::new(entries)?;
+        let unspecified = SocketAddr::new(IpAddr::from([0u8; 4]), 0);
+        let slots = (0..ring.params().sq_entries())
+            .map(|_| SendSlot {
+                buffer: Vec::new(),
+                addr: unspecified.into(),
+                iov: unsafe { std::mem::zeroed() },
+                msg: unsafe { std::mem::zeroed() },
+            })
+            .collect();
+        Ok(UringTransport {
+            ring,
+            socket,
+            port,
+            slots,
+            nb_queued: 0,
+        })
+    }
+}
+
+impl Transport for UringTransport {
+    fn send_to(&mut self, packet: &[u8], next_hop: IpAddr) -> io::Result<usize> {
+        if self.nb_queued == self.slots.len() {
+            self.flush()?;
+        }
+        let slot = &mut self.slots[self.nb_queued];
+        slot.buffer.clear();
+        slot.buffer.extend_from_slice(packet);
+        slot.addr = SocketAddr::new(next_hop, self.port).into();
+        slot.iov = libc::iovec {
+            iov_base: slot.buffer.as_mut_ptr() as *mut _,
+            iov_len: slot.buffer.len(),
+        };
+        slot.msg.msg_name = slot.addr.as_ptr() as *mut _;
+        slot.msg.msg_namelen = slot.addr.len();
+        slot.msg.msg_iov = &mut slot.iov;
+        slot.msg.msg_iovlen = 1;
+        let entry = opcode::SendMsg::new(types::Fd(self.socket.as_raw_fd()), &slot.msg)
+            .build()
+            .user_data(self.nb_queued as u64);
+        // The slot is only used again once the copies are flushed.
+        unsafe { push(&mut self.ring, &entry)? };
+        self.nb_queued += 1;
+        Ok(packet.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut nb_completed = 0;
+        let mut res = Ok(());
+        while nb_completed < self.nb_queued {
+            match self.ring.submit_and_wait(self.nb_queued - nb_completed) {
+                Ok(_) => (),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+            for cqe in self.ring.completion() {
+                nb_completed += 1;
+                if cqe.result() >= 0 {
+                    continue;
+                }
+                let e = io::Error::from_raw_os_error(-cqe.result());
+                let next_hop = self.slots[cqe.user_data() as usize].addr.as_socket();
+                debug!(
+                    "Error when sending the packet to {:?}. Error is: {:?}, continuing...",
+                    next_hop.map(|addr| addr.ip()),
+                    e
+                );
+                res = res.and(Err(e));
+            }
+        }
+        self.nb_queued = 0;
+        res
+    }
+}
+
+impl Drop for UringTransport {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::api::SendInfo;
+    use crate::bier::BierState;
+    use crate::control::{ControlRequest, ControlResponse};
+    use crate::daemon::BierDaemon;
+    use crate::header::BierHeader;
+    use std::net::UdpSocket;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    /// Tests the packets received and sent with io_uring, and the other sources.
+    fn test_uring_event_loop() {
+        let mut state: BierState =
+            serde_json::from_str(include_str!("../configs/example.json")).unwrap();
+        // The neighbors are UDP sockets on localhost.
+        let neighbor = UdpSocket::bind("[::1]:0").unwrap();
+        let port = neighbor.local_addr().unwrap().port();
+        for bift in state.bifts.iter_mut() {
+            for entry in bift.entries.iter_mut() {
+                for path in entry.paths.iter_mut() {
+                    path.next_hop = "::1".parse().unwrap();
+                }
+            }
+        }
+        let socket = socket2::Socket::from(UdpSocket::bind("[::1]:0").unwrap());
+        let bfr_addr = socket.local_addr().unwrap().as_socket().unwrap();
+        let transport =
+            UringTransport::new(socket.try_clone().unwrap(), port, DEFAULT_RING_SIZE).unwrap();
+        let mut router = Router::new(
+            BierDaemon::builder()
+                .state(state)
+                .transport(transport)
+                .build()
+                .unwrap(),
+        );
+
+        let mut event_loop = UringEventLoop::new(DEFAULT_RING_SIZE).unwrap();
+        event_loop
+            .register_socket(socket, Framing::Bier, 1500, DEFAULT_RECV_DEPTH)
+            .unwrap();
+        let dir = std::env::temp_dir();
+        let control_path = dir.join(format!("bier-uring-loop-{}", std::process::id()));
+        let requester_path = dir.join(format!("bier-uring-loop-req-{}", std::process::id()));
+        let _ = std::fs::remove_file(&control_path);
+        let _ = std::fs::remove_file(&requester_path);
+        let control = UnixDatagram::bind(&control_path).unwrap();
+        let requester = UnixDatagram::bind(&requester_path).unwrap();
+        requester.connect(&control_path).unwrap();
+        event_loop
+            .register(crate::runtime::ControlSource::new(socket2::Socket::from(control)).unwrap())
+            .unwrap();
+
+        // Packet with the bits of the BFERs behind node B.
+        let mut packet = vec![0u8; 22];
+        BierHeader::from_recv_info(&SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, 0b11010],
+            payload: &[],
+            metadata: None,
+        })
+        .unwrap()
+        .to_slice(&mut packet)
+        .unwrap();
+        let sender = UdpSocket::bind("[::1]:0").unwrap();
+        for _ in 0..3 {
+            sender.send_to(&packet, bfr_addr).unwrap();
+        }
+        // Larger than the buffers.
+        sender.send_to(&[0u8; 2000], bfr_addr).unwrap();
+        requester
+            .send(&serde_json::to_vec(&ControlRequest::Stats).unwrap())
+            .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut response = None;
+        requester.set_nonblocking(true).unwrap();
+        let mut buffer = vec![0u8; 65535];
+        while Instant::now() < deadline
+            && (router.daemon().stats().received.packets < 3 || response.is_none())
+        {
+            event_loop
+                .run_once(&mut router, Some(Duration::from_millis(10)))
+                .unwrap();
+            if let Ok(read) = requester.recv(&mut buffer) {
+                response = Some(serde_json::from_slice::<ControlResponse>(&buffer[..read]));
+            }
+        }
+        assert!(matches!(response, Some(Ok(ControlResponse::Stats(_)))));
+        let stats = router.daemon().stats();
+        assert_eq!(stats.received.packets, 3);
+        assert_eq!(stats.drops[&crate::stats::DropReason::Truncated], 1);
+
+        let nb_copies: u64 = stats.neighbors.values().map(|n| n.sent.packets).sum();
+        assert_eq!(nb_copies, 3);
+        neighbor
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        for _ in 0..nb_copies {
+            let read = neighbor.recv(&mut buffer).unwrap();
+            assert_eq!(read, packet.len());
+        }
+        let _ = std::fs::remove_file(&control_path);
+        let _ = std::fs::remove_file(&requester_path);
+    }
+}
@@ -0,0 +1,684 @@
+//! AF_XDP data plane: the BIER frames of a queue of an interface are received and sent
+//! through the rings of an AF_XDP socket, without the copies and the per-packet system calls
+//! of the raw IP socket. The lookup is the one of the daemon (`BierDaemon::process_packet`).
+//!
+//! The BIER packets are carried in Ethernet frames with the ethertype 0xAB37 (RFC 8296).
+//! An XDP program attached to the interface, e.g., loaded with `xdp-loader`, must redirect
+//! these frames to the socket with an XSKMAP. The socket is inserted in the map pinned at
+//! `XdpConfig::xsk_map`, at the index of its queue.
+//!
+//! The UMEM (the memory shared with the kernel) is split in two halves: the frames of the
+//! fill and RX rings, owned by `XdpSource`, and the frames of the TX and completion rings,
+//! owned by `XdpTransport`.
+
+use crate::af_packet::interface;
+use crate::daemon::Transport;
+pub use crate::ethernet::{
+    bier_packet, write_ethernet_header, MacAddr, ETHERNET_HEADER_LEN, ETHERTYPE_BIER,
+};
+use crate::router::{Framing, Router};
+use crate::runtime::EventSource;
+use crate::stats::DropReason;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::io;
+use std::net::IpAddr;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// Default size of the frames of the UMEM, the largest packet it can hold.
+pub const DEFAULT_FRAME_SIZE: u32 = 4096;
+
+/// Default number of descriptors of each ring.
+pub const DEFAULT_RING_SIZE: u32 = 2048;
+
+/// Number of descriptors read from the RX ring at once.
+const BATCH_SIZE: u32 = 64;
+
+const BPF_MAP_UPDATE_ELEM: libc::c_long = 2;
+const BPF_OBJ_GET: libc::c_long = 7;
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct XdpConfig {
+    /// Name of the interface.
+    pub ifname: String,
+    /// Queue of the interface bound to the socket.
+    #[serde(default)]
+    pub queue_id: u32,
+    /// Requires the zero-copy mode of the driver, instead of the copy mode.
+    #[serde(default)]
+    pub zero_copy: bool,
+    /// Path of the pinned XSKMAP of the XDP program, where the socket is inserted.
+    #[serde(default)]
+    pub xsk_map: Option<String>,
+    /// MAC address of the interface, read from sysfs by default.
+    #[serde(default)]
+    pub mac: Option<MacAddr>,
+    /// MAC address of the next hops. The copies toward the other next hops are sent with the
+    /// fallback transport of `XdpTransport`.
+    #[serde(default)]
+    pub neighbors: HashMap<IpAddr, MacAddr>,
+    #[serde(default = "default_frame_size")]
+    pub frame_size: u32,
+    /// Number of descriptors of each ring, a power of two. The UMEM has twice this number
+    /// of frames.
+    #[serde(default = "default_ring_size")]
+    pub ring_size: u32,
+}
+
+fn default_frame_size() -> u32 {
+    DEFAULT_FRAME_SIZE
+}
+
+fn default_ring_size() -> u32 {
+    DEFAULT_RING_SIZE
+}
+
+impl XdpConfig {
+    pub fn new(ifname: &str) -> Self {
+        XdpConfig {
+            ifname: ifname.to_string(),
+            queue_id: 0,
+            zero_copy: false,
+            xsk_map: None,
+            mac: None,
+            neighbors: HashMap::new(),
+            frame_size: DEFAULT_FRAME_SIZE,
+            ring_size: DEFAULT_RING_SIZE,
+        }
+    }
+}
+
+/// Memory mapping, unmapped when dropped.
+struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// The mapped memory is only accessed through the rings and the frames they own.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    fn new(len: usize, fd: RawFd, offset: libc::off_t) -> io::Result<Self> {
+        let flags = match fd {
+            -1 => libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            _ => libc::MAP_SHARED,
+        };
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                flags | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    fn anonymous(len: usize) -> io::Result<Self> {
+        Mmap::new(len, -1, 0)
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as *mut _, self.len) };
+    }
+}
+
+/// Single-producer single-consumer ring shared with the kernel. This side produces the
+/// descriptors of the fill and TX rings, and consumes the ones of the RX and completion rings.
+struct Ring<T> {
+    /// Keeps the ring mapped.
+    _map: Mmap,
+    producer: *const AtomicU32,
+    consumer: *const AtomicU32,
+    flags: *const AtomicU32,
+    descs: *mut T,
+    size: u32,
+    /// Index of the next descriptor produced or consumed by this side.
+    local: u32,
+}
+
+unsafe impl<T> Send for Ring<T> {}
+
+impl<T: Copy> Ring<T> {
+    /// Ring of `size` descriptors in `map`, at the offsets given by the kernel.
+    fn new(map: Mmap, offset: &libc::xdp_ring_offset, size: u32, producer_side: bool) -> Self {
+        let at = |offset: u64| unsafe { map.ptr.add(offset as usize) };
+        let mut ring = Ring {
+            producer: at(offset.producer) as *const AtomicU32,
+            consumer: at(offset.consumer) as *const AtomicU32,
+            flags: at(offset.flags) as *const AtomicU32,
+            descs: at(offset.desc) as *mut T,
+            size,
+            local: 0,
+            _map: map,
+        };
+        ring.local = match producer_side {
+            true => ring.producer().load(Ordering::Relaxed),
+            false => ring.consumer().load(Ordering::Relaxed),
+        };
+        ring
+    }
+
+    /// Maps the ring of `size` descriptors of `fd` at the page offset `pgoff`.
+    fn map(
+        fd: RawFd,
+        offset: &libc::xdp_ring_offset,
+        size: u32,
+        pgoff: libc::off_t,
+        producer_side: bool,
+    ) -> io::Result<Self> {
+        let len = offset.desc as usize + size as usize * std::mem::size_of::<T>();
+        let map = Mmap::new(len, fd, pgoff)?;
+        Ok(Ring::new(map, offset, size, producer_side))
+    }
+
+    fn producer(&self) -> &AtomicU32 {
+        unsafe { &*self.producer }
+    }
+
+    fn consumer(&self) -> &AtomicU32 {
+        unsafe { &*self.consumer }
+    }
+
+    /// Whether the kernel must be woken up to process the ring, with `XDP_USE_NEED_WAKEUP`.
+    fn needs_wakeup(&self) -> bool {
+        let flags = unsafe { &*self.flags };
+        flags.load(Ordering::Relaxed) & libc::XDP_RING_NEED_WAKEUP != 0
+    }
+
+    /// Produces the first `descs`, as many as the free descriptors. Returns their number.
+    fn push(&mut self, descs: &[T]) -> usize {
+        let consumer = self.consumer().load(Ordering::Acquire);
+        let free = self.size - self.local.wrapping_sub(consumer);
+        let nb = descs.len().min(free as usize);
+        for desc in &descs[..nb] {
+            let idx = (self.local & (self.size - 1)) as usize;
+            unsafe { self.descs.add(idx).write(*desc) };
+            self.local = self.local.wrapping_add(1);
+        }
+        self.producer().store(self.local, Ordering::Release);
+        nb
+    }
+
+    /// Consumes at most `max` descriptors with `f`. Returns their number.
+    fn consume(&mut self, max: u32, mut f: impl FnMut(T)) -> u32 {
+        let producer = self.producer().load(Ordering::Acquire);
+        let nb = producer.wrapping_sub(self.local).min(max);
+        for _ in 0..nb {
+            let idx = (self.local & (self.size - 1)) as usize;
+            f(unsafe { self.descs.add(idx).read() });
+            self.local = self.local.wrapping_add(1);
+        }
+        self.consumer().store(self.local, Ordering::Release);
+        nb
+    }
+}
+
+/// AF_XDP socket and its UMEM, shared by the source and the transport.
+struct Xsk {
+    fd: OwnedFd,
+    umem: Mmap,
+    frame_size: u32,
+}
+
+impl Xsk {
+    /// Frame of the UMEM starting at `addr`, `None` if it is outside of the UMEM.
+    ///
+    /// # Safety
+    ///
+    /// The frame must be owned by the caller, i.e., not be in a ring or used elsewhere.
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn frame(&self, addr: u64, len: usize) -> Option<&mut [u8]> {
+        let end = (addr as usize).checked_add(len)?;
+        if end > self.umem.len {
+            return None;
+        }
+        Some(std::slice::from_raw_parts_mut(
+            self.umem.ptr.add(addr as usize),
+            len,
+        ))
+    }
+
+    /// Asks the kernel to send the descriptors of the TX ring.
+    fn wake_tx(&self) -> io::Result<()> {
+        let res = unsafe {
+            libc::sendto(
+                self.fd.as_raw_fd(),
+                std::ptr::null(),
+                0,
+                libc::MSG_DONTWAIT,
+                std::ptr::null(),
+                0,
+            )
+        };
+        match res < 0 {
+            true => ignore_busy(io::Error::last_os_error()),
+            false => Ok(()),
+        }
+    }
+
+    /// Asks the kernel to use the descriptors of the fill ring.
+    fn wake_rx(&self) -> io::Result<()> {
+        let res = unsafe {
+            libc::recvfrom(
+                self.fd.as_raw_fd(),
+                std::ptr::null_mut(),
+                0,
+                libc::MSG_DONTWAIT,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        };
+        match res < 0 {
+            true => ignore_busy(io::Error::last_os_error()),
+            false => Ok(()),
+        }
+    }
+}
+
+/// The kernel is already processing the ring, or has no buffer yet.
+fn ignore_busy(e: io::Error) -> io::Result<()> {
+    match e.raw_os_error() {
+        Some(libc::EAGAIN | libc::EBUSY | libc::ENOBUFS) => Ok(()),
+        _ => Err(e),
+    }
+}
+
+fn set_option<T>(fd: RawFd, name: libc::c_int, value: &T) -> io::Result<()> {
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_XDP,
+            name,
+            value as *const T as *const _,
+            std::mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    match res < 0 {
+        true => Err(io::Error::last_os_error()),
+        false => Ok(()),
+    }
+}
+
+/// Opens an AF_XDP socket on the queue of the interface of `config`, and splits it in the
+/// source receiving the BIER frames and the transport sending the copies. Requires
+/// `CAP_NET_RAW` and `CAP_BPF` (or root).
+pub fn open(config: &XdpConfig) -> io::Result<(XdpSource, XdpTransport)> {
+    if !config.ring_size.is_power_of_two() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The size of the rings must be a power of two",
+        ));
+    }
+    let (ifindex, mac) = interface(&config.ifname)?;
+    let mac = config.mac.unwrap_or(mac);
+
+    let fd = unsafe { libc::socket(libc::AF_XDP, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let raw_fd = fd.as_raw_fd();
+
+    let nb_frames = 2 * config.ring_size as usize;
+    let umem = Mmap::anonymous(nb_frames * config.frame_size as usize)?;
+    let umem_reg = libc::xdp_umem_reg {
+        addr: umem.ptr as u64,
+        len: umem.len as u64,
+        chunk_size: config.frame_size,
+        headroom: 0,
+        flags: 0,
+        tx_metadata_len: 0,
+    };
+    set_option(raw_fd, libc::XDP_UMEM_REG, &umem_reg)?;
+    for ring in [
+        libc::XDP_UMEM_FILL_RING,
+        libc::XDP_UMEM_COMPLETION_RING,
+        libc::XDP_RX_RING,
+        libc::XDP_TX_RING,
+    ] {
+        set_option(raw_fd, ring, &config.ring_size)?;
+    }
+
+    let mut offsets: libc::xdp_mmap_offsets = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of_val(&offsets) as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(
+            raw_fd,
+            libc::SOL_XDP,
+            libc::XDP_MMAP_OFFSETS,
+            &mut offsets as *mut _ as *mut _,
+            &mut len,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let size = config.ring_size;
+    let mut fill: Ring<u64> = Ring::map(
+        raw_fd,
+        &offsets.fr,
+        size,
+        libc::XDP_UMEM_PGOFF_FILL_RING as libc::off_t,
+        true,
+    )?;
+    let completion = Ring::map(
+        raw_fd,
+        &offsets.cr,
+        size,
+        libc::XDP_UMEM_PGOFF_COMPLETION_RING as libc::off_t,
+        false,
+    )?;
+    let rx = Ring::map(raw_fd, &offsets.rx, size, libc::XDP_PGOFF_RX_RING, false)?;
+    let tx = Ring::map(raw_fd, &offsets.tx, size, libc::XDP_PGOFF_TX_RING, true)?;
+
+    // The first half of the frames receive the packets.
+    let frame_size = config.frame_size as u64;
+    let rx_frames: Vec<u64> = (0..size as u64).map(|idx| idx * frame_size).collect();
+    fill.push(&rx_frames);
+    let tx_frames = (size as u64..2 * size as u64)
+        .map(|idx| idx * frame_size)
+        .collect();
+
+    let mode = match config.zero_copy {
+        true => libc::XDP_ZEROCOPY,
+        false => libc::XDP_COPY,
+    };
+    let addr = libc::sockaddr_xdp {
+        sxdp_family: libc::AF_XDP as u16,
+        sxdp_flags: mode | libc::XDP_USE_NEED_WAKEUP,
+        sxdp_ifindex: ifindex,
+        sxdp_queue_id: config.queue_id,
+        sxdp_shared_umem_fd: 0,
+    };
+    let res = unsafe {
+        libc::bind(
+            raw_fd,
+            &addr as *const _ as *const libc::sockaddr,
+            std::mem::size_of_val(&addr) as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if let Some(path) = &config.xsk_map {
+        insert_in_xsk_map(path, config.queue_id, raw_fd)?;
+    }
+
+    let xsk = Arc::new(Xsk {
+        fd,
+        umem,
+        frame_size: config.frame_size,
+    });
+    let source = XdpSource {
+        xsk: xsk.clone(),
+        rx,
+        fill,
+        recycled: Vec::with_capacity(BATCH_SIZE as usize),
+    };
+    let transport = XdpTransport {
+        xsk,
+        tx,
+        completion,
+        free: tx_frames,
+        mac,
+        neighbors: config.neighbors.clone(),
+        fallback: None,
+    };
+    Ok((source, transport))
+}
+
+/// Inserts the socket `fd` in the XSKMAP pinned at `path`, at the index `queue_id`, so that
+/// the XDP program redirects the frames of the queue to the socket.
+fn insert_in_xsk_map(path: &str, queue_id: u32, fd: RawFd) -> io::Result<()> {
+    #[repr(C)]
+    struct ObjGetAttr {
+        pathname: u64,
+        bpf_fd: u32,
+        file_flags: u32,
+    }
+    #[repr(C)]
+    struct MapUpdateAttr {
+        map_fd: u32,
+        pad: u32,
+        key: u64,
+        value: u64,
+        flags: u64,
+    }
+
+    let path = CString::new(path)?;
+    let attr = ObjGetAttr {
+        pathname: path.as_ptr() as u64,
+        bpf_fd: 0,
+        file_flags: 0,
+    };
+    let map_fd = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            BPF_OBJ_GET,
+            &attr as *const ObjGetAttr,
+            std::mem::size_of::<ObjGetAttr>(),
+        )
+    };
+    if map_fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let map_fd = unsafe { OwnedFd::from_raw_fd(map_fd as RawFd) };
+    let value = fd as u32;
+    let attr = MapUpdateAttr {
+        map_fd: map_fd.as_raw_fd() as u32,
+        pad: 0,
+        key: &queue_id as *const u32 as u64,
+        value: &value as *const u32 as u64,
+        flags: 0,
+    };
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            BPF_MAP_UPDATE_ELEM,
+            &attr as *const MapUpdateAttr,
+            std::mem::size_of::<MapUpdateAttr>(),
+        )
+    };
+    match res < 0 {
+        true => Err(io::Error::last_os_error()),
+        false => Ok(()),
+    }
+}
+
+/// Receives the BIER frames of the RX ring and forwards them with the `Router`. The frames
+/// are processed in place in the UMEM, then returned to the fill ring.
+pub struct XdpSource {
+    xsk: Arc<Xsk>,
+    rx: Ring<libc::xdp_desc>,
+    fill: Ring<u64>,
+    recycled: Vec<u64>,
+}
+
+impl EventSource for XdpSource {
+    fn fd(&self) -> Option<RawFd> {
+        Some(self.xsk.fd.as_raw_fd())
+    }
+
+    fn handle(&mut self, router: &mut Router) -> io::Result<()> {
+        loop {
+            let (xsk, recycled) = (&self.xsk, &mut self.recycled);
+            let nb = self.rx.consume(BATCH_SIZE, |desc| {
+                // In the aligned mode, the frame starts at a multiple of the frame size.
+                recycled.push(desc.addr - desc.addr % xsk.frame_size as u64);
+                let frame = unsafe { xsk.frame(desc.addr, desc.len as usize) };
+                let Some(packet) = frame.and_then(bier_packet) else {
+                    router.daemon_mut().record_drop(DropReason::MalformedPacket);
+                    return;
+                };
+                if let Err(e) = router.handle_packet(Framing::Bier, packet) {
+                    debug!(
+                        "Error when processing the BIER packet: {:?}, continuing...",
+                        e
+                    );
+                }
+            });
+            // The fill ring has room for all the frames of the RX half of the UMEM.
+            self.fill.push(&self.recycled);
+            self.recycled.clear();
+            if nb == 0 {
+                break;
+            }
+        }
+        if self.fill.needs_wakeup() {
+            self.xsk.wake_rx()?;
+        }
+        Ok(())
+    }
+}
+
+/// Sends the copies in Ethernet frames through the TX ring. The copies toward the next hops
+/// without MAC address are sent with the fallback transport, if any.
+pub struct XdpTransport {
+    xsk: Arc<Xsk>,
+    tx: Ring<libc::xdp_desc>,
+    completion: Ring<u64>,
+    /// Frames of the TX half of the UMEM which are not in the rings.
+    free: Vec<u64>,
+    mac: MacAddr,
+    neighbors: HashMap<IpAddr, MacAddr>,
+    fallback: Option<Box<dyn Transport + Send>>,
+}
+
+impl XdpTransport {
+    /// Sends the copies toward the next hops without MAC address with `fallback`, e.g., the
+    /// raw IP socket.
+    pub fn fallback(mut self, fallback: impl Transport + Send + 'static) -> Self {
+        self.fallback = Some(Box::new(fallback));
+        self
+    }
+
+    /// Returns the frames of the sent copies to the free frames.
+    fn reclaim(&mut self) {
+        let free = &mut self.free;
+        self.completion.consume(u32::MAX, |addr| free.push(addr));
+    }
+}
+
+impl Transport for XdpTransport {
+    fn send_to(&mut self, packet: &[u8], next_hop: IpAddr) -> io::Result<usize> {
+        let Some(&dst) = self.neighbors.get(&next_hop) else {
+            return match &mut self.fallback {
+                Some(fallback) => fallback.send_to(packet, next_hop),
+                None => Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No MAC address for the next hop {}", next_hop),
+                )),
+            };
+        };
+        let len = ETHERNET_HEADER_LEN + packet.len();
+        if len > self.xsk.frame_size as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The packet is larger than the frames of the UMEM",
+            ));
+        }
+        self.reclaim();
+        if self.free.is_empty() {
+            self.xsk.wake_tx()?;
+            self.reclaim();
+        }
+        let addr = self.free.pop().ok_or(io::ErrorKind::WouldBlock)?;
+        let frame = unsafe { self.xsk.frame(addr, len) }.expect("The frame is in the UMEM");
+        write_ethernet_header(frame, dst, self.mac, ETHERTYPE_BIER);
+        frame[ETHERNET_HEADER_LEN..].copy_from_slice(packet);
+        let desc = libc::xdp_desc {
+            addr,
+            len: len as u32,
+            options: 0,
+        };
+        if self.tx.push(&[desc]) == 0 {
+            self.free.push(addr);
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        Ok(packet.len())
+    }
+
+    /// Wakes the kernel up once for all the copies of a packet.
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(fallback) = &mut self.fallback {
+            fallback.flush()?;
+        }
+        match self.tx.needs_wakeup() {
+            true => self.xsk.wake_tx(),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /// Ring of 4 descriptors in anonymous memory, the kernel side being played by the test.
+    fn ring<T: Copy>(producer_side: bool) -> Ring<T> {
+        let offset = libc::xdp_ring_offset {
+            producer: 0,
+            consumer: 64,
+            flags: 128,
+            desc: 192,
+        };
+        let map = Mmap::anonymous(4096).unwrap();
+        Ring::new(map, &offset, 4, producer_side)
+    }
+
+    #[test]
+    /// Tests the rings shared with the kernel.
+    fn test_xdp() {
+        // Fill ring: the producer stops at the free descriptors.
+        let mut fill: Ring<u64> = ring(true);
+        assert_eq!(fill.push(&[0, 4096, 8192]), 3);
+        assert_eq!(fill.push(&[12288, 16384]), 1);
+        assert_eq!(fill.producer().load(Ordering::Relaxed), 4);
+        // The kernel consumes two descriptors.
+        fill.consumer().store(2, Ordering::Relaxed);
+        assert_eq!(fill.push(&[16384, 20480, 24576]), 2);
+        let descs = unsafe { std::slice::from_raw_parts(fill.descs, 4) };
+        assert_eq!(descs, [16384, 20480, 8192, 12288]);
+        assert!(!fill.needs_wakeup());
+        unsafe { &*fill.flags }.store(libc::XDP_RING_NEED_WAKEUP, Ordering::Relaxed);
+        assert!(fill.needs_wakeup());
+
+        // Completion ring: the consumer reads the descriptors produced by the kernel.
+        let mut completion: Ring<u64> = ring(false);
+        let mut consumed = Vec::new();
+        assert_eq!(completion.consume(8, |addr| consumed.push(addr)), 0);
+        for (idx, addr) in [1u64, 2, 3].iter().enumerate() {
+            unsafe { completion.descs.add(idx).write(*addr) };
+        }
+        completion.producer().store(3, Ordering::Relaxed);
+        assert_eq!(completion.consume(2, |addr| consumed.push(addr)), 2);
+        assert_eq!(completion.consume(8, |addr| consumed.push(addr)), 1);
+        assert_eq!(consumed, [1, 2, 3]);
+        assert_eq!(completion.consumer().load(Ordering::Relaxed), 3);
+
+        assert_eq!(
+            open(&XdpConfig::new("bier-no-such-if"))
+                .err()
+                .unwrap()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//! Integration test of several `bier-rust` daemons in network namespaces connected by veth
+//! pairs, checking the delivery of the packets through the kernel: the raw sockets, their
+//! binding to the loopbacks, the routes toward the next hops and the IP proto. The unit tests
+//! replace all of them with in-memory transports.
+//!
+//! It needs root and iproute2, thus is ignored by default:
+//!
+//! ```bash
+//! $ cargo test --test netns --no-run
+//! $ sudo -E cargo test --test netns -- --ignored
+//! ```
+#![cfg(target_os = "linux")]
+
+use bier_rust::api::SendInfo;
+use bier_rust::bier::{BierEntryPath, BierState, Bift, BiftEntry};
+use bier_rust::client::BierClient;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Delay for the daemons to open their sockets and for the packets to be delivered.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Runs `ip` with `args`, failing if it exits with an error.
+fn ip(args: &[&str]) -> io::Result<()> {
+    let output = Command::new("ip").args(args).output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "ip {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+/// Network namespace running a daemon, deleted with its interfaces when dropped.
+struct Namespace {
+    name: String,
+    daemon: Option<Child>,
+}
+
+impl Namespace {
+    /// Creates the namespace `<prefix>-<suffix>`, with its loopback interface up and the
+    /// `loopback` address of its BFR.
+    fn new(prefix: &str, suffix: &str, loopback: &str) -> io::Result<Self> {
+        let name = format!("{}-{}", prefix, suffix);
+        ip(&["netns", "add", &name])?;
+        let namespace = Namespace { name, daemon: None };
+        namespace.ip(&["link", "set", "lo", "up"])?;
+        namespace.ip(&[
+            "-6",
+            "addr",
+            "add",
+            &format!("{}/128", loopback),
+            "dev",
+            "lo",
+        ])?;
+        Ok(namespace)
+    }
+
+    /// Runs `ip` in the namespace.
+    fn ip(&self, args: &[&str]) -> io::Result<()> {
+        let mut namespaced = vec!["-n", &self.name];
+        namespaced.extend(args);
+        ip(&namespaced)
+    }
+
+    /// Connects the namespace to `other` with a veth pair, whose interfaces `veth.0` (here) and
+    /// `veth.1` (there) have the addresses `address` and `other_address`.
+    fn connect(&self, other: &Namespace, veth: (&str, &str), address: &str, other_address: &str) {
+        ip(&[
+            "link",
+            "add",
+            veth.0,
+            "netns",
+            &self.name,
+            "type",
+            "veth",
+            "peer",
+            "name",
+            veth.1,
+            "netns",
+            &other.name,
+        ])
+        .unwrap();
+        for (namespace, ifname, address) in
+            [(self, veth.0, address), (other, veth.1, other_address)]
+        {
+            let address = format!("{}/64", address);
+            namespace
+                .ip(&["-6", "addr", "add", &address, "dev", ifname, "nodad"])
+                .unwrap();
+            namespace.ip(&["link", "set", ifname, "up"]).unwrap();
+        }
+    }
+
+    /// Adds a route toward the loopback `destination` of a BFR through `via`.
+    fn route(&self, destination: &str, via: &str) {
+        let destination = format!("{}/128", destination);
+        self.ip(&["-6", "route", "add", &destination, "via", via])
+            .unwrap();
+    }
+
+    /// Starts the daemon with the `config`, and waits for its API socket at `bier_path`.
+    fn start_daemon(&mut self, config: &Path, bier_path: &Path) {
+        let child = Command::new("ip")
+            .args(["netns", "exec", &self.name, env!("CARGO_BIN_EXE_bier-rust")])
+            .arg("-c")
+            .arg(config)
+            .arg("--bier-path")
+            .arg(bier_path)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        self.daemon = Some(child);
+        let start = Instant::now();
+        while !bier_path.exists() {
+            let status = self.daemon.as_mut().unwrap().try_wait().unwrap();
+            assert!(
+                status.is_none(),
+                "The daemon of {} exited: {:?}",
+                self.name,
+                status
+            );
+            assert!(
+                start.elapsed() < TIMEOUT,
+                "The daemon of {} does not start",
+                self.name
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+impl Drop for Namespace {
+    fn drop(&mut self) {
+        if let Some(mut daemon) = self.daemon.take() {
+            let _ = daemon.kill();
+            let _ = daemon.wait();
+        }
+        let _ = ip(&["netns", "del", &self.name]);
+    }
+}
+
+/// Writes the configuration of the BFR `bfr_id` at `fc00::<bfr_id>`, with the BIFT entries
+/// `(bit, F-BM, next hop)`, and returns its path.
+fn write_config(dir: &Path, bfr_id: u64, entries: &[(u64, &str, &str)]) -> PathBuf {
+    let entries = entries
+        .iter()
+        .map(|(bit, bitstring, next_hop)| {
+            let path = BierEntryPath::new(bitstring.parse().unwrap(), next_hop.parse().unwrap());
+            BiftEntry::new(*bit, vec![path])
+        })
+        .collect();
+    let mut state = BierState::new(format!("fc00::{}", bfr_id).parse().unwrap());
+    state.add_bift(Bift::new(1, bfr_id, entries)).unwrap();
+    let path = dir.join(format!("bfr{}.json", bfr_id));
+    std::fs::write(&path, serde_json::to_string(&state).unwrap()).unwrap();
+    path
+}
+
+#[test]
+#[ignore = "needs root and iproute2"]
+/// Tests the delivery of packets along the line of BFRs 1 - 2 - 3, each in its namespace.
+fn test_netns_line() {
+    let prefix = format!("bier{}", std::process::id());
+    let dir = std::env::temp_dir().join(&prefix);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut namespaces: Vec<_> = ["a", "b", "c"]
+        .iter()
+        .zip(["fc00::1", "fc00::2", "fc00::3"])
+        .map(|(suffix, loopback)| Namespace::new(&prefix, suffix, loopback).unwrap())
+        .collect();
+    namespaces[0].connect(
+        &namespaces[1],
+        ("veth0", "veth0"),
+        "fc00:12::1",
+        "fc00:12::2",
+    );
+    namespaces[1].connect(
+        &namespaces[2],
+        ("veth1", "veth0"),
+        "fc00:23::2",
+        "fc00:23::3",
+    );
+    namespaces[0].route("fc00::2", "fc00:12::2");
+    namespaces[1].route("fc00::1", "fc00:12::1");
+    namespaces[1].route("fc00::3", "fc00:23::3");
+    namespaces[2].route("fc00::2", "fc00:23::2");
+
+    let configs = [
+        write_config(
+            &dir,
+            1,
+            &[
+                (1, "1", "fc00::1"),
+                (2, "110", "fc00::2"),
+                (3, "110", "fc00::2"),
+            ],
+        ),
+        write_config(
+            &dir,
+            2,
+            &[
+                (1, "1", "fc00::1"),
+                (2, "10", "fc00::2"),
+                (3, "100", "fc00::3"),
+            ],
+        ),
+        write_config(
+            &dir,
+            3,
+            &[
+                (1, "11", "fc00::2"),
+                (2, "11", "fc00::2"),
+                (3, "100", "fc00::3"),
+            ],
+        ),
+    ];
+    let bier_paths: Vec<_> = (1..=3)
+        .map(|bfr_id| dir.join(format!("bfr{}.sock", bfr_id)))
+        .collect();
+    for ((namespace, config), bier_path) in namespaces.iter_mut().zip(&configs).zip(&bier_paths) {
+        namespace.start_daemon(config, bier_path);
+    }
+
+    // The applications reach their daemon through the file system, outside of the namespaces.
+    let mut clients: Vec<_> = bier_paths
+        .iter()
+        .enumerate()
+        .map(|(i, bier_path)| {
+            let mut client = BierClient::connect(bier_path, dir.join(format!("app{}", i))).unwrap();
+            client.register(6, TIMEOUT).unwrap();
+            client
+        })
+        .collect();
+
+    // The first packet for the BFRs 2 and 3, the second one for the BFR 3 only.
+    for (bitstring, payload) in [(0b110, [1, 2, 3]), (0b100, [4, 5, 6])] {
+        let send_info = SendInfo {
+            bift_id: 1,
+            proto: 6,
+            bitstring: &[0, 0, 0, 0, 0, 0, 0, bitstring],
+            payload: &payload,
+            metadata: None,
+        };
+        clients[0].send(&send_info).unwrap();
+    }
+
+    let received = |client: &mut BierClient| {
+        let recv_info = client.recv_timeout(TIMEOUT).unwrap()?;
+        assert_eq!(recv_info.metadata.unwrap().bfir_id, Some(1));
+        Some(recv_info.payload.to_vec())
+    };
+    assert_eq!(received(&mut clients[1]), Some(vec![1, 2, 3]));
+    assert_eq!(received(&mut clients[2]), Some(vec![1, 2, 3]));
+    assert_eq!(received(&mut clients[2]), Some(vec![4, 5, 6]));
+    for client in clients.iter_mut() {
+        assert!(client
+            .recv_timeout(Duration::from_millis(100))
+            .unwrap()
+            .is_none());
+    }
+
+    drop(clients);
+    drop(namespaces);
+    let _ = std::fs::remove_dir_all(&dir);
+}